@@ -0,0 +1,81 @@
+//! Benchmarks of traversing and restructuring a [`Forest`].
+
+use aerugo_comp::{
+    forest::Node,
+    testing::{self, TreeShape},
+};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+const SHAPES: [TreeShape; 2] = [
+    // Many windows in few containers.
+    TreeShape {
+        depth: 2,
+        fanout: 32,
+        seed: 1,
+    },
+    // Deeply nested containers.
+    TreeShape {
+        depth: 8,
+        fanout: 3,
+        seed: 1,
+    },
+];
+
+fn traverse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("forest/traverse");
+
+    for shape in SHAPES {
+        let (forest, root) = testing::forest(shape);
+        let name = format!("{}x{}", shape.depth, shape.fanout);
+
+        group.bench_function(format!("dfs_descend/{name}"), |b| {
+            b.iter(|| forest.dfs_descend(black_box(root)).unwrap().count())
+        });
+
+        group.bench_function(format!("preorder_traverse/{name}"), |b| {
+            b.iter(|| forest.preorder_traverse(black_box(root)).unwrap().count())
+        });
+    }
+
+    group.finish();
+}
+
+fn mutate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("forest/mutate");
+
+    for shape in SHAPES {
+        let name = format!("{}x{}", shape.depth, shape.fanout);
+
+        // Raise the last child of every node, as focusing a window in every container would.
+        group.bench_function(format!("move_to_front/{name}"), |b| {
+            b.iter_batched(
+                || testing::forest(shape),
+                |(mut forest, root)| {
+                    let parents = forest.dfs_descend(root).unwrap().collect::<Vec<_>>();
+
+                    for parent in parents {
+                        if let Some(last) = forest.get(parent).and_then(Node::last_child) {
+                            forest.move_to_front(last).unwrap();
+                        }
+                    }
+
+                    forest
+                },
+                BatchSize::SmallInput,
+            )
+        });
+
+        group.bench_function(format!("remove_subtree/{name}"), |b| {
+            b.iter_batched(
+                || testing::forest(shape),
+                |(mut forest, root)| forest.remove_subtree(root).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, traverse, mutate);
+criterion_main!(benches);
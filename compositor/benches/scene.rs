@@ -0,0 +1,51 @@
+//! Benchmarks of presenting and restacking a deep scene graph.
+//!
+//! Surfaces can only be created by a connected client and importing them needs a renderer, so the synthetic
+//! scenes only contain branches. This measures the traversal and placement done every frame, not the cost of
+//! importing buffers.
+
+use aerugo_comp::testing::{SyntheticScene, TreeShape};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+const SHAPES: [TreeShape; 2] = [
+    TreeShape {
+        depth: 2,
+        fanout: 32,
+        seed: 1,
+    },
+    TreeShape {
+        depth: 8,
+        fanout: 3,
+        seed: 1,
+    },
+];
+
+fn render_elements(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scene");
+
+    for shape in SHAPES {
+        let scene = SyntheticScene::generate(shape);
+        let name = format!("{}x{}", shape.depth, shape.fanout);
+
+        group.bench_function(format!("render_elements/{name}"), |b| {
+            b.iter(|| scene.render_elements())
+        });
+        group.bench_function(format!("node_location/{name}"), |b| b.iter(|| scene.leaf_locations()));
+
+        group.bench_function(format!("restack/{name}"), |b| {
+            b.iter_batched(
+                || SyntheticScene::generate(shape),
+                |mut scene| {
+                    scene.restack();
+                    scene
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, render_elements);
+criterion_main!(benches);
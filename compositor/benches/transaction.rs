@@ -0,0 +1,47 @@
+//! Benchmarks of tracking the dependencies of thousands of transactions.
+
+use aerugo_comp::{testing, transaction::DependencyTracker};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+const COUNTS: [usize; 2] = [1_000, 4_000];
+
+/// The number of commits each transaction waits on.
+const COMMITS: usize = 3;
+
+fn dependency_tracker(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dependency_tracker");
+    group.sample_size(20);
+
+    for count in COUNTS {
+        group.bench_function(format!("add_dependency/{count}"), |b| {
+            b.iter(|| {
+                let mut tracker = DependencyTracker::new();
+                testing::dependency_graph(&mut tracker, count, COMMITS, 1);
+                tracker
+            })
+        });
+
+        group.bench_function(format!("finish/{count}"), |b| {
+            b.iter_batched(
+                || {
+                    let mut tracker = DependencyTracker::new();
+                    let graph = testing::dependency_graph(&mut tracker, count, COMMITS, 1);
+                    (tracker, graph)
+                },
+                |(mut tracker, graph)| {
+                    for commit in graph.commits {
+                        tracker.finish(commit);
+                    }
+
+                    tracker.drain_finished()
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, dependency_tracker);
+criterion_main!(benches);
@@ -0,0 +1,220 @@
+//! Animations of the scene
+//!
+//! A wm may animate the changes of a transaction instead of applying them at once. The scene is changed to the
+//! final state of the transaction immediately, but the offset, scale and opacity of the changed nodes are
+//! presented moving from the previously presented values to the new values over the duration of the animation.
+//! The values are interpolated when a frame is rendered, so an animation does not need a round trip to the wm
+//! every frame.
+//!
+//! If a node is animated again before the previous animation finished, the new animation starts from the values
+//! presented at that time so the node does not jump.
+
+use std::time::{Duration, Instant};
+
+use rustc_hash::FxHashMap;
+use smithay::utils::{Physical, Point};
+
+use crate::forest::Index;
+
+/// How the progress of an animation is mapped to the interpolated values.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    #[default]
+    Linear,
+
+    /// Starts slowly and accelerates.
+    EaseIn,
+
+    /// Starts quickly and decelerates.
+    EaseOut,
+
+    /// Accelerates until halfway and decelerates afterwards.
+    EaseInOut,
+}
+
+impl Easing {
+    /// Map the progress of an animation from 0.0 to 1.0 with the easing curve.
+    pub fn apply(self, progress: f64) -> f64 {
+        let t = progress.clamp(0.0, 1.0);
+
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+        }
+    }
+}
+
+/// The duration and easing of an animation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Animation {
+    pub duration: Duration,
+    pub easing: Easing,
+}
+
+/// The animated properties of a node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    /// The offset of the node relative to the parent.
+    pub offset: Point<f64, Physical>,
+    pub scale: f64,
+    pub opacity: f32,
+}
+
+impl Keyframe {
+    /// The keyframe between `self` and `to` after the specified progress from 0.0 to 1.0.
+    pub fn interpolate(&self, to: &Keyframe, progress: f64) -> Keyframe {
+        let lerp = |from: f64, to: f64| from + (to - from) * progress;
+
+        Keyframe {
+            offset: (lerp(self.offset.x, to.offset.x), lerp(self.offset.y, to.offset.y)).into(),
+            scale: lerp(self.scale, to.scale),
+            opacity: lerp(self.opacity.into(), to.opacity.into()) as f32,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct NodeAnimation {
+    start: Instant,
+    animation: Animation,
+    from: Keyframe,
+    to: Keyframe,
+
+    /// The keyframe presented in the current frame.
+    current: Keyframe,
+}
+
+/// The running animations of the nodes of the scene.
+#[derive(Debug, Default)]
+pub struct Animations {
+    nodes: FxHashMap<Index, NodeAnimation>,
+}
+
+impl Animations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Animate a node from one keyframe to another.
+    ///
+    /// If the node is already animated, the animation starts from the currently presented keyframe instead.
+    pub fn start(&mut self, index: Index, from: Keyframe, to: Keyframe, animation: Animation, now: Instant) {
+        let from = self.current(index).unwrap_or(from);
+
+        if from == to || animation.duration.is_zero() {
+            self.nodes.remove(&index);
+            return;
+        }
+
+        self.nodes.insert(
+            index,
+            NodeAnimation {
+                start: now,
+                animation,
+                from,
+                to,
+                current: from,
+            },
+        );
+    }
+
+    /// Stop animating a node, presenting the final keyframe of the node.
+    pub fn stop(&mut self, index: Index) {
+        self.nodes.remove(&index);
+    }
+
+    /// The keyframe presented for a node, if the node is animated.
+    pub fn current(&self, index: Index) -> Option<Keyframe> {
+        self.nodes.get(&index).map(|node| node.current)
+    }
+
+    pub fn is_animating(&self) -> bool {
+        !self.nodes.is_empty()
+    }
+
+    /// Interpolate the keyframes of every node for a frame presented at the specified time.
+    ///
+    /// Finished animations are removed.
+    pub fn advance(&mut self, now: Instant) {
+        self.nodes.retain(|_, node| {
+            let elapsed = now.saturating_duration_since(node.start);
+
+            if elapsed >= node.animation.duration {
+                return false;
+            }
+
+            let progress = elapsed.as_secs_f64() / node.animation.duration.as_secs_f64();
+            node.current = node.from.interpolate(&node.to, node.animation.easing.apply(progress));
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::forest::Forest;
+
+    use super::{Animation, Animations, Easing, Keyframe};
+
+    fn keyframe(x: f64, opacity: f32) -> Keyframe {
+        Keyframe {
+            offset: (x, 0.0).into(),
+            scale: 1.0,
+            opacity,
+        }
+    }
+
+    #[test]
+    fn easing_endpoints() {
+        for easing in [Easing::Linear, Easing::EaseIn, Easing::EaseOut, Easing::EaseInOut] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+            assert_eq!(easing.apply(2.0), 1.0);
+        }
+
+        assert!(Easing::EaseIn.apply(0.5) < 0.5);
+        assert!(Easing::EaseOut.apply(0.5) > 0.5);
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+    }
+
+    #[test]
+    fn animation_interpolates_and_finishes() {
+        let mut forest = Forest::<()>::new();
+        let index = forest.insert(());
+        let mut animations = Animations::new();
+        let now = Instant::now();
+        let animation = Animation {
+            duration: Duration::from_millis(100),
+            easing: Easing::Linear,
+        };
+
+        animations.start(index, keyframe(0.0, 0.0), keyframe(100.0, 1.0), animation, now);
+        animations.advance(now + Duration::from_millis(25));
+        assert_eq!(animations.current(index), Some(keyframe(25.0, 0.25)));
+
+        // Animating again starts from the presented keyframe.
+        animations.start(
+            index,
+            keyframe(100.0, 1.0),
+            keyframe(0.0, 1.0),
+            animation,
+            now + Duration::from_millis(25),
+        );
+        animations.advance(now + Duration::from_millis(75));
+        assert_eq!(animations.current(index), Some(keyframe(12.5, 0.625)));
+
+        animations.advance(now + Duration::from_millis(125));
+        assert_eq!(animations.current(index), None);
+        assert!(!animations.is_animating());
+    }
+}
@@ -0,0 +1,162 @@
+//! Multi-GPU buffer migration
+//!
+//! On systems with more than one GPU, such as laptops with an integrated and a discrete GPU, the GPU a client
+//! renders with is not necessarily the GPU the compositor renders with. A dmabuf allocated on another GPU can
+//! often not be sampled by the render GPU, so the buffer must be migrated.
+//!
+//! Clients are steered towards the render GPU using dmabuf feedback (see [`GpuManager::feedback`]). A client
+//! may still render on another GPU, for example when running a game on the discrete GPU. In that case the
+//! buffer is migrated when imported:
+//!
+//! 1. The buffer is imported directly on the render GPU. Many drivers can import linear buffers from another
+//!    device, in which case no copy is needed.
+//! 2. Otherwise the buffer is imported by the GPU which can read it (the GPU the buffer was rendered on), read
+//!    back into memory and uploaded to the render GPU. This CPU bounce is slow, but always works.
+
+use std::{fmt, io};
+
+use smithay::{
+    backend::{
+        allocator::{dmabuf::Dmabuf, Buffer, Fourcc},
+        drm::DrmNode,
+        renderer::{Bind, ExportMem, ImportDma, ImportMem, Renderer},
+    },
+    utils::Rectangle,
+    wayland::dmabuf::{DmabufFeedback, DmabufFeedbackBuilder},
+};
+
+/// Format of the pixels read back from a GPU during a CPU bounce.
+const BOUNCE_FORMAT: Fourcc = Fourcc::Abgr8888;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    /// No GPU could import the buffer.
+    #[error("no gpu could import the buffer")]
+    Unsupported,
+
+    /// The buffer could be imported, but copying the buffer to the render GPU failed.
+    #[error("failed to copy the buffer from {source_node} to the render gpu")]
+    Copy { source_node: DrmNode },
+}
+
+/// How a buffer was imported on the render GPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportPath {
+    /// The buffer was imported directly.
+    Direct,
+
+    /// The buffer was copied through memory from another GPU.
+    CpuBounce { source: DrmNode },
+}
+
+/// The GPUs of the system and the renderer for each GPU.
+pub struct GpuManager<R> {
+    /// The GPU the compositor renders with.
+    render: (DrmNode, R),
+
+    /// Other GPUs which clients may render on.
+    others: Vec<(DrmNode, R)>,
+}
+
+impl<R> fmt::Debug for GpuManager<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GpuManager")
+            .field("render", &self.render.0)
+            .field("others", &self.others.iter().map(|(node, _)| node).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<R> GpuManager<R>
+where
+    R: Renderer + ImportDma + ImportMem + ExportMem + Bind<Dmabuf>,
+{
+    pub fn new(node: DrmNode, renderer: R) -> Self {
+        Self {
+            render: (node, renderer),
+            others: Vec::new(),
+        }
+    }
+
+    /// Add a GPU which clients may render on.
+    pub fn add_gpu(&mut self, node: DrmNode, renderer: R) {
+        if node == self.render.0 || self.others.iter().any(|(other, _)| *other == node) {
+            return;
+        }
+
+        tracing::info!(%node, "Added secondary gpu");
+        self.others.push((node, renderer));
+    }
+
+    /// Remove a GPU, for example when the GPU was unplugged.
+    pub fn remove_gpu(&mut self, node: DrmNode) {
+        self.others.retain(|(other, _)| *other != node);
+    }
+
+    /// The GPU the compositor renders with.
+    pub fn render_node(&self) -> DrmNode {
+        self.render.0
+    }
+
+    pub fn renderer(&mut self) -> &mut R {
+        &mut self.render.1
+    }
+
+    /// Build dmabuf feedback preferring the render GPU.
+    ///
+    /// Buffers allocated on the render GPU never need to be migrated. The other GPUs are offered as less
+    /// preferred tranches, so a client rendering on another GPU allocates buffers that GPU can import, which is
+    /// how [`GpuManager::import_dmabuf`] finds the device of the buffer.
+    pub fn feedback(&self) -> io::Result<DmabufFeedback> {
+        let formats = self.render.1.dmabuf_formats().copied().collect::<Vec<_>>();
+
+        self.others
+            .iter()
+            .fold(
+                DmabufFeedbackBuilder::new(self.render.0.dev_id(), formats),
+                |builder, (node, renderer)| {
+                    let formats = renderer.dmabuf_formats().copied().collect::<Vec<_>>();
+                    builder.add_preference_tranche(node.dev_id(), None, formats)
+                },
+            )
+            .build()
+    }
+
+    /// Import a dmabuf on the render GPU, migrating the buffer from another GPU if needed.
+    pub fn import_dmabuf(&mut self, dmabuf: &Dmabuf) -> Result<(R::TextureId, ImportPath), MigrationError> {
+        if let Ok(texture) = self.render.1.import_dmabuf(dmabuf, None) {
+            return Ok((texture, ImportPath::Direct));
+        }
+
+        // Find the GPU which can read the buffer and bounce the contents through memory.
+        let (render, others) = (&mut self.render.1, &mut self.others);
+
+        for (node, renderer) in others.iter_mut() {
+            if renderer.import_dmabuf(dmabuf, None).is_err() {
+                continue;
+            }
+
+            let texture = cpu_bounce(renderer, render, dmabuf).ok_or(MigrationError::Copy { source_node: *node })?;
+            tracing::trace!(source = %node, "Migrated buffer through memory");
+            return Ok((texture, ImportPath::CpuBounce { source: *node }));
+        }
+
+        Err(MigrationError::Unsupported)
+    }
+}
+
+/// Read the buffer back on the source GPU and upload the contents to the destination GPU.
+fn cpu_bounce<R>(source: &mut R, destination: &mut R, dmabuf: &Dmabuf) -> Option<R::TextureId>
+where
+    R: Renderer + ImportMem + ExportMem + Bind<Dmabuf>,
+{
+    let size = dmabuf.size();
+
+    source.bind(dmabuf.clone()).ok()?;
+    let mapping = source
+        .copy_framebuffer(Rectangle::from_loc_and_size((0, 0), size))
+        .ok()?;
+    let pixels = source.map_texture(&mapping).ok()?;
+
+    destination.import_memory(pixels, BOUNCE_FORMAT, size, false).ok()
+}
@@ -1,11 +1,21 @@
+pub mod gpu;
+pub mod renderer;
+pub mod selection;
+pub mod shm;
+pub mod windowed;
 mod x11;
 
-use std::{error::Error, fmt};
+use std::{error::Error, fmt, os::fd::BorrowedFd};
 
-use calloop::LoopHandle;
+use calloop::{
+    channel::{self, Sender},
+    LoopHandle,
+};
 use downcast_rs::{impl_downcast, Downcast};
 use smithay::{
     backend::allocator::dmabuf::Dmabuf,
+    output::Output,
+    utils::{Physical, Size},
     wayland::{
         dmabuf::{DmabufGlobal, DmabufState, ImportError},
         shm::ShmState,
@@ -13,9 +23,20 @@ use smithay::{
 };
 use wayland_server::DisplayHandle;
 
-use crate::Loop;
+use crate::{gamma::GammaRamp, shutdown::ShutdownReason, wayland::wlr::output_management::OutputConfiguration, Loop};
+
+use self::{
+    renderer::{RendererKind, RendererSelection},
+    selection::{BackendKind, BackendSelection, Environment},
+};
 
 pub trait Backend: fmt::Debug + Downcast {
+    /// The name of the backend, such as `x11`.
+    fn name(&self) -> &'static str;
+
+    /// The renderer the backend draws with.
+    fn renderer_kind(&self) -> RendererKind;
+
     fn shm_state(&self) -> &ShmState;
 
     /// Return the delegate type for the dmabuf protocol state.
@@ -26,24 +47,133 @@ pub trait Backend: fmt::Debug + Downcast {
     /// Import a client's dmabuf buffer into the backend.
     fn dmabuf_imported(&mut self, _global: &DmabufGlobal, _dmabuf: Dmabuf) -> Result<(), ImportError>;
 
-    /// Check if the backend is asking the compositor to shutdown.
+    /// Check if the backend is asking the compositor to shutdown, returning why.
+    ///
+    /// Outside of the windowed test backends, this should only return a reason if the backend lost access to
+    /// the display and cannot recover.
+    fn shutdown_reason(&self) -> Option<ShutdownReason> {
+        None
+    }
+
+    /// Check whether the backend can apply the configuration to the output.
+    ///
+    /// This is called for every output in a configuration before the configuration is applied. If any output
+    /// cannot be configured, the configuration is not applied.
+    fn test_output_configuration(&self, _output: &Output, _configuration: &OutputConfiguration) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// The largest cursor image the hardware cursor plane of the output can present.
+    ///
+    /// Backends without cursor planes return [`None`], in which case the cursor is drawn in software.
+    fn cursor_plane_size(&self, _output: &Output) -> Option<Size<i32, Physical>> {
+        None
+    }
+
+    /// The DRM device client timelines are imported into for explicit synchronization.
+    ///
+    /// Backends which return [`None`] only support implicit synchronization and the linux-drm-syncobj global is
+    /// not advertised.
+    fn syncobj_device(&self) -> Option<BorrowedFd<'_>> {
+        None
+    }
+
+    /// Turn the display connected to the output on or off.
+    ///
+    /// DRM backends set the `ACTIVE` property of the CRTC driving the output. Nothing is presented to an output
+    /// which is off, so backends which cannot power down a display present black frames instead.
+    fn set_output_power(&mut self, _output: &Output, _on: bool) {}
+
+    /// The number of entries of each channel of the gamma ramp of the output.
+    ///
+    /// Backends which cannot set the gamma of the output return [`None`].
+    fn gamma_size(&self, _output: &Output) -> Option<u32> {
+        None
+    }
+
+    /// Set the gamma ramp of the output, or reset the gamma if the ramp is [`None`].
     ///
-    /// Outside of the windowed test backends, this should return [`false`]
-    fn should_shutdown(&self) -> bool {
+    /// The ramp has [`gamma_size`](Self::gamma_size) entries per channel. DRM backends set the `GAMMA_LUT`
+    /// property of the CRTC driving the output.
+    fn set_gamma(&mut self, _output: &Output, _ramp: Option<&GammaRamp>) -> Result<(), String> {
+        Err("the backend cannot set the gamma of outputs".into())
+    }
+
+    /// Whether the display connected to the output supports adaptive sync.
+    fn supports_adaptive_sync(&self, _output: &Output) -> bool {
         false
     }
 
-    // TODO: Outputs?
+    /// Enable or disable adaptive sync on the output.
+    ///
+    /// This is only called for outputs which [support adaptive sync](Self::supports_adaptive_sync). DRM backends
+    /// set the `VRR_ENABLED` property of the CRTC driving the output.
+    fn set_adaptive_sync(&mut self, _output: &Output, _enabled: bool) {}
+
     // TODO: Seat?
 }
 impl_downcast!(Backend);
 
+/// A change to the outputs or input devices of a backend.
+///
+/// Backends publish these using the sender returned by [`event_sender`] at any time after startup, for example
+/// when a monitor is hotplugged. Events are applied on the event loop in the order they were sent.
+#[derive(Debug)]
+pub enum BackendEvent {
+    /// An output was connected.
+    ///
+    /// The output is advertised to clients, added to the scene and output management, and announced to the wm.
+    OutputAdded(Output),
+
+    /// An output was disconnected.
+    OutputRemoved(Output),
+
+    /// An input device with the specified name was connected.
+    InputDeviceAdded(String),
+
+    /// An input device with the specified name was disconnected.
+    InputDeviceRemoved(String),
+}
+
+/// Create a sender which a backend uses to publish [`BackendEvent`]s.
+///
+/// The sender may be used from any thread.
+pub fn event_sender(r#loop: &LoopHandle<'static, Loop>) -> Sender<BackendEvent> {
+    let (sender, channel) = channel::channel();
+
+    r#loop
+        .insert_source(channel, |event, _, state| {
+            if let channel::Event::Msg(event) = event {
+                state.handle_backend_event(event);
+            }
+        })
+        .expect("Failed to insert backend event source");
+
+    sender
+}
+
+/// Create the backend selected automatically, see [`selection`].
 pub fn default_backend(
     r#loop: LoopHandle<'static, Loop>,
     display: DisplayHandle,
+    renderer: RendererSelection,
+) -> Result<Box<dyn Backend>, Box<dyn Error>> {
+    create_backend(BackendSelection::Auto, r#loop, display, renderer)
+}
+
+/// Create the selected backend.
+pub fn create_backend(
+    selection: BackendSelection,
+    r#loop: LoopHandle<'static, Loop>,
+    display: DisplayHandle,
+    renderer: RendererSelection,
 ) -> Result<Box<dyn Backend>, Box<dyn Error>> {
-    // TODO: X11 backend only exists right now, so the backend selection is ignored.
-    Ok(Box::new(x11::Backend::new(r#loop, display).expect("TODO: Error type")))
+    let kind = selection::select(selection, &Environment::from_env())?;
+    tracing::info!(backend = %kind, "Selected backend");
+
+    match kind {
+        BackendKind::X11 => Ok(Box::new(x11::Backend::new(r#loop, display, renderer)?)),
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,144 @@
+//! Renderer selection
+//!
+//! The renderer is selected once at startup. The selection is taken from the `renderer` option of the config
+//! file, the `--renderer` command line argument or the [`RENDERER_ENV`] environment variable, in that order. By
+//! default the renderer is chosen automatically: the backend probes each renderer in order of preference and
+//! uses the first one which is supported.
+//!
+//! Right now the OpenGL ES renderer is the only renderer.
+
+use std::{fmt, str::FromStr};
+
+use serde::Deserialize;
+
+/// Environment variable used to select the renderer if no renderer is set on the command line.
+pub const RENDERER_ENV: &str = "AERUGO_RENDERER";
+
+/// Which renderer should be used.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RendererSelection {
+    /// Use the most preferred renderer which is supported.
+    #[default]
+    Auto,
+
+    /// Use the OpenGL ES renderer.
+    Gles,
+}
+
+impl RendererSelection {
+    /// Read the selection from the [`RENDERER_ENV`] environment variable.
+    ///
+    /// If the variable is not set or is invalid, the renderer is selected automatically.
+    pub fn from_env() -> Self {
+        let Ok(value) = std::env::var(RENDERER_ENV) else {
+            return Self::Auto;
+        };
+
+        value.parse().unwrap_or_else(|err| {
+            tracing::warn!(%err, "Ignoring {RENDERER_ENV}");
+            Self::Auto
+        })
+    }
+}
+
+impl FromStr for RendererSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" | "default" => Ok(Self::Auto),
+            "gles" | "gl" | "egl" => Ok(Self::Gles),
+            _ => Err(format!("unknown renderer \"{s}\"")),
+        }
+    }
+}
+
+/// A renderer a backend may draw with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RendererKind {
+    Gles,
+}
+
+impl RendererKind {
+    /// Renderers in order of preference when the renderer is selected automatically.
+    pub const PREFERENCE: [Self; 1] = [Self::Gles];
+}
+
+impl fmt::Display for RendererKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gles => f.write_str("OpenGL ES"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RendererError {
+    #[error("the {kind} renderer is not supported: {reason}")]
+    Unsupported { kind: RendererKind, reason: String },
+
+    #[error("no supported renderer is available")]
+    NoRenderer,
+}
+
+/// Select the renderer to use.
+///
+/// The probe checks whether the backend can use a renderer, returning why not if the renderer is not
+/// supported.
+pub fn select(
+    selection: RendererSelection,
+    mut probe: impl FnMut(RendererKind) -> Result<(), String>,
+) -> Result<RendererKind, RendererError> {
+    let kind = match selection {
+        RendererSelection::Auto => {
+            for kind in RendererKind::PREFERENCE {
+                match probe(kind) {
+                    Ok(()) => return Ok(kind),
+                    Err(reason) => tracing::debug!(%kind, %reason, "Renderer is not supported"),
+                }
+            }
+
+            return Err(RendererError::NoRenderer);
+        }
+
+        RendererSelection::Gles => RendererKind::Gles,
+    };
+
+    probe(kind)
+        .map(|()| kind)
+        .map_err(|reason| RendererError::Unsupported { kind, reason })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{select, RendererError, RendererKind, RendererSelection};
+
+    #[test]
+    fn auto_uses_supported_renderer() {
+        assert_eq!(select(RendererSelection::Auto, |_| Ok(())).unwrap(), RendererKind::Gles);
+
+        assert!(matches!(
+            select(RendererSelection::Auto, |_| Err(String::new())),
+            Err(RendererError::NoRenderer)
+        ));
+    }
+
+    #[test]
+    fn explicit_selection_reports_why_unsupported() {
+        assert!(matches!(
+            select(RendererSelection::Gles, |_| Err(String::from("no device"))),
+            Err(RendererError::Unsupported {
+                kind: RendererKind::Gles,
+                reason,
+            }) if reason == "no device"
+        ));
+    }
+
+    #[test]
+    fn parse_selection() {
+        assert_eq!("GL".parse(), Ok(RendererSelection::Gles));
+        assert_eq!("auto".parse(), Ok(RendererSelection::Auto));
+        assert!("vulkan".parse::<RendererSelection>().is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! Backend selection
+//!
+//! The backend is selected once at startup, using the `--backend` command line argument. By default the backend
+//! is chosen automatically: the backends are probed in order of preference and the first one which can be used in
+//! the environment the display server was started in is used.
+//!
+//! Right now the x11 backend is the only backend, so the display server always runs nested in an X11 session.
+
+use std::{env, fmt};
+
+/// Which backend should be used.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BackendSelection {
+    /// Use the most preferred backend which can be used.
+    #[default]
+    Auto,
+
+    /// Run nested, using the display server of the session.
+    Windowed,
+
+    /// Use the x11 backend.
+    X11,
+}
+
+/// A backend the display server may run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Present the output in a window of an X11 display server.
+    X11,
+}
+
+impl BackendKind {
+    /// Backends in order of preference when the backend is selected automatically.
+    pub const PREFERENCE: [Self; 1] = [Self::X11];
+
+    /// Backends presenting the output in a window, in order of preference.
+    pub const WINDOWED: [Self; 1] = [Self::X11];
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::X11 => "x11",
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackendError {
+    #[error("the {kind} backend cannot be used: {reason}")]
+    Unsupported { kind: BackendKind, reason: String },
+
+    #[error("no backend can be used, start the display server inside an X11 session")]
+    NoBackend,
+}
+
+/// The environment the display server was started in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Environment {
+    /// Whether an X11 display server is running in the session.
+    pub x11: bool,
+}
+
+impl Environment {
+    /// Read the environment from the environment variables of the session.
+    pub fn from_env() -> Self {
+        let set = |name: &str| env::var_os(name).map_or(false, |value| !value.is_empty());
+
+        Self { x11: set("DISPLAY") }
+    }
+
+    /// Check whether a backend can be used, returning why not if the backend cannot be used.
+    pub fn probe(&self, kind: BackendKind) -> Result<(), String> {
+        match kind {
+            BackendKind::X11 if !self.x11 => Err("DISPLAY is not set".into()),
+            BackendKind::X11 => Ok(()),
+        }
+    }
+}
+
+/// Select the backend to use.
+pub fn select(selection: BackendSelection, environment: &Environment) -> Result<BackendKind, BackendError> {
+    let kind = match selection {
+        BackendSelection::Auto => return first(&BackendKind::PREFERENCE, environment),
+        BackendSelection::Windowed => return first(&BackendKind::WINDOWED, environment),
+        BackendSelection::X11 => BackendKind::X11,
+    };
+
+    environment
+        .probe(kind)
+        .map(|()| kind)
+        .map_err(|reason| BackendError::Unsupported { kind, reason })
+}
+
+/// The first backend which can be used.
+fn first(kinds: &[BackendKind], environment: &Environment) -> Result<BackendKind, BackendError> {
+    for &kind in kinds {
+        match environment.probe(kind) {
+            Ok(()) => return Ok(kind),
+            Err(reason) => tracing::debug!(%kind, %reason, "Backend cannot be used"),
+        }
+    }
+
+    Err(BackendError::NoBackend)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{select, BackendError, BackendKind, BackendSelection, Environment};
+
+    #[test]
+    fn auto_runs_nested() {
+        let environment = Environment { x11: true };
+
+        assert_eq!(select(BackendSelection::Auto, &environment).unwrap(), BackendKind::X11);
+        assert_eq!(
+            select(BackendSelection::Windowed, &environment).unwrap(),
+            BackendKind::X11
+        );
+        assert!(matches!(
+            select(BackendSelection::Auto, &Environment::default()),
+            Err(BackendError::NoBackend)
+        ));
+    }
+
+    #[test]
+    fn explicit_selection_reports_why_unsupported() {
+        assert!(matches!(
+            select(BackendSelection::X11, &Environment::default()),
+            Err(BackendError::Unsupported {
+                kind: BackendKind::X11,
+                ..
+            })
+        ));
+    }
+}
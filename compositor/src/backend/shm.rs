@@ -0,0 +1,65 @@
+//! Formats of shared memory buffers
+//!
+//! Every compositor must support `argb8888` and `xrgb8888`, which [`ShmState`] always advertises. Other formats
+//! are only advertised if the renderer of the backend can import buffers of that format. Clients creating a
+//! buffer with a format which was not advertised get an `invalid_format` protocol error when the buffer is
+//! created, instead of the buffer silently failing to import when the surface is drawn.
+
+use smithay::wayland::shm::ShmState;
+use wayland_server::{protocol::wl_shm, DisplayHandle};
+
+use crate::Aerugo;
+
+/// Formats which are advertised in addition to the mandatory formats, in order of preference.
+pub const OPTIONAL_FORMATS: [wl_shm::Format; 7] = [
+    wl_shm::Format::Abgr8888,
+    wl_shm::Format::Xbgr8888,
+    wl_shm::Format::Argb2101010,
+    wl_shm::Format::Xrgb2101010,
+    wl_shm::Format::Abgr2101010,
+    wl_shm::Format::Xbgr2101010,
+    wl_shm::Format::Rgb565,
+];
+
+/// The optional formats which should be advertised, given the formats the renderer can import.
+///
+/// The mandatory formats are not included, since [`ShmState`] always advertises them.
+pub fn formats(renderer: impl IntoIterator<Item = wl_shm::Format>) -> Vec<wl_shm::Format> {
+    let renderer = renderer.into_iter().collect::<Vec<_>>();
+
+    OPTIONAL_FORMATS
+        .into_iter()
+        .filter(|format| renderer.contains(format))
+        .collect()
+}
+
+/// Create the `wl_shm` global advertising the formats the renderer can import.
+pub fn create_state(display: &DisplayHandle, renderer: impl IntoIterator<Item = wl_shm::Format>) -> ShmState {
+    let formats = formats(renderer);
+    tracing::info!(?formats, "Advertising additional shm formats");
+
+    ShmState::new::<Aerugo>(display, formats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_formats_are_not_advertised() {
+        let renderer = [
+            wl_shm::Format::Argb8888,
+            wl_shm::Format::Xrgb8888,
+            wl_shm::Format::Rgb565,
+            wl_shm::Format::Xrgb2101010,
+            wl_shm::Format::Yuyv,
+        ];
+
+        assert_eq!(formats(renderer), [wl_shm::Format::Xrgb2101010, wl_shm::Format::Rgb565]);
+    }
+
+    #[test]
+    fn renderer_without_optional_formats() {
+        assert!(formats([wl_shm::Format::Argb8888, wl_shm::Format::Xrgb8888]).is_empty());
+    }
+}
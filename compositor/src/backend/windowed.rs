@@ -0,0 +1,71 @@
+//! Outputs presented in a window
+//!
+//! Backends nested in another display server present the output in a window. The output has a single mode, the
+//! size of the window, and follows the window when it is resized like a monitor with a new mode was plugged in.
+//! Since the size of the window is up to the host display server, output management clients cannot change the
+//! mode of the output or disable the output.
+
+use smithay::{
+    output::{Mode, Output},
+    utils::{Physical, Size},
+};
+
+use crate::{wayland::wlr::output_management::OutputConfiguration, Aerugo};
+
+/// Refresh rate of an output presented in a window, in mHz.
+///
+/// The host display server does not tell which monitor the window is presented on, so the common refresh rate is
+/// assumed.
+pub const WINDOW_REFRESH: i32 = 60_000;
+
+/// The mode of an output presented in a window of the specified size.
+pub fn window_mode(size: Size<i32, Physical>) -> Mode {
+    Mode {
+        size,
+        refresh: WINDOW_REFRESH,
+    }
+}
+
+/// Check whether an output presented in a window can apply the configuration.
+pub fn test_output_configuration(
+    backend: &str,
+    output: &Output,
+    configuration: &OutputConfiguration,
+) -> Result<(), String> {
+    if !configuration.enabled {
+        return Err(format!("the output of the {backend} backend cannot be disabled"));
+    }
+
+    // The size of the output is the size of the window.
+    if configuration.mode != output.current_mode() {
+        return Err(format!("the {backend} backend cannot change the output mode"));
+    }
+
+    if configuration.adaptive_sync {
+        return Err(format!("the {backend} backend does not support adaptive sync"));
+    }
+
+    Ok(())
+}
+
+impl Aerugo {
+    /// Follow the size of the window an output is presented in with the mode of the output.
+    ///
+    /// This does nothing if the size of the window did not change.
+    pub fn window_resized(&mut self, output: &Output, size: Size<i32, Physical>) {
+        self.set_output_mode(output, window_mode(size));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{window_mode, WINDOW_REFRESH};
+
+    #[test]
+    fn mode() {
+        let mode = window_mode((1280, 720).into());
+
+        assert_eq!(mode.size, (1280, 720).into());
+        assert_eq!(mode.refresh, WINDOW_REFRESH);
+    }
+}
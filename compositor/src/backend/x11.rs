@@ -1,6 +1,16 @@
 //! X11 input and output backend
 
-use calloop::LoopHandle;
+use std::{
+    error::Error,
+    os::fd::{AsFd, BorrowedFd, OwnedFd},
+    time::Instant,
+};
+
+use calloop::{
+    channel::Sender,
+    timer::{TimeoutAction, Timer},
+    LoopHandle,
+};
 use smithay::{
     backend::{
         allocator::{
@@ -8,11 +18,21 @@ use smithay::{
             gbm::GbmAllocator,
         },
         egl::{EGLContext, EGLDisplay},
-        renderer::{element::AsRenderElements, gles::GlesRenderer, utils::draw_render_elements, Bind, Frame, Renderer},
-        x11::{Window, WindowBuilder, X11Backend, X11Event, X11Handle, X11Surface},
+        input::{
+            AbsolutePositionEvent, Device, Event, InputEvent, KeyboardKeyEvent, PointerButtonEvent, PointerMotionEvent,
+            TouchEvent,
+        },
+        renderer::{
+            element::AsRenderElements,
+            gles::GlesRenderer,
+            utils::{draw_render_elements, import_surface_tree},
+            Bind, Frame, ImportMemWl, Renderer,
+        },
+        x11::{Window, WindowBuilder, X11Backend, X11Event, X11Handle, X11Input, X11Surface},
     },
+    output::Output,
     reexports::gbm::{self, BufferObjectFlags},
-    utils::{DeviceFd, Rectangle, Transform},
+    utils::{DeviceFd, Logical, Physical, Rectangle, Scale, Size, Transform},
     wayland::{
         dmabuf::{DmabufGlobal, DmabufState, ImportError},
         shm::ShmState,
@@ -20,18 +40,68 @@ use smithay::{
 };
 use wayland_server::DisplayHandle;
 
-use crate::{scene::SceneGraphElement, Aerugo, Loop};
+use crate::{
+    backend::{
+        event_sender,
+        gpu::GpuManager,
+        renderer::{self, RendererKind, RendererSelection},
+        shm, windowed, BackendEvent,
+    },
+    color,
+    cursor::{CursorPlane, DEFAULT_CURSOR_COLOR},
+    damage::{DamageHistory, ElementState},
+    frame, frame_scheduler,
+    input::buttons::{FilteredEvent, MIDDLE_EMULATION_TIMEOUT},
+    scene::{Occlusion, SceneGraphElement},
+    screenshot,
+    shutdown::ShutdownReason,
+    state::Aerugo,
+    stats::FrameTimings,
+    virtual_output,
+    wayland::wlr::{output_management::OutputConfiguration, screencopy},
+    Loop,
+};
+
+/// Color of the output background.
+const BACKGROUND_COLOR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+/// Color of the output background while the session is locked.
+const LOCKED_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+/// Color of the window while the output is off, since the window cannot be powered down.
+const OFF_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+/// Title of the window.
+const WINDOW_TITLE: &str = "Aerugo";
+
+/// Height of the banner shown when the wm failed.
+const ERROR_BANNER_HEIGHT: i32 = 32;
+
+/// Color of the banner shown when the wm failed.
+const ERROR_BANNER_COLOR: [f32; 4] = [0.8, 0.1, 0.1, 1.0];
 
 #[derive(Debug)]
 pub struct Backend {
     x11: X11Handle,
     window: Window,
-    renderer: GlesRenderer,
+    /// The GPU of the X server, which renders the window, and any GPUs clients render on.
+    gpus: GpuManager<GlesRenderer>,
+    renderer_kind: RendererKind,
     surface: X11Surface,
     r#loop: LoopHandle<'static, Loop>,
     display: DisplayHandle,
     shm_state: ShmState,
-    shutdown: bool,
+    dmabuf_state: DmabufState,
+    shutdown: Option<ShutdownReason>,
+    /// The DRM device of the X server, used for explicit synchronization.
+    drm: Option<OwnedFd>,
+    damage: DamageHistory<(Vec<ElementState>, [f32; 4], Option<Rectangle<i32, Physical>>)>,
+    /// The wm error shown in the window title.
+    error_message: Option<String>,
+    /// Publishes the input devices of the X server.
+    ///
+    /// The output is the window, which exists for as long as the backend, so outputs are never published.
+    events: Sender<BackendEvent>,
 }
 
 impl dyn super::Backend {
@@ -42,8 +112,12 @@ impl dyn super::Backend {
 
 impl Backend {
     // TODO: Error type
-    pub fn new(r#loop: LoopHandle<'static, Loop>, display: DisplayHandle) -> Result<Self, ()> {
-        let backend = X11Backend::new().unwrap();
+    pub fn new(
+        r#loop: LoopHandle<'static, Loop>,
+        display: DisplayHandle,
+        renderer: RendererSelection,
+    ) -> Result<Self, Box<dyn Error>> {
+        let backend = X11Backend::new().map_err(|err| format!("failed to connect to the X server: {err}"))?;
         let x11 = backend.handle();
 
         // TODO: Initialize output with window.
@@ -53,7 +127,10 @@ impl Backend {
         //   backend to select Argb8888 or Xrgb8888. It may be desireable however to use Argb2101010 if
         //   available. This will however require a way to enumerate what formats the window could be created
         //   with.
-        let window = WindowBuilder::new().title("Aerugo").build(&x11).unwrap();
+        let window = WindowBuilder::new()
+            .title(WINDOW_TITLE)
+            .build(&x11)
+            .map_err(|err| format!("failed to create the window: {err}"))?;
         window.map();
 
         // Get the drm node for buffer allocation and initializing EGL.
@@ -61,10 +138,19 @@ impl Backend {
         // TODO for Smithay:
         // - This should return just the path to the drm device. For the legacy DRI3 fallback, there should be
         //   a separate function to get the DRM file descriptor in that case.
-        let (_, fd) = x11.drm_node().expect("Failed to get DRM node used by X server");
+        let (node, fd) = x11
+            .drm_node()
+            .map_err(|err| format!("failed to get the DRM node used by the X server: {err}"))?;
+        let drm = fd.try_clone().ok();
         let device = gbm::Device::new(DeviceFd::from(fd)).unwrap();
         let egl = EGLDisplay::new(device.clone()).unwrap();
-        let context = EGLContext::new(&egl).unwrap();
+        let context = EGLContext::new(&egl);
+
+        let kind = renderer::select(renderer, |kind| match kind {
+            RendererKind::Gles => context.as_ref().map(|_| ()).map_err(ToString::to_string),
+        })?;
+        tracing::info!(renderer = %kind, "Selected renderer");
+        let context = context.unwrap();
 
         let surface = x11
             .create_surface(
@@ -75,19 +161,33 @@ impl Backend {
             .unwrap();
 
         let renderer = unsafe { GlesRenderer::new(context) }.unwrap();
+        let shm_state = shm::create_state(&display, renderer.shm_formats());
+        let gpus = GpuManager::new(node, renderer);
+
+        let feedback = gpus
+            .feedback()
+            .map_err(|err| format!("failed to build the dmabuf feedback: {err}"))?;
+        let mut dmabuf_state = DmabufState::new();
+        dmabuf_state.create_global_with_default_feedback::<Aerugo>(&display, &feedback);
 
         r#loop.insert_source(backend, dispatch_x11_event).unwrap();
+        let events = event_sender(&r#loop);
 
         Ok(Self {
             x11,
             window,
             r#loop,
             display: display.clone(),
-            // TODO: Additional renderer shm formats
-            shm_state: ShmState::new::<Aerugo>(&display, Vec::with_capacity(2)),
-            shutdown: false,
-            renderer,
+            shm_state,
+            dmabuf_state,
+            shutdown: None,
+            drm,
+            gpus,
+            renderer_kind: kind,
             surface,
+            damage: DamageHistory::new(),
+            error_message: None,
+            events,
         })
     }
 }
@@ -95,85 +195,474 @@ impl Backend {
 fn dispatch_x11_event(event: X11Event, _: &mut (), aerugo: &mut Loop) {
     match event {
         X11Event::Refresh { window_id: _ } => draw(aerugo),
-        X11Event::Input(_) => {}
-        X11Event::Resized {
-            new_size: _,
-            window_id: _,
-        } => draw(aerugo),
-        X11Event::PresentCompleted { window_id: _ } => draw(aerugo),
+        X11Event::Input(event) => handle_input(aerugo, event),
+        X11Event::Resized { new_size, window_id: _ } => {
+            resize(aerugo, new_size);
+            draw(aerugo);
+        }
+        X11Event::PresentCompleted { window_id: _ } => {
+            if let Some(drm_syncobj) = &mut aerugo.comp.drm_syncobj {
+                drm_syncobj.frame_presented();
+            }
+
+            schedule_frame(aerugo);
+        }
         X11Event::CloseRequested { window_id: _ } => {
             // TODO: shutdown based on output counts
             let backend: &mut Backend = &mut aerugo.comp.backend.downcast_mut().unwrap();
-            backend.shutdown = true;
+            backend.shutdown = Some(ShutdownReason::UserRequest);
             aerugo.check_shutdown();
         }
     }
 }
 
+fn handle_input(aerugo: &mut Loop, event: InputEvent<X11Input>) {
+    match event {
+        InputEvent::DeviceAdded { device } => {
+            let event = BackendEvent::InputDeviceAdded(device.name());
+            let _ = aerugo.comp.backend.x11_mut().events.send(event);
+        }
+
+        InputEvent::DeviceRemoved { device } => {
+            let event = BackendEvent::InputDeviceRemoved(device.name());
+            let _ = aerugo.comp.backend.x11_mut().events.send(event);
+        }
+
+        InputEvent::PointerMotionAbsolute { event } => {
+            let size = aerugo.comp.backend.x11_mut().window.size();
+            let location = event.position_transformed((size.w as i32, size.h as i32).into());
+            aerugo.comp.pointer_motion(location, event.time_msec());
+        }
+
+        InputEvent::PointerMotion { event } => {
+            let device = event.device().name();
+            let events = aerugo
+                .comp
+                .input
+                .filter(&device)
+                .motion(event.delta_x(), event.delta_y(), event.time_msec());
+            handle_filtered(aerugo, &device, events);
+        }
+
+        InputEvent::PointerButton { event } => {
+            let device = event.device().name();
+            let events =
+                aerugo
+                    .comp
+                    .input
+                    .filter(&device)
+                    .button(event.button_code(), event.state(), event.time_msec());
+            handle_filtered(aerugo, &device, events);
+        }
+
+        InputEvent::Keyboard { event } => {
+            aerugo
+                .comp
+                .keyboard_key(event.key_code(), event.state(), event.time_msec());
+        }
+
+        InputEvent::TouchDown { event } => {
+            let position = (event.x_transformed(1), event.y_transformed(1));
+            aerugo
+                .comp
+                .touch_down(&event.device().name(), event.slot(), position, event.time_msec());
+        }
+
+        InputEvent::TouchMotion { event } => {
+            let position = (event.x_transformed(1), event.y_transformed(1));
+            aerugo
+                .comp
+                .touch_motion(&event.device().name(), event.slot(), position, event.time_msec());
+        }
+
+        InputEvent::TouchUp { event } => {
+            aerugo.comp.touch_up(event.slot(), event.time_msec());
+        }
+
+        InputEvent::TouchCancel { event } => {
+            aerugo.comp.touch_cancel(event.time_msec());
+        }
+
+        _ => {}
+    }
+}
+
+/// Deliver the events of a device which passed the button filter.
+fn handle_filtered(aerugo: &mut Loop, device: &str, events: Vec<FilteredEvent>) {
+    aerugo.comp.pointer_filtered(events);
+
+    // Deliver a press held back for middle button emulation once the other button was not pressed in time.
+    if let Some(time) = aerugo.comp.input.filter(device).schedule_timeout() {
+        let device = device.to_owned();
+        let timer = Timer::from_duration(MIDDLE_EMULATION_TIMEOUT);
+        let _ = aerugo
+            .comp
+            .backend
+            .x11_mut()
+            .r#loop
+            .insert_source(timer, move |_, _, aerugo| {
+                let events = aerugo.comp.input.filter(&device).timeout(time);
+                handle_filtered(aerugo, &device, events);
+                TimeoutAction::Drop
+            });
+    }
+}
+
+/// Follow the size of the window with the mode of the output.
+///
+/// The surface reallocates the buffers presented to the window when the next buffer is acquired.
+fn resize(aerugo: &mut Loop, size: Size<u16, Logical>) {
+    let output = aerugo.comp.output.clone();
+    aerugo
+        .comp
+        .window_resized(&output, (i32::from(size.w), i32::from(size.h)).into());
+}
+
+/// Schedule the next frame once the previous frame was presented.
+fn schedule_frame(aerugo: &mut Loop) {
+    let now = Instant::now();
+    let refresh = frame_scheduler::refresh_interval(&aerugo.comp.output);
+    let delay = {
+        let mut scheduler = frame_scheduler::frame_scheduler(&aerugo.comp.output);
+        scheduler.presented(now, refresh);
+        scheduler.delay(now, refresh)
+    };
+
+    if delay.is_zero() {
+        draw(aerugo);
+        return;
+    }
+
+    let _ = aerugo
+        .comp
+        .backend
+        .x11_mut()
+        .r#loop
+        .insert_source(Timer::from_duration(delay), |_, _, aerugo| {
+            draw(aerugo);
+            TimeoutAction::Drop
+        });
+}
+
 fn draw(aerugo: &mut Loop) {
+    let _span = tracing::trace_span!("draw", output = aerugo.comp.output.name()).entered();
+
+    // The window is not necessarily resized after being mapped, so the first frame sets the initial mode.
+    let size = aerugo.comp.backend.x11_mut().window.size();
+    resize(aerugo, size);
+
+    aerugo.comp.send_preferred_buffer_state();
+
+    let mut timings = FrameTimings::new();
+    let start = Instant::now();
+
+    // The window is redrawn for every presented frame, so animations advance every frame.
+    aerugo.comp.scene.advance_animations(start);
+
+    let cursor_plane_size = aerugo.comp.backend.cursor_plane_size(&aerugo.comp.output);
+    let conversion = color::srgb_conversion(&aerugo.comp.output);
+    let convert = |color: [f32; 4]| conversion.map_or(color, |conversion| conversion.apply_color(color));
     let backend = aerugo.comp.backend.x11_mut();
-    let (buffer, _age) = backend.surface.buffer().unwrap();
-    backend.renderer.bind(buffer).unwrap();
+    let (buffer, age) = backend.surface.buffer().unwrap();
+    backend.gpus.renderer().bind(buffer).unwrap();
+    let area = Rectangle::from_loc_and_size((0, 0), (backend.window.size().w as i32, backend.window.size().h as i32));
+
+    let locked = aerugo.comp.session_lock.is_locked();
+    let on = aerugo.comp.output_power.is_on(&aerugo.comp.output);
+
+    // Surfaces presented in this frame, other surfaces have their frame callbacks throttled.
+    let mut visible = Vec::new();
+
+    let elements_span = tracing::trace_span!("zone", name = "elements").entered();
+
+    // While the session is locked, only the lock surface of the output may be presented.
+    let mut elems: Vec<SceneGraphElement> = if !on {
+        Vec::new()
+    } else if locked {
+        aerugo
+            .comp
+            .session_lock
+            .surface(&aerugo.comp.output)
+            .and_then(|surface| {
+                import_surface_tree(backend.gpus.renderer(), surface).ok()?;
+                visible.extend(frame::surface_tree(surface));
+                Some(vec![SceneGraphElement::from_surface(surface)])
+            })
+            .unwrap_or_default()
+    } else if aerugo.comp.overview.is_some() {
+        let area =
+            Rectangle::from_loc_and_size((0, 0), (backend.window.size().w as i32, backend.window.size().h as i32));
+        let toplevels = aerugo.comp.shell.mapped_toplevels();
+
+        for (_, surface) in &toplevels {
+            if let Err(err) = import_surface_tree(backend.gpus.renderer(), surface) {
+                tracing::debug!(%err, "Failed to import toplevel buffers");
+            }
+            visible.extend(frame::surface_tree(surface));
+        }
 
-    let elems: Vec<SceneGraphElement> = if let Some(hir) = aerugo.comp.scene.get_graph(&aerugo.comp.output) {
-        hir.render_elements(
-            &mut backend.renderer,
+        let overview = aerugo.comp.overview.as_mut().unwrap();
+        overview.update(area, toplevels);
+        overview.render_elements(1.0)
+    } else {
+        // Drag icons follow the pointer above everything but the cursor.
+        let mut elems: Vec<SceneGraphElement> = aerugo.comp.scene.get_drag_icons().render_elements(
+            backend.gpus.renderer(),
             (0, 0).into(),
-            smithay::utils::Scale { x: 1., y: 1. },
+            Scale { x: 1., y: 1. },
             1.0,
-        )
-        .into()
-    } else {
-        Vec::new()
+        );
+
+        // Shell surfaces in the overlay are presented above the content of the wm.
+        elems.extend(aerugo.comp.scene.get_overlay().render_elements::<SceneGraphElement>(
+            backend.gpus.renderer(),
+            (0, 0).into(),
+            Scale { x: 1., y: 1. },
+            1.0,
+        ));
+
+        let graph = aerugo.comp.scene.get_graph(&aerugo.comp.output);
+
+        if let Some(hir) = &graph {
+            elems.extend(hir.render_elements::<SceneGraphElement>(
+                backend.gpus.renderer(),
+                (0, 0).into(),
+                Scale { x: 1., y: 1. },
+                1.0,
+            ));
+        }
+
+        // Visit from top to bottom so surfaces below opaque surfaces are occluded.
+        let mut occlusion = Occlusion::new(Rectangle::from_loc_and_size(
+            (0, 0),
+            (backend.window.size().w as i32, backend.window.size().h as i32),
+        ));
+        let scene = &aerugo.comp.scene;
+
+        for hir in [Some(scene.get_drag_icons()), Some(scene.get_overlay()), graph]
+            .iter()
+            .flatten()
+        {
+            visible.extend(hir.visible_surfaces((0, 0).into(), &mut occlusion));
+        }
+
+        elems
+    };
+
+    if let Some(conversion) = &conversion {
+        elems.iter_mut().for_each(|element| element.convert_color(conversion));
+    }
+
+    let mut content = elems
+        .iter()
+        .map(|element| ElementState::new(element, Scale::from(1.0)))
+        .collect::<Vec<_>>();
+
+    // The software cursor is drawn above everything else.
+    let scale = aerugo.comp.output.current_scale().fractional_scale();
+    let cursor = &mut aerugo.comp.cursor;
+    let cursor_geometry = cursor.geometry(aerugo.comp.pointer_location, scale);
+    let image_size = cursor_geometry.map(|geometry| geometry.size).unwrap_or_default();
+    let software_cursor =
+        cursor_geometry.filter(|_| on && cursor.select_plane(cursor_plane_size, image_size) == CursorPlane::Software);
+    let cursor_damage = cursor.damage(software_cursor);
+
+    let default_cursor = match (software_cursor, cursor.surface()) {
+        (Some(geometry), Some(surface)) => {
+            if import_surface_tree(backend.gpus.renderer(), surface).is_ok() {
+                // A new cursor image redraws the output, moving the cursor only damages the area of the cursor.
+                content.push(ElementState::new(
+                    &SceneGraphElement::from_surface(surface),
+                    Scale::from(scale),
+                ));
+                elems.insert(0, SceneGraphElement::scaled(surface, geometry));
+                visible.extend(frame::surface_tree(surface));
+            }
+
+            None
+        }
+        (geometry, _) => geometry,
+    };
+
+    // Show a banner over the output if the wm is not running because of an error. There is no text rendering,
+    // so the error message is shown in the window title.
+    let error_message = aerugo.comp.wm.error_message();
+
+    if backend.error_message != error_message {
+        match error_message.as_deref().and_then(|message| message.lines().next()) {
+            Some(message) => backend.window.set_title(&format!("{WINDOW_TITLE} - {message}")),
+            None => backend.window.set_title(WINDOW_TITLE),
+        }
+
+        backend.error_message = error_message;
+    }
+
+    let error_banner = backend
+        .error_message
+        .as_ref()
+        .filter(|_| on)
+        .map(|_| Rectangle::from_loc_and_size((0, 0), (backend.window.size().w as i32, ERROR_BANNER_HEIGHT)));
+
+    let clear_color = match (on, locked) {
+        (false, _) => OFF_COLOR,
+        (true, true) => LOCKED_COLOR,
+        (true, false) => BACKGROUND_COLOR,
+    };
+
+    let damage = backend.damage.frame(
+        area,
+        (content, clear_color, error_banner),
+        cursor_damage,
+        usize::from(age),
+    );
+    let damaged = |rect: Rectangle<i32, Physical>| {
+        damage
+            .iter()
+            .filter_map(|damage| damage.intersection(rect))
+            .collect::<Vec<_>>()
     };
 
-    {
+    timings.record("elements", start.elapsed());
+    drop(elements_span);
+
+    timings.zone("render", || {
         let mut frame = backend
-            .renderer
+            .gpus
+            .renderer()
             .render(
                 (backend.window.size().w as i32, backend.window.size().h as i32).into(),
                 Transform::Normal,
             )
             .unwrap();
 
-        frame
-            .clear(
-                [0.8, 0.8, 0.8, 1.0],
-                &[Rectangle::from_loc_and_size(
-                    (0, 0),
-                    (backend.window.size().w as i32, backend.window.size().h as i32),
-                )],
-            )
-            .unwrap();
+        frame.clear(convert(clear_color), &damage).unwrap();
+        draw_render_elements::<GlesRenderer, _, _>(&mut frame, 1.0, &elems, &damage).unwrap();
 
-        draw_render_elements::<GlesRenderer, _, _>(
-            &mut frame,
-            1.0,
-            &elems,
-            &[Rectangle::from_loc_and_size((0, 0), (i32::MAX, i32::MAX))],
-        )
-        .unwrap();
+        if let Some(banner) = error_banner {
+            frame.clear(convert(ERROR_BANNER_COLOR), &damaged(banner)).unwrap();
+        }
+
+        if let Some(cursor) = default_cursor {
+            frame.clear(convert(DEFAULT_CURSOR_COLOR), &damaged(cursor)).unwrap();
+        }
 
         frame.finish().unwrap();
+    });
+
+    // The framebuffer is still bound, so fulfill any captures of the output.
+    let captures = aerugo.comp.screencopy.take_pending(&aerugo.comp.output);
+    timings.zone("screencopy", || {
+        screencopy::submit_captures(backend.gpus.renderer(), captures, backend.damage.last_frame());
+        aerugo.comp.screencasts.submit_output_frame(
+            backend.gpus.renderer(),
+            &aerugo.comp.output,
+            (backend.window.size().w as i32, backend.window.size().h as i32).into(),
+        );
+    });
+
+    tracing::trace_span!("submit").in_scope(|| backend.surface.submit().unwrap());
+    let frame_time = start.elapsed();
+    frame_scheduler::frame_scheduler(&aerugo.comp.output).rendered(frame_time);
+
+    let damage_area = if on {
+        damage
+            .iter()
+            .map(|damage| damage.size.w as u64 * damage.size.h as u64)
+            .sum()
+    } else {
+        0
+    };
+    aerugo.comp.counters.frame(frame_time, damage_area);
+
+    // Screenshots are rendered offscreen, so this must happen after the frame was submitted.
+    let screenshots = aerugo.comp.screenshots.take_pending();
+    timings.zone("screenshots", || {
+        screenshot::submit_screenshots(
+            backend.gpus.renderer(),
+            &aerugo.comp.shell,
+            &mut aerugo.comp.wm,
+            screenshots,
+        );
+        aerugo
+            .comp
+            .screencasts
+            .render_toplevel_frames(backend.gpus.renderer(), &aerugo.comp.shell);
+    });
+
+    // Virtual outputs are only rendered when a capture is waiting or the output is cast, also offscreen.
+    let (mut presented, virtual_visible) = timings.zone("virtual_outputs", || {
+        virtual_output::render_virtual_outputs(
+            backend.gpus.renderer(),
+            &aerugo.comp.scene,
+            &mut aerugo.comp.screencopy,
+            &mut aerugo.comp.screencasts,
+            &aerugo.comp.virtual_outputs,
+        )
+    });
+
+    let output = aerugo.comp.output.clone();
+    aerugo.comp.update_adaptive_sync(&output, &visible);
+    visible.extend(virtual_visible);
+
+    // The wm is not told about frames of an output which is off, since nothing was presented.
+    if on {
+        presented.push(aerugo.comp.output.clone());
+    }
+
+    aerugo.comp.send_frames(&visible, &presented);
+
+    aerugo.comp.render_stats.record(&aerugo.comp.output, timings);
+
+    // Offscreen rendering is submitted after the frame, so buffers are released after all rendering of the frame.
+    if let Some(drm_syncobj) = &mut aerugo.comp.drm_syncobj {
+        drm_syncobj.frame_submitted();
     }
 
-    backend.surface.submit().unwrap();
+    if locked {
+        aerugo.comp.session_lock.frame_presented();
+    }
 }
 
 impl crate::backend::Backend for Backend {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+
+    fn renderer_kind(&self) -> RendererKind {
+        self.renderer_kind
+    }
+
     fn shm_state(&self) -> &ShmState {
         &self.shm_state
     }
 
     fn dmabuf_state(&mut self) -> &mut DmabufState {
-        todo!("X11 does not initialize the dmabuf global yet")
+        &mut self.dmabuf_state
+    }
+
+    fn dmabuf_imported(&mut self, _global: &DmabufGlobal, dmabuf: Dmabuf) -> Result<(), ImportError> {
+        match self.gpus.import_dmabuf(&dmabuf) {
+            Ok((_, path)) => {
+                tracing::trace!(?path, "Imported dmabuf");
+                Ok(())
+            }
+
+            Err(err) => {
+                tracing::debug!(%err, "Failed to import dmabuf");
+                Err(ImportError::Failed)
+            }
+        }
+    }
+
+    fn syncobj_device(&self) -> Option<BorrowedFd<'_>> {
+        self.drm.as_ref().map(AsFd::as_fd)
     }
 
-    fn dmabuf_imported(&mut self, _global: &DmabufGlobal, _dmabuf: Dmabuf) -> Result<(), ImportError> {
-        todo!("X11 does not initialize the dmabuf global yet")
+    fn test_output_configuration(&self, output: &Output, configuration: &OutputConfiguration) -> Result<(), String> {
+        windowed::test_output_configuration("x11", output, configuration)
     }
 
-    fn should_shutdown(&self) -> bool {
+    fn shutdown_reason(&self) -> Option<ShutdownReason> {
         self.shutdown
     }
 }
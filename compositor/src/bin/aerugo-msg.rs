@@ -0,0 +1,290 @@
+//! Send a request to a running Aerugo instance over the IPC socket.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process,
+};
+
+use aerugo_comp::ipc::protocol::{EventKind, Reply, Request, SceneFormat, SOCKET_ENV};
+use clap::{Parser, Subcommand};
+
+/// Control a running Aerugo instance
+#[deny(missing_docs)]
+#[derive(Parser, Debug)]
+#[clap(author, version)]
+struct Args {
+    /// Path to the IPC socket
+    ///
+    /// By default the socket in the `AERUGO_SOCK` environment variable is used.
+    #[clap(short, long)]
+    socket: Option<PathBuf>,
+
+    /// Print replies as a single line of JSON instead of pretty printing them
+    #[clap(short, long)]
+    raw: bool,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[deny(missing_docs)]
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the connected outputs
+    Outputs,
+
+    /// List the toplevels
+    Toplevels,
+
+    /// List the workspaces created by the wm
+    Workspaces,
+
+    /// Show the state of the wm
+    Wm,
+
+    /// Ask the wm to activate a toplevel
+    ActivateToplevel {
+        /// Id of the toplevel
+        id: u64,
+    },
+
+    /// Ask the wm to activate a workspace
+    ActivateWorkspace {
+        /// Id of the workspace
+        id: u32,
+    },
+
+    /// Show the overview
+    ShowOverview,
+
+    /// Hide the overview
+    HideOverview,
+
+    /// Restart the wm
+    ReloadWm,
+
+    /// Write the recent wm traffic to a file
+    DumpWmTrace {
+        /// Only write the traffic from the last number of seconds
+        #[clap(long)]
+        seconds: Option<u64>,
+
+        /// Path of the file to write
+        ///
+        /// By default the trace is written to the state directory of the display server.
+        #[clap(long)]
+        path: Option<PathBuf>,
+    },
+
+    /// Shut the display server down
+    Shutdown,
+
+    /// Create a headless output which can be captured, for example to cast a workspace
+    CreateVirtualOutput {
+        /// Width in pixels
+        width: i32,
+
+        /// Height in pixels
+        height: i32,
+
+        /// Refresh rate in mHz
+        #[clap(long)]
+        refresh: Option<i32>,
+    },
+
+    /// Remove a virtual output
+    DestroyVirtualOutput {
+        /// Name of the virtual output
+        name: String,
+    },
+
+    /// Turn an output on or off
+    OutputPower {
+        /// Name of the output
+        output: String,
+
+        /// `on` or `off`
+        #[clap(value_parser = parse_power)]
+        power: bool,
+    },
+
+    /// Show when frames are rendered, how long rendering takes and how many frames missed their vblank, per output
+    FrameStats,
+
+    /// Show how many frames were rendered and how many client requests were dispatched
+    Counters,
+
+    /// Show how long the wm takes to handle events and how much memory the wm uses
+    WmStats,
+
+    /// Start a program connected to the display server
+    Spawn {
+        /// Start the program in a transient scope of the systemd user instance
+        #[clap(long)]
+        systemd_scope: bool,
+
+        /// The program and the arguments of the program
+        #[clap(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+
+    /// Inspect the display server
+    Debug {
+        #[clap(subcommand)]
+        command: DebugCommand,
+    },
+
+    /// Print events until interrupted
+    Subscribe {
+        /// Kinds of events to print: `toplevel`, `workspace` or `wm`
+        #[clap(required = true, value_parser = parse_event_kind)]
+        events: Vec<EventKind>,
+    },
+}
+
+#[deny(missing_docs)]
+#[derive(Subcommand, Debug)]
+enum DebugCommand {
+    /// Print the recent log messages of the display server, including debug messages
+    Log {
+        /// Only print the last number of messages
+        #[clap(short = 'n', long)]
+        lines: Option<usize>,
+    },
+
+    /// Print the scene graph, to inspect why a surface is not visible
+    Scene {
+        /// Print the scene graph in the DOT language of Graphviz instead of JSON
+        #[clap(long)]
+        dot: bool,
+    },
+}
+
+impl From<Command> for Request {
+    fn from(command: Command) -> Self {
+        match command {
+            Command::Outputs => Request::GetOutputs,
+            Command::Toplevels => Request::GetToplevels,
+            Command::Workspaces => Request::GetWorkspaces,
+            Command::Wm => Request::GetWm,
+            Command::ActivateToplevel { id } => Request::ActivateToplevel { id },
+            Command::ActivateWorkspace { id } => Request::ActivateWorkspace { id },
+            Command::ShowOverview => Request::ShowOverview,
+            Command::HideOverview => Request::HideOverview,
+            Command::ReloadWm => Request::ReloadWm,
+            Command::DumpWmTrace { seconds, path } => Request::DumpWmTrace { seconds, path },
+            Command::Shutdown => Request::Shutdown,
+            Command::CreateVirtualOutput { width, height, refresh } => {
+                Request::CreateVirtualOutput { width, height, refresh }
+            }
+            Command::DestroyVirtualOutput { name } => Request::DestroyVirtualOutput { name },
+            Command::OutputPower { output, power } => Request::SetOutputPower { output, on: power },
+            Command::FrameStats => Request::GetFrameStats,
+            Command::Counters => Request::GetCounters,
+            Command::WmStats => Request::GetWmStats,
+            Command::Spawn { systemd_scope, command } => Request::Spawn { command, systemd_scope },
+            Command::Debug {
+                command: DebugCommand::Log { lines },
+            } => Request::GetLog { lines },
+            Command::Debug {
+                command: DebugCommand::Scene { dot },
+            } => Request::GetScene {
+                format: if dot { SceneFormat::Dot } else { SceneFormat::Json },
+            },
+            Command::Subscribe { events } => Request::Subscribe { events },
+        }
+    }
+}
+
+fn parse_power(power: &str) -> Result<bool, String> {
+    match power {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("unknown power state `{power}`, expected `on` or `off`")),
+    }
+}
+
+fn parse_event_kind(kind: &str) -> Result<EventKind, String> {
+    serde_json::from_value(serde_json::Value::String(kind.into())).map_err(|_| format!("unknown event kind `{kind}`"))
+}
+
+fn main() {
+    let args = Args::parse();
+
+    if let Err(err) = run(args) {
+        eprintln!("aerugo-msg: {err}");
+        process::exit(1);
+    }
+}
+
+fn run(args: Args) -> io::Result<()> {
+    let path = args
+        .socket
+        .or_else(|| std::env::var_os(SOCKET_ENV).map(PathBuf::from))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{SOCKET_ENV} is not set")))?;
+
+    let subscribe = matches!(args.command, Command::Subscribe { .. });
+    let plain = matches!(
+        args.command,
+        Command::Debug {
+            command: DebugCommand::Log { .. } | DebugCommand::Scene { dot: true }
+        }
+    );
+    let request = Request::from(args.command);
+
+    let mut stream = UnixStream::connect(&path)?;
+    let mut line = serde_json::to_vec(&request)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+
+    let mut lines = BufReader::new(stream).lines();
+    let reply = lines
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "the display server closed the connection"))??;
+
+    match serde_json::from_str::<Reply>(&reply)? {
+        Reply::Ok { data } => {
+            match data {
+                // Log messages and graphs are printed as text, so they can be read or piped to other tools.
+                Some(serde_json::Value::Array(lines)) if plain && !args.raw => {
+                    let mut stdout = io::stdout().lock();
+
+                    for line in lines {
+                        writeln!(stdout, "{}", line.as_str().unwrap_or_default())?;
+                    }
+                }
+
+                Some(serde_json::Value::String(text)) if plain && !args.raw => print!("{text}"),
+
+                Some(data) => print(&data, args.raw)?,
+                None => {}
+            }
+        }
+
+        Reply::Error { message } => return Err(io::Error::new(io::ErrorKind::Other, message)),
+    }
+
+    if subscribe {
+        for line in lines {
+            let event = serde_json::from_str::<serde_json::Value>(&line?)?;
+            print(&event, args.raw)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn print(value: &serde_json::Value, raw: bool) -> io::Result<()> {
+    let text = if raw {
+        serde_json::to_string(value)?
+    } else {
+        serde_json::to_string_pretty(value)?
+    };
+
+    // Events are printed as they arrive, so flush every line.
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{text}")?;
+    stdout.flush()
+}
@@ -1,5 +1,8 @@
 //! Command line argument parsing using clap.
 
+use std::path::PathBuf;
+
+use aerugo_comp::backend::{renderer::RendererSelection, selection::BackendSelection};
 use clap::{Parser, ValueEnum};
 
 /// The Aerugo wayland compositor
@@ -11,16 +14,12 @@ pub struct AerugoArgs {
     ///
     /// By default the backend will be selected depending on the environment (`auto`).
     ///
-    /// There are two primary backends that may be chosen:
-    ///
-    /// `kms`: Used when the compositor is run under a session. This backend is used when aerugo is your primary display
-    /// server.
+    /// `windowed`: The compositor is run inside a window as a client of the display server of the session. The
+    /// windowed backend is useful for testing purposes.
     ///
-    /// `windowed`: The compositor is run inside a window as an X11 or Wayland client. The windowed backend is useful
-    /// for testing purposes.
+    /// The `x11` option acts like `windowed`, but always runs aerugo as an X11 client.
     ///
-    /// The `x11` and `wayland` options both act like `windowed`, but allow specifying whether aerugo is run as an X11
-    /// or Wayland client.
+    /// Right now the x11 backend is the only backend, so aerugo always runs inside an X11 session.
     #[clap(value_enum, default_value_t, short, long)]
     pub backend: Backend,
 
@@ -28,10 +27,37 @@ pub struct AerugoArgs {
     ///
     /// This allows overriding the renderer to use at runtime. This may be useful in case of driver bugs.
     ///
+    /// By default the renderer is read from the `AERUGO_RENDERER` environment variable. If that is not set, the
+    /// most optimal renderer supported by the backend is selected.
+    ///
     /// Right now only the OpenGL ES renderer is supported. In the future a Vulkan renderer will be available.
     #[clap(value_enum, default_value_t, long)]
     pub renderer: Renderer,
-    // TODO: WM process to start
+
+    /// Path to the wasm module of the window manager
+    ///
+    /// The `wm` option of the configuration file takes precedence.
+    #[clap(long)]
+    pub wm: Option<PathBuf>,
+
+    /// Path to the configuration file
+    ///
+    /// By default `$XDG_CONFIG_HOME/aerugo/config.toml` is used.
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+
+    /// Post protocol errors for protocol violations which are otherwise tolerated
+    ///
+    /// This is intended for running test suites against the compositor.
+    #[clap(long)]
+    pub strict: bool,
+
+    /// Name of the Wayland socket in `$XDG_RUNTIME_DIR`
+    ///
+    /// By default the socket passed by systemd socket activation is used. If the compositor was not socket
+    /// activated, the first available `wayland-N` socket is used.
+    #[clap(long)]
+    pub socket: Option<String>,
     // TODO: How should the WM spawn privileged clients?
 }
 
@@ -43,26 +69,24 @@ pub enum Backend {
     #[default]
     Auto,
 
-    /// Launch the compositor using kernel mode setting.
-    ///
-    /// This should be used if you launch the compositor from a TTY.
-    #[clap(alias("tty"))]
-    Kms,
-
     /// Launch the compositor inside a window.
-    ///
-    /// This will select Wayland or X11 as appropriate.
     Windowed,
 
-    /// Launch the compositor inside a window as a Wayland client.
-    #[clap(alias("wl"))]
-    Wayland,
-
     /// Launch the compositor inside a window as an X11 client.
     #[clap(alias("x"))]
     X11,
 }
 
+impl From<Backend> for BackendSelection {
+    fn from(backend: Backend) -> Self {
+        match backend {
+            Backend::Auto => BackendSelection::Auto,
+            Backend::Windowed => BackendSelection::Windowed,
+            Backend::X11 => BackendSelection::X11,
+        }
+    }
+}
+
 /// Enum containing all possible renderer backends
 #[deny(missing_docs)]
 #[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
@@ -79,3 +103,12 @@ pub enum Renderer {
     // #[clap(alias("vk"))]
     // Vulkan, // TODO
 }
+
+impl From<Renderer> for RendererSelection {
+    fn from(renderer: Renderer) -> Self {
+        match renderer {
+            Renderer::Default => RendererSelection::from_env(),
+            Renderer::Gles => RendererSelection::Gles,
+        }
+    }
+}
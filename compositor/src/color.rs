@@ -0,0 +1,327 @@
+//! Color management groundwork
+//!
+//! Every output has a [`ColorState`], which is the color space its pixels are encoded in. Outputs are sRGB unless
+//! configured otherwise (see [`OutputConfig`](crate::config::OutputConfig)).
+//!
+//! Colors drawn by the display server, such as the solid colors of the wm and the background, are given in sRGB
+//! and converted to the color state of the output using a [`ColorConversion`]: the color is decoded to linear
+//! light, converted between the primaries and encoded for the output. The color management protocol is not stable
+//! yet, so surfaces are presented as if they had the color state of the output.
+
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use smithay::output::Output;
+
+/// The luminance of SDR reference white in cd/m², which linear light values of 1.0 correspond to.
+pub const REFERENCE_WHITE_LUMINANCE: f32 = 203.0;
+
+/// The luminance encoded as the largest PQ value in cd/m².
+const PQ_MAX_LUMINANCE: f32 = 10000.0;
+
+/// The chromaticity of the D65 white point, which every supported color space uses.
+const D65: [f32; 2] = [0.3127, 0.3290];
+
+type Matrix = [[f32; 3]; 3];
+
+/// The color primaries of a color space.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Primaries {
+    /// The primaries of sRGB and BT.709.
+    #[default]
+    Srgb,
+
+    DisplayP3,
+
+    /// The primaries of BT.2020 and BT.2100, used by HDR content.
+    Bt2020,
+}
+
+impl Primaries {
+    /// The chromaticities of the red, green and blue primaries.
+    pub fn chromaticities(self) -> [[f32; 2]; 3] {
+        match self {
+            Self::Srgb => [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06]],
+            Self::DisplayP3 => [[0.680, 0.320], [0.265, 0.690], [0.150, 0.060]],
+            Self::Bt2020 => [[0.708, 0.292], [0.170, 0.797], [0.131, 0.046]],
+        }
+    }
+
+    /// The matrix converting linear RGB with these primaries to CIE XYZ.
+    fn to_xyz(self) -> Matrix {
+        let xyz = |[x, y]: [f32; 2]| [x / y, 1.0, (1.0 - x - y) / y];
+        let [red, green, blue] = self.chromaticities().map(xyz);
+        let primaries = [
+            [red[0], green[0], blue[0]],
+            [red[1], green[1], blue[1]],
+            [red[2], green[2], blue[2]],
+        ];
+
+        // Scale the primaries so that RGB white is the white point.
+        let scale = mul_vector(&invert(&primaries), xyz(D65));
+
+        primaries.map(|row| [row[0] * scale[0], row[1] * scale[1], row[2] * scale[2]])
+    }
+}
+
+/// How linear light is encoded in pixel values.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferFunction {
+    /// The piecewise sRGB transfer function.
+    #[default]
+    Srgb,
+
+    /// A pure power function with an exponent of 2.2.
+    Gamma22,
+
+    Linear,
+
+    /// The perceptual quantizer of SMPTE ST 2084, used by HDR10.
+    Pq,
+
+    /// Hybrid log-gamma of ARIB STD-B67.
+    Hlg,
+}
+
+impl TransferFunction {
+    /// Decode a pixel value to linear light, where 1.0 is SDR reference white.
+    pub fn decode(self, value: f32) -> f32 {
+        match self {
+            Self::Srgb if value <= 0.04045 => value / 12.92,
+            Self::Srgb => ((value + 0.055) / 1.055).powf(2.4),
+            Self::Gamma22 => value.max(0.0).powf(2.2),
+            Self::Linear => value,
+            Self::Pq => {
+                let p = value.clamp(0.0, 1.0).powf(1.0 / PQ_M2);
+                let luminance = ((p - PQ_C1).max(0.0) / (PQ_C2 - PQ_C3 * p)).powf(1.0 / PQ_M1);
+                luminance * PQ_MAX_LUMINANCE / REFERENCE_WHITE_LUMINANCE
+            }
+            // Scene light is not scaled to display light, so the nominal peak is 1.0.
+            Self::Hlg if value <= 0.5 => value * value / 3.0,
+            Self::Hlg => (((value - HLG_C) / HLG_A).exp() + HLG_B) / 12.0,
+        }
+    }
+
+    /// Encode linear light, where 1.0 is SDR reference white, as a pixel value.
+    pub fn encode(self, value: f32) -> f32 {
+        match self {
+            Self::Srgb if value <= 0.0031308 => value * 12.92,
+            Self::Srgb => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+            Self::Gamma22 => value.max(0.0).powf(1.0 / 2.2),
+            Self::Linear => value,
+            Self::Pq => {
+                let y = (value * REFERENCE_WHITE_LUMINANCE / PQ_MAX_LUMINANCE)
+                    .clamp(0.0, 1.0)
+                    .powf(PQ_M1);
+                ((PQ_C1 + PQ_C2 * y) / (1.0 + PQ_C3 * y)).powf(PQ_M2)
+            }
+            Self::Hlg if value <= 1.0 / 12.0 => (3.0 * value.max(0.0)).sqrt(),
+            Self::Hlg => HLG_A * (12.0 * value - HLG_B).ln() + HLG_C,
+        }
+    }
+
+    /// Whether the transfer function encodes light brighter than SDR reference white.
+    pub fn is_hdr(self) -> bool {
+        matches!(self, Self::Pq | Self::Hlg)
+    }
+}
+
+const PQ_M1: f32 = 2610.0 / 16384.0;
+const PQ_M2: f32 = 2523.0 / 4096.0 * 128.0;
+const PQ_C1: f32 = 3424.0 / 4096.0;
+const PQ_C2: f32 = 2413.0 / 4096.0 * 32.0;
+const PQ_C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+const HLG_A: f32 = 0.17883277;
+const HLG_B: f32 = 0.28466892;
+const HLG_C: f32 = 0.55991073;
+
+/// The color space pixels are encoded in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ColorState {
+    pub primaries: Primaries,
+    pub transfer_function: TransferFunction,
+}
+
+impl ColorState {
+    pub const SRGB: Self = Self {
+        primaries: Primaries::Srgb,
+        transfer_function: TransferFunction::Srgb,
+    };
+
+    pub fn is_hdr(&self) -> bool {
+        self.transfer_function.is_hdr()
+    }
+}
+
+/// The conversion of pixels from one color state to another.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorConversion {
+    /// Decodes the source pixels to linear light.
+    pub source: TransferFunction,
+
+    /// Converts linear RGB from the source primaries to the target primaries.
+    pub matrix: [[f32; 3]; 3],
+
+    /// Encodes linear light as target pixels.
+    pub target: TransferFunction,
+}
+
+impl ColorConversion {
+    /// The conversion between two color states, or [`None`] if the color states are the same.
+    pub fn new(source: ColorState, target: ColorState) -> Option<Self> {
+        if source == target {
+            return None;
+        }
+
+        let matrix = mul(&invert(&target.primaries.to_xyz()), &source.primaries.to_xyz());
+
+        Some(Self {
+            source: source.transfer_function,
+            matrix,
+            target: target.transfer_function,
+        })
+    }
+
+    /// Convert a pixel.
+    ///
+    /// Light which the target cannot encode is clipped, there is no tone mapping yet.
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let linear = rgb.map(|value| self.source.decode(value));
+        let converted = mul_vector(&self.matrix, linear);
+        let max = if self.target.is_hdr() { f32::MAX } else { 1.0 };
+
+        converted.map(|value| self.target.encode(value.clamp(0.0, max)))
+    }
+
+    /// Convert a color which is not premultiplied, keeping the alpha.
+    pub fn apply_color(&self, [r, g, b, a]: [f32; 4]) -> [f32; 4] {
+        let [r, g, b] = self.apply([r, g, b]);
+        [r, g, b, a]
+    }
+}
+
+/// The color state of an output.
+pub fn output_color_state(output: &Output) -> ColorState {
+    output
+        .user_data()
+        .get::<Mutex<ColorState>>()
+        .map(|state| *state.lock().unwrap())
+        .unwrap_or_default()
+}
+
+pub fn set_output_color_state(output: &Output, state: ColorState) {
+    let user_data = output.user_data();
+    user_data.insert_if_missing_threadsafe(|| Mutex::new(ColorState::SRGB));
+    *user_data.get::<Mutex<ColorState>>().unwrap().lock().unwrap() = state;
+}
+
+/// The conversion of sRGB colors drawn by the display server to the color state of an output.
+pub fn srgb_conversion(output: &Output) -> Option<ColorConversion> {
+    ColorConversion::new(ColorState::SRGB, output_color_state(output))
+}
+
+fn mul(a: &Matrix, b: &Matrix) -> Matrix {
+    std::array::from_fn(|row| std::array::from_fn(|col| (0..3).map(|i| a[row][i] * b[i][col]).sum::<f32>()))
+}
+
+fn mul_vector(m: &Matrix, v: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn invert(m: &Matrix) -> Matrix {
+    let cofactor = |row: usize, col: usize| {
+        let (r0, r1) = ((row + 1) % 3, (row + 2) % 3);
+        let (c0, c1) = ((col + 1) % 3, (col + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+
+    let det = (0..3).map(|col| m[0][col] * cofactor(0, col)).sum::<f32>();
+
+    // The inverse is the transposed cofactor matrix divided by the determinant.
+    std::array::from_fn(|row| std::array::from_fn(|col| cofactor(col, row) / det))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColorConversion, ColorState, Primaries, TransferFunction};
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        for (a, b) in a.iter().zip(b) {
+            assert!((a - b).abs() < 1e-3, "{a:?} != {b:?}");
+        }
+    }
+
+    #[test]
+    fn same_color_state_needs_no_conversion() {
+        assert_eq!(ColorConversion::new(ColorState::SRGB, ColorState::SRGB), None);
+    }
+
+    #[test]
+    fn colors_keep_alpha() {
+        let linear = ColorState {
+            transfer_function: TransferFunction::Linear,
+            ..ColorState::SRGB
+        };
+        let conversion = ColorConversion::new(ColorState::SRGB, linear).unwrap();
+
+        let [r, g, b, a] = conversion.apply_color([0.5, 1.0, 0.0, 0.25]);
+        assert_close([r, g, b], [0.214, 1.0, 0.0]);
+        assert_eq!(a, 0.25);
+    }
+
+    #[test]
+    fn transfer_functions_round_trip() {
+        for function in [
+            TransferFunction::Srgb,
+            TransferFunction::Gamma22,
+            TransferFunction::Linear,
+            TransferFunction::Pq,
+            TransferFunction::Hlg,
+        ] {
+            for value in [0.0, 0.02, 0.25, 0.5, 0.75, 1.0] {
+                let round_trip = function.encode(function.decode(value));
+                assert!(
+                    (round_trip - value).abs() < 1e-3,
+                    "{function:?}: {value} -> {round_trip}"
+                );
+            }
+        }
+
+        // SDR reference white is encoded at roughly 58% of the PQ range.
+        assert!((TransferFunction::Pq.encode(1.0) - 0.58).abs() < 0.01);
+    }
+
+    #[test]
+    fn white_is_preserved_between_primaries() {
+        let bt2020 = ColorState {
+            primaries: Primaries::Bt2020,
+            transfer_function: TransferFunction::Linear,
+        };
+        let srgb = ColorState {
+            transfer_function: TransferFunction::Linear,
+            ..ColorState::SRGB
+        };
+
+        let to_bt2020 = ColorConversion::new(srgb, bt2020).unwrap();
+        assert_close(to_bt2020.apply([1.0, 1.0, 1.0]), [1.0, 1.0, 1.0]);
+
+        // Saturated sRGB red is inside the BT.2020 gamut, so converting back restores the color.
+        let red = to_bt2020.apply([1.0, 0.0, 0.0]);
+        assert!(red[1] > 0.0 && red[0] < 1.0);
+        assert_close(ColorConversion::new(bt2020, srgb).unwrap().apply(red), [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn sdr_targets_clip() {
+        let pq = ColorState {
+            primaries: Primaries::Bt2020,
+            transfer_function: TransferFunction::Pq,
+        };
+        let conversion = ColorConversion::new(pq, ColorState::SRGB).unwrap();
+
+        assert_close(conversion.apply([1.0, 1.0, 1.0]), [1.0, 1.0, 1.0]);
+    }
+}
@@ -0,0 +1,721 @@
+//! Compositor configuration
+//!
+//! The configuration is read from a TOML file, by default `$XDG_CONFIG_HOME/aerugo/config.toml`. The file is
+//! watched for changes (see [`watcher`]) and changes are applied to the running compositor.
+//!
+//! Not every option can be changed at runtime. Changing the socket name or the renderer requires a restart of the
+//! compositor.
+//!
+//! An example configuration:
+//!
+//! ```toml
+//! wm = "/usr/share/aerugo/tiling_wm.wasm"
+//! socket = "wayland-1"
+//! renderer = "gles"
+//!
+//! [keyboard]
+//! layout = "us,de"
+//! options = "grp:alt_shift_toggle"
+//! repeat_rate = 30
+//! repeat_delay = 300
+//!
+//! [transactions]
+//! configure_timeout = 500
+//! timeout_policy = "apply"
+//!
+//! [idle]
+//! blank_timeout = 600
+//!
+//! [limits]
+//! requests_per_second = 20000
+//! max_surfaces = 4096
+//! max_buffer_size = 16384
+//!
+//! [session]
+//! lock_command = ["swaylock"]
+//! lock_before_sleep = true
+//!
+//! [night_light]
+//! enabled = true
+//! temperature = 4000
+//!
+//! [wm_recovery]
+//! max_restarts = 5
+//! restart_window = 120
+//! max_callback_time = 500
+//!
+//! [wm_wasi]
+//! enabled = true
+//! config_dir = "/home/user/.config/aerugo/wm"
+//!
+//! [remote_desktop]
+//! apps = ["org.remmina.Remmina"]
+//!
+//! [[outputs]]
+//! name = "DP-1"
+//! position = [1920, 0]
+//! scale = 1.5
+//! primaries = "bt2020"
+//! transfer_function = "pq"
+//! adaptive_sync = true
+//!
+//! [[inputs]]
+//! name = "ELAN Touchscreen"
+//! output = "DP-1"
+//!
+//! [[clients]]
+//! executable = "/usr/bin/grim"
+//! globals = ["screencopy"]
+//!
+//! [[clients]]
+//! executable = "/usr/bin/wl-paste"
+//! globals = ["data-control"]
+//!
+//! [[clients]]
+//! executable = "/usr/bin/gammastep"
+//! globals = ["gamma-control"]
+//!
+//! [[rules]]
+//! app_id = "foot"
+//! decorations = "server"
+//! title_bar = false
+//! ```
+
+pub mod watcher;
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use wm_runtime::{ExecutionLimits, RetryPolicy, WasiConfig};
+
+use crate::{
+    backend::renderer::RendererSelection,
+    color::{ColorState, Primaries, TransferFunction},
+    gamma::NightLightConfig,
+    input::InputConfig,
+    policy::{ClientMatcher, ClientPolicy},
+    rules::WindowRule,
+    state::PrivilegedGlobals,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read config: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("failed to parse config: {0}")]
+    Parse(#[from] toml::de::Error),
+
+    #[error("unknown privileged global \"{0}\"")]
+    UnknownGlobal(String),
+
+    #[error("client rule must specify exactly one of executable, pid or systemd_unit")]
+    InvalidClientRule,
+
+    #[error("transactions.configure_timeout must be greater than 0")]
+    ZeroConfigureTimeout,
+}
+
+/// The configuration of the compositor.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Path to the wasm module of the wm.
+    pub wm: Option<PathBuf>,
+
+    /// Name of the Wayland socket.
+    ///
+    /// If not set, the first available `wayland-N` socket is used.
+    pub socket: Option<String>,
+
+    /// The renderer to use, see [`renderer`](crate::backend::renderer).
+    ///
+    /// Takes precedence over the renderer selected on the command line.
+    pub renderer: Option<RendererSelection>,
+
+    pub keyboard: KeyboardConfig,
+
+    pub transactions: TransactionConfig,
+
+    pub idle: IdleConfig,
+
+    pub session: SessionConfig,
+
+    pub limits: LimitsConfig,
+
+    /// See [`gamma`](crate::gamma).
+    pub night_light: NightLightConfig,
+
+    pub wm_recovery: WmRecoveryConfig,
+
+    pub wm_wasi: WmWasiConfig,
+
+    pub remote_desktop: RemoteDesktopConfig,
+
+    /// Overrides for specific outputs.
+    pub outputs: Vec<OutputConfig>,
+
+    /// Overrides for specific input devices, see [`input`](crate::input).
+    pub inputs: Vec<InputConfig>,
+
+    /// Clients which are allowed to see privileged globals.
+    pub clients: Vec<ClientConfig>,
+
+    /// Window rules, see [`rules`](crate::rules).
+    pub rules: Vec<WindowRule>,
+}
+
+/// Keyboard configuration.
+///
+/// The keymap options are the xkb RMLVO names. Empty values use the xkb defaults.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardConfig {
+    pub rules: String,
+    pub model: String,
+    pub layout: String,
+    pub variant: String,
+    pub options: Option<String>,
+
+    /// Key repeats per second.
+    pub repeat_rate: i32,
+
+    /// Delay before a held key repeats, in milliseconds.
+    pub repeat_delay: i32,
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> Self {
+        Self {
+            rules: String::new(),
+            model: String::new(),
+            layout: String::new(),
+            variant: String::new(),
+            options: None,
+            repeat_rate: 25,
+            repeat_delay: 600,
+        }
+    }
+}
+
+impl KeyboardConfig {
+    /// Whether the keymap differs between the configurations.
+    pub fn keymap_changed(&self, other: &Self) -> bool {
+        self.rules != other.rules
+            || self.model != other.model
+            || self.layout != other.layout
+            || self.variant != other.variant
+            || self.options != other.options
+    }
+}
+
+/// How long the compositor waits on clients.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransactionConfig {
+    /// How long a client may take to ack a configure from the wm, in milliseconds. Must be greater than 0.
+    pub configure_timeout: u64,
+
+    /// What happens to a configure which was not acked in time.
+    pub timeout_policy: TimeoutPolicy,
+}
+
+impl Default for TransactionConfig {
+    fn default() -> Self {
+        Self {
+            configure_timeout: 1000,
+            timeout_policy: TimeoutPolicy::default(),
+        }
+    }
+}
+
+impl TransactionConfig {
+    pub fn configure_timeout(&self) -> Duration {
+        Duration::from_millis(self.configure_timeout)
+    }
+}
+
+/// Idle behavior, see [`idle`](crate::idle).
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdleConfig {
+    /// How long the user must be idle before the outputs are blanked, in seconds.
+    ///
+    /// If not set, the outputs are never blanked.
+    pub blank_timeout: Option<u64>,
+}
+
+impl IdleConfig {
+    pub fn blank_timeout(&self) -> Option<Duration> {
+        self.blank_timeout.map(Duration::from_secs)
+    }
+}
+
+/// Session management, see [`session`](crate::session).
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// The lock screen started when the display server locks the session, as a program and arguments.
+    ///
+    /// The lock screen must lock the session using `ext-session-lock-v1`. If not set, the display server does not
+    /// lock the session.
+    pub lock_command: Vec<String>,
+
+    /// Lock the session before the system sleeps.
+    pub lock_before_sleep: bool,
+}
+
+/// Resource limits of clients, see [`limits`](crate::limits).
+///
+/// A client exceeding a limit is disconnected. A limit of `0` disables the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// How many requests of a client are dispatched per second.
+    pub requests_per_second: u32,
+
+    /// How many surfaces a client may have.
+    pub max_surfaces: u32,
+
+    /// The largest width or height of a buffer attached to a surface, in pixels.
+    pub max_buffer_size: u32,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 20_000,
+            max_surfaces: 4096,
+            max_buffer_size: 16384,
+        }
+    }
+}
+
+/// Access to the remote desktop portal.
+///
+/// Applications are identified by the app id `xdg-desktop-portal` passes to the portal. Applications running
+/// outside of a sandbox have an empty app id and can never control the desktop.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RemoteDesktopConfig {
+    /// App ids of applications which may control the desktop without a grant in the permission store.
+    pub apps: Vec<String>,
+}
+
+impl RemoteDesktopConfig {
+    /// Whether an application may start a remote desktop session.
+    ///
+    /// `granted` is whether the permission store of `xdg-desktop-portal` grants the application access.
+    pub fn allows(&self, app_id: &str, granted: bool) -> bool {
+        !app_id.is_empty() && (granted || self.apps.iter().any(|app| app == app_id))
+    }
+}
+
+/// How the wm runtime recovers a wm which failed, see [`wm`](crate::wm).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WmRecoveryConfig {
+    /// How many times a failed wm is instantiated again within the restart window.
+    pub max_restarts: u32,
+
+    /// The restart window, in seconds.
+    pub restart_window: u64,
+
+    /// How long the wm may take to handle an event before the wm is considered stuck, in milliseconds.
+    pub max_callback_time: u64,
+}
+
+impl Default for WmRecoveryConfig {
+    fn default() -> Self {
+        let policy = RetryPolicy::default();
+
+        Self {
+            max_restarts: policy.max_restarts,
+            restart_window: policy.window.as_secs(),
+            max_callback_time: ExecutionLimits::default().max_callback_time.as_millis() as u64,
+        }
+    }
+}
+
+impl WmRecoveryConfig {
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_restarts: self.max_restarts,
+            window: Duration::from_secs(self.restart_window),
+        }
+    }
+
+    pub fn execution_limits(&self) -> ExecutionLimits {
+        ExecutionLimits {
+            max_callback_time: Duration::from_millis(self.max_callback_time),
+        }
+    }
+}
+
+/// Sandboxed WASI access of the wm.
+///
+/// If enabled, the wm may read the configuration directory and use the clocks and random number generator of the
+/// host. The configuration directory defaults to `$XDG_CONFIG_HOME/aerugo/wm`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WmWasiConfig {
+    pub enabled: bool,
+    pub config_dir: Option<PathBuf>,
+}
+
+impl WmWasiConfig {
+    /// The WASI features available to the wm, or [`None`] if WASI is disabled.
+    pub fn wasi_config(&self) -> Option<WasiConfig> {
+        self.enabled.then(|| WasiConfig {
+            config_dir: self
+                .config_dir
+                .clone()
+                .or_else(|| config_home().map(|dir| dir.join("aerugo/wm"))),
+        })
+    }
+}
+
+/// What happens to a configure from the wm which was not acked in time.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutPolicy {
+    /// Cancel the configure. The toplevel keeps its current state.
+    #[default]
+    Cancel,
+
+    /// Stop waiting and apply the state of the configure, as if the client acked it.
+    Apply,
+}
+
+/// Configuration overrides for an output.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Name of the output the overrides apply to.
+    pub name: String,
+
+    /// Position of the output in the global compositor space.
+    pub position: Option<(i32, i32)>,
+
+    pub scale: Option<f64>,
+
+    /// The primaries of the color space of the output, sRGB by default.
+    pub primaries: Option<Primaries>,
+
+    /// The transfer function of the color space of the output, sRGB by default.
+    ///
+    /// HDR outputs use `pq`.
+    pub transfer_function: Option<TransferFunction>,
+
+    /// Whether adaptive sync is enabled while a fullscreen toplevel is visible on the output.
+    pub adaptive_sync: Option<bool>,
+}
+
+impl OutputConfig {
+    pub fn color_state(&self) -> ColorState {
+        ColorState {
+            primaries: self.primaries.unwrap_or_default(),
+            transfer_function: self.transfer_function.unwrap_or_default(),
+        }
+    }
+}
+
+/// A client allowed to see privileged globals.
+///
+/// Exactly one of `executable`, `pid` and `systemd_unit` must be set.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig {
+    pub executable: Option<PathBuf>,
+    pub pid: Option<u32>,
+    pub systemd_unit: Option<String>,
+
+    /// Names of the privileged globals the client may see.
+    pub globals: Vec<String>,
+}
+
+impl Config {
+    /// Read the configuration from a file.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path)?;
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let config: Config = toml::from_str(contents)?;
+
+        // Validate the client rules now so errors are reported when loading.
+        config.policy()?;
+
+        // Every configure would expire as soon as it is sent.
+        if config.transactions.configure_timeout == 0 {
+            return Err(ConfigError::ZeroConfigureTimeout);
+        }
+
+        Ok(config)
+    }
+
+    /// The client policy described by the configuration.
+    pub fn policy(&self) -> Result<ClientPolicy, ConfigError> {
+        self.clients.iter().try_fold(ClientPolicy::default(), |policy, client| {
+            let matcher = match (&client.executable, client.pid, &client.systemd_unit) {
+                (Some(path), None, None) => ClientMatcher::Executable(path.clone()),
+                (None, Some(pid), None) => ClientMatcher::Pid(pid),
+                (None, None, Some(unit)) => ClientMatcher::SystemdUnit(unit.clone()),
+                _ => return Err(ConfigError::InvalidClientRule),
+            };
+
+            let globals = client
+                .globals
+                .iter()
+                .try_fold(PrivilegedGlobals::empty(), |globals, name| {
+                    parse_global(name).map(|global| globals | global)
+                })?;
+
+            Ok(policy.allow(matcher, globals))
+        })
+    }
+}
+
+fn parse_global(name: &str) -> Result<PrivilegedGlobals, ConfigError> {
+    Ok(match name {
+        "foreign-toplevel-list" => PrivilegedGlobals::FOREIGN_TOPLEVEL_LIST,
+        "foreign-toplevel-state" => PrivilegedGlobals::FOREIGN_TOPLEVEL_STATE,
+        "session-lock" => PrivilegedGlobals::SESSION_LOCK,
+        "layer-shell" => PrivilegedGlobals::LAYER_SHELL,
+        "aerugo-shell" => PrivilegedGlobals::AERUGO_SHELL,
+        "aerugo-debug" => PrivilegedGlobals::DEBUG,
+        "screencopy" => PrivilegedGlobals::SCREENCOPY,
+        "output-management" => PrivilegedGlobals::OUTPUT_MANAGEMENT,
+        "data-control" => PrivilegedGlobals::DATA_CONTROL,
+        "gamma-control" => PrivilegedGlobals::GAMMA_CONTROL,
+        _ => return Err(ConfigError::UnknownGlobal(name.into())),
+    })
+}
+
+/// `$XDG_CONFIG_HOME/aerugo/config.toml`, falling back to `~/.config/aerugo/config.toml`.
+pub fn default_path() -> Option<PathBuf> {
+    config_home().map(|dir| dir.join("aerugo/config.toml"))
+}
+
+/// `$XDG_CONFIG_HOME`, falling back to `~/.config`.
+fn config_home() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use wm_runtime::{ExecutionLimits, RetryPolicy, WasiConfig};
+
+    use crate::{
+        backend::renderer::RendererSelection,
+        color::{ColorState, Primaries, TransferFunction},
+        gamma::TimeOfDay,
+        input::Calibration,
+        policy::{ClientIdentity, ClientPolicy},
+        rules::{Color, ForcedDecorations},
+        state::PrivilegedGlobals,
+    };
+
+    use super::{Config, ConfigError, LimitsConfig, RemoteDesktopConfig, TimeoutPolicy};
+
+    #[test]
+    fn parse_empty() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn parse_full() {
+        let config = Config::parse(
+            r##"
+            wm = "wm.wasm"
+            socket = "wayland-1"
+            renderer = "gles"
+
+            [keyboard]
+            layout = "de"
+            repeat_rate = 30
+
+            [transactions]
+            timeout_policy = "apply"
+
+            [idle]
+            blank_timeout = 300
+
+            [session]
+            lock_command = ["swaylock", "-f"]
+            lock_before_sleep = true
+
+            [limits]
+            max_surfaces = 256
+            max_buffer_size = 0
+
+            [night_light]
+            enabled = true
+            start = "22:00"
+
+            [wm_recovery]
+            max_restarts = 5
+            max_callback_time = 250
+
+            [wm_wasi]
+            enabled = true
+            config_dir = "/etc/aerugo/wm"
+
+            [remote_desktop]
+            apps = ["org.remmina.Remmina"]
+
+            [[outputs]]
+            name = "DP-1"
+            position = [1920, 0]
+            scale = 1.5
+            transfer_function = "pq"
+            adaptive_sync = true
+
+            [[inputs]]
+            name = "ELAN Touchscreen"
+            output = "DP-1"
+            calibration = [0.0, -1.0, 1.0, 1.0, 0.0, 0.0]
+            middle_emulation = true
+            remap = [[0x110, 0x111]]
+
+            [[clients]]
+            executable = "/usr/bin/grim"
+            globals = ["screencopy"]
+
+            [[rules]]
+            app_id = "foot"
+            decorations = "server"
+            border_color = "#3584e4"
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(config.wm, Some(PathBuf::from("wm.wasm")));
+        assert_eq!(config.socket.as_deref(), Some("wayland-1"));
+        assert_eq!(config.renderer, Some(RendererSelection::Gles));
+        assert_eq!(config.keyboard.layout, "de");
+        assert_eq!(config.keyboard.repeat_rate, 30);
+        assert_eq!(config.keyboard.repeat_delay, 600);
+        assert_eq!(config.outputs[0].position, Some((1920, 0)));
+        assert_eq!(
+            config.outputs[0].color_state(),
+            ColorState {
+                primaries: Primaries::Srgb,
+                transfer_function: TransferFunction::Pq,
+            }
+        );
+        assert_eq!(config.outputs[0].adaptive_sync, Some(true));
+        assert_eq!(config.inputs[0].output.as_deref(), Some("DP-1"));
+        assert_eq!(
+            config.inputs[0].calibration,
+            Some(Calibration([0.0, -1.0, 1.0, 1.0, 0.0, 0.0]))
+        );
+        assert!(config.inputs[0].middle_emulation);
+        assert_eq!(config.inputs[0].remap, [(0x110, 0x111)]);
+        assert_eq!(config.transactions.configure_timeout, 1000);
+        assert_eq!(config.transactions.timeout_policy, TimeoutPolicy::Apply);
+        assert_eq!(config.idle.blank_timeout(), Some(Duration::from_secs(300)));
+        assert_eq!(config.session.lock_command, ["swaylock", "-f"]);
+        assert!(config.session.lock_before_sleep);
+        assert_eq!(
+            config.limits,
+            LimitsConfig {
+                requests_per_second: 20_000,
+                max_surfaces: 256,
+                max_buffer_size: 0,
+            }
+        );
+        assert!(config.night_light.enabled);
+        assert_eq!(config.night_light.start, TimeOfDay::new(22, 0).unwrap());
+        assert_eq!(config.night_light.temperature, 4500);
+        assert_eq!(
+            config.wm_recovery.retry_policy(),
+            RetryPolicy {
+                max_restarts: 5,
+                window: Duration::from_secs(60),
+            }
+        );
+        assert_eq!(
+            config.wm_recovery.execution_limits(),
+            ExecutionLimits {
+                max_callback_time: Duration::from_millis(250),
+            }
+        );
+        assert_eq!(
+            config.wm_wasi.wasi_config(),
+            Some(WasiConfig {
+                config_dir: Some(PathBuf::from("/etc/aerugo/wm")),
+            })
+        );
+        assert_eq!(config.remote_desktop.apps, ["org.remmina.Remmina"]);
+        assert_eq!(config.rules[0].decorations, Some(ForcedDecorations::Server));
+        assert_eq!(config.rules[0].border_color, Some(Color(0x3584E4FF)));
+
+        let policy: ClientPolicy = config.policy().unwrap();
+        let identity = ClientIdentity {
+            executable: Some(PathBuf::from("/usr/bin/grim")),
+            ..Default::default()
+        };
+        assert_eq!(policy.globals(&identity), PrivilegedGlobals::SCREENCOPY);
+    }
+
+    #[test]
+    fn remote_desktop_access() {
+        let config = RemoteDesktopConfig {
+            apps: vec!["org.remmina.Remmina".into()],
+        };
+
+        assert!(config.allows("org.remmina.Remmina", false));
+        assert!(config.allows("org.gnome.Connections", true));
+        assert!(!config.allows("org.gnome.Connections", false));
+
+        // Unsandboxed applications cannot be told apart, even if the permission store lists them.
+        assert!(!config.allows("", true));
+    }
+
+    #[test]
+    fn invalid_client_rules() {
+        let err = Config::parse(
+            r#"
+            [[clients]]
+            pid = 1
+            systemd_unit = "swaylock.service"
+            globals = []
+            "#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, ConfigError::InvalidClientRule));
+
+        let err = Config::parse(
+            r#"
+            [[clients]]
+            pid = 1
+            globals = ["everything"]
+            "#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, ConfigError::UnknownGlobal(_)));
+    }
+
+    #[test]
+    fn zero_configure_timeout() {
+        let err = Config::parse(
+            r#"
+            [transactions]
+            configure_timeout = 0
+            "#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, ConfigError::ZeroConfigureTimeout));
+    }
+}
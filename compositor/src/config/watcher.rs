@@ -0,0 +1,70 @@
+//! Watching the configuration file for changes.
+//!
+//! The watcher polls the modification time of the configuration file. Polling is used instead of inotify since
+//! editors commonly replace the file instead of writing to it, which would invalidate an inotify watch.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use calloop::{
+    timer::{TimeoutAction, Timer},
+    LoopHandle, RegistrationToken,
+};
+
+use crate::Loop;
+
+use super::Config;
+
+/// How often the configuration file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    r#loop: LoopHandle<'static, Loop>,
+    token: RegistrationToken,
+}
+
+impl ConfigWatcher {
+    /// Watch the configuration file.
+    ///
+    /// When the file changes, the new configuration is applied with [`Loop::apply_config`]. If the new
+    /// configuration is invalid, the error is logged and the current configuration stays in effect.
+    pub fn new(r#loop: LoopHandle<'static, Loop>, path: PathBuf) -> Self {
+        let mut modified = modified(&path);
+
+        let token = r#loop
+            .insert_source(Timer::from_duration(POLL_INTERVAL), move |_, _, state| {
+                let current = modified(&path);
+
+                if current != modified {
+                    modified = current;
+
+                    match Config::load(&path) {
+                        Ok(config) => {
+                            tracing::info!(path = %path.display(), "Reloading config");
+                            state.apply_config(config);
+                        }
+                        Err(err) => tracing::error!(%err, path = %path.display(), "Failed to reload config"),
+                    }
+                }
+
+                TimeoutAction::ToDuration(POLL_INTERVAL)
+            })
+            .expect("Failed to insert config watcher");
+
+        Self { r#loop, token }
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        self.r#loop.remove(self.token);
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,57 @@
+//! Protocol conformance checks.
+//!
+//! Some clients misuse protocols in ways which are harmless in practice, such as setting a window geometry
+//! larger than the surface. By default these violations are tolerated and a warning is logged, since killing
+//! real-world clients for them helps nobody.
+//!
+//! In strict mode, these violations post a protocol error instead. This is intended for CI, where wlcs and
+//! client test suites should catch any ambiguity. Strict mode may be enabled at runtime through the
+//! [`Configuration`](crate::Configuration) or by default with the `strict` feature.
+
+use std::fmt;
+
+use wayland_server::Resource;
+
+/// How protocol violations by clients are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conformance {
+    /// Tolerate violations and log a warning.
+    Lenient,
+
+    /// Post a protocol error on violations.
+    Strict,
+}
+
+impl Conformance {
+    pub fn is_strict(self) -> bool {
+        self == Conformance::Strict
+    }
+
+    /// Report a protocol violation on the resource.
+    ///
+    /// Returns `true` if the violation was tolerated. If the violation is not tolerated, a protocol error was
+    /// posted and the client will be disconnected.
+    pub fn violation<R: Resource>(self, resource: &R, code: impl Into<u32>, message: impl fmt::Display) -> bool {
+        match self {
+            Conformance::Lenient => {
+                tracing::warn!(resource = %resource.id(), "Tolerating protocol violation: {message}");
+                true
+            }
+
+            Conformance::Strict => {
+                resource.post_error(code, message.to_string());
+                false
+            }
+        }
+    }
+}
+
+impl Default for Conformance {
+    fn default() -> Self {
+        if cfg!(feature = "strict") {
+            Conformance::Strict
+        } else {
+            Conformance::Lenient
+        }
+    }
+}
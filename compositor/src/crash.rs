@@ -0,0 +1,145 @@
+//! Crash reports
+//!
+//! When any thread of the display server panics, a report is written to `$XDG_STATE_HOME/aerugo/crashes`. The
+//! report contains the panic message, a backtrace, the wm module which was running, why the server was shutting
+//! down if it was and the [most recent log messages](crate::logging).
+//!
+//! A panic on the event loop thread unwinds the event loop, which drops the backend and so restores the state of
+//! the outputs. The x11 backend leaves nothing behind on the host display server, a backend driving DRM devices
+//! must restore the previous state of the TTY when dropped, so the TTY is usable again after a crash.
+
+use std::{
+    backtrace::Backtrace,
+    fmt::Write as _,
+    fs, io, panic,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+};
+
+use crate::{logging, shutdown::ShutdownReason, wm};
+
+/// The wm module which is running.
+static WM: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Why the server is shutting down, once it started to.
+static SHUTDOWN: Mutex<Option<ShutdownReason>> = Mutex::new(None);
+
+/// Write a crash report when a thread panics.
+///
+/// The previous panic hook still runs first, so the panic is printed as usual.
+pub fn install_panic_hook() {
+    let previous = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        previous(info);
+
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("Box<dyn Any>");
+        let location = info.location().map(ToString::to_string);
+        let backtrace = Backtrace::force_capture();
+
+        // The panic may have happened while a lock was held, in which case the report goes without.
+        let wm = WM.try_lock().ok().and_then(|wm| wm.clone());
+        let shutdown = SHUTDOWN.try_lock().ok().and_then(|shutdown| *shutdown);
+        let recent = logging::try_recent_events();
+
+        let report = report(
+            thread::current().name().unwrap_or("<unnamed>"),
+            message,
+            location.as_deref(),
+            &backtrace,
+            wm.as_deref(),
+            shutdown,
+            &recent,
+        );
+
+        match write_report(&report) {
+            Ok(path) => eprintln!("Wrote crash report to {}", path.display()),
+            Err(err) => eprintln!("Failed to write crash report: {err}"),
+        }
+    }));
+}
+
+/// Record the wm module which is running, if any.
+pub(crate) fn set_wm(module: Option<&Path>) {
+    *WM.lock().unwrap() = module.map(Path::to_owned);
+}
+
+/// Record why the server is shutting down.
+pub(crate) fn set_shutdown(reason: ShutdownReason) {
+    *SHUTDOWN.lock().unwrap() = Some(reason);
+}
+
+fn report(
+    thread: &str,
+    message: &str,
+    location: Option<&str>,
+    backtrace: &Backtrace,
+    wm: Option<&Path>,
+    shutdown: Option<ShutdownReason>,
+    recent: &[String],
+) -> String {
+    let mut report = String::new();
+
+    let _ = writeln!(report, "thread: {thread}");
+    let _ = writeln!(report, "panic: {message}");
+    let _ = writeln!(report, "location: {}", location.unwrap_or("unknown"));
+    let _ = writeln!(
+        report,
+        "wm: {}",
+        wm.map_or_else(|| "none".into(), |wm| wm.display().to_string())
+    );
+    let _ = writeln!(
+        report,
+        "shutdown: {}",
+        shutdown.map_or_else(|| "none".into(), |reason| reason.to_string())
+    );
+    let _ = writeln!(report, "\nbacktrace:\n{backtrace}");
+    let _ = writeln!(report, "recent log messages:");
+
+    for line in recent {
+        let _ = writeln!(report, "{line}");
+    }
+
+    report
+}
+
+fn write_report(report: &str) -> io::Result<PathBuf> {
+    let dir = wm::state_dir().join("crashes");
+    fs::create_dir_all(&dir)?;
+
+    let path = dir.join(format!("{}-{}.log", wm::timestamp(), std::process::id()));
+    fs::write(&path, report)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{backtrace::Backtrace, path::Path};
+
+    use super::report;
+    use crate::shutdown::ShutdownReason;
+
+    #[test]
+    fn report_contents() {
+        let recent = ["12:00:00.000 INFO aerugo_comp: Bound Wayland socket".to_string()];
+        let report = report(
+            "Aerugo event loop",
+            "oops",
+            Some("src/lib.rs:1:1"),
+            &Backtrace::disabled(),
+            Some(Path::new("/usr/share/aerugo/tiling_wm.wasm")),
+            Some(ShutdownReason::SessionTerminated),
+            &recent,
+        );
+
+        assert!(report.starts_with("thread: Aerugo event loop\npanic: oops\nlocation: src/lib.rs:1:1\n"));
+        assert!(report.contains("wm: /usr/share/aerugo/tiling_wm.wasm\nshutdown: session terminated\n"));
+        assert!(report.ends_with("recent log messages:\n12:00:00.000 INFO aerugo_comp: Bound Wayland socket\n"));
+    }
+}
@@ -0,0 +1,186 @@
+//! Cursor presentation
+//!
+//! The cursor is presented on a hardware cursor plane if the backend has one and the cursor image fits the
+//! plane. Otherwise the cursor is drawn by the renderer after everything else on the output, which is the
+//! software cursor. Backends without cursor planes, such as nested and headless backends, always use the
+//! software cursor.
+//!
+//! The plane is selected for every frame, so a cursor image which is too large for the plane switches to the
+//! software cursor in the same frame the plane is disabled. The area covered by the
+//! software cursor is tracked separately from the rest of the output, so moving the cursor only damages the
+//! previous and new area of the cursor.
+
+use std::{mem, sync::Mutex};
+
+use smithay::{
+    backend::renderer::utils::RendererSurfaceStateUserData,
+    input::pointer::{CursorImageAttributes, CursorImageStatus},
+    utils::{Logical, Physical, Point, Rectangle, Size},
+    wayland::compositor,
+};
+use wayland_server::protocol::wl_surface::WlSurface;
+
+/// Size of the cursor drawn when no client set a cursor image.
+pub const DEFAULT_CURSOR_SIZE: i32 = 8;
+
+/// Color of the cursor drawn when no client set a cursor image.
+pub const DEFAULT_CURSOR_COLOR: [f32; 4] = [0.1, 0.1, 0.1, 1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorPlane {
+    Hardware,
+    Software,
+}
+
+#[derive(Debug)]
+pub struct Cursor {
+    image: CursorImageStatus,
+    plane: CursorPlane,
+
+    /// Area covered by the software cursor when the cursor was last drawn.
+    drawn: Option<Rectangle<i32, Physical>>,
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Self {
+            image: CursorImageStatus::Default,
+            plane: CursorPlane::Software,
+            drawn: None,
+        }
+    }
+}
+
+impl Cursor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_image(&mut self, image: CursorImageStatus) {
+        self.image = image;
+    }
+
+    /// The surface of the cursor image, if a client set one.
+    pub fn surface(&self) -> Option<&WlSurface> {
+        match &self.image {
+            CursorImageStatus::Surface(surface) if surface.is_alive() => Some(surface),
+            _ => None,
+        }
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        matches!(self.image, CursorImageStatus::Hidden)
+    }
+
+    pub fn plane(&self) -> CursorPlane {
+        self.plane
+    }
+
+    /// The area covered by the cursor image with the pointer at `location`.
+    pub fn geometry(&self, location: Point<f64, Logical>, scale: f64) -> Option<Rectangle<i32, Physical>> {
+        if self.is_hidden() {
+            return None;
+        }
+
+        let (hotspot, size) = match self.surface() {
+            Some(surface) => compositor::with_states(surface, |states| {
+                let hotspot = states
+                    .data_map
+                    .get::<Mutex<CursorImageAttributes>>()
+                    .map_or_else(Point::default, |attributes| attributes.lock().unwrap().hotspot);
+                let size = states
+                    .data_map
+                    .get::<RendererSurfaceStateUserData>()
+                    .and_then(|data| data.borrow().surface_size())
+                    .unwrap_or_default();
+
+                (hotspot, size)
+            }),
+            None => (Point::default(), Size::from((DEFAULT_CURSOR_SIZE, DEFAULT_CURSOR_SIZE))),
+        };
+
+        let location = (location - hotspot.to_f64()).to_physical(scale).to_i32_round();
+        Some(Rectangle::from_loc_and_size(
+            location,
+            size.to_f64().to_physical(scale).to_i32_round(),
+        ))
+    }
+
+    /// Select the plane used to present the cursor image.
+    ///
+    /// `plane_size` is the largest image the hardware cursor plane of the output supports, if there is one.
+    pub fn select_plane(
+        &mut self,
+        plane_size: Option<Size<i32, Physical>>,
+        image_size: Size<i32, Physical>,
+    ) -> CursorPlane {
+        let plane = select_plane(plane_size, image_size);
+
+        if plane != self.plane {
+            tracing::debug!(?plane, "Switching cursor plane");
+            self.plane = plane;
+        }
+
+        plane
+    }
+
+    /// Record where the software cursor is drawn and return the damage caused by the cursor.
+    ///
+    /// The damage is the previous and new area of the cursor if the cursor moved. Once the cursor is on the
+    /// hardware plane, the previous area of the software cursor is damaged so the cursor is erased.
+    pub fn damage(&mut self, geometry: Option<Rectangle<i32, Physical>>) -> Vec<Rectangle<i32, Physical>> {
+        let geometry = geometry.filter(|_| self.plane == CursorPlane::Software);
+        let previous = mem::replace(&mut self.drawn, geometry);
+
+        if previous == geometry {
+            return Vec::new();
+        }
+
+        previous.into_iter().chain(geometry).collect()
+    }
+}
+
+/// Whether a cursor image can be presented on a hardware cursor plane.
+pub fn select_plane(plane_size: Option<Size<i32, Physical>>, image_size: Size<i32, Physical>) -> CursorPlane {
+    match plane_size {
+        Some(plane) if image_size.w <= plane.w && image_size.h <= plane.h => CursorPlane::Hardware,
+        _ => CursorPlane::Software,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::utils::Rectangle;
+
+    use super::{select_plane, Cursor, CursorPlane};
+
+    #[test]
+    fn plane_selection() {
+        assert_eq!(select_plane(None, (24, 24).into()), CursorPlane::Software);
+        assert_eq!(
+            select_plane(Some((64, 64).into()), (24, 24).into()),
+            CursorPlane::Hardware
+        );
+        assert_eq!(
+            select_plane(Some((64, 64).into()), (96, 24).into()),
+            CursorPlane::Software
+        );
+    }
+
+    #[test]
+    fn software_cursor_damage() {
+        let mut cursor = Cursor::new();
+        let first = Rectangle::from_loc_and_size((0, 0), (8, 8));
+        let second = Rectangle::from_loc_and_size((4, 0), (8, 8));
+
+        assert_eq!(cursor.damage(Some(first)), [first]);
+        assert!(cursor.damage(Some(first)).is_empty());
+        assert_eq!(cursor.damage(Some(second)), [first, second]);
+
+        // Switching to the hardware plane erases the software cursor.
+        cursor.select_plane(Some((64, 64).into()), (8, 8).into());
+        assert_eq!(cursor.plane(), CursorPlane::Hardware);
+        assert_eq!(cursor.damage(Some(second)), [second]);
+        assert!(cursor.damage(Some(second)).is_empty());
+    }
+}
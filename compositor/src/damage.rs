@@ -0,0 +1,153 @@
+//! Output damage
+//!
+//! Backends only redraw the damaged part of the buffer a frame is rendered into. A buffer still holds the frame
+//! rendered into it `age` frames ago, so the part to redraw is the damage of every frame since, see
+//! [`DamageHistory`].
+//!
+//! The scene has no damage tracking yet, so any change to the presented elements damages the whole output. The
+//! software cursor is tracked separately (see [`Cursor::damage`](crate::cursor::Cursor::damage)), so moving the
+//! cursor over unchanged content only damages the previous and new area of the cursor.
+
+use std::collections::VecDeque;
+
+use smithay::{
+    backend::renderer::{
+        element::{Element, Id},
+        utils::CommitCounter,
+    },
+    utils::{Physical, Rectangle, Scale, Transform},
+};
+
+/// How many frames of damage are kept, which is the oldest buffer which is redrawn partially.
+const MAX_AGE: usize = 4;
+
+/// The state of a presented element, which changes whenever the element looks different.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementState {
+    id: Id,
+    commit: CommitCounter,
+    geometry: Rectangle<i32, Physical>,
+    transform: Transform,
+    alpha: f32,
+}
+
+impl ElementState {
+    pub fn new(element: &impl Element, scale: Scale<f64>) -> Self {
+        Self {
+            id: element.id().clone(),
+            commit: element.current_commit(),
+            geometry: element.geometry(scale),
+            transform: element.transform(),
+            alpha: element.alpha(),
+        }
+    }
+}
+
+/// The damage of the recent frames of an output.
+///
+/// `C` describes everything presented but the software cursor. The whole output is damaged whenever it changes.
+#[derive(Debug)]
+pub struct DamageHistory<C> {
+    area: Rectangle<i32, Physical>,
+    content: Option<C>,
+
+    /// The damage of each recent frame, oldest first.
+    frames: VecDeque<Vec<Rectangle<i32, Physical>>>,
+}
+
+impl<C> Default for DamageHistory<C> {
+    fn default() -> Self {
+        Self {
+            area: Rectangle::default(),
+            content: None,
+            frames: VecDeque::new(),
+        }
+    }
+}
+
+impl<C: PartialEq> DamageHistory<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a frame and return the part of a buffer of `age` which must be redrawn.
+    ///
+    /// `cursor` is the damage of the software cursor. A buffer of age 0 has undefined contents, so the whole
+    /// output is redrawn.
+    pub fn frame(
+        &mut self,
+        area: Rectangle<i32, Physical>,
+        content: C,
+        cursor: Vec<Rectangle<i32, Physical>>,
+        age: usize,
+    ) -> Vec<Rectangle<i32, Physical>> {
+        let damage = if area != self.area || self.content.as_ref() != Some(&content) {
+            vec![area]
+        } else {
+            cursor
+        };
+
+        self.area = area;
+        self.content = Some(content);
+
+        if self.frames.len() == MAX_AGE {
+            self.frames.pop_front();
+        }
+
+        self.frames.push_back(damage);
+
+        if age == 0 || age > self.frames.len() {
+            return vec![area];
+        }
+
+        self.frames.iter().rev().take(age).flatten().copied().collect()
+    }
+
+    /// The damage of the last recorded frame relative to the frame before.
+    pub fn last_frame(&self) -> &[Rectangle<i32, Physical>] {
+        self.frames.back().map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::utils::Rectangle;
+
+    use super::DamageHistory;
+
+    #[test]
+    fn cursor_damage_accumulates_with_age() {
+        let area = Rectangle::from_loc_and_size((0, 0), (100, 100));
+        let first = Rectangle::from_loc_and_size((0, 0), (8, 8));
+        let second = Rectangle::from_loc_and_size((4, 0), (8, 8));
+        let mut history = DamageHistory::new();
+
+        // Nothing was rendered before, so the whole output is damaged.
+        assert_eq!(history.frame(area, 1, vec![first], 1), [area]);
+
+        assert_eq!(history.frame(area, 1, vec![first, second], 1), [first, second]);
+        assert_eq!(history.frame(area, 1, Vec::new(), 2), [first, second]);
+
+        // A buffer older than the recorded frames or without contents is redrawn completely.
+        assert_eq!(history.frame(area, 1, Vec::new(), 0), [area]);
+        assert_eq!(history.frame(area, 1, Vec::new(), 5), [area]);
+
+        // Only the most recent frames are kept.
+        assert_eq!(history.frame(area, 1, vec![second], 4), [second]);
+        assert_eq!(history.last_frame(), [second]);
+    }
+
+    #[test]
+    fn content_changes_damage_everything() {
+        let area = Rectangle::from_loc_and_size((0, 0), (100, 100));
+        let cursor = Rectangle::from_loc_and_size((0, 0), (8, 8));
+        let mut history = DamageHistory::new();
+
+        history.frame(area, 1, Vec::new(), 1);
+        assert_eq!(history.frame(area, 2, vec![cursor], 1), [area]);
+
+        // Resizing the output damages the new area.
+        let resized = Rectangle::from_loc_and_size((0, 0), (200, 100));
+        assert_eq!(history.frame(resized, 2, Vec::new(), 1), [resized]);
+    }
+}
@@ -10,6 +10,9 @@ pub enum Error {
 
     #[error("failed to insert because the forest would become cyclic")]
     Cycle,
+
+    #[error("{0:?} has no parent")]
+    NoParent(Index),
 }
 
 #[derive(Debug)]
@@ -58,15 +61,49 @@ impl<T> Forest<T> {
     }
 
     /// Removes the index from the forest, returning the value stored with the index.
+    ///
+    /// The children of the node become roots. Use [`Forest::remove_subtree`] or [`Forest::remove_promote`] to
+    /// remove or keep the children attached instead.
     pub fn remove(&mut self, index: Index) -> Result<T, Error> {
         // Detach the node before removing from the map.
         self.detach(index)?;
-        // TODO: Detach children from the node.
+
+        for child in self.children(index).collect::<Vec<_>>() {
+            self.detach(child).unwrap();
+        }
 
         let node = self.inner.remove(index).unwrap();
         Ok(node.value)
     }
 
+    /// Removes the node and all of its descendants, returning the values in preorder.
+    pub fn remove_subtree(&mut self, index: Index) -> Result<Vec<T>, Error> {
+        self.detach(index)?;
+
+        let descendants = self.dfs_descend(index).unwrap().collect::<Vec<_>>();
+
+        Ok(descendants
+            .into_iter()
+            .map(|index| self.inner.remove(index).unwrap().value)
+            .collect())
+    }
+
+    /// Removes the node and re-attaches its children to the parent of the node, at the position of the node.
+    ///
+    /// If the node has no parent, the children become roots.
+    pub fn remove_promote(&mut self, index: Index) -> Result<T, Error> {
+        self.is_present(index)?;
+
+        if Node::parent(self.get(index).unwrap()).is_some() {
+            for child in self.children(index).collect::<Vec<_>>() {
+                // The node is a descendant of its parent, so moving a child next to it cannot create a cycle.
+                self.move_before(child, index).unwrap();
+            }
+        }
+
+        self.remove(index)
+    }
+
     /// Adds makes the `child` a child of the `index`.
     pub fn add_child(&mut self, index: Index, child: Index) -> Result<(), Error> {
         self.is_present(index)?;
@@ -119,49 +156,32 @@ impl<T> Forest<T> {
         self.is_present(index)?;
 
         let node = self.get_mut(index).unwrap();
-        let parent = Node::parent(node);
-        node.parent.take();
-
-        let prev_sibling = Node::prev_sibling(node);
-        let next_sibling = Node::next_sibling(node);
-
-        match (prev_sibling, next_sibling) {
-            // If this node is the only child of it's parent we need to fully detach the parent.
-            (None, None) => {
-                if let Some(parent) = parent {
-                    let node = self.get_mut(parent).unwrap();
-                    node.first_last_child.take();
-                }
-            }
+        let parent = node.parent.take();
+        let prev_sibling = node.prev.take();
+        let next_sibling = node.next.take();
 
-            // This node is the first child of the parent
-            (None, Some(next)) => {
-                if let Some(parent) = parent {
-                    let node = self.get_mut(parent).unwrap();
-                    let last_child = Node::last_child(node).unwrap();
-                    node.first_last_child = Some((next, last_child))
-                }
-            }
-
-            // This node is the last child of the parent
-            (Some(prev), None) => {
-                if let Some(parent) = parent {
-                    let node = self.get_mut(parent).unwrap();
-                    let first_child = Node::first_child(node).unwrap();
-                    node.first_last_child = Some((first_child, prev))
-                }
-            }
+        // Relink the previous and next siblings.
+        if let Some(prev) = prev_sibling {
+            self.get_mut(prev).unwrap().next = next_sibling;
+        }
 
-            (Some(prev), Some(next)) => {
-                // Detach the previous and next siblings, relinking those as needed.
-                if let Some(prev) = self.get_mut(prev) {
-                    prev.next = next_sibling;
-                }
+        if let Some(next) = next_sibling {
+            self.get_mut(next).unwrap().prev = prev_sibling;
+        }
 
-                if let Some(next) = self.get_mut(next) {
-                    next.prev = prev_sibling;
-                }
-            }
+        if let Some(parent) = parent {
+            let node = self.get_mut(parent).unwrap();
+            let (first_child, last_child) = node.first_last_child.expect("parent has children");
+
+            node.first_last_child = match (prev_sibling, next_sibling) {
+                // If this node is the only child of it's parent we need to fully detach the parent.
+                (None, None) => None,
+                // This node is the first child of the parent
+                (None, Some(next)) => Some((next, last_child)),
+                // This node is the last child of the parent
+                (Some(prev), None) => Some((first_child, prev)),
+                (Some(_), Some(_)) => Some((first_child, last_child)),
+            };
         }
 
         Ok(())
@@ -205,6 +225,14 @@ impl<T> Forest<T> {
         })
     }
 
+    /// The nodes without a parent.
+    pub fn roots(&self) -> impl Iterator<Item = Index> + '_ {
+        self.inner
+            .iter()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(index, _)| index)
+    }
+
     pub fn children(&self, index: Index) -> Children<'_, T> {
         let (first_child, last_child) = self
             .get(index)
@@ -218,8 +246,108 @@ impl<T> Forest<T> {
         }
     }
 
-    // TODO: Relation related methods
-    // - Raise/lower node as child
+    /// Moves the node to be the previous sibling of `sibling`.
+    ///
+    /// If the node has a different parent than `sibling`, the node is moved to the parent of `sibling`.
+    pub fn move_before(&mut self, index: Index, sibling: Index) -> Result<(), Error> {
+        let parent = self.check_sibling_move(index, sibling)?;
+        self.detach(index)?;
+
+        let prev = self.get(sibling).unwrap().prev;
+        let node = self.get_mut(index).unwrap();
+        node.parent = Some(parent);
+        node.prev = prev;
+        node.next = Some(sibling);
+        self.get_mut(sibling).unwrap().prev = Some(index);
+
+        match prev {
+            Some(prev) => self.get_mut(prev).unwrap().next = Some(index),
+            None => {
+                let parent = self.get_mut(parent).unwrap();
+                let (_, last) = parent.first_last_child.expect("parent has children");
+                parent.first_last_child = Some((index, last));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the node to be the next sibling of `sibling`.
+    ///
+    /// If the node has a different parent than `sibling`, the node is moved to the parent of `sibling`.
+    pub fn move_after(&mut self, index: Index, sibling: Index) -> Result<(), Error> {
+        let parent = self.check_sibling_move(index, sibling)?;
+        self.detach(index)?;
+
+        let next = self.get(sibling).unwrap().next;
+        let node = self.get_mut(index).unwrap();
+        node.parent = Some(parent);
+        node.prev = Some(sibling);
+        node.next = next;
+        self.get_mut(sibling).unwrap().next = Some(index);
+
+        match next {
+            Some(next) => self.get_mut(next).unwrap().prev = Some(index),
+            None => {
+                let parent = self.get_mut(parent).unwrap();
+                let (first, _) = parent.first_last_child.expect("parent has children");
+                parent.first_last_child = Some((first, index));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the node to be the first child of its parent.
+    pub fn move_to_front(&mut self, index: Index) -> Result<(), Error> {
+        self.is_present(index)?;
+        let parent = Node::parent(self.get(index).unwrap()).ok_or(Error::NoParent(index))?;
+        let first = Node::first_child(self.get(parent).unwrap()).unwrap();
+
+        if first != index {
+            self.move_before(index, first)?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the node to be the last child of its parent.
+    pub fn move_to_back(&mut self, index: Index) -> Result<(), Error> {
+        self.is_present(index)?;
+        let parent = Node::parent(self.get(index).unwrap()).ok_or(Error::NoParent(index))?;
+        let last = Node::last_child(self.get(parent).unwrap()).unwrap();
+
+        if last != index {
+            self.move_after(index, last)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check whether the node can be moved next to the sibling, returning the parent of the sibling.
+    fn check_sibling_move(&self, index: Index, sibling: Index) -> Result<Index, Error> {
+        self.is_present(index)?;
+        self.is_present(sibling)?;
+
+        if index == sibling {
+            return Err(Error::Cycle);
+        }
+
+        let parent = Node::parent(self.get(sibling).unwrap()).ok_or(Error::NoParent(sibling))?;
+
+        // The node cannot become a child of one of its descendants.
+        let mut ancestor = Some(parent);
+
+        while let Some(current) = ancestor {
+            if current == index {
+                return Err(Error::Cycle);
+            }
+
+            ancestor = Node::parent(self.get(current).unwrap());
+        }
+
+        Ok(parent)
+    }
 
     fn is_present(&self, index: Index) -> Result<(), Error> {
         if !self.contains_index(index) {
@@ -508,6 +636,144 @@ mod tests {
         assert!(matches!(forest.add_child(b, a), Err(Error::Cycle)));
     }
 
+    /// Ensure detaching a node relinks the siblings and the node can be added again.
+    #[test]
+    fn detach_relinks_siblings() {
+        let mut forest = Forest::new();
+        let a = forest.insert(0);
+        let b = forest.insert(1);
+        let c = forest.insert(2);
+        let d = forest.insert(3);
+
+        forest.add_child(a, b).unwrap();
+        forest.add_child(a, c).unwrap();
+        forest.add_child(a, d).unwrap();
+
+        // Detach the first child.
+        forest.detach(b).unwrap();
+        assert_eq!(forest.children(a).collect::<Vec<_>>(), [c, d]);
+        assert_eq!(Node::prev_sibling(forest.get(c).unwrap()), None);
+
+        let node_b = forest.get(b).unwrap();
+        assert_eq!(Node::parent(node_b), None);
+        assert_eq!(Node::next_sibling(node_b), None);
+
+        // Detach the last child and add it back.
+        forest.detach(d).unwrap();
+        assert_eq!(Node::next_sibling(forest.get(c).unwrap()), None);
+
+        forest.add_child(a, d).unwrap();
+        forest.add_child(a, b).unwrap();
+        assert_eq!(forest.children(a).collect::<Vec<_>>(), [c, d, b]);
+
+        // Detach the middle child.
+        forest.detach(d).unwrap();
+        assert_eq!(forest.children(a).collect::<Vec<_>>(), [c, b]);
+        assert_eq!(Node::prev_sibling(forest.get(b).unwrap()), Some(c));
+    }
+
+    #[test]
+    fn move_siblings() {
+        let mut forest = Forest::new();
+        let parent = forest.insert(0);
+        let [a, b, c, d] = [1, 2, 3, 4].map(|value| forest.insert(value));
+
+        for child in [a, b, c] {
+            forest.add_child(parent, child).unwrap();
+        }
+
+        forest.move_before(c, a).unwrap();
+        assert_eq!(forest.children(parent).collect::<Vec<_>>(), [c, a, b]);
+
+        forest.move_after(c, b).unwrap();
+        assert_eq!(forest.children(parent).collect::<Vec<_>>(), [a, b, c]);
+
+        forest.move_after(a, b).unwrap();
+        assert_eq!(forest.children(parent).collect::<Vec<_>>(), [b, a, c]);
+
+        forest.move_to_front(c).unwrap();
+        assert_eq!(forest.children(parent).collect::<Vec<_>>(), [c, b, a]);
+
+        forest.move_to_back(c).unwrap();
+        assert_eq!(forest.children(parent).collect::<Vec<_>>(), [b, a, c]);
+
+        // Moving a node next to a node with another parent reparents the node.
+        forest.move_after(d, a).unwrap();
+        assert_eq!(forest.children(parent).collect::<Vec<_>>(), [b, a, d, c]);
+        assert_eq!(Node::parent(forest.get(d).unwrap()), Some(parent));
+
+        let reversed = forest.previous_siblings(c).unwrap().collect::<Vec<_>>();
+        assert_eq!(reversed, [c, d, a, b]);
+    }
+
+    #[test]
+    fn invalid_moves() {
+        let mut forest = Forest::new();
+        let [root, a, b] = [0, 1, 2].map(|value| forest.insert(value));
+        forest.add_child(root, a).unwrap();
+        forest.add_child(a, b).unwrap();
+
+        // a cannot become a sibling of its own child.
+        assert!(matches!(forest.move_before(a, b), Err(Error::Cycle)));
+        assert!(matches!(forest.move_after(a, a), Err(Error::Cycle)));
+        assert!(matches!(forest.move_before(b, root), Err(Error::NoParent(_))));
+        assert!(matches!(forest.move_to_back(root), Err(Error::NoParent(_))));
+
+        // The forest is unchanged.
+        assert_eq!(forest.children(root).collect::<Vec<_>>(), [a]);
+        assert_eq!(forest.children(a).collect::<Vec<_>>(), [b]);
+    }
+
+    #[test]
+    fn remove_orphans_children() {
+        let mut forest = Forest::new();
+        let [a, b, c] = [0, 1, 2].map(|value| forest.insert(value));
+        forest.add_child(a, b).unwrap();
+        forest.add_child(b, c).unwrap();
+
+        assert_eq!(forest.remove(b).unwrap(), 1);
+        assert_eq!(forest.children(a).count(), 0);
+        assert_eq!(Node::parent(forest.get(c).unwrap()), None);
+    }
+
+    #[test]
+    fn remove_subtree() {
+        let mut forest = Forest::new();
+        let [root, a, b, c, d] = [0, 1, 2, 3, 4].map(|value| forest.insert(value));
+        forest.add_child(root, a).unwrap();
+        forest.add_child(root, d).unwrap();
+        forest.add_child(a, b).unwrap();
+        forest.add_child(b, c).unwrap();
+
+        assert_eq!(forest.remove_subtree(a).unwrap(), [1, 2, 3]);
+        assert!(![a, b, c].into_iter().any(|index| forest.contains_index(index)));
+        assert_eq!(forest.children(root).collect::<Vec<_>>(), [d]);
+        assert!(matches!(forest.remove_subtree(a), Err(Error::NotPresent(_))));
+    }
+
+    #[test]
+    fn remove_promote() {
+        let mut forest = Forest::new();
+        let [root, a, b, c, d, e] = [0, 1, 2, 3, 4, 5].map(|value| forest.insert(value));
+
+        for child in [a, b, e] {
+            forest.add_child(root, child).unwrap();
+        }
+
+        forest.add_child(b, c).unwrap();
+        forest.add_child(b, d).unwrap();
+
+        assert_eq!(forest.remove_promote(b).unwrap(), 2);
+        assert_eq!(forest.children(root).collect::<Vec<_>>(), [a, c, d, e]);
+        assert_eq!(Node::parent(forest.get(c).unwrap()), Some(root));
+
+        // The children of a root become roots.
+        assert_eq!(forest.remove_promote(root).unwrap(), 0);
+        assert!([a, c, d, e]
+            .into_iter()
+            .all(|index| Node::parent(forest.get(index).unwrap()).is_none()));
+    }
+
     /// a -> b -> c
     #[test]
     fn preorder_traverse_line() {
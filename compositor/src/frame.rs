@@ -0,0 +1,155 @@
+//! Frame callbacks
+//!
+//! Clients use frame callbacks to pace drawing. Surfaces which were visible in the last presented frame receive
+//! frame callbacks every frame. Surfaces in the scene which are fully occluded or not presented, such as the
+//! views of inactive workspaces, are throttled to [`THROTTLED_FRAME_INTERVAL`] to save power. Throttled
+//! surfaces still receive frame callbacks so clients which wait for a frame callback before committing do not
+//! stall entirely.
+//!
+//! The wm may also request to be told when the next frame of an output was presented in order to drive
+//! animations.
+
+use std::time::{Duration, Instant};
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use smithay::{
+    output::Output,
+    wayland::compositor::{self, SurfaceAttributes, TraversalAction},
+};
+use wayland_server::{backend::ObjectId, protocol::wl_surface::WlSurface, Resource};
+
+use crate::Aerugo;
+
+/// How often surfaces which are not visible receive frame callbacks.
+pub const THROTTLED_FRAME_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When throttled surfaces last received frame callbacks.
+#[derive(Debug, Default)]
+pub struct FrameThrottle {
+    hidden: FxHashMap<ObjectId, Instant>,
+}
+
+impl FrameThrottle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether frame callbacks should be sent to a surface.
+    pub fn should_send(&mut self, surface: &ObjectId, visible: bool, now: Instant) -> bool {
+        if visible {
+            self.hidden.remove(surface);
+            return true;
+        }
+
+        match self.hidden.get(surface) {
+            Some(last) if now.duration_since(*last) < THROTTLED_FRAME_INTERVAL => false,
+            _ => {
+                self.hidden.insert(surface.clone(), now);
+                true
+            }
+        }
+    }
+
+    /// Forget surfaces which no longer exist.
+    fn retain(&mut self, mut f: impl FnMut(&ObjectId) -> bool) {
+        self.hidden.retain(|surface, _| f(surface));
+    }
+}
+
+impl Aerugo {
+    /// Send frame callbacks once a frame was presented.
+    ///
+    /// The visible surfaces are the surfaces presented in the frame on any of the presented outputs. Surfaces in
+    /// the scene which were not visible are throttled.
+    pub fn send_frames(&mut self, visible_surfaces: &[WlSurface], outputs: &[Output]) {
+        let now = Instant::now();
+        let time = frame_time();
+
+        let visible = visible_surfaces.iter().map(Resource::id).collect::<FxHashSet<_>>();
+        let mut surfaces = FxHashSet::default();
+
+        for surface in visible_surfaces {
+            self.frame_throttle.should_send(&surface.id(), true, now);
+            send_frame_callbacks(surface, time);
+        }
+
+        for surface in self.scene.all_surfaces() {
+            let id = surface.id();
+
+            if !visible.contains(&id) && self.frame_throttle.should_send(&id, false, now) {
+                send_frame_callbacks(surface, time);
+            }
+
+            surfaces.insert(id);
+        }
+
+        self.frame_throttle.retain(|id| surfaces.contains(id));
+        self.idle
+            .set_inhibited(self.idle_inhibit.is_inhibited(visible_surfaces));
+
+        for output in outputs {
+            self.wm.frame_presented(output, time);
+        }
+    }
+}
+
+/// The surface and its subsurfaces.
+pub fn surface_tree(surface: &WlSurface) -> Vec<WlSurface> {
+    let mut surfaces = Vec::new();
+
+    compositor::with_surface_tree_downward(
+        surface,
+        (),
+        |_, _, &()| TraversalAction::DoChildren(()),
+        |surface, _, &()| surfaces.push(surface.clone()),
+        |_, _, &()| true,
+    );
+
+    surfaces
+}
+
+fn send_frame_callbacks(surface: &WlSurface, time: u32) {
+    compositor::with_states(surface, |states| {
+        for callback in states
+            .cached_state
+            .current::<SurfaceAttributes>()
+            .frame_callbacks
+            .drain(..)
+        {
+            callback.done(time);
+        }
+    });
+}
+
+/// The time of a frame in milliseconds of the monotonic clock.
+fn frame_time() -> u32 {
+    let time = rustix::time::clock_gettime(rustix::time::ClockId::Monotonic);
+    (time.tv_sec as u64 * 1000 + time.tv_nsec as u64 / 1_000_000) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use wayland_server::backend::ObjectId;
+
+    use super::{FrameThrottle, THROTTLED_FRAME_INTERVAL};
+
+    #[test]
+    fn hidden_surfaces_are_throttled() {
+        let mut throttle = FrameThrottle::new();
+        let surface = ObjectId::null();
+        let now = Instant::now();
+
+        assert!(throttle.should_send(&surface, true, now));
+        assert!(throttle.should_send(&surface, true, now));
+
+        // The first frame after the surface was hidden is sent, following frames are throttled.
+        assert!(throttle.should_send(&surface, false, now));
+        assert!(!throttle.should_send(&surface, false, now + THROTTLED_FRAME_INTERVAL / 2));
+        assert!(throttle.should_send(&surface, false, now + THROTTLED_FRAME_INTERVAL));
+
+        // Becoming visible again is not throttled.
+        assert!(throttle.should_send(&surface, true, now + THROTTLED_FRAME_INTERVAL));
+    }
+}
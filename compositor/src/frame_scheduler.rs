@@ -0,0 +1,272 @@
+//! Frame scheduling and adaptive sync
+//!
+//! Every output has a frame scheduler which decides when the next frame of the output is rendered. A frame
+//! rendered right after the previous frame was presented is already most of a refresh old once it is presented.
+//! Instead, the render is kicked as late before the next vblank as possible: the time the frame is predicted to
+//! take to render before the vblank.
+//!
+//! The prediction is the slowest of the recently rendered frames plus [`RENDER_MARGIN`]. A frame presented after
+//! the vblank it was scheduled for is counted as missed, which shows up in the statistics reported over IPC.
+//!
+//! Displays which support adaptive sync (VRR) wait for the frame instead of presenting at a fixed refresh rate.
+//! Adaptive sync is enabled on an output while a fullscreen toplevel is visible on the output, if the output
+//! allows adaptive sync in the configuration or using output management:
+//!
+//! ```toml
+//! [[outputs]]
+//! name = "DP-1"
+//! adaptive_sync = true
+//! ```
+//!
+//! While adaptive sync is enabled, frames are rendered as soon as the previous frame was presented.
+
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use smithay::output::Output;
+use wayland_server::protocol::wl_surface::WlSurface;
+
+use crate::Aerugo;
+
+/// How many render times the prediction is based on.
+const WINDOW: usize = 30;
+
+/// Time added to the predicted render time to absorb jitter.
+pub const RENDER_MARGIN: Duration = Duration::from_millis(2);
+
+/// The refresh interval of outputs which do not have a mode.
+const DEFAULT_REFRESH: Duration = Duration::from_micros(16_667);
+
+/// Decides when the frames of an output are rendered.
+#[derive(Debug, Default)]
+pub struct FrameScheduler {
+    /// Durations of the recently rendered frames.
+    render_times: VecDeque<Duration>,
+
+    /// When the last frame was presented.
+    last_presentation: Option<Instant>,
+
+    /// The vblank the next frame is scheduled for.
+    deadline: Option<Instant>,
+
+    /// Whether adaptive sync may be enabled.
+    adaptive_sync_allowed: bool,
+
+    /// Whether adaptive sync is enabled.
+    adaptive_sync: bool,
+
+    frames: u64,
+    missed: u64,
+}
+
+/// Statistics of the frame scheduler of an output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameSchedulerStats {
+    pub refresh: Duration,
+    pub predicted_render_time: Duration,
+    pub frames: u64,
+    pub missed: u64,
+    pub adaptive_sync: bool,
+}
+
+impl FrameScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The time the next frame is predicted to take to render, including [`RENDER_MARGIN`].
+    ///
+    /// The prediction never exceeds the refresh interval, since a frame cannot be kicked before the previous
+    /// vblank.
+    pub fn predicted_render_time(&self, refresh: Duration) -> Duration {
+        let slowest = self.render_times.iter().copied().max().unwrap_or_default();
+        (slowest + RENDER_MARGIN).min(refresh)
+    }
+
+    /// How long to wait before rendering the next frame.
+    ///
+    /// The next frame is scheduled for the first vblank after the last presentation which leaves enough time to
+    /// render the frame.
+    pub fn delay(&mut self, now: Instant, refresh: Duration) -> Duration {
+        let Some(last_presentation) = self.last_presentation.filter(|_| !self.adaptive_sync) else {
+            self.deadline = None;
+            return Duration::ZERO;
+        };
+
+        let predicted = self.predicted_render_time(refresh);
+        let mut vblank = last_presentation + refresh;
+
+        while vblank < now + predicted {
+            vblank += refresh;
+        }
+
+        self.deadline = Some(vblank);
+        vblank - predicted - now
+    }
+
+    /// Record how long a frame took to render.
+    pub fn rendered(&mut self, duration: Duration) {
+        if self.render_times.len() == WINDOW {
+            self.render_times.pop_front();
+        }
+
+        self.render_times.push_back(duration);
+    }
+
+    /// Record that a frame was presented.
+    ///
+    /// The frame was missed if it was presented more than half a refresh after the vblank it was scheduled for.
+    pub fn presented(&mut self, now: Instant, refresh: Duration) {
+        self.frames += 1;
+
+        if self
+            .deadline
+            .take()
+            .map_or(false, |deadline| now > deadline + refresh / 2)
+        {
+            self.missed += 1;
+        }
+
+        self.last_presentation = Some(now);
+    }
+
+    pub fn adaptive_sync_allowed(&self) -> bool {
+        self.adaptive_sync_allowed
+    }
+
+    pub fn adaptive_sync(&self) -> bool {
+        self.adaptive_sync
+    }
+
+    pub fn stats(&self, refresh: Duration) -> FrameSchedulerStats {
+        FrameSchedulerStats {
+            refresh,
+            predicted_render_time: self.predicted_render_time(refresh),
+            frames: self.frames,
+            missed: self.missed,
+            adaptive_sync: self.adaptive_sync,
+        }
+    }
+}
+
+/// The frame scheduler of an output.
+pub fn frame_scheduler(output: &Output) -> MutexGuard<'_, FrameScheduler> {
+    let user_data = output.user_data();
+    user_data.insert_if_missing_threadsafe(|| Mutex::new(FrameScheduler::new()));
+    user_data.get::<Mutex<FrameScheduler>>().unwrap().lock().unwrap()
+}
+
+/// The refresh interval of the current mode of an output.
+pub fn refresh_interval(output: &Output) -> Duration {
+    output
+        .current_mode()
+        .map(|mode| mode.refresh)
+        .filter(|&refresh| refresh > 0)
+        .map_or(DEFAULT_REFRESH, |refresh| {
+            Duration::from_nanos(1_000_000_000_000 / refresh as u64)
+        })
+}
+
+impl Aerugo {
+    /// Allow or disallow adaptive sync on an output.
+    ///
+    /// Adaptive sync is never allowed on outputs which do not support adaptive sync.
+    pub fn set_adaptive_sync_allowed(&mut self, output: &Output, allowed: bool) {
+        frame_scheduler(output).adaptive_sync_allowed = allowed && self.backend.supports_adaptive_sync(output);
+    }
+
+    /// Enable adaptive sync on an output while a fullscreen toplevel is one of the visible surfaces of the output.
+    pub fn update_adaptive_sync(&mut self, output: &Output, visible: &[WlSurface]) {
+        let fullscreen = self
+            .shell
+            .fullscreen_surfaces()
+            .iter()
+            .any(|surface| visible.contains(surface));
+
+        let mut scheduler = frame_scheduler(output);
+        let enabled = fullscreen && scheduler.adaptive_sync_allowed;
+
+        if enabled == scheduler.adaptive_sync {
+            return;
+        }
+
+        tracing::debug!(output = output.name(), enabled, "Adaptive sync changed");
+        scheduler.adaptive_sync = enabled;
+        self.backend.set_adaptive_sync(output, enabled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{FrameScheduler, RENDER_MARGIN};
+
+    const REFRESH: Duration = Duration::from_millis(16);
+
+    #[test]
+    fn prediction_uses_slowest_frame() {
+        let mut scheduler = FrameScheduler::new();
+        assert_eq!(scheduler.predicted_render_time(REFRESH), RENDER_MARGIN);
+
+        scheduler.rendered(Duration::from_millis(3));
+        scheduler.rendered(Duration::from_millis(5));
+        scheduler.rendered(Duration::from_millis(1));
+        assert_eq!(
+            scheduler.predicted_render_time(REFRESH),
+            Duration::from_millis(5) + RENDER_MARGIN
+        );
+
+        scheduler.rendered(Duration::from_millis(40));
+        assert_eq!(scheduler.predicted_render_time(REFRESH), REFRESH);
+    }
+
+    #[test]
+    fn render_is_kicked_before_vblank() {
+        let mut scheduler = FrameScheduler::new();
+        let start = Instant::now();
+
+        // Nothing was presented yet, so there is no vblank to schedule against.
+        assert_eq!(scheduler.delay(start, REFRESH), Duration::ZERO);
+
+        scheduler.rendered(Duration::from_millis(4));
+        scheduler.presented(start, REFRESH);
+
+        // 16ms refresh - 4ms render - 2ms margin
+        assert_eq!(scheduler.delay(start, REFRESH), Duration::from_millis(10));
+
+        // Too late for the next vblank, so the frame is scheduled for the one after.
+        let late = start + Duration::from_millis(12);
+        assert_eq!(scheduler.delay(late, REFRESH), Duration::from_millis(14));
+    }
+
+    #[test]
+    fn count_missed_frames() {
+        let mut scheduler = FrameScheduler::new();
+        let start = Instant::now();
+        scheduler.presented(start, REFRESH);
+
+        scheduler.delay(start, REFRESH);
+        scheduler.presented(start + REFRESH, REFRESH);
+
+        scheduler.delay(start + REFRESH, REFRESH);
+        scheduler.presented(start + REFRESH * 3, REFRESH);
+
+        let stats = scheduler.stats(REFRESH);
+        assert_eq!(stats.frames, 3);
+        assert_eq!(stats.missed, 1);
+    }
+
+    #[test]
+    fn adaptive_sync_renders_immediately() {
+        let mut scheduler = FrameScheduler::new();
+        let start = Instant::now();
+        scheduler.presented(start, REFRESH);
+        scheduler.adaptive_sync = true;
+
+        assert_eq!(scheduler.delay(start, REFRESH), Duration::ZERO);
+    }
+}
@@ -0,0 +1,375 @@
+//! Output gamma and the night light
+//!
+//! The gamma ramp of an output is set by the backend, see [`Backend::set_gamma`](crate::backend::Backend::set_gamma).
+//! A client may set the ramp of an output using the gamma control protocol (see
+//! [`gamma_control`](crate::wayland::wlr::gamma_control)), which takes precedence over the built-in night light.
+//!
+//! The night light lowers the color temperature of every output while it is scheduled, from the configured start
+//! to the configured end time in local time:
+//!
+//! ```toml
+//! [night_light]
+//! enabled = true
+//! temperature = 4000
+//! start = "21:30"
+//! end = "06:45"
+//! ```
+
+use std::{fmt, time::Duration};
+
+use calloop::timer::{TimeoutAction, Timer};
+use chrono::Timelike;
+use serde::Deserialize;
+use smithay::output::Output;
+
+use crate::Aerugo;
+
+/// How often the night light schedule is checked.
+pub const NIGHT_LIGHT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The color temperature of an output without a night light, in Kelvin.
+pub const NEUTRAL_TEMPERATURE: u32 = 6500;
+
+/// The lowest color temperature of the night light, in Kelvin.
+pub const MIN_TEMPERATURE: u32 = 1000;
+
+/// A gamma lookup table for each channel of an output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GammaRamp {
+    pub red: Vec<u16>,
+    pub green: Vec<u16>,
+    pub blue: Vec<u16>,
+}
+
+impl GammaRamp {
+    /// Parse a ramp with `size` entries per channel from the red, green and blue tables of native endian entries.
+    pub fn from_bytes(size: usize, bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != size * 3 * 2 {
+            return None;
+        }
+
+        let mut entries = bytes
+            .chunks_exact(2)
+            .map(|entry| u16::from_ne_bytes([entry[0], entry[1]]));
+
+        Some(Self {
+            red: entries.by_ref().take(size).collect(),
+            green: entries.by_ref().take(size).collect(),
+            blue: entries.collect(),
+        })
+    }
+
+    /// A ramp which shifts the white point of the output to a color temperature in Kelvin.
+    pub fn color_temperature(size: usize, temperature: u32) -> Self {
+        let [red, green, blue] = white_point(temperature);
+        let channel = |factor: f64| {
+            (0..size)
+                .map(|i| {
+                    let value = i as f64 / (size.max(2) - 1) as f64;
+                    (value * factor * f64::from(u16::MAX)).round() as u16
+                })
+                .collect()
+        };
+
+        Self {
+            red: channel(red),
+            green: channel(green),
+            blue: channel(blue),
+        }
+    }
+
+    /// The number of entries of each channel.
+    pub fn size(&self) -> usize {
+        self.red.len()
+    }
+}
+
+/// The color of a black body at a temperature relative to [`NEUTRAL_TEMPERATURE`].
+///
+/// This uses the approximation of the black body locus by Tanner Helland.
+fn white_point(temperature: u32) -> [f64; 3] {
+    let rgb = |temperature: u32| {
+        let t = f64::from(temperature) / 100.0;
+
+        let red = if t <= 66.0 {
+            255.0
+        } else {
+            329.698727446 * (t - 60.0).powf(-0.1332047592)
+        };
+
+        let green = if t <= 66.0 {
+            99.4708025861 * t.ln() - 161.1195681661
+        } else {
+            288.1221695283 * (t - 60.0).powf(-0.0755148492)
+        };
+
+        let blue = if t >= 66.0 {
+            255.0
+        } else if t <= 19.0 {
+            0.0
+        } else {
+            138.5177312231 * (t - 10.0).ln() - 305.0447927307
+        };
+
+        [red, green, blue]
+    };
+
+    let temperature = temperature.clamp(MIN_TEMPERATURE, NEUTRAL_TEMPERATURE);
+    let [red, green, blue] = rgb(temperature);
+    let [neutral_red, neutral_green, neutral_blue] = rgb(NEUTRAL_TEMPERATURE);
+
+    [
+        (red / neutral_red).clamp(0.0, 1.0),
+        (green / neutral_green).clamp(0.0, 1.0),
+        (blue / neutral_blue).clamp(0.0, 1.0),
+    ]
+}
+
+/// A time of day written as `HH:MM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay {
+    /// Minutes since midnight.
+    minutes: u32,
+}
+
+impl TimeOfDay {
+    pub fn new(hour: u32, minute: u32) -> Option<Self> {
+        (hour < 24 && minute < 60).then_some(Self {
+            minutes: hour * 60 + minute,
+        })
+    }
+
+    /// The current time of day in the local time zone.
+    pub fn now() -> Self {
+        let now = chrono::Local::now();
+
+        Self {
+            minutes: now.hour() * 60 + now.minute(),
+        }
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value
+            .split_once(':')
+            .and_then(|(hour, minute)| Self::new(hour.parse().ok()?, minute.parse().ok()?))
+            .ok_or_else(|| format!("invalid time of day \"{value}\", expected HH:MM"))
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.minutes / 60, self.minutes % 60)
+    }
+}
+
+/// Configuration of the night light.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NightLightConfig {
+    pub enabled: bool,
+
+    /// The color temperature while the night light is active, in Kelvin.
+    pub temperature: u32,
+
+    /// When the night light turns on.
+    pub start: TimeOfDay,
+
+    /// When the night light turns off.
+    ///
+    /// If the end is the same as the start, the night light is always on.
+    pub end: TimeOfDay,
+}
+
+impl Default for NightLightConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            temperature: 4500,
+            start: TimeOfDay { minutes: 20 * 60 },
+            end: TimeOfDay { minutes: 7 * 60 },
+        }
+    }
+}
+
+impl NightLightConfig {
+    /// Whether the night light is scheduled at a time of day.
+    pub fn is_scheduled(&self, now: TimeOfDay) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        match self.start.cmp(&self.end) {
+            std::cmp::Ordering::Less => self.start <= now && now < self.end,
+            std::cmp::Ordering::Greater => now >= self.start || now < self.end,
+            std::cmp::Ordering::Equal => true,
+        }
+    }
+}
+
+/// The state of the night light.
+#[derive(Debug, Default)]
+pub struct NightLight {
+    config: NightLightConfig,
+    active: bool,
+}
+
+impl NightLight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The color temperature of the outputs, if the night light is active.
+    pub fn temperature(&self) -> Option<u32> {
+        self.active.then_some(self.config.temperature)
+    }
+
+    /// Update whether the night light is active, returning whether the night light changed.
+    fn update(&mut self, now: TimeOfDay) -> bool {
+        let active = self.config.is_scheduled(now);
+        std::mem::replace(&mut self.active, active) != active
+    }
+}
+
+impl Aerugo {
+    /// Set the gamma ramp of an output to the ramp of the gamma control of the output or the night light.
+    pub fn apply_gamma(&mut self, output: &Output) -> Result<(), String> {
+        let Some(size) = self.backend.gamma_size(output) else {
+            return Ok(());
+        };
+
+        let ramp = match self.gamma_control.ramp(output) {
+            Some(ramp) => Some(ramp.clone()),
+            None => self
+                .night_light
+                .temperature()
+                .map(|temperature| GammaRamp::color_temperature(size as usize, temperature)),
+        };
+
+        self.backend.set_gamma(output, ramp.as_ref())
+    }
+
+    /// Apply a new night light configuration.
+    pub fn apply_night_light_config(&mut self, config: &NightLightConfig) {
+        if self.night_light.config == *config {
+            return;
+        }
+
+        self.night_light.config = config.clone();
+        self.night_light.update(TimeOfDay::now());
+        self.apply_gamma_all();
+    }
+
+    /// Check the night light schedule for as long as the event loop runs.
+    pub(crate) fn start_night_light_timer(&self) {
+        self.r#loop
+            .insert_source(Timer::from_duration(NIGHT_LIGHT_CHECK_INTERVAL), |_, _, state| {
+                if state.comp.night_light.update(TimeOfDay::now()) {
+                    tracing::info!(
+                        temperature = state.comp.night_light.temperature(),
+                        "Night light changed"
+                    );
+                    state.comp.apply_gamma_all();
+                }
+
+                TimeoutAction::ToDuration(NIGHT_LIGHT_CHECK_INTERVAL)
+            })
+            .expect("Failed to insert night light timer");
+    }
+
+    fn apply_gamma_all(&mut self) {
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|(output, _)| output.clone())
+            .collect::<Vec<_>>();
+
+        for output in outputs {
+            if let Err(err) = self.apply_gamma(&output) {
+                tracing::warn!(output = output.name(), %err, "Failed to set gamma");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GammaRamp, NightLightConfig, TimeOfDay, NEUTRAL_TEMPERATURE};
+
+    fn time(value: &str) -> TimeOfDay {
+        TimeOfDay::try_from(value.to_owned()).unwrap()
+    }
+
+    #[test]
+    fn parse_ramp() {
+        let bytes = [1u16, 2, 3, 4, 5, 6]
+            .iter()
+            .flat_map(|entry| entry.to_ne_bytes())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            GammaRamp::from_bytes(2, &bytes),
+            Some(GammaRamp {
+                red: vec![1, 2],
+                green: vec![3, 4],
+                blue: vec![5, 6],
+            })
+        );
+        assert_eq!(GammaRamp::from_bytes(3, &bytes), None);
+    }
+
+    #[test]
+    fn color_temperature_reduces_blue() {
+        let neutral = GammaRamp::color_temperature(256, NEUTRAL_TEMPERATURE);
+        assert_eq!(neutral.size(), 256);
+        assert_eq!(neutral.red, neutral.blue);
+        assert_eq!(neutral.blue[255], u16::MAX);
+
+        let warm = GammaRamp::color_temperature(256, 3000);
+        assert_eq!(warm.red[255], u16::MAX);
+        assert!(warm.green[255] < u16::MAX);
+        assert!(warm.blue[255] < warm.green[255]);
+    }
+
+    #[test]
+    fn parse_time_of_day() {
+        assert_eq!(time("07:05"), TimeOfDay::new(7, 5).unwrap());
+        assert_eq!(time("23:59").to_string(), "23:59");
+        assert!(TimeOfDay::try_from("24:00".to_owned()).is_err());
+        assert!(TimeOfDay::try_from("noon".to_owned()).is_err());
+    }
+
+    #[test]
+    fn schedule_wraps_midnight() {
+        let config = NightLightConfig {
+            enabled: true,
+            start: time("20:00"),
+            end: time("07:00"),
+            ..Default::default()
+        };
+
+        assert!(config.is_scheduled(time("23:00")));
+        assert!(config.is_scheduled(time("03:00")));
+        assert!(!config.is_scheduled(time("07:00")));
+        assert!(!config.is_scheduled(time("12:00")));
+
+        let config = NightLightConfig {
+            start: time("01:00"),
+            end: time("05:00"),
+            ..config
+        };
+
+        assert!(config.is_scheduled(time("01:00")));
+        assert!(!config.is_scheduled(time("23:00")));
+
+        let disabled = NightLightConfig {
+            enabled: false,
+            ..config
+        };
+        assert!(!disabled.is_scheduled(time("03:00")));
+    }
+}
@@ -0,0 +1,161 @@
+//! Idle tracking and auto-blank
+//!
+//! Input on any device, including input injected on virtual seats, is user activity. Once no activity happened for
+//! the configured blank timeout, every output is blanked (see [`output_power`](crate::wayland::wlr::output_power))
+//! and the next activity turns the outputs back on.
+//!
+//! Clients may inhibit idle with the idle inhibit protocol, for example while playing a video. An inhibitor only
+//! has an effect while its surface is visible, and the idle time starts counting again once no inhibitor is
+//! visible anymore.
+
+use std::time::{Duration, Instant};
+
+use calloop::timer::{TimeoutAction, Timer};
+
+use crate::Aerugo;
+
+/// How often idle is checked while the blank timeout is disabled or idle is inhibited.
+pub const IDLE_RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What to do when the idle timer fires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleAction {
+    /// Check again after the duration.
+    Wait(Duration),
+
+    /// The blank timeout passed, blank the outputs.
+    Blank,
+}
+
+#[derive(Debug)]
+pub struct IdleState {
+    /// How long the user must be idle before the outputs are blanked, or [`None`] to never blank.
+    timeout: Option<Duration>,
+
+    last_activity: Instant,
+
+    /// Whether the surface of an idle inhibitor was visible in the last presented frame.
+    inhibited: bool,
+
+    /// Whether the outputs were blanked because of idle.
+    blanked: bool,
+}
+
+impl IdleState {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            timeout: None,
+            last_activity: now,
+            inhibited: false,
+            blanked: false,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn set_inhibited(&mut self, inhibited: bool) {
+        self.inhibited = inhibited;
+    }
+
+    pub fn is_blanked(&self) -> bool {
+        self.blanked
+    }
+
+    /// Record user activity, returning whether the outputs should be unblanked.
+    pub fn activity(&mut self, now: Instant) -> bool {
+        self.last_activity = now;
+        std::mem::take(&mut self.blanked)
+    }
+
+    /// Check whether the outputs should be blanked.
+    pub fn poll(&mut self, now: Instant) -> IdleAction {
+        let Some(timeout) = self.timeout.filter(|_| !self.blanked) else {
+            return IdleAction::Wait(IDLE_RECHECK_INTERVAL);
+        };
+
+        // Being inhibited counts as activity, so the timeout starts once the inhibitor is gone.
+        if self.inhibited {
+            self.last_activity = now;
+            return IdleAction::Wait(IDLE_RECHECK_INTERVAL.min(timeout));
+        }
+
+        let idle = now.saturating_duration_since(self.last_activity);
+
+        match timeout.checked_sub(idle) {
+            Some(remaining) if !remaining.is_zero() => IdleAction::Wait(remaining),
+            _ => {
+                self.blanked = true;
+                IdleAction::Blank
+            }
+        }
+    }
+}
+
+impl Aerugo {
+    /// Record user activity, turning the outputs back on if they were blanked because of idle.
+    pub fn user_activity(&mut self) {
+        if self.idle.activity(Instant::now()) {
+            self.set_outputs_blanked(false);
+        }
+    }
+
+    /// Check for idle for as long as the event loop runs.
+    pub(crate) fn start_idle_timer(&self) {
+        self.r#loop
+            .insert_source(Timer::from_duration(IDLE_RECHECK_INTERVAL), |_, _, state| {
+                match state.comp.idle.poll(Instant::now()) {
+                    IdleAction::Wait(duration) => TimeoutAction::ToDuration(duration),
+                    IdleAction::Blank => {
+                        tracing::info!("Blanking outputs after idle timeout");
+                        state.comp.set_outputs_blanked(true);
+                        TimeoutAction::ToDuration(IDLE_RECHECK_INTERVAL)
+                    }
+                }
+            })
+            .expect("Failed to insert idle timer");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{IdleAction, IdleState, IDLE_RECHECK_INTERVAL};
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn blank_after_timeout() {
+        let now = Instant::now();
+        let mut idle = IdleState::new(now);
+
+        assert_eq!(idle.poll(now + TIMEOUT), IdleAction::Wait(IDLE_RECHECK_INTERVAL));
+
+        idle.set_timeout(Some(TIMEOUT));
+        assert_eq!(idle.poll(now + TIMEOUT / 4), IdleAction::Wait(TIMEOUT * 3 / 4));
+        assert_eq!(idle.poll(now + TIMEOUT), IdleAction::Blank);
+        assert!(idle.is_blanked());
+
+        // Blanking happens once until the next activity.
+        assert_eq!(idle.poll(now + TIMEOUT * 2), IdleAction::Wait(IDLE_RECHECK_INTERVAL));
+        assert!(idle.activity(now + TIMEOUT * 2));
+        assert!(!idle.activity(now + TIMEOUT * 2));
+        assert_eq!(idle.poll(now + TIMEOUT * 2), IdleAction::Wait(TIMEOUT));
+    }
+
+    #[test]
+    fn inhibited_idle_restarts_timeout() {
+        let now = Instant::now();
+        let mut idle = IdleState::new(now);
+        idle.set_timeout(Some(TIMEOUT));
+        idle.set_inhibited(true);
+
+        assert_eq!(idle.poll(now + TIMEOUT * 2), IdleAction::Wait(IDLE_RECHECK_INTERVAL));
+
+        idle.set_inhibited(false);
+        assert_eq!(idle.poll(now + TIMEOUT * 2), IdleAction::Wait(TIMEOUT));
+        assert_eq!(idle.poll(now + TIMEOUT * 3), IdleAction::Blank);
+    }
+}
@@ -0,0 +1,339 @@
+//! Button remapping and emulation
+//!
+//! Pointer events of a device pass through a [`ButtonFilter`] before they are delivered to clients and the wm.
+//! The filter applies the configuration of the device in order:
+//!
+//! 1. Buttons are remapped.
+//! 2. While the scroll button is held, motion is converted into scrolling. This is useful for trackpoints,
+//!    which have no scroll wheel. If the scroll button is released without moving, the button is clicked.
+//! 3. Pressing the left and right buttons at the same time emulates the middle button, which usually pastes
+//!    the primary selection. The first button press is delayed by up to [`MIDDLE_EMULATION_TIMEOUT`] while
+//!    waiting for the other button.
+
+use std::time::Duration;
+
+use smithay::backend::input::ButtonState;
+
+use super::InputConfig;
+
+pub const BTN_LEFT: u32 = 0x110;
+pub const BTN_RIGHT: u32 = 0x111;
+pub const BTN_MIDDLE: u32 = 0x112;
+
+/// How long a left or right button press waits for the other button to emulate the middle button.
+pub const MIDDLE_EMULATION_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// An event produced by a [`ButtonFilter`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilteredEvent {
+    Button {
+        button: u32,
+        state: ButtonState,
+        /// Timestamp in milliseconds.
+        time: u32,
+    },
+
+    Motion {
+        dx: f64,
+        dy: f64,
+        time: u32,
+    },
+
+    Scroll {
+        dx: f64,
+        dy: f64,
+        time: u32,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct ButtonFilter {
+    remap: Vec<(u32, u32)>,
+    scroll_button: Option<u32>,
+    middle_emulation: bool,
+
+    /// State of the scroll button, if held.
+    scroll: Option<ScrollState>,
+
+    /// A left or right button press waiting for the other button, with the time of the press.
+    pending: Option<(u32, u32)>,
+
+    /// Whether a timeout was scheduled for the pending press.
+    scheduled: bool,
+
+    /// The buttons emulating the middle button which are still held.
+    emulating: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ScrollState {
+    /// Whether the button was moved while held.
+    scrolled: bool,
+}
+
+impl ButtonFilter {
+    pub fn new(config: &InputConfig) -> Self {
+        Self {
+            remap: config.remap.clone(),
+            scroll_button: config.scroll_button,
+            middle_emulation: config.middle_emulation,
+            ..Default::default()
+        }
+    }
+
+    /// Filter a button event.
+    pub fn button(&mut self, button: u32, state: ButtonState, time: u32) -> Vec<FilteredEvent> {
+        let button = self
+            .remap
+            .iter()
+            .find(|(from, _)| *from == button)
+            .map_or(button, |&(_, to)| to);
+
+        let mut events = Vec::new();
+
+        if Some(button) == self.scroll_button {
+            self.flush(&mut events);
+
+            match state {
+                ButtonState::Pressed => self.scroll = Some(ScrollState { scrolled: false }),
+
+                ButtonState::Released => {
+                    // A click without motion is delivered as a click of the button.
+                    if let Some(ScrollState { scrolled: false }) = self.scroll.take() {
+                        events.push(FilteredEvent::Button {
+                            button,
+                            state: ButtonState::Pressed,
+                            time,
+                        });
+                        events.push(FilteredEvent::Button { button, state, time });
+                    }
+                }
+            }
+
+            return events;
+        }
+
+        if !self.middle_emulation || !matches!(button, BTN_LEFT | BTN_RIGHT) {
+            self.flush(&mut events);
+            events.push(FilteredEvent::Button { button, state, time });
+            return events;
+        }
+
+        match state {
+            ButtonState::Pressed => match self.pending.take() {
+                // The other button was pressed in time.
+                Some((pending, pending_time))
+                    if pending != button && time.wrapping_sub(pending_time) <= timeout_ms() =>
+                {
+                    self.emulating = vec![pending, button];
+                    events.push(FilteredEvent::Button {
+                        button: BTN_MIDDLE,
+                        state,
+                        time,
+                    });
+                }
+
+                pending => {
+                    self.pending = pending;
+                    self.flush(&mut events);
+
+                    if self.emulating.is_empty() {
+                        self.pending = Some((button, time));
+                        self.scheduled = false;
+                    } else {
+                        events.push(FilteredEvent::Button { button, state, time });
+                    }
+                }
+            },
+
+            ButtonState::Released => {
+                if let Some(index) = self.emulating.iter().position(|&held| held == button) {
+                    self.emulating.remove(index);
+
+                    // The middle button is released once both buttons are released.
+                    if self.emulating.is_empty() {
+                        events.push(FilteredEvent::Button {
+                            button: BTN_MIDDLE,
+                            state,
+                            time,
+                        });
+                    }
+                } else {
+                    self.flush(&mut events);
+                    events.push(FilteredEvent::Button { button, state, time });
+                }
+            }
+        }
+
+        events
+    }
+
+    /// Filter relative pointer motion.
+    pub fn motion(&mut self, dx: f64, dy: f64, time: u32) -> Vec<FilteredEvent> {
+        if let Some(scroll) = self.scroll.as_mut() {
+            scroll.scrolled = true;
+            return vec![FilteredEvent::Scroll { dx, dy, time }];
+        }
+
+        vec![FilteredEvent::Motion { dx, dy, time }]
+    }
+
+    /// The time of the button press waiting for middle button emulation.
+    ///
+    /// The press must be flushed using [`ButtonFilter::timeout`] once [`MIDDLE_EMULATION_TIMEOUT`] passes.
+    pub fn pending(&self) -> Option<u32> {
+        self.pending.map(|(_, time)| time)
+    }
+
+    /// The time of the button press waiting for middle button emulation, if no timeout was scheduled for it yet.
+    ///
+    /// The time is returned once per press, so a single [`ButtonFilter::timeout`] is scheduled for every press.
+    pub fn schedule_timeout(&mut self) -> Option<u32> {
+        let time = self.pending().filter(|_| !self.scheduled)?;
+        self.scheduled = true;
+        Some(time)
+    }
+
+    /// Deliver the button press waiting for middle button emulation which was pressed at `time`.
+    ///
+    /// Nothing is delivered if the press was already delivered or turned into a middle button press.
+    pub fn timeout(&mut self, time: u32) -> Vec<FilteredEvent> {
+        let mut events = Vec::new();
+
+        if self.pending() == Some(time) {
+            self.flush(&mut events);
+        }
+
+        events
+    }
+
+    fn flush(&mut self, events: &mut Vec<FilteredEvent>) {
+        if let Some((button, time)) = self.pending.take() {
+            events.push(FilteredEvent::Button {
+                button,
+                state: ButtonState::Pressed,
+                time,
+            });
+        }
+    }
+}
+
+fn timeout_ms() -> u32 {
+    MIDDLE_EMULATION_TIMEOUT.as_millis() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::backend::input::ButtonState::{Pressed, Released};
+
+    use super::{ButtonFilter, FilteredEvent, BTN_LEFT, BTN_MIDDLE, BTN_RIGHT};
+
+    fn button(button: u32, state: smithay::backend::input::ButtonState, time: u32) -> FilteredEvent {
+        FilteredEvent::Button { button, state, time }
+    }
+
+    #[test]
+    fn remap() {
+        let mut filter = ButtonFilter {
+            remap: vec![(BTN_LEFT, BTN_RIGHT)],
+            ..Default::default()
+        };
+
+        assert_eq!(filter.button(BTN_LEFT, Pressed, 0), [button(BTN_RIGHT, Pressed, 0)]);
+        assert_eq!(filter.button(BTN_MIDDLE, Pressed, 0), [button(BTN_MIDDLE, Pressed, 0)]);
+    }
+
+    #[test]
+    fn middle_emulation() {
+        let mut filter = ButtonFilter {
+            middle_emulation: true,
+            ..Default::default()
+        };
+
+        assert!(filter.button(BTN_RIGHT, Pressed, 0).is_empty());
+        assert_eq!(filter.pending(), Some(0));
+        assert_eq!(filter.button(BTN_LEFT, Pressed, 10), [button(BTN_MIDDLE, Pressed, 10)]);
+        assert!(filter.button(BTN_LEFT, Released, 20).is_empty());
+        assert_eq!(
+            filter.button(BTN_RIGHT, Released, 30),
+            [button(BTN_MIDDLE, Released, 30)]
+        );
+
+        // The timer of the first press must not deliver anything.
+        assert!(filter.timeout(0).is_empty());
+    }
+
+    #[test]
+    fn middle_emulation_timeout() {
+        let mut filter = ButtonFilter {
+            middle_emulation: true,
+            ..Default::default()
+        };
+
+        assert!(filter.button(BTN_LEFT, Pressed, 0).is_empty());
+        assert_eq!(filter.timeout(0), [button(BTN_LEFT, Pressed, 0)]);
+        assert!(filter.button(BTN_RIGHT, Pressed, 100).is_empty());
+        assert_eq!(
+            filter.button(BTN_RIGHT, Released, 110),
+            [button(BTN_RIGHT, Pressed, 100), button(BTN_RIGHT, Released, 110)]
+        );
+
+        // Pressing the other button too late is not a middle click.
+        assert!(filter.button(BTN_LEFT, Pressed, 200).is_empty());
+        assert_eq!(filter.button(BTN_RIGHT, Pressed, 300), [button(BTN_LEFT, Pressed, 200)]);
+        assert_eq!(filter.pending(), Some(300));
+    }
+
+    #[test]
+    fn middle_emulation_schedules_one_timeout() {
+        let mut filter = ButtonFilter {
+            middle_emulation: true,
+            ..Default::default()
+        };
+
+        assert!(filter.button(BTN_LEFT, Pressed, 0).is_empty());
+        assert_eq!(filter.schedule_timeout(), Some(0));
+
+        // Motion while the press is pending must not schedule another timeout.
+        filter.motion(1.0, 0.0, 5);
+        assert_eq!(filter.schedule_timeout(), None);
+
+        assert_eq!(filter.timeout(0), [button(BTN_LEFT, Pressed, 0)]);
+        assert!(filter.button(BTN_RIGHT, Pressed, 100).is_empty());
+        assert_eq!(filter.schedule_timeout(), Some(100));
+    }
+
+    #[test]
+    fn scroll_button() {
+        let mut filter = ButtonFilter {
+            scroll_button: Some(BTN_MIDDLE),
+            ..Default::default()
+        };
+
+        assert!(filter.button(BTN_MIDDLE, Pressed, 0).is_empty());
+        assert_eq!(
+            filter.motion(1.0, 2.0, 10),
+            [FilteredEvent::Scroll {
+                dx: 1.0,
+                dy: 2.0,
+                time: 10
+            }]
+        );
+        assert!(filter.button(BTN_MIDDLE, Released, 20).is_empty());
+
+        // Without motion the button is clicked.
+        assert!(filter.button(BTN_MIDDLE, Pressed, 30).is_empty());
+        assert_eq!(
+            filter.button(BTN_MIDDLE, Released, 40),
+            [button(BTN_MIDDLE, Pressed, 40), button(BTN_MIDDLE, Released, 40)]
+        );
+        assert_eq!(
+            filter.motion(1.0, 0.0, 50),
+            [FilteredEvent::Motion {
+                dx: 1.0,
+                dy: 0.0,
+                time: 50
+            }]
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Keybindings
+//!
+//! A keybinding is a set of modifiers and a keysym, written in a human readable form such as `Super+Shift+Q`.
+//! Modifier names are case insensitive. The key is an xkb keysym name, where letters are case insensitive.
+//!
+//! Bindings are matched against the keysym of a key without any modifiers applied, so `Super+Shift+Q` matches
+//! pressing `Q` while holding the logo and shift keys. Caps lock and num lock are ignored when matching.
+
+use std::{fmt, str::FromStr};
+
+use smithay::input::keyboard::{xkb, ModifiersState};
+use wm_runtime::KeyModifiers;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum KeybindingError {
+    #[error("unknown modifier \"{0}\"")]
+    UnknownModifier(String),
+
+    #[error("unknown keysym \"{0}\"")]
+    UnknownKeysym(String),
+
+    #[error("keybinding has no key")]
+    MissingKey,
+
+    #[error("keybinding {0} is already registered")]
+    AlreadyRegistered(Keybinding),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keybinding {
+    pub modifiers: KeyModifiers,
+    pub keysym: u32,
+}
+
+impl Keybinding {
+    /// Modifiers which do not affect whether a binding matches.
+    fn ignored_modifiers() -> KeyModifiers {
+        KeyModifiers::CAPS_LOCK | KeyModifiers::NUM_LOCK
+    }
+
+    pub fn matches(&self, modifiers: KeyModifiers, keysym: u32) -> bool {
+        let modifiers = modifiers & !Self::ignored_modifiers();
+        self.modifiers == modifiers && self.keysym == keysym
+    }
+}
+
+/// Convert the modifier state of a keyboard to the modifiers bindings are matched against.
+pub fn key_modifiers(state: &ModifiersState) -> KeyModifiers {
+    let modifiers = [
+        (state.ctrl, KeyModifiers::CTRL),
+        (state.alt, KeyModifiers::ALT),
+        (state.shift, KeyModifiers::SHIFT),
+        (state.caps_lock, KeyModifiers::CAPS_LOCK),
+        (state.logo, KeyModifiers::LOGO),
+        (state.num_lock, KeyModifiers::NUM_LOCK),
+    ];
+
+    modifiers
+        .into_iter()
+        .filter(|(active, _)| *active)
+        .fold(KeyModifiers::empty(), |modifiers, (_, modifier)| modifiers | modifier)
+}
+
+impl FromStr for Keybinding {
+    type Err = KeybindingError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut parts = spec.split('+').map(str::trim).collect::<Vec<_>>();
+        let key = parts
+            .pop()
+            .filter(|key| !key.is_empty())
+            .ok_or(KeybindingError::MissingKey)?;
+
+        let modifiers = parts.into_iter().try_fold(KeyModifiers::empty(), |modifiers, name| {
+            let modifier = match name.to_ascii_lowercase().as_str() {
+                "super" | "logo" | "mod4" => KeyModifiers::LOGO,
+                "ctrl" | "control" => KeyModifiers::CTRL,
+                "alt" | "mod1" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(KeybindingError::UnknownModifier(name.into())),
+            };
+
+            Ok(modifiers | modifier)
+        })?;
+
+        // Case insensitive lookup returns the lowercase keysym for letters, which is the keysym of the key
+        // without modifiers.
+        let keysym = xkb::keysym_from_name(key, xkb::KEYSYM_CASE_INSENSITIVE);
+
+        if keysym == xkb::keysyms::KEY_NoSymbol {
+            return Err(KeybindingError::UnknownKeysym(key.into()));
+        }
+
+        Ok(Self { modifiers, keysym })
+    }
+}
+
+impl fmt::Display for Keybinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (KeyModifiers::LOGO, "Super"),
+            (KeyModifiers::CTRL, "Ctrl"),
+            (KeyModifiers::ALT, "Alt"),
+            (KeyModifiers::SHIFT, "Shift"),
+        ];
+
+        for (modifier, name) in names {
+            if self.modifiers & modifier == modifier {
+                write!(f, "{name}+")?;
+            }
+        }
+
+        f.write_str(&xkb::keysym_get_name(self.keysym))
+    }
+}
+
+/// A set of keybindings, each with an associated value.
+///
+/// The value is used to dispatch a matched binding, for example the id the wm registered the binding with.
+#[derive(Debug)]
+pub struct KeybindingManager<T> {
+    bindings: Vec<(Keybinding, T)>,
+}
+
+impl<T> Default for KeybindingManager<T> {
+    fn default() -> Self {
+        Self { bindings: Vec::new() }
+    }
+}
+
+impl<T> KeybindingManager<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, binding: Keybinding, value: T) -> Result<(), KeybindingError> {
+        if self.bindings.iter().any(|(registered, _)| *registered == binding) {
+            return Err(KeybindingError::AlreadyRegistered(binding));
+        }
+
+        self.bindings.push((binding, value));
+        Ok(())
+    }
+
+    /// Parse and register a binding.
+    pub fn register_spec(&mut self, spec: &str, value: T) -> Result<Keybinding, KeybindingError> {
+        let binding = spec.parse()?;
+        self.register(binding, value)?;
+        Ok(binding)
+    }
+
+    pub fn unregister(&mut self, binding: &Keybinding) -> Option<T> {
+        let index = self.bindings.iter().position(|(registered, _)| registered == binding)?;
+        Some(self.bindings.remove(index).1)
+    }
+
+    /// Remove every binding whose value does not satisfy the predicate.
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        self.bindings.retain(|(_, value)| f(value));
+    }
+
+    /// The value of the binding matching a key press.
+    pub fn get(&self, modifiers: KeyModifiers, keysym: u32) -> Option<&T> {
+        self.bindings
+            .iter()
+            .find(|(binding, _)| binding.matches(modifiers, keysym))
+            .map(|(_, value)| value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bindings.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::input::keyboard::{xkb::keysyms, ModifiersState};
+    use wm_runtime::KeyModifiers;
+
+    use super::{key_modifiers, Keybinding, KeybindingError, KeybindingManager};
+
+    #[test]
+    fn parse() {
+        let binding = "Super+Shift+Q".parse::<Keybinding>().unwrap();
+        assert_eq!(binding.modifiers, KeyModifiers::LOGO | KeyModifiers::SHIFT);
+        assert_eq!(binding.keysym, keysyms::KEY_q);
+        assert_eq!(binding.to_string(), "Super+Shift+q");
+
+        let binding = "ctrl + alt + Return".parse::<Keybinding>().unwrap();
+        assert_eq!(binding.modifiers, KeyModifiers::CTRL | KeyModifiers::ALT);
+        assert_eq!(binding.keysym, keysyms::KEY_Return);
+
+        assert_eq!(
+            "Hyper+Q".parse::<Keybinding>(),
+            Err(KeybindingError::UnknownModifier("Hyper".into()))
+        );
+        assert_eq!(
+            "Super+NotAKey".parse::<Keybinding>(),
+            Err(KeybindingError::UnknownKeysym("NotAKey".into()))
+        );
+        assert_eq!("Super+".parse::<Keybinding>(), Err(KeybindingError::MissingKey));
+    }
+
+    #[test]
+    fn dispatch() {
+        let mut bindings = KeybindingManager::new();
+        let close = bindings.register_spec("Super+Shift+Q", 1).unwrap();
+        bindings.register_spec("Super+Return", 2).unwrap();
+
+        assert!(matches!(
+            bindings.register_spec("super+shift+q", 3),
+            Err(KeybindingError::AlreadyRegistered(_))
+        ));
+
+        // Lock modifiers are ignored.
+        let modifiers = KeyModifiers::LOGO | KeyModifiers::SHIFT | KeyModifiers::NUM_LOCK;
+        assert_eq!(bindings.get(modifiers, keysyms::KEY_q), Some(&1));
+        assert_eq!(bindings.get(KeyModifiers::LOGO, keysyms::KEY_q), None);
+        assert_eq!(bindings.get(KeyModifiers::LOGO, keysyms::KEY_Return), Some(&2));
+
+        assert_eq!(bindings.unregister(&close), Some(1));
+        assert_eq!(bindings.get(modifiers, keysyms::KEY_q), None);
+    }
+
+    #[test]
+    fn matches_keyboard_modifiers() {
+        let binding = "Super+Shift+Q".parse::<Keybinding>().unwrap();
+        let state = ModifiersState {
+            logo: true,
+            shift: true,
+            caps_lock: true,
+            ..Default::default()
+        };
+
+        let modifiers = key_modifiers(&state);
+        assert_eq!(
+            modifiers,
+            KeyModifiers::LOGO | KeyModifiers::SHIFT | KeyModifiers::CAPS_LOCK
+        );
+        assert!(binding.matches(modifiers, keysyms::KEY_q));
+
+        // Extra modifiers which are not lock modifiers prevent a match.
+        let state = ModifiersState { ctrl: true, ..state };
+        assert!(!binding.matches(key_modifiers(&state), keysyms::KEY_q));
+    }
+}
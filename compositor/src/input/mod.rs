@@ -0,0 +1,334 @@
+//! Input device configuration
+//!
+//! Absolute input devices, such as touchscreens, report positions normalized to the size of the device. The
+//! position is mapped onto the output the device is associated with. If the output is rotated or flipped, the
+//! position is transformed the same way so a touch lands on the content under the finger.
+//!
+//! A calibration matrix may be set for devices which report positions that do not line up with the panel. The
+//! matrix uses the libinput format (see `LIBINPUT_CALIBRATION_MATRIX`) and is applied to the normalized position
+//! before the output transform.
+//!
+//! Button events can be remapped and used to emulate scrolling and the middle button, see [`buttons`].
+//!
+//! Touch points and gestures recognized from them are sent to the wm, and clients receive the primary touch point
+//! as pointer input, see [`touch`].
+//!
+//! Input injected by remote desktop sessions is attributed to virtual seats, see [`virtual_seat`].
+//!
+//! ```toml
+//! [[inputs]]
+//! name = "ELAN Touchscreen"
+//! output = "eDP-1"
+//! calibration = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+//!
+//! [[inputs]]
+//! name = "TPPS/2 IBM TrackPoint"
+//! scroll_button = 0x112
+//! middle_emulation = true
+//! # Swap the left and right buttons.
+//! remap = [[0x110, 0x111], [0x111, 0x110]]
+//! ```
+
+pub mod buttons;
+pub mod keybindings;
+pub mod repeat;
+pub mod touch;
+pub mod virtual_seat;
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use smithay::{
+    backend::input::{KeyState, TouchSlot},
+    input::keyboard::FilterResult,
+    output::Output,
+    utils::{Logical, Point, Transform},
+};
+
+use self::{
+    buttons::ButtonFilter,
+    repeat::KeyRepeat,
+    touch::TouchState,
+    virtual_seat::{VirtualSeat, VirtualSeatId},
+};
+
+/// Configuration for an input device.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputConfig {
+    /// Name of the device the configuration applies to.
+    pub name: String,
+
+    /// Name of the output absolute positions are mapped to.
+    ///
+    /// If not set, positions are mapped to the primary output.
+    pub output: Option<String>,
+
+    pub calibration: Option<Calibration>,
+
+    /// Button which emulates scrolling while held.
+    #[serde(default)]
+    pub scroll_button: Option<u32>,
+
+    /// Whether pressing the left and right buttons together emulates the middle button.
+    #[serde(default)]
+    pub middle_emulation: bool,
+
+    /// Pairs of button codes, where the first button is delivered as the second button.
+    #[serde(default)]
+    pub remap: Vec<(u32, u32)>,
+}
+
+/// The configuration and filter state of the input devices.
+#[derive(Debug, Default)]
+pub struct InputState {
+    devices: Vec<InputConfig>,
+    filters: HashMap<String, ButtonFilter>,
+
+    /// Keycodes of pressed keys which were intercepted by a keybinding.
+    ///
+    /// The release of an intercepted key is not delivered to clients either.
+    intercepted_keys: Vec<u32>,
+
+    key_repeat: KeyRepeat,
+    touch: TouchState<TouchSlot>,
+    virtual_seats: HashMap<VirtualSeatId, VirtualSeat>,
+}
+
+impl InputState {
+    /// Apply a new device configuration.
+    ///
+    /// This resets the filter state of every device.
+    pub fn apply_config(&mut self, devices: &[InputConfig]) {
+        self.devices = devices.to_vec();
+        self.filters.clear();
+    }
+
+    /// Start tracking a connected device.
+    pub fn add_device(&mut self, device: &str) {
+        tracing::info!(device, configured = self.config(device).is_some(), "Input device added");
+        self.filters.remove(device);
+    }
+
+    /// Stop tracking a disconnected device.
+    ///
+    /// Any button press held back by the filter of the device is discarded.
+    pub fn remove_device(&mut self, device: &str) {
+        tracing::info!(device, "Input device removed");
+        self.filters.remove(device);
+    }
+
+    pub fn key_repeat(&mut self) -> &mut KeyRepeat {
+        &mut self.key_repeat
+    }
+
+    pub fn touch(&mut self) -> &mut TouchState<TouchSlot> {
+        &mut self.touch
+    }
+
+    /// Decide whether a key event is intercepted by a keybinding.
+    ///
+    /// A key press is intercepted if it matched the binding with the id `binding`, and the release of an
+    /// intercepted key is intercepted as well. An intercepted press carries the id of the binding.
+    pub fn filter_key(&mut self, keycode: u32, state: KeyState, binding: Option<u32>) -> FilterResult<Option<u32>> {
+        match (state, binding) {
+            (KeyState::Pressed, Some(id)) => {
+                self.intercept_key(keycode);
+                FilterResult::Intercept(Some(id))
+            }
+
+            (KeyState::Pressed, None) => FilterResult::Forward,
+
+            (KeyState::Released, _) if self.release_intercepted_key(keycode) => FilterResult::Intercept(None),
+
+            (KeyState::Released, _) => FilterResult::Forward,
+        }
+    }
+
+    fn intercept_key(&mut self, keycode: u32) {
+        self.intercepted_keys.push(keycode);
+    }
+
+    /// Stop tracking an intercepted key which was released, returning whether the key was intercepted.
+    pub fn release_intercepted_key(&mut self, keycode: u32) -> bool {
+        let Some(index) = self.intercepted_keys.iter().position(|&key| key == keycode) else {
+            return false;
+        };
+
+        self.intercepted_keys.swap_remove(index);
+        true
+    }
+
+    pub fn add_virtual_seat(&mut self, id: VirtualSeatId) {
+        tracing::info!(id, "Virtual seat added");
+        self.virtual_seats.insert(id, VirtualSeat::new());
+    }
+
+    pub fn remove_virtual_seat(&mut self, id: VirtualSeatId) -> Option<VirtualSeat> {
+        let seat = self.virtual_seats.remove(&id)?;
+        tracing::info!(id, "Virtual seat removed");
+        Some(seat)
+    }
+
+    pub fn virtual_seat(&mut self, id: VirtualSeatId) -> Option<&mut VirtualSeat> {
+        self.virtual_seats.get_mut(&id)
+    }
+
+    pub fn config(&self, device: &str) -> Option<&InputConfig> {
+        self.devices.iter().find(|config| config.name == device)
+    }
+
+    /// The button filter of a device.
+    pub fn filter(&mut self, device: &str) -> &mut ButtonFilter {
+        if !self.filters.contains_key(device) {
+            let filter = self.config(device).map(ButtonFilter::new).unwrap_or_default();
+            self.filters.insert(device.to_owned(), filter);
+        }
+
+        self.filters.get_mut(device).unwrap()
+    }
+}
+
+/// A libinput calibration matrix.
+///
+/// The matrix is the first two rows of a 3x3 matrix in row-major order. A normalized position `(x, y)` is mapped
+/// to `(a * x + b * y + c, d * x + e * y + f)`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct Calibration(pub [f64; 6]);
+
+impl Calibration {
+    pub const IDENTITY: Self = Self([1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+
+    pub fn apply(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let [a, b, c, d, e, f] = self.0;
+        (a * x + b * y + c, d * x + e * y + f)
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Transform a normalized position on a panel into the normalized position in the content of the output.
+///
+/// Transforms rotate the content counter-clockwise, and flipped transforms mirror the content around the
+/// vertical axis before rotating.
+pub fn transform_normalized(transform: Transform, (x, y): (f64, f64)) -> (f64, f64) {
+    match transform {
+        Transform::Normal => (x, y),
+        Transform::_90 => (1.0 - y, x),
+        Transform::_180 => (1.0 - x, 1.0 - y),
+        Transform::_270 => (y, 1.0 - x),
+        Transform::Flipped => (1.0 - x, y),
+        Transform::Flipped90 => (y, x),
+        Transform::Flipped180 => (x, 1.0 - y),
+        Transform::Flipped270 => (1.0 - y, 1.0 - x),
+    }
+}
+
+/// Map a normalized device position into the global compositor space.
+pub fn map_to_output(position: (f64, f64), calibration: Option<&Calibration>, output: &Output) -> Point<f64, Logical> {
+    let position = calibration.map_or(position, |calibration| calibration.apply(position));
+    let transform = output.current_transform();
+    let (x, y) = transform_normalized(transform, position);
+
+    let size = output
+        .current_mode()
+        .map(|mode| {
+            transform
+                .transform_size(mode.size)
+                .to_f64()
+                .to_logical(output.current_scale().fractional_scale())
+        })
+        .unwrap_or_default();
+
+    output.current_location().to_f64() + Point::from((x * size.w, y * size.h))
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::{backend::input::KeyState, input::keyboard::FilterResult, utils::Transform};
+
+    use super::{transform_normalized, Calibration, InputState};
+
+    const TRANSFORMS: [Transform; 8] = [
+        Transform::Normal,
+        Transform::_90,
+        Transform::_180,
+        Transform::_270,
+        Transform::Flipped,
+        Transform::Flipped90,
+        Transform::Flipped180,
+        Transform::Flipped270,
+    ];
+
+    #[test]
+    fn rotated_corners() {
+        // The top left corner of the rotated content is at the bottom left of the panel.
+        assert_eq!(transform_normalized(Transform::_90, (0.0, 1.0)), (0.0, 0.0));
+        assert_eq!(transform_normalized(Transform::_90, (0.0, 0.0)), (1.0, 0.0));
+        assert_eq!(transform_normalized(Transform::_270, (1.0, 0.0)), (0.0, 0.0));
+        assert_eq!(transform_normalized(Transform::_180, (1.0, 1.0)), (0.0, 0.0));
+        assert_eq!(transform_normalized(Transform::Flipped, (1.0, 0.0)), (0.0, 0.0));
+    }
+
+    #[test]
+    fn transforms_are_bijective() {
+        let corners = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)];
+
+        for transform in TRANSFORMS {
+            let mut mapped = corners.map(|corner| transform_normalized(transform, corner));
+            mapped.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let mut expected = corners;
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(mapped, expected, "{transform:?}");
+        }
+    }
+
+    #[test]
+    fn calibration() {
+        assert_eq!(Calibration::IDENTITY.apply((0.25, 0.75)), (0.25, 0.75));
+
+        // Swap the axes and mirror horizontally.
+        let calibration = Calibration([0.0, -1.0, 1.0, 1.0, 0.0, 0.0]);
+        assert_eq!(calibration.apply((0.25, 0.75)), (0.25, 0.25));
+    }
+
+    #[test]
+    fn intercepted_release() {
+        let mut input = InputState::default();
+
+        // A bound press is intercepted, and so is the release of the key.
+        assert!(matches!(
+            input.filter_key(24, KeyState::Pressed, Some(1)),
+            FilterResult::Intercept(Some(1))
+        ));
+        assert!(matches!(
+            input.filter_key(24, KeyState::Released, None),
+            FilterResult::Intercept(None)
+        ));
+
+        // The key is no longer tracked once released.
+        assert!(!input.release_intercepted_key(24));
+
+        // A key pressed before the binding was registered is delivered to the client on release.
+        assert!(matches!(
+            input.filter_key(38, KeyState::Pressed, None),
+            FilterResult::Forward
+        ));
+        assert!(matches!(
+            input.filter_key(38, KeyState::Released, None),
+            FilterResult::Forward
+        ));
+
+        // Releases are tracked per key, so a bound key released while another is held stays intercepted.
+        input.filter_key(24, KeyState::Pressed, Some(1));
+        input.filter_key(25, KeyState::Pressed, Some(2));
+        assert!(input.release_intercepted_key(25));
+        assert!(input.release_intercepted_key(24));
+        assert!(!input.release_intercepted_key(24));
+    }
+}
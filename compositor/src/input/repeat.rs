@@ -0,0 +1,164 @@
+//! Key repeat
+//!
+//! Clients repeat keys themselves using the repeat rate and delay sent with `wl_keyboard.repeat_info`, so keys
+//! delivered to clients are never repeated by the compositor. Keys intercepted by a wm keybinding never reach a
+//! client, so the compositor repeats those on behalf of the wm using the same rate and delay.
+//!
+//! Only the most recently pressed key repeats. Pressing another key or releasing the key stops the repeat.
+
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct KeyRepeat {
+    /// Repeats per second. Zero disables repeat.
+    rate: i32,
+
+    /// Delay before the first repeat in milliseconds.
+    delay: i32,
+
+    held: Option<HeldKey>,
+
+    /// Incremented for every press so timers of previous presses stop.
+    generation: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct HeldKey {
+    keycode: u32,
+    binding: u32,
+
+    /// Timestamp of the next repeat in milliseconds.
+    time: u32,
+}
+
+/// A repeat of a held keybinding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repeat {
+    /// The id of the keybinding.
+    pub binding: u32,
+
+    /// Timestamp of the repeat in milliseconds.
+    pub time: u32,
+
+    /// Delay until the next repeat.
+    pub next: Duration,
+}
+
+impl Default for KeyRepeat {
+    fn default() -> Self {
+        Self {
+            rate: 25,
+            delay: 600,
+            held: None,
+            generation: 0,
+        }
+    }
+}
+
+impl KeyRepeat {
+    pub fn set_info(&mut self, rate: i32, delay: i32) {
+        self.rate = rate;
+        self.delay = delay;
+    }
+
+    /// Start repeating a key press intercepted by a keybinding.
+    ///
+    /// Returns the delay until the first repeat and the generation of the press, which must be passed to
+    /// [`KeyRepeat::repeat`]. Returns [`None`] if repeat is disabled.
+    pub fn press(&mut self, keycode: u32, binding: u32, time: u32) -> Option<(Duration, u64)> {
+        self.stop();
+
+        if self.rate <= 0 {
+            return None;
+        }
+
+        let delay = self.delay.max(0) as u32;
+        self.held = Some(HeldKey {
+            keycode,
+            binding,
+            time: time.wrapping_add(delay),
+        });
+
+        Some((Duration::from_millis(delay.into()), self.generation))
+    }
+
+    /// Stop repeating the key if the key is repeating.
+    pub fn release(&mut self, keycode: u32) {
+        if self.held.map_or(false, |held| held.keycode == keycode) {
+            self.stop();
+        }
+    }
+
+    /// Stop repeating any key.
+    pub fn stop(&mut self) {
+        self.held = None;
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Produce the next repeat of the key pressed in `generation`.
+    ///
+    /// Returns [`None`] if the key was released or another key was pressed since.
+    pub fn repeat(&mut self, generation: u64) -> Option<Repeat> {
+        if generation != self.generation || self.rate <= 0 {
+            return None;
+        }
+
+        let held = self.held.as_mut()?;
+        let interval = 1000 / self.rate.max(1) as u32;
+        let time = held.time;
+        held.time = time.wrapping_add(interval);
+
+        Some(Repeat {
+            binding: held.binding,
+            time,
+            next: Duration::from_millis(interval.into()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{KeyRepeat, Repeat};
+
+    #[test]
+    fn repeat_held_key() {
+        let mut repeat = KeyRepeat::default();
+        repeat.set_info(10, 500);
+
+        let (delay, generation) = repeat.press(30, 1, 1000).unwrap();
+        assert_eq!(delay, Duration::from_millis(500));
+
+        assert_eq!(
+            repeat.repeat(generation),
+            Some(Repeat {
+                binding: 1,
+                time: 1500,
+                next: Duration::from_millis(100),
+            })
+        );
+        assert_eq!(repeat.repeat(generation).unwrap().time, 1600);
+
+        // Releasing another key does not stop the repeat.
+        repeat.release(31);
+        assert!(repeat.repeat(generation).is_some());
+
+        repeat.release(30);
+        assert_eq!(repeat.repeat(generation), None);
+    }
+
+    #[test]
+    fn new_press_replaces_repeat() {
+        let mut repeat = KeyRepeat::default();
+        let (_, first) = repeat.press(30, 1, 0).unwrap();
+        let (_, second) = repeat.press(31, 2, 10).unwrap();
+
+        assert_eq!(repeat.repeat(first), None);
+        assert_eq!(repeat.repeat(second).unwrap().binding, 2);
+
+        // A rate of zero disables repeat.
+        repeat.set_info(0, 600);
+        assert_eq!(repeat.press(30, 1, 0), None);
+    }
+}
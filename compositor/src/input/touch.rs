@@ -0,0 +1,319 @@
+//! Touch point tracking and gesture recognition
+//!
+//! Every touch point which is down is tracked with the id the wm uses for the point. Ids are assigned by the
+//! compositor since backends identify touch points using slots, which are reused.
+//!
+//! While two or more touch points are down, the points are checked for a gesture. Moving the points apart or
+//! together by more than [`PINCH_THRESHOLD`] begins a pinch, unless the center of the points moved further than
+//! the points moved apart. Moving the center of the points by more than [`SWIPE_THRESHOLD`] begins a swipe. A
+//! gesture ends once a touch point is lifted, and is cancelled if another touch point goes down.
+//!
+//! The seat has no touch support yet, so clients receive the primary touch point as pointer input instead. The
+//! primary point is the point which went down while no other point was down.
+
+use smithay::utils::{Logical, Point};
+
+/// Relative change of the distance between touch points which begins a pinch.
+pub const PINCH_THRESHOLD: f64 = 0.15;
+
+/// Distance in logical pixels the touch points must move together to begin a swipe.
+pub const SWIPE_THRESHOLD: f64 = 24.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GestureKind {
+    Swipe,
+    Pinch,
+}
+
+/// A change in a recognized gesture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GestureEvent {
+    Begin {
+        kind: GestureKind,
+        fingers: u32,
+    },
+
+    /// The gesture progressed.
+    ///
+    /// The delta is the motion of the center of the touch points since the last update. The scale is relative to
+    /// the distance between the touch points when the gesture began, and is always 1 for swipes.
+    Update {
+        dx: f64,
+        dy: f64,
+        scale: f64,
+    },
+
+    End {
+        cancelled: bool,
+    },
+}
+
+#[derive(Debug)]
+pub struct TouchState<S> {
+    points: Vec<TouchPoint<S>>,
+    next_id: i32,
+    gesture: Option<Gesture>,
+
+    /// The id of the primary touch point.
+    primary: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TouchPoint<S> {
+    slot: S,
+    id: i32,
+    location: Point<f64, Logical>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Gesture {
+    /// The kind of gesture, once recognized.
+    kind: Option<GestureKind>,
+
+    /// The center of the touch points at the last update.
+    center: Point<f64, Logical>,
+
+    /// The average distance of the touch points from the center when the gesture began.
+    spread: f64,
+}
+
+impl<S> Default for TouchState<S> {
+    fn default() -> Self {
+        Self {
+            points: Vec::new(),
+            next_id: 0,
+            gesture: None,
+            primary: None,
+        }
+    }
+}
+
+impl<S: Copy + PartialEq> TouchState<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A touch point went down, returning the id of the point.
+    pub fn down(&mut self, slot: S, location: Point<f64, Logical>, events: &mut Vec<GestureEvent>) -> i32 {
+        // A slot which is still down was not lifted by the backend, replace the point.
+        self.points.retain(|point| point.slot != slot);
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        if self.points.is_empty() {
+            self.primary = Some(id);
+        }
+
+        self.points.push(TouchPoint { slot, id, location });
+
+        self.end_gesture(true, events);
+
+        if self.points.len() >= 2 {
+            let center = self.center();
+            self.gesture = Some(Gesture {
+                kind: None,
+                center,
+                spread: self.spread(center),
+            });
+        }
+
+        id
+    }
+
+    /// A touch point moved, returning the id of the point.
+    pub fn motion(&mut self, slot: S, location: Point<f64, Logical>, events: &mut Vec<GestureEvent>) -> Option<i32> {
+        let point = self.points.iter_mut().find(|point| point.slot == slot)?;
+        point.location = location;
+        let id = point.id;
+
+        let center = self.center();
+        let spread = self.spread(center);
+        let fingers = self.points.len() as u32;
+
+        if let Some(gesture) = self.gesture.as_mut() {
+            let delta = center - gesture.center;
+            let scale = if gesture.spread > 0.0 {
+                spread / gesture.spread
+            } else {
+                1.0
+            };
+
+            if gesture.kind.is_none() {
+                let distance = (delta.x * delta.x + delta.y * delta.y).sqrt();
+
+                if (scale - 1.0).abs() > PINCH_THRESHOLD && (spread - gesture.spread).abs() >= distance {
+                    gesture.kind = Some(GestureKind::Pinch);
+                } else if distance > SWIPE_THRESHOLD {
+                    gesture.kind = Some(GestureKind::Swipe);
+                }
+
+                if let Some(kind) = gesture.kind {
+                    events.push(GestureEvent::Begin { kind, fingers });
+                }
+            }
+
+            // Motion before the gesture is recognized is not reported.
+            if let Some(kind) = gesture.kind {
+                events.push(GestureEvent::Update {
+                    dx: delta.x,
+                    dy: delta.y,
+                    scale: if kind == GestureKind::Pinch { scale } else { 1.0 },
+                });
+                gesture.center = center;
+            }
+        }
+
+        Some(id)
+    }
+
+    /// A touch point was lifted, returning the id of the point.
+    pub fn up(&mut self, slot: S, events: &mut Vec<GestureEvent>) -> Option<i32> {
+        let index = self.points.iter().position(|point| point.slot == slot)?;
+        let point = self.points.remove(index);
+        self.end_gesture(false, events);
+
+        if self.primary == Some(point.id) {
+            self.primary = None;
+        }
+
+        Some(point.id)
+    }
+
+    /// All touch points were cancelled by the backend.
+    pub fn cancel(&mut self, events: &mut Vec<GestureEvent>) {
+        self.points.clear();
+        self.primary = None;
+        self.end_gesture(true, events);
+    }
+
+    /// Whether the touch point in a slot is the primary touch point.
+    pub fn is_primary(&self, slot: S) -> bool {
+        self.points
+            .iter()
+            .any(|point| point.slot == slot && Some(point.id) == self.primary)
+    }
+
+    /// Whether the primary touch point is down.
+    pub fn primary_down(&self) -> bool {
+        self.primary.is_some()
+    }
+
+    fn end_gesture(&mut self, cancelled: bool, events: &mut Vec<GestureEvent>) {
+        if let Some(Gesture { kind: Some(_), .. }) = self.gesture.take() {
+            events.push(GestureEvent::End { cancelled });
+        }
+    }
+
+    fn center(&self) -> Point<f64, Logical> {
+        let sum = self
+            .points
+            .iter()
+            .fold(Point::default(), |sum, point| sum + point.location);
+        let count = self.points.len().max(1) as f64;
+        (sum.x / count, sum.y / count).into()
+    }
+
+    fn spread(&self, center: Point<f64, Logical>) -> f64 {
+        let total = self
+            .points
+            .iter()
+            .map(|point| {
+                let delta = point.location - center;
+                (delta.x * delta.x + delta.y * delta.y).sqrt()
+            })
+            .sum::<f64>();
+
+        total / self.points.len().max(1) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GestureEvent, GestureKind, TouchState};
+
+    #[test]
+    fn swipe() {
+        let mut touch = TouchState::new();
+        let mut events = Vec::new();
+
+        assert_eq!(touch.down(0, (0.0, 0.0).into(), &mut events), 0);
+        assert_eq!(touch.down(1, (100.0, 0.0).into(), &mut events), 1);
+        assert_eq!(touch.down(2, (50.0, 50.0).into(), &mut events), 2);
+
+        // Small motion is not a gesture.
+        touch.motion(0, (0.0, 60.0).into(), &mut events);
+        assert!(events.is_empty());
+
+        touch.motion(1, (100.0, 60.0).into(), &mut events);
+        assert_eq!(
+            events[0],
+            GestureEvent::Begin {
+                kind: GestureKind::Swipe,
+                fingers: 3
+            }
+        );
+
+        touch.motion(2, (50.0, 110.0).into(), &mut events);
+        assert!(matches!(events[2], GestureEvent::Update { dx, scale, .. } if dx == 0.0 && scale == 1.0));
+
+        events.clear();
+        assert_eq!(touch.up(0, &mut events), Some(0));
+        assert_eq!(events, [GestureEvent::End { cancelled: false }]);
+    }
+
+    #[test]
+    fn pinch() {
+        let mut touch = TouchState::new();
+        let mut events = Vec::new();
+
+        touch.down(0, (40.0, 0.0).into(), &mut events);
+        touch.down(1, (60.0, 0.0).into(), &mut events);
+        touch.motion(0, (30.0, 0.0).into(), &mut events);
+        touch.motion(1, (70.0, 0.0).into(), &mut events);
+
+        assert_eq!(
+            events[0],
+            GestureEvent::Begin {
+                kind: GestureKind::Pinch,
+                fingers: 2
+            }
+        );
+        assert!(matches!(events.last(), Some(GestureEvent::Update { scale, .. }) if *scale == 2.0));
+
+        // Another touch point cancels the gesture.
+        events.clear();
+        assert_eq!(touch.down(2, (0.0, 0.0).into(), &mut events), 2);
+        assert_eq!(events, [GestureEvent::End { cancelled: true }]);
+
+        events.clear();
+        touch.cancel(&mut events);
+        assert!(events.is_empty());
+        assert_eq!(touch.motion(0, (0.0, 0.0).into(), &mut events), None);
+    }
+
+    #[test]
+    fn primary_point() {
+        let mut touch = TouchState::new();
+        let mut events = Vec::new();
+
+        touch.down(0, (0.0, 0.0).into(), &mut events);
+        touch.down(1, (10.0, 0.0).into(), &mut events);
+        assert!(touch.is_primary(0));
+        assert!(!touch.is_primary(1));
+
+        // Lifting the primary point does not make another point primary.
+        touch.up(0, &mut events);
+        touch.down(0, (0.0, 0.0).into(), &mut events);
+        assert!(!touch.primary_down());
+        assert!(!touch.is_primary(0));
+
+        touch.up(0, &mut events);
+        touch.up(1, &mut events);
+        touch.down(1, (0.0, 0.0).into(), &mut events);
+        assert!(touch.is_primary(1));
+
+        touch.cancel(&mut events);
+        assert!(!touch.primary_down());
+    }
+}
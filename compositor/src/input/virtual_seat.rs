@@ -0,0 +1,143 @@
+//! Virtual seats for injected input
+//!
+//! Remote desktop sessions inject input into the same pipeline as physical devices, so keybindings, the overview
+//! and the fallback wm see injected events like any other. Each session is attributed to a virtual seat, which
+//! tracks the keys and buttons the session holds. Inconsistent events, such as a release of a key the session
+//! never pressed, are dropped, and everything still held is released when the session ends so nothing stays stuck.
+
+use smithay::{
+    backend::input::{ButtonState, KeyState},
+    utils::{Logical, Point},
+};
+
+/// Identifies a virtual seat, which is the id of the session injecting input.
+pub type VirtualSeatId = u64;
+
+/// An input event injected on a virtual seat.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InjectedEvent {
+    /// Relative pointer motion in logical pixels.
+    PointerMotion {
+        dx: f64,
+        dy: f64,
+    },
+
+    /// Move the pointer to a location in the global compositor space.
+    PointerMotionAbsolute {
+        location: Point<f64, Logical>,
+    },
+
+    PointerButton {
+        button: u32,
+        state: ButtonState,
+    },
+
+    /// A key identified by its evdev keycode.
+    Key {
+        keycode: u32,
+        state: KeyState,
+    },
+}
+
+/// The keys and buttons held on a virtual seat.
+#[derive(Debug, Default)]
+pub struct VirtualSeat {
+    keys: Vec<u32>,
+    buttons: Vec<u32>,
+}
+
+impl VirtualSeat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track an event, returning whether the event should be delivered.
+    ///
+    /// Presses of keys or buttons which are already held and releases of keys or buttons which are not held are
+    /// dropped.
+    pub fn track(&mut self, event: &InjectedEvent) -> bool {
+        match *event {
+            InjectedEvent::PointerMotion { .. } | InjectedEvent::PointerMotionAbsolute { .. } => true,
+            InjectedEvent::PointerButton { button, state } => {
+                track(&mut self.buttons, button, state == ButtonState::Pressed)
+            }
+            InjectedEvent::Key { keycode, state } => track(&mut self.keys, keycode, state == KeyState::Pressed),
+        }
+    }
+
+    /// Take the events releasing every key and button which is held.
+    pub fn release_all(&mut self) -> Vec<InjectedEvent> {
+        let keys = self.keys.drain(..).map(|keycode| InjectedEvent::Key {
+            keycode,
+            state: KeyState::Released,
+        });
+        let buttons = self.buttons.drain(..).map(|button| InjectedEvent::PointerButton {
+            button,
+            state: ButtonState::Released,
+        });
+
+        keys.chain(buttons).collect()
+    }
+}
+
+fn track(held: &mut Vec<u32>, code: u32, pressed: bool) -> bool {
+    let index = held.iter().position(|&held| held == code);
+
+    match (pressed, index) {
+        (true, None) => {
+            held.push(code);
+            true
+        }
+
+        (false, Some(index)) => {
+            held.swap_remove(index);
+            true
+        }
+
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::backend::input::{ButtonState, KeyState};
+
+    use super::{InjectedEvent, VirtualSeat};
+
+    fn key(keycode: u32, state: KeyState) -> InjectedEvent {
+        InjectedEvent::Key { keycode, state }
+    }
+
+    #[test]
+    fn inconsistent_events_are_dropped() {
+        let mut seat = VirtualSeat::new();
+
+        assert!(!seat.track(&key(30, KeyState::Released)));
+        assert!(seat.track(&key(30, KeyState::Pressed)));
+        assert!(!seat.track(&key(30, KeyState::Pressed)));
+        assert!(seat.track(&key(30, KeyState::Released)));
+        assert!(seat.track(&InjectedEvent::PointerMotion { dx: 1.0, dy: 0.0 }));
+    }
+
+    #[test]
+    fn held_input_is_released() {
+        let mut seat = VirtualSeat::new();
+        seat.track(&key(30, KeyState::Pressed));
+        seat.track(&InjectedEvent::PointerButton {
+            button: 0x110,
+            state: ButtonState::Pressed,
+        });
+
+        assert_eq!(
+            seat.release_all(),
+            vec![
+                key(30, KeyState::Released),
+                InjectedEvent::PointerButton {
+                    button: 0x110,
+                    state: ButtonState::Released,
+                },
+            ]
+        );
+        assert!(seat.release_all().is_empty());
+    }
+}
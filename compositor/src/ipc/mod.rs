@@ -0,0 +1,594 @@
+//! IPC control socket
+//!
+//! External tools such as `aerugo-msg` control the display server through a unix socket in `$XDG_RUNTIME_DIR`.
+//! The path of the socket is exported in the [`AERUGO_SOCK`](protocol::SOCKET_ENV) environment variable, so
+//! clients started by the display server find the socket of the instance they run in.
+//!
+//! The messages are described in [`protocol`]. Requests are handled on the event loop, the same as Wayland
+//! requests. Requests which are decisions of the wm, such as activating a toplevel, are forwarded to the wm
+//! instead of being applied directly.
+//!
+//! Clients which subscribed to events are sent the changes to the state of the display server once per
+//! dispatch of the event loop. A client which does not read the events is disconnected once more than
+//! [`MAX_PENDING_WRITE`] bytes are waiting to be written.
+
+pub mod protocol;
+
+use std::{
+    io::{self, Read, Write},
+    num::{NonZeroU32, NonZeroU64},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    time::Duration,
+};
+
+use calloop::{generic::Generic, Interest, LoopHandle, Mode, PostAction, RegistrationToken};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::Serialize;
+use wm_runtime::{Id, WmEvent};
+
+use crate::{
+    frame_scheduler::{self, frame_scheduler},
+    launcher::Origin,
+    logging,
+    popup::output_geometry,
+    shutdown::ShutdownReason,
+    wm::{self, WmStatus},
+    Aerugo, Loop,
+};
+
+use self::protocol::{
+    CallbackInfo, CountersInfo, EventKind, FrameStatsInfo, OutputInfo, Reply, Request, SceneFormat, Snapshot,
+    SpawnInfo, ToplevelInfo, WmInfo, WmStatsInfo, WorkspaceInfo, ZoneInfo, SOCKET_ENV,
+};
+
+/// The maximum length of a request in bytes.
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+/// How many bytes may wait to be written to a client before the client is disconnected.
+const MAX_PENDING_WRITE: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub struct IpcServer {
+    path: PathBuf,
+    listener: UnixListener,
+    r#loop: LoopHandle<'static, Loop>,
+    token: RegistrationToken,
+    connections: FxHashMap<u64, Connection>,
+    next_connection: u64,
+
+    /// The state last reported to subscribed clients.
+    ///
+    /// This is [`None`] while no client is subscribed.
+    snapshot: Option<Snapshot>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    stream: UnixStream,
+    token: RegistrationToken,
+
+    /// Bytes read which do not form a complete request yet.
+    read: Vec<u8>,
+
+    /// Bytes waiting to be written.
+    write: Vec<u8>,
+
+    subscriptions: FxHashSet<EventKind>,
+}
+
+impl IpcServer {
+    /// Bind the IPC socket and export the path of the socket in the environment.
+    pub fn bind(r#loop: &LoopHandle<'static, Loop>) -> io::Result<Self> {
+        let path = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "XDG_RUNTIME_DIR is not set"))?
+            .join(format!("aerugo-ipc.{}.sock", std::process::id()));
+
+        // A socket left behind by a previous instance with the same pid is stale.
+        let _ = std::fs::remove_file(&path);
+
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+
+        let token = r#loop
+            .insert_source(
+                Generic::new(listener.try_clone()?, Interest::READ, Mode::Level),
+                |_, _, state| {
+                    state.accept_ipc_clients();
+                    Ok(PostAction::Continue)
+                },
+            )
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.error))?;
+
+        tracing::info!(path = %path.display(), "Bound IPC socket");
+        std::env::set_var(SOCKET_ENV, &path);
+
+        Ok(Self {
+            path,
+            listener,
+            r#loop: r#loop.clone(),
+            token,
+            connections: FxHashMap::default(),
+            next_connection: 0,
+            snapshot: None,
+        })
+    }
+
+    /// Queue a message to be written to a client.
+    fn send(&mut self, id: u64, message: &impl Serialize) {
+        let Some(connection) = self.connections.get_mut(&id) else {
+            return;
+        };
+
+        match serde_json::to_vec(message) {
+            Ok(line) => {
+                connection.write.extend_from_slice(&line);
+                connection.write.push(b'\n');
+                connection.flush();
+            }
+
+            Err(err) => tracing::error!(%err, "Failed to serialize IPC message"),
+        }
+    }
+
+    /// Read the complete requests a client sent.
+    ///
+    /// Returns [`None`] if the client disconnected or sent a request which is too long.
+    fn read(&mut self, id: u64) -> Option<Vec<Vec<u8>>> {
+        let connection = self.connections.get_mut(&id)?;
+        let mut buf = [0; 4096];
+
+        loop {
+            match connection.stream.read(&mut buf) {
+                Ok(0) => return None,
+                Ok(len) => {
+                    connection.read.extend_from_slice(&buf[..len]);
+
+                    // Stop reading as soon as the incomplete request is too long, rather than buffering
+                    // everything the client writes.
+                    let pending = connection.read.iter().rev().take_while(|&&byte| byte != b'\n').count();
+
+                    if pending > MAX_REQUEST_LEN {
+                        tracing::debug!("IPC client sent a request which is too long");
+                        return None;
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    tracing::debug!(%err, "Failed to read from IPC client");
+                    return None;
+                }
+            }
+        }
+
+        let mut lines = Vec::new();
+
+        while let Some(end) = connection.read.iter().position(|&byte| byte == b'\n') {
+            let mut line = connection.read.drain(..=end).collect::<Vec<_>>();
+            line.pop();
+            lines.push(line);
+        }
+
+        Some(lines)
+    }
+
+    /// Forget a client which disconnected.
+    ///
+    /// The event source of the client is removed by returning [`PostAction::Remove`] from the callback.
+    fn forget(&mut self, id: u64) {
+        self.connections.remove(&id);
+
+        if !self.has_subscribers() {
+            self.snapshot = None;
+        }
+    }
+
+    fn has_subscribers(&self) -> bool {
+        self.connections
+            .values()
+            .any(|connection| !connection.subscriptions.is_empty())
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        for (_, connection) in self.connections.drain() {
+            self.r#loop.remove(connection.token);
+        }
+
+        self.r#loop.remove(self.token);
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl Connection {
+    /// Write as much of the pending bytes as the client accepts.
+    fn flush(&mut self) {
+        while !self.write.is_empty() {
+            match self.stream.write(&self.write) {
+                Ok(0) => break,
+                Ok(len) => {
+                    self.write.drain(..len);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    tracing::debug!(%err, "Failed to write to IPC client");
+                    self.write.clear();
+                    break;
+                }
+            }
+        }
+
+        // The client is disconnected by the next read, which sees the end of the stream.
+        if self.write.len() > MAX_PENDING_WRITE {
+            tracing::debug!("IPC client is not reading, disconnecting");
+            self.write.clear();
+            let _ = self.stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+}
+
+impl Loop {
+    /// Send the changes made while dispatching to subscribed IPC clients.
+    pub fn refresh_ipc(&mut self) {
+        let subscribed = self.ipc.as_ref().map_or(false, IpcServer::has_subscribers);
+        let snapshot = subscribed.then(|| self.comp.ipc_snapshot());
+
+        let Some(ipc) = self.ipc.as_mut() else {
+            return;
+        };
+
+        if let (Some(snapshot), Some(old)) = (snapshot.as_ref(), ipc.snapshot.as_ref()) {
+            for event in snapshot.diff(old) {
+                let kind = event.kind();
+                let ids = ipc
+                    .connections
+                    .iter()
+                    .filter(|(_, connection)| connection.subscriptions.contains(&kind))
+                    .map(|(&id, _)| id)
+                    .collect::<Vec<_>>();
+
+                for id in ids {
+                    ipc.send(id, &event);
+                }
+            }
+        }
+
+        ipc.snapshot = snapshot;
+
+        for connection in ipc.connections.values_mut() {
+            connection.flush();
+        }
+    }
+
+    fn accept_ipc_clients(&mut self) {
+        let Some(ipc) = self.ipc.as_mut() else {
+            return;
+        };
+
+        loop {
+            let stream = match ipc.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(err) => {
+                    tracing::warn!(%err, "Failed to accept IPC client");
+                    return;
+                }
+            };
+
+            let id = ipc.next_connection;
+            ipc.next_connection += 1;
+
+            let source = stream
+                .set_nonblocking(true)
+                .and_then(|()| stream.try_clone())
+                .map(|stream| Generic::new(stream, Interest::READ, Mode::Level));
+
+            let token = source.map_err(|err| err.to_string()).and_then(|source| {
+                ipc.r#loop
+                    .insert_source(source, move |_, _, state| Ok(state.dispatch_ipc_client(id)))
+                    .map_err(|err| err.error.to_string())
+            });
+
+            match token {
+                Ok(token) => {
+                    tracing::debug!(id, "IPC client connected");
+                    ipc.connections.insert(
+                        id,
+                        Connection {
+                            stream,
+                            token,
+                            read: Vec::new(),
+                            write: Vec::new(),
+                            subscriptions: FxHashSet::default(),
+                        },
+                    );
+                }
+
+                Err(err) => tracing::warn!(%err, "Failed to register IPC client"),
+            }
+        }
+    }
+
+    fn dispatch_ipc_client(&mut self, id: u64) -> PostAction {
+        let Some(ipc) = self.ipc.as_mut() else {
+            return PostAction::Remove;
+        };
+
+        let Some(lines) = ipc.read(id) else {
+            tracing::debug!(id, "IPC client disconnected");
+            ipc.forget(id);
+            return PostAction::Remove;
+        };
+
+        for line in lines {
+            let reply = match serde_json::from_slice::<Request>(&line) {
+                Ok(request) => self.handle_ipc_request(id, request),
+                Err(err) => Reply::error(format!("invalid request: {err}")),
+            };
+
+            if let Some(ipc) = self.ipc.as_mut() {
+                ipc.send(id, &reply);
+            }
+        }
+
+        PostAction::Continue
+    }
+
+    fn handle_ipc_request(&mut self, client: u64, request: Request) -> Reply {
+        tracing::debug!(client, ?request, "IPC request");
+
+        match request {
+            Request::GetOutputs => Reply::data(self.comp.ipc_outputs()),
+            Request::GetToplevels => Reply::data(self.comp.ipc_toplevels()),
+            Request::GetWorkspaces => Reply::data(self.comp.ipc_workspaces()),
+            Request::GetWm => Reply::data(self.comp.ipc_wm()),
+
+            Request::ActivateToplevel { id } => {
+                let Some(toplevel) = NonZeroU64::new(id).filter(|&id| self.comp.shell.get_state(id).is_some()) else {
+                    return Reply::error(format!("no toplevel with id {id}"));
+                };
+
+                let Some(toplevel) = wm::wm_toplevel_id(toplevel).filter(|_| self.comp.wm.is_running()) else {
+                    return Reply::error("no wm is running");
+                };
+
+                self.comp.wm.send(WmEvent::ActivationRequested {
+                    toplevel,
+                    token_valid: true,
+                });
+                Reply::ok()
+            }
+
+            Request::ActivateWorkspace { id } => {
+                let Some(workspace) = NonZeroU32::new(id)
+                    .map(Id::workspace)
+                    .filter(|&workspace| self.comp.wm.workspaces().get(workspace).is_some())
+                else {
+                    return Reply::error(format!("no workspace with id {id}"));
+                };
+
+                self.comp.wm.send(WmEvent::WorkspaceActivationRequested(workspace));
+                Reply::ok()
+            }
+
+            Request::ShowOverview => {
+                self.comp.show_overview();
+                Reply::ok()
+            }
+
+            Request::HideOverview => {
+                self.comp.hide_overview();
+                Reply::ok()
+            }
+
+            Request::ReloadWm => {
+                self.comp.wm.reload();
+                Reply::ok()
+            }
+
+            Request::DumpWmTrace { seconds, path } => {
+                match self.comp.wm.dump_trace(seconds.map(Duration::from_secs), path) {
+                    Ok(path) => Reply::data(path),
+                    Err(err) => Reply::error(err),
+                }
+            }
+
+            Request::Shutdown => {
+                self.shutdown(ShutdownReason::UserRequest);
+                Reply::ok()
+            }
+
+            Request::CreateVirtualOutput { width, height, refresh } => {
+                match self.comp.create_virtual_output((width, height).into(), refresh) {
+                    Ok(output) => Reply::data(output.name()),
+                    Err(err) => Reply::error(err),
+                }
+            }
+
+            Request::DestroyVirtualOutput { name } => match self.comp.destroy_virtual_output(&name) {
+                Ok(()) => Reply::ok(),
+                Err(err) => Reply::error(err),
+            },
+
+            Request::SetOutputPower { output, on } => {
+                let Some(output) = self.comp.output_by_name(&output).cloned() else {
+                    return Reply::error(format!("no output named {output}"));
+                };
+
+                self.comp.set_output_power(&output, on);
+                Reply::ok()
+            }
+
+            Request::GetFrameStats => Reply::data(self.comp.ipc_frame_stats()),
+
+            Request::GetCounters => Reply::data(self.comp.ipc_counters()),
+
+            Request::GetWmStats => Reply::data(self.comp.ipc_wm_stats()),
+
+            Request::Spawn { command, systemd_scope } => match self.comp.spawn(Origin::Ipc, &command, systemd_scope) {
+                Ok(spawned) => Reply::data(SpawnInfo {
+                    pid: spawned.pid,
+                    token: spawned.token,
+                }),
+                Err(err) => Reply::error(err),
+            },
+
+            Request::GetLog { lines } => Reply::data(logging::recent_events(lines)),
+
+            Request::GetScene { format } => match format {
+                SceneFormat::Json => Reply::data(self.comp.scene.dump()),
+                SceneFormat::Dot => Reply::data(self.comp.scene.dump().to_dot()),
+            },
+
+            Request::Subscribe { events } => {
+                if let Some(connection) = self.ipc.as_mut().and_then(|ipc| ipc.connections.get_mut(&client)) {
+                    connection.subscriptions = events.into_iter().collect();
+                }
+
+                Reply::ok()
+            }
+        }
+    }
+}
+
+impl Aerugo {
+    fn ipc_snapshot(&self) -> Snapshot {
+        Snapshot {
+            toplevels: self.ipc_toplevels(),
+            workspaces: self.ipc_workspaces(),
+            wm: Some(self.ipc_wm()),
+        }
+    }
+
+    fn ipc_outputs(&self) -> Vec<OutputInfo> {
+        self.outputs
+            .iter()
+            .map(|(output, _)| {
+                let geometry = output_geometry(output);
+
+                OutputInfo {
+                    name: output.name(),
+                    description: output.description(),
+                    x: geometry.loc.x,
+                    y: geometry.loc.y,
+                    width: geometry.size.w,
+                    height: geometry.size.h,
+                    refresh: output.current_mode().map(|mode| mode.refresh),
+                    scale: output.current_scale().fractional_scale(),
+                    on: self.output_power.is_on(output),
+                }
+            })
+            .collect()
+    }
+
+    fn ipc_frame_stats(&self) -> Vec<FrameStatsInfo> {
+        self.outputs
+            .iter()
+            .map(|(output, _)| {
+                let stats = frame_scheduler(output).stats(frame_scheduler::refresh_interval(output));
+
+                FrameStatsInfo {
+                    output: output.name(),
+                    refresh: stats.refresh.as_micros() as u64,
+                    predicted_render_time: stats.predicted_render_time.as_micros() as u64,
+                    frames: stats.frames,
+                    missed: stats.missed,
+                    adaptive_sync: stats.adaptive_sync,
+                    zones: self
+                        .render_stats
+                        .summary(output)
+                        .into_iter()
+                        .map(|zone| ZoneInfo {
+                            name: zone.name.into(),
+                            average: zone.average.as_micros() as u64,
+                            max: zone.max.as_micros() as u64,
+                        })
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    fn ipc_counters(&self) -> CountersInfo {
+        let counters = &self.counters;
+
+        CountersInfo {
+            frames: counters.frames,
+            average_frame_time: counters.average_frame_time().as_micros() as u64,
+            last_frame_time: counters.last_frame_time.as_micros() as u64,
+            damage_area: counters.damage_area,
+            dispatches: counters.dispatches,
+            requests: counters.requests,
+        }
+    }
+
+    fn ipc_toplevels(&self) -> Vec<ToplevelInfo> {
+        let mut toplevels = self
+            .shell
+            .toplevels
+            .iter()
+            .map(|(id, toplevel)| ToplevelInfo {
+                id: id.get(),
+                title: toplevel.title(),
+                app_id: toplevel.app_id(),
+                mapped: toplevel.is_mapped(),
+            })
+            .collect::<Vec<_>>();
+
+        toplevels.sort_unstable_by_key(|toplevel| toplevel.id);
+        toplevels
+    }
+
+    fn ipc_workspaces(&self) -> Vec<WorkspaceInfo> {
+        self.wm
+            .workspaces()
+            .iter()
+            .map(|(id, workspace)| WorkspaceInfo {
+                id: id.rep().get(),
+                name: workspace.name.clone(),
+                output: workspace.output.name(),
+                active: workspace.active,
+            })
+            .collect()
+    }
+
+    fn ipc_wm(&self) -> WmInfo {
+        let status = match self.wm.status() {
+            WmStatus::None => "none",
+            WmStatus::Running => "running",
+            WmStatus::Backoff { .. } => "backoff",
+            WmStatus::Quarantined { .. } => "quarantined",
+            WmStatus::ShuttingDown => "shutting_down",
+        };
+
+        WmInfo {
+            status: status.into(),
+            error: self.wm.error_message(),
+        }
+    }
+
+    fn ipc_wm_stats(&self) -> Option<WmStatsInfo> {
+        let stats = self.wm.stats()?;
+
+        Some(WmStatsInfo {
+            interval: stats.interval.as_micros() as u64,
+            callbacks: stats
+                .callbacks
+                .iter()
+                .map(|(&event, callback)| CallbackInfo {
+                    event: event.into(),
+                    count: callback.count,
+                    average: callback.average().as_micros() as u64,
+                    max: callback.max.as_micros() as u64,
+                })
+                .collect(),
+            fuel_consumed: stats.fuel_consumed,
+            max_queue_depth: stats.max_queue_depth as u64,
+            memory_size: stats.memory_size as u64,
+        })
+    }
+}
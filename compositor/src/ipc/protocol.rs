@@ -0,0 +1,533 @@
+//! Messages exchanged over the IPC socket.
+//!
+//! Every message is a single line of JSON. A client sends [`Request`]s and receives exactly one [`Reply`] per
+//! request, in order. After [`Request::Subscribe`], the client additionally receives [`Event`]s whenever the
+//! subscribed state changes.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Name of the environment variable the path of the IPC socket is exported in.
+pub const SOCKET_ENV: &str = "AERUGO_SOCK";
+
+/// A request sent by a client.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Query the connected outputs.
+    GetOutputs,
+
+    /// Query the toplevels.
+    GetToplevels,
+
+    /// Query the workspaces created by the wm.
+    GetWorkspaces,
+
+    /// Query the state of the wm.
+    GetWm,
+
+    /// Ask the wm to activate a toplevel.
+    ///
+    /// The request is forwarded to the wm the same way as a valid xdg-activation request, so the wm decides
+    /// whether the toplevel is activated.
+    ActivateToplevel { id: u64 },
+
+    /// Ask the wm to activate a workspace.
+    ActivateWorkspace { id: u32 },
+
+    /// Show the overview.
+    ShowOverview,
+
+    /// Hide the overview.
+    HideOverview,
+
+    /// Restart the wm.
+    ReloadWm,
+
+    /// Write the recent wm traffic to a file.
+    DumpWmTrace {
+        /// Only write the traffic from the last number of seconds.
+        #[serde(default)]
+        seconds: Option<u64>,
+
+        #[serde(default)]
+        path: Option<PathBuf>,
+    },
+
+    /// Shut the display server down.
+    Shutdown,
+
+    /// Create a virtual output, replying with the name of the output.
+    CreateVirtualOutput {
+        width: i32,
+        height: i32,
+
+        /// The refresh rate in mHz.
+        #[serde(default)]
+        refresh: Option<i32>,
+    },
+
+    /// Remove a virtual output.
+    DestroyVirtualOutput { name: String },
+
+    /// Turn an output on or off.
+    SetOutputPower { output: String, on: bool },
+
+    /// Query the statistics of the frame scheduler of each output.
+    GetFrameStats,
+
+    /// Query the counters of the display server.
+    GetCounters,
+
+    /// Query the statistics last reported by the wm.
+    GetWmStats,
+
+    /// Start a program, replying with the pid and the xdg-activation token of the program.
+    ///
+    /// The first element of the command is the program and the remaining elements are the arguments.
+    Spawn {
+        command: Vec<String>,
+
+        /// Start the program in a transient scope of the systemd user instance.
+        #[serde(default)]
+        systemd_scope: bool,
+    },
+
+    /// Query the most recent log messages of the display server, oldest first.
+    ///
+    /// Debug messages are included regardless of the log filter of the display server.
+    GetLog {
+        /// Only reply with the last number of messages.
+        #[serde(default)]
+        lines: Option<usize>,
+    },
+
+    /// Query the scene graph, with the node hierarchy, offsets, surfaces and outputs.
+    GetScene {
+        #[serde(default)]
+        format: SceneFormat,
+    },
+
+    /// Receive events when the state of the display server changes.
+    ///
+    /// Subscribing again replaces the subscribed events.
+    Subscribe { events: Vec<EventKind> },
+}
+
+/// The reply to a request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Reply {
+    Ok {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        data: Option<serde_json::Value>,
+    },
+
+    Error {
+        message: String,
+    },
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Self::Ok { data: None }
+    }
+
+    pub fn data(data: impl Serialize) -> Self {
+        match serde_json::to_value(data) {
+            Ok(data) => Self::Ok { data: Some(data) },
+            Err(err) => Self::error(err),
+        }
+    }
+
+    pub fn error(message: impl ToString) -> Self {
+        Self::Error {
+            message: message.to_string(),
+        }
+    }
+}
+
+/// How the scene graph is described.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SceneFormat {
+    /// A tree of nodes in JSON.
+    #[default]
+    Json,
+
+    /// A string in the DOT language of Graphviz.
+    Dot,
+}
+
+/// The kinds of events a client can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Toplevel,
+    Workspace,
+    Wm,
+}
+
+/// An event sent to subscribed clients.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A toplevel was created.
+    ToplevelNew { toplevel: ToplevelInfo },
+
+    /// The state of a toplevel changed.
+    ToplevelChanged { toplevel: ToplevelInfo },
+
+    /// A toplevel was closed.
+    ToplevelClosed { id: u64 },
+
+    /// The workspaces changed.
+    Workspaces { workspaces: Vec<WorkspaceInfo> },
+
+    /// The state of the wm changed.
+    Wm { wm: WmInfo },
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::ToplevelNew { .. } | Self::ToplevelChanged { .. } | Self::ToplevelClosed { .. } => {
+                EventKind::Toplevel
+            }
+            Self::Workspaces { .. } => EventKind::Workspace,
+            Self::Wm { .. } => EventKind::Wm,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputInfo {
+    pub name: String,
+    pub description: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+
+    /// The refresh rate in mHz.
+    pub refresh: Option<i32>,
+
+    /// The scale of the output.
+    pub scale: f64,
+
+    /// Whether the output is on.
+    pub on: bool,
+}
+
+/// Statistics of the frame scheduler of an output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameStatsInfo {
+    pub output: String,
+
+    /// The refresh interval in µs.
+    pub refresh: u64,
+
+    /// How long before the vblank a frame is rendered, in µs.
+    pub predicted_render_time: u64,
+
+    /// The number of presented frames.
+    pub frames: u64,
+
+    /// The number of frames presented after the vblank they were scheduled for.
+    pub missed: u64,
+
+    /// Whether adaptive sync is enabled.
+    pub adaptive_sync: bool,
+
+    /// The time spent in each zone of recent frames, see [`crate::stats`].
+    ///
+    /// The first zone is the whole frame. Timings are measured on the CPU, the time the GPU spends on a frame is
+    /// not included.
+    pub zones: Vec<ZoneInfo>,
+}
+
+/// Timings of a zone of the frames of an output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZoneInfo {
+    pub name: String,
+
+    /// The average time of the zone, in µs.
+    pub average: u64,
+
+    /// The longest time of the zone, in µs.
+    pub max: u64,
+}
+
+/// Totals since the display server started.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CountersInfo {
+    /// The number of rendered frames.
+    pub frames: u64,
+
+    /// The average time a frame took to render, in µs.
+    pub average_frame_time: u64,
+
+    /// How long the last frame took to render, in µs.
+    pub last_frame_time: u64,
+
+    /// The area of all rendered frames which was damaged, in physical pixels.
+    pub damage_area: u64,
+
+    /// How often clients were dispatched.
+    pub dispatches: u64,
+
+    /// The number of requests of clients which were dispatched.
+    pub requests: u64,
+}
+
+/// A program started by the display server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpawnInfo {
+    pub pid: u32,
+
+    /// The xdg-activation token given to the program in `XDG_ACTIVATION_TOKEN` and `DESKTOP_STARTUP_ID`.
+    pub token: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToplevelInfo {
+    pub id: u64,
+    pub title: Option<String>,
+    pub app_id: Option<String>,
+    pub mapped: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkspaceInfo {
+    pub id: u32,
+    pub name: String,
+    pub output: String,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WmInfo {
+    /// One of `none`, `running`, `backoff`, `quarantined` or `shutting_down`.
+    pub status: String,
+
+    /// The error shown to the user, if the wm was quarantined.
+    pub error: Option<String>,
+}
+
+/// Statistics of the wm over the interval before the last report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WmStatsInfo {
+    /// The length of the interval in µs.
+    pub interval: u64,
+
+    /// The callbacks into the wm, by event.
+    pub callbacks: Vec<CallbackInfo>,
+
+    /// The fuel the wm consumed.
+    pub fuel_consumed: u64,
+
+    /// The most events which waited to be dispatched to the wm at once.
+    pub max_queue_depth: u64,
+
+    /// The size of the memory of the wm in bytes.
+    pub memory_size: u64,
+}
+
+/// Statistics of the callbacks handling one kind of event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallbackInfo {
+    pub event: String,
+    pub count: u32,
+
+    /// The average time of the callbacks in µs.
+    pub average: u64,
+
+    /// The slowest callback in µs.
+    pub max: u64,
+}
+
+/// The state reported to subscribed clients, used to find what changed since the last refresh.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The toplevels, sorted by id.
+    pub toplevels: Vec<ToplevelInfo>,
+    pub workspaces: Vec<WorkspaceInfo>,
+    pub wm: Option<WmInfo>,
+}
+
+impl Snapshot {
+    /// The events describing the changes from `old` to `self`.
+    pub fn diff(&self, old: &Snapshot) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        for toplevel in &self.toplevels {
+            match old.toplevels.iter().find(|old| old.id == toplevel.id) {
+                None => events.push(Event::ToplevelNew {
+                    toplevel: toplevel.clone(),
+                }),
+                Some(old) if old != toplevel => events.push(Event::ToplevelChanged {
+                    toplevel: toplevel.clone(),
+                }),
+                Some(_) => (),
+            }
+        }
+
+        for old in &old.toplevels {
+            if !self.toplevels.iter().any(|toplevel| toplevel.id == old.id) {
+                events.push(Event::ToplevelClosed { id: old.id });
+            }
+        }
+
+        if self.workspaces != old.workspaces {
+            events.push(Event::Workspaces {
+                workspaces: self.workspaces.clone(),
+            });
+        }
+
+        if let Some(wm) = self.wm.as_ref().filter(|&wm| Some(wm) != old.wm.as_ref()) {
+            events.push(Event::Wm { wm: wm.clone() });
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, EventKind, Reply, Request, SceneFormat, Snapshot, ToplevelInfo, WmInfo};
+
+    fn toplevel(id: u64, title: &str) -> ToplevelInfo {
+        ToplevelInfo {
+            id,
+            title: Some(title.into()),
+            app_id: None,
+            mapped: true,
+        }
+    }
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"type":"get_toplevels"}"#).unwrap(),
+            Request::GetToplevels
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"type":"activate_toplevel","id":3}"#).unwrap(),
+            Request::ActivateToplevel { id: 3 }
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"type":"subscribe","events":["toplevel","wm"]}"#).unwrap(),
+            Request::Subscribe {
+                events: vec![EventKind::Toplevel, EventKind::Wm]
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"type":"dump_wm_trace"}"#).unwrap(),
+            Request::DumpWmTrace {
+                seconds: None,
+                path: None
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"type":"create_virtual_output","width":1280,"height":720}"#).unwrap(),
+            Request::CreateVirtualOutput {
+                width: 1280,
+                height: 720,
+                refresh: None
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"type":"set_output_power","output":"DP-1","on":false}"#).unwrap(),
+            Request::SetOutputPower {
+                output: "DP-1".into(),
+                on: false
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"type":"get_frame_stats"}"#).unwrap(),
+            Request::GetFrameStats
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"type":"get_counters"}"#).unwrap(),
+            Request::GetCounters
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"type":"get_wm_stats"}"#).unwrap(),
+            Request::GetWmStats
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"type":"spawn","command":["foot"]}"#).unwrap(),
+            Request::Spawn {
+                command: vec!["foot".into()],
+                systemd_scope: false
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"type":"get_log","lines":50}"#).unwrap(),
+            Request::GetLog { lines: Some(50) }
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"type":"get_scene","format":"dot"}"#).unwrap(),
+            Request::GetScene {
+                format: SceneFormat::Dot
+            }
+        );
+        assert!(serde_json::from_str::<Request>(r#"{"type":"unknown"}"#).is_err());
+    }
+
+    #[test]
+    fn serialize_replies() {
+        assert_eq!(serde_json::to_string(&Reply::ok()).unwrap(), r#"{"status":"ok"}"#);
+        assert_eq!(
+            serde_json::to_string(&Reply::error("no")).unwrap(),
+            r#"{"status":"error","message":"no"}"#
+        );
+    }
+
+    #[test]
+    fn diff_toplevels() {
+        let old = Snapshot {
+            toplevels: vec![toplevel(1, "a"), toplevel(2, "b")],
+            ..Snapshot::default()
+        };
+        let new = Snapshot {
+            toplevels: vec![toplevel(2, "c"), toplevel(3, "d")],
+            ..Snapshot::default()
+        };
+
+        assert_eq!(
+            new.diff(&old),
+            [
+                Event::ToplevelChanged {
+                    toplevel: toplevel(2, "c")
+                },
+                Event::ToplevelNew {
+                    toplevel: toplevel(3, "d")
+                },
+                Event::ToplevelClosed { id: 1 },
+            ]
+        );
+        assert!(new.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn diff_wm() {
+        let wm = |status: &str| WmInfo {
+            status: status.into(),
+            error: None,
+        };
+        let old = Snapshot {
+            wm: Some(wm("running")),
+            ..Snapshot::default()
+        };
+        let new = Snapshot {
+            wm: Some(wm("backoff")),
+            ..Snapshot::default()
+        };
+
+        let events = new.diff(&old);
+        assert_eq!(events, [Event::Wm { wm: wm("backoff") }]);
+        assert_eq!(events[0].kind(), EventKind::Wm);
+    }
+}
@@ -0,0 +1,294 @@
+//! Starting programs
+//!
+//! The wm and IPC clients start programs through the display server, so programs connect to this display server
+//! regardless of the environment the display server itself was started in. A program is started with
+//! `WAYLAND_DISPLAY` set to the socket of the display server and inherits [`AERUGO_SOCK`](crate::ipc::protocol::SOCKET_ENV).
+//!
+//! Each program is given an xdg-activation token in `XDG_ACTIVATION_TOKEN` and `DESKTOP_STARTUP_ID`. The token is
+//! valid, since programs are only started on behalf of the user. When a toplevel requests activation using the
+//! token, the launch is tied to the toplevel, so the wm learns which toplevel a program it started created.
+//!
+//! A program may be started in a transient scope of the systemd user instance using `systemd-run`. The resources
+//! of the program are then accounted separately from the display server, and the program is not stopped with the
+//! unit of the display server.
+
+use std::{
+    ffi::{OsStr, OsString},
+    io,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rustc_hash::FxHashMap;
+use smithay::wayland::xdg_activation::XdgActivationToken;
+
+use crate::Aerugo;
+
+/// How long a launch may be tied to a toplevel after the program was started.
+///
+/// Programs may take much longer to create a toplevel than a token stays valid, so this is longer than the
+/// [`TOKEN_TIMEOUT`](crate::wayland::xdg_activation::TOKEN_TIMEOUT).
+pub const LAUNCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Who started a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// The wm, with the id of the launch assigned by the wm runtime.
+    Wm(u32),
+
+    /// An IPC client.
+    Ipc,
+
+    /// The display server itself, for example to start the lock screen.
+    Server,
+}
+
+/// A program which was started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spawned {
+    pub pid: u32,
+
+    /// The xdg-activation token given to the program.
+    pub token: String,
+}
+
+#[derive(Debug)]
+pub struct Launcher {
+    /// The name of the listening socket, if the display server listens on a socket.
+    wayland_display: Option<OsString>,
+
+    /// Launches which were not tied to a toplevel yet, by the token given to the program.
+    launches: FxHashMap<XdgActivationToken, Launch>,
+
+    /// The number of programs started in a systemd scope, used to name the scopes.
+    scopes: u64,
+}
+
+#[derive(Debug)]
+struct Launch {
+    origin: Origin,
+    started: Instant,
+}
+
+impl Launcher {
+    pub fn new(wayland_display: Option<OsString>) -> Self {
+        Self {
+            wayland_display,
+            launches: FxHashMap::default(),
+            scopes: 0,
+        }
+    }
+
+    /// The name of the listening socket, if the display server listens on a socket.
+    pub fn wayland_display(&self) -> Option<&OsStr> {
+        self.wayland_display.as_deref()
+    }
+
+    /// Start a program.
+    ///
+    /// The command must not be empty.
+    fn spawn(
+        &mut self,
+        origin: Origin,
+        command: &[String],
+        token: XdgActivationToken,
+        systemd_scope: bool,
+        now: Instant,
+    ) -> io::Result<u32> {
+        let scope = systemd_scope.then(|| self.scope_name(&command[0]));
+        let mut child = self.command(command, &token, scope.as_deref()).spawn()?;
+        let pid = child.id();
+        tracing::info!(pid, ?command, ?scope, "Started program");
+
+        // Wait for the program on another thread, so the program does not linger as a zombie once it exits.
+        if let Err(err) = thread::Builder::new()
+            .name("Aerugo launcher".into())
+            .spawn(move || child.wait())
+        {
+            tracing::warn!(%err, pid, "Failed to wait for program");
+        }
+
+        // Launches which were never tied to a toplevel are forgotten once expired.
+        self.launches
+            .retain(|_, launch| now.duration_since(launch.started) < LAUNCH_TIMEOUT);
+        self.launches.insert(token, Launch { origin, started: now });
+
+        Ok(pid)
+    }
+
+    /// Tie the launch which was given a token to a toplevel, returning who started the program.
+    ///
+    /// A launch may only be tied to one toplevel.
+    pub fn take(&mut self, token: &XdgActivationToken, now: Instant) -> Option<Origin> {
+        self.launches
+            .remove(token)
+            .filter(|launch| now.duration_since(launch.started) < LAUNCH_TIMEOUT)
+            .map(|launch| launch.origin)
+    }
+
+    fn command(&self, command: &[String], token: &str, scope: Option<&str>) -> Command {
+        let mut cmd = match scope {
+            Some(unit) => {
+                let mut cmd = Command::new("systemd-run");
+                cmd.args(["--user", "--scope", "--collect", "--quiet"])
+                    .arg(format!("--unit={unit}"))
+                    .arg("--")
+                    .args(command);
+                cmd
+            }
+
+            None => {
+                let mut cmd = Command::new(&command[0]);
+                cmd.args(&command[1..]);
+                cmd
+            }
+        };
+
+        match &self.wayland_display {
+            Some(wayland_display) => cmd.env("WAYLAND_DISPLAY", wayland_display),
+            None => cmd.env_remove("WAYLAND_DISPLAY"),
+        };
+
+        cmd
+            // A socket inherited from the display server the display server is nested in is not for the program.
+            .env_remove("WAYLAND_SOCKET")
+            // TODO: Set DISPLAY once Xwayland is supported, until then X11 clients would connect to the display
+            // server the display server is nested in.
+            .env_remove("DISPLAY")
+            .env("XDG_ACTIVATION_TOKEN", token)
+            .env("DESKTOP_STARTUP_ID", token)
+            .stdin(Stdio::null());
+
+        cmd
+    }
+
+    /// The name of the next scope, following the naming of application units of systemd.
+    fn scope_name(&mut self, program: &str) -> String {
+        self.scopes += 1;
+
+        let name = program
+            .rsplit('/')
+            .next()
+            .unwrap_or(program)
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' => c,
+                _ => '_',
+            })
+            .collect::<String>();
+
+        format!("app-aerugo-{name}-{}-{}.scope", std::process::id(), self.scopes)
+    }
+}
+
+impl Aerugo {
+    /// Start a program on behalf of the wm, an IPC client or the display server.
+    pub fn spawn(&mut self, origin: Origin, command: &[String], systemd_scope: bool) -> io::Result<Spawned> {
+        if command.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the command is empty"));
+        }
+
+        let token = self.create_launch_token();
+        let token_string = token.to_string();
+        let pid = self
+            .launcher
+            .spawn(origin, command, token, systemd_scope, Instant::now())?;
+
+        Ok(Spawned {
+            pid,
+            token: token_string,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::OsStr,
+        time::{Duration, Instant},
+    };
+
+    use smithay::wayland::xdg_activation::XdgActivationToken;
+
+    use super::{Launch, Launcher, Origin, LAUNCH_TIMEOUT};
+
+    fn command() -> Vec<String> {
+        vec!["/usr/bin/foot".into(), "--server".into()]
+    }
+
+    fn env<'a>(cmd: &'a std::process::Command, key: &str) -> Option<Option<&'a OsStr>> {
+        cmd.get_envs().find(|(name, _)| *name == key).map(|(_, value)| value)
+    }
+
+    #[test]
+    fn environment() {
+        let launcher = Launcher::new(Some("wayland-1".into()));
+        let cmd = launcher.command(&command(), "token", None);
+
+        assert_eq!(cmd.get_program(), "/usr/bin/foot");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), ["--server"]);
+        assert_eq!(env(&cmd, "WAYLAND_DISPLAY"), Some(Some(OsStr::new("wayland-1"))));
+        assert_eq!(env(&cmd, "XDG_ACTIVATION_TOKEN"), Some(Some(OsStr::new("token"))));
+        assert_eq!(env(&cmd, "DESKTOP_STARTUP_ID"), Some(Some(OsStr::new("token"))));
+        assert_eq!(env(&cmd, "DISPLAY"), Some(None));
+    }
+
+    #[test]
+    fn environment_without_socket() {
+        let launcher = Launcher::new(None);
+        let cmd = launcher.command(&command(), "token", None);
+
+        assert_eq!(env(&cmd, "WAYLAND_DISPLAY"), Some(None));
+    }
+
+    #[test]
+    fn systemd_scope() {
+        let mut launcher = Launcher::new(Some("wayland-1".into()));
+        let unit = launcher.scope_name("/usr/bin/foot-client");
+        assert_eq!(unit, format!("app-aerugo-foot_client-{}-1.scope", std::process::id()));
+
+        let cmd = launcher.command(&command(), "token", Some(&unit));
+        assert_eq!(cmd.get_program(), "systemd-run");
+
+        let args = cmd.get_args().collect::<Vec<_>>();
+        assert!(args.contains(&OsStr::new("--scope")));
+        assert!(args.ends_with(&[OsStr::new("--"), OsStr::new("/usr/bin/foot"), OsStr::new("--server")]));
+        assert_eq!(env(&cmd, "XDG_ACTIVATION_TOKEN"), Some(Some(OsStr::new("token"))));
+    }
+
+    #[test]
+    fn launches_are_tied_once() {
+        let mut launcher = Launcher::new(Some("wayland-1".into()));
+        let token = XdgActivationToken::from(String::from("token"));
+        let now = Instant::now();
+
+        launcher.launches.insert(
+            token.clone(),
+            Launch {
+                origin: Origin::Wm(3),
+                started: now,
+            },
+        );
+
+        assert_eq!(launcher.take(&token, now + Duration::from_secs(1)), Some(Origin::Wm(3)));
+        assert_eq!(launcher.take(&token, now + Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn launches_expire() {
+        let mut launcher = Launcher::new(Some("wayland-1".into()));
+        let token = XdgActivationToken::from(String::from("token"));
+        let now = Instant::now();
+
+        launcher.launches.insert(
+            token.clone(),
+            Launch {
+                origin: Origin::Ipc,
+                started: now,
+            },
+        );
+
+        assert_eq!(launcher.take(&token, now + LAUNCH_TIMEOUT), None);
+    }
+}
@@ -1,54 +1,181 @@
 use std::{
     error::Error,
+    ffi::{OsStr, OsString},
     io,
-    os::fd::OwnedFd,
+    os::{
+        fd::OwnedFd,
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
     sync::{
         mpsc::{self, SendError},
         Arc,
     },
     thread::{self, JoinHandle, Thread},
+    time::Duration,
 };
 
-use calloop::{channel::SyncSender, generic::Generic, EventLoop, Interest, LoopHandle, LoopSignal, Mode, PostAction};
+use calloop::{
+    channel::SyncSender,
+    generic::Generic,
+    timer::{TimeoutAction, Timer},
+    EventLoop, Interest, LoopHandle, LoopSignal, Mode, PostAction, RegistrationToken,
+};
 
-use backend::Backend;
+use backend::{renderer::RendererSelection, Backend, BackendEvent};
 use smithay::wayland::{compositor::CompositorClientState, socket::ListeningSocketSource};
-use wayland_server::{Display, DisplayHandle};
+use wayland_server::{
+    backend::{DisconnectReason, ProtocolError},
+    protocol::wl_display,
+    Display, DisplayHandle,
+};
 
+mod animation;
 pub mod backend;
+pub mod color;
+pub mod config;
+pub mod conformance;
+pub mod crash;
+pub mod cursor;
+mod damage;
 pub mod forest;
+mod frame;
+pub mod frame_scheduler;
+pub mod gamma;
+mod idle;
+pub mod input;
+pub mod ipc;
+mod launcher;
+mod limits;
+pub mod logging;
+mod overview;
+pub mod policy;
+mod popup;
+pub mod rules;
 mod scene;
+// Without the `screencast` feature, nothing starts casts.
+#[cfg_attr(not(feature = "screencast"), allow(dead_code))]
+mod screencast;
+mod screenshot;
+mod session;
 mod shell;
+pub mod shutdown;
+pub mod socket;
 mod state;
-mod transaction;
+pub mod stats;
+#[doc(hidden)]
+pub mod testing;
+pub mod transaction;
+mod virtual_output;
 mod wayland;
+mod wm;
+mod workspace;
+
+pub use state::{Aerugo, PrivilegedGlobals};
+
+use crate::{
+    config::{watcher::ConfigWatcher, Config, ConfigError},
+    conformance::Conformance,
+    ipc::IpcServer,
+    limits::ClientLimits,
+    policy::{ClientIdentity, ClientPolicy},
+    shutdown::{ShutdownReason, SHUTDOWN_GRACE_PERIOD},
+    socket::Socket,
+    state::ClientData,
+};
 
-pub use state::Aerugo;
-
-use crate::state::{ClientData, PrivilegedGlobals};
+/// How long the global of a removed output stays disabled before the global is destroyed.
+const GLOBAL_REMOVAL_DELAY: Duration = Duration::from_secs(5);
 
 type BackendConstructor = Box<
-    dyn FnOnce(LoopHandle<'static, Loop>, DisplayHandle) -> Result<Box<dyn Backend>, Box<dyn Error>> + Send + 'static,
+    dyn FnOnce(LoopHandle<'static, Loop>, DisplayHandle, RendererSelection) -> Result<Box<dyn Backend>, Box<dyn Error>>
+        + Send
+        + 'static,
 >;
 
 /// Configuration used to create a server instance.
 pub struct Configuration {
     backend_constructor: BackendConstructor,
+    renderer: RendererSelection,
+    wm: Option<PathBuf>,
+    policy: ClientPolicy,
+    conformance: Conformance,
+    config: Option<PathBuf>,
+    socket: Socket,
 }
 
 impl Configuration {
     pub fn new<B>(b: B) -> Self
     where
-        B: FnOnce(LoopHandle<'static, Loop>, DisplayHandle) -> Result<Box<dyn Backend>, Box<dyn Error>>
+        B: FnOnce(
+                LoopHandle<'static, Loop>,
+                DisplayHandle,
+                RendererSelection,
+            ) -> Result<Box<dyn Backend>, Box<dyn Error>>
             + Send
             + 'static,
     {
         Self {
             backend_constructor: Box::new(b),
+            renderer: RendererSelection::default(),
+            wm: None,
+            policy: ClientPolicy::default(),
+            conformance: Conformance::default(),
+            config: None,
+            socket: Socket::default(),
         }
     }
 
-    // TODO: Socket creation here
+    /// Sets which renderer the backend should use.
+    ///
+    /// By default the renderer is selected automatically, see [`renderer`](backend::renderer).
+    pub fn renderer(mut self, renderer: RendererSelection) -> Self {
+        self.renderer = renderer;
+        self
+    }
+
+    /// Sets the path to the wasm module of the wm.
+    ///
+    /// If no wm is set, windows will not be managed.
+    pub fn wm(mut self, path: impl Into<PathBuf>) -> Self {
+        self.wm = Some(path.into());
+        self
+    }
+
+    /// Sets the policy deciding which privileged globals are available to clients.
+    ///
+    /// By default no privileged globals are available to clients connecting to the socket.
+    pub fn policy(mut self, policy: ClientPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets how protocol violations by clients are handled.
+    ///
+    /// See [`Conformance`] for more details.
+    pub fn conformance(mut self, conformance: Conformance) -> Self {
+        self.conformance = conformance;
+        self
+    }
+
+    /// Sets the path to the configuration file.
+    ///
+    /// The configuration file is watched and changes are applied while the server is running. Options set in
+    /// the configuration file take precedence over options set on the [`Configuration`].
+    pub fn config(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config = Some(path.into());
+        self
+    }
+
+    /// Sets the socket clients connect to.
+    ///
+    /// By default the first free `wayland-N` socket is bound. A socket name set in the configuration file takes
+    /// precedence over [`Socket::Auto`] and [`Socket::Named`], but not over a socket bound by the caller or a
+    /// disabled socket. See [`socket`] for more details.
+    pub fn socket(mut self, socket: Socket) -> Self {
+        self.socket = socket;
+        self
+    }
 
     /// Creates a server using the configuration.
     ///
@@ -67,16 +194,27 @@ impl Configuration {
 
             let signal = r#loop.get_signal();
             let (send_server, recv_server) = calloop::channel::sync_channel::<ExecutorMessage>(5);
-            send.send((signal, send_server)).expect("Executor thread died");
 
-            let mut aerugo = Loop::new(&r#loop, self.backend_constructor).expect("TODO: Error type");
+            // The caller is told why the server failed to start.
+            let mut aerugo = match Loop::new(&r#loop, self) {
+                Ok(aerugo) => aerugo,
+                Err(err) => {
+                    send.send(Err(err)).expect("Executor thread died");
+                    return ShutdownReason::BackendLost;
+                }
+            };
+
+            // The socket is bound once the server was created.
+            let socket_name = aerugo.comp.launcher.wayland_display().map(OsStr::to_owned);
+            send.send(Ok((signal, send_server, socket_name)))
+                .expect("Executor thread died");
 
             {
                 let r#loop = r#loop.handle();
                 r#loop
-                    .insert_source(recv_server, |msg, _, _state| {
-                        if let calloop::channel::Event::Msg(_msg) = msg {
-                            todo!("Handle executor messages")
+                    .insert_source(recv_server, |msg, _, state| {
+                        if let calloop::channel::Event::Msg(msg) = msg {
+                            state.handle_executor_message(msg);
                         }
                     })
                     .unwrap();
@@ -84,6 +222,19 @@ impl Configuration {
 
             r#loop
                 .run(None, &mut aerugo, |state| {
+                    state.comp.cleanup_disconnected_clients();
+                    state.comp.refresh_fallback_wm();
+                    state.comp.refresh_wm_handover();
+                    state.comp.refresh_wm_views();
+                    // Workspace and toplevel changes made while dispatching are sent to clients together.
+                    state.comp.refresh_workspaces();
+                    state
+                        .comp
+                        .shell
+                        .refresh_foreign_toplevels(&state.comp.display, &state.comp.scene);
+                    state.refresh_ipc();
+                    #[cfg(feature = "logind")]
+                    state.refresh_logind();
                     // Flush any pending messages to ensure clients can respond to server events.
                     state.flush_display();
                     // Check the backend has met any internal shutdown conditions.
@@ -91,19 +242,25 @@ impl Configuration {
                 })
                 .unwrap();
 
-            tracing::info!("Server shutting down");
+            // A server stopped through the loop signal without a reason was stopped by the executor.
+            let reason = aerugo.shutdown.unwrap_or(ShutdownReason::UserRequest);
+            tracing::info!(%reason, exit_code = reason.exit_code(), "Server shutting down");
+            reason
         })?;
 
         // Get the signal from the oneshot channel so the executor can stop the server.
         //
-        // There is no need to use try_recv since the event loop is either successfully created or the
-        // thread fails to initialize the event loop
-        let (signal, channel) = recv.recv().expect("TODO: Add error variant");
+        // There is no need to use try_recv since the server is either successfully created or the thread fails
+        // to create the server.
+        let (signal, channel, socket_name) = recv
+            .recv()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "the server failed to start"))??;
 
         Ok(AerugoExecutor {
             thread,
             signal,
             channel,
+            socket_name,
         })
     }
 }
@@ -112,9 +269,10 @@ impl Configuration {
 ///
 /// Messages may be sent to the display server's event loop using this type.
 pub struct AerugoExecutor {
-    thread: JoinHandle<()>,
+    thread: JoinHandle<ShutdownReason>,
     signal: LoopSignal,
     channel: SyncSender<ExecutorMessage>,
+    socket_name: Option<OsString>,
 }
 
 impl AerugoExecutor {
@@ -123,6 +281,13 @@ impl AerugoExecutor {
         self.thread.thread()
     }
 
+    /// The name clients use to connect to the display server in `WAYLAND_DISPLAY`.
+    ///
+    /// This is [`None`] if the socket is disabled.
+    pub fn socket_name(&self) -> Option<&OsStr> {
+        self.socket_name.as_deref()
+    }
+
     /// Creates a client using the specified file descriptor for the client socket.
     ///
     /// This function is primarily intended for allowing wlcs to create clients for testing.
@@ -131,25 +296,56 @@ impl AerugoExecutor {
             .send(ExecutorMessage::CreateClient(fd))
             .map_err(|msg| match msg.0 {
                 ExecutorMessage::CreateClient(fd) => SendError(fd),
+                _ => unreachable!(),
             })
     }
 
+    /// Reloads the wm.
+    ///
+    /// This will also restart a wm which was stopped after crashing repeatedly.
+    pub fn reload_wm(&self) {
+        let _ = self.channel.send(ExecutorMessage::ReloadWm);
+    }
+
+    /// Writes the recent traffic between the compositor and the wm to a file.
+    ///
+    /// Only traffic from the last `duration` is written, or all recorded traffic if `duration` is [`None`]. If
+    /// `path` is [`None`], the trace is written to the state directory.
+    pub fn dump_wm_trace(&self, duration: Option<Duration>, path: Option<PathBuf>) {
+        let _ = self.channel.send(ExecutorMessage::DumpWmTrace { duration, path });
+    }
+
     /// Stops the server event loop.
     pub fn stop(&self) {
-        // Stopping the server is twofold, first we send the event loop to stop and then immediately wake the
-        // event loop to immediately ask the event loop to shut down.
+        self.stop_with(ShutdownReason::UserRequest);
+    }
+
+    /// Stops the server event loop for the specified reason.
+    pub fn stop_with(&self, reason: ShutdownReason) {
+        if self.channel.send(ExecutorMessage::Shutdown(reason)).is_ok() {
+            return;
+        }
+
+        // The event loop stopped receiving messages. Stopping the server is twofold, first we send the event loop
+        // to stop and then immediately wake the event loop to immediately ask the event loop to shut down.
         self.signal.stop();
         self.signal.wakeup();
     }
 
-    /// Wait for the server event loop to stop.
-    pub fn join(self) -> thread::Result<()> {
+    /// Wait for the server event loop to stop, returning why the server stopped.
+    pub fn join(self) -> thread::Result<ShutdownReason> {
         self.thread.join()
     }
 }
 
 enum ExecutorMessage {
     CreateClient(OwnedFd),
+    Shutdown(ShutdownReason),
+    ReloadWm,
+    DumpWmTrace {
+        duration: Option<Duration>,
+        path: Option<PathBuf>,
+    },
 }
 
 #[derive(Debug)]
@@ -158,10 +354,62 @@ pub struct Loop {
     signal: LoopSignal,
     comp: Aerugo,
     display: DisplayHandle,
+
+    /// The listening socket, until the server stops accepting clients.
+    listening_socket: Option<RegistrationToken>,
+
+    /// The IPC control socket, if the socket could be bound.
+    ipc: Option<IpcServer>,
+
+    /// The screen cast portal backend, if the session bus is available.
+    #[cfg(feature = "screencast")]
+    _portal: Option<screencast::Portal>,
+
+    /// The logind session, if logind is available.
+    #[cfg(feature = "logind")]
+    logind: Option<session::Logind>,
+
+    /// The effective client policy.
+    policy: ClientPolicy,
+
+    /// The policy set on the [`Configuration`], which the configuration file extends.
+    base_policy: ClientPolicy,
+
+    /// The wm set on the [`Configuration`], used if the configuration file does not set a wm.
+    base_wm: Option<PathBuf>,
+
+    config: Config,
+    _config_watcher: Option<ConfigWatcher>,
+
+    /// Why the server is stopping, once a shutdown was requested.
+    shutdown: Option<ShutdownReason>,
 }
 
 impl Loop {
-    pub fn new(r#loop: &EventLoop<'static, Self>, backend: BackendConstructor) -> Result<Self, ()> {
+    pub fn new(r#loop: &EventLoop<'static, Self>, configuration: Configuration) -> io::Result<Self> {
+        let Configuration {
+            backend_constructor: backend,
+            renderer,
+            wm: base_wm,
+            policy: base_policy,
+            conformance,
+            config: config_path,
+            socket,
+        } = configuration;
+
+        let config = config_path
+            .as_deref()
+            .and_then(|path| match Config::load(path) {
+                Ok(config) => Some(config),
+                // A missing configuration file is not an error, the defaults are used.
+                Err(ConfigError::Io(err)) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => {
+                    tracing::error!(%err, path = %path.display(), "Failed to load config");
+                    None
+                }
+            })
+            .unwrap_or_default();
+
         let display = Display::new().expect("Failed to initialize Wayland display");
         let signal = r#loop.get_signal();
         let r#loop = r#loop.handle();
@@ -174,37 +422,249 @@ impl Loop {
         let display = display_handle;
 
         // Register the listening socket so clients can connect
-        register_listening_socket(&r#loop);
-
-        let backend = backend(r#loop.clone(), display.clone()).expect("TODO: Error type");
-        let comp = Aerugo::new(&r#loop, display.clone(), backend);
+        let socket = match (socket, &config.socket) {
+            (Socket::Auto | Socket::Named(_), Some(name)) => Socket::Named(name.clone()),
+            (socket, _) => socket,
+        };
+        let (listening_socket, socket_name) = register_listening_socket(&r#loop, socket)?;
+
+        // The display server is usable without the IPC socket, only external control is unavailable.
+        let ipc = IpcServer::bind(&r#loop)
+            .map_err(|err| tracing::warn!(%err, "Failed to bind IPC socket"))
+            .ok();
+
+        #[cfg(feature = "screencast")]
+        let portal = screencast::Portal::spawn(&r#loop)
+            .map_err(|err| tracing::warn!(%err, "Failed to serve screen cast portal"))
+            .ok();
+
+        #[cfg(feature = "logind")]
+        let logind = session::Logind::connect(&r#loop)
+            .map_err(|err| tracing::warn!(%err, "Failed to connect to logind"))
+            .ok()
+            .map(|mut logind| {
+                logind.set_lock_before_sleep(config.session.lock_before_sleep);
+                logind
+            });
+
+        let renderer = config.renderer.unwrap_or(renderer);
+        let backend = match backend(r#loop.clone(), display.clone(), renderer) {
+            Ok(backend) => backend,
+            Err(err) => {
+                tracing::error!("Failed to start the backend: {err}");
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("failed to start the backend: {err}"),
+                ));
+            }
+        };
+        let wm = config.wm.clone().or_else(|| base_wm.clone());
+        let mut comp = Aerugo::new(&r#loop, display.clone(), backend, wm, socket_name, conformance);
+        comp.apply_keyboard_config(&config.keyboard, None);
+        comp.apply_output_config(&config.outputs);
+        comp.input.apply_config(&config.inputs);
+        comp.transactions = config.transactions.clone();
+        comp.limits = config.limits;
+        comp.idle.set_timeout(config.idle.blank_timeout());
+        comp.apply_night_light_config(&config.night_light);
+        comp.apply_window_rules(config.rules.clone());
+        comp.wm.set_retry_policy(config.wm_recovery.retry_policy());
+        comp.wm.set_execution_limits(config.wm_recovery.execution_limits());
+        comp.wm.set_wasi(config.wm_wasi.wasi_config());
+        comp.wm.start();
+
+        let policy = base_policy.clone().merge(config.policy().unwrap_or_default());
+        let config_watcher = config_path.map(|path| ConfigWatcher::new(r#loop.clone(), path));
+
+        // Clients may connect once the event loop runs.
+        session::notify_ready();
 
         Ok(Self {
             r#loop,
             signal,
             comp,
             display,
+            listening_socket,
+            ipc,
+            #[cfg(feature = "screencast")]
+            _portal: portal,
+            #[cfg(feature = "logind")]
+            logind,
+            policy,
+            base_policy,
+            base_wm,
+            config,
+            _config_watcher: config_watcher,
+            shutdown: None,
         })
     }
 
+    /// Apply a new configuration to the running server.
+    pub fn apply_config(&mut self, config: Config) {
+        // The configuration was validated when loaded.
+        self.policy = self.base_policy.clone().merge(config.policy().unwrap_or_default());
+
+        if config.socket != self.config.socket {
+            tracing::warn!("Changing the socket name requires restarting the compositor");
+        }
+
+        if config.renderer != self.config.renderer {
+            tracing::warn!("Changing the renderer requires restarting the compositor");
+        }
+
+        self.comp
+            .apply_keyboard_config(&config.keyboard, Some(&self.config.keyboard));
+        self.comp.apply_output_config(&config.outputs);
+        self.comp.input.apply_config(&config.inputs);
+        self.comp.transactions = config.transactions.clone();
+        self.comp.limits = config.limits;
+        self.comp.idle.set_timeout(config.idle.blank_timeout());
+        self.comp.apply_night_light_config(&config.night_light);
+        self.comp.wm.set_retry_policy(config.wm_recovery.retry_policy());
+        self.comp.wm.set_execution_limits(config.wm_recovery.execution_limits());
+        self.comp.wm.set_wasi(config.wm_wasi.wasi_config());
+
+        #[cfg(feature = "logind")]
+        if let Some(logind) = &mut self.logind {
+            logind.set_lock_before_sleep(config.session.lock_before_sleep);
+        }
+
+        if config.rules != self.config.rules {
+            self.comp.apply_window_rules(config.rules.clone());
+        }
+
+        let wm = config.wm.clone().or_else(|| self.base_wm.clone());
+        if wm != self.config.wm.clone().or_else(|| self.base_wm.clone()) {
+            self.comp.wm.set_module(wm);
+            self.comp.wm.reload();
+        }
+
+        self.config = config;
+    }
+
+    fn handle_executor_message(&mut self, msg: ExecutorMessage) {
+        match msg {
+            ExecutorMessage::CreateClient(fd) => {
+                if let Err(err) = self.display.insert_client(
+                    UnixStream::from(fd),
+                    Arc::new(ClientData {
+                        globals: PrivilegedGlobals::all(),
+                        compositor: CompositorClientState::default(),
+                        disconnected: self.comp.disconnected_clients.clone(),
+                        limits: ClientLimits::default(),
+                    }),
+                ) {
+                    tracing::error!(%err, "Failed to create client");
+                }
+            }
+
+            ExecutorMessage::ReloadWm => self.comp.wm.reload(),
+
+            ExecutorMessage::Shutdown(reason) => self.shutdown(reason),
+
+            ExecutorMessage::DumpWmTrace { duration, path } => match self.comp.wm.dump_trace(duration, path) {
+                Ok(path) => tracing::info!(path = %path.display(), "Wrote wm trace"),
+                Err(err) => tracing::error!(%err, "Failed to write wm trace"),
+            },
+        }
+    }
+
+    pub(crate) fn handle_backend_event(&mut self, event: BackendEvent) {
+        match event {
+            BackendEvent::OutputAdded(output) => {
+                self.comp.add_output(output);
+                self.comp.apply_output_config(&self.config.outputs);
+            }
+
+            BackendEvent::OutputRemoved(output) => {
+                if let Some(global) = self.comp.remove_output(&output) {
+                    self.comp.remove_global_later(global);
+                }
+            }
+
+            BackendEvent::InputDeviceAdded(device) => self.comp.input.add_device(&device),
+            BackendEvent::InputDeviceRemoved(device) => self.comp.input.remove_device(&device),
+        }
+    }
+
     pub fn flush_display(&mut self) {
         self.display.flush_clients().expect("TODO: Error?");
     }
 
     pub fn check_shutdown(&mut self) {
-        let shutdown =
-            // Check if the backend has requested a shutdown
-            self.comp.backend.should_shutdown();
+        // Check if the backend has requested a shutdown
+        if let Some(reason) = self.comp.backend.shutdown_reason() {
+            self.shutdown(reason);
+        }
+
+        // A wm usually asks to shut down on behalf of the user, for example from a logout binding.
+        if self.comp.wm.shutdown_requested() {
+            self.shutdown(ShutdownReason::UserRequest);
+        }
+    }
+
+    /// Stop the server.
+    ///
+    /// The server stops accepting clients, tells the wm to finalize and disables globals. The event loop keeps
+    /// running for [`SHUTDOWN_GRACE_PERIOD`] so clients and the wm can react before the server stops.
+    ///
+    /// The first reason is kept if the server is stopped more than once.
+    pub fn shutdown(&mut self, reason: ShutdownReason) {
+        if self.shutdown.is_some() {
+            return;
+        }
 
-        // TODO: Other shutdown check mechanisms, such as a logout.
+        tracing::info!(%reason, "Shutting down");
+        self.shutdown = Some(reason);
+        crash::set_shutdown(reason);
+
+        if let Some(token) = self.listening_socket.take() {
+            self.r#loop.remove(token);
+        }
 
-        if shutdown {
-            // Signal the event loop to stop
-            self.signal.stop();
-            // In order to terminate the event loop quickly after stopping it, we need to wake the event loop.
-            self.signal.wakeup();
+        self.comp.wm.finalize();
+        self.comp.disable_globals();
+        self.flush_display();
+
+        let timer = self
+            .r#loop
+            .insert_source(Timer::from_duration(SHUTDOWN_GRACE_PERIOD), |_, _, state| {
+                state.stop();
+                TimeoutAction::Drop
+            });
+
+        // Without the timer the server would never stop, so stop immediately.
+        if let Err(err) = timer {
+            tracing::error!(%err, "Failed to insert shutdown timer");
+            self.stop();
         }
     }
+
+    /// Disconnect the remaining clients and stop the event loop.
+    fn stop(&mut self) {
+        let handle = self.display.backend_handle();
+        let clients = handle.all_clients().collect::<Vec<_>>();
+
+        for client in clients {
+            handle.kill_client(
+                client,
+                DisconnectReason::ProtocolError(ProtocolError {
+                    code: wl_display::Error::Implementation as u32,
+                    object_id: 1,
+                    object_interface: "wl_display".into(),
+                    message: "the compositor is shutting down".into(),
+                }),
+            );
+        }
+
+        // Stopping the wm closes the channel to the runtime, which makes the wm thread exit.
+        self.comp.wm.stop();
+
+        // Signal the event loop to stop
+        self.signal.stop();
+        // In order to terminate the event loop quickly after stopping it, we need to wake the event loop.
+        self.signal.wakeup();
+    }
 }
 
 fn register_display_source(display: Display<Aerugo>, r#loop: &LoopHandle<'static, Loop>) {
@@ -212,10 +672,11 @@ fn register_display_source(display: Display<Aerugo>, r#loop: &LoopHandle<'static
         .insert_source(
             Generic::new(display, Interest::READ, Mode::Level),
             |_, display, state| {
+                let _span = tracing::trace_span!("dispatch_clients").entered();
+
                 // SAFETY: we don't drop the display
-                unsafe {
-                    display.get_mut().dispatch_clients(&mut state.comp).unwrap();
-                }
+                let requests = state.dispatch_clients(unsafe { display.get_mut() });
+                state.comp.counters.dispatched(requests);
 
                 Ok(PostAction::Continue)
             },
@@ -223,28 +684,93 @@ fn register_display_source(display: Display<Aerugo>, r#loop: &LoopHandle<'static
         .unwrap();
 }
 
-fn register_listening_socket(r#loop: &LoopHandle<'static, Loop>) {
-    let listening_socket = ListeningSocketSource::new_auto().expect("Failed to bind a socket");
+fn register_listening_socket(
+    r#loop: &LoopHandle<'static, Loop>,
+    socket: Socket,
+) -> io::Result<(Option<RegistrationToken>, Option<OsString>)> {
+    let listening_socket = match socket {
+        Socket::Auto => ListeningSocketSource::new_auto(),
+        Socket::Named(name) => ListeningSocketSource::with_name(&name),
+        Socket::Listener(listener) => {
+            let (token, socket) = register_listener(r#loop, listener)?;
+            return Ok((Some(token), Some(socket)));
+        }
+        Socket::Disabled => {
+            tracing::info!("Not listening on a Wayland socket");
+            return Ok((None, None));
+        }
+    }
+    .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("failed to bind a socket: {err}")))?;
 
     let socket = listening_socket.socket_name().to_owned();
     tracing::info!("Bound Wayland socket: {:?}", socket);
 
-    r#loop
-        .insert_source(listening_socket, |client, _, state| {
-            let info = format!("{client:?}");
-
-            // TODO: Graceful error handling
-            if let Err(err) = state.display.insert_client(
-                client,
-                Arc::new(ClientData {
-                    // TODO: Limit the available globals
-                    globals: PrivilegedGlobals::all(),
-                    compositor: CompositorClientState::default(),
-                }),
-            ) {
-                // TODO: Provide info about the socket (name)
-                tracing::error!(%err, "Failed to register client with fd: {info}");
-            }
-        })
+    let token = r#loop
+        .insert_source(listening_socket, |client, _, state| accept_client(state, client))
         .unwrap();
+
+    Ok((Some(token), Some(socket)))
+}
+
+/// Accept clients on a socket bound by the caller.
+fn register_listener(
+    r#loop: &LoopHandle<'static, Loop>,
+    listener: UnixListener,
+) -> io::Result<(RegistrationToken, OsString)> {
+    let path = listener
+        .local_addr()?
+        .as_pathname()
+        .map(Path::to_owned)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the socket is not bound to a path"))?;
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from);
+    let socket = socket::display_name(&path, runtime_dir.as_deref());
+    listener.set_nonblocking(true)?;
+
+    let token = r#loop
+        .insert_source(
+            Generic::new(listener.try_clone()?, Interest::READ, Mode::Level),
+            move |_, _, state| {
+                loop {
+                    match listener.accept() {
+                        Ok((client, _)) => accept_client(state, client),
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(err) => {
+                            tracing::warn!(%err, "Failed to accept client");
+                            break;
+                        }
+                    }
+                }
+
+                Ok(PostAction::Continue)
+            },
+        )
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.error))?;
+
+    tracing::info!("Listening on Wayland socket: {:?}", socket);
+    Ok((token, socket))
+}
+
+fn accept_client(state: &mut Loop, client: UnixStream) {
+    let info = format!("{client:?}");
+
+    let identity = ClientIdentity::from_stream(&client).unwrap_or_else(|err| {
+        tracing::warn!(%err, "Failed to read client credentials");
+        ClientIdentity::default()
+    });
+    let globals = state.policy.globals(&identity);
+    tracing::debug!(?identity, ?globals, "Client connected");
+
+    // TODO: Graceful error handling
+    if let Err(err) = state.display.insert_client(
+        client,
+        Arc::new(ClientData {
+            globals,
+            compositor: CompositorClientState::default(),
+            disconnected: state.comp.disconnected_clients.clone(),
+            limits: ClientLimits::default(),
+        }),
+    ) {
+        // TODO: Provide info about the socket (name)
+        tracing::error!(%err, "Failed to register client with fd: {info}");
+    }
 }
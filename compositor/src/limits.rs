@@ -0,0 +1,248 @@
+//! Resource limits of clients
+//!
+//! A client flooding the display server with requests or allocating unbounded resources degrades the display
+//! server for every other client. Each client is limited in how many requests are dispatched per second, how many
+//! surfaces the client may have and how large the buffers attached to the surfaces may be. A client exceeding a
+//! limit is disconnected and the violation is logged.
+//!
+//! The clients are dispatched one at a time, so the requests of each client are counted. The limits are
+//! configured in the `[limits]` section of the configuration file, see
+//! [`LimitsConfig`](crate::config::LimitsConfig).
+
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use smithay::{
+    backend::renderer::buffer_dimensions,
+    wayland::compositor::{self, BufferAssignment, SurfaceAttributes},
+};
+use wayland_server::{
+    backend::{protocol::ProtocolError, DisconnectReason},
+    protocol::{wl_display, wl_surface::WlSurface},
+    Client, Display, Resource,
+};
+
+use crate::{state::ClientData, Aerugo, Loop};
+
+/// The window requests are counted in.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// The resources used by a client, see [`ClientData`].
+#[derive(Debug, Default)]
+pub struct ClientLimits {
+    requests: Mutex<RequestWindow>,
+    surfaces: AtomicU32,
+}
+
+impl ClientLimits {
+    /// Count requests which were dispatched, returning whether the client stayed within the limit.
+    fn dispatched(&self, requests: usize, now: Instant, limit: u32) -> bool {
+        self.requests.lock().unwrap().record(requests, now, limit)
+    }
+
+    /// Count a new surface, returning whether the client stayed within the limit.
+    fn surface_created(&self, limit: u32) -> bool {
+        let surfaces = self.surfaces.fetch_add(1, Ordering::Relaxed) + 1;
+        limit == 0 || surfaces <= limit
+    }
+
+    fn surface_destroyed(&self) {
+        let _ = self
+            .surfaces
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |surfaces| surfaces.checked_sub(1));
+    }
+}
+
+/// The requests of a client dispatched within the current window.
+#[derive(Debug, Default)]
+struct RequestWindow {
+    start: Option<Instant>,
+    requests: u64,
+}
+
+impl RequestWindow {
+    /// Record dispatched requests, returning whether the client stayed within `limit` requests per second.
+    ///
+    /// A limit of `0` disables the limit.
+    fn record(&mut self, requests: usize, now: Instant, limit: u32) -> bool {
+        if limit == 0 {
+            return true;
+        }
+
+        match self.start {
+            Some(start) if now.duration_since(start) < RATE_WINDOW => self.requests += requests as u64,
+            _ => {
+                self.start = Some(now);
+                self.requests = requests as u64;
+            }
+        }
+
+        self.requests <= u64::from(limit)
+    }
+}
+
+/// Whether a buffer of the specified size is within the limit on the largest dimension.
+fn buffer_allowed(width: i32, height: i32, limit: u32) -> bool {
+    limit == 0 || (width.unsigned_abs() <= limit && height.unsigned_abs() <= limit)
+}
+
+impl Loop {
+    /// Dispatch the requests of every client, returning how many requests were dispatched.
+    ///
+    /// Clients exceeding the request rate are disconnected.
+    pub fn dispatch_clients(&mut self, display: &mut Display<Aerugo>) -> usize {
+        let now = Instant::now();
+        let limit = self.comp.limits.requests_per_second;
+        let clients = self.display.backend_handle().all_clients().collect::<Vec<_>>();
+        let mut total = 0;
+
+        for id in clients {
+            let requests = match display.backend().dispatch_single_client(&mut self.comp, id.clone()) {
+                Ok(requests) => requests,
+                Err(err) => {
+                    tracing::debug!(client = ?id, %err, "Failed to dispatch client");
+                    continue;
+                }
+            };
+
+            total += requests;
+
+            if requests == 0 {
+                continue;
+            }
+
+            let Ok(client) = Client::from_id(&self.display, id) else {
+                continue;
+            };
+
+            let within_limit =
+                ClientData::get_data(&client).map_or(true, |data| data.limits.dispatched(requests, now, limit));
+
+            if !within_limit {
+                self.comp
+                    .disconnect_client(&client, format!("exceeded {limit} requests per second"));
+            }
+        }
+
+        total
+    }
+}
+
+impl Aerugo {
+    /// Count a new surface of a client and check the buffers attached to the surface on commit.
+    pub fn limit_surface(&mut self, surface: &WlSurface) {
+        let Some(client) = surface.client() else {
+            return;
+        };
+
+        let limit = self.limits.max_surfaces;
+        let within_limit = ClientData::get_data(&client).map_or(true, |data| data.limits.surface_created(limit));
+
+        if !within_limit {
+            self.disconnect_client(&client, format!("exceeded {limit} surfaces"));
+            return;
+        }
+
+        compositor::add_pre_commit_hook::<Self, _>(surface, |state, _display, surface| {
+            state.check_buffer_size(surface);
+        });
+    }
+
+    /// Stop counting a destroyed surface of a client.
+    pub fn release_surface(&mut self, surface: &WlSurface) {
+        if let Some(data) = surface.client().as_ref().and_then(ClientData::get_data) {
+            data.limits.surface_destroyed();
+        }
+    }
+
+    fn check_buffer_size(&mut self, surface: &WlSurface) {
+        let limit = self.limits.max_buffer_size;
+
+        if limit == 0 {
+            return;
+        }
+
+        let size = compositor::with_states(surface, |states| {
+            match &states.cached_state.pending::<SurfaceAttributes>().buffer {
+                Some(BufferAssignment::NewBuffer(buffer)) => buffer_dimensions(buffer),
+                _ => None,
+            }
+        });
+
+        if let (Some(size), Some(client)) = (size, surface.client()) {
+            if !buffer_allowed(size.w, size.h, limit) {
+                self.disconnect_client(
+                    &client,
+                    format!("attached a {}x{} buffer, larger than {limit} pixels", size.w, size.h),
+                );
+            }
+        }
+    }
+
+    /// Disconnect a client which exceeded a limit.
+    pub fn disconnect_client(&self, client: &Client, message: String) {
+        let credentials = client.get_credentials(&self.display).ok();
+        tracing::warn!(client = ?client.id(), pid = credentials.map(|c| c.pid), "Client {message}, disconnecting");
+
+        self.display.backend_handle().kill_client(
+            client.id(),
+            DisconnectReason::ProtocolError(ProtocolError {
+                code: wl_display::Error::NoMemory as u32,
+                object_id: 1,
+                object_interface: "wl_display".into(),
+                message: format!("the client {message}"),
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{buffer_allowed, ClientLimits, RequestWindow};
+
+    #[test]
+    fn request_rate() {
+        let mut window = RequestWindow::default();
+        let now = Instant::now();
+
+        assert!(window.record(60, now, 100));
+        assert!(window.record(40, now + Duration::from_millis(500), 100));
+        assert!(!window.record(1, now + Duration::from_millis(900), 100));
+
+        // A new window starts once a second passed.
+        assert!(window.record(100, now + Duration::from_secs(1), 100));
+    }
+
+    #[test]
+    fn unlimited_request_rate() {
+        let mut window = RequestWindow::default();
+        assert!(window.record(usize::MAX, Instant::now(), 0));
+    }
+
+    #[test]
+    fn surface_quota() {
+        let limits = ClientLimits::default();
+
+        assert!(limits.surface_created(2));
+        assert!(limits.surface_created(2));
+        assert!(!limits.surface_created(2));
+
+        limits.surface_destroyed();
+        limits.surface_destroyed();
+        assert!(limits.surface_created(2));
+    }
+
+    #[test]
+    fn buffer_size() {
+        assert!(buffer_allowed(3840, 2160, 16384));
+        assert!(!buffer_allowed(32768, 1, 16384));
+        assert!(buffer_allowed(32768, 32768, 0));
+    }
+}
@@ -0,0 +1,110 @@
+//! Recent log messages
+//!
+//! The most recent log messages are kept in memory by the [`RecentEvents`] layer, which must be added to the
+//! tracing subscriber. Debug messages are kept regardless of the filter of the printed log, so an issue which
+//! already happened can be diagnosed without restarting the display server with verbose logging. The messages are
+//! included in [crash reports](crate::crash) and may be queried over IPC using `aerugo-msg debug log`.
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write as _},
+    sync::Mutex,
+};
+
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// How many log messages are kept.
+pub const RECENT_EVENTS: usize = 1024;
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// The most recent log messages, oldest first.
+///
+/// If `count` is set, only the last `count` messages are returned.
+pub fn recent_events(count: Option<usize>) -> Vec<String> {
+    RECENT.lock().map(|recent| last(&recent, count)).unwrap_or_default()
+}
+
+/// The most recent log messages, unless the messages are being recorded.
+///
+/// This never blocks, for use while panicking.
+pub(crate) fn try_recent_events() -> Vec<String> {
+    RECENT.try_lock().map(|recent| last(&recent, None)).unwrap_or_default()
+}
+
+fn last(recent: &VecDeque<String>, count: Option<usize>) -> Vec<String> {
+    let skip = count.map_or(0, |count| recent.len().saturating_sub(count));
+    recent.iter().skip(skip).cloned().collect()
+}
+
+fn push(recent: &mut VecDeque<String>, line: String) {
+    if recent.len() == RECENT_EVENTS {
+        recent.pop_front();
+    }
+
+    recent.push_back(line);
+}
+
+/// A tracing layer keeping the most recent log messages.
+#[derive(Debug, Default)]
+pub struct RecentEvents;
+
+impl<S: Subscriber> Layer<S> for RecentEvents {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut line = format!(
+            "{} {} {}:",
+            chrono::Local::now().format("%H:%M:%S%.3f"),
+            metadata.level(),
+            metadata.target()
+        );
+        event.record(&mut FieldVisitor(&mut line));
+
+        if let Ok(mut recent) = RECENT.lock() {
+            push(&mut recent, line);
+        }
+    }
+}
+
+struct FieldVisitor<'a>(&'a mut String);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let _ = match field.name() {
+            "message" => write!(self.0, " {value:?}"),
+            name => write!(self.0, " {name}={value:?}"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::{last, push, RECENT_EVENTS};
+
+    #[test]
+    fn bounded() {
+        let mut recent = VecDeque::new();
+
+        for i in 0..RECENT_EVENTS + 10 {
+            push(&mut recent, i.to_string());
+        }
+
+        assert_eq!(recent.len(), RECENT_EVENTS);
+        assert_eq!(recent.front().map(String::as_str), Some("10"));
+    }
+
+    #[test]
+    fn last_events() {
+        let recent = VecDeque::from(["a".to_string(), "b".into(), "c".into()]);
+
+        assert_eq!(last(&recent, None), ["a", "b", "c"]);
+        assert_eq!(last(&recent, Some(2)), ["b", "c"]);
+        assert_eq!(last(&recent, Some(5)), ["a", "b", "c"]);
+    }
+}
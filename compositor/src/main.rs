@@ -1,26 +1,71 @@
-use std::panic;
+use std::{panic, process};
 
-use aerugo_comp::{backend, Configuration};
+use aerugo_comp::{
+    backend, config, conformance::Conformance, crash, logging, shutdown::ShutdownReason, socket::Socket, Configuration,
+};
 use clap::Parser;
 use tracing::metadata::LevelFilter;
-use tracing_subscriber::{EnvFilter, FmtSubscriber};
+use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Layer};
 
 mod cli;
 
 fn main() {
-    let _args = cli::AerugoArgs::parse();
+    let args = cli::AerugoArgs::parse();
     let env_filter = EnvFilter::builder()
         .with_default_directive(LevelFilter::DEBUG.into())
         .from_env()
         .unwrap();
-    let subscriber = FmtSubscriber::builder().with_env_filter(env_filter).finish();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(env_filter))
+        .with(logging::RecentEvents.with_filter(LevelFilter::DEBUG));
+
+    // The profiler receives every span, independent of the log filter.
+    #[cfg(feature = "profiling")]
+    let subscriber = subscriber.with(tracing_tracy::TracyLayer::new());
 
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    crash::install_panic_hook();
+
+    let selection = args.backend.into();
+    let mut configuration = Configuration::new(move |r#loop, display, renderer| {
+        backend::create_backend(selection, r#loop, display, renderer)
+    })
+    .renderer(args.renderer.into());
+
+    if let Some(path) = args.wm {
+        configuration = configuration.wm(path);
+    }
+
+    if let Some(path) = args.config.or_else(config::default_path) {
+        configuration = configuration.config(path);
+    }
+
+    if args.strict {
+        configuration = configuration.conformance(Conformance::Strict);
+    }
+
+    let socket = match args.socket {
+        Some(name) => Some(Socket::Named(name)),
+        None => Socket::from_systemd().unwrap_or_else(|err| {
+            tracing::warn!(%err, "Ignoring sockets passed by systemd");
+            None
+        }),
+    };
+
+    if let Some(socket) = socket {
+        configuration = configuration.socket(socket);
+    }
 
-    let configuration = Configuration::new(backend::default_backend);
-    let executor = configuration.create_server().expect("Failed to create server");
+    let executor = match configuration.create_server() {
+        Ok(executor) => executor,
+        Err(err) => {
+            tracing::error!(%err, "Failed to create server");
+            process::exit(ShutdownReason::BackendLost.exit_code());
+        }
+    };
 
-    if let Err(err) = executor.join() {
-        panic::resume_unwind(err)
+    match executor.join() {
+        Ok(reason) => process::exit(reason.exit_code()),
+        Err(err) => panic::resume_unwind(err),
     }
 }
@@ -0,0 +1,216 @@
+//! Workspace overview
+//!
+//! The overview presents live thumbnails of every mapped toplevel in a grid. The wm decides when the overview is
+//! shown, while the compositor does the heavy lifting: laying out and rendering the thumbnails and hit testing
+//! pointer input against them.
+//!
+//! Thumbnails are live views of the toplevel surfaces, scaled to fit the cells of the grid. The layout is
+//! updated every frame, so thumbnails follow toplevels which resize while the overview is shown.
+
+use smithay::{
+    backend::renderer::utils::with_renderer_surface_state,
+    utils::{Logical, Point, Rectangle, Size, SERIAL_COUNTER},
+};
+use wayland_server::protocol::wl_surface::WlSurface;
+
+use crate::{scene::SceneGraphElement, shell::ToplevelId, Aerugo};
+
+/// Gap between thumbnails and around the edges of the overview.
+const GAP: i32 = 32;
+
+/// A thumbnail of a toplevel in the overview.
+#[derive(Debug)]
+pub struct Thumbnail {
+    pub toplevel: ToplevelId,
+    pub surface: WlSurface,
+
+    /// Where the thumbnail is presented.
+    pub geometry: Rectangle<i32, Logical>,
+}
+
+#[derive(Debug, Default)]
+pub struct Overview {
+    thumbnails: Vec<Thumbnail>,
+}
+
+impl Overview {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn thumbnails(&self) -> &[Thumbnail] {
+        &self.thumbnails
+    }
+
+    /// Lay out the thumbnails of the toplevels inside the area.
+    pub fn update(
+        &mut self,
+        area: Rectangle<i32, Logical>,
+        toplevels: impl IntoIterator<Item = (ToplevelId, WlSurface)>,
+    ) {
+        let toplevels = toplevels
+            .into_iter()
+            .filter_map(|(toplevel, surface)| {
+                let size = with_renderer_surface_state(&surface, |state| state.surface_size())?;
+                Some((toplevel, surface, size))
+            })
+            .collect::<Vec<_>>();
+
+        let sizes = toplevels.iter().map(|(_, _, size)| *size).collect::<Vec<_>>();
+        let cells = grid_layout(area, GAP, &sizes);
+
+        self.thumbnails = toplevels
+            .into_iter()
+            .zip(cells)
+            .map(|((toplevel, surface, _), geometry)| Thumbnail {
+                toplevel,
+                surface,
+                geometry,
+            })
+            .collect();
+    }
+
+    /// Find the toplevel whose thumbnail is under the point.
+    pub fn hit_test(&self, point: Point<f64, Logical>) -> Option<ToplevelId> {
+        self.thumbnails
+            .iter()
+            .find(|thumbnail| thumbnail.geometry.to_f64().contains(point))
+            .map(|thumbnail| thumbnail.toplevel)
+    }
+
+    /// Elements presenting the thumbnails.
+    ///
+    /// The surfaces of the thumbnails must have been imported by the renderer.
+    pub fn render_elements(&self, scale: f64) -> Vec<SceneGraphElement> {
+        self.thumbnails
+            .iter()
+            .map(|thumbnail| {
+                SceneGraphElement::scaled(&thumbnail.surface, thumbnail.geometry.to_physical_precise_round(scale))
+            })
+            .collect()
+    }
+}
+
+impl Aerugo {
+    pub fn show_overview(&mut self) {
+        if self.overview.is_none() {
+            tracing::debug!("Showing overview");
+            self.overview = Some(Overview::new());
+        }
+    }
+
+    pub fn hide_overview(&mut self) {
+        if self.overview.take().is_some() {
+            tracing::debug!("Hiding overview");
+        }
+    }
+
+    /// Handle a click in the overview.
+    ///
+    /// If a thumbnail was clicked, the toplevel is activated and the overview is hidden.
+    pub fn overview_click(&mut self, location: Point<f64, Logical>) {
+        let Some(overview) = self.overview.as_ref() else {
+            return;
+        };
+
+        let Some(id) = overview.hit_test(location) else {
+            return;
+        };
+
+        let surface = self.shell.get_state(id).and_then(|toplevel| toplevel.wl_surface());
+
+        if let Some(surface) = surface {
+            tracing::debug!(id, "Activating toplevel from overview");
+            self.set_keyboard_focus(Some(surface), SERIAL_COUNTER.next_serial());
+        }
+
+        // TODO: Tell the wm which toplevel was activated once toplevels are shared with the wm.
+        self.hide_overview();
+    }
+}
+
+/// Lay out windows of the specified sizes in a grid inside the area.
+///
+/// The grid is as square as possible. Each window is scaled to fit in its cell while keeping its aspect ratio,
+/// and is centered in the cell. Windows are never scaled up.
+pub fn grid_layout(
+    area: Rectangle<i32, Logical>,
+    gap: i32,
+    sizes: &[Size<i32, Logical>],
+) -> Vec<Rectangle<i32, Logical>> {
+    if sizes.is_empty() {
+        return Vec::new();
+    }
+
+    let columns = (sizes.len() as f64).sqrt().ceil() as i32;
+    let rows = (sizes.len() as i32 + columns - 1) / columns;
+
+    let cell_w = ((area.size.w - gap * (columns + 1)) / columns).max(1);
+    let cell_h = ((area.size.h - gap * (rows + 1)) / rows).max(1);
+
+    sizes
+        .iter()
+        .enumerate()
+        .map(|(index, size)| {
+            let column = index as i32 % columns;
+            let row = index as i32 / columns;
+
+            let scale = (cell_w as f64 / size.w.max(1) as f64)
+                .min(cell_h as f64 / size.h.max(1) as f64)
+                .min(1.0);
+            let w = ((size.w as f64 * scale).round() as i32).max(1);
+            let h = ((size.h as f64 * scale).round() as i32).max(1);
+
+            let cell_x = area.loc.x + gap + column * (cell_w + gap);
+            let cell_y = area.loc.y + gap + row * (cell_h + gap);
+
+            Rectangle::from_loc_and_size((cell_x + (cell_w - w) / 2, cell_y + (cell_h - h) / 2), (w, h))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::utils::{Logical, Rectangle, Size};
+
+    use super::grid_layout;
+
+    fn area() -> Rectangle<i32, Logical> {
+        Rectangle::from_loc_and_size((0, 0), (1000, 1000))
+    }
+
+    #[test]
+    fn empty() {
+        assert!(grid_layout(area(), 0, &[]).is_empty());
+    }
+
+    #[test]
+    fn square_grid() {
+        let sizes = [Size::from((500, 500)); 4];
+        let cells = grid_layout(area(), 0, &sizes);
+
+        assert_eq!(
+            cells,
+            [
+                Rectangle::from_loc_and_size((0, 0), (500, 500)),
+                Rectangle::from_loc_and_size((500, 0), (500, 500)),
+                Rectangle::from_loc_and_size((0, 500), (500, 500)),
+                Rectangle::from_loc_and_size((500, 500), (500, 500)),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_aspect_ratio() {
+        let cells = grid_layout(area(), 0, &[Size::from((2000, 1000))]);
+
+        // Scaled down by half and centered vertically.
+        assert_eq!(cells, [Rectangle::from_loc_and_size((0, 250), (1000, 500))]);
+    }
+
+    #[test]
+    fn never_scales_up() {
+        let cells = grid_layout(area(), 0, &[Size::from((100, 100))]);
+        assert_eq!(cells, [Rectangle::from_loc_and_size((450, 450), (100, 100))]);
+    }
+}
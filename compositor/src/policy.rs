@@ -0,0 +1,199 @@
+//! Policy deciding which privileged globals are available to a client.
+//!
+//! When a client connects, the identity of the client is read from the credentials of the socket. The
+//! identity is then matched against the rules of the [`ClientPolicy`] to decide which [`PrivilegedGlobals`]
+//! are advertised to the client.
+//!
+//! Identities are read from the kernel and `/proc`. A client may lie about its executable by being launched
+//! through another executable, so the identity is only as trustworthy as the process which started the
+//! client. Matching by systemd unit is more robust if the session is managed by systemd.
+
+use std::{
+    fs, io,
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+};
+
+use crate::state::PrivilegedGlobals;
+
+/// The identity of a connecting client.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// The process id of the client.
+    pub pid: Option<u32>,
+
+    /// The path to the executable of the client.
+    pub executable: Option<PathBuf>,
+
+    /// The systemd unit the client is running in.
+    pub systemd_unit: Option<String>,
+}
+
+impl ClientIdentity {
+    /// Read the identity of the client connected to the other end of the stream.
+    pub fn from_stream(stream: &UnixStream) -> io::Result<Self> {
+        let credentials = rustix::net::sockopt::get_socket_peercred(stream)?;
+        let pid = credentials.pid.as_raw_nonzero().get() as u32;
+
+        Ok(Self::from_pid(pid))
+    }
+
+    /// Read the identity of a process.
+    ///
+    /// Parts of the identity which cannot be read are left as [`None`].
+    pub fn from_pid(pid: u32) -> Self {
+        let proc = Path::new("/proc").join(pid.to_string());
+        let executable = fs::read_link(proc.join("exe")).ok();
+        let systemd_unit = fs::read_to_string(proc.join("cgroup"))
+            .ok()
+            .and_then(|cgroup| systemd_unit(&cgroup));
+
+        Self {
+            pid: Some(pid),
+            executable,
+            systemd_unit,
+        }
+    }
+}
+
+/// A rule granting privileged globals to matching clients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyRule {
+    pub matcher: ClientMatcher,
+    pub globals: PrivilegedGlobals,
+}
+
+/// How a [`PolicyRule`] matches a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientMatcher {
+    /// Match clients by the path to their executable.
+    Executable(PathBuf),
+
+    /// Match a client by process id.
+    Pid(u32),
+
+    /// Match clients running in a systemd unit (such as `waybar.service`).
+    SystemdUnit(String),
+}
+
+impl ClientMatcher {
+    pub fn matches(&self, identity: &ClientIdentity) -> bool {
+        match self {
+            ClientMatcher::Executable(path) => identity.executable.as_deref() == Some(path.as_path()),
+            ClientMatcher::Pid(pid) => identity.pid == Some(*pid),
+            ClientMatcher::SystemdUnit(unit) => identity.systemd_unit.as_deref() == Some(unit.as_str()),
+        }
+    }
+}
+
+/// Allowlist of clients which may see privileged globals.
+#[derive(Debug, Default, Clone)]
+pub struct ClientPolicy {
+    /// Globals available to every client.
+    default: PrivilegedGlobals,
+
+    rules: Vec<PolicyRule>,
+}
+
+impl ClientPolicy {
+    /// Create a policy where every client may see the specified globals.
+    pub fn new(default: PrivilegedGlobals) -> Self {
+        Self {
+            default,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Grant the globals to clients matched by the matcher.
+    pub fn allow(mut self, matcher: ClientMatcher, globals: PrivilegedGlobals) -> Self {
+        self.rules.push(PolicyRule { matcher, globals });
+        self
+    }
+
+    /// Combine the rules of both policies.
+    pub fn merge(mut self, other: ClientPolicy) -> Self {
+        self.default |= other.default;
+        self.rules.extend(other.rules);
+        self
+    }
+
+    /// The globals which are available to the client.
+    ///
+    /// The globals granted by every matching rule are combined.
+    pub fn globals(&self, identity: &ClientIdentity) -> PrivilegedGlobals {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matcher.matches(identity))
+            .fold(self.default, |globals, rule| globals | rule.globals)
+    }
+}
+
+/// Find the systemd unit from the contents of `/proc/<pid>/cgroup`.
+fn systemd_unit(cgroup: &str) -> Option<String> {
+    cgroup
+        .lines()
+        // Only the unified hierarchy is considered (`0::/path`).
+        .find_map(|line| line.strip_prefix("0::"))
+        .and_then(|path| {
+            path.rsplit('/')
+                .find(|component| component.ends_with(".service") || component.ends_with(".scope"))
+        })
+        .map(ToOwned::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::state::PrivilegedGlobals;
+
+    use super::{systemd_unit, ClientIdentity, ClientMatcher, ClientPolicy};
+
+    #[test]
+    fn unmatched_client_gets_default() {
+        let policy = ClientPolicy::new(PrivilegedGlobals::FOREIGN_TOPLEVEL_LIST)
+            .allow(ClientMatcher::Pid(42), PrivilegedGlobals::SCREENCOPY);
+
+        let identity = ClientIdentity {
+            pid: Some(7),
+            ..Default::default()
+        };
+
+        assert_eq!(policy.globals(&identity), PrivilegedGlobals::FOREIGN_TOPLEVEL_LIST);
+    }
+
+    #[test]
+    fn matching_rules_are_combined() {
+        let policy = ClientPolicy::default()
+            .allow(
+                ClientMatcher::Executable(PathBuf::from("/usr/bin/grim")),
+                PrivilegedGlobals::SCREENCOPY,
+            )
+            .allow(
+                ClientMatcher::SystemdUnit("swaylock.service".into()),
+                PrivilegedGlobals::SESSION_LOCK,
+            );
+
+        let identity = ClientIdentity {
+            pid: Some(7),
+            executable: Some(PathBuf::from("/usr/bin/grim")),
+            systemd_unit: Some("swaylock.service".into()),
+        };
+
+        assert_eq!(
+            policy.globals(&identity),
+            PrivilegedGlobals::SCREENCOPY | PrivilegedGlobals::SESSION_LOCK
+        );
+    }
+
+    #[test]
+    fn parse_systemd_unit() {
+        let cgroup = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/waybar.service\n";
+        assert_eq!(systemd_unit(cgroup).as_deref(), Some("waybar.service"));
+
+        let cgroup = "0::/user.slice/user-1000.slice/session-2.scope\n";
+        assert_eq!(systemd_unit(cgroup).as_deref(), Some("session-2.scope"));
+
+        assert_eq!(systemd_unit("0::/\n"), None);
+    }
+}
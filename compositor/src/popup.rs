@@ -0,0 +1,280 @@
+//! Popup surfaces
+//!
+//! A popup is placed relative to the window geometry of its parent surface using the positioner provided by the
+//! client. The position is constrained so the popup stays on the output, using the constraint adjustments the
+//! client allowed.
+//!
+//! The surface tree of a popup is a child of the surface tree of the parent in the scene. This presents the popup
+//! above the parent and moves the popup with the parent.
+//!
+//! A popup may take an explicit grab. Grabbing popups form a stack where the topmost popup is the most recently
+//! grabbed, and the topmost popup has keyboard focus. If keyboard focus moves to a surface outside of the stack,
+//! every grabbing popup is dismissed.
+
+use rustc_hash::FxHashMap;
+use smithay::{
+    output::Output,
+    utils::{Logical, Point, Rectangle},
+    wayland::{
+        compositor,
+        shell::xdg::{PopupSurface, PositionerState, SurfaceCachedState, XdgPopupSurfaceData},
+    },
+};
+use wayland_server::{backend::ObjectId, protocol::wl_surface::WlSurface, Resource};
+
+use crate::scene::{NodeIndex, Scene, SurfaceTreeIndex};
+
+#[derive(Debug, Default)]
+pub struct Popups {
+    /// Popups keyed by the surface of the popup.
+    popups: FxHashMap<ObjectId, Popup>,
+
+    /// Popups with an explicit grab, bottom first.
+    grab: Vec<PopupSurface>,
+}
+
+#[derive(Debug)]
+struct Popup {
+    surface: PopupSurface,
+    tree: SurfaceTreeIndex,
+
+    /// The configured geometry of the popup, relative to the window geometry of the parent.
+    geometry: Rectangle<i32, Logical>,
+}
+
+impl Popups {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking a new popup and position the popup relative to the parent.
+    pub fn new_popup(
+        &mut self,
+        scene: &mut Scene,
+        output: &Output,
+        surface: PopupSurface,
+        positioner: PositionerState,
+    ) {
+        let tree = scene.create_surface_tree(surface.wl_surface().clone());
+        let parent = surface.get_parent_surface();
+
+        match parent.clone().and_then(|parent| scene.get_surface_tree_index(parent)) {
+            Some(parent) => scene.surface_tree_add_child(parent, tree).unwrap(),
+            None => tracing::debug!("Parent of popup is not in the scene"),
+        }
+
+        let geometry = constrain(scene, output, parent.as_ref(), &positioner);
+        surface.with_pending_state(|state| {
+            state.geometry = geometry;
+            state.positioner = positioner;
+        });
+
+        self.popups.insert(
+            surface.wl_surface().id(),
+            Popup {
+                surface,
+                tree,
+                geometry,
+            },
+        );
+    }
+
+    /// Handle a commit of a popup surface.
+    ///
+    /// The initial configure is sent after the initial commit, and the popup is moved if the window geometry of
+    /// the popup changed.
+    pub fn commit(&mut self, scene: &mut Scene, scale: f64, surface: &WlSurface) {
+        let Some(popup) = self.popups.get(&surface.id()) else {
+            return;
+        };
+
+        let initial_configure_sent = compositor::with_states(surface, |states| {
+            states
+                .data_map
+                .get::<XdgPopupSurfaceData>()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .initial_configure_sent
+        });
+
+        if !initial_configure_sent {
+            // The only error is the parent being destroyed, in which case the popup is dismissed anyways.
+            let _ = popup.surface.send_configure();
+        }
+
+        let parent = popup
+            .surface
+            .get_parent_surface()
+            .map_or_else(Point::default, |parent| window_geometry(&parent));
+        let offset = popup_offset(popup.geometry, parent, window_geometry(surface));
+
+        scene.set_node_offset(
+            NodeIndex::SurfaceTree(popup.tree),
+            offset.to_physical_precise_round(scale),
+        );
+    }
+
+    /// Position the popup using a new positioner.
+    pub fn reposition(
+        &mut self,
+        scene: &Scene,
+        output: &Output,
+        surface: &PopupSurface,
+        positioner: PositionerState,
+        token: u32,
+    ) {
+        let Some(popup) = self.popups.get_mut(&surface.wl_surface().id()) else {
+            return;
+        };
+
+        let geometry = constrain(scene, output, surface.get_parent_surface().as_ref(), &positioner);
+        popup.geometry = geometry;
+        surface.with_pending_state(|state| {
+            state.geometry = geometry;
+            state.positioner = positioner;
+        });
+
+        // The new position is applied when the client commits after the configure.
+        surface.send_repositioned(token);
+        let _ = surface.send_configure();
+    }
+
+    /// Add a popup to the grab stack.
+    ///
+    /// The parent of the popup must be the topmost grabbing popup, or a toplevel if there is no grab. Otherwise
+    /// the popup is dismissed immediately and `false` is returned.
+    pub fn grab(&mut self, surface: &PopupSurface) -> bool {
+        let parent = surface.get_parent_surface();
+
+        let valid = match self.grab.last() {
+            Some(top) => parent.as_ref() == Some(top.wl_surface()),
+            None => parent.map_or(false, |parent| !self.popups.contains_key(&parent.id())),
+        };
+
+        if !valid {
+            tracing::debug!("Dismissing popup: grab is not on top of the grab stack");
+            surface.send_popup_done();
+            return false;
+        }
+
+        self.grab.push(surface.clone());
+        true
+    }
+
+    /// Dismiss every grabbing popup if keyboard focus left the grab.
+    pub fn focus_changed(&mut self, focused: Option<&WlSurface>) {
+        let in_grab = focused.map_or(false, |focused| {
+            self.grab.iter().any(|popup| popup.wl_surface() == focused)
+        });
+
+        if !in_grab {
+            self.dismiss();
+        }
+    }
+
+    /// Dismiss every grabbing popup, topmost first.
+    pub fn dismiss(&mut self) {
+        for popup in self.grab.drain(..).rev() {
+            popup.send_popup_done();
+        }
+    }
+
+    /// Stop tracking a destroyed popup.
+    ///
+    /// If the popup was grabbing, the surface which should receive keyboard focus is returned.
+    pub fn remove(&mut self, scene: &mut Scene, surface: &WlSurface) -> Option<WlSurface> {
+        if let Some(popup) = self.popups.remove(&surface.id()) {
+            scene.destroy_surface_tree(popup.tree);
+        }
+
+        let position = self.grab.iter().position(|popup| popup.wl_surface() == surface)?;
+
+        // Popups above the destroyed popup must have been destroyed first.
+        let popup = self.grab.drain(position..).next().unwrap();
+        popup.get_parent_surface()
+    }
+}
+
+/// The geometry of a popup, constrained to the output of the parent.
+fn constrain(
+    scene: &Scene,
+    output: &Output,
+    parent: Option<&WlSurface>,
+    positioner: &PositionerState,
+) -> Rectangle<i32, Logical> {
+    let Some(parent) = parent else {
+        return positioner.get_geometry();
+    };
+
+    let scale = output.current_scale().fractional_scale();
+    let parent_location = scene
+        .get_surface_tree_index(parent.clone())
+        .map(|tree| {
+            scene
+                .node_location(NodeIndex::SurfaceTree(tree))
+                .to_f64()
+                .to_logical(scale)
+                .to_i32_round()
+        })
+        .unwrap_or_default();
+
+    // The positioner is relative to the window geometry of the parent.
+    let mut target = output_geometry(output);
+    target.loc -= parent_location + window_geometry(parent);
+
+    positioner.get_unconstrained_geometry(target)
+}
+
+/// The offset of the popup surface from the parent surface.
+fn popup_offset(
+    geometry: Rectangle<i32, Logical>,
+    parent_geometry: Point<i32, Logical>,
+    window_geometry: Point<i32, Logical>,
+) -> Point<i32, Logical> {
+    // The geometry is relative to the window geometry of the parent and places the window geometry of the popup.
+    parent_geometry + geometry.loc - window_geometry
+}
+
+/// The location of the window geometry of a surface.
+fn window_geometry(surface: &WlSurface) -> Point<i32, Logical> {
+    compositor::with_states(surface, |states| {
+        states
+            .cached_state
+            .current::<SurfaceCachedState>()
+            .geometry
+            .map_or_else(Point::default, |geometry| geometry.loc)
+    })
+}
+
+/// The geometry of an output in the global compositor space.
+pub(crate) fn output_geometry(output: &Output) -> Rectangle<i32, Logical> {
+    let size = output
+        .current_mode()
+        .map(|mode| {
+            output
+                .current_transform()
+                .transform_size(mode.size)
+                .to_f64()
+                .to_logical(output.current_scale().fractional_scale())
+                .to_i32_round()
+        })
+        .unwrap_or_default();
+
+    Rectangle::from_loc_and_size(output.current_location(), size)
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::utils::Rectangle;
+
+    use super::popup_offset;
+
+    #[test]
+    fn offset_accounts_for_window_geometry() {
+        // A popup at (10, 20) from the window geometry of the parent, where both surfaces have a shadow.
+        let geometry = Rectangle::from_loc_and_size((10, 20), (100, 100));
+        let offset = popup_offset(geometry, (8, 8).into(), (4, 4).into());
+        assert_eq!(offset, (14, 24).into());
+    }
+}
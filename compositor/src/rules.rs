@@ -0,0 +1,206 @@
+//! Window rules
+//!
+//! Window rules let the user override how toplevels are decorated. A rule matches toplevels by app id and
+//! title. Every matching rule is applied in order, so a later rule overrides the properties set by an earlier
+//! rule.
+//!
+//! The decoration mode of a rule is enforced by the compositor in every configure, regardless of the mode the
+//! wm requested. The border and title bar style are passed to the wm, which draws the decorations.
+//!
+//! ```toml
+//! [[rules]]
+//! app_id = "org.gnome.Nautilus"
+//! decorations = "server"
+//! border_width = 2
+//! border_color = "#333333"
+//! focused_border_color = "#3584e4"
+//! title_bar = false
+//! ```
+
+use std::fmt;
+
+use serde::Deserialize;
+use wm_runtime::{DecorationMode, DecorationStyle};
+
+/// A rule applied to matching toplevels.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowRule {
+    /// The app id the toplevel must have.
+    ///
+    /// If not set, the rule matches every app id.
+    pub app_id: Option<String>,
+
+    /// The title the toplevel must have.
+    ///
+    /// If not set, the rule matches every title.
+    pub title: Option<String>,
+
+    /// Force the toplevel to use server or client side decorations.
+    pub decorations: Option<ForcedDecorations>,
+
+    /// Width of the border in logical pixels.
+    pub border_width: Option<u32>,
+
+    /// Color of the border while the toplevel is not focused.
+    pub border_color: Option<Color>,
+
+    /// Color of the border while the toplevel is focused.
+    pub focused_border_color: Option<Color>,
+
+    /// Whether a title bar is drawn.
+    pub title_bar: Option<bool>,
+}
+
+impl WindowRule {
+    pub fn matches(&self, app_id: Option<&str>, title: Option<&str>) -> bool {
+        let matches = |expected: &Option<String>, value: Option<&str>| {
+            expected.as_deref().map_or(true, |expected| Some(expected) == value)
+        };
+
+        matches(&self.app_id, app_id) && matches(&self.title, title)
+    }
+}
+
+/// The decoration overrides of the rules matching a toplevel.
+///
+/// Properties which are not set are left to the client and wm.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Decorations {
+    pub mode: Option<ForcedDecorations>,
+    pub border_width: Option<u32>,
+    pub border_color: Option<Color>,
+    pub focused_border_color: Option<Color>,
+    pub title_bar: Option<bool>,
+}
+
+impl Decorations {
+    /// Apply the properties set by a rule on top of these.
+    fn merge(&mut self, rule: &WindowRule) {
+        self.mode = rule.decorations.or(self.mode);
+        self.border_width = rule.border_width.or(self.border_width);
+        self.border_color = rule.border_color.or(self.border_color);
+        self.focused_border_color = rule.focused_border_color.or(self.focused_border_color);
+        self.title_bar = rule.title_bar.or(self.title_bar);
+    }
+
+    /// The decoration mode which must be used instead of the mode the wm configured.
+    pub fn forced_mode(&self) -> Option<DecorationMode> {
+        self.mode.map(|mode| match mode {
+            ForcedDecorations::Server => DecorationMode::ServerSide,
+            ForcedDecorations::Client => DecorationMode::ClientSide,
+        })
+    }
+
+    /// The style passed to the wm.
+    pub fn style(&self) -> DecorationStyle {
+        DecorationStyle {
+            border_width: self.border_width,
+            border_color: self.border_color.map(|color| color.0),
+            focused_border_color: self.focused_border_color.map(|color| color.0),
+            title_bar: self.title_bar,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForcedDecorations {
+    Server,
+    Client,
+}
+
+/// A color in the `#RRGGBB` or `#RRGGBBAA` format.
+///
+/// The color is stored as `0xRRGGBBAA`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color(pub u32);
+
+impl TryFrom<String> for Color {
+    type Error = InvalidColor;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let hex = value
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6 || hex.len() == 8)
+            .ok_or_else(|| InvalidColor(value.clone()))?;
+        let color = u32::from_str_radix(hex, 16).map_err(|_| InvalidColor(value.clone()))?;
+
+        // Colors without alpha are opaque.
+        Ok(Self(if hex.len() == 6 { color << 8 | 0xFF } else { color }))
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidColor(String);
+
+impl fmt::Display for InvalidColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid color \"{}\", expected #RRGGBB or #RRGGBBAA", self.0)
+    }
+}
+
+/// The decoration overrides of every rule matching a toplevel.
+pub fn resolve(rules: &[WindowRule], app_id: Option<&str>, title: Option<&str>) -> Decorations {
+    rules
+        .iter()
+        .filter(|rule| rule.matches(app_id, title))
+        .fold(Decorations::default(), |mut decorations, rule| {
+            decorations.merge(rule);
+            decorations
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve, Color, ForcedDecorations, WindowRule};
+
+    #[test]
+    fn parse_color() {
+        assert_eq!(Color::try_from("#3584e4".to_string()).unwrap(), Color(0x3584E4FF));
+        assert_eq!(Color::try_from("#3584e480".to_string()).unwrap(), Color(0x3584E480));
+        assert!(Color::try_from("3584e4".to_string()).is_err());
+        assert!(Color::try_from("#3584".to_string()).is_err());
+        assert!(Color::try_from("#zzzzzz".to_string()).is_err());
+    }
+
+    #[test]
+    fn later_rules_override() {
+        let rules = [
+            WindowRule {
+                decorations: Some(ForcedDecorations::Server),
+                border_width: Some(2),
+                ..Default::default()
+            },
+            WindowRule {
+                app_id: Some("foot".into()),
+                border_width: Some(0),
+                title_bar: Some(false),
+                ..Default::default()
+            },
+        ];
+
+        let foot = resolve(&rules, Some("foot"), None);
+        assert_eq!(foot.mode, Some(ForcedDecorations::Server));
+        assert_eq!(foot.border_width, Some(0));
+        assert_eq!(foot.title_bar, Some(false));
+
+        let other = resolve(&rules, Some("firefox"), Some("Mozilla Firefox"));
+        assert_eq!(other.border_width, Some(2));
+        assert_eq!(other.title_bar, None);
+    }
+
+    #[test]
+    fn match_title() {
+        let rule = WindowRule {
+            app_id: Some("firefox".into()),
+            title: Some("Picture-in-Picture".into()),
+            ..Default::default()
+        };
+
+        assert!(rule.matches(Some("firefox"), Some("Picture-in-Picture")));
+        assert!(!rule.matches(Some("firefox"), Some("Mozilla Firefox")));
+        assert!(!rule.matches(None, Some("Picture-in-Picture")));
+    }
+}
@@ -2,9 +2,15 @@
 //!
 //! TODO: Documentation
 
-use std::ops::{Deref, DerefMut};
+use std::{
+    fmt::Write as _,
+    ops::{Deref, DerefMut},
+    time::Instant,
+};
 
 use rustc_hash::FxHashMap;
+use serde::Serialize;
+use slotmap::Key;
 use smithay::{
     backend::renderer::{
         element::{AsRenderElements, Element, Id, RenderElement, UnderlyingStorage},
@@ -12,12 +18,16 @@ use smithay::{
         Frame, ImportAll, Renderer,
     },
     output::Output,
-    utils::{Buffer, Physical, Point, Rectangle, Scale, Transform},
-    wayland::compositor,
+    utils::{Buffer, Logical, Physical, Point, Rectangle, Scale, Size, Transform},
+    wayland::compositor::{self, SurfaceAttributes, SurfaceData},
 };
 use wayland_server::{backend::ObjectId, protocol::wl_surface, Resource};
 
-use crate::forest::{Error, Forest, Index};
+use crate::{
+    animation::{Animation, Animations, Keyframe},
+    color::ColorConversion,
+    forest::{Error, Forest, Index, Node},
+};
 
 /// A stable index to reference an [`OutputNode`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -35,10 +45,15 @@ pub struct SurfaceIndex(Index);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BranchIndex(Index);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A stable index to reference a [`SolidColorNode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SolidColorIndex(Index);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum NodeIndex {
     SurfaceTree(SurfaceTreeIndex),
     Branch(BranchIndex),
+    SolidColor(SolidColorIndex),
 }
 
 impl PartialEq<SurfaceTreeIndex> for NodeIndex {
@@ -53,6 +68,12 @@ impl PartialEq<BranchIndex> for NodeIndex {
     }
 }
 
+impl PartialEq<SolidColorIndex> for NodeIndex {
+    fn eq(&self, other: &SolidColorIndex) -> bool {
+        Self::SolidColor(*other) == *self
+    }
+}
+
 #[derive(Debug)]
 pub struct OutputNode {
     index: OutputIndex,
@@ -79,6 +100,7 @@ pub struct SurfaceTreeNode {
     top: SurfaceIndex,
     /// The offset of the root surface from the parent.
     offset: Point<i32, Physical>,
+    properties: NodeProperties,
 }
 
 impl SurfaceTreeNode {
@@ -120,6 +142,75 @@ pub struct SurfaceNode {
 pub struct BranchNode {
     index: BranchIndex,
     offset: Point<i32, Physical>,
+    properties: NodeProperties,
+}
+
+/// A rectangle filled with a single color.
+///
+/// Solid color nodes are drawn by the renderer without a buffer, which makes them cheap backgrounds and dimming
+/// overlays.
+#[derive(Debug)]
+pub struct SolidColorNode {
+    index: SolidColorIndex,
+
+    /// The id of the element presenting the node, so damage is tracked across frames.
+    id: Id,
+    offset: Point<i32, Physical>,
+    size: Size<i32, Physical>,
+
+    /// The color, which is not premultiplied.
+    color: [f32; 4],
+
+    /// Incremented whenever the color or size changes.
+    commit: CommitCounter,
+    properties: NodeProperties,
+}
+
+impl SolidColorNode {
+    pub fn index(&self) -> SolidColorIndex {
+        self.index
+    }
+
+    pub fn size(&self) -> Size<i32, Physical> {
+        self.size
+    }
+
+    pub fn color(&self) -> [f32; 4] {
+        self.color
+    }
+}
+
+/// Properties of a node which also apply to the descendants of the node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeProperties {
+    /// The scale of the node around the origin of the node.
+    pub scale: f64,
+
+    /// Rotation and flipping of the node around the origin of the node.
+    ///
+    /// Rotations are counter-clockwise, the same as the transform of an output.
+    pub transform: Transform,
+
+    /// The opacity of the node, from 0.0 to 1.0.
+    ///
+    /// The opacity is multiplied with the opacity of the ancestors of the node.
+    pub opacity: f32,
+
+    /// Only the part of the node inside the clip is presented.
+    ///
+    /// The clip is relative to the origin of the node, before the node is scaled and transformed.
+    pub clip: Option<Rectangle<i32, Physical>>,
+}
+
+impl Default for NodeProperties {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            transform: Transform::Normal,
+            opacity: 1.0,
+            clip: None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -128,15 +219,66 @@ pub struct Scene {
     surface_trees: FxHashMap<ObjectId, SurfaceTreeIndex>,
     surfaces: FxHashMap<ObjectId, SurfaceIndex>,
     forest: Forest<SceneNode>,
+
+    /// Surfaces placed above the content of the wm, such as shell surfaces.
+    overlay: BranchIndex,
+
+    /// Drag icons, which are placed above the overlay and follow the pointer.
+    drag_icons: BranchIndex,
+
+    animations: Animations,
 }
 
 impl Scene {
     pub fn new() -> Self {
+        let mut forest = Forest::new();
+        let overlay = BranchIndex(forest.insert_with(|index| {
+            SceneNode::Branch(BranchNode {
+                index: BranchIndex(index),
+                offset: (0, 0).into(),
+                properties: NodeProperties::default(),
+            })
+        }));
+        let drag_icons = BranchIndex(forest.insert_with(|index| {
+            SceneNode::Branch(BranchNode {
+                index: BranchIndex(index),
+                offset: (0, 0).into(),
+                properties: NodeProperties::default(),
+            })
+        }));
+
         Self {
             outputs: FxHashMap::default(),
             surface_trees: FxHashMap::default(),
             surfaces: FxHashMap::default(),
-            forest: Forest::new(),
+            forest,
+            overlay,
+            drag_icons,
+            animations: Animations::new(),
+        }
+    }
+
+    /// The branch of surfaces presented above the content of the wm.
+    pub fn overlay(&self) -> BranchIndex {
+        self.overlay
+    }
+
+    pub fn get_overlay(&self) -> Hierarchy<'_> {
+        Hierarchy {
+            scene: self,
+            root: NodeIndex::Branch(self.overlay),
+        }
+    }
+
+    /// The branch of drag icons, which is presented above the overlay.
+    pub fn drag_icons(&self) -> BranchIndex {
+        self.drag_icons
+    }
+
+    pub fn get_drag_icons(&self) -> Hierarchy<'_> {
+        Hierarchy {
+            scene: self,
+            root: NodeIndex::Branch(self.drag_icons),
         }
     }
 
@@ -157,11 +299,17 @@ impl Scene {
         // Disassociating the output from child surfaces needs to occur before we destroy the node.
         self.unset_output_root(output);
 
+        // Nodes attached to the output are owned by whoever created them, so they are kept as roots.
         if let Some(OutputIndex(index)) = self.outputs.remove(output) {
-            let _ = self.forest.remove(index);
+            let _ = self.forest.remove_promote(index);
         }
     }
 
+    /// All outputs in the scene.
+    pub fn outputs(&self) -> impl Iterator<Item = &Output> {
+        self.outputs.keys()
+    }
+
     pub fn get_output_index(&self, output: &Output) -> Option<OutputIndex> {
         self.outputs.get(output).cloned()
     }
@@ -191,6 +339,12 @@ impl Scene {
         // TODO: Send enter and exit events
     }
 
+    /// The node presented on the output, if any.
+    pub fn output_node(&self, output: &Output) -> Option<NodeIndex> {
+        let index = self.get_output_index(output)?;
+        self.get_output(index)?.present
+    }
+
     pub fn get_surface_tree_index(&self, surface: wl_surface::WlSurface) -> Option<SurfaceTreeIndex> {
         self.surface_trees.get(&surface.id()).cloned()
     }
@@ -219,16 +373,86 @@ impl Scene {
                 base: root,
                 top: root,
                 offset: Default::default(),
+                properties: NodeProperties::default(),
             })
         }));
 
         self.forest.add_child(index.0, root.0).unwrap();
+        self.surface_trees.insert(surface.id(), index);
+        self.surfaces.insert(surface.id(), root);
 
         // Initialize the surface tree
         self.apply_surface_commit(&surface);
         index
     }
 
+    /// Add a surface tree as a child of another surface tree.
+    ///
+    /// The child tree is presented above the surfaces of the parent tree and is positioned relative to the parent
+    /// tree. This is used for popups, which move with their parent.
+    pub fn surface_tree_add_child(&mut self, parent: SurfaceTreeIndex, child: SurfaceTreeIndex) -> Result<(), Error> {
+        self.forest.add_child(parent.into(), child.into())
+    }
+
+    /// The location of a node relative to the root of the tree containing the node.
+    pub fn node_location(&self, index: NodeIndex) -> Point<i32, Physical> {
+        let mut location = Point::default();
+        let mut current = Some(Index::from(index));
+
+        while let Some(node) = current.and_then(|index| self.forest.get(index)) {
+            location += node.offset();
+            current = Node::parent(node);
+        }
+
+        location
+    }
+
+    /// Whether the node is the ancestor node or a descendant of the ancestor node.
+    pub fn is_within(&self, index: NodeIndex, ancestor: NodeIndex) -> bool {
+        let ancestor = Index::from(ancestor);
+        let mut current = Some(Index::from(index));
+
+        while let Some(node) = current.and_then(|index| self.forest.get(index)) {
+            if Node::index(node) == ancestor {
+                return true;
+            }
+
+            current = Node::parent(node);
+        }
+
+        false
+    }
+
+    /// The outputs presenting the node, either directly or through an ancestor of the node.
+    pub fn node_outputs(&self, index: NodeIndex) -> Vec<Output> {
+        let mut ancestors = Vec::new();
+        let mut current = Some(Index::from(index));
+
+        while let Some(node) = current.and_then(|index| self.forest.get(index)) {
+            ancestors.push(Node::index(node));
+            current = Node::parent(node);
+        }
+
+        self.outputs
+            .keys()
+            .filter(|output| {
+                self.output_node(output)
+                    .map_or(false, |present| ancestors.contains(&present.into()))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// All surfaces in the scene, including surfaces which are not presented.
+    pub fn all_surfaces(&self) -> impl Iterator<Item = &wl_surface::WlSurface> {
+        self.surfaces
+            .values()
+            .filter_map(|index| match self.forest.get(index.0)?.deref() {
+                SceneNode::Surface(node) => Some(&node.surface),
+                _ => None,
+            })
+    }
+
     pub fn get_surface_index(&self, surface: wl_surface::WlSurface) -> Option<SurfaceIndex> {
         self.surfaces.get(&surface.id()).cloned()
     }
@@ -247,13 +471,41 @@ impl Scene {
         // TODO: Do we need a commit state to apply since we are transaction based?
     }
 
-    // TODO: Surface destroyed (for both tree and surface)
+    /// Remove a surface tree and the surfaces in the tree from the scene.
+    pub fn destroy_surface_tree(&mut self, index: SurfaceTreeIndex) {
+        if self.get_surface_tree(index).is_none() {
+            return;
+        }
+
+        // Child surface trees, such as popups, are destroyed with their own surfaces.
+        let children = self.forest.children(index.0).collect::<Vec<_>>();
+
+        for child in children {
+            if matches!(
+                self.forest.get(child).map(Deref::deref),
+                Some(SceneNode::SurfaceTree(_))
+            ) {
+                self.forest.detach(child).unwrap();
+            }
+        }
+
+        self.animations.stop(index.0);
+
+        // The subsurfaces are removed with the tree instead of becoming roots.
+        for node in self.forest.remove_subtree(index.0).unwrap() {
+            if let SceneNode::Surface(node) = node {
+                self.surfaces.remove(&node.surface.id());
+                self.surface_trees.remove(&node.surface.id());
+            }
+        }
+    }
 
     pub fn create_branch(&mut self) -> BranchIndex {
         BranchIndex(self.forest.insert_with(|index| {
             SceneNode::Branch(BranchNode {
                 index: BranchIndex(index),
                 offset: (0, 0).into(),
+                properties: NodeProperties::default(),
             })
         }))
     }
@@ -269,7 +521,49 @@ impl Scene {
         self.forest.add_child(branch.into(), index.into())
     }
 
+    /// Remove a branch from the scene.
+    ///
+    /// The children of the branch take the place of the branch in its parent.
     pub fn destroy_branch(&mut self, index: BranchIndex) {
+        self.unset_presented(NodeIndex::Branch(index));
+        self.animations.stop(index.into());
+        let _ = self.forest.remove_promote(index.into());
+    }
+
+    /// Take a node out of the scene without destroying the node.
+    ///
+    /// The node is no longer presented until the node is placed in the scene again.
+    pub fn detach_node(&mut self, index: NodeIndex) {
+        self.unset_presented(index);
+        let _ = self.forest.detach(index.into());
+    }
+
+    /// Create a rectangle filled with a color, which is not premultiplied.
+    pub fn create_solid_color(&mut self, size: Size<i32, Physical>, color: [f32; 4]) -> SolidColorIndex {
+        SolidColorIndex(self.forest.insert_with(|index| {
+            SceneNode::SolidColor(SolidColorNode {
+                index: SolidColorIndex(index),
+                id: Id::new(),
+                offset: (0, 0).into(),
+                size,
+                color,
+                commit: CommitCounter::default(),
+                properties: NodeProperties::default(),
+            })
+        }))
+    }
+
+    pub fn get_solid_color(&mut self, index: SolidColorIndex) -> Option<&mut SolidColorNode> {
+        self.forest.get_mut(index.0).map(|node| match node.deref_mut() {
+            SceneNode::SolidColor(node) => node,
+            _ => unreachable!(),
+        })
+    }
+
+    /// Remove a solid color node from the scene.
+    pub fn destroy_solid_color(&mut self, index: SolidColorIndex) {
+        self.unset_presented(NodeIndex::SolidColor(index));
+        self.animations.stop(index.into());
         let _ = self.forest.remove(index.into());
     }
 
@@ -287,6 +581,26 @@ impl Scene {
                     branch.offset = offset;
                 }
             }
+
+            NodeIndex::SolidColor(index) => {
+                if let Some(solid_color) = self.get_solid_color(index) {
+                    solid_color.offset = offset;
+                }
+            }
+        }
+    }
+
+    /// The properties of a node.
+    pub fn node_properties(&self, index: NodeIndex) -> Option<&NodeProperties> {
+        self.forest.get(index.into()).and_then(|node| node.properties())
+    }
+
+    fn node_properties_mut(&mut self, index: NodeIndex) -> Option<&mut NodeProperties> {
+        match self.forest.get_mut(index.into())?.deref_mut() {
+            SceneNode::SurfaceTree(node) => Some(&mut node.properties),
+            SceneNode::Branch(node) => Some(&mut node.properties),
+            SceneNode::SolidColor(node) => Some(&mut node.properties),
+            _ => None,
         }
     }
 
@@ -294,217 +608,1294 @@ impl Scene {
     ///
     /// This will cause the node to farther above the parent.
     pub fn raise_node(&mut self, index: NodeIndex) {
-        todo!()
+        let next = self.forest.get(index.into()).and_then(Node::next_sibling);
+
+        if let Some(next) = next {
+            self.forest.move_after(index.into(), next).unwrap();
+        }
     }
 
     /// Raise the node to become child node placed highest above the parent.
     pub fn raise_node_to_top(&mut self, index: NodeIndex) {
-        todo!()
+        // Later children are presented above earlier children.
+        let _ = self.forest.move_to_back(index.into());
     }
 
     /// Lower the node one node relative to other children of it's parent.
     ///
     /// This will cause the node to be closer but still above the parent node.
     pub fn lower_node(&mut self, index: NodeIndex) {
-        todo!()
+        let prev = self.forest.get(index.into()).and_then(Node::prev_sibling);
+
+        if let Some(prev) = prev {
+            self.forest.move_before(index.into(), prev).unwrap();
+        }
     }
 
     /// Lower the node to be the lowest node above it's parent.
     pub fn lower_node_to_bottom(&mut self, index: NodeIndex) {
-        todo!()
-    }
-
-    pub fn get_graph(&self, output: &Output) -> Option<Hierarchy<'_>> {
-        let output = self.get_output_index(output)?;
-        let output = self.get_output(output).unwrap();
-        Some(Hierarchy {
-            scene: self,
-            root: output.present?,
-        })
+        let _ = self.forest.move_to_front(index.into());
     }
 
-    /// Unsets the node which is the output root and sends leave events.
-    fn unset_output_root(&mut self, output: &Output) {
-        if let Some(index) = self.get_output_index(output) {
-            let node = self.get_output(index).unwrap();
+    /// Apply the operations of a transaction to the scene.
+    ///
+    /// Every operation is validated before any operation is applied, so either the whole transaction is applied
+    /// or the scene is left unchanged.
+    ///
+    /// If the transaction is animated, the changed offsets, scales and opacities are presented moving to the new
+    /// values over the duration of the animation. Otherwise animations of the changed nodes are stopped.
+    pub fn apply_transaction(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        let _span = tracing::trace_span!("apply_transaction", operations = transaction.operations.len()).entered();
+        self.validate_transaction(&transaction)?;
+
+        // The nodes whose animated properties are changed, with the keyframes before the change.
+        let mut animated = FxHashMap::<NodeIndex, Keyframe>::default();
+
+        for operation in &transaction.operations {
+            if let Operation::SetOffset { node, .. }
+            | Operation::SetScale { node, .. }
+            | Operation::SetOpacity { node, .. } = *operation
+            {
+                if let Some(keyframe) = self.keyframe(node) {
+                    animated.entry(node).or_insert(keyframe);
+                }
+            }
+        }
 
-            if let Some(_root) = node.present {
-                // TODO: Send leave events
+        for operation in transaction.operations {
+            match operation {
+                Operation::SetOffset { node, offset } => self.set_node_offset(node, offset),
+                // Later children are presented above earlier children.
+                Operation::PlaceAbove { node, sibling } => self
+                    .forest
+                    .move_after(node.into(), sibling.into())
+                    .expect("restack was validated"),
+                Operation::PlaceBelow { node, sibling } => self
+                    .forest
+                    .move_before(node.into(), sibling.into())
+                    .expect("restack was validated"),
+                Operation::Reparent { node, parent } => {
+                    self.forest.detach(node.into()).unwrap();
+                    self.forest
+                        .add_child(parent.into(), node.into())
+                        .expect("reparent was validated");
+                }
+                Operation::SetOutput { output, node } => self.set_output_node(&output, node),
+                Operation::SetScale { node, scale } => {
+                    if let Some(properties) = self.node_properties_mut(node) {
+                        properties.scale = scale;
+                    }
+                }
+                Operation::SetTransform { node, transform } => {
+                    if let Some(properties) = self.node_properties_mut(node) {
+                        properties.transform = transform;
+                    }
+                }
+                Operation::SetOpacity { node, opacity } => {
+                    if let Some(properties) = self.node_properties_mut(node) {
+                        properties.opacity = opacity;
+                    }
+                }
+                Operation::SetClip { node, clip } => {
+                    if let Some(properties) = self.node_properties_mut(node) {
+                        properties.clip = clip;
+                    }
+                }
+                Operation::SetSolidColor { node, size, color } => {
+                    if let Some(node) = self.get_solid_color(node) {
+                        node.size = size;
+                        node.color = color;
+                        node.commit.increment();
+                    }
+                }
             }
         }
-    }
-}
 
-pub struct SceneGraphElement {
-    id: Id,
-    surface: wl_surface::WlSurface,
-}
+        let now = Instant::now();
 
-impl SceneGraphElement {}
+        for (node, from) in animated {
+            match (transaction.animation, self.keyframe(node)) {
+                (Some(animation), Some(to)) => self.animations.start(node.into(), from, to, animation, now),
+                _ => self.animations.stop(node.into()),
+            }
+        }
 
-impl Element for SceneGraphElement {
-    fn id(&self) -> &Id {
-        &self.id
+        Ok(())
     }
 
-    fn current_commit(&self) -> CommitCounter {
-        compositor::with_states(&self.surface, |states| {
-            let data = states.data_map.get::<RendererSurfaceStateUserData>();
-            data.map(|d| d.borrow().current_commit())
-        })
-        .unwrap_or_default()
+    /// Advance the animations of the scene to a frame presented at the specified time.
+    pub fn advance_animations(&mut self, now: Instant) {
+        let _span = tracing::trace_span!("advance_animations").entered();
+        self.animations.advance(now);
     }
 
-    fn src(&self) -> Rectangle<f64, Buffer> {
-        compositor::with_states(&self.surface, |states| {
-            let data = states.data_map.get::<RendererSurfaceStateUserData>();
-            if let Some(data) = data {
-                let data = data.borrow();
-
-                if let Some(view) = data.view() {
-                    Some(view.src.to_buffer(
-                        // TODO: Do not hardcode these
-                        1.0,
-                        Transform::Normal,
-                        &data.buffer_size().unwrap().to_f64(),
-                    ))
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        })
-        .unwrap_or_default()
+    /// Whether any node of the scene is animated.
+    pub fn is_animating(&self) -> bool {
+        self.animations.is_animating()
     }
 
-    fn geometry(&self, _scale: Scale<f64>) -> Rectangle<i32, Physical> {
-        let size = compositor::with_states(&self.surface, |states| {
-            let data = states.data_map.get::<RendererSurfaceStateUserData>();
-            data.and_then(|d| d.borrow().view()).map(|surface_view| {
-                (surface_view.dst.to_f64().to_physical(1.0).to_point())
-                    .to_i32_round()
-                    .to_size()
-            })
-        })
-        .unwrap_or_default();
+    /// The animated properties of a node after the last applied transaction.
+    fn keyframe(&self, index: NodeIndex) -> Option<Keyframe> {
+        let node = self.forest.get(index.into())?;
+        let properties = node.properties()?;
 
-        Rectangle::from_loc_and_size((0, 0), size)
+        Some(Keyframe {
+            offset: node.offset().to_f64(),
+            scale: properties.scale,
+            opacity: properties.opacity,
+        })
     }
-}
 
-impl<R: Renderer + ImportAll> RenderElement<R> for SceneGraphElement
-where
-    R::TextureId: 'static,
-{
-    fn draw<'a>(
-        &self,
-        frame: &mut R::Frame<'a>,
-        src: Rectangle<f64, Buffer>,
-        dst: Rectangle<i32, Physical>,
-        damage: &[Rectangle<i32, Physical>],
-    ) -> Result<(), R::Error> {
-        compositor::with_states(&self.surface, |states| {
-            let data = states.data_map.get::<RendererSurfaceStateUserData>();
-            if let Some(data) = data {
-                let data = data.borrow();
+    /// Describe the scene graph, for example to inspect why a node is not presented.
+    ///
+    /// Animated nodes are described with the offset and properties the animations end with.
+    pub fn dump(&self) -> SceneDump {
+        let mut roots = self.forest.roots().collect::<Vec<_>>();
+        roots.sort();
+
+        SceneDump {
+            roots: roots
+                .into_iter()
+                .filter_map(|index| self.dump_node(index, Point::default()))
+                .collect(),
+        }
+    }
 
-                if let Some(texture) = data.texture::<R>(frame.id()) {
-                    // TODO: data.buffer_transform is private
-                    frame.render_texture_from_to(texture, src, dst, damage, Transform::Normal, 1.0f32)?;
+    fn dump_node(&self, index: Index, parent_location: Point<i32, Physical>) -> Option<DumpNode> {
+        let node = self.forest.get(index)?;
+        let offset = node.offset();
+        let location = parent_location + offset;
+
+        let kind = match node.deref() {
+            SceneNode::Output(node) => DumpKind::Output {
+                name: node.output.name(),
+                present: node.present.map(|present| dump_id(present.into())),
+            },
+            SceneNode::SurfaceTree(_) => DumpKind::SurfaceTree,
+            SceneNode::Surface(node) => DumpKind::Surface {
+                surface: node.surface.id().to_string(),
+            },
+            SceneNode::Branch(_) => DumpKind::Branch {
+                name: if index == self.overlay.0 {
+                    Some("overlay")
+                } else if index == self.drag_icons.0 {
+                    Some("drag_icons")
                 } else {
-                    dbg!("Not available");
-                    // warn!("trying to render texture from different renderer");
-                }
-            }
+                    None
+                },
+            },
+            SceneNode::SolidColor(node) => DumpKind::SolidColor {
+                size: (node.size.w, node.size.h),
+                color: node.color,
+            },
+        };
 
-            Ok(())
+        Some(DumpNode {
+            id: dump_id(index),
+            kind,
+            offset: (offset.x, offset.y),
+            location: (location.x, location.y),
+            properties: node.properties().map(|properties| DumpProperties {
+                scale: properties.scale,
+                transform: format!("{:?}", properties.transform),
+                opacity: properties.opacity,
+                clip: properties
+                    .clip
+                    .map(|clip| [clip.loc.x, clip.loc.y, clip.size.w, clip.size.h]),
+            }),
+            children: self
+                .forest
+                .children(index)
+                .filter_map(|child| self.dump_node(child, location))
+                .collect(),
         })
     }
 
-    fn underlying_storage(&self, _renderer: &mut R) -> Option<UnderlyingStorage> {
-        compositor::with_states(&self.surface, |states| {
-            let data = states.data_map.get::<RendererSurfaceStateUserData>();
-            data.and_then(|d| d.borrow().buffer().cloned())
-                .map(UnderlyingStorage::Wayland)
+    pub fn get_graph(&self, output: &Output) -> Option<Hierarchy<'_>> {
+        let output = self.get_output_index(output)?;
+        let output = self.get_output(output).unwrap();
+        Some(Hierarchy {
+            scene: self,
+            root: output.present?,
         })
     }
-}
-
-pub struct Hierarchy<'scene> {
-    scene: &'scene Scene,
-    root: NodeIndex,
-}
-
-impl<R: Renderer + ImportAll> AsRenderElements<R> for Hierarchy<'_>
-where
-    R::TextureId: 'static,
-{
-    type RenderElement = SceneGraphElement;
 
-    fn render_elements<C: From<Self::RenderElement>>(
-        &self,
-        renderer: &mut R,
-        _location: Point<i32, Physical>,
-        _scale: Scale<f64>,
-        _alpha: f32,
-    ) -> Vec<C> {
-        let Some(iter) = self.scene.forest.dfs_descend(self.root.into()) else {
-            return Vec::new();
+    fn validate_transaction(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        // Parents changed by earlier operations in the transaction.
+        let mut parents = FxHashMap::<Index, Index>::default();
+        let parent_of = |parents: &FxHashMap<Index, Index>, index: Index| {
+            parents
+                .get(&index)
+                .copied()
+                .or_else(|| self.forest.get(index).and_then(Node::parent))
         };
 
-        // Determine the final offset of the indices because smithay expects the render elements top to bottom.
-        let final_offset: Point<i32, Physical> = iter.clone().fold((0, 0).into(), |mut offset, index| {
-            match self.scene.forest.get(index).unwrap().deref() {
-                SceneNode::Output(_) => unreachable!(),
-                SceneNode::SurfaceTree(node) => offset += node.offset,
-                SceneNode::Surface(node) => offset += node.offset,
-                SceneNode::Branch(node) => offset += node.offset,
-            }
+        for operation in &transaction.operations {
+            match *operation {
+                Operation::SetOffset { node, .. } => self.check_present(node.into())?,
 
-            offset
-        });
+                Operation::PlaceAbove { node, sibling } | Operation::PlaceBelow { node, sibling } => {
+                    self.check_present(node.into())?;
+                    self.check_present(sibling.into())?;
 
-        // Collect all the surfaces, subtracting from the final offset to get the expected offset.
-        let indices = iter.collect::<Vec<_>>();
+                    let parent = parent_of(&parents, node.into());
 
-        let mut offset = final_offset;
-        indices
-            .iter()
-            .rev()
-            .filter_map(|&index| {
-                let node = self.scene.forest.get(index)?;
-
-                match node.deref() {
-                    SceneNode::Output(_) => unreachable!(),
-                    SceneNode::SurfaceTree(node) => {
-                        offset -= node.offset;
-                        None
+                    if node == sibling || parent.is_none() || parent != parent_of(&parents, sibling.into()) {
+                        return Err(TransactionError::NotSiblings);
                     }
+                }
 
-                    SceneNode::Surface(node) => {
-                        smithay::backend::renderer::utils::import_surface_tree(renderer, &node.surface)
-                            .expect("Failed to import");
+                Operation::Reparent { node, parent } => {
+                    self.check_present(node.into())?;
+                    self.check_present(parent.into())?;
 
-                        let elem = SceneGraphElement {
-                            id: Id::from_wayland_resource(&node.surface),
-                            surface: node.surface.clone(),
-                        };
+                    // The scene becomes cyclic if the node is the new parent or an ancestor of the new parent.
+                    let mut ancestor = Some(Index::from(parent));
 
-                        offset -= node.offset;
-                        Some(elem)
-                    }
+                    while let Some(index) = ancestor {
+                        if index == Index::from(node) {
+                            return Err(TransactionError::Cycle);
+                        }
 
-                    SceneNode::Branch(node) => {
-                        offset -= node.offset;
-                        None
+                        ancestor = parent_of(&parents, index);
                     }
+
+                    parents.insert(node.into(), parent.into());
                 }
-            })
-            .map(C::from)
-            .collect()
-    }
-}
+
+                Operation::SetOutput { ref output, node } => {
+                    if !self.outputs.contains_key(output) {
+                        return Err(TransactionError::UnknownOutput);
+                    }
+
+                    self.check_present(node.into())?;
+                }
+
+                Operation::SetScale { node, scale } => {
+                    self.check_present(node.into())?;
+
+                    if !(scale.is_finite() && scale > 0.0) {
+                        return Err(TransactionError::InvalidProperty);
+                    }
+                }
+
+                Operation::SetOpacity { node, opacity } => {
+                    self.check_present(node.into())?;
+
+                    if !(0.0..=1.0).contains(&opacity) {
+                        return Err(TransactionError::InvalidProperty);
+                    }
+                }
+
+                Operation::SetTransform { node, .. } | Operation::SetClip { node, .. } => {
+                    self.check_present(node.into())?
+                }
+
+                Operation::SetSolidColor { node, size, color } => {
+                    self.check_present(node.into())?;
+
+                    if size.w < 0 || size.h < 0 || !color.iter().all(|channel| (0.0..=1.0).contains(channel)) {
+                        return Err(TransactionError::InvalidProperty);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_present(&self, index: Index) -> Result<(), TransactionError> {
+        if !self.forest.contains_index(index) {
+            return Err(TransactionError::NotPresent);
+        }
+
+        Ok(())
+    }
+
+    /// Stop presenting a node which is removed from the scene on every output presenting the node.
+    fn unset_presented(&mut self, index: NodeIndex) {
+        for &OutputIndex(output) in self.outputs.values() {
+            if let Some(SceneNode::Output(node)) = self.forest.get_mut(output).map(|node| node.deref_mut()) {
+                if node.present == Some(index) {
+                    node.present = None;
+                }
+            }
+        }
+    }
+
+    /// Unsets the node which is the output root and sends leave events.
+    fn unset_output_root(&mut self, output: &Output) {
+        if let Some(index) = self.get_output_index(output) {
+            let node = self.get_output(index).unwrap();
+
+            if let Some(_root) = node.present {
+                // TODO: Send leave events
+            }
+        }
+    }
+}
+
+/// A description of the scene graph, see [`Scene::dump`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SceneDump {
+    /// The nodes without a parent, such as the outputs and the overlay.
+    pub roots: Vec<DumpNode>,
+}
+
+impl SceneDump {
+    /// The scene graph in the DOT language of Graphviz.
+    ///
+    /// The node presented on an output is connected to the output by a dashed edge.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph scene {\n    node [shape=box];\n");
+
+        for root in &self.roots {
+            root.write_dot(&mut dot);
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// A node of the scene graph and the descendants of the node.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DumpNode {
+    /// Identifies the node within the dump.
+    pub id: u64,
+
+    #[serde(flatten)]
+    pub kind: DumpKind,
+
+    /// The offset of the node relative to the parent.
+    pub offset: (i32, i32),
+
+    /// The location of the node relative to the root of the tree containing the node.
+    pub location: (i32, i32),
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<DumpProperties>,
+
+    /// The children of the node, from bottom to top.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<DumpNode>,
+}
+
+impl DumpNode {
+    fn write_dot(&self, dot: &mut String) {
+        let label = match &self.kind {
+            DumpKind::Output { name, .. } => format!("output {name}"),
+            DumpKind::SurfaceTree => "surface tree".into(),
+            DumpKind::Surface { surface } => surface.clone(),
+            DumpKind::Branch { name } => name.unwrap_or("branch").into(),
+            DumpKind::SolidColor { size, .. } => format!("solid color {}x{}", size.0, size.1),
+        };
+
+        let _ = writeln!(
+            dot,
+            "    n{} [label=\"{}\\n({}, {})\"];",
+            self.id,
+            label.replace('"', "\\\""),
+            self.location.0,
+            self.location.1
+        );
+
+        if let DumpKind::Output {
+            present: Some(present), ..
+        } = self.kind
+        {
+            let _ = writeln!(dot, "    n{} -> n{present} [style=dashed];", self.id);
+        }
+
+        for child in &self.children {
+            let _ = writeln!(dot, "    n{} -> n{};", self.id, child.id);
+            child.write_dot(dot);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DumpKind {
+    /// An output, with the id of the node presented on the output.
+    Output {
+        name: String,
+        present: Option<u64>,
+    },
+
+    SurfaceTree,
+
+    /// A surface, named by the protocol object of the surface.
+    Surface {
+        surface: String,
+    },
+
+    /// A branch, named if the branch is part of the scene itself.
+    Branch {
+        name: Option<&'static str>,
+    },
+
+    SolidColor {
+        size: (i32, i32),
+        color: [f32; 4],
+    },
+}
+
+/// The [`NodeProperties`] of a node.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DumpProperties {
+    pub scale: f64,
+    pub transform: String,
+    pub opacity: f32,
+
+    /// The clip as `[x, y, width, height]`.
+    pub clip: Option<[i32; 4]>,
+}
+
+fn dump_id(index: Index) -> u64 {
+    index.data().as_ffi()
+}
+
+/// A change to the scene made by a [`Transaction`].
+#[derive(Debug, Clone)]
+enum Operation {
+    SetOffset {
+        node: NodeIndex,
+        offset: Point<i32, Physical>,
+    },
+    PlaceAbove {
+        node: NodeIndex,
+        sibling: NodeIndex,
+    },
+    PlaceBelow {
+        node: NodeIndex,
+        sibling: NodeIndex,
+    },
+    Reparent {
+        node: NodeIndex,
+        parent: BranchIndex,
+    },
+    SetOutput {
+        output: Output,
+        node: NodeIndex,
+    },
+    SetScale {
+        node: NodeIndex,
+        scale: f64,
+    },
+    SetTransform {
+        node: NodeIndex,
+        transform: Transform,
+    },
+    SetOpacity {
+        node: NodeIndex,
+        opacity: f32,
+    },
+    SetClip {
+        node: NodeIndex,
+        clip: Option<Rectangle<i32, Physical>>,
+    },
+    SetSolidColor {
+        node: SolidColorIndex,
+        size: Size<i32, Physical>,
+        color: [f32; 4],
+    },
+}
+
+/// A set of changes to the scene which are applied atomically.
+///
+/// Operations are applied in the order they were added. This lets a wm describe a complete layout change, such
+/// as moving a window to another output and restacking the windows around it, without intermediate states
+/// being presented.
+#[derive(Debug, Default, Clone)]
+pub struct Transaction {
+    operations: Vec<Operation>,
+    animation: Option<Animation>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Animate the changes to the offset, scale and opacity of nodes made by the transaction.
+    pub fn animate(&mut self, animation: Animation) -> &mut Self {
+        self.animation = Some(animation);
+        self
+    }
+
+    /// Sets the offset of the node relative to it's parent.
+    pub fn set_offset(&mut self, node: NodeIndex, offset: Point<i32, Physical>) -> &mut Self {
+        self.operations.push(Operation::SetOffset { node, offset });
+        self
+    }
+
+    /// Place the node directly above a sibling.
+    pub fn place_above(&mut self, node: NodeIndex, sibling: NodeIndex) -> &mut Self {
+        self.operations.push(Operation::PlaceAbove { node, sibling });
+        self
+    }
+
+    /// Place the node directly below a sibling.
+    pub fn place_below(&mut self, node: NodeIndex, sibling: NodeIndex) -> &mut Self {
+        self.operations.push(Operation::PlaceBelow { node, sibling });
+        self
+    }
+
+    /// Make the node a child of a branch.
+    ///
+    /// The node is placed above the existing children of the branch.
+    pub fn reparent(&mut self, node: NodeIndex, parent: BranchIndex) -> &mut Self {
+        self.operations.push(Operation::Reparent { node, parent });
+        self
+    }
+
+    /// Present the node on an output.
+    pub fn set_output(&mut self, output: Output, node: NodeIndex) -> &mut Self {
+        self.operations.push(Operation::SetOutput { output, node });
+        self
+    }
+
+    /// Sets the scale of the node around the origin of the node.
+    ///
+    /// The scale must be positive.
+    pub fn set_scale(&mut self, node: NodeIndex, scale: f64) -> &mut Self {
+        self.operations.push(Operation::SetScale { node, scale });
+        self
+    }
+
+    /// Sets the rotation and flipping of the node around the origin of the node.
+    pub fn set_transform(&mut self, node: NodeIndex, transform: Transform) -> &mut Self {
+        self.operations.push(Operation::SetTransform { node, transform });
+        self
+    }
+
+    /// Sets the opacity of the node, from 0.0 to 1.0.
+    pub fn set_opacity(&mut self, node: NodeIndex, opacity: f32) -> &mut Self {
+        self.operations.push(Operation::SetOpacity { node, opacity });
+        self
+    }
+
+    /// Only present the part of the node inside the clip.
+    pub fn set_clip(&mut self, node: NodeIndex, clip: Option<Rectangle<i32, Physical>>) -> &mut Self {
+        self.operations.push(Operation::SetClip { node, clip });
+        self
+    }
+
+    /// Sets the size and the color of a solid color node.
+    ///
+    /// The color is not premultiplied, and every channel must be from 0.0 to 1.0.
+    pub fn set_solid_color(&mut self, node: SolidColorIndex, size: Size<i32, Physical>, color: [f32; 4]) -> &mut Self {
+        self.operations.push(Operation::SetSolidColor { node, size, color });
+        self
+    }
+}
+
+/// An error from applying a [`Transaction`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum TransactionError {
+    #[error("node is not present in the scene")]
+    NotPresent,
+
+    #[error("output is not present in the scene")]
+    UnknownOutput,
+
+    #[error("nodes do not have the same parent")]
+    NotSiblings,
+
+    #[error("reparenting would make the scene cyclic")]
+    Cycle,
+
+    #[error("the scale, opacity, size or color of a node is out of range")]
+    InvalidProperty,
+}
+
+pub struct SceneGraphElement {
+    id: Id,
+    content: Content,
+
+    /// Where the surface is presented, if the surface is scaled.
+    dst: Option<Rectangle<i32, Physical>>,
+
+    /// Location of the surface if the surface is not scaled.
+    location: Point<i32, Physical>,
+
+    /// Rotation and flipping of the surface, applied after the buffer transform of the surface.
+    transform: Transform,
+
+    /// Only the part of the surface inside the clip is presented.
+    clip: Option<Rectangle<i32, Physical>>,
+
+    alpha: f32,
+}
+
+/// What a [`SceneGraphElement`] presents.
+enum Content {
+    Surface(wl_surface::WlSurface),
+
+    /// A rectangle filled with a color, which is not premultiplied.
+    SolidColor {
+        color: [f32; 4],
+        commit: CommitCounter,
+    },
+}
+
+impl SceneGraphElement {
+    /// Create an element which presents a surface outside of the scene graph.
+    ///
+    /// The surface must have been imported by the renderer.
+    pub fn from_surface(surface: &wl_surface::WlSurface) -> Self {
+        Self {
+            id: Id::from_wayland_resource(surface),
+            content: Content::Surface(surface.clone()),
+            dst: None,
+            location: Point::default(),
+            transform: Transform::Normal,
+            clip: None,
+            alpha: 1.0,
+        }
+    }
+
+    /// Create an element which presents a surface at a location outside of the scene graph.
+    ///
+    /// The surface must have been imported by the renderer.
+    pub fn at(surface: &wl_surface::WlSurface, location: Point<i32, Physical>) -> Self {
+        Self {
+            location,
+            ..Self::from_surface(surface)
+        }
+    }
+
+    /// Create an element which presents a surface scaled to fit the destination.
+    ///
+    /// The surface must have been imported by the renderer.
+    pub fn scaled(surface: &wl_surface::WlSurface, dst: Rectangle<i32, Physical>) -> Self {
+        Self {
+            // The surface may also be presented unscaled, so the element needs a separate id.
+            id: Id::new(),
+            dst: Some(dst),
+            ..Self::from_surface(surface)
+        }
+    }
+
+    /// Create an element which presents a surface of the scene graph with the properties of it's ancestors.
+    ///
+    /// Returns [`None`] if the surface has no buffer attached.
+    fn placed(surface: &wl_surface::WlSurface, placement: &Placement, scale: Scale<f64>) -> Option<Self> {
+        let size = compositor::with_states(surface, |states| {
+            let data = states.data_map.get::<RendererSurfaceStateUserData>()?;
+            let view = data.borrow().view()?;
+            Some(view.dst.to_physical_precise_round(scale))
+        })?;
+
+        Some(Self {
+            dst: Some(placement.map_rect(Rectangle::from_loc_and_size((0, 0), size))),
+            transform: placement.transform,
+            clip: placement.clip,
+            alpha: placement.opacity,
+            ..Self::from_surface(surface)
+        })
+    }
+
+    /// Create an element which presents a solid color node of the scene graph with the properties of it's
+    /// ancestors.
+    fn solid_color(node: &SolidColorNode, placement: &Placement) -> Self {
+        Self {
+            id: node.id.clone(),
+            content: Content::SolidColor {
+                color: node.color,
+                commit: node.commit,
+            },
+            dst: Some(placement.map_rect(Rectangle::from_loc_and_size((0, 0), node.size))),
+            location: Point::default(),
+            // A solid color looks the same in every orientation.
+            transform: Transform::Normal,
+            clip: placement.clip,
+            alpha: placement.opacity,
+        }
+    }
+
+    /// Convert the color of a solid color element, which is given in sRGB, to the color state of the output.
+    pub fn convert_color(&mut self, conversion: &ColorConversion) {
+        if let Content::SolidColor { color, .. } = &mut self.content {
+            *color = conversion.apply_color(*color);
+        }
+    }
+
+    /// The surface presented by the element, if the element presents a surface.
+    fn surface(&self) -> Option<&wl_surface::WlSurface> {
+        match &self.content {
+            Content::Surface(surface) => Some(surface),
+            Content::SolidColor { .. } => None,
+        }
+    }
+
+    /// The part of the surface which is presented after clipping.
+    ///
+    /// The part is a fraction of the surface before the surface is transformed.
+    fn visible_fraction(&self) -> Rectangle<f64, Physical> {
+        let whole = Rectangle::from_loc_and_size((0.0, 0.0), (1.0, 1.0));
+
+        let (Some(dst), Some(clip)) = (self.dst, self.clip) else {
+            return whole;
+        };
+
+        if dst.is_empty() {
+            return whole;
+        }
+
+        let Some(visible) = dst.intersection(clip) else {
+            return Rectangle::default();
+        };
+
+        let fraction = |point: Point<i32, Physical>| -> Point<f64, Physical> {
+            let point = point - dst.loc;
+            (point.x as f64 / dst.size.w as f64, point.y as f64 / dst.size.h as f64).into()
+        };
+
+        // Undo the transform, which rotates and flips the surface inside of the whole surface.
+        let inverse = invert(self.transform);
+        let center = Point::<f64, Physical>::from((0.5, 0.5));
+        let [start, end] = [visible.loc, visible.loc + visible.size.to_point()]
+            .map(|point| rotate(inverse, fraction(point) - center) + center);
+
+        let loc = Point::<f64, Physical>::from((start.x.min(end.x), start.y.min(end.y)));
+        let size = ((start.x - end.x).abs(), (start.y - end.y).abs());
+        Rectangle::from_loc_and_size(loc, size)
+    }
+
+    /// The transform the renderer presents the buffer with.
+    fn buffer_transform(&self, states: &SurfaceData) -> Transform {
+        let (_, buffer_transform) = buffer_scale_transform(states);
+
+        // The renderer undoes the buffer transform, so the buffer is transformed the opposite way to present the
+        // surface with the transform of the element.
+        compose(invert(self.transform), buffer_transform)
+    }
+}
+
+impl Element for SceneGraphElement {
+    fn id(&self) -> &Id {
+        &self.id
+    }
+
+    fn current_commit(&self) -> CommitCounter {
+        let surface = match &self.content {
+            Content::Surface(surface) => surface,
+            Content::SolidColor { commit, .. } => return *commit,
+        };
+
+        compositor::with_states(surface, |states| {
+            let data = states.data_map.get::<RendererSurfaceStateUserData>();
+            data.map(|d| d.borrow().current_commit())
+        })
+        .unwrap_or_default()
+    }
+
+    fn src(&self) -> Rectangle<f64, Buffer> {
+        let Some(surface) = self.surface() else {
+            // A solid color has no buffer, so the source is the presented area.
+            let size = self.geometry(Scale::from(1.0)).size;
+            return Rectangle::from_loc_and_size((0.0, 0.0), (size.w as f64, size.h as f64));
+        };
+
+        compositor::with_states(surface, |states| {
+            let data = states.data_map.get::<RendererSurfaceStateUserData>()?;
+            let data = data.borrow();
+            let view = data.view()?;
+            let (buffer_scale, buffer_transform) = buffer_scale_transform(states);
+
+            // Only the visible part of the surface is sampled if the surface is clipped.
+            let fraction = self.visible_fraction();
+            let mut src = view.src;
+            src.loc.x += fraction.loc.x * view.src.size.w;
+            src.loc.y += fraction.loc.y * view.src.size.h;
+            src.size.w *= fraction.size.w;
+            src.size.h *= fraction.size.h;
+
+            // The view accounts for the source rectangle of a viewport.
+            Some(src.to_buffer(buffer_scale as f64, buffer_transform, &data.buffer_size()?.to_f64()))
+        })
+        .unwrap_or_default()
+    }
+
+    fn geometry(&self, scale: Scale<f64>) -> Rectangle<i32, Physical> {
+        let geometry = self.dst.unwrap_or_else(|| {
+            // Solid colors always have a destination.
+            let Some(surface) = self.surface() else {
+                return Rectangle::default();
+            };
+
+            // The destination size of the view accounts for the destination size of a viewport. Scaling the
+            // logical size rather than the buffer size keeps viewported surfaces at the right size on outputs
+            // with a fractional scale.
+            let size = compositor::with_states(surface, |states| {
+                let data = states.data_map.get::<RendererSurfaceStateUserData>();
+                data.and_then(|d| d.borrow().view())
+                    .map(|surface_view| surface_view.dst.to_physical_precise_round(scale))
+            })
+            .unwrap_or_default();
+
+            Rectangle::from_loc_and_size(self.location, size)
+        });
+
+        match self.clip {
+            Some(clip) => geometry.intersection(clip).unwrap_or_default(),
+            None => geometry,
+        }
+    }
+
+    fn transform(&self) -> Transform {
+        self.surface().map_or(Transform::Normal, |surface| {
+            compositor::with_states(surface, |states| self.buffer_transform(states))
+        })
+    }
+
+    fn alpha(&self) -> f32 {
+        self.alpha
+    }
+}
+
+impl<R: Renderer + ImportAll> RenderElement<R> for SceneGraphElement
+where
+    R::TextureId: 'static,
+{
+    fn draw<'a>(
+        &self,
+        frame: &mut R::Frame<'a>,
+        src: Rectangle<f64, Buffer>,
+        dst: Rectangle<i32, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+    ) -> Result<(), R::Error> {
+        let surface = match &self.content {
+            Content::Surface(surface) => surface,
+            Content::SolidColor { color, .. } => {
+                // The renderer expects premultiplied colors.
+                let alpha = color[3] * self.alpha;
+                return frame.draw_solid(
+                    dst,
+                    damage,
+                    [color[0] * alpha, color[1] * alpha, color[2] * alpha, alpha],
+                );
+            }
+        };
+
+        compositor::with_states(surface, |states| {
+            let data = states.data_map.get::<RendererSurfaceStateUserData>();
+            if let Some(data) = data {
+                let data = data.borrow();
+
+                if let Some(texture) = data.texture::<R>(frame.id()) {
+                    let transform = self.buffer_transform(states);
+                    frame.render_texture_from_to(texture, src, dst, damage, transform, self.alpha)?;
+                } else {
+                    dbg!("Not available");
+                    // warn!("trying to render texture from different renderer");
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    fn underlying_storage(&self, _renderer: &mut R) -> Option<UnderlyingStorage> {
+        compositor::with_states(self.surface()?, |states| {
+            let data = states.data_map.get::<RendererSurfaceStateUserData>();
+            data.and_then(|d| d.borrow().buffer().cloned())
+                .map(UnderlyingStorage::Wayland)
+        })
+    }
+}
+
+/// Where a node is presented, accumulated from the properties of the node and it's ancestors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Placement {
+    /// The location of the origin of the node.
+    origin: Point<f64, Physical>,
+    scale: f64,
+    transform: Transform,
+    opacity: f32,
+
+    /// The clip of the node and it's ancestors.
+    clip: Option<Rectangle<i32, Physical>>,
+}
+
+impl Placement {
+    fn at(location: Point<i32, Physical>) -> Self {
+        Self {
+            origin: location.to_f64(),
+            scale: 1.0,
+            transform: Transform::Normal,
+            opacity: 1.0,
+            clip: None,
+        }
+    }
+
+    /// The placement of a child node with the specified offset and properties.
+    fn child(&self, offset: Point<f64, Physical>, properties: Option<&NodeProperties>) -> Self {
+        let properties = properties.copied().unwrap_or_default();
+        let mut child = Self {
+            origin: self.map_point(offset),
+            scale: self.scale * properties.scale,
+            transform: compose(properties.transform, self.transform),
+            opacity: self.opacity * properties.opacity,
+            clip: self.clip,
+        };
+
+        if let Some(clip) = properties.clip {
+            let clip = child.map_rect(clip);
+            child.clip = Some(match self.clip {
+                Some(parent) => parent.intersection(clip).unwrap_or_default(),
+                None => clip,
+            });
+        }
+
+        child
+    }
+
+    /// Map a point relative to the node to the location the point is presented at.
+    fn map_point(&self, point: Point<f64, Physical>) -> Point<f64, Physical> {
+        self.origin + rotate(self.transform, (point.x * self.scale, point.y * self.scale).into())
+    }
+
+    /// Map a presented point back to the point relative to the node, the inverse of [`Placement::map_point`].
+    fn unmap_point(&self, point: Point<f64, Physical>) -> Point<f64, Physical> {
+        let point = rotate(invert(self.transform), point - self.origin);
+        (point.x / self.scale, point.y / self.scale).into()
+    }
+
+    /// Map a rectangle relative to the node to the area the rectangle is presented in.
+    fn map_rect(&self, rect: Rectangle<i32, Physical>) -> Rectangle<i32, Physical> {
+        let rect = rect.to_f64();
+        let start = self.map_point(rect.loc);
+        let end = self.map_point(rect.loc + rect.size.to_point());
+
+        let loc = Point::<f64, Physical>::from((start.x.min(end.x), start.y.min(end.y)));
+        let size = ((start.x - end.x).abs(), (start.y - end.y).abs());
+        Rectangle::<f64, Physical>::from_loc_and_size(loc, size).to_i32_round()
+    }
+}
+
+/// Every transform, to find the transform of a matrix.
+const TRANSFORMS: [Transform; 8] = [
+    Transform::Normal,
+    Transform::_90,
+    Transform::_180,
+    Transform::_270,
+    Transform::Flipped,
+    Transform::Flipped90,
+    Transform::Flipped180,
+    Transform::Flipped270,
+];
+
+/// The matrix rotating and flipping points around the origin by a transform.
+///
+/// Rotations are counter-clockwise on screen, where y grows downwards.
+fn transform_matrix(transform: Transform) -> [[i32; 2]; 2] {
+    match transform {
+        Transform::Normal => [[1, 0], [0, 1]],
+        Transform::_90 => [[0, 1], [-1, 0]],
+        Transform::_180 => [[-1, 0], [0, -1]],
+        Transform::_270 => [[0, -1], [1, 0]],
+        Transform::Flipped => [[-1, 0], [0, 1]],
+        Transform::Flipped90 => [[0, 1], [1, 0]],
+        Transform::Flipped180 => [[1, 0], [0, -1]],
+        Transform::Flipped270 => [[0, -1], [-1, 0]],
+    }
+}
+
+fn matrix_transform(matrix: [[i32; 2]; 2]) -> Transform {
+    TRANSFORMS
+        .into_iter()
+        .find(|&transform| transform_matrix(transform) == matrix)
+        .expect("every rotation and flip is a transform")
+}
+
+/// The transform which applies `first` and then `second`.
+fn compose(first: Transform, second: Transform) -> Transform {
+    let [a, b] = [transform_matrix(second), transform_matrix(first)];
+    let entry = |row: usize, column: usize| a[row][0] * b[0][column] + a[row][1] * b[1][column];
+    matrix_transform([[entry(0, 0), entry(0, 1)], [entry(1, 0), entry(1, 1)]])
+}
+
+/// The scale and transform of the current buffer of a surface.
+fn buffer_scale_transform(states: &SurfaceData) -> (i32, Transform) {
+    let attributes = states.cached_state.current::<SurfaceAttributes>();
+    (attributes.buffer_scale, attributes.buffer_transform.into())
+}
+
+fn invert(transform: Transform) -> Transform {
+    // Rotations and flips are orthogonal, so the inverse is the transpose.
+    let [[a, b], [c, d]] = transform_matrix(transform);
+    matrix_transform([[a, c], [b, d]])
+}
+
+fn rotate(transform: Transform, point: Point<f64, Physical>) -> Point<f64, Physical> {
+    let [[a, b], [c, d]] = transform_matrix(transform).map(|row| row.map(f64::from));
+    (a * point.x + b * point.y, c * point.x + d * point.y).into()
+}
+
+pub struct Hierarchy<'scene> {
+    scene: &'scene Scene,
+    root: NodeIndex,
+}
+
+impl Hierarchy<'_> {
+    /// Where a node is presented if the root of the hierarchy is presented at the location.
+    ///
+    /// The offsets and properties of the node and the ancestors of the node up to the root are applied. Animated
+    /// nodes use the currently presented keyframe of the animation.
+    fn placement(&self, index: Index, location: Point<i32, Physical>) -> Placement {
+        let root = Index::from(self.root);
+        let mut ancestors = Vec::new();
+        let mut current = Some(index);
+
+        while let Some(index) = current {
+            let Some(node) = self.scene.forest.get(index) else {
+                break;
+            };
+
+            ancestors.push((index, node.deref()));
+
+            if index == root {
+                break;
+            }
+
+            current = Node::parent(node);
+        }
+
+        ancestors
+            .into_iter()
+            .rev()
+            .fold(Placement::at(location), |placement, (index, node)| {
+                let mut properties = node.properties().copied();
+                let mut offset = node.offset().to_f64();
+
+                if let Some(keyframe) = self.scene.animations.current(index) {
+                    offset = keyframe.offset;
+                    properties = properties.map(|properties| NodeProperties {
+                        scale: keyframe.scale,
+                        opacity: keyframe.opacity,
+                        ..properties
+                    });
+                }
+
+                placement.child(offset, properties.as_ref())
+            })
+    }
+
+    /// Visit the surfaces of the hierarchy from top to bottom, returning the surfaces which are visible.
+    ///
+    /// Surfaces visited by the occlusion before, such as the surfaces of a hierarchy presented above this one,
+    /// occlude the surfaces of this hierarchy.
+    pub fn visible_surfaces(
+        &self,
+        location: Point<i32, Physical>,
+        occlusion: &mut Occlusion,
+    ) -> Vec<wl_surface::WlSurface> {
+        let Some(iter) = self.scene.forest.dfs_descend(self.root.into()) else {
+            return Vec::new();
+        };
+
+        let mut nodes = iter.collect::<Vec<_>>();
+        nodes.reverse();
+
+        nodes
+            .into_iter()
+            .filter_map(|index| match self.scene.forest.get(index)?.deref() {
+                SceneNode::Surface(node) => {
+                    let placement = self.placement(index, location);
+                    let geometry = SceneGraphElement::placed(&node.surface, &placement, Scale::from(1.0))
+                        .map(|element| element.geometry(Scale::from(1.0)))
+                        .unwrap_or_default();
+
+                    // Opaque regions are relative to the surface. Translucent surfaces do not occlude.
+                    let opaque = compositor::with_states(&node.surface, |states| {
+                        let data = states.data_map.get::<RendererSurfaceStateUserData>()?;
+                        let data = data.borrow();
+                        let regions = data.opaque_regions().filter(|_| placement.opacity >= 1.0)?;
+
+                        Some(
+                            regions
+                                .iter()
+                                .filter_map(|region| {
+                                    let region = placement.map_rect(region.to_physical(1));
+
+                                    match placement.clip {
+                                        Some(clip) => region.intersection(clip),
+                                        None => Some(region),
+                                    }
+                                })
+                                .collect::<Vec<_>>(),
+                        )
+                    })
+                    .unwrap_or_default();
+
+                    occlusion.visit(geometry, opaque).then(|| node.surface.clone())
+                }
+
+                // Solid colors are not surfaces, but occlude the surfaces below if the color is opaque.
+                SceneNode::SolidColor(node) => {
+                    let element = SceneGraphElement::solid_color(node, &self.placement(index, location));
+                    let geometry = element.geometry(Scale::from(1.0));
+                    let opaque = (node.color[3] >= 1.0 && element.alpha >= 1.0).then_some(geometry);
+                    occlusion.visit(geometry, opaque);
+                    None
+                }
+
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The elements of the surfaces and solid colors of the hierarchy from top to bottom.
+    ///
+    /// This is [`AsRenderElements::render_elements`] without importing the surfaces into the renderer, so the
+    /// surfaces must have been imported already.
+    pub fn elements(&self, location: Point<i32, Physical>, scale: Scale<f64>, alpha: f32) -> Vec<SceneGraphElement> {
+        let Some(iter) = self.scene.forest.dfs_descend(self.root.into()) else {
+            return Vec::new();
+        };
+
+        let mut elements = iter
+            .filter_map(|index| match self.scene.forest.get(index)?.deref() {
+                SceneNode::Surface(node) => {
+                    let mut element =
+                        SceneGraphElement::placed(&node.surface, &self.placement(index, location), scale)?;
+                    element.alpha *= alpha;
+
+                    // Surfaces which are transparent or clipped completely are not presented.
+                    (element.alpha > 0.0 && !element.geometry(scale).is_empty()).then_some(element)
+                }
+
+                SceneNode::SolidColor(node) => {
+                    let mut element = SceneGraphElement::solid_color(node, &self.placement(index, location));
+                    element.alpha *= alpha;
+                    (element.alpha > 0.0 && !element.geometry(scale).is_empty()).then_some(element)
+                }
+
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        // Smithay expects the render elements top to bottom.
+        elements.reverse();
+        elements
+    }
+
+    /// The topmost surface presented at a point, with the point relative to the surface.
+    ///
+    /// Transparent surfaces and points outside of the input region of a surface do not hit the surface.
+    pub fn surface_under(
+        &self,
+        location: Point<i32, Physical>,
+        scale: Scale<f64>,
+        point: Point<f64, Physical>,
+    ) -> Option<(wl_surface::WlSurface, Point<f64, Logical>)> {
+        let mut nodes = self.scene.forest.dfs_descend(self.root.into())?.collect::<Vec<_>>();
+        nodes.reverse();
+
+        nodes.into_iter().find_map(|index| {
+            let SceneNode::Surface(node) = self.scene.forest.get(index)?.deref() else {
+                return None;
+            };
+
+            let placement = self.placement(index, location);
+            let element = SceneGraphElement::placed(&node.surface, &placement, scale)?;
+
+            if element.alpha <= 0.0 || !element.geometry(scale).to_f64().contains(point) {
+                return None;
+            }
+
+            let local = placement.unmap_point(point).to_logical(scale);
+            let accepts = compositor::with_states(&node.surface, |states| {
+                let attributes = states.cached_state.current::<SurfaceAttributes>();
+                attributes
+                    .input_region
+                    .as_ref()
+                    .map_or(true, |region| region.contains(local.to_i32_floor()))
+            });
+
+            accepts.then(|| (node.surface.clone(), local))
+        })
+    }
+
+    /// All surfaces in the hierarchy.
+    pub fn surfaces(&self) -> impl Iterator<Item = &wl_surface::WlSurface> {
+        self.scene
+            .forest
+            .dfs_descend(self.root.into())
+            .into_iter()
+            .flatten()
+            .filter_map(|index| match self.scene.forest.get(index)?.deref() {
+                SceneNode::Surface(node) => Some(&node.surface),
+                _ => None,
+            })
+    }
+}
+
+impl<R: Renderer + ImportAll> AsRenderElements<R> for Hierarchy<'_>
+where
+    R::TextureId: 'static,
+{
+    type RenderElement = SceneGraphElement;
+
+    fn render_elements<C: From<Self::RenderElement>>(
+        &self,
+        renderer: &mut R,
+        location: Point<i32, Physical>,
+        scale: Scale<f64>,
+        alpha: f32,
+    ) -> Vec<C> {
+        for surface in self.surfaces() {
+            smithay::backend::renderer::utils::import_surface_tree(renderer, surface).expect("Failed to import");
+        }
+
+        self.elements(location, scale, alpha).into_iter().map(C::from).collect()
+    }
+}
+
+/// Tracks which surfaces are visible while visiting surfaces from top to bottom.
+///
+/// A surface is occluded if it is outside of the presented area or the opaque regions of the surfaces visited
+/// before cover the surface completely.
+#[derive(Debug)]
+pub struct Occlusion {
+    area: Rectangle<i32, Physical>,
+
+    /// The opaque regions of the surfaces visited so far.
+    opaque: Vec<Rectangle<i32, Physical>>,
+}
+
+impl Occlusion {
+    pub fn new(area: Rectangle<i32, Physical>) -> Self {
+        Self {
+            area,
+            opaque: Vec::new(),
+        }
+    }
+
+    /// Visit a surface and return whether the surface is visible.
+    pub fn visit(
+        &mut self,
+        geometry: Rectangle<i32, Physical>,
+        opaque: impl IntoIterator<Item = Rectangle<i32, Physical>>,
+    ) -> bool {
+        let visible = geometry.intersection(self.area).map_or(false, |geometry| {
+            !geometry.subtract_rects(self.opaque.iter().copied()).is_empty()
+        });
+
+        let area = self.area;
+        self.opaque
+            .extend(opaque.into_iter().filter_map(|region| region.intersection(area)));
+
+        visible
+    }
+}
 
 #[derive(Debug)]
 enum SceneNode {
@@ -512,6 +1903,30 @@ enum SceneNode {
     SurfaceTree(SurfaceTreeNode),
     Surface(SurfaceNode),
     Branch(BranchNode),
+    SolidColor(SolidColorNode),
+}
+
+impl SceneNode {
+    /// The offset of the node relative to the parent.
+    fn offset(&self) -> Point<i32, Physical> {
+        match self {
+            SceneNode::Output(_) => Point::default(),
+            SceneNode::SurfaceTree(node) => node.offset,
+            SceneNode::Surface(node) => node.offset,
+            SceneNode::Branch(node) => node.offset,
+            SceneNode::SolidColor(node) => node.offset,
+        }
+    }
+
+    /// The properties of the node, if the node has properties.
+    fn properties(&self) -> Option<&NodeProperties> {
+        match self {
+            SceneNode::SurfaceTree(node) => Some(&node.properties),
+            SceneNode::Branch(node) => Some(&node.properties),
+            SceneNode::SolidColor(node) => Some(&node.properties),
+            _ => None,
+        }
+    }
 }
 
 impl From<BranchIndex> for Index {
@@ -532,11 +1947,459 @@ impl From<SurfaceIndex> for Index {
     }
 }
 
+impl From<SolidColorIndex> for Index {
+    fn from(value: SolidColorIndex) -> Self {
+        value.0
+    }
+}
+
 impl From<NodeIndex> for Index {
     fn from(value: NodeIndex) -> Self {
         match value {
             NodeIndex::SurfaceTree(index) => index.into(),
             NodeIndex::Branch(index) => index.into(),
+            NodeIndex::SolidColor(index) => index.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::output::{Output, PhysicalProperties, Subpixel};
+
+    use std::time::Duration;
+
+    use smithay::{
+        backend::renderer::element::Element,
+        utils::{Rectangle, Scale, Transform},
+    };
+
+    use crate::animation::{Animation, Easing};
+
+    use super::{
+        compose, invert, DumpKind, NodeIndex, NodeProperties, Occlusion, Placement, Scene, Transaction,
+        TransactionError, TRANSFORMS,
+    };
+
+    fn output(name: &str) -> Output {
+        Output::new(
+            name.into(),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: String::new(),
+                model: String::new(),
+            },
+        )
+    }
+
+    #[test]
+    fn transaction_restacks_siblings() {
+        let mut scene = Scene::new();
+        let parent = scene.create_branch();
+        let [a, b, c] = [(); 3].map(|_| NodeIndex::Branch(scene.create_branch()));
+
+        for node in [a, b, c] {
+            scene.branch_add_child(parent, node).unwrap();
         }
+
+        let mut transaction = Transaction::new();
+        transaction.place_above(a, c).place_below(b, a);
+        scene.apply_transaction(transaction).unwrap();
+
+        let children = scene.forest.children(parent.into()).collect::<Vec<_>>();
+        assert_eq!(children, [c.into(), b.into(), a.into()]);
+    }
+
+    #[test]
+    fn destroy_branch_promotes_children() {
+        let mut scene = Scene::new();
+        let parent = scene.create_branch();
+        let branch = scene.create_branch();
+        let [a, b, c] = [(); 3].map(|_| NodeIndex::Branch(scene.create_branch()));
+
+        scene.branch_add_child(parent, a).unwrap();
+        scene.branch_add_child(parent, NodeIndex::Branch(branch)).unwrap();
+        scene.branch_add_child(parent, c).unwrap();
+        scene.branch_add_child(branch, b).unwrap();
+
+        scene.destroy_branch(branch);
+        assert_eq!(
+            scene.forest.children(parent.into()).collect::<Vec<_>>(),
+            [a.into(), b.into(), c.into()]
+        );
+    }
+
+    #[test]
+    fn raise_and_lower() {
+        let mut scene = Scene::new();
+        let parent = scene.create_branch();
+        let [a, b, c] = [(); 3].map(|_| NodeIndex::Branch(scene.create_branch()));
+
+        for node in [a, b, c] {
+            scene.branch_add_child(parent, node).unwrap();
+        }
+
+        scene.raise_node(a);
+        assert_eq!(
+            scene.forest.children(parent.into()).collect::<Vec<_>>(),
+            [b.into(), a.into(), c.into()]
+        );
+
+        scene.lower_node_to_bottom(c);
+        assert_eq!(
+            scene.forest.children(parent.into()).collect::<Vec<_>>(),
+            [c.into(), b.into(), a.into()]
+        );
+
+        scene.raise_node_to_top(c);
+        scene.lower_node(a);
+        assert_eq!(
+            scene.forest.children(parent.into()).collect::<Vec<_>>(),
+            [a.into(), b.into(), c.into()]
+        );
+
+        // Raising the topmost node does nothing.
+        scene.raise_node(c);
+        assert_eq!(
+            scene.forest.children(parent.into()).collect::<Vec<_>>(),
+            [a.into(), b.into(), c.into()]
+        );
+    }
+
+    #[test]
+    fn node_location_includes_ancestors() {
+        let mut scene = Scene::new();
+        let parent = scene.create_branch();
+        let child = scene.create_branch();
+        scene.branch_add_child(parent, NodeIndex::Branch(child)).unwrap();
+        scene.set_node_offset(NodeIndex::Branch(parent), (10, 20).into());
+        scene.set_node_offset(NodeIndex::Branch(child), (5, 5).into());
+
+        assert_eq!(scene.node_location(NodeIndex::Branch(child)), (15, 25).into());
+        assert_eq!(scene.node_location(NodeIndex::Branch(parent)), (10, 20).into());
+    }
+
+    #[test]
+    fn nodes_within_ancestor() {
+        let mut scene = Scene::new();
+        let parent = scene.create_branch();
+        let child = scene.create_branch();
+        let other = scene.create_branch();
+        scene.branch_add_child(parent, NodeIndex::Branch(child)).unwrap();
+
+        assert!(scene.is_within(NodeIndex::Branch(child), NodeIndex::Branch(parent)));
+        assert!(scene.is_within(NodeIndex::Branch(parent), NodeIndex::Branch(parent)));
+        assert!(!scene.is_within(NodeIndex::Branch(parent), NodeIndex::Branch(child)));
+        assert!(!scene.is_within(NodeIndex::Branch(child), NodeIndex::Branch(other)));
+    }
+
+    #[test]
+    fn invalid_transaction_is_not_applied() {
+        let mut scene = Scene::new();
+        let parent = scene.create_branch();
+        let child = scene.create_branch();
+        scene.branch_add_child(parent, NodeIndex::Branch(child)).unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction
+            .set_offset(NodeIndex::Branch(child), (10, 10).into())
+            // The parent would become a child of it's own child.
+            .reparent(NodeIndex::Branch(parent), child);
+
+        assert_eq!(scene.apply_transaction(transaction), Err(TransactionError::Cycle));
+        assert_eq!(scene.get_branch(child).unwrap().offset, (0, 0).into());
+    }
+
+    #[test]
+    fn transaction_for_destroyed_node_is_not_applied() {
+        let mut scene = Scene::new();
+        let parent = scene.create_branch();
+        let child = scene.create_branch();
+
+        let mut transaction = Transaction::new();
+        transaction
+            .set_offset(NodeIndex::Branch(parent), (10, 10).into())
+            .reparent(NodeIndex::Branch(child), parent);
+
+        // The client owning the child disconnected before the transaction was applied.
+        scene.destroy_branch(child);
+
+        assert_eq!(scene.apply_transaction(transaction), Err(TransactionError::NotPresent));
+        assert_eq!(scene.get_branch(parent).unwrap().offset, (0, 0).into());
+    }
+
+    #[test]
+    fn transaction_validates_reparent_before_restack() {
+        let mut scene = Scene::new();
+        let [first, second] = [(); 2].map(|_| scene.create_branch());
+        let node = NodeIndex::Branch(scene.create_branch());
+        let sibling = NodeIndex::Branch(scene.create_branch());
+        scene.branch_add_child(first, node).unwrap();
+        scene.branch_add_child(second, sibling).unwrap();
+
+        // The nodes only become siblings after the reparent.
+        let mut transaction = Transaction::new();
+        transaction.place_above(node, sibling);
+        assert_eq!(
+            scene.apply_transaction(transaction.clone()),
+            Err(TransactionError::NotSiblings)
+        );
+
+        let mut transaction = Transaction::new();
+        transaction.reparent(node, second).place_below(node, sibling);
+        scene.apply_transaction(transaction).unwrap();
+
+        let children = scene.forest.children(second.into()).collect::<Vec<_>>();
+        assert_eq!(children, [node.into(), sibling.into()]);
+        assert_eq!(scene.forest.children(first.into()).count(), 0);
+    }
+
+    #[test]
+    fn node_outputs_through_ancestors() {
+        let mut scene = Scene::new();
+        let [first, second] = [output("first"), output("second")];
+        scene.create_output(first.clone());
+        scene.create_output(second.clone());
+
+        let workspace = scene.create_branch();
+        let view = NodeIndex::Branch(scene.create_branch());
+        scene.branch_add_child(workspace, view).unwrap();
+        assert!(scene.node_outputs(view).is_empty());
+
+        scene.set_output_node(&first, NodeIndex::Branch(workspace));
+        assert_eq!(scene.node_outputs(view), [first.clone()]);
+
+        // A node presented directly is also on the output.
+        scene.set_output_node(&second, view);
+        let mut outputs = scene.node_outputs(view);
+        outputs.sort_by_key(|output| output.name());
+        assert_eq!(outputs, [first.clone(), second]);
+
+        assert_eq!(scene.node_outputs(NodeIndex::Branch(workspace)), [first]);
+    }
+
+    #[test]
+    fn animated_transaction_presents_previous_values() {
+        let mut scene = Scene::new();
+        let branch = scene.create_branch();
+        let node = NodeIndex::Branch(branch);
+        scene.set_node_offset(node, (100, 0).into());
+
+        let mut transaction = Transaction::new();
+        transaction
+            .animate(Animation {
+                duration: Duration::from_secs(60),
+                easing: Easing::Linear,
+            })
+            .set_offset(node, (200, 0).into())
+            .set_opacity(node, 0.5);
+        scene.apply_transaction(transaction).unwrap();
+
+        // The scene has the final values, but the node is presented from the previous values.
+        assert_eq!(scene.get_branch(branch).unwrap().offset, (200, 0).into());
+        assert!(scene.is_animating());
+
+        let mut transaction = Transaction::new();
+        transaction.set_opacity(node, 1.0);
+        scene.apply_transaction(transaction).unwrap();
+        assert!(!scene.is_animating());
+    }
+
+    #[test]
+    fn transforms_compose() {
+        assert_eq!(compose(Transform::_90, Transform::_90), Transform::_180);
+        assert_eq!(compose(Transform::_90, Transform::_270), Transform::Normal);
+        assert_eq!(compose(Transform::Flipped, Transform::Flipped), Transform::Normal);
+        assert_eq!(invert(Transform::_90), Transform::_270);
+
+        for transform in TRANSFORMS {
+            assert_eq!(compose(transform, invert(transform)), Transform::Normal);
+        }
+    }
+
+    #[test]
+    fn placement_applies_ancestors() {
+        let parent = NodeProperties {
+            scale: 2.0,
+            opacity: 0.5,
+            clip: Some(Rectangle::from_loc_and_size((0, 0), (50, 50))),
+            ..NodeProperties::default()
+        };
+        let child = NodeProperties {
+            transform: Transform::_90,
+            opacity: 0.5,
+            ..NodeProperties::default()
+        };
+
+        let placement = Placement::at((100, 100).into())
+            .child((10.0, 0.0).into(), Some(&parent))
+            .child((5.0, 5.0).into(), Some(&child));
+
+        // The offset of the child is scaled by the parent.
+        assert_eq!(placement.origin, (120.0, 110.0).into());
+        assert_eq!(placement.scale, 2.0);
+        assert_eq!(placement.opacity, 0.25);
+        assert_eq!(
+            placement.clip,
+            Some(Rectangle::from_loc_and_size((110, 100), (100, 100)))
+        );
+
+        // Rotating counter-clockwise moves the right of the node up.
+        assert_eq!(
+            placement.map_rect(Rectangle::from_loc_and_size((0, 0), (10, 5))),
+            Rectangle::from_loc_and_size((120, 90), (10, 20))
+        );
+    }
+
+    #[test]
+    fn unmap_point_inverts_placement() {
+        for transform in TRANSFORMS {
+            let properties = NodeProperties {
+                scale: 1.5,
+                transform,
+                ..NodeProperties::default()
+            };
+            let placement = Placement::at((40, 20).into()).child((8.0, 4.0).into(), Some(&properties));
+
+            // Input at a presented point reaches the point of the surface presented there.
+            let point = (6.0, 3.0).into();
+            assert_eq!(placement.unmap_point(placement.map_point(point)), point);
+        }
+    }
+
+    #[test]
+    fn transaction_sets_properties() {
+        let mut scene = Scene::new();
+        let node = NodeIndex::Branch(scene.create_branch());
+
+        let mut transaction = Transaction::new();
+        transaction.set_scale(node, 0.5).set_opacity(node, 2.0);
+        assert_eq!(
+            scene.apply_transaction(transaction),
+            Err(TransactionError::InvalidProperty)
+        );
+        assert_eq!(scene.node_properties(node), Some(&NodeProperties::default()));
+
+        let mut transaction = Transaction::new();
+        transaction.set_scale(node, 0.5).set_transform(node, Transform::_180);
+        scene.apply_transaction(transaction).unwrap();
+
+        let properties = scene.node_properties(node).unwrap();
+        assert_eq!((properties.scale, properties.transform), (0.5, Transform::_180));
+    }
+
+    #[test]
+    fn opaque_surfaces_occlude() {
+        let mut occlusion = Occlusion::new(Rectangle::from_loc_and_size((0, 0), (100, 100)));
+        let rect = |x, y, w, h| Rectangle::from_loc_and_size((x, y), (w, h));
+
+        // An opaque surface covering the left half.
+        assert!(occlusion.visit(rect(0, 0, 50, 100), [rect(0, 0, 50, 100)]));
+        // Covered completely by the surface above.
+        assert!(!occlusion.visit(rect(10, 10, 20, 20), []));
+        // Partially covered surfaces are visible.
+        assert!(occlusion.visit(rect(40, 0, 20, 20), []));
+        // Surfaces outside of the area are not visible.
+        assert!(!occlusion.visit(rect(100, 0, 20, 20), []));
+
+        // Surfaces without opaque regions do not occlude.
+        assert!(occlusion.visit(rect(50, 0, 50, 100), []));
+        assert!(occlusion.visit(rect(60, 10, 10, 10), []));
+    }
+
+    #[test]
+    fn solid_color_elements() {
+        let mut scene = Scene::new();
+        let first = output("first");
+        scene.create_output(first.clone());
+        let branch = scene.create_branch();
+        let dim = scene.create_solid_color((100, 50).into(), [0.0, 0.0, 0.0, 0.5]);
+        scene.branch_add_child(branch, NodeIndex::SolidColor(dim)).unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction
+            .set_output(first.clone(), NodeIndex::Branch(branch))
+            .set_offset(NodeIndex::SolidColor(dim), (10, 20).into())
+            .set_opacity(NodeIndex::SolidColor(dim), 0.5);
+        scene.apply_transaction(transaction).unwrap();
+
+        let elements = |scene: &Scene| {
+            let graph = scene.get_graph(&first).unwrap();
+            graph.elements((0, 0).into(), Scale::from(1.0), 1.0)
+        };
+        let presented = elements(&scene);
+        assert_eq!(presented.len(), 1);
+        assert_eq!(
+            presented[0].geometry(Scale::from(1.0)),
+            Rectangle::from_loc_and_size((10, 20), (100, 50))
+        );
+        assert_eq!(presented[0].alpha(), 0.5);
+
+        // Every channel must be from 0.0 to 1.0.
+        let mut transaction = Transaction::new();
+        transaction.set_solid_color(dim, (200, 50).into(), [2.0, 0.0, 0.0, 1.0]);
+        assert_eq!(
+            scene.apply_transaction(transaction),
+            Err(TransactionError::InvalidProperty)
+        );
+
+        // Changing the color damages the element.
+        let mut transaction = Transaction::new();
+        transaction.set_solid_color(dim, (200, 50).into(), [1.0, 0.0, 0.0, 1.0]);
+        scene.apply_transaction(transaction).unwrap();
+        let changed = elements(&scene);
+        assert_eq!(changed[0].geometry(Scale::from(1.0)).size, (200, 50).into());
+        assert_ne!(changed[0].current_commit(), presented[0].current_commit());
+
+        scene.destroy_solid_color(dim);
+        assert!(elements(&scene).is_empty());
+    }
+
+    #[test]
+    fn dump() {
+        let mut scene = Scene::new();
+        let output = output("DP-1");
+        scene.create_output(output.clone());
+
+        let workspace = scene.create_branch();
+        let background = scene.create_solid_color((1920, 1080).into(), [0.0, 0.0, 0.0, 1.0]);
+        let background = NodeIndex::SolidColor(background);
+        scene.branch_add_child(workspace, background).unwrap();
+        scene.set_node_offset(NodeIndex::Branch(workspace), (10, 20).into());
+        scene.set_node_offset(background, (5, 5).into());
+        scene.set_output_node(&output, NodeIndex::Branch(workspace));
+
+        let dump = scene.dump();
+        let overlay = &dump.roots[0];
+        assert_eq!(overlay.kind, DumpKind::Branch { name: Some("overlay") });
+
+        let output = dump
+            .roots
+            .iter()
+            .find(|node| matches!(node.kind, DumpKind::Output { .. }))
+            .unwrap();
+        let DumpKind::Output { name, present } = &output.kind else {
+            unreachable!();
+        };
+        assert_eq!(name, "DP-1");
+
+        let workspace = dump.roots.iter().find(|node| Some(node.id) == *present).unwrap();
+        assert_eq!(workspace.location, (10, 20));
+        assert_eq!(workspace.children.len(), 1);
+        assert_eq!(workspace.children[0].offset, (5, 5));
+        assert_eq!(workspace.children[0].location, (15, 25));
+        assert_eq!(
+            workspace.children[0].kind,
+            DumpKind::SolidColor {
+                size: (1920, 1080),
+                color: [0.0, 0.0, 0.0, 1.0],
+            }
+        );
+
+        let dot = dump.to_dot();
+        assert!(dot.starts_with("digraph scene {"));
+        assert!(dot.contains(&format!("n{} -> n{} [style=dashed];", output.id, workspace.id)));
+        assert!(dot.contains(&format!("n{} -> n{};", workspace.id, workspace.children[0].id)));
     }
 }
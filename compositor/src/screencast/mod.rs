@@ -0,0 +1,277 @@
+//! Screen casting
+//!
+//! A screen cast continuously exports the frames of an output or a toplevel to a consumer, such as a video call or
+//! a screen recorder. The backend reads back the frames of the outputs being cast after rendering and renders the
+//! toplevels being cast offscreen. The frames are passed to a [`FrameSink`], which sends them to the consumer.
+//!
+//! With the `screencast` feature, aerugo implements the `org.freedesktop.impl.portal.ScreenCast` interface so
+//! `xdg-desktop-portal` can start screen casts for clients, and the frames are exported as PipeWire streams. The
+//! `org.freedesktop.impl.portal.RemoteDesktop` interface is served alongside, since remote desktop sessions share
+//! the sessions and casts of the screen cast portal.
+
+#[cfg(feature = "screencast")]
+mod portal;
+#[cfg(feature = "screencast")]
+mod remote_desktop;
+#[cfg(feature = "screencast")]
+mod stream;
+
+use rustc_hash::FxHashMap;
+use smithay::{
+    backend::renderer::{gles::GlesRenderer, utils::with_renderer_surface_state, ExportMem},
+    output::Output,
+    utils::{Physical, Rectangle, Size},
+};
+
+use crate::{
+    screenshot,
+    shell::{Shell, ToplevelId},
+};
+
+#[cfg(feature = "screencast")]
+pub use portal::{Portal, PortalError};
+
+/// Bytes per pixel of a [`Frame`].
+pub const BYTES_PER_PIXEL: usize = 4;
+
+/// The largest width or height of a cast toplevel in pixels.
+pub const MAX_TOPLEVEL_CAST_SIZE: i32 = 4096;
+
+/// A frame of a screen cast.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+
+    /// The pixels in RGBA byte order, without padding between rows.
+    pub data: Vec<u8>,
+}
+
+/// What a screen cast shows.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CastSource {
+    Output(Output),
+    Toplevel(ToplevelId),
+}
+
+/// The consumer of the frames of a screen cast.
+pub trait FrameSink: std::fmt::Debug {
+    /// Whether the consumer is waiting for frames.
+    ///
+    /// Frames are only read back or rendered for active sinks.
+    fn is_active(&self) -> bool;
+
+    /// Whether the consumer went away, in which case the cast is stopped.
+    fn is_closed(&self) -> bool;
+
+    fn submit(&mut self, frame: Frame);
+}
+
+#[derive(Debug)]
+struct Cast {
+    /// The portal session which started the cast.
+    session: u64,
+    source: CastSource,
+    sink: Box<dyn FrameSink>,
+
+    /// The size of the frames the consumer expects.
+    size: Size<i32, Physical>,
+}
+
+/// The running screen casts.
+#[derive(Debug, Default)]
+pub struct Screencasts {
+    casts: Vec<Cast>,
+
+    /// Virtual outputs created for portal sessions, destroyed when the session is closed.
+    session_outputs: FxHashMap<u64, Output>,
+
+    /// The connection to PipeWire, once the first cast was started.
+    #[cfg(feature = "screencast")]
+    pipewire: Option<stream::PipeWire>,
+}
+
+impl Screencasts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, session: u64, source: CastSource, size: Size<i32, Physical>, sink: Box<dyn FrameSink>) {
+        self.casts.push(Cast {
+            session,
+            source,
+            sink,
+            size,
+        });
+    }
+
+    /// Record the virtual output created for the casts of a portal session.
+    pub fn set_session_output(&mut self, session: u64, output: Output) {
+        self.session_outputs.insert(session, output);
+    }
+
+    /// Stop the casts started by a portal session.
+    ///
+    /// Returns the virtual output created for the session, which should be destroyed.
+    pub fn stop_session(&mut self, session: u64) -> Option<Output> {
+        self.casts.retain(|cast| cast.session != session);
+        self.session_outputs.remove(&session)
+    }
+
+    /// Stop the casts of an output which was removed.
+    pub fn remove_output(&mut self, output: &Output) {
+        self.casts
+            .retain(|cast| !matches!(&cast.source, CastSource::Output(cast_output) if cast_output == output));
+    }
+
+    /// Stop the casts of a toplevel which was closed.
+    pub fn remove_toplevel(&mut self, toplevel: ToplevelId) {
+        self.casts.retain(|cast| cast.source != CastSource::Toplevel(toplevel));
+    }
+
+    /// The output cast by a portal session, if the session casts an output.
+    pub fn cast_output(&self, session: u64) -> Option<&Output> {
+        self.casts
+            .iter()
+            .filter(|cast| cast.session == session)
+            .find_map(|cast| match &cast.source {
+                CastSource::Output(output) => Some(output),
+                CastSource::Toplevel(_) => None,
+            })
+    }
+
+    /// Whether frames of the output should be read back.
+    pub fn wants_output(&self, output: &Output) -> bool {
+        self.casts
+            .iter()
+            .any(|cast| cast.source == CastSource::Output(output.clone()) && cast.sink.is_active())
+    }
+
+    /// Read back the frame rendered for an output and submit it to the casts of the output.
+    ///
+    /// This must be called after the frame has finished rendering but before the renderer is unbound from the
+    /// framebuffer of the output.
+    pub fn submit_output_frame<R: ExportMem>(&mut self, renderer: &mut R, output: &Output, size: Size<i32, Physical>) {
+        self.remove_closed();
+
+        if !self.wants_output(output) {
+            return;
+        }
+
+        let Some(frame) = read_frame(renderer, size) else {
+            tracing::debug!(output = output.name(), "Failed to read back frame for screen cast");
+            return;
+        };
+
+        for cast in self
+            .casts
+            .iter_mut()
+            .filter(|cast| cast.source == CastSource::Output(output.clone()) && cast.sink.is_active())
+        {
+            cast.sink.submit(frame.clone());
+        }
+    }
+
+    /// Render every toplevel being cast and submit the frames.
+    ///
+    /// This binds an offscreen buffer, so the backend must bind its framebuffer again before rendering.
+    pub fn render_toplevel_frames(&mut self, renderer: &mut GlesRenderer, shell: &Shell) {
+        self.remove_closed();
+
+        for cast in self.casts.iter_mut().filter(|cast| cast.sink.is_active()) {
+            let CastSource::Toplevel(toplevel) = cast.source else {
+                continue;
+            };
+
+            let Some(surface) = shell.get_state(toplevel).and_then(|toplevel| toplevel.wl_surface()) else {
+                continue;
+            };
+
+            match screenshot::capture(renderer, &surface, cast.size) {
+                Ok(image) => cast.sink.submit(Frame {
+                    width: image.width,
+                    height: image.height,
+                    data: image.data,
+                }),
+                Err(err) => tracing::debug!(%err, %toplevel, "Failed to render toplevel for screen cast"),
+            }
+        }
+    }
+
+    fn remove_closed(&mut self) {
+        self.casts.retain(|cast| !cast.sink.is_closed());
+    }
+}
+
+/// The size of the frames of a cast toplevel, which is the current size of the toplevel.
+pub fn toplevel_cast_size(shell: &Shell, toplevel: ToplevelId) -> Option<Size<i32, Physical>> {
+    let surface = shell.get_state(toplevel)?.wl_surface()?;
+    let size = with_renderer_surface_state(&surface, |state| state.surface_size())?;
+    // TODO: Account for the buffer scale of the surface.
+    let size = Size::from((
+        size.w.clamp(1, MAX_TOPLEVEL_CAST_SIZE),
+        size.h.clamp(1, MAX_TOPLEVEL_CAST_SIZE),
+    ));
+
+    Some(size)
+}
+
+fn read_frame<R: ExportMem>(renderer: &mut R, size: Size<i32, Physical>) -> Option<Frame> {
+    let mapping = renderer
+        .copy_framebuffer(Rectangle::from_loc_and_size((0, 0), (size.w, size.h)))
+        .ok()?;
+    let data = renderer.map_texture(&mapping).ok()?.to_vec();
+
+    Some(Frame {
+        width: size.w as u32,
+        height: size.h as u32,
+        data,
+    })
+}
+
+/// Copy a frame into a buffer of a fixed size with the specified stride.
+///
+/// Frames larger than the buffer are cropped and the rest of a buffer larger than the frame is cleared. Returns
+/// the number of bytes of the buffer which were written.
+pub fn copy_frame(frame: &Frame, dest: &mut [u8], size: Size<i32, Physical>, stride: usize) -> usize {
+    let height = (size.h.max(0) as usize).min(dest.len() / stride.max(1));
+    let row = (size.w.max(0) as usize * BYTES_PER_PIXEL).min(stride);
+    let src_stride = frame.width as usize * BYTES_PER_PIXEL;
+    let copied = row.min(src_stride);
+
+    for y in 0..height {
+        let dest = &mut dest[y * stride..y * stride + row];
+
+        match frame.data.get(y * src_stride..y * src_stride + copied) {
+            Some(src) if y < frame.height as usize => {
+                dest[..copied].copy_from_slice(src);
+                dest[copied..].fill(0);
+            }
+            _ => dest.fill(0),
+        }
+    }
+
+    height * stride
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy_frame, Frame};
+
+    #[test]
+    fn copy_frame_crops_and_clears() {
+        // A 2x2 frame copied into a 3x1 buffer with a stride of 16 bytes.
+        let frame = Frame {
+            width: 2,
+            height: 2,
+            data: (1..=16).collect(),
+        };
+        let mut dest = [0xff; 16];
+
+        assert_eq!(copy_frame(&frame, &mut dest, (3, 1).into(), 16), 16);
+        assert_eq!(&dest[..8], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(&dest[8..12], &[0; 4]);
+        // Padding past the row is left alone.
+        assert_eq!(&dest[12..], &[0xff; 4]);
+    }
+}
@@ -0,0 +1,522 @@
+//! The `org.freedesktop.impl.portal.ScreenCast` portal backend
+//!
+//! `xdg-desktop-portal` forwards the screen cast requests of clients to the portal backend of the desktop, which
+//! selects the sources with the user and creates the PipeWire streams. aerugo does not show a source picker yet:
+//! monitor casts show the primary output, window casts show the toplevel with keyboard focus and virtual casts
+//! show a new virtual output which is destroyed when the session is closed.
+//!
+//! The portal is served on the session bus by the executor thread of zbus. Starting a cast needs the compositor
+//! state, so the request is sent to the event loop and the portal waits for the reply.
+//!
+//! Sessions are shared with the remote desktop portal (see [`super::remote_desktop`]), since
+//! `xdg-desktop-portal` selects the sources of a remote desktop session through the screen cast interface.
+
+use std::{
+    collections::HashMap,
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use bitflags::bitflags;
+use calloop::{
+    channel::{self, Event},
+    LoopHandle,
+};
+use rustc_hash::FxHashMap;
+use smithay::utils::{Logical, Physical, Point, Size};
+use zbus::{
+    dbus_interface,
+    zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value},
+    ObjectServer,
+};
+
+use crate::{
+    input::virtual_seat::{InjectedEvent, VirtualSeatId},
+    shell::{Shell, ToplevelId},
+    virtual_output::{VirtualOutputError, DEFAULT_REFRESH},
+    Aerugo, Loop,
+};
+
+use super::{
+    remote_desktop::{DeviceTypes, RemoteDesktop},
+    stream::{PipeWire, StreamError},
+    toplevel_cast_size, CastSource,
+};
+
+/// The well known name the portal backend is served at.
+///
+/// `xdg-desktop-portal` finds the name through the `aerugo.portal` file.
+pub const BUS_NAME: &str = "org.freedesktop.impl.portal.desktop.aerugo";
+
+const OBJECT_PATH: &str = "/org/freedesktop/portal/desktop";
+
+/// The version of the `org.freedesktop.impl.portal.ScreenCast` interface.
+const SCREENCAST_VERSION: u32 = 4;
+
+/// How long starting a cast may take before the request fails.
+pub(super) const START_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The cursor is not part of the cast.
+const CURSOR_MODE_HIDDEN: u32 = 1;
+
+// Response codes of portal requests.
+pub(super) const RESPONSE_SUCCESS: u32 = 0;
+pub(super) const RESPONSE_OTHER: u32 = 2;
+
+bitflags! {
+    /// The types of sources a screen cast may show.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct SourceTypes: u32 {
+        const MONITOR = 1;
+        const WINDOW = 2;
+        const VIRTUAL = 4;
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PortalError {
+    #[error(transparent)]
+    Dbus(#[from] zbus::Error),
+
+    #[error("failed to register the portal with the event loop")]
+    Loop,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScreencastError {
+    #[error("no source of the selected types is available")]
+    NoSource,
+
+    #[error("the compositor did not respond")]
+    Timeout,
+
+    #[error("the application is not allowed to use the remote desktop portal")]
+    NotAllowed,
+
+    #[error(transparent)]
+    VirtualOutput(#[from] VirtualOutputError),
+
+    #[error(transparent)]
+    Stream(#[from] StreamError),
+}
+
+/// A request of the portal handled by the event loop.
+#[derive(Debug)]
+pub enum PortalRequest {
+    Start {
+        session: u64,
+        types: SourceTypes,
+        reply: mpsc::Sender<Result<StartedCast, ScreencastError>>,
+    },
+
+    StartRemoteDesktop {
+        session: u64,
+
+        /// The sources to cast, if sources were selected for the session.
+        sources: Option<SourceTypes>,
+
+        /// The app id of the application the session is started for.
+        app_id: String,
+
+        /// Whether the permission store grants the application remote desktop access.
+        granted: bool,
+        reply: mpsc::Sender<Result<Option<StartedCast>, ScreencastError>>,
+    },
+
+    Inject {
+        session: u64,
+        event: InjectedEvent,
+    },
+
+    /// Move the pointer to a position relative to the output cast by the session.
+    InjectAbsolute {
+        session: u64,
+        position: Point<f64, Logical>,
+    },
+
+    Close {
+        session: u64,
+    },
+}
+
+/// A cast started by the compositor.
+#[derive(Debug)]
+pub struct StartedCast {
+    source_type: SourceTypes,
+
+    /// The location of the output in the global compositor space, for monitor casts.
+    position: Option<Point<i32, Logical>>,
+    size: Size<i32, Physical>,
+
+    /// Receives the id of the PipeWire node once the stream was created.
+    node: mpsc::Receiver<Result<u32, StreamError>>,
+}
+
+impl StartedCast {
+    /// Wait for the stream of the cast and describe the stream for the results of a `Start` request.
+    pub(super) fn streams(self) -> Result<OwnedValue, ScreencastError> {
+        let node = self
+            .node
+            .recv_timeout(START_TIMEOUT)
+            .map_err(|_| ScreencastError::Timeout)??;
+
+        let mut properties = HashMap::<&str, Value>::new();
+        properties.insert("size", Value::from((self.size.w, self.size.h)));
+        properties.insert("source_type", Value::from(self.source_type.bits()));
+
+        if let Some(position) = self.position {
+            properties.insert("position", Value::from((position.x, position.y)));
+        }
+
+        Ok(Value::from(vec![(node, properties)]).into())
+    }
+}
+
+/// The portal backends, served until dropped.
+#[derive(Debug)]
+pub struct Portal {
+    _connection: zbus::blocking::Connection,
+}
+
+impl Portal {
+    pub fn spawn(r#loop: &LoopHandle<'static, Loop>) -> Result<Self, PortalError> {
+        let (requests, channel) = channel::channel();
+
+        r#loop
+            .insert_source(channel, |event, _, state| {
+                if let Event::Msg(request) = event {
+                    state.handle_portal_request(request);
+                }
+            })
+            .map_err(|_| PortalError::Loop)?;
+
+        let sessions = Sessions::default();
+        let screencast = ScreenCast {
+            requests: requests.clone(),
+            sessions: sessions.clone(),
+        };
+        let remote_desktop = RemoteDesktop { requests, sessions };
+
+        let connection = zbus::blocking::ConnectionBuilder::session()?
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, screencast)?
+            .serve_at(OBJECT_PATH, remote_desktop)?
+            .build()?;
+
+        tracing::info!(name = BUS_NAME, "Serving portal backends");
+
+        Ok(Self {
+            _connection: connection,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct SessionState {
+    pub id: u64,
+
+    /// The selected sources, if the session casts.
+    pub sources: Option<SourceTypes>,
+
+    /// The selected devices, if the session is a remote desktop session.
+    pub devices: Option<DeviceTypes>,
+
+    pub started: bool,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct SessionTable {
+    pub sessions: FxHashMap<OwnedObjectPath, SessionState>,
+    next_id: u64,
+}
+
+pub(super) type Sessions = Arc<Mutex<SessionTable>>;
+
+pub(super) type Results = HashMap<String, OwnedValue>;
+
+/// Export a new session at the handle chosen by `xdg-desktop-portal`.
+pub(super) async fn create_session(
+    server: &ObjectServer,
+    requests: &channel::Sender<PortalRequest>,
+    sessions: &Sessions,
+    session_handle: ObjectPath<'_>,
+    devices: Option<DeviceTypes>,
+) -> (u32, Results) {
+    let id = {
+        let mut sessions = sessions.lock().unwrap();
+        sessions.next_id += 1;
+        sessions.next_id
+    };
+
+    let session = Session {
+        id,
+        handle: session_handle.clone().into(),
+        requests: requests.clone(),
+        sessions: sessions.clone(),
+    };
+
+    if let Err(err) = server.at(&session_handle, session).await {
+        tracing::warn!(%err, "Failed to export portal session");
+        return (RESPONSE_OTHER, Results::new());
+    }
+
+    sessions.lock().unwrap().sessions.insert(
+        session_handle.into(),
+        SessionState {
+            id,
+            sources: None,
+            devices,
+            started: false,
+        },
+    );
+
+    (RESPONSE_SUCCESS, Results::new())
+}
+
+struct ScreenCast {
+    requests: channel::Sender<PortalRequest>,
+    sessions: Sessions,
+}
+
+impl ScreenCast {
+    fn start_cast(&self, session: u64, types: SourceTypes) -> Result<Results, ScreencastError> {
+        let (reply, recv) = mpsc::channel();
+
+        self.requests
+            .send(PortalRequest::Start { session, types, reply })
+            .map_err(|_| ScreencastError::Timeout)?;
+
+        let cast = recv
+            .recv_timeout(START_TIMEOUT)
+            .map_err(|_| ScreencastError::Timeout)??;
+
+        let mut results = Results::new();
+        results.insert("streams".into(), cast.streams()?);
+        Ok(results)
+    }
+}
+
+#[dbus_interface(name = "org.freedesktop.impl.portal.ScreenCast")]
+impl ScreenCast {
+    async fn create_session(
+        &self,
+        _handle: ObjectPath<'_>,
+        session_handle: ObjectPath<'_>,
+        _app_id: String,
+        _options: HashMap<String, OwnedValue>,
+        #[zbus(object_server)] server: &ObjectServer,
+    ) -> (u32, Results) {
+        create_session(server, &self.requests, &self.sessions, session_handle, None).await
+    }
+
+    async fn select_sources(
+        &self,
+        _handle: ObjectPath<'_>,
+        session_handle: ObjectPath<'_>,
+        _app_id: String,
+        options: HashMap<String, OwnedValue>,
+    ) -> (u32, Results) {
+        let types = options
+            .get("types")
+            .and_then(|types| u32::try_from(types.clone()).ok())
+            .map(SourceTypes::from_bits_truncate)
+            .filter(|types| !types.is_empty())
+            .unwrap_or(SourceTypes::MONITOR);
+
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let Some(session) = sessions.sessions.get_mut(&OwnedObjectPath::from(session_handle)) else {
+            return (RESPONSE_OTHER, Results::new());
+        };
+
+        session.sources = Some(types);
+        (RESPONSE_SUCCESS, Results::new())
+    }
+
+    // This blocks the executor of the connection until the compositor replied, which is fine since the connection
+    // only serves the portal.
+    async fn start(
+        &self,
+        _handle: ObjectPath<'_>,
+        session_handle: ObjectPath<'_>,
+        _app_id: String,
+        _parent_window: String,
+        _options: HashMap<String, OwnedValue>,
+    ) -> (u32, Results) {
+        let session = self
+            .sessions
+            .lock()
+            .unwrap()
+            .sessions
+            .get_mut(&OwnedObjectPath::from(session_handle))
+            .filter(|session| !session.started)
+            .map(|session| {
+                session.started = true;
+                (session.id, session.sources.unwrap_or(SourceTypes::MONITOR))
+            });
+
+        let Some((id, types)) = session else {
+            return (RESPONSE_OTHER, Results::new());
+        };
+
+        match self.start_cast(id, types) {
+            Ok(results) => (RESPONSE_SUCCESS, results),
+            Err(err) => {
+                tracing::warn!(%err, "Failed to start screen cast");
+                (RESPONSE_OTHER, Results::new())
+            }
+        }
+    }
+
+    #[dbus_interface(property)]
+    fn available_source_types(&self) -> u32 {
+        SourceTypes::all().bits()
+    }
+
+    #[dbus_interface(property)]
+    fn available_cursor_modes(&self) -> u32 {
+        CURSOR_MODE_HIDDEN
+    }
+
+    #[dbus_interface(property, name = "version")]
+    fn version(&self) -> u32 {
+        SCREENCAST_VERSION
+    }
+}
+
+/// A portal session, exported at the handle chosen by `xdg-desktop-portal`.
+struct Session {
+    id: u64,
+    handle: OwnedObjectPath,
+    requests: channel::Sender<PortalRequest>,
+    sessions: Sessions,
+}
+
+#[dbus_interface(name = "org.freedesktop.impl.portal.Session")]
+impl Session {
+    async fn close(&self, #[zbus(object_server)] server: &ObjectServer) {
+        self.sessions.lock().unwrap().sessions.remove(&self.handle);
+        let _ = self.requests.send(PortalRequest::Close { session: self.id });
+        let _ = server.remove::<Self, _>(&self.handle).await;
+    }
+
+    #[dbus_interface(property, name = "version")]
+    fn version(&self) -> u32 {
+        1
+    }
+}
+
+impl Loop {
+    fn handle_portal_request(&mut self, request: PortalRequest) {
+        match request {
+            PortalRequest::Start { session, types, reply } => {
+                let _ = reply.send(self.comp.start_screencast(session, types));
+            }
+
+            PortalRequest::StartRemoteDesktop {
+                session,
+                sources,
+                app_id,
+                granted,
+                reply,
+            } => {
+                let result = if self.config.remote_desktop.allows(&app_id, granted) {
+                    self.comp.start_remote_desktop(session, sources)
+                } else {
+                    Err(ScreencastError::NotAllowed)
+                };
+
+                let _ = reply.send(result);
+            }
+
+            PortalRequest::Inject { session, event } => self.comp.inject_input(session, event),
+
+            PortalRequest::InjectAbsolute { session, position } => {
+                let output = self.comp.screencasts.cast_output(session).unwrap_or(&self.comp.output);
+                let location = output.current_location().to_f64() + position;
+                self.comp
+                    .inject_input(session, InjectedEvent::PointerMotionAbsolute { location });
+            }
+
+            PortalRequest::Close { session } => {
+                self.comp.remove_virtual_seat(session);
+
+                if let Some(output) = self.comp.screencasts.stop_session(session) {
+                    if let Err(err) = self.comp.destroy_virtual_output(&output.name()) {
+                        tracing::warn!(%err, "Failed to destroy virtual output of screen cast");
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Aerugo {
+    fn start_screencast(&mut self, session: u64, types: SourceTypes) -> Result<StartedCast, ScreencastError> {
+        let output_size = self.output.current_mode().map(|mode| mode.size);
+
+        // Prefer the source types in the order of the portal specification.
+        let (source_type, size) = if types.contains(SourceTypes::MONITOR) {
+            (SourceTypes::MONITOR, output_size.ok_or(ScreencastError::NoSource)?)
+        } else if types.contains(SourceTypes::WINDOW) {
+            let toplevel = self.focused_toplevel().ok_or(ScreencastError::NoSource)?;
+            let size = toplevel_cast_size(&self.shell, toplevel).ok_or(ScreencastError::NoSource)?;
+            (SourceTypes::WINDOW, size)
+        } else if types.contains(SourceTypes::VIRTUAL) {
+            (SourceTypes::VIRTUAL, output_size.unwrap_or_else(|| (1920, 1080).into()))
+        } else {
+            return Err(ScreencastError::NoSource);
+        };
+
+        // Create the stream first so a failure does not leave a virtual output behind.
+        let pipewire = match &mut self.screencasts.pipewire {
+            Some(pipewire) => pipewire,
+            pipewire @ None => pipewire.insert(PipeWire::spawn()?),
+        };
+        let (sink, node) = pipewire.create_stream(size)?;
+
+        let (source, position) = match source_type {
+            SourceTypes::MONITOR => (
+                CastSource::Output(self.output.clone()),
+                Some(self.output.current_location()),
+            ),
+
+            SourceTypes::WINDOW => (
+                CastSource::Toplevel(self.focused_toplevel().ok_or(ScreencastError::NoSource)?),
+                None,
+            ),
+
+            _ => {
+                let output = self.create_virtual_output(size, Some(DEFAULT_REFRESH))?;
+                self.screencasts.set_session_output(session, output.clone());
+                (CastSource::Output(output.clone()), Some(output.current_location()))
+            }
+        };
+
+        self.screencasts.add(session, source, size, Box::new(sink));
+
+        Ok(StartedCast {
+            source_type,
+            position,
+            size,
+            node,
+        })
+    }
+
+    /// Start a remote desktop session, attributing the input of the session to a virtual seat.
+    fn start_remote_desktop(
+        &mut self,
+        session: VirtualSeatId,
+        sources: Option<SourceTypes>,
+    ) -> Result<Option<StartedCast>, ScreencastError> {
+        let cast = sources.map(|types| self.start_screencast(session, types)).transpose()?;
+
+        self.input.add_virtual_seat(session);
+        Ok(cast)
+    }
+
+    fn focused_toplevel(&self) -> Option<ToplevelId> {
+        let surface = self.seat.get_keyboard()?.current_focus()?;
+        Shell::get_toplevel_id(&surface)
+    }
+}
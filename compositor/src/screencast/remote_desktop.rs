@@ -0,0 +1,341 @@
+//! The `org.freedesktop.impl.portal.RemoteDesktop` portal backend
+//!
+//! A remote desktop session injects pointer and keyboard input, and may also cast the screen if sources were
+//! selected for the session through the screen cast interface. The input of a session is attributed to a virtual
+//! seat (see [`crate::input::virtual_seat`]) and delivered through the same pipeline as physical devices.
+//!
+//! Injecting input is privileged. Only `xdg-desktop-portal` may start a session, and the application it starts the
+//! session for must either be granted access in the permission store of `xdg-desktop-portal` or be listed in the
+//! `remote_desktop` section of the config. Scrolling, keysyms and touch input are not supported yet.
+
+use std::{collections::HashMap, sync::mpsc};
+
+use bitflags::bitflags;
+use calloop::channel;
+use smithay::backend::input::{ButtonState, KeyState};
+use zbus::{
+    dbus_interface,
+    fdo::DBusProxy,
+    names::{BusName, WellKnownName},
+    zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value},
+    Connection, MessageHeader, ObjectServer,
+};
+
+use crate::input::virtual_seat::InjectedEvent;
+
+use super::portal::{
+    create_session, PortalRequest, Results, ScreencastError, Sessions, SourceTypes, RESPONSE_OTHER, RESPONSE_SUCCESS,
+    START_TIMEOUT,
+};
+
+/// The version of the `org.freedesktop.impl.portal.RemoteDesktop` interface.
+const REMOTE_DESKTOP_VERSION: u32 = 2;
+
+bitflags! {
+    /// The types of devices a remote desktop session may use.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct DeviceTypes: u32 {
+        const KEYBOARD = 1;
+        const POINTER = 2;
+        const TOUCHSCREEN = 4;
+    }
+}
+
+/// The bus name of `xdg-desktop-portal`, which is the only process allowed to start sessions.
+const PORTAL_FRONTEND: &str = "org.freedesktop.portal.Desktop";
+
+// The entry of the permission store which grants applications remote desktop access.
+const PERMISSION_STORE: &str = "org.freedesktop.impl.portal.PermissionStore";
+const PERMISSION_STORE_PATH: &str = "/org/freedesktop/impl/portal/PermissionStore";
+const PERMISSION_TABLE: &str = "remote-desktop";
+const PERMISSION_ID: &str = "remote-desktop";
+
+/// The device types which can be injected.
+const AVAILABLE_DEVICE_TYPES: DeviceTypes = DeviceTypes::KEYBOARD.union(DeviceTypes::POINTER);
+
+pub(super) struct RemoteDesktop {
+    pub requests: channel::Sender<PortalRequest>,
+    pub sessions: Sessions,
+}
+
+impl RemoteDesktop {
+    /// Send an injected event of a started session which selected the device type.
+    fn notify(&self, session_handle: ObjectPath<'_>, device: DeviceTypes, request: impl FnOnce(u64) -> PortalRequest) {
+        let session = self
+            .sessions
+            .lock()
+            .unwrap()
+            .sessions
+            .get(&OwnedObjectPath::from(session_handle))
+            .filter(|session| session.started && session.devices.is_some_and(|devices| devices.contains(device)))
+            .map(|session| session.id);
+
+        if let Some(session) = session {
+            let _ = self.requests.send(request(session));
+        }
+    }
+
+    fn start_session(
+        &self,
+        session: u64,
+        devices: DeviceTypes,
+        sources: Option<SourceTypes>,
+        app_id: String,
+        granted: bool,
+    ) -> Result<Results, ScreencastError> {
+        let (reply, recv) = mpsc::channel();
+
+        self.requests
+            .send(PortalRequest::StartRemoteDesktop {
+                session,
+                sources,
+                app_id,
+                granted,
+                reply,
+            })
+            .map_err(|_| ScreencastError::Timeout)?;
+
+        let cast = recv
+            .recv_timeout(START_TIMEOUT)
+            .map_err(|_| ScreencastError::Timeout)??;
+
+        let mut results = Results::new();
+        results.insert("devices".into(), Value::from(devices.bits()).into());
+
+        if let Some(cast) = cast {
+            results.insert("streams".into(), cast.streams()?);
+        }
+
+        Ok(results)
+    }
+}
+
+#[dbus_interface(name = "org.freedesktop.impl.portal.RemoteDesktop")]
+impl RemoteDesktop {
+    async fn create_session(
+        &self,
+        _handle: ObjectPath<'_>,
+        session_handle: ObjectPath<'_>,
+        _app_id: String,
+        _options: HashMap<String, OwnedValue>,
+        #[zbus(object_server)] server: &ObjectServer,
+    ) -> (u32, Results) {
+        create_session(
+            server,
+            &self.requests,
+            &self.sessions,
+            session_handle,
+            Some(AVAILABLE_DEVICE_TYPES),
+        )
+        .await
+    }
+
+    async fn select_devices(
+        &self,
+        _handle: ObjectPath<'_>,
+        session_handle: ObjectPath<'_>,
+        _app_id: String,
+        options: HashMap<String, OwnedValue>,
+    ) -> (u32, Results) {
+        let types = options
+            .get("types")
+            .and_then(|types| u32::try_from(types.clone()).ok())
+            .map(|types| DeviceTypes::from_bits_truncate(types) & AVAILABLE_DEVICE_TYPES)
+            .unwrap_or(AVAILABLE_DEVICE_TYPES);
+
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let Some(session) = sessions
+            .sessions
+            .get_mut(&OwnedObjectPath::from(session_handle))
+            .filter(|session| session.devices.is_some())
+        else {
+            return (RESPONSE_OTHER, Results::new());
+        };
+
+        session.devices = Some(types);
+        (RESPONSE_SUCCESS, Results::new())
+    }
+
+    // Like starting a screen cast, this blocks the executor of the connection until the compositor replied.
+    #[allow(clippy::too_many_arguments)]
+    async fn start(
+        &self,
+        _handle: ObjectPath<'_>,
+        session_handle: ObjectPath<'_>,
+        app_id: String,
+        _parent_window: String,
+        _options: HashMap<String, OwnedValue>,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> (u32, Results) {
+        // The app id is only meaningful if the session is started by xdg-desktop-portal, since any client of the
+        // session bus may call the backend directly.
+        if !sent_by_portal(connection, &header).await {
+            tracing::warn!(%app_id, "Remote desktop session was not started by xdg-desktop-portal");
+            return (RESPONSE_OTHER, Results::new());
+        }
+
+        let granted = permission_granted(connection, &app_id).await;
+
+        let session = self
+            .sessions
+            .lock()
+            .unwrap()
+            .sessions
+            .get_mut(&OwnedObjectPath::from(session_handle))
+            .filter(|session| !session.started)
+            .and_then(|session| {
+                let devices = session.devices?;
+                session.started = true;
+                Some((session.id, devices, session.sources))
+            });
+
+        let Some((id, devices, sources)) = session else {
+            return (RESPONSE_OTHER, Results::new());
+        };
+
+        match self.start_session(id, devices, sources, app_id, granted) {
+            Ok(results) => (RESPONSE_SUCCESS, results),
+            Err(err) => {
+                tracing::warn!(%err, "Failed to start remote desktop session");
+                (RESPONSE_OTHER, Results::new())
+            }
+        }
+    }
+
+    async fn notify_pointer_motion(
+        &self,
+        session_handle: ObjectPath<'_>,
+        _options: HashMap<String, OwnedValue>,
+        dx: f64,
+        dy: f64,
+    ) {
+        self.notify(session_handle, DeviceTypes::POINTER, |session| PortalRequest::Inject {
+            session,
+            event: InjectedEvent::PointerMotion { dx, dy },
+        });
+    }
+
+    /// Move the pointer to a position relative to the cast output of the session.
+    ///
+    /// A session casts at most one stream, so the stream is not checked.
+    async fn notify_pointer_motion_absolute(
+        &self,
+        session_handle: ObjectPath<'_>,
+        _options: HashMap<String, OwnedValue>,
+        _stream: u32,
+        x: f64,
+        y: f64,
+    ) {
+        self.notify(session_handle, DeviceTypes::POINTER, |session| {
+            PortalRequest::InjectAbsolute {
+                session,
+                position: (x, y).into(),
+            }
+        });
+    }
+
+    async fn notify_pointer_button(
+        &self,
+        session_handle: ObjectPath<'_>,
+        _options: HashMap<String, OwnedValue>,
+        button: i32,
+        state: u32,
+    ) {
+        let (Ok(button), Some(state)) = (u32::try_from(button), button_state(state)) else {
+            return;
+        };
+
+        self.notify(session_handle, DeviceTypes::POINTER, |session| PortalRequest::Inject {
+            session,
+            event: InjectedEvent::PointerButton { button, state },
+        });
+    }
+
+    async fn notify_keyboard_keycode(
+        &self,
+        session_handle: ObjectPath<'_>,
+        _options: HashMap<String, OwnedValue>,
+        keycode: i32,
+        state: u32,
+    ) {
+        let (Ok(keycode), Some(state)) = (u32::try_from(keycode), key_state(state)) else {
+            return;
+        };
+
+        self.notify(session_handle, DeviceTypes::KEYBOARD, |session| PortalRequest::Inject {
+            session,
+            event: InjectedEvent::Key { keycode, state },
+        });
+    }
+
+    #[dbus_interface(property)]
+    fn available_device_types(&self) -> u32 {
+        AVAILABLE_DEVICE_TYPES.bits()
+    }
+
+    #[dbus_interface(property, name = "version")]
+    fn version(&self) -> u32 {
+        REMOTE_DESKTOP_VERSION
+    }
+}
+
+/// Whether a message was sent by `xdg-desktop-portal`.
+async fn sent_by_portal(connection: &Connection, header: &MessageHeader<'_>) -> bool {
+    let Ok(Some(sender)) = header.sender() else {
+        return false;
+    };
+
+    let Ok(proxy) = DBusProxy::new(connection).await else {
+        return false;
+    };
+
+    let name = BusName::WellKnown(WellKnownName::from_static_str_unchecked(PORTAL_FRONTEND));
+    proxy
+        .get_name_owner(name)
+        .await
+        .is_ok_and(|owner| owner.as_str() == sender.as_str())
+}
+
+/// Whether the permission store of `xdg-desktop-portal` grants an application remote desktop access.
+///
+/// An application without an entry is not granted access.
+async fn permission_granted(connection: &Connection, app_id: &str) -> bool {
+    let reply = connection
+        .call_method(
+            Some(PERMISSION_STORE),
+            PERMISSION_STORE_PATH,
+            Some(PERMISSION_STORE),
+            "Lookup",
+            &(PERMISSION_TABLE, PERMISSION_ID),
+        )
+        .await;
+
+    let permissions = reply.ok().and_then(|reply| {
+        reply
+            .body::<(HashMap<String, Vec<String>>, OwnedValue)>()
+            .ok()
+            .map(|(permissions, _data)| permissions)
+    });
+
+    permissions
+        .and_then(|mut permissions| permissions.remove(app_id))
+        .is_some_and(|permission| permission.iter().any(|permission| permission == "yes"))
+}
+
+fn button_state(state: u32) -> Option<ButtonState> {
+    match state {
+        0 => Some(ButtonState::Released),
+        1 => Some(ButtonState::Pressed),
+        _ => None,
+    }
+}
+
+fn key_state(state: u32) -> Option<KeyState> {
+    match state {
+        0 => Some(KeyState::Released),
+        1 => Some(KeyState::Pressed),
+        _ => None,
+    }
+}
@@ -0,0 +1,378 @@
+//! PipeWire streams of screen casts
+//!
+//! PipeWire objects may only be used on the thread running the PipeWire main loop, so the streams live on a
+//! dedicated thread. The compositor sends commands and frames to the thread through a PipeWire channel, and each
+//! stream is represented on the compositor side by a [`StreamSink`].
+
+use std::{
+    cell::RefCell,
+    fmt,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use pipewire::{
+    context::Context,
+    core::Core,
+    main_loop::MainLoop,
+    properties::properties,
+    spa::{
+        self,
+        param::{
+            format::{FormatProperties, MediaSubtype, MediaType},
+            video::VideoFormat,
+            ParamType,
+        },
+        pod::{serialize::PodSerializer, Object, Pod, Value},
+        utils::{Direction, Fraction, Rectangle, SpaTypes},
+    },
+    stream::{Stream, StreamFlags, StreamListener, StreamState},
+};
+use rustc_hash::FxHashMap;
+use smithay::utils::{Physical, Size};
+
+use super::{copy_frame, Frame, FrameSink, BYTES_PER_PIXEL};
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum StreamError {
+    #[error("the PipeWire thread is not running")]
+    Disconnected,
+
+    #[error("PipeWire error: {0}")]
+    PipeWire(String),
+}
+
+impl From<pipewire::Error> for StreamError {
+    fn from(err: pipewire::Error) -> Self {
+        Self::PipeWire(err.to_string())
+    }
+}
+
+/// The state of a stream shared between the PipeWire thread and the [`StreamSink`].
+#[derive(Debug, Default)]
+struct SharedState {
+    /// Whether a consumer is connected and the stream is streaming.
+    active: AtomicBool,
+    closed: AtomicBool,
+}
+
+enum Command {
+    Create {
+        id: u64,
+        size: Size<i32, Physical>,
+        state: Arc<SharedState>,
+
+        /// Receives the id of the PipeWire node once the stream was created.
+        reply: mpsc::Sender<Result<u32, StreamError>>,
+    },
+
+    Frame {
+        id: u64,
+        frame: Frame,
+    },
+
+    Destroy {
+        id: u64,
+    },
+
+    Terminate,
+}
+
+/// The thread running the PipeWire main loop.
+pub struct PipeWire {
+    sender: pipewire::channel::Sender<Command>,
+    thread: Option<JoinHandle<()>>,
+    next_id: u64,
+}
+
+impl PipeWire {
+    pub fn spawn() -> Result<Self, StreamError> {
+        let (sender, receiver) = pipewire::channel::channel();
+        let (init_send, init_recv) = mpsc::channel();
+
+        let thread = thread::Builder::new()
+            .name("aerugo-pipewire".into())
+            .spawn(move || {
+                if let Err(err) = run(receiver, &init_send) {
+                    let _ = init_send.send(Err(err));
+                }
+            })
+            .map_err(|err| StreamError::PipeWire(err.to_string()))?;
+
+        init_recv.recv().map_err(|_| StreamError::Disconnected)??;
+
+        Ok(Self {
+            sender,
+            thread: Some(thread),
+            next_id: 0,
+        })
+    }
+
+    /// Create a stream producing frames of the specified size.
+    ///
+    /// Returns the sink for the frames of the stream and a receiver for the id of the PipeWire node of the stream.
+    pub fn create_stream(
+        &mut self,
+        size: Size<i32, Physical>,
+    ) -> Result<(StreamSink, mpsc::Receiver<Result<u32, StreamError>>), StreamError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let state = Arc::new(SharedState::default());
+        let (reply, node) = mpsc::channel();
+
+        self.sender
+            .send(Command::Create {
+                id,
+                size,
+                state: state.clone(),
+                reply,
+            })
+            .map_err(|_| StreamError::Disconnected)?;
+
+        let sink = StreamSink {
+            id,
+            sender: self.sender.clone(),
+            state,
+        };
+
+        Ok((sink, node))
+    }
+}
+
+impl fmt::Debug for PipeWire {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PipeWire")
+            .field("next_id", &self.next_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for PipeWire {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Command::Terminate);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Sends the frames of a screen cast to a PipeWire stream.
+///
+/// The stream is destroyed when the sink is dropped.
+pub struct StreamSink {
+    id: u64,
+    sender: pipewire::channel::Sender<Command>,
+    state: Arc<SharedState>,
+}
+
+impl fmt::Debug for StreamSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamSink")
+            .field("id", &self.id)
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FrameSink for StreamSink {
+    fn is_active(&self) -> bool {
+        self.state.active.load(Ordering::Acquire)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state.closed.load(Ordering::Acquire)
+    }
+
+    fn submit(&mut self, frame: Frame) {
+        if self.sender.send(Command::Frame { id: self.id, frame }).is_err() {
+            self.state.closed.store(true, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for StreamSink {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Command::Destroy { id: self.id });
+    }
+}
+
+struct CastStream {
+    stream: Stream,
+    _listener: StreamListener<Option<mpsc::Sender<Result<u32, StreamError>>>>,
+    size: Size<i32, Physical>,
+}
+
+impl CastStream {
+    fn new(
+        core: &Core,
+        size: Size<i32, Physical>,
+        state: Arc<SharedState>,
+        reply: mpsc::Sender<Result<u32, StreamError>>,
+    ) -> Result<Self, StreamError> {
+        let stream = Stream::new(
+            core,
+            "aerugo-screencast",
+            properties! {
+                *pipewire::keys::MEDIA_TYPE => "Video",
+                *pipewire::keys::MEDIA_CATEGORY => "Capture",
+                *pipewire::keys::MEDIA_ROLE => "Screen",
+            },
+        )?;
+
+        let listener = stream
+            .add_local_listener_with_user_data(Some(reply))
+            .state_changed(move |stream, reply, _, new| match new {
+                // The node exists once the stream is paused, waiting for a consumer.
+                StreamState::Paused => {
+                    state.active.store(false, Ordering::Release);
+
+                    if let Some(reply) = reply.take() {
+                        let _ = reply.send(Ok(stream.node_id()));
+                    }
+                }
+
+                StreamState::Streaming => state.active.store(true, Ordering::Release),
+
+                StreamState::Error(err) => {
+                    tracing::warn!(%err, "Screen cast stream failed");
+                    state.active.store(false, Ordering::Release);
+                    state.closed.store(true, Ordering::Release);
+
+                    if let Some(reply) = reply.take() {
+                        let _ = reply.send(Err(StreamError::PipeWire(err)));
+                    }
+                }
+
+                StreamState::Unconnected => {
+                    state.active.store(false, Ordering::Release);
+                    state.closed.store(true, Ordering::Release);
+                }
+
+                StreamState::Connecting => (),
+            })
+            .register()?;
+
+        let format = format_param(size)?;
+        let mut params = [Pod::from_bytes(&format).ok_or_else(|| StreamError::PipeWire("invalid format".into()))?];
+
+        stream.connect(
+            Direction::Output,
+            None,
+            StreamFlags::DRIVER | StreamFlags::ALLOC_BUFFERS | StreamFlags::MAP_BUFFERS,
+            &mut params,
+        )?;
+
+        Ok(Self {
+            stream,
+            _listener: listener,
+            size,
+        })
+    }
+
+    fn submit(&mut self, frame: &Frame) {
+        // Without a free buffer the consumer is behind, so the frame is dropped.
+        let Some(mut buffer) = self.stream.dequeue_buffer() else {
+            return;
+        };
+
+        let Some(data) = buffer.datas_mut().first_mut() else {
+            return;
+        };
+
+        let stride = self.size.w as usize * BYTES_PER_PIXEL;
+        let written = data
+            .data()
+            .map(|dest| copy_frame(frame, dest, self.size, stride))
+            .unwrap_or(0);
+
+        let chunk = data.chunk_mut();
+        *chunk.offset_mut() = 0;
+        *chunk.stride_mut() = stride as i32;
+        *chunk.size_mut() = written as u32;
+    }
+}
+
+/// The format offered to consumers: RGBA frames of a fixed size at a variable frame rate.
+fn format_param(size: Size<i32, Physical>) -> Result<Vec<u8>, StreamError> {
+    let object = Object {
+        type_: SpaTypes::ObjectParamFormat.as_raw(),
+        id: ParamType::EnumFormat.as_raw(),
+        properties: vec![
+            spa::pod::property!(FormatProperties::MediaType, Id, MediaType::Video),
+            spa::pod::property!(FormatProperties::MediaSubtype, Id, MediaSubtype::Raw),
+            spa::pod::property!(FormatProperties::VideoFormat, Id, VideoFormat::RGBA),
+            spa::pod::property!(
+                FormatProperties::VideoSize,
+                Rectangle,
+                Rectangle {
+                    width: size.w as u32,
+                    height: size.h as u32,
+                }
+            ),
+            spa::pod::property!(
+                FormatProperties::VideoFramerate,
+                Fraction,
+                Fraction { num: 0, denom: 1 }
+            ),
+        ],
+    };
+
+    PodSerializer::serialize(std::io::Cursor::new(Vec::new()), &Value::Object(object))
+        .map(|(cursor, _)| cursor.into_inner())
+        .map_err(|err| StreamError::PipeWire(format!("{err:?}")))
+}
+
+fn run(
+    receiver: pipewire::channel::Receiver<Command>,
+    init: &mpsc::Sender<Result<(), StreamError>>,
+) -> Result<(), StreamError> {
+    pipewire::init();
+
+    let main_loop = MainLoop::new(None)?;
+    let context = Context::new(&main_loop)?;
+    let core = context.connect(None)?;
+    let streams = Rc::new(RefCell::new(FxHashMap::<u64, CastStream>::default()));
+
+    let _receiver = receiver.attach(main_loop.loop_(), {
+        let main_loop = main_loop.clone();
+
+        move |command| match command {
+            Command::Create { id, size, state, reply } => {
+                match CastStream::new(&core, size, state.clone(), reply.clone()) {
+                    Ok(stream) => {
+                        streams.borrow_mut().insert(id, stream);
+                    }
+
+                    Err(err) => {
+                        state.closed.store(true, Ordering::Release);
+                        let _ = reply.send(Err(err));
+                    }
+                }
+            }
+
+            Command::Frame { id, frame } => {
+                if let Some(stream) = streams.borrow_mut().get_mut(&id) {
+                    stream.submit(&frame);
+                }
+            }
+
+            Command::Destroy { id } => {
+                if let Some(stream) = streams.borrow_mut().remove(&id) {
+                    let _ = stream.stream.disconnect();
+                }
+            }
+
+            Command::Terminate => main_loop.quit(),
+        }
+    });
+
+    let _ = init.send(Ok(()));
+    main_loop.run();
+    Ok(())
+}
@@ -0,0 +1,213 @@
+//! Toplevel screenshots for the wm
+//!
+//! The wm may request an image of a toplevel, for example to show thumbnails in a task switcher or to save
+//! thumbnails for session restore. Requests are rate limited per toplevel by the wm runtime and are queued until
+//! the backend renders the next frame. The backend then renders each toplevel offscreen at the requested size and
+//! reads the pixels back using [`submit_screenshots`].
+
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            gles::{GlesRenderer, GlesTexture},
+            utils::{draw_render_elements, import_surface_tree, with_renderer_surface_state},
+            Bind, ExportMem, Frame, Offscreen, Renderer, Unbind,
+        },
+    },
+    utils::{Physical, Rectangle, Size, Transform},
+};
+use wayland_server::protocol::wl_surface::WlSurface;
+use wm_runtime::{Id, ScreenshotImage, WmEvent};
+
+use crate::{
+    scene::SceneGraphElement,
+    shell::{Shell, ToplevelId},
+    wm::WmSupervisor,
+};
+
+/// Format of the pixels read back from the renderer.
+///
+/// This matches the RGBA byte order the wm expects.
+const SCREENSHOT_FORMAT: Fourcc = Fourcc::Abgr8888;
+
+/// A screenshot waiting for the next frame to be rendered.
+#[derive(Debug)]
+pub struct PendingScreenshot {
+    pub toplevel: ToplevelId,
+
+    /// The id the wm uses for the toplevel.
+    pub wm_id: Id,
+    pub serial: u32,
+    pub max_size: Size<i32, Physical>,
+}
+
+#[derive(Debug, Default)]
+pub struct ScreenshotState {
+    pending: Vec<PendingScreenshot>,
+}
+
+impl ScreenshotState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn request(&mut self, screenshot: PendingScreenshot) {
+        self.pending.push(screenshot);
+    }
+
+    /// Forget screenshots of a toplevel which was closed.
+    pub fn remove_toplevel(&mut self, toplevel: ToplevelId) {
+        self.pending.retain(|screenshot| screenshot.toplevel != toplevel);
+    }
+
+    pub fn take_pending(&mut self) -> Vec<PendingScreenshot> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScreenshotError {
+    #[error("toplevel is not mapped")]
+    NotMapped,
+
+    #[error("failed to render the toplevel")]
+    Render,
+
+    #[error("failed to read back the rendered toplevel")]
+    Export,
+}
+
+/// Take the pending screenshots and send the results to the wm.
+///
+/// This binds an offscreen buffer, so the backend must bind its framebuffer again before rendering.
+pub fn submit_screenshots(
+    renderer: &mut GlesRenderer,
+    shell: &Shell,
+    wm: &mut WmSupervisor,
+    screenshots: Vec<PendingScreenshot>,
+) {
+    for screenshot in screenshots {
+        let surface = shell
+            .get_state(screenshot.toplevel)
+            .and_then(|toplevel| toplevel.wl_surface());
+
+        let image = surface
+            .ok_or(ScreenshotError::NotMapped)
+            .and_then(|surface| capture(renderer, &surface, screenshot.max_size));
+
+        let image = match image {
+            Ok(image) => Some(image),
+            Err(err) => {
+                tracing::debug!(%err, toplevel = %screenshot.toplevel, "Failed to take screenshot of toplevel");
+                None
+            }
+        };
+
+        wm.send(WmEvent::ToplevelScreenshot {
+            toplevel: screenshot.wm_id,
+            serial: screenshot.serial,
+            image,
+        });
+    }
+}
+
+/// Render a toplevel offscreen, scaled down to fit within `max_size`, and read back the pixels.
+pub fn capture(
+    renderer: &mut GlesRenderer,
+    surface: &WlSurface,
+    max_size: Size<i32, Physical>,
+) -> Result<ScreenshotImage, ScreenshotError> {
+    import_surface_tree(renderer, surface).map_err(|_| ScreenshotError::Render)?;
+
+    let surface_size =
+        with_renderer_surface_state(surface, |state| state.surface_size()).ok_or(ScreenshotError::NotMapped)?;
+    // TODO: Account for the buffer scale of the surface.
+    let size = fit(Size::from((surface_size.w, surface_size.h)), max_size).ok_or(ScreenshotError::NotMapped)?;
+
+    let texture: GlesTexture = renderer
+        .create_buffer(SCREENSHOT_FORMAT, (size.w, size.h).into())
+        .map_err(|_| ScreenshotError::Render)?;
+    renderer.bind(texture).map_err(|_| ScreenshotError::Render)?;
+
+    let result = render_and_read(renderer, surface, size);
+    let _ = renderer.unbind();
+    result
+}
+
+fn render_and_read(
+    renderer: &mut GlesRenderer,
+    surface: &WlSurface,
+    size: Size<i32, Physical>,
+) -> Result<ScreenshotImage, ScreenshotError> {
+    let area = Rectangle::from_loc_and_size((0, 0), size);
+    let elements = [SceneGraphElement::scaled(surface, area)];
+
+    {
+        let mut frame = renderer
+            .render(size, Transform::Normal)
+            .map_err(|_| ScreenshotError::Render)?;
+        frame
+            .clear([0.0, 0.0, 0.0, 0.0], &[area])
+            .map_err(|_| ScreenshotError::Render)?;
+        draw_render_elements::<GlesRenderer, _, _>(&mut frame, 1.0, &elements, &[area])
+            .map_err(|_| ScreenshotError::Render)?;
+        frame.finish().map_err(|_| ScreenshotError::Render)?;
+    }
+
+    let mapping = renderer
+        .copy_framebuffer(Rectangle::from_loc_and_size((0, 0), (size.w, size.h)))
+        .map_err(|_| ScreenshotError::Export)?;
+    let data = renderer
+        .map_texture(&mapping)
+        .map_err(|_| ScreenshotError::Export)?
+        .to_vec();
+
+    Ok(ScreenshotImage {
+        width: size.w as u32,
+        height: size.h as u32,
+        data,
+    })
+}
+
+/// Scale a size down to fit within `max` while preserving the aspect ratio.
+///
+/// Sizes which already fit are not scaled up. Returns [`None`] if either size is empty.
+fn fit(size: Size<i32, Physical>, max: Size<i32, Physical>) -> Option<Size<i32, Physical>> {
+    if size.w <= 0 || size.h <= 0 || max.w <= 0 || max.h <= 0 {
+        return None;
+    }
+
+    let scale = f64::min(max.w as f64 / size.w as f64, max.h as f64 / size.h as f64).min(1.0);
+    let w = ((size.w as f64 * scale).round() as i32).max(1);
+    let h = ((size.h as f64 * scale).round() as i32).max(1);
+
+    Some((w, h).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::utils::{Physical, Size};
+
+    use super::fit;
+
+    fn size(w: i32, h: i32) -> Size<i32, Physical> {
+        (w, h).into()
+    }
+
+    #[test]
+    fn fit_preserves_aspect_ratio() {
+        assert_eq!(fit(size(1920, 1080), size(480, 480)), Some(size(480, 270)));
+        assert_eq!(fit(size(1080, 1920), size(480, 480)), Some(size(270, 480)));
+    }
+
+    #[test]
+    fn fit_never_upscales() {
+        assert_eq!(fit(size(200, 100), size(1024, 1024)), Some(size(200, 100)));
+    }
+
+    #[test]
+    fn fit_empty() {
+        assert_eq!(fit(size(0, 100), size(100, 100)), None);
+        assert_eq!(fit(size(100, 100), size(0, 0)), None);
+    }
+}
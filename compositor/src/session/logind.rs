@@ -0,0 +1,296 @@
+//! systemd-logind integration
+//!
+//! The display server takes control of the logind session it runs in, which makes logind hand out the devices of
+//! the session to the display server and switch sessions on its behalf, without going through libseat. Taking
+//! control fails if another display server already controls the session, for example while running nested, in
+//! which case the signals of the session are still handled.
+//!
+//! While the session should be locked before the system sleeps, the display server holds a delay inhibitor. When
+//! logind announces the system is about to sleep, the session is locked and the inhibitor is released once a
+//! locked frame was presented, so no client content is visible when the system resumes.
+//!
+//! When logind removes the session, for example because it was terminated, the display server shuts down.
+//!
+//! logind is called synchronously from the event loop, the signals are received on separate threads and
+//! forwarded to the event loop.
+
+use std::{fmt, io, thread};
+
+use calloop::{
+    channel::{self, Event, Sender},
+    LoopHandle,
+};
+use zbus::{
+    dbus_proxy,
+    zvariant::{OwnedFd, OwnedObjectPath},
+};
+
+use crate::{shutdown::ShutdownReason, Loop};
+
+#[derive(Debug, thiserror::Error)]
+pub enum LogindError {
+    #[error(transparent)]
+    Dbus(#[from] zbus::Error),
+
+    #[error("failed to receive signals: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("failed to register logind with the event loop")]
+    Loop,
+}
+
+/// A signal of logind handled by the event loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogindEvent {
+    /// logind asked the session be locked, for example by `loginctl lock-session`.
+    Lock,
+
+    /// logind asked the session be unlocked, for example by `loginctl unlock-session`.
+    Unlock,
+
+    /// The system is about to sleep, or resumed if `start` is false.
+    PrepareForSleep { start: bool },
+
+    /// The session of the display server was removed, for example by `loginctl terminate-session`.
+    SessionRemoved,
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait Manager {
+    fn get_session(&self, session_id: &str) -> zbus::Result<OwnedObjectPath>;
+
+    fn get_session_by_pid(&self, pid: u32) -> zbus::Result<OwnedObjectPath>;
+
+    fn inhibit(&self, what: &str, who: &str, why: &str, mode: &str) -> zbus::Result<OwnedFd>;
+
+    #[dbus_proxy(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn session_removed(&self, session_id: &str, object_path: OwnedObjectPath) -> zbus::Result<()>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1"
+)]
+trait Session {
+    fn take_control(&self, force: bool) -> zbus::Result<()>;
+
+    fn release_control(&self) -> zbus::Result<()>;
+
+    fn set_type(&self, r#type: &str) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn lock(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn unlock(&self) -> zbus::Result<()>;
+}
+
+/// The logind session of the display server.
+pub struct Logind {
+    manager: ManagerProxyBlocking<'static>,
+    session: SessionProxyBlocking<'static>,
+
+    /// Whether the display server controls the session.
+    controls_session: bool,
+
+    /// The sleep inhibitor, while held.
+    inhibitor: Option<OwnedFd>,
+
+    /// Whether the system waits for the session to be locked before sleeping.
+    sleep_pending: bool,
+}
+
+impl fmt::Debug for Logind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Logind")
+            .field("session", &self.session.path())
+            .field("controls_session", &self.controls_session)
+            .field("inhibited", &self.inhibitor.is_some())
+            .field("sleep_pending", &self.sleep_pending)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Logind {
+    /// Connect to logind and take control of the session of the display server.
+    pub fn connect(r#loop: &LoopHandle<'static, Loop>) -> Result<Self, LogindError> {
+        let connection = zbus::blocking::Connection::system()?;
+        let manager = ManagerProxyBlocking::new(&connection)?;
+
+        // The session is inherited from the login, but the display server may be started from a unit outside of
+        // any session, in which case the session is named in the environment.
+        let path = match std::env::var("XDG_SESSION_ID") {
+            Ok(id) => manager.get_session(&id)?,
+            Err(_) => manager.get_session_by_pid(std::process::id())?,
+        };
+        let session = SessionProxyBlocking::builder(&connection).path(path)?.build()?;
+
+        let controls_session = match session.take_control(false) {
+            Ok(()) => {
+                if let Err(err) = session.set_type("wayland") {
+                    tracing::warn!(%err, "Failed to set the type of the session");
+                }
+
+                true
+            }
+
+            Err(err) => {
+                tracing::warn!(%err, "Failed to take control of the session");
+                false
+            }
+        };
+
+        let (sender, channel) = channel::channel();
+        r#loop
+            .insert_source(channel, |event, _, state| {
+                if let Event::Msg(event) = event {
+                    state.handle_logind_event(event);
+                }
+            })
+            .map_err(|_| LogindError::Loop)?;
+
+        forward(
+            "sleep",
+            manager.receive_prepare_for_sleep()?,
+            sender.clone(),
+            |signal| {
+                let start = *signal.args().ok()?.start();
+                Some(LogindEvent::PrepareForSleep { start })
+            },
+        )?;
+        let path = session.path().to_string();
+        forward(
+            "session",
+            manager.receive_session_removed()?,
+            sender.clone(),
+            move |signal| {
+                let args = signal.args().ok()?;
+                (args.object_path().as_str() == path).then_some(LogindEvent::SessionRemoved)
+            },
+        )?;
+        forward("lock", session.receive_lock()?, sender.clone(), |_| {
+            Some(LogindEvent::Lock)
+        })?;
+        forward("unlock", session.receive_unlock()?, sender, |_| {
+            Some(LogindEvent::Unlock)
+        })?;
+
+        tracing::info!(session = %session.path(), controls_session, "Connected to logind");
+
+        Ok(Self {
+            manager,
+            session,
+            controls_session,
+            inhibitor: None,
+            sleep_pending: false,
+        })
+    }
+
+    /// Hold or release the sleep inhibitor, depending on whether the session is locked before sleeping.
+    pub fn set_lock_before_sleep(&mut self, lock_before_sleep: bool) {
+        if !lock_before_sleep {
+            self.inhibitor = None;
+            return;
+        }
+
+        if self.inhibitor.is_some() || self.sleep_pending {
+            return;
+        }
+
+        match self
+            .manager
+            .inhibit("sleep", "Aerugo", "Lock the session before sleeping", "delay")
+        {
+            Ok(inhibitor) => self.inhibitor = Some(inhibitor),
+            Err(err) => tracing::warn!(%err, "Failed to inhibit sleep"),
+        }
+    }
+}
+
+impl Drop for Logind {
+    fn drop(&mut self) {
+        if self.controls_session {
+            if let Err(err) = self.session.release_control() {
+                tracing::warn!(%err, "Failed to release control of the session");
+            }
+        }
+    }
+}
+
+/// Forward the signals of logind to the event loop on a separate thread.
+fn forward<S>(
+    name: &str,
+    signals: impl Iterator<Item = S> + Send + 'static,
+    sender: Sender<LogindEvent>,
+    event: impl Fn(S) -> Option<LogindEvent> + Send + 'static,
+) -> io::Result<()> {
+    thread::Builder::new()
+        .name(format!("Aerugo logind {name}"))
+        .spawn(move || {
+            for event in signals.filter_map(event) {
+                // The event loop stopped.
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+impl Loop {
+    pub fn handle_logind_event(&mut self, event: LogindEvent) {
+        tracing::debug!(?event, "logind event");
+        let session = &self.config.session;
+
+        match event {
+            LogindEvent::Lock => {
+                self.comp.lock_session(&session.lock_command);
+            }
+
+            LogindEvent::Unlock => self.comp.unlock_session(),
+
+            LogindEvent::PrepareForSleep { start: true } => {
+                let locked = session.lock_before_sleep && self.comp.lock_session(&session.lock_command);
+
+                if let Some(logind) = &mut self.logind {
+                    // Sleep is delayed until the lock was presented, see refresh_logind.
+                    logind.sleep_pending = locked;
+
+                    if !locked {
+                        logind.inhibitor = None;
+                    }
+                }
+            }
+
+            LogindEvent::PrepareForSleep { start: false } => {
+                if let Some(logind) = &mut self.logind {
+                    logind.sleep_pending = false;
+                    logind.set_lock_before_sleep(session.lock_before_sleep);
+                }
+            }
+
+            LogindEvent::SessionRemoved => self.shutdown(ShutdownReason::SessionTerminated),
+        }
+    }
+
+    /// Let the system sleep once the session is locked.
+    pub fn refresh_logind(&mut self) {
+        let Some(logind) = self.logind.as_mut().filter(|logind| logind.sleep_pending) else {
+            return;
+        };
+
+        if self.comp.session_lock.is_presented() {
+            tracing::debug!("Session locked, releasing sleep inhibitor");
+            logind.sleep_pending = false;
+            logind.inhibitor = None;
+        }
+    }
+}
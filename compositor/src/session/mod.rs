@@ -0,0 +1,98 @@
+//! Session management
+//!
+//! Once the display server accepts clients, the service manager is notified using the `sd_notify` protocol, so a
+//! `Type=notify` unit of the display server is only considered started once clients can connect. Programs started
+//! by the display server do not inherit the notification socket.
+//!
+//! With the `logind` feature, the display server integrates with the systemd-logind session it runs in, see
+//! [`Logind`]. If a lock command is configured, the session is locked when logind asks for it and optionally
+//! before the system sleeps.
+
+#[cfg(feature = "logind")]
+mod logind;
+
+use std::{
+    ffi::OsStr,
+    io,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram},
+        },
+    },
+    path::Path,
+};
+
+#[cfg(feature = "logind")]
+pub use logind::Logind;
+
+/// Name of the environment variable the service manager passes the notification socket in.
+pub const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+
+/// Notify the service manager that the display server is ready, if the display server runs as a notify service.
+pub fn notify_ready() {
+    let Some(socket) = std::env::var_os(NOTIFY_SOCKET_ENV) else {
+        return;
+    };
+
+    std::env::remove_var(NOTIFY_SOCKET_ENV);
+
+    match notify(&socket, "READY=1\n") {
+        Ok(()) => tracing::info!("Notified the service manager of readiness"),
+        Err(err) => tracing::warn!(%err, "Failed to notify the service manager of readiness"),
+    }
+}
+
+/// Send a state to the notification socket.
+///
+/// A socket starting with `@` is in the abstract namespace.
+fn notify(socket: &OsStr, state: &str) -> io::Result<()> {
+    let datagram = UnixDatagram::unbound()?;
+
+    match socket.as_bytes().strip_prefix(b"@") {
+        Some(name) => datagram.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?,
+        None => datagram.send_to(state.as_bytes(), Path::new(socket))?,
+    };
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::OsStr,
+        os::{
+            linux::net::SocketAddrExt,
+            unix::net::{SocketAddr, UnixDatagram},
+        },
+    };
+
+    use super::notify;
+
+    #[test]
+    fn notify_path() {
+        let path = std::env::temp_dir().join(format!("aerugo-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        notify(path.as_os_str(), "READY=1\n").unwrap();
+
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn notify_abstract() {
+        let name = format!("aerugo-notify-{}", std::process::id());
+        let receiver = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+
+        notify(OsStr::new(&format!("@{name}")), "READY=1\n").unwrap();
+
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1\n");
+    }
+}
@@ -36,11 +36,18 @@
 //!
 //! # Transactions
 //!
-//! **TODO**
+//! A configure sent on behalf of the wm is tracked until the client acks the configure. A client which does not
+//! ack a configure in time is pinged, and the configure is cancelled or treated as acked depending on the
+//! [`TimeoutPolicy`](crate::config::TimeoutPolicy). This way a client which never responds cannot stall the wm.
+//!
+//! Acking a configure implicitly acks every older configure, so the wm is told about the newest acked configure
+//! combined with the older ones. A client acking a configure the wm no longer waits on is acking a stale
+//! configure, which is not forwarded to the wm.
 //!
 //! # Window management
 //!
-//! **TODO**
+//! After the initial commit a toplevel is announced to the wm, which sends the initial configure. While no wm is
+//! running, the [fallback wm](crate::wm::fallback) configures and places toplevels instead.
 
 #![allow(dead_code)]
 
@@ -59,36 +66,54 @@ First the WM creates a graph to describe what is desired to be posted to a displ
 nodes. The WM may need to change the state of a window however to apply this new state. However the surface
 update may take some time. Furthermore the WM state applying before the surface state or vice versa would
 cause issues. To solve this we ensure that changes to the WM state are commited once the window states have
-been committed. (TODO: How do we handle windows which refuse to respond? We could ping the client to test for
-that in the transaction).
+been committed. Windows which refuse to respond are pinged and the transaction times out.
 
 If the clients fail to commit the previous transaction states, should the WM's next state override the current
 client state, and cancel the previous transaction?
 */
 
-use std::{fmt, num::NonZeroU64, sync::Arc};
+use std::{
+    fmt,
+    num::NonZeroU64,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use rustc_hash::FxHashMap;
 use smithay::{
-    backend::renderer::utils::with_renderer_surface_state,
-    utils::{Logical, Serial, Size},
+    backend::renderer::utils::{with_renderer_surface_state, RendererSurfaceStateUserData},
+    output::Output,
+    reexports::wayland_protocols::xdg::{
+        decoration::zv1::server::zxdg_toplevel_decoration_v1,
+        shell::server::{xdg_surface, xdg_toplevel},
+    },
+    utils::{Logical, Point, Rectangle, Serial, Size, SERIAL_COUNTER},
     wayland::{
-        compositor::{self, SurfaceAttributes, TraversalAction},
+        compositor::{self, SubsurfaceCachedState, SurfaceAttributes, TraversalAction},
         shell::{
             wlr_layer,
-            xdg::{ToplevelSurface, XdgToplevelSurfaceData},
+            xdg::{ShellClient, SurfaceCachedState, ToplevelSurface, XdgToplevelSurfaceData, XDG_TOPLEVEL_ROLE},
         },
     },
     xwayland::X11Surface,
 };
 use wayland_server::{backend::ObjectId, protocol::wl_surface::WlSurface, Client, DisplayHandle, Resource};
+use wm_runtime::{
+    geometry::LogicalSize, AckedConfigure, ConfigureRequest, ConfigureUpdate, DecorationMode, Features, SnapshotInfo,
+    StateRequest, ToplevelState, ToplevelUpdate, WmEvent, MAX_CONFIGURE_SIZE,
+};
 
 use crate::{
-    wayland::ext::foreign_toplevel::{
-        ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1,
-        ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1,
+    rules::{self, Decorations, WindowRule},
+    scene::{NodeIndex, Scene},
+    wayland::ext::{
+        foreign_toplevel::{
+            ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1,
+            ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1,
+        },
+        foreign_toplevel_state::{ext_foreign_toplevel_state_v1, ExtForeignToplevelStateV1},
     },
-    Aerugo,
+    wm, Aerugo,
 };
 
 /// A surface with some assigned role.
@@ -139,12 +164,23 @@ pub struct Shell {
     /// State related to instances of the foreign toplevel protocols and extension protocols.
     pub foreign_toplevel_instances: FxHashMap<ObjectId, ForeignToplevelInstance>,
 
+    /// The xdg_surface creating a toplevel while the request is dispatched.
+    pub creating_toplevel: Option<xdg_surface::XdgSurface>,
+
     next_toplevel_id: ToplevelId,
+
+    /// Counter used to allocate foreign toplevel identifiers.
+    next_foreign_identifier: u64,
 }
 
 #[derive(Debug)]
 pub struct ForeignToplevelInstance {
     pub instance: ExtForeignToplevelListV1,
+
+    /// Whether the client stopped the instance.
+    ///
+    /// A stopped instance was sent `finished` and is not told about new toplevels. The handles created before
+    /// the instance was stopped stay valid until the client destroys them.
     pub stopped: bool,
 }
 
@@ -182,55 +218,399 @@ pub struct Toplevel {
     /// This is updated when the configure is acked.
     pending: Option<Mapped>,
 
+    /// Identifier sent to every foreign toplevel handle of this toplevel.
+    ///
+    /// A new identifier is allocated every time the toplevel is mapped again, since identifiers must not be
+    /// reused once the toplevel was unmapped.
+    identifier: String,
+
     /// Foreign handles to this toplevel.
     handles: FxHashMap<ObjectId, ToplevelHandles>,
+
+    /// The states last configured by the wm.
+    states: ToplevelState,
+
+    /// Whether the wm minimized the toplevel.
+    minimized: bool,
+
+    /// Configures sent on behalf of the wm which have not been acked yet.
+    wm_configures: WmConfigures,
+
+    /// Whether the client was pinged and has not responded yet.
+    pinged: bool,
+
+    /// The size and scale of the last snapshot given to the wm.
+    snapshot: Option<SnapshotInfo>,
     // TODO: xdg-foreign id?
 }
 
+/// A configure sent on behalf of the wm.
+#[derive(Debug)]
+struct WmConfigure {
+    /// Serial of the configure sent to the client.
+    serial: Serial,
+
+    /// Serial the wm was given for the configure.
+    wm_serial: u32,
+
+    /// The configure the wm submitted.
+    request: ConfigureRequest,
+
+    sent: Instant,
+}
+
+impl WmConfigure {
+    fn into_acked(self) -> AckedConfigure {
+        AckedConfigure {
+            serial: self.wm_serial,
+            request: self.request,
+        }
+    }
+}
+
+/// The configures sent to a toplevel on behalf of the wm.
+#[derive(Debug, Default)]
+struct WmConfigures {
+    /// Configures which were not acked yet, oldest first.
+    outstanding: Vec<WmConfigure>,
+
+    /// The serial of the newest configure which was acked or which the wm no longer waits on.
+    retired: Option<Serial>,
+}
+
+/// How an ack of a toplevel matched the configures sent on behalf of the wm.
+#[derive(Debug)]
+pub enum Ack {
+    /// The wm is waiting for the configure to be acked.
+    Wm(AckedConfigure),
+
+    /// The configure was sent on behalf of the wm, but the wm no longer waits on the configure since the
+    /// configure was cancelled, timed out or a newer configure was acked.
+    Stale,
+
+    /// The configure was not sent on behalf of the wm.
+    Untracked,
+}
+
+impl WmConfigures {
+    fn push(&mut self, configure: WmConfigure) {
+        self.outstanding.push(configure);
+    }
+
+    /// Match an ack to the configure with the specified serial.
+    ///
+    /// Older configures are acked implicitly and combined into the acked configure.
+    fn acked(&mut self, serial: Serial) -> Ack {
+        let Some(index) = self.outstanding.iter().position(|configure| configure.serial == serial) else {
+            return match self.retired {
+                Some(retired) if serial <= retired => Ack::Stale,
+                _ => Ack::Untracked,
+            };
+        };
+
+        self.retired = Some(serial);
+        let mut acked = self.outstanding.drain(..=index).map(WmConfigure::into_acked);
+        let first = acked.next().unwrap();
+
+        Ack::Wm(acked.fold(first, |older, newer| AckedConfigure {
+            serial: newer.serial,
+            request: older.request.merge(newer.request),
+        }))
+    }
+
+    /// Stop waiting on the oldest configures.
+    fn retire(&mut self, count: usize) -> Vec<WmConfigure> {
+        let retired: Vec<_> = self.outstanding.drain(..count).collect();
+
+        if let Some(configure) = retired.last() {
+            self.retired = Some(configure.serial);
+        }
+
+        retired
+    }
+}
+
+/// A foreign toplevel handle and the extension objects created for it.
 #[derive(Debug)]
 pub struct ToplevelHandles {
     pub handle: ExtForeignToplevelHandleV1,
+
+    /// The list instance which created the handle.
+    pub instance: ObjectId,
+
+    pub state: Option<ExtForeignToplevelStateV1>,
+
+    /// The state last sent to the handle.
+    ///
+    /// Extension objects created later are initialized from this state, so every object of the handle
+    /// describes the same state.
+    sent: HandleState,
+}
+
+/// The state of a toplevel described by a foreign toplevel handle and its extension objects.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct HandleState {
+    title: Option<String>,
+    app_id: Option<String>,
+    states: Vec<ext_foreign_toplevel_state_v1::State>,
+
+    /// The handle of the parent toplevel created by the same list instance.
+    parent: Option<ExtForeignToplevelHandleV1>,
+
+    outputs: Vec<Output>,
+}
+
+impl ToplevelHandles {
+    /// Create a state object for the handle and send the state last sent to the handle.
+    pub fn init_state(&mut self, display: &DisplayHandle, state: ExtForeignToplevelStateV1) {
+        let sent = HandleState {
+            states: Vec::new(),
+            parent: None,
+            outputs: Vec::new(),
+            ..self.sent.clone()
+        };
+
+        self.state = Some(state);
+        let current = std::mem::replace(&mut self.sent, sent);
+        self.update(display, current);
+        self.handle.done();
+    }
+
+    /// Send the changes between the current state and the state last sent to the handle.
+    ///
+    /// Returns true if any event was sent.
+    fn update(&mut self, display: &DisplayHandle, current: HandleState) -> bool {
+        let sent = &self.sent;
+        let mut changed = false;
+
+        if current.title != sent.title {
+            if let Some(title) = current.title.clone() {
+                self.handle.title(title);
+                changed = true;
+            }
+        }
+
+        if current.app_id != sent.app_id {
+            if let Some(app_id) = current.app_id.clone() {
+                self.handle.app_id(app_id);
+                changed = true;
+            }
+        }
+
+        if let Some(ref state) = self.state {
+            if current.states != sent.states {
+                let states = current
+                    .states
+                    .iter()
+                    .flat_map(|&state| u32::from(state).to_ne_bytes())
+                    .collect();
+                state.state(states);
+                changed = true;
+            }
+
+            if current.parent != sent.parent {
+                state.parent(current.parent.as_ref());
+                changed = true;
+            }
+
+            if let Ok(client) = display.get_client(self.handle.id()) {
+                for output in sent.outputs.iter().filter(|output| !current.outputs.contains(output)) {
+                    for wl_output in output.client_outputs(&client) {
+                        state.output_leave(&wl_output);
+                        changed = true;
+                    }
+                }
+
+                for output in current.outputs.iter().filter(|output| !sent.outputs.contains(output)) {
+                    for wl_output in output.client_outputs(&client) {
+                        state.output_enter(&wl_output);
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        self.sent = current;
+        changed
+    }
 }
 
 pub type ToplevelId = NonZeroU64;
 
 impl Toplevel {
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.current, State::Mapped(_))
+    }
+
+    /// Map the toplevel with the state the client committed.
+    fn map(&mut self) {
+        let Surface::Toplevel(ref xdg) = self.surface else {
+            // TODO: XWayland
+            return;
+        };
+
+        let serial = compositor::with_states(xdg.wl_surface(), |states| {
+            states
+                .data_map
+                .get::<XdgToplevelSurfaceData>()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .current_serial
+        });
+
+        self.current = State::Mapped(Mapped {
+            size: xdg.current_state().size.unwrap_or_default(),
+            serial: serial.unwrap_or_else(|| Serial::from(0)),
+        });
+    }
+
+    /// Send the initial configure, letting the client choose the size of the toplevel.
+    ///
+    /// This is used if no wm is running to send the initial configure.
+    pub fn send_initial_configure(&self) {
+        if let Surface::Toplevel(ref xdg) = self.surface {
+            xdg.with_pending_state(|state| state.size = None);
+            xdg.send_configure();
+        }
+    }
+
+    /// Describe the toplevel to a wm which does not know the toplevel yet.
+    pub fn initial_update(&self, rules: &[WindowRule]) -> ToplevelUpdate {
+        let preferred_decorations = match self.requests(|requests| requests.decorations) {
+            Some(preferred) => ConfigureUpdate::Update(preferred),
+            None => ConfigureUpdate::None,
+        };
+
+        ToplevelUpdate {
+            app_id: self.app_id(),
+            title: self.title(),
+            preferred_decorations,
+            decoration_style: Some(self.window_rules(rules).style()),
+            // The wm is only told about a request made before the initial commit once.
+            request: self.requests(|requests| requests.state.take()),
+            ..Default::default()
+        }
+    }
+
+    /// The features of the toplevel the wm is told about when the toplevel is announced.
+    pub fn features(&self) -> Features {
+        let mut features = Features::empty();
+
+        // A toplevel which negotiates decorations can be decorated by the server.
+        if self.requests(|requests| requests.decorations.is_some()) {
+            features |= Features::SERVER_SIDE_DECORATIONS;
+        }
+
+        features
+    }
+
+    fn requests<T: Default>(&self, f: impl FnOnce(&mut ToplevelRequests) -> T) -> T {
+        self.wl_surface()
+            .map(|surface| Shell::with_toplevel_requests(&surface, f))
+            .unwrap_or_default()
+    }
+
     pub fn create_handle(
         &mut self,
-        generation: u64,
         instance: &ExtForeignToplevelListV1,
         display: &DisplayHandle,
         client: &Client,
     ) -> ExtForeignToplevelHandleV1 {
-        // An identifier is made of a 64-bit generation value created from a timestamp on startup and a 64-bit
-        // monotonic counter. Aerugo coverts both of these into hex to create the identifier. Clients should
-        // NOT rely on the behavior which Aerugo uses to allocate identifiers.
-        let identifier = format!("{generation:016X}{:016X}", self.id);
         let handle = client
             .create_resource::<ExtForeignToplevelHandleV1, _, Aerugo>(display, 1, self.id)
             .unwrap();
         instance.toplevel(&handle);
-        handle.identifier(identifier.into());
-        self.handles
-            .insert(handle.id(), ToplevelHandles { handle: handle.clone() });
+        handle.identifier(self.identifier.clone());
+        self.handles.insert(
+            handle.id(),
+            ToplevelHandles {
+                handle: handle.clone(),
+                instance: instance.id(),
+                state: None,
+                sent: HandleState::default(),
+            },
+        );
         // Defer sending other information about the toplevel handles.
         handle
     }
 
     /// Initialize the state of a toplevel handle.
-    pub fn initialize_handle(&self, handle: &ExtForeignToplevelHandleV1) {
-        if let Some(title) = self.title() {
-            handle.title(title);
-        }
+    ///
+    /// The parent and outputs of the toplevel are sent by the next refresh of the foreign toplevels.
+    pub fn initialize_handle(&mut self, display: &DisplayHandle, handle: &ExtForeignToplevelHandleV1) {
+        let current = HandleState {
+            title: self.title(),
+            app_id: self.app_id(),
+            states: self.foreign_states(),
+            ..Default::default()
+        };
 
-        if let Some(app_id) = self.app_id() {
-            handle.app_id(app_id);
+        if let Some(handles) = self.handles.get_mut(&handle.id()) {
+            handles.update(display, current);
         }
 
         // Apply the current state of the toplevel handle.
         handle.done();
     }
 
+    /// The states of the toplevel described to foreign toplevel handles.
+    fn foreign_states(&self) -> Vec<ext_foreign_toplevel_state_v1::State> {
+        [
+            (
+                self.states.contains(ToplevelState::MAXIMIZED),
+                ext_foreign_toplevel_state_v1::State::Maximized,
+            ),
+            (self.minimized, ext_foreign_toplevel_state_v1::State::Minimized),
+            (
+                self.states.contains(ToplevelState::ACTIVATED),
+                ext_foreign_toplevel_state_v1::State::Activated,
+            ),
+            (
+                self.states.contains(ToplevelState::FULLSCREEN),
+                ext_foreign_toplevel_state_v1::State::Fullscreen,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(set, state)| set.then_some(state))
+        .collect()
+    }
+
+    /// The toplevel the client chose as the parent of the toplevel.
+    fn parent(&self) -> Option<ToplevelId> {
+        match self.surface {
+            Surface::Toplevel(ref toplevel) => Shell::get_toplevel_id(&toplevel.parent()?),
+            // TODO: XWayland transient for
+            Surface::XWayland(_) => None,
+        }
+    }
+
+    /// Send the current state of the toplevel to every foreign toplevel handle.
+    fn update_handles(
+        &mut self,
+        display: &DisplayHandle,
+        outputs: &[Output],
+        parents: &FxHashMap<ObjectId, ExtForeignToplevelHandleV1>,
+    ) {
+        let title = self.title();
+        let app_id = self.app_id();
+        let states = self.foreign_states();
+
+        for (id, handles) in &mut self.handles {
+            let current = HandleState {
+                title: title.clone(),
+                app_id: app_id.clone(),
+                states: states.clone(),
+                parent: parents.get(id).cloned(),
+                outputs: outputs.to_vec(),
+            };
+
+            if handles.update(display, current) {
+                handles.handle.done();
+            }
+        }
+    }
+
     pub fn title(&self) -> Option<String> {
         match self.surface {
             Surface::Toplevel(ref toplevel) => compositor::with_states(&toplevel.wl_surface(), |states| {
@@ -263,6 +643,11 @@ impl Toplevel {
         }
     }
 
+    /// The decoration overrides of the window rules matching the toplevel.
+    pub fn window_rules(&self, rules: &[WindowRule]) -> Decorations {
+        rules::resolve(rules, self.app_id().as_deref(), self.title().as_deref())
+    }
+
     pub fn wl_surface(&self) -> Option<WlSurface> {
         match &self.surface {
             Surface::Toplevel(toplevel) => Some(toplevel.wl_surface().clone()),
@@ -270,10 +655,233 @@ impl Toplevel {
         }
     }
 
+    /// Apply a configure from the wm and send the configure to the client.
+    ///
+    /// The wm serial is returned by [`Toplevel::acked`] once the client acks the configure.
+    pub fn configure(&mut self, configure: &ConfigureRequest, wm_serial: u32) {
+        let Surface::Toplevel(ref xdg) = self.surface else {
+            // TODO: XWayland
+            return;
+        };
+
+        if let Some(states) = configure.state {
+            self.states = states;
+        }
+
+        // Toplevels have no minimized state, so this is only described to foreign toplevel handles.
+        if let Some(minimized) = configure.minimized {
+            self.minimized = minimized;
+        }
+
+        xdg.with_pending_state(|state| {
+            if let Some(states) = configure.state {
+                for (flag, xdg_state) in [
+                    (ToplevelState::MAXIMIZED, xdg_toplevel::State::Maximized),
+                    (ToplevelState::FULLSCREEN, xdg_toplevel::State::Fullscreen),
+                    (ToplevelState::RESIZING, xdg_toplevel::State::Resizing),
+                    (ToplevelState::ACTIVATED, xdg_toplevel::State::Activated),
+                    (ToplevelState::TILED_LEFT, xdg_toplevel::State::TiledLeft),
+                    (ToplevelState::TILED_RIGHT, xdg_toplevel::State::TiledRight),
+                    (ToplevelState::TILED_TOP, xdg_toplevel::State::TiledTop),
+                    (ToplevelState::TILED_BOTTOM, xdg_toplevel::State::TiledBottom),
+                ] {
+                    if states.contains(flag) {
+                        state.states.set(xdg_state);
+                    } else {
+                        state.states.unset(xdg_state);
+                    }
+                }
+
+                // TODO: Suspended state, requires xdg-shell version 6.
+            }
+
+            if let ConfigureUpdate::Update(size) = &configure.size {
+                state.size = size.map(to_logical_size);
+            }
+
+            if let ConfigureUpdate::Update(bounds) = &configure.bounds {
+                state.bounds = bounds.map(to_logical_size);
+            }
+
+            if let Some(decorations) = configure.decorations {
+                state.decoration_mode = Some(match decorations {
+                    DecorationMode::ClientSide => zxdg_toplevel_decoration_v1::Mode::ClientSide,
+                    DecorationMode::ServerSide => zxdg_toplevel_decoration_v1::Mode::ServerSide,
+                });
+            }
+        });
+
+        // The parent of an xdg_toplevel is chosen by the client, so the wm parent is only used for window
+        // management.
+
+        let serial = xdg.send_configure();
+        self.wm_configures.push(WmConfigure {
+            serial,
+            wm_serial,
+            request: configure.clone(),
+            sent: Instant::now(),
+        });
+    }
+
+    /// Let the client choose the size of the toplevel, as if the toplevel was floating.
+    ///
+    /// This is used while no wm manages the toplevel. Configures sent on behalf of the wm are forgotten since the
+    /// wm which sent them is gone. The activated state is kept so focus does not move.
+    pub fn float(&mut self) {
+        let outstanding = self.wm_configures.outstanding.len();
+        self.wm_configures.retire(outstanding);
+        self.states = if self.states.contains(ToplevelState::ACTIVATED) {
+            ToplevelState::ACTIVATED
+        } else {
+            ToplevelState::empty()
+        };
+
+        let Surface::Toplevel(ref xdg) = self.surface else {
+            // TODO: XWayland
+            return;
+        };
+
+        xdg.with_pending_state(|state| {
+            for xdg_state in [
+                xdg_toplevel::State::Maximized,
+                xdg_toplevel::State::Fullscreen,
+                xdg_toplevel::State::Resizing,
+                xdg_toplevel::State::TiledLeft,
+                xdg_toplevel::State::TiledRight,
+                xdg_toplevel::State::TiledTop,
+                xdg_toplevel::State::TiledBottom,
+            ] {
+                state.states.unset(xdg_state);
+            }
+
+            state.size = None;
+            state.bounds = None;
+        });
+
+        xdg.send_pending_configure();
+    }
+
+    /// The client acked the configure with the specified serial and size.
+    ///
+    /// The acked state becomes the pending state of the toplevel, which is applied once the client commits the
+    /// state. Acking a configure implicitly acks all older configures.
+    pub fn acked(&mut self, serial: Serial, size: Option<Size<i32, Logical>>) -> Ack {
+        self.pending = Some(Mapped {
+            size: size.unwrap_or_default(),
+            serial,
+        });
+
+        self.wm_configures.acked(serial)
+    }
+
+    /// Apply the pending state once the client committed the acked state.
+    fn apply_pending(&mut self) {
+        let Surface::Toplevel(ref xdg) = self.surface else {
+            // TODO: XWayland
+            return;
+        };
+
+        let committed = compositor::with_states(xdg.wl_surface(), |states| {
+            states
+                .data_map
+                .get::<XdgToplevelSurfaceData>()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .current_serial
+        });
+
+        let applies =
+            matches!((&self.pending, committed), (Some(pending), Some(committed)) if pending.serial <= committed);
+
+        if applies && self.is_mapped() {
+            self.current = State::Mapped(self.pending.take().unwrap());
+        }
+    }
+
+    /// Stop tracking every configure sent on behalf of the wm which was not acked yet.
+    ///
+    /// Returns the wm serials of the configures, oldest first.
+    pub fn cancel_configures(&mut self) -> Vec<u32> {
+        let outstanding = self.wm_configures.outstanding.len();
+
+        self.wm_configures
+            .retire(outstanding)
+            .into_iter()
+            .map(|configure| configure.wm_serial)
+            .collect()
+    }
+
+    /// Stop tracking configures sent on behalf of the wm which were not acked within the timeout.
+    ///
+    /// Returns the expired configures, oldest first.
+    pub fn expire_configures(&mut self, now: Instant, timeout: Duration) -> Vec<AckedConfigure> {
+        let expired = self
+            .wm_configures
+            .outstanding
+            .iter()
+            .take_while(|configure| now.duration_since(configure.sent) >= timeout)
+            .count();
+
+        self.wm_configures
+            .retire(expired)
+            .into_iter()
+            .map(WmConfigure::into_acked)
+            .collect()
+    }
+
+    /// Ping the client to test whether the client is responsive.
+    ///
+    /// Returns false if the client has not answered the previous ping.
+    pub fn ping(&mut self) -> bool {
+        let responsive = !self.pinged;
+
+        if let Surface::Toplevel(ref xdg) = self.surface {
+            // Fails if a ping is already pending, in which case the client is still considered pinged.
+            if xdg.client().send_ping(SERIAL_COUNTER.next_serial()).is_ok() {
+                self.pinged = true;
+            }
+        }
+
+        responsive
+    }
+
+    /// The client answered a ping.
+    pub fn pong(&mut self, client: &ShellClient) {
+        if let Surface::Toplevel(ref xdg) = self.surface {
+            if xdg.client() == *client {
+                self.pinged = false;
+            }
+        }
+    }
+
     pub fn update_state(&mut self) {
         todo!()
     }
 
+    /// A snapshot of the committed surface tree, if the size or scale changed since the last snapshot.
+    fn snapshot(&mut self, surface: &WlSurface) -> Option<SnapshotInfo> {
+        let (geometry, scale) = compositor::with_states(surface, |states| {
+            (
+                states.cached_state.current::<SurfaceCachedState>().geometry,
+                states.cached_state.current::<SurfaceAttributes>().buffer_scale,
+            )
+        });
+        let size = geometry.unwrap_or_else(|| surface_tree_bounds(surface)).size;
+
+        let snapshot = SnapshotInfo {
+            size: LogicalSize::new(size.w.max(0) as u32, size.h.max(0) as u32),
+            scale: scale as f32,
+        };
+
+        if self.snapshot == Some(snapshot) {
+            return None;
+        }
+
+        self.snapshot = Some(snapshot);
+        Some(snapshot)
+    }
+
     pub fn remove_handle(&mut self, id: ObjectId) {
         let _ = self.handles.remove(&id);
     }
@@ -283,6 +891,22 @@ impl Toplevel {
     }
 }
 
+/// Convert a size configured by the wm.
+///
+/// The wm runtime rejects sizes which are too large, but the size is clamped again so a configure can never
+/// send a size the display server cannot present.
+fn to_logical_size(size: LogicalSize) -> Size<i32, Logical> {
+    if size.width > MAX_CONFIGURE_SIZE || size.height > MAX_CONFIGURE_SIZE {
+        tracing::warn!(?size, "Clamping size configured by wm");
+    }
+
+    (
+        size.width.min(MAX_CONFIGURE_SIZE) as i32,
+        size.height.min(MAX_CONFIGURE_SIZE) as i32,
+    )
+        .into()
+}
+
 /// The state of a toplevel.
 #[derive(Debug, Default)]
 enum State {
@@ -310,7 +934,59 @@ struct AerugoToplevelData {
     toplevel_id: ToplevelId,
 }
 
+/// The xdg_surface a toplevel surface was created from.
+///
+/// Smithay does not expose the xdg_surface of a toplevel, so the xdg_surface is recorded when the toplevel is
+/// created.
+struct ToplevelXdgSurface(xdg_surface::XdgSurface);
+
+/// Requests the client of a toplevel made, recorded on the surface.
+///
+/// A client may make requests before the initial commit of the toplevel, before the wm knows about the toplevel.
+/// The requests are included when the toplevel is announced, so they reach the wm and every wm started later.
+#[derive(Debug, Default)]
+pub struct ToplevelRequests {
+    /// The decoration mode the client prefers.
+    ///
+    /// [`None`] if the client does not negotiate decorations, `Some(None)` if the client has no preference.
+    pub decorations: Option<Option<DecorationMode>>,
+
+    /// The latest change of state the client requested before the toplevel was announced.
+    pub state: Option<StateRequest>,
+}
+
 impl Shell {
+    /// Access the requests the client of a toplevel surface made.
+    pub fn with_toplevel_requests<T>(surface: &WlSurface, f: impl FnOnce(&mut ToplevelRequests) -> T) -> T {
+        compositor::with_states(surface, |states| {
+            states
+                .data_map
+                .insert_if_missing_threadsafe(|| Mutex::new(ToplevelRequests::default()));
+            let mut requests = states
+                .data_map
+                .get::<Mutex<ToplevelRequests>>()
+                .unwrap()
+                .lock()
+                .unwrap();
+            f(&mut requests)
+        })
+    }
+
+    pub fn set_xdg_surface(surface: &WlSurface, xdg_surface: xdg_surface::XdgSurface) {
+        compositor::with_states(surface, |states| {
+            states
+                .data_map
+                .insert_if_missing_threadsafe(|| ToplevelXdgSurface(xdg_surface));
+        });
+    }
+
+    /// The xdg_surface a toplevel surface was created from.
+    pub fn xdg_surface(surface: &WlSurface) -> Option<xdg_surface::XdgSurface> {
+        compositor::with_states(surface, |states| {
+            states.data_map.get::<ToplevelXdgSurface>().map(|data| data.0.clone())
+        })
+    }
+
     pub fn get_toplevel_id(surface: &WlSurface) -> Option<ToplevelId> {
         compositor::with_states(surface, |data| {
             data.data_map.get::<AerugoToplevelData>().map(|data| data.toplevel_id)
@@ -322,17 +998,47 @@ impl Shell {
             pending_toplevels: Vec::new(),
             toplevels: Default::default(),
             foreign_toplevel_instances: Default::default(),
+            creating_toplevel: None,
             next_toplevel_id: NonZeroU64::new(1).unwrap(),
+            next_foreign_identifier: 0,
         }
     }
 
+    /// Allocate the identifier described to foreign toplevel handles.
+    ///
+    /// Identifiers are never reused, including for a toplevel which is mapped again.
+    pub fn allocate_foreign_identifier(&mut self, generation: u64) -> String {
+        let identifier = foreign_identifier(generation, self.next_foreign_identifier);
+        self.next_foreign_identifier = self
+            .next_foreign_identifier
+            .checked_add(1)
+            .expect("u64 overflow (unlikely)");
+        identifier
+    }
+
+    /// Allocate an id for an object known to the wm.
+    ///
+    /// Outputs and toplevels share the id space of the wm, so both are allocated here.
+    pub fn allocate_id(&mut self) -> ToplevelId {
+        let id = self.next_toplevel_id;
+        self.next_toplevel_id = id.checked_add(1).expect("u64 overflow (unlikely)");
+        id
+    }
+
     pub fn commit(comp: &mut Aerugo, surface: &WlSurface) {
         // Handle commit for each type of role.
-        Shell::toplevel_commit(comp, surface);
+        match compositor::get_role(surface) {
+            Some(XDG_TOPLEVEL_ROLE) => Shell::toplevel_commit(comp, surface),
+
+            // Popups and surface nodes are committed before the root surface is selected, and subsurfaces are
+            // committed with their root surface.
+            _ => {}
+        }
     }
 
     pub fn toplevel_commit(comp: &mut Aerugo, surface: &WlSurface) {
-        let Some(id) = Shell::get_toplevel_id(surface) else {
+        // An unmapped toplevel is pending again but keeps the id of the toplevel.
+        let Some(id) = Shell::get_toplevel_id(surface).filter(|id| comp.shell.toplevels.contains_key(id)) else {
             // If the surface is pending, then an initial commit has happened.
             if let Some(toplevel_index) = comp
                 .shell
@@ -341,6 +1047,27 @@ impl Shell {
                 .position(|toplevel| toplevel.wl_surface() == surface)
             {
                 let toplevel = comp.shell.pending_toplevels.remove(toplevel_index);
+
+                // A buffer may not be attached before the initial configure. In strict mode this posts the
+                // unconfigured buffer error.
+                let has_buffer = with_renderer_surface_state(surface, |state| state.buffer().is_some());
+                if has_buffer {
+                    if comp.conformance.is_strict() {
+                        toplevel.ensure_configured();
+                    } else {
+                        tracing::warn!("Tolerating protocol violation: buffer attached during initial commit");
+                    }
+                }
+
+                let id = Shell::create_toplevel(comp, toplevel);
+
+                if comp.wm.handover_pending() {
+                    // The toplevel is announced with the other toplevels handed over to the new wm.
+                } else if comp.wm.is_running() {
+                    comp.announce_toplevel(id);
+                } else {
+                    comp.shell.toplevels.get(&id).unwrap().send_initial_configure();
+                }
             }
 
             return;
@@ -362,6 +1089,7 @@ impl Shell {
                 // TODO: Include app_id, remove toplevel debug impl
                 tracing::debug!(?toplevel, "Unmap toplevel");
                 let toplevel = comp.shell.toplevels.remove(&id).unwrap();
+                comp.fallback_wm.remove(id);
 
                 // Notify clients the toplevel is being unmapped.
                 for handle in toplevel.handles.values() {
@@ -382,9 +1110,100 @@ impl Shell {
             let id = toplevel.id;
             let app_id = toplevel.app_id().unwrap_or_default();
             tracing::warn!(%id, %app_id, "Killing client: toplevel not configured");
+            return;
+        }
+
+        // The window geometry should be within the bounds of the surface tree. The protocol says the geometry
+        // is clamped, but clients doing this are likely confused about their own size.
+        if let Some(xdg_surface) = Shell::xdg_surface(surface) {
+            let geometry = compositor::with_states(surface, |states| {
+                states.cached_state.current::<SurfaceCachedState>().geometry
+            });
+
+            if let Some(geometry) = geometry {
+                let bounds = surface_tree_bounds(surface);
+
+                if has_buffer && !bounds.contains_rect(geometry) {
+                    comp.conformance.violation(
+                        &xdg_surface,
+                        xdg_surface::Error::InvalidSize,
+                        format!("window geometry {geometry:?} is outside of the surface bounds {bounds:?}"),
+                    );
+                }
+            }
+        }
+
+        // The first commit with a buffer maps the toplevel.
+        if has_buffer && !toplevel.is_mapped() {
+            toplevel.map();
+            tracing::debug!(id, "Mapped toplevel");
+
+            if comp.scene.get_surface_tree_index(surface.clone()).is_none() {
+                comp.scene.create_surface_tree(surface.clone());
+            }
+
+            if comp.fallback_wm.is_active() {
+                comp.fallback_map(id);
+            }
+        }
+
+        // Once mapped, the wm is told about every commit so the toplevel can be presented. A new snapshot is only
+        // given to the wm if the size or scale of the toplevel changed.
+        if has_buffer {
+            let snapshot = comp.shell.toplevels.get_mut(&id).and_then(|toplevel| {
+                toplevel.apply_pending();
+                toplevel.snapshot(surface)
+            });
+
+            if let Some(toplevel) = wm::wm_toplevel_id(id) {
+                comp.wm.send(WmEvent::CommittedToplevel { toplevel, snapshot });
+            }
         }
     }
 
+    /// Create a toplevel after the initial commit of a pending toplevel.
+    ///
+    /// A foreign toplevel handle is created for every list instance. A toplevel which is mapped again keeps the id
+    /// the toplevel had before.
+    fn create_toplevel(comp: &mut Aerugo, surface: ToplevelSurface) -> ToplevelId {
+        let id = Shell::get_toplevel_id(surface.wl_surface()).unwrap_or_else(|| comp.shell.allocate_id());
+        compositor::with_states(surface.wl_surface(), |states| {
+            states
+                .data_map
+                .insert_if_missing_threadsafe(|| AerugoToplevelData { toplevel_id: id })
+        });
+
+        let mut toplevel = Toplevel {
+            id,
+            surface: Surface::Toplevel(surface),
+            current: State::default(),
+            pending: None,
+            identifier: comp.shell.allocate_foreign_identifier(comp.generation),
+            handles: FxHashMap::default(),
+            states: ToplevelState::empty(),
+            minimized: false,
+            wm_configures: WmConfigures::default(),
+            pinged: false,
+            snapshot: None,
+        };
+
+        for instance in comp.shell.foreign_toplevel_instances.values() {
+            if instance.stopped {
+                continue;
+            }
+
+            if let Some(client) = instance.instance.client() {
+                let handle = toplevel.create_handle(&instance.instance, &comp.display, &client);
+                toplevel.initialize_handle(&comp.display, &handle);
+            }
+        }
+
+        let app_id = toplevel.app_id();
+        tracing::debug!(id, app_id, "Initial commit of toplevel");
+        comp.shell.toplevels.insert(id, toplevel);
+        id
+    }
+
     // pub fn commit(comp: &mut Aerugo, surface: &WlSurface) {
     //     let has_buffer = with_renderer_surface_state(surface, |state| state.buffer().is_some());
 
@@ -566,6 +1385,7 @@ impl Shell {
     //     }
     // }
 
+    /// Remove the toplevel of a destroyed surface.
     pub fn remove_toplevel(comp: &mut Aerugo, surface: &WlSurface) {
         // Remove toplevels that are pending
         comp.shell
@@ -576,9 +1396,111 @@ impl Shell {
             let remove = toplevel.wl_surface().as_ref() == Some(surface);
             remove.then_some(*key)
         }) {
-            let toplevel = comp.shell.toplevels.remove(&id).unwrap();
-            let app_id = toplevel.app_id();
-            tracing::debug!(id, app_id, "Removed toplevel");
+            Shell::close_toplevel(comp, id);
+        }
+
+        if let Some(tree) = comp.scene.get_surface_tree_index(surface.clone()) {
+            comp.wm.forget_node(NodeIndex::SurfaceTree(tree));
+            comp.scene.destroy_surface_tree(tree);
+        }
+    }
+
+    /// Remove a toplevel which closed.
+    ///
+    /// Every foreign toplevel handle of the toplevel is closed. Configures sent on behalf of the wm which the
+    /// client never acked are cancelled before the wm is told the toplevel closed, so the wm does not wait for
+    /// transactions which can never complete.
+    pub fn close_toplevel(comp: &mut Aerugo, id: ToplevelId) {
+        let Some(toplevel) = comp.shell.toplevels.remove(&id) else {
+            return;
+        };
+
+        let app_id = toplevel.app_id();
+        tracing::debug!(id, app_id, "Removed toplevel");
+
+        for handle in toplevel.handles.values() {
+            handle.handle.closed();
+        }
+
+        comp.screenshots.remove_toplevel(id);
+        comp.screencasts.remove_toplevel(id);
+        comp.fallback_wm.remove(id);
+
+        let Some(wm_id) = wm::wm_toplevel_id(id) else {
+            return;
+        };
+
+        for configure in &toplevel.wm_configures {
+            comp.wm.send(WmEvent::ConfigureCancelled {
+                toplevel: wm_id,
+                serial: configure.wm_serial,
+            });
+        }
+
+        comp.wm.send(WmEvent::ClosedToplevel(wm_id));
+    }
+
+    /// Toplevels whose surface was destroyed without the toplevel being removed.
+    pub fn dead_toplevels(&mut self) -> Vec<ToplevelId> {
+        self.pending_toplevels.retain(|toplevel| toplevel.alive());
+
+        self.toplevels
+            .iter()
+            .filter(|(_, toplevel)| !toplevel.wl_surface().map_or(false, |surface| surface.is_alive()))
+            .map(|(&id, _)| id)
+            .collect()
+    }
+
+    /// The surfaces of all mapped toplevels.
+    pub fn mapped_toplevels(&self) -> Vec<(ToplevelId, WlSurface)> {
+        self.toplevels
+            .iter()
+            .filter(|(_, toplevel)| toplevel.is_mapped())
+            .filter_map(|(&id, toplevel)| Some((id, toplevel.wl_surface()?)))
+            .collect()
+    }
+
+    /// The surfaces of the mapped toplevels which are fullscreen.
+    pub fn fullscreen_surfaces(&self) -> Vec<WlSurface> {
+        self.toplevels
+            .values()
+            .filter(|toplevel| toplevel.is_mapped() && toplevel.states.contains(ToplevelState::FULLSCREEN))
+            .filter_map(Toplevel::wl_surface)
+            .collect()
+    }
+
+    /// Send changes to the state of the toplevels to every foreign toplevel handle.
+    ///
+    /// Each handle is compared against the state last sent to it, so a handle is only sent events if the
+    /// toplevel changed since the last refresh.
+    pub fn refresh_foreign_toplevels(&mut self, display: &DisplayHandle, scene: &Scene) {
+        // The parent of a handle must be a handle created by the same list instance.
+        let mut parents = FxHashMap::default();
+
+        for toplevel in self.toplevels.values() {
+            let Some(parent) = toplevel.parent().and_then(|id| self.toplevels.get(&id)) else {
+                continue;
+            };
+
+            for (id, handles) in &toplevel.handles {
+                if let Some(parent) = parent
+                    .handles
+                    .values()
+                    .find(|parent| parent.instance == handles.instance)
+                {
+                    parents.insert(id.clone(), parent.handle.clone());
+                }
+            }
+        }
+
+        for toplevel in self.toplevels.values_mut() {
+            let outputs = toplevel
+                .wl_surface()
+                .and_then(|surface| scene.get_surface_tree_index(surface))
+                .map(|index| scene.node_outputs(NodeIndex::SurfaceTree(index)))
+                .unwrap_or_default();
+
+            toplevel.update_handles(display, &outputs, &parents);
         }
     }
 
@@ -591,6 +1513,46 @@ impl Shell {
     }
 }
 
+/// Create a foreign toplevel identifier.
+///
+/// An identifier is made of a 64-bit generation value created from a timestamp on startup and a 64-bit monotonic
+/// counter, both in hex. Clients should NOT rely on the behavior which Aerugo uses to allocate identifiers.
+fn foreign_identifier(generation: u64, counter: u64) -> String {
+    format!("{generation:016X}{counter:016X}")
+}
+
+/// The bounding box of a surface and its subsurfaces, relative to the surface.
+pub(crate) fn surface_tree_bounds(surface: &WlSurface) -> Rectangle<i32, Logical> {
+    let mut bounds = Rectangle::default();
+
+    compositor::with_surface_tree_downward(
+        surface,
+        Point::<i32, Logical>::default(),
+        |_, states, location| {
+            let mut location = *location;
+            let size = states
+                .data_map
+                .get::<RendererSurfaceStateUserData>()
+                .and_then(|data| data.borrow().surface_size());
+
+            let Some(size) = size else {
+                return TraversalAction::SkipChildren;
+            };
+
+            if states.role == Some("subsurface") {
+                location += states.cached_state.current::<SubsurfaceCachedState>().location;
+            }
+
+            bounds = bounds.merge(Rectangle::from_loc_and_size(location, size));
+            TraversalAction::DoChildren(location)
+        },
+        |_, _, _| {},
+        |_, _, _| true,
+    );
+
+    bounds
+}
+
 pub fn send_frames_surface_tree(surface: &WlSurface, time: u32) {
     compositor::with_surface_tree_downward(
         surface,
@@ -611,3 +1573,94 @@ pub fn send_frames_surface_tree(surface: &WlSurface, time: u32) {
         |_, _, &()| true,
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use smithay::utils::Serial;
+    use wm_runtime::{ConfigureRequest, ToplevelState};
+
+    use super::{Ack, Shell, WmConfigure, WmConfigures};
+
+    fn configures(states: &[ToplevelState]) -> WmConfigures {
+        let mut configures = WmConfigures::default();
+
+        for (wm_serial, &state) in states.iter().enumerate() {
+            configures.push(WmConfigure {
+                serial: Serial::from(wm_serial as u32 + 10),
+                wm_serial: wm_serial as u32,
+                request: ConfigureRequest {
+                    state: Some(state),
+                    ..ConfigureRequest::default()
+                },
+                sent: Instant::now(),
+            });
+        }
+
+        configures
+    }
+
+    #[test]
+    fn ack_combines_older_configures() {
+        let mut configures = configures(&[
+            ToplevelState::MAXIMIZED,
+            ToplevelState::ACTIVATED,
+            ToplevelState::FULLSCREEN,
+        ]);
+
+        let Ack::Wm(acked) = configures.acked(Serial::from(11)) else {
+            panic!("configure was sent on behalf of the wm");
+        };
+        assert_eq!(acked.serial, 1);
+        assert_eq!(acked.request.state, Some(ToplevelState::ACTIVATED));
+        assert_eq!(configures.outstanding.len(), 1);
+
+        // The older configure was acked implicitly.
+        assert!(matches!(configures.acked(Serial::from(10)), Ack::Stale));
+        assert!(matches!(configures.acked(Serial::from(12)), Ack::Wm(acked) if acked.serial == 2));
+    }
+
+    #[test]
+    fn ack_of_retired_configure_is_stale() {
+        let mut configures = configures(&[ToplevelState::MAXIMIZED, ToplevelState::ACTIVATED]);
+
+        // The first configure timed out.
+        assert_eq!(configures.retire(1).len(), 1);
+        assert!(matches!(configures.acked(Serial::from(10)), Ack::Stale));
+
+        // Configures sent without the wm are not tracked.
+        assert!(matches!(configures.acked(Serial::from(20)), Ack::Untracked));
+        assert!(matches!(configures.acked(Serial::from(11)), Ack::Wm(_)));
+    }
+
+    #[test]
+    fn unexpected_acks_leave_configures_outstanding() {
+        let mut configures = configures(&[ToplevelState::MAXIMIZED, ToplevelState::ACTIVATED]);
+
+        // Serials which were not sent on behalf of the wm do not ack any configure of the wm.
+        assert!(matches!(configures.acked(Serial::from(5)), Ack::Untracked));
+        assert!(matches!(configures.acked(Serial::from(30)), Ack::Untracked));
+        assert_eq!(configures.outstanding.len(), 2);
+
+        // A configure acked twice is only reported to the wm once.
+        assert!(matches!(configures.acked(Serial::from(10)), Ack::Wm(acked) if acked.serial == 0));
+        assert!(matches!(configures.acked(Serial::from(10)), Ack::Stale));
+        assert_eq!(configures.outstanding.len(), 1);
+    }
+
+    #[test]
+    fn foreign_identifiers_are_not_reused() {
+        let mut shell = Shell::new();
+        let first = shell.allocate_foreign_identifier(0x1234);
+        let second = shell.allocate_foreign_identifier(0x1234);
+
+        assert_ne!(first, second);
+
+        // Identifiers are at most 32 printable ASCII bytes.
+        for identifier in [first, second] {
+            assert!(!identifier.is_empty() && identifier.len() <= 32);
+            assert!(identifier.bytes().all(|byte| byte.is_ascii_graphic()));
+        }
+    }
+}
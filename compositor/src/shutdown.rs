@@ -0,0 +1,72 @@
+//! Shutdown reasons
+//!
+//! Every path which stops the server records why the server stopped. The reason is logged when the event loop
+//! exits and mapped to the exit code of the process, so a session manager can tell a requested shutdown apart
+//! from a failure and decide whether to restart the compositor.
+//!
+//! Shutting down happens in stages. First the server stops accepting clients, the wm is told to finalize and
+//! globals are disabled. After [`SHUTDOWN_GRACE_PERIOD`] remaining clients are disconnected with a protocol
+//! error, the wm is stopped and the event loop exits.
+
+use std::{fmt, time::Duration};
+
+/// How long clients and the wm have to react to the server shutting down before the event loop stops.
+pub const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+/// Why the server stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// The user asked the compositor to exit, for example by closing the window of a nested backend.
+    UserRequest,
+
+    /// The backend lost access to the display or input devices and cannot recover.
+    BackendLost,
+
+    /// The logind session the compositor runs in was terminated.
+    SessionTerminated,
+}
+
+impl ShutdownReason {
+    /// The exit code of the process for this reason.
+    ///
+    /// Exit code 1 is not used, since that is the exit code of a panic.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::UserRequest => 0,
+            Self::BackendLost => 2,
+            Self::SessionTerminated => 3,
+        }
+    }
+}
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::UserRequest => "requested by the user",
+            Self::BackendLost => "backend lost",
+            Self::SessionTerminated => "session terminated",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShutdownReason;
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let reasons = [
+            ShutdownReason::UserRequest,
+            ShutdownReason::BackendLost,
+            ShutdownReason::SessionTerminated,
+        ];
+
+        for (i, a) in reasons.iter().enumerate() {
+            assert_ne!(a.exit_code(), 1);
+
+            for b in &reasons[i + 1..] {
+                assert_ne!(a.exit_code(), b.exit_code());
+            }
+        }
+    }
+}
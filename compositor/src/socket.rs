@@ -0,0 +1,134 @@
+//! The socket clients connect to
+//!
+//! By default the display server binds the first free `wayland-N` socket in `$XDG_RUNTIME_DIR`. The name of the
+//! socket may be chosen instead, or the socket may be bound by the caller, for example by systemd socket
+//! activation. Nested and test instances may not listen on a socket at all and only create clients using
+//! [`AerugoExecutor::create_client`](crate::AerugoExecutor::create_client).
+//!
+//! The name of the socket is available from [`AerugoExecutor::socket_name`](crate::AerugoExecutor::socket_name)
+//! and is passed to programs started by the display server in `WAYLAND_DISPLAY`.
+
+use std::{
+    ffi::OsString,
+    io,
+    os::{
+        fd::{FromRawFd, OwnedFd},
+        unix::net::UnixListener,
+    },
+    path::Path,
+};
+
+use rustix::io::FdFlags;
+
+/// The first file descriptor passed by systemd, see `sd_listen_fds(3)`.
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// How the display server accepts clients.
+#[derive(Debug, Default)]
+pub enum Socket {
+    /// Bind the first free `wayland-N` socket in `$XDG_RUNTIME_DIR`.
+    #[default]
+    Auto,
+
+    /// Bind the socket with the specified name in `$XDG_RUNTIME_DIR`.
+    Named(String),
+
+    /// Accept clients on a socket bound by the caller.
+    Listener(UnixListener),
+
+    /// Do not listen on a socket.
+    Disabled,
+}
+
+impl Socket {
+    /// The socket passed by systemd socket activation, if the display server was socket activated.
+    ///
+    /// Only a single socket may be passed. The environment variables of the protocol are removed, so programs
+    /// started by the display server do not mistake the socket for their own.
+    pub fn from_systemd() -> io::Result<Option<Self>> {
+        let pid = std::env::var("LISTEN_PID").ok();
+        let fds = std::env::var("LISTEN_FDS").ok();
+
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            std::env::remove_var(name);
+        }
+
+        if !listen_fds(pid.as_deref(), fds.as_deref(), std::process::id())? {
+            return Ok(None);
+        }
+
+        // SAFETY: systemd passes the socket as the first file descriptor, which nothing else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(SD_LISTEN_FDS_START) };
+        rustix::io::fcntl_setfd(&fd, FdFlags::CLOEXEC)?;
+
+        Ok(Some(Self::Listener(UnixListener::from(fd))))
+    }
+}
+
+/// Whether a socket was passed to the process, given the `LISTEN_PID` and `LISTEN_FDS` variables.
+fn listen_fds(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> io::Result<bool> {
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Ok(false);
+    };
+
+    // The sockets were passed to another process, which then started this process.
+    if pid.parse::<u32>().ok() != Some(own_pid) {
+        return Ok(false);
+    }
+
+    match fds.parse::<u32>() {
+        Ok(0) => Ok(false),
+        Ok(1) => Ok(true),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("expected a single socket from systemd, got LISTEN_FDS={fds}"),
+        )),
+    }
+}
+
+/// The name clients use to connect to a socket bound by the caller.
+///
+/// Sockets in `$XDG_RUNTIME_DIR` are named relative to the directory, other sockets are named by their absolute
+/// path.
+pub(crate) fn display_name(path: &Path, runtime_dir: Option<&Path>) -> OsString {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if Some(parent) == runtime_dir => name.to_owned(),
+        _ => path.as_os_str().to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, path::Path};
+
+    use super::{display_name, listen_fds};
+
+    #[test]
+    fn systemd_sockets() {
+        assert!(!listen_fds(None, None, 10).unwrap());
+        assert!(listen_fds(Some("10"), Some("1"), 10).unwrap());
+        assert!(!listen_fds(Some("10"), Some("0"), 10).unwrap());
+
+        // Sockets passed to another process are not for the display server.
+        assert!(!listen_fds(Some("11"), Some("1"), 10).unwrap());
+        assert!(listen_fds(Some("10"), Some("2"), 10).is_err());
+    }
+
+    #[test]
+    fn socket_names() {
+        let runtime_dir = Path::new("/run/user/1000");
+
+        assert_eq!(
+            display_name(Path::new("/run/user/1000/wayland-1"), Some(runtime_dir)),
+            OsStr::new("wayland-1")
+        );
+        assert_eq!(
+            display_name(Path::new("/tmp/aerugo/wayland-1"), Some(runtime_dir)),
+            OsStr::new("/tmp/aerugo/wayland-1")
+        );
+        assert_eq!(
+            display_name(Path::new("/run/user/1000/wayland-1"), None),
+            OsStr::new("/run/user/1000/wayland-1")
+        );
+    }
+}
@@ -1,53 +1,232 @@
 use std::{
+    ffi::OsString,
     fmt,
-    time::{Duration, SystemTime},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
 
 use bitflags::bitflags;
-use calloop::LoopHandle;
+use calloop::{
+    timer::{TimeoutAction, Timer},
+    LoopHandle,
+};
 use smithay::{
-    input::SeatState,
-    output::{Output, PhysicalProperties},
+    backend::input::{Axis, AxisSource, ButtonState, KeyState, TouchSlot},
+    input::{
+        keyboard::XkbConfig,
+        pointer::{AxisFrame, ButtonEvent, MotionEvent},
+        Seat, SeatState,
+    },
+    output::{Mode, Output, PhysicalProperties, Scale},
+    reexports::{
+        wayland_protocols::wp::idle_inhibit::zv1::server::zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1,
+        wayland_protocols_wlr::{
+            data_control::v1::server::zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+            gamma_control::v1::server::zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1,
+            output_management::v1::server::zwlr_output_manager_v1::ZwlrOutputManagerV1,
+            output_power_management::v1::server::zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1,
+            screencopy::v1::server::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+        },
+    },
+    utils::{Logical, Point, SERIAL_COUNTER},
     wayland::{
         compositor::{CompositorClientState, CompositorState},
-        shell::xdg::XdgShellState,
+        data_device::DataDeviceState,
+        primary_selection::PrimarySelectionState,
+        session_lock::SessionLockManagerState,
+        shell::xdg::{decoration::XdgDecorationState, XdgShellState},
+        single_pixel_buffer::SinglePixelBufferState,
+        viewporter::ViewporterState,
+        xdg_activation::XdgActivationState,
     },
 };
 use wayland_server::{
-    backend::{ClientId, DisconnectReason},
+    backend::{ClientId, DisconnectReason, GlobalId},
+    protocol::wl_surface::WlSurface,
     Client, DisplayHandle,
 };
+use wm_runtime::{
+    GestureBegin, GestureKind, GestureUpdate, PointerAxis, PointerButton, PointerEvent, PointerLocation,
+    ToplevelUpdate, TouchEvent, TouchPoint, WmEvent,
+};
 
 use crate::{
     backend::Backend,
-    scene::Scene,
+    color,
+    config::{KeyboardConfig, LimitsConfig, OutputConfig, TransactionConfig},
+    conformance::Conformance,
+    cursor::Cursor,
+    frame::FrameThrottle,
+    gamma::NightLight,
+    idle::IdleState,
+    input::{
+        self,
+        buttons::{FilteredEvent, BTN_LEFT},
+        keybindings, touch,
+        virtual_seat::{InjectedEvent, VirtualSeatId},
+        InputState,
+    },
+    launcher::Launcher,
+    limits::ClientLimits,
+    overview::Overview,
+    popup::{self, Popups},
+    rules::WindowRule,
+    scene::{Scene, SurfaceTreeIndex},
+    screencast::Screencasts,
+    screenshot::ScreenshotState,
     shell::Shell,
-    wayland::{ext::foreign_toplevel::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1, versions},
-    Loop,
+    stats::{Counters, RenderStats},
+    virtual_output::VirtualOutputs,
+    wayland::{
+        aerugo::{
+            debug::AerugoDebugV1,
+            shell::{AerugoShellState, AerugoShellV1},
+        },
+        ext::{
+            foreign_toplevel::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1,
+            foreign_toplevel_state::ExtForeignToplevelStateManagerV1,
+            session_lock::SessionLock,
+            workspace::{ExtWorkspaceManagerV1, ExtWorkspaceState},
+        },
+        versions,
+        wlr::{
+            data_control::DataControlState, gamma_control::GammaControlState, output_management::OutputManagementState,
+            output_power::OutputPowerState, screencopy::ScreencopyState,
+        },
+        wp::{
+            drm_syncobj::{DrmSyncobjState, WpLinuxDrmSyncobjManagerV1},
+            idle_inhibit::IdleInhibitState,
+        },
+        xdg_activation::ActivationTokens,
+    },
+    wm::{self, fallback::FallbackWm, WmSupervisor},
+    Loop, GLOBAL_REMOVAL_DELAY,
 };
 
 #[derive(Debug)]
 pub struct Aerugo {
+    pub r#loop: LoopHandle<'static, Loop>,
     pub display: DisplayHandle,
     pub shell: Shell,
+    pub popups: Popups,
     pub scene: Scene,
+    pub frame_throttle: FrameThrottle,
+    /// The primary output.
+    ///
+    /// This is the first output which was added and is still connected.
     // This is not what I want in the future, but is for testing.
     pub output: Output,
+    /// Connected outputs and their globals, in the order the outputs were added.
+    pub outputs: Vec<(Output, GlobalId)>,
+    /// Globals of the protocols implemented in tree.
+    pub globals: Vec<GlobalId>,
     pub backend: Box<dyn Backend>,
     pub wl_compositor: CompositorState,
     pub xdg_shell: XdgShellState,
+    pub xdg_decoration: XdgDecorationState,
+    pub xdg_activation: XdgActivationState,
+    pub activation_tokens: ActivationTokens,
+    pub launcher: Launcher,
     pub seat_state: SeatState<Self>,
+    pub seat: Seat<Self>,
+    pub data_device: DataDeviceState,
+    pub primary_selection: PrimarySelectionState,
+    pub data_control: DataControlState,
+    /// The surface tree of the drag icon, if a drag with an icon is in progress.
+    pub drag_icon: Option<SurfaceTreeIndex>,
+    pub screencopy: ScreencopyState,
+    pub screenshots: ScreenshotState,
+    pub screencasts: Screencasts,
+    pub virtual_outputs: VirtualOutputs,
+    pub viewporter: ViewporterState,
+    pub single_pixel_buffer: SinglePixelBufferState,
+    /// Explicit synchronization, if the backend supports timeline syncobjs.
+    pub drm_syncobj: Option<DrmSyncobjState>,
+    pub aerugo_shell: AerugoShellState,
+    pub render_stats: RenderStats,
+    pub counters: Counters,
+    pub output_management: OutputManagementState,
+    pub output_power: OutputPowerState,
+    pub gamma_control: GammaControlState,
+    pub night_light: NightLight,
+    pub idle: IdleState,
+    pub idle_inhibit: IdleInhibitState,
+    pub session_lock_state: SessionLockManagerState,
+    pub session_lock: SessionLock,
+    pub wm: WmSupervisor,
+    pub fallback_wm: FallbackWm,
+    pub ext_workspace: ExtWorkspaceState,
+    pub transactions: TransactionConfig,
+    pub limits: LimitsConfig,
+    pub window_rules: Vec<WindowRule>,
+    pub input: InputState,
+    pub conformance: Conformance,
+    /// The overview, if shown.
+    pub overview: Option<Overview>,
+    pub pointer_location: Point<f64, Logical>,
+    pub cursor: Cursor,
     pub generation: u64,
+    pub disconnected_clients: DisconnectedClients,
 }
 
 impl Aerugo {
-    pub fn new(_loop: &LoopHandle<'static, Loop>, display: DisplayHandle, backend: Box<dyn Backend>) -> Self {
+    pub fn new(
+        r#loop: &LoopHandle<'static, Loop>,
+        display: DisplayHandle,
+        backend: Box<dyn Backend>,
+        wm: Option<PathBuf>,
+        socket_name: Option<OsString>,
+        conformance: Conformance,
+    ) -> Self {
         // Initialize common globals
-        let seat_state = SeatState::new();
-        let wl_compositor = CompositorState::new::<Self>(&display);
+        let mut seat_state = SeatState::new();
+        let mut seat = seat_state.new_wl_seat(&display, "seat0");
+        seat.add_pointer();
+        // Version 6 of wl_surface adds the preferred buffer scale and transform events.
+        let wl_compositor = CompositorState::new_v6::<Self>(&display);
         let xdg_shell = XdgShellState::new::<Self>(&display);
-        let _foreign_toplevel_list =
-            display.create_global::<Self, ExtForeignToplevelListV1, _>(versions::EXT_FOREIGN_TOPLEVEL_LIST_V1, ());
+        let viewporter = ViewporterState::new::<Self>(&display);
+        let single_pixel_buffer = SinglePixelBufferState::new::<Self>(&display);
+        let xdg_decoration = XdgDecorationState::new::<Self>(&display);
+        let xdg_activation = XdgActivationState::new::<Self>(&display);
+        let data_device = DataDeviceState::new::<Self>(&display);
+        let primary_selection = PrimarySelectionState::new::<Self>(&display);
+        let mut globals = vec![
+            display.create_global::<Self, ExtForeignToplevelListV1, _>(versions::EXT_FOREIGN_TOPLEVEL_LIST_V1, ()),
+            display.create_global::<Self, ExtForeignToplevelStateManagerV1, _>(
+                versions::EXT_FOREIGN_TOPLEVEL_STATE_MANAGER_V1,
+                (),
+            ),
+            display.create_global::<Self, ExtWorkspaceManagerV1, _>(versions::EXT_WORKSPACE_MANAGER_V1, ()),
+            display.create_global::<Self, ZwlrOutputManagerV1, _>(versions::ZWLR_OUTPUT_MANAGER_V1, ()),
+            display.create_global::<Self, ZwlrOutputPowerManagerV1, _>(versions::ZWLR_OUTPUT_POWER_MANAGER_V1, ()),
+            display.create_global::<Self, ZwpIdleInhibitManagerV1, _>(versions::ZWP_IDLE_INHIBIT_MANAGER_V1, ()),
+            display.create_global::<Self, AerugoShellV1, _>(versions::AERUGO_SHELL_V1, ()),
+            display.create_global::<Self, AerugoDebugV1, _>(versions::AERUGO_DEBUG_V1, ()),
+            display.create_global::<Self, ZwlrDataControlManagerV1, _>(versions::ZWLR_DATA_CONTROL_MANAGER_V1, ()),
+            display.create_global::<Self, ZwlrScreencopyManagerV1, _>(versions::ZWLR_SCREENCOPY_MANAGER_V1, ()),
+            display.create_global::<Self, ZwlrGammaControlManagerV1, _>(versions::ZWLR_GAMMA_CONTROL_MANAGER_V1, ()),
+        ];
+        let drm_syncobj = backend
+            .syncobj_device()
+            .and_then(|device| device.try_clone_to_owned().ok())
+            .and_then(DrmSyncobjState::new);
+
+        if drm_syncobj.is_some() {
+            globals.push(
+                display.create_global::<Self, WpLinuxDrmSyncobjManagerV1, _>(
+                    versions::WP_LINUX_DRM_SYNCOBJ_MANAGER_V1,
+                    (),
+                ),
+            );
+        }
+
+        let session_lock_state = SessionLockManagerState::new::<Self, _>(&display, |client| {
+            ClientData::get_data(client)
+                .map(|data| data.is_visible(PrivilegedGlobals::SESSION_LOCK))
+                .unwrap_or(false)
+        });
         let output = Output::new(
             "Test output".into(),
             PhysicalProperties {
@@ -57,12 +236,9 @@ impl Aerugo {
                 model: String::new(),
             },
         );
-        output.create_global::<Self>(&display);
-
-        let mut scene = Scene::new();
-        scene.create_output(output.clone());
 
         let shell = Shell::new();
+        let wm = WmSupervisor::new(r#loop.clone(), wm);
 
         let generation = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -71,17 +247,688 @@ impl Aerugo {
             // If the system time is messed up, pick some predefined generation timestamp.
             .unwrap_or(u64::MAX);
 
-        Self {
+        let mut state = Self {
+            r#loop: r#loop.clone(),
             display,
             wl_compositor,
             xdg_shell,
+            xdg_decoration,
+            xdg_activation,
+            activation_tokens: ActivationTokens::new(),
+            launcher: Launcher::new(socket_name),
             seat_state,
+            seat,
+            data_device,
+            primary_selection,
+            data_control: DataControlState::new(),
+            drag_icon: None,
+            screencopy: ScreencopyState::new(),
+            screenshots: ScreenshotState::new(),
+            screencasts: Screencasts::new(),
+            virtual_outputs: VirtualOutputs::new(),
+            viewporter,
+            single_pixel_buffer,
+            drm_syncobj,
+            aerugo_shell: AerugoShellState::new(),
+            render_stats: RenderStats::new(),
+            counters: Counters::new(),
+            output_management: OutputManagementState::new(),
+            output_power: OutputPowerState::new(),
+            gamma_control: GammaControlState::new(),
+            night_light: NightLight::new(),
+            idle: IdleState::new(Instant::now()),
+            idle_inhibit: IdleInhibitState::new(),
+            session_lock_state,
+            session_lock: SessionLock::default(),
+            wm,
+            fallback_wm: FallbackWm::new(),
+            ext_workspace: ExtWorkspaceState::new(),
+            transactions: TransactionConfig::default(),
+            limits: LimitsConfig::default(),
+            window_rules: Vec::new(),
+            input: InputState::default(),
+            conformance,
+            overview: None,
+            pointer_location: Point::default(),
+            cursor: Cursor::new(),
             shell,
-            scene,
-            output,
+            popups: Popups::new(),
+            scene: Scene::new(),
+            frame_throttle: FrameThrottle::new(),
+            output: output.clone(),
+            outputs: Vec::new(),
+            globals,
             backend,
             generation,
+            disconnected_clients: DisconnectedClients::default(),
+        };
+
+        state.add_output(output);
+        state.start_idle_timer();
+        state.start_night_light_timer();
+        state
+    }
+}
+
+impl Aerugo {
+    /// Apply the keyboard configuration to the seat.
+    ///
+    /// The keyboard is recreated if the keymap changed from the previous configuration.
+    pub fn apply_keyboard_config(&mut self, config: &KeyboardConfig, previous: Option<&KeyboardConfig>) {
+        let keymap_changed = previous.map_or(true, |previous| previous.keymap_changed(config));
+        self.input
+            .key_repeat()
+            .set_info(config.repeat_rate, config.repeat_delay);
+
+        if !keymap_changed {
+            if let Some(keyboard) = self.seat.get_keyboard() {
+                keyboard.change_repeat_info(config.repeat_rate, config.repeat_delay);
+                return;
+            }
+        }
+
+        let xkb = XkbConfig {
+            rules: &config.rules,
+            model: &config.model,
+            layout: &config.layout,
+            variant: &config.variant,
+            options: config.options.clone(),
+        };
+
+        self.seat.remove_keyboard();
+
+        if let Err(err) = self.seat.add_keyboard(xkb, config.repeat_delay, config.repeat_rate) {
+            tracing::error!(%err, "Failed to load keymap, using the default keymap");
+            self.seat
+                .add_keyboard(XkbConfig::default(), config.repeat_delay, config.repeat_rate)
+                .expect("Failed to load the default keymap");
+        }
+    }
+
+    /// Apply new window rules and send the resulting decoration style of every toplevel to the wm.
+    pub fn apply_window_rules(&mut self, rules: Vec<WindowRule>) {
+        self.window_rules = rules;
+
+        for (&id, toplevel) in &self.shell.toplevels {
+            let Some(wm_id) = wm::wm_toplevel_id(id) else {
+                continue;
+            };
+
+            let update = ToplevelUpdate {
+                decoration_style: Some(toplevel.window_rules(&self.window_rules).style()),
+                ..Default::default()
+            };
+
+            self.wm.send(WmEvent::UpdateToplevel {
+                toplevel: wm_id,
+                update,
+            });
+        }
+    }
+
+    /// Advertise a new output to clients and add the output to the scene, output management and the wm.
+    pub fn add_output(&mut self, output: Output) {
+        if self.outputs.iter().any(|(connected, _)| *connected == output) {
+            return;
+        }
+
+        tracing::info!(output = output.name(), "Output added");
+
+        let global = output.create_global::<Self>(&self.display);
+        self.scene.create_output(output.clone());
+        self.output_management.add_output(&self.display, &output, true);
+
+        match wm::wm_output_id(self.shell.allocate_id()) {
+            Some(id) => self.wm.output_connected(id, output.clone()),
+            None => tracing::error!(output = output.name(), "Ran out of wm ids for the output"),
+        }
+
+        if self.outputs.is_empty() {
+            self.output = output.clone();
         }
+
+        self.outputs.push((output.clone(), global));
+
+        if let Err(err) = self.apply_gamma(&output) {
+            tracing::warn!(output = output.name(), %err, "Failed to set gamma");
+        }
+    }
+
+    /// Remove a disconnected output.
+    ///
+    /// The global of the output is disabled and returned. The global should be removed once clients had a
+    /// chance to notice the global was disabled, otherwise clients binding the global at the same time are
+    /// disconnected.
+    pub fn remove_output(&mut self, output: &Output) -> Option<GlobalId> {
+        let index = self.outputs.iter().position(|(connected, _)| connected == output)?;
+        let (output, global) = self.outputs.remove(index);
+
+        tracing::info!(output = output.name(), "Output removed");
+
+        self.display.disable_global::<Self>(global.clone());
+        // The wm is told which toplevels were on the output while the output is still in the scene.
+        self.wm_output_disconnected(&output);
+        self.scene.destroy_output(&output);
+        self.output_management.remove_output(&output);
+        self.output_power.remove_output(&output);
+        self.gamma_control.remove_output(&output);
+        self.screencopy.remove_output(&output);
+        self.screencasts.remove_output(&output);
+        self.render_stats.remove_output(&output);
+
+        // The primary output stays set if there are no outputs left, since nothing is presented on it anyways.
+        if self.output == output {
+            if let Some((next, _)) = self.outputs.first() {
+                self.output = next.clone();
+            }
+        }
+
+        Some(global)
+    }
+
+    /// Destroy the global of a removed output once clients had a chance to notice the global was disabled.
+    ///
+    /// Clients binding the global at the same time the global is destroyed would be disconnected otherwise.
+    pub fn remove_global_later(&self, global: GlobalId) {
+        let display = self.display.clone();
+        self.r#loop
+            .insert_source(Timer::from_duration(GLOBAL_REMOVAL_DELAY), move |_, _, _| {
+                display.remove_global::<Aerugo>(global.clone());
+                TimeoutAction::Drop
+            })
+            .expect("Failed to insert global removal timer");
+    }
+
+    /// Disable the globals of the protocols implemented in tree and of every output.
+    ///
+    /// Clients are told the globals were removed, so no new objects are created while the server shuts down.
+    pub fn disable_globals(&mut self) {
+        for global in self.globals.iter().chain(self.outputs.iter().map(|(_, global)| global)) {
+            self.display.disable_global::<Self>(global.clone());
+        }
+    }
+
+    /// Clean up after clients which disconnected.
+    ///
+    /// Smithay destroys every object of a client when the client disconnects, so the destructors of the objects
+    /// already removed the toplevels, scene nodes and foreign toplevel handles of the client. This removes state
+    /// which outlives the objects of the client, and toplevels which were missed by the destructors.
+    pub fn cleanup_disconnected_clients(&mut self) {
+        let clients = self.disconnected_clients.take();
+
+        if clients.is_empty() {
+            return;
+        }
+
+        // Captures are fulfilled when the next frame is rendered, after the frame object was destroyed.
+        self.screencopy.remove_dead_captures();
+
+        for id in self.shell.dead_toplevels() {
+            tracing::warn!(id, "Toplevel outlived the surface of the toplevel");
+            Shell::close_toplevel(self, id);
+        }
+    }
+
+    /// The connected output with the specified name.
+    pub fn output_by_name(&self, name: &str) -> Option<&Output> {
+        self.outputs
+            .iter()
+            .map(|(output, _)| output)
+            .find(|output| output.name() == name)
+    }
+
+    /// Apply the output overrides to the matching outputs.
+    pub fn apply_output_config(&mut self, outputs: &[OutputConfig]) {
+        for config in outputs {
+            let Some(output) = self.output_by_name(&config.name).cloned() else {
+                continue;
+            };
+
+            output.change_current_state(
+                None,
+                None,
+                config.scale.map(Scale::Fractional),
+                config.position.map(Into::into),
+            );
+            color::set_output_color_state(&output, config.color_state());
+            self.set_adaptive_sync_allowed(&output, config.adaptive_sync.unwrap_or(false));
+            self.wm.output_changed(&output);
+        }
+    }
+
+    /// Change the mode of an output on behalf of the backend, for example when the window of a nested backend
+    /// was resized.
+    ///
+    /// The previous mode is no longer advertised. Output management clients and the wm are told about the new
+    /// geometry of the output, the scene follows the mode of the output on the next frame.
+    pub fn set_output_mode(&mut self, output: &Output, mode: Mode) {
+        let previous = output.current_mode();
+
+        if previous == Some(mode) {
+            return;
+        }
+
+        tracing::info!(output = output.name(), size = ?mode.size, refresh = mode.refresh, "Output mode changed");
+
+        output.change_current_state(Some(mode), None, None, None);
+        output.set_preferred(mode);
+
+        if let Some(previous) = previous {
+            output.delete_mode(previous);
+        }
+
+        let scene = &self.scene;
+        self.output_management
+            .configuration_changed(&self.display, |output| scene.get_output_index(output).is_some());
+        self.wm.output_changed(output);
+    }
+
+    /// Deliver a key press or release to the focused client, unless the key press matches a wm keybinding.
+    ///
+    /// A key press matching a keybinding is sent to the wm instead, and the release of the key is dropped.
+    /// Keybindings are not matched while the session is locked.
+    pub fn keyboard_key(&mut self, keycode: u32, state: KeyState, time: u32) {
+        self.user_activity();
+
+        let Some(keyboard) = self.seat.get_keyboard() else {
+            return;
+        };
+
+        let serial = SERIAL_COUNTER.next_serial();
+        let binding = keyboard.input(self, keycode, state, serial, time, |comp, modifiers, handle| {
+            let binding = match state {
+                KeyState::Pressed if !comp.session_lock.is_locked() => {
+                    let modifiers = keybindings::key_modifiers(modifiers);
+                    handle
+                        .raw_syms()
+                        .iter()
+                        .find_map(|&keysym| comp.wm.binding(modifiers, keysym))
+                }
+
+                KeyState::Pressed | KeyState::Released => None,
+            };
+
+            comp.input.filter_key(keycode, state, binding)
+        });
+
+        match (state, binding) {
+            (KeyState::Pressed, Some(Some(id))) => {
+                self.wm.send(WmEvent::Binding {
+                    id,
+                    time,
+                    repeat: false,
+                });
+
+                if let Some((delay, generation)) = self.input.key_repeat().press(keycode, id, time) {
+                    self.repeat_binding(delay, generation);
+                }
+            }
+
+            // Pressing a key delivered to a client stops the repeat of a keybinding.
+            (KeyState::Pressed, _) => self.input.key_repeat().stop(),
+
+            (KeyState::Released, _) => self.input.key_repeat().release(keycode),
+        }
+    }
+
+    /// Move the pointer to a location in the global compositor space.
+    ///
+    /// The surface under the pointer receives pointer focus.
+    pub fn pointer_motion(&mut self, location: Point<f64, Logical>, time: u32) {
+        self.user_activity();
+        self.pointer_location = location;
+        self.update_drag_icon();
+
+        // While the session is locked, pointer input only reaches the lock surfaces.
+        if !self.session_lock.is_locked() {
+            self.fallback_motion(location);
+            self.wm.send(WmEvent::Pointer {
+                time,
+                event: PointerEvent::Motion(PointerLocation {
+                    x: location.x as f32,
+                    y: location.y as f32,
+                }),
+            });
+        }
+
+        let Some(pointer) = self.seat.get_pointer() else {
+            return;
+        };
+
+        let focus = self.surface_under(location);
+        let event = MotionEvent {
+            location,
+            serial: SERIAL_COUNTER.next_serial(),
+            time,
+        };
+        pointer.motion(self, focus, &event);
+        pointer.frame(self);
+    }
+
+    /// Give pointer focus to the surface under the pointer again after the surfaces which receive input changed.
+    pub fn refresh_pointer_focus(&mut self) {
+        let Some(pointer) = self.seat.get_pointer() else {
+            return;
+        };
+
+        let focus = self.surface_under(self.pointer_location);
+        let event = MotionEvent {
+            location: self.pointer_location,
+            serial: SERIAL_COUNTER.next_serial(),
+            time: monotonic_time(),
+        };
+        pointer.motion(self, focus, &event);
+        pointer.frame(self);
+    }
+
+    /// Move the pointer by a distance, keeping the pointer on the output.
+    pub fn pointer_motion_relative(&mut self, delta: Point<f64, Logical>, time: u32) {
+        let area = popup::output_geometry(&self.output).to_f64();
+        let end = area.loc + area.size.to_point();
+        let location = self.pointer_location + delta;
+        let x = location.x.min(end.x - 1.0).max(area.loc.x);
+        let y = location.y.min(end.y - 1.0).max(area.loc.y);
+        self.pointer_motion((x, y).into(), time);
+    }
+
+    /// Deliver a button press or release at the location of the pointer.
+    ///
+    /// While the overview is shown, a press selects the toplevel under the pointer instead.
+    pub fn pointer_button(&mut self, button: u32, state: ButtonState, time: u32) {
+        self.user_activity();
+        let location = self.pointer_location;
+        let locked = self.session_lock.is_locked();
+
+        if self.overview.is_some() && !locked {
+            if state == ButtonState::Pressed {
+                self.overview_click(location);
+            }
+
+            return;
+        }
+
+        if !locked {
+            self.fallback_button(button, state, location);
+            self.wm.send(WmEvent::Pointer {
+                time,
+                event: PointerEvent::Button(PointerButton {
+                    button,
+                    pressed: state == ButtonState::Pressed,
+                }),
+            });
+        }
+
+        // A press also starts the implicit grab a client needs to start a drag.
+        if let Some(pointer) = self.seat.get_pointer() {
+            let event = ButtonEvent {
+                serial: SERIAL_COUNTER.next_serial(),
+                time,
+                button,
+                state,
+            };
+            pointer.button(self, &event);
+            pointer.frame(self);
+        }
+    }
+
+    /// Scroll by a distance at the location of the pointer.
+    pub fn pointer_axis(&mut self, dx: f64, dy: f64, time: u32) {
+        self.user_activity();
+
+        if !self.session_lock.is_locked() {
+            self.wm.send(WmEvent::Pointer {
+                time,
+                event: PointerEvent::Axis(PointerAxis {
+                    horizontal: dx as f32,
+                    vertical: dy as f32,
+                }),
+            });
+        }
+
+        let Some(pointer) = self.seat.get_pointer() else {
+            return;
+        };
+
+        let mut frame = AxisFrame::new(time).source(AxisSource::Continuous);
+
+        for (axis, value) in [(Axis::Horizontal, dx), (Axis::Vertical, dy)] {
+            if value != 0.0 {
+                frame = frame.value(axis, value);
+            }
+        }
+
+        pointer.axis(self, frame);
+        pointer.frame(self);
+    }
+
+    /// Deliver the pointer events of a device which passed the button filter of the device.
+    pub fn pointer_filtered(&mut self, events: Vec<FilteredEvent>) {
+        for event in events {
+            match event {
+                FilteredEvent::Button { button, state, time } => self.pointer_button(button, state, time),
+                FilteredEvent::Motion { dx, dy, time } => self.pointer_motion_relative((dx, dy).into(), time),
+                FilteredEvent::Scroll { dx, dy, time } => self.pointer_axis(dx, dy, time),
+            }
+        }
+    }
+
+    /// The surface presented at a location in the global compositor space, with the location of the surface.
+    ///
+    /// While the session is locked, only the lock surface of the output is under the pointer. Nothing is under the
+    /// pointer while the overview is shown.
+    fn surface_under(&self, location: Point<f64, Logical>) -> Option<(WlSurface, Point<i32, Logical>)> {
+        if self.session_lock.is_locked() {
+            let surface = self.session_lock.surface(&self.output)?;
+            return Some((surface.clone(), self.output.current_location()));
+        }
+
+        if self.overview.is_some() {
+            return None;
+        }
+
+        let scale = self.output.current_scale().fractional_scale();
+        let point = (location - self.output.current_location().to_f64()).to_physical(scale);
+        let (surface, local) = self
+            .scene
+            .get_graph(&self.output)?
+            .surface_under((0, 0).into(), scale.into(), point)?;
+
+        // The client receives the location relative to the location of the surface.
+        Some((surface, (location - local).to_i32_round()))
+    }
+
+    /// Deliver an event injected on a virtual seat through the same pipeline as physical devices.
+    ///
+    /// Events of virtual seats which do not exist are dropped.
+    pub fn inject_input(&mut self, seat: VirtualSeatId, event: InjectedEvent) {
+        let Some(virtual_seat) = self.input.virtual_seat(seat) else {
+            return;
+        };
+
+        if virtual_seat.track(&event) {
+            self.deliver_injected(event);
+        }
+    }
+
+    /// Remove a virtual seat, releasing every key and button held on the seat.
+    pub fn remove_virtual_seat(&mut self, seat: VirtualSeatId) {
+        let Some(mut virtual_seat) = self.input.remove_virtual_seat(seat) else {
+            return;
+        };
+
+        for event in virtual_seat.release_all() {
+            self.deliver_injected(event);
+        }
+    }
+
+    fn deliver_injected(&mut self, event: InjectedEvent) {
+        match event {
+            InjectedEvent::PointerMotion { dx, dy } => {
+                self.pointer_motion_relative((dx, dy).into(), monotonic_time());
+            }
+
+            InjectedEvent::PointerMotionAbsolute { location } => self.pointer_motion(location, monotonic_time()),
+
+            InjectedEvent::PointerButton { button, state } => self.pointer_button(button, state, monotonic_time()),
+
+            InjectedEvent::Key { keycode, state } => self.keyboard_key(keycode, state, monotonic_time()),
+        }
+    }
+
+    /// Send the keybinding held in `generation` to the wm again after `delay`, until the key is released.
+    fn repeat_binding(&mut self, delay: Duration, generation: u64) {
+        let timer = Timer::from_duration(delay);
+        let result = self.r#loop.insert_source(timer, move |_, _, aerugo| {
+            let Some(repeat) = aerugo.comp.input.key_repeat().repeat(generation) else {
+                return TimeoutAction::Drop;
+            };
+
+            aerugo.comp.wm.send(WmEvent::Binding {
+                id: repeat.binding,
+                time: repeat.time,
+                repeat: true,
+            });
+            TimeoutAction::ToDuration(repeat.next)
+        });
+
+        if let Err(err) = result {
+            tracing::error!(%err, "Failed to schedule keybinding repeat");
+        }
+    }
+
+    /// A touch point went down at a normalized position reported by an absolute input device.
+    ///
+    /// The seat has no touch support, so clients receive the primary touch point as the left pointer button
+    /// instead of `wl_touch` events.
+    pub fn touch_down(&mut self, device: &str, slot: TouchSlot, position: (f64, f64), time: u32) {
+        self.user_activity();
+        let location = self.map_absolute_position(device, position);
+
+        let mut gestures = Vec::new();
+        let id = self.input.touch().down(slot, location, &mut gestures);
+
+        if self.input.touch().is_primary(slot) {
+            self.pointer_motion(location, time);
+            self.pointer_button(BTN_LEFT, ButtonState::Pressed, time);
+        }
+
+        self.send_gestures(time, gestures);
+        self.wm.send(WmEvent::Touch {
+            time,
+            event: TouchEvent::Down(touch_point(id, location)),
+        });
+    }
+
+    pub fn touch_motion(&mut self, device: &str, slot: TouchSlot, position: (f64, f64), time: u32) {
+        self.user_activity();
+        let location = self.map_absolute_position(device, position);
+
+        let mut gestures = Vec::new();
+        let Some(id) = self.input.touch().motion(slot, location, &mut gestures) else {
+            return;
+        };
+
+        if self.input.touch().is_primary(slot) {
+            self.pointer_motion(location, time);
+        }
+
+        self.wm.send(WmEvent::Touch {
+            time,
+            event: TouchEvent::Motion(touch_point(id, location)),
+        });
+        self.send_gestures(time, gestures);
+    }
+
+    pub fn touch_up(&mut self, slot: TouchSlot, time: u32) {
+        self.user_activity();
+        let primary = self.input.touch().is_primary(slot);
+        let mut gestures = Vec::new();
+        let Some(id) = self.input.touch().up(slot, &mut gestures) else {
+            return;
+        };
+
+        if primary {
+            self.pointer_button(BTN_LEFT, ButtonState::Released, time);
+        }
+
+        self.send_gestures(time, gestures);
+        self.wm.send(WmEvent::Touch {
+            time,
+            event: TouchEvent::Up(id),
+        });
+    }
+
+    pub fn touch_cancel(&mut self, time: u32) {
+        let primary = self.input.touch().primary_down();
+        let mut gestures = Vec::new();
+        self.input.touch().cancel(&mut gestures);
+
+        if primary {
+            self.pointer_button(BTN_LEFT, ButtonState::Released, time);
+        }
+
+        self.send_gestures(time, gestures);
+        self.wm.send(WmEvent::Touch {
+            time,
+            event: TouchEvent::Cancel,
+        });
+    }
+
+    fn send_gestures(&mut self, time: u32, gestures: Vec<touch::GestureEvent>) {
+        for gesture in gestures {
+            let event = match gesture {
+                touch::GestureEvent::Begin { kind, fingers } => wm_runtime::GestureEvent::Begin(GestureBegin {
+                    kind: match kind {
+                        touch::GestureKind::Swipe => GestureKind::Swipe,
+                        touch::GestureKind::Pinch => GestureKind::Pinch,
+                    },
+                    fingers,
+                }),
+                touch::GestureEvent::Update { dx, dy, scale } => wm_runtime::GestureEvent::Update(GestureUpdate {
+                    dx: dx as f32,
+                    dy: dy as f32,
+                    scale: scale as f32,
+                }),
+                touch::GestureEvent::End { cancelled } => wm_runtime::GestureEvent::End(cancelled),
+            };
+
+            self.wm.send(WmEvent::Gesture { time, event });
+        }
+    }
+
+    /// Map a normalized position reported by an absolute input device into the global compositor space.
+    ///
+    /// The position is calibrated and mapped onto the output associated with the device.
+    pub fn map_absolute_position(&self, device: &str, position: (f64, f64)) -> Point<f64, Logical> {
+        let config = self.input.config(device);
+
+        let output = match config.and_then(|config| config.output.as_deref()) {
+            Some(name) => self.output_by_name(name).unwrap_or_else(|| {
+                tracing::debug!(
+                    device,
+                    output = name,
+                    "Input device is associated with an unknown output"
+                );
+                &self.output
+            }),
+            None => &self.output,
+        };
+
+        input::map_to_output(position, config.and_then(|config| config.calibration.as_ref()), output)
+    }
+}
+
+/// The time of the monotonic clock in milliseconds, the clock used for the timestamps of input events.
+fn monotonic_time() -> u32 {
+    let now = rustix::time::clock_gettime(rustix::time::ClockId::Monotonic);
+    (now.tv_sec as u64 * 1000 + now.tv_nsec as u64 / 1_000_000) as u32
+}
+
+fn touch_point(id: i32, location: Point<f64, Logical>) -> TouchPoint {
+    TouchPoint {
+        id,
+        x: location.x as f32,
+        y: location.y as f32,
     }
 }
 
@@ -96,7 +943,7 @@ bitflags! {
         ///
         /// This protocol is always enabled with the `ext-foreign-toplevel-list-v1` protocol.
         ///
-        /// This is not enabled at the moment since the protocol is not yet done: https://gitlab.freedesktop.org/wayland/wayland-protocols/-/merge_requests/196
+        /// The protocol is not yet done upstream, so a draft is vendored: https://gitlab.freedesktop.org/wayland/wayland-protocols/-/merge_requests/196
         const FOREIGN_TOPLEVEL_STATE = 0x03;
 
         /// Whether the foreign toplevel management global is available.
@@ -119,6 +966,21 @@ bitflags! {
 
         /// Whether the `aerugo-shell-v1` protocol is available.
         const AERUGO_SHELL = 0x40;
+
+        /// Whether the `zwlr-screencopy-manager-v1` protocol is available.
+        const SCREENCOPY = 0x80;
+
+        /// Whether the `zwlr-output-manager-v1` protocol is available.
+        const OUTPUT_MANAGEMENT = 0x100;
+
+        /// Whether the `zwlr-data-control-manager-v1` protocol is available.
+        const DATA_CONTROL = 0x200;
+
+        /// Whether the `zwlr-gamma-control-manager-v1` protocol is available.
+        const GAMMA_CONTROL = 0x800;
+
+        /// Whether the `aerugo-debug-v1` protocol is available.
+        const DEBUG = 0x1000;
     }
 }
 
@@ -127,6 +989,25 @@ pub struct ClientData {
     // TODO: Make private
     pub(super) globals: PrivilegedGlobals,
     pub(super) compositor: CompositorClientState,
+    pub(super) disconnected: DisconnectedClients,
+    pub(super) limits: ClientLimits,
+}
+
+/// Clients which disconnected since the compositor last cleaned up after disconnected clients.
+///
+/// Clients are reported from [`ClientData::disconnected`], which has no access to the compositor state, so the
+/// cleanup happens on the next iteration of the event loop.
+#[derive(Debug, Default, Clone)]
+pub struct DisconnectedClients(Arc<Mutex<Vec<ClientId>>>);
+
+impl DisconnectedClients {
+    fn push(&self, client: ClientId) {
+        self.0.lock().unwrap().push(client);
+    }
+
+    fn take(&self) -> Vec<ClientId> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
 }
 
 impl ClientData {
@@ -146,7 +1027,10 @@ impl ClientData {
 impl wayland_server::backend::ClientData for ClientData {
     fn initialized(&self, _client_id: ClientId) {}
 
-    fn disconnected(&self, _client_id: ClientId, _reason: DisconnectReason) {}
+    fn disconnected(&self, client_id: ClientId, reason: DisconnectReason) {
+        tracing::debug!(?client_id, ?reason, "Client disconnected");
+        self.disconnected.push(client_id);
+    }
 
     fn debug(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         <Self as fmt::Debug>::fmt(self, f)
@@ -0,0 +1,242 @@
+//! Render statistics
+//!
+//! The backends time each pass of a frame (a zone) and record the timings per output. The statistics are kept
+//! over a window of recent frames, which is enough to evaluate the cost of effects and to triage performance
+//! reports. The summary of each output is reported by the `get_frame_stats` IPC request (`aerugo-msg
+//! frame-stats`).
+//!
+//! The timings are measured on the CPU around the work submitted to the renderer. The GLES renderer does not
+//! expose timestamp queries or debug labels, so the time the GPU spends on a pass is not measured and the passes
+//! are not labelled in graphics debuggers.
+//!
+//! Every zone is also entered as a `zone` span at the trace level. When built with the `profiling` feature the
+//! spans are sent to [Tracy](https://github.com/wolfpld/tracy), which shows the per-frame path (client dispatch,
+//! commits, scene updates, rendering and submission) on a timeline.
+//!
+//! [`Counters`] accumulate totals over the lifetime of the display server, such as how many frames were rendered
+//! and how many requests of clients were dispatched.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use rustc_hash::FxHashMap;
+use smithay::output::Output;
+
+/// How many frames are kept per output.
+const WINDOW: usize = 120;
+
+/// The timings of the zones of a single frame.
+#[derive(Debug, Default, Clone)]
+pub struct FrameTimings {
+    zones: Vec<(&'static str, Duration)>,
+}
+
+impl FrameTimings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `f` and record how long it took as the zone with the specified name.
+    pub fn zone<T>(&mut self, name: &'static str, f: impl FnOnce() -> T) -> T {
+        let _span = tracing::trace_span!("zone", name).entered();
+        let start = Instant::now();
+        let value = f();
+        self.record(name, start.elapsed());
+        value
+    }
+
+    pub fn record(&mut self, name: &'static str, duration: Duration) {
+        self.zones.push((name, duration));
+    }
+
+    /// The total time of the frame.
+    pub fn total(&self) -> Duration {
+        self.zones.iter().map(|(_, duration)| *duration).sum()
+    }
+}
+
+/// Statistics of a zone over the recorded frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZoneSummary {
+    pub name: &'static str,
+    pub average: Duration,
+    pub max: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct RenderStats {
+    outputs: FxHashMap<Output, VecDeque<FrameTimings>>,
+}
+
+impl RenderStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the timings of a frame rendered on an output.
+    pub fn record(&mut self, output: &Output, frame: FrameTimings) {
+        let frames = self.outputs.entry(output.clone()).or_default();
+
+        if frames.len() == WINDOW {
+            frames.pop_front();
+        }
+
+        frames.push_back(frame);
+    }
+
+    pub fn remove_output(&mut self, output: &Output) {
+        self.outputs.remove(output);
+    }
+
+    /// Summarize the recorded frames of an output.
+    ///
+    /// The summary of the whole frame is named `frame`, followed by the zones in the order they were first
+    /// recorded.
+    pub fn summary(&self, output: &Output) -> Vec<ZoneSummary> {
+        self.outputs.get(output).map(summarize).unwrap_or_default()
+    }
+}
+
+/// Totals since the display server started.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counters {
+    /// The number of rendered frames.
+    pub frames: u64,
+
+    /// The time spent rendering frames.
+    pub frame_time: Duration,
+
+    /// How long the last frame took to render.
+    pub last_frame_time: Duration,
+
+    /// The area of all rendered frames which was damaged, in physical pixels.
+    pub damage_area: u64,
+
+    /// How often clients were dispatched.
+    pub dispatches: u64,
+
+    /// The number of requests of clients which were dispatched.
+    pub requests: u64,
+}
+
+impl Counters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a rendered frame.
+    pub fn frame(&mut self, duration: Duration, damage_area: u64) {
+        self.frames += 1;
+        self.frame_time += duration;
+        self.last_frame_time = duration;
+        self.damage_area += damage_area;
+    }
+
+    /// Record that clients were dispatched.
+    pub fn dispatched(&mut self, requests: usize) {
+        self.dispatches += 1;
+        self.requests += requests as u64;
+    }
+
+    /// The average time a frame took to render.
+    pub fn average_frame_time(&self) -> Duration {
+        match self.frames {
+            0 => Duration::ZERO,
+            frames => self.frame_time / frames as u32,
+        }
+    }
+}
+
+fn summarize(frames: &VecDeque<FrameTimings>) -> Vec<ZoneSummary> {
+    if frames.is_empty() {
+        return Vec::new();
+    }
+
+    let mut zones = vec![("frame", frames.iter().map(FrameTimings::total).collect::<Vec<_>>())];
+
+    for frame in frames {
+        for &(name, duration) in &frame.zones {
+            match zones.iter_mut().find(|(zone, _)| *zone == name) {
+                Some((_, durations)) => durations.push(duration),
+                None => zones.push((name, vec![duration])),
+            }
+        }
+    }
+
+    zones
+        .into_iter()
+        .map(|(name, durations)| ZoneSummary {
+            name,
+            average: durations.iter().sum::<Duration>() / durations.len() as u32,
+            max: durations.iter().copied().max().unwrap_or_default(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, time::Duration};
+
+    use super::{summarize, Counters, FrameTimings, ZoneSummary};
+
+    fn frame(zones: &[(&'static str, u64)]) -> FrameTimings {
+        let mut frame = FrameTimings::new();
+
+        for &(name, millis) in zones {
+            frame.record(name, Duration::from_millis(millis));
+        }
+
+        frame
+    }
+
+    #[test]
+    fn summary() {
+        let frames = VecDeque::from([frame(&[("scene", 2), ("copy", 4)]), frame(&[("scene", 4)])]);
+
+        assert_eq!(
+            summarize(&frames),
+            [
+                ZoneSummary {
+                    name: "frame",
+                    average: Duration::from_millis(5),
+                    max: Duration::from_millis(6),
+                },
+                ZoneSummary {
+                    name: "scene",
+                    average: Duration::from_millis(3),
+                    max: Duration::from_millis(4),
+                },
+                ZoneSummary {
+                    name: "copy",
+                    average: Duration::from_millis(4),
+                    max: Duration::from_millis(4),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn empty() {
+        assert!(summarize(&VecDeque::new()).is_empty());
+    }
+
+    #[test]
+    fn counters() {
+        let mut counters = Counters::new();
+        assert_eq!(counters.average_frame_time(), Duration::ZERO);
+
+        counters.frame(Duration::from_millis(2), 100);
+        counters.frame(Duration::from_millis(4), 50);
+        counters.dispatched(3);
+        counters.dispatched(0);
+
+        assert_eq!(counters.frames, 2);
+        assert_eq!(counters.average_frame_time(), Duration::from_millis(3));
+        assert_eq!(counters.last_frame_time, Duration::from_millis(4));
+        assert_eq!(counters.damage_area, 150);
+        assert_eq!(counters.dispatches, 2);
+        assert_eq!(counters.requests, 3);
+    }
+}
@@ -0,0 +1,271 @@
+//! Synthetic workloads for tests and benchmarks
+//!
+//! The generators build large trees shaped like the state of a wm with many nested containers. They are
+//! deterministic for a seed, so benchmark runs are comparable.
+//!
+//! This module is not part of the stable API of the crate.
+
+use smithay::{
+    output::{Output, PhysicalProperties, Subpixel},
+    utils::{Physical, Point, Scale},
+};
+
+use crate::{
+    forest::{Forest, Index},
+    scene::{BranchIndex, NodeIndex, Scene, Transaction},
+    transaction::{DependencyTracker, Id},
+};
+
+/// A small xorshift generator, good enough to scatter nodes.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift never leaves zero.
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `0..bound`.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound.max(1)
+    }
+}
+
+/// The shape of a generated tree.
+#[derive(Debug, Clone, Copy)]
+pub struct TreeShape {
+    /// How many levels of nodes are below the root.
+    pub depth: usize,
+
+    /// How many children every node above the last level has.
+    pub fanout: usize,
+
+    pub seed: u64,
+}
+
+impl TreeShape {
+    /// The number of nodes of a tree with this shape, including the root.
+    pub fn nodes(&self) -> usize {
+        (0..=self.depth).map(|level| self.fanout.pow(level as u32)).sum()
+    }
+}
+
+/// Generate a forest containing a single tree, returning the forest and the root of the tree.
+///
+/// Every node holds its depth.
+pub fn forest(shape: TreeShape) -> (Forest<usize>, Index) {
+    let mut forest = Forest::new();
+    let root = forest.insert(0);
+    let mut level = vec![root];
+
+    for depth in 1..=shape.depth {
+        let mut next = Vec::with_capacity(level.len() * shape.fanout);
+
+        for &parent in &level {
+            for _ in 0..shape.fanout {
+                let child = forest.insert(depth);
+                forest.add_child(parent, child).unwrap();
+                next.push(child);
+            }
+        }
+
+        level = next;
+    }
+
+    (forest, root)
+}
+
+/// A generated scene graph presented on a single output.
+///
+/// The scene consists of nested branches with scattered offsets and varying opacity. Creating surfaces
+/// requires a connected Wayland client, so the scene does not contain any surfaces.
+#[derive(Debug)]
+pub struct SyntheticScene {
+    scene: Scene,
+    output: Output,
+
+    /// The branches on the last level, which have no children.
+    leaves: Vec<BranchIndex>,
+    fanout: usize,
+}
+
+impl SyntheticScene {
+    pub fn generate(shape: TreeShape) -> Self {
+        let mut rng = Rng::new(shape.seed);
+        let mut scene = Scene::new();
+        let output = Output::new(
+            "SYNTHETIC-1".into(),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: String::new(),
+                model: String::new(),
+            },
+        );
+        scene.create_output(output.clone());
+
+        let root = scene.create_branch();
+        scene.set_output_node(&output, NodeIndex::Branch(root));
+
+        let mut transaction = Transaction::new();
+        let mut level = vec![root];
+
+        for _ in 0..shape.depth {
+            let mut next = Vec::with_capacity(level.len() * shape.fanout);
+
+            for &parent in &level {
+                for _ in 0..shape.fanout {
+                    let branch = scene.create_branch();
+                    let node = NodeIndex::Branch(branch);
+                    scene.branch_add_child(parent, node).unwrap();
+
+                    let offset = Point::from((rng.below(64) as i32, rng.below(64) as i32));
+                    let opacity = 0.5 + rng.below(50) as f32 / 100.0;
+                    transaction.set_offset(node, offset).set_opacity(node, opacity);
+                    next.push(branch);
+                }
+            }
+
+            level = next;
+        }
+
+        scene.apply_transaction(transaction).unwrap();
+
+        Self {
+            scene,
+            output,
+            leaves: level,
+            fanout: shape.fanout,
+        }
+    }
+
+    /// The number of elements presented on the output.
+    pub fn render_elements(&self) -> usize {
+        self.scene
+            .get_graph(&self.output)
+            .map(|graph| graph.elements((0, 0).into(), Scale::from(1.0), 1.0).len())
+            .unwrap_or_default()
+    }
+
+    /// Sum the locations of every leaf, which walks the ancestors of each leaf.
+    pub fn leaf_locations(&self) -> Point<i32, Physical> {
+        self.leaves
+            .iter()
+            .map(|&leaf| self.scene.node_location(NodeIndex::Branch(leaf)))
+            .fold(Point::default(), |sum, location| sum + location)
+    }
+
+    /// Move the topmost leaf of every branch to the bottom and offset it, as a wm restacking every container
+    /// would.
+    pub fn restack(&mut self) {
+        let mut transaction = Transaction::new();
+
+        for siblings in self
+            .leaves
+            .chunks_exact(self.fanout.max(1))
+            .filter(|siblings| siblings.len() > 1)
+        {
+            let [bottom, top] = [siblings[0], siblings[siblings.len() - 1]].map(NodeIndex::Branch);
+            transaction.place_below(top, bottom).set_offset(top, (1, 1).into());
+        }
+
+        self.scene.apply_transaction(transaction).unwrap();
+    }
+}
+
+/// Transactions waiting on surface commits, as created by a wm configuring many windows.
+#[derive(Debug)]
+pub struct DependencyGraph {
+    /// The transactions in the order they were created.
+    pub transactions: Vec<Id>,
+
+    /// The commits the transactions wait on.
+    pub commits: Vec<Id>,
+}
+
+/// Create `count` transactions, each waiting on the previous transaction and `commits` commits.
+///
+/// The commits are picked from a pool of `count` commits, so a commit may be waited on by several transactions.
+pub fn dependency_graph(tracker: &mut DependencyTracker, count: usize, commits: usize, seed: u64) -> DependencyGraph {
+    let mut rng = Rng::new(seed);
+    let pool = (0..count).map(|_| tracker.create_id()).collect::<Vec<_>>();
+    let mut transactions = Vec::<Id>::with_capacity(count);
+
+    for _ in 0..count {
+        let id = tracker.create_id();
+
+        if let Some(&previous) = transactions.last() {
+            tracker.add_dependency(id, previous).unwrap();
+        }
+
+        for _ in 0..commits {
+            let commit = pool[rng.below(count as u64) as usize];
+            tracker.add_dependency(id, commit).unwrap();
+        }
+
+        transactions.push(id);
+    }
+
+    DependencyGraph {
+        transactions,
+        commits: pool,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transaction::{DependencyTracker, Status};
+
+    use super::{dependency_graph, forest, SyntheticScene, TreeShape};
+
+    const SHAPE: TreeShape = TreeShape {
+        depth: 3,
+        fanout: 4,
+        seed: 7,
+    };
+
+    #[test]
+    fn forest_shape() {
+        let (forest, root) = forest(SHAPE);
+        assert_eq!(SHAPE.nodes(), 85);
+        assert_eq!(forest.dfs_descend(root).unwrap().count(), SHAPE.nodes());
+    }
+
+    #[test]
+    fn synthetic_scene() {
+        let mut scene = SyntheticScene::generate(SHAPE);
+        assert_eq!(scene.leaves.len(), 64);
+        assert!(scene.scene.output_node(&scene.output).is_some());
+
+        // Branches are not presented by themselves.
+        assert_eq!(scene.render_elements(), 0);
+
+        let before = scene.leaf_locations();
+        scene.restack();
+        assert_ne!(scene.leaf_locations(), before);
+    }
+
+    #[test]
+    fn transactions_finish_once_commits_finish() {
+        let mut tracker = DependencyTracker::new();
+        let graph = dependency_graph(&mut tracker, 100, 3, 7);
+        assert_eq!(graph.transactions.len(), 100);
+
+        for &commit in &graph.commits {
+            tracker.finish(commit);
+        }
+
+        assert!(graph
+            .transactions
+            .iter()
+            .all(|&id| tracker.get_status(id) == Some(Status::Finished)));
+    }
+}
@@ -1,6 +1,17 @@
 //! Dependency tracking
 //!
 //! This module provides the [`DependencyTracker`] type to help manage transaction dependencies.
+//!
+//! Besides other transactions, a transaction may wait on a [`Fence`] of the backend, such as the release of a
+//! buffer or the presentation of a prior frame. A fence is a node without dependencies which only finishes when
+//! the backend signals it. Backends report signalled fences on the event loop, where the owner of the tracker
+//! calls [`DependencyTracker::signal`].
+//!
+//! Nodes are removed from the tracker once their final status was observed by draining it, using
+//! [`drain_finished`](DependencyTracker::drain_finished), [`drain_failed`](DependencyTracker::drain_failed) or
+//! [`drain_cancelled`](DependencyTracker::drain_cancelled). The slots of removed nodes are reused by new nodes.
+//! An [`Id`] of a removed node is never mistaken for a new node, since the slot map keys are generational:
+//! the tracker treats the [`Id`] as not present.
 
 use std::mem;
 
@@ -16,6 +27,9 @@ pub enum Status {
     Queued,
     Finished,
     Failed,
+
+    /// The node or a dependency was cancelled.
+    Cancelled,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -23,13 +37,42 @@ pub enum Error {
     NotPresent,
 
     CausesCycle,
+
+    /// A fence cannot depend on other nodes.
+    FenceDependency,
+
+    /// The node signalled is not a fence.
+    NotFence,
+}
+
+/// Work of the backend a transaction may wait on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fence {
+    /// The backend no longer reads from a buffer, so the buffer can be released to the client.
+    BufferRelease,
+
+    /// A prior frame was presented on an output.
+    Presentation,
 }
 
-#[derive(Default)]
+/// The number of nodes in a [`DependencyTracker`] by status.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TrackerStats {
+    pub queued: usize,
+    pub finished: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+
+    /// The number of nodes the tracker can hold without allocating.
+    pub capacity: usize,
+}
+
+#[derive(Debug, Default)]
 pub struct DependencyTracker {
     nodes: SlotMap<Id, Node>,
     failed: Vec<Id>,
     finished: Vec<Id>,
+    cancelled: Vec<Id>,
 }
 
 impl DependencyTracker {
@@ -38,6 +81,7 @@ impl DependencyTracker {
             nodes: SlotMap::with_key(),
             failed: Vec::new(),
             finished: Vec::new(),
+            cancelled: Vec::new(),
         }
     }
 
@@ -49,6 +93,31 @@ impl DependencyTracker {
         self.nodes.insert(Node::default())
     }
 
+    /// Create a node which finishes once the backend signals the fence.
+    pub fn create_fence(&mut self, fence: Fence) -> Id {
+        self.nodes.insert(Node {
+            fence: Some(fence),
+            ..Node::default()
+        })
+    }
+
+    /// The fence of a node, if the node is a fence.
+    pub fn get_fence(&self, id: Id) -> Option<Fence> {
+        self.nodes.get(id).and_then(|node| node.fence)
+    }
+
+    /// Signal a fence, finishing the fence and the nodes which only waited on the fence.
+    pub fn signal(&mut self, id: Id) -> Result<(), Error> {
+        let node = self.nodes.get(id).ok_or(Error::NotPresent)?;
+
+        if node.fence.is_none() {
+            return Err(Error::NotFence);
+        }
+
+        self.finish(id);
+        Ok(())
+    }
+
     /// Add a dependency to the specified node.
     ///
     /// Returns [`Err`] if adding the dependency would cause a cycle.
@@ -61,6 +130,10 @@ impl DependencyTracker {
             return Err(Error::CausesCycle);
         }
 
+        if self.nodes[id].fence.is_some() {
+            return Err(Error::FenceDependency);
+        }
+
         // Does id appear in the dependency's dependencies?
         {
             // Use a stack to iterate without recursion.
@@ -120,10 +193,11 @@ impl DependencyTracker {
             return Ok(Status::Queued);
         }
 
-        // The dependency failed, so propagate the failure to dependents.
-        self.fail(id);
+        // The dependency failed or was cancelled, so propagate the status to dependents.
+        let status = dependency_node.status;
+        self.propagate(id, status);
 
-        Ok(Status::Failed)
+        Ok(status)
     }
 
     /// Changes the node status to failed.
@@ -132,6 +206,29 @@ impl DependencyTracker {
     ///
     /// The list of nodes that failed as a result of this call be be obtained using [`DependencyTracker::drain_failed`].
     pub fn fail(&mut self, id: Id) {
+        self.propagate(id, Status::Failed);
+    }
+
+    /// Changes the node status to cancelled.
+    ///
+    /// If a node is cancelled, all dependent nodes are also cancelled.
+    ///
+    /// The list of nodes that were cancelled as a result of this call be be obtained using
+    /// [`DependencyTracker::drain_cancelled`].
+    pub fn cancel(&mut self, id: Id) {
+        self.propagate(id, Status::Cancelled);
+    }
+
+    /// Take the nodes which were cancelled since the last call, removing them from the tracker.
+    #[must_use]
+    pub fn drain_cancelled(&mut self) -> Vec<Id> {
+        let cancelled = mem::take(&mut self.cancelled);
+        self.remove_all(&cancelled);
+        cancelled
+    }
+
+    /// Change the status of the node and all dependents.
+    fn propagate(&mut self, id: Id, status: Status) {
         if !self.nodes.contains_key(id) {
             return;
         };
@@ -142,17 +239,31 @@ impl DependencyTracker {
         while !stack.is_empty() {
             for dependent in mem::take(&mut stack) {
                 let node = self.nodes.get_mut(dependent).unwrap();
+
+                // A node reached through several dependencies is only changed once.
+                if node.status == status {
+                    continue;
+                }
+
                 stack.extend(node.dependents.iter());
 
-                self.failed.push(dependent);
-                node.status = Status::Failed;
+                match status {
+                    Status::Failed => self.failed.push(dependent),
+                    Status::Cancelled => self.cancelled.push(dependent),
+                    Status::Queued | Status::Finished => unreachable!(),
+                }
+
+                node.status = status;
             }
         }
     }
 
+    /// Take the nodes which failed since the last call, removing them from the tracker.
     #[must_use]
     pub fn drain_failed(&mut self) -> Vec<Id> {
-        mem::take(&mut self.failed)
+        let failed = mem::take(&mut self.failed);
+        self.remove_all(&failed);
+        failed
     }
 
     /// Changes the node status to finished.
@@ -173,8 +284,8 @@ impl DependencyTracker {
             for id in mem::take(&mut stack) {
                 let node = self.nodes.get_mut(id).unwrap();
 
-                // If the node has unfinished dependencies, skip it.
-                if !node.dependencies.is_empty() {
+                // If the node has unfinished dependencies or already finished, skip it.
+                if !node.dependencies.is_empty() || node.status == Status::Finished {
                     continue;
                 }
 
@@ -195,24 +306,80 @@ impl DependencyTracker {
         }
     }
 
+    /// Take the nodes which finished since the last call, removing them from the tracker.
     #[must_use]
     pub fn drain_finished(&mut self) -> Vec<Id> {
-        mem::take(&mut self.finished)
+        let finished = mem::take(&mut self.finished);
+        self.remove_all(&finished);
+        finished
+    }
+
+    /// Remove every node, keeping the allocated capacity.
+    ///
+    /// The [`Id`]s of the removed nodes are no longer present.
+    pub fn reset(&mut self) {
+        self.nodes.clear();
+        self.failed.clear();
+        self.finished.clear();
+        self.cancelled.clear();
+    }
+
+    pub fn stats(&self) -> TrackerStats {
+        let mut stats = TrackerStats {
+            capacity: self.nodes.capacity(),
+            ..TrackerStats::default()
+        };
+
+        for node in self.nodes.values() {
+            match node.status {
+                Status::Queued => stats.queued += 1,
+                Status::Finished => stats.finished += 1,
+                Status::Failed => stats.failed += 1,
+                Status::Cancelled => stats.cancelled += 1,
+            }
+        }
+
+        stats
+    }
+
+    /// Remove nodes, unlinking them from the nodes they depend on and the nodes depending on them.
+    fn remove_all(&mut self, ids: &[Id]) {
+        for &id in ids {
+            // A node may be drained more than once, for example if a finished node was cancelled.
+            let Some(node) = self.nodes.remove(id) else {
+                continue;
+            };
+
+            for dependency in node.dependencies {
+                if let Some(dependency) = self.nodes.get_mut(dependency) {
+                    dependency.dependents.retain(|&dependent| dependent != id);
+                }
+            }
+
+            for dependent in node.dependents {
+                if let Some(dependent) = self.nodes.get_mut(dependent) {
+                    dependent.dependencies.retain(|&dependency| dependency != id);
+                }
+            }
+        }
     }
 }
 
-#[derive(Default)]
+#[derive(Debug, Default)]
 struct Node {
     dependents: Vec<Id>,
     dependencies: Vec<Id>,
     status: Status,
+
+    /// The fence, if the node is signalled by the backend.
+    fence: Option<Fence>,
 }
 
 #[cfg(test)]
 mod tests {
     use slotmap::KeyData;
 
-    use crate::transaction::{Error, Status};
+    use crate::transaction::{Error, Fence, Status, TrackerStats};
 
     use super::{DependencyTracker, Id};
 
@@ -475,18 +642,13 @@ mod tests {
         assert!(!finished.contains(&c));
         assert_eq!(finished.len(), 2);
 
-        // B finished, C and A should still be queued.
+        // B was removed once drained, so finishing B again does nothing.
         tracker.finish(b);
-        assert_eq!(tracker.get_status(b), Some(Status::Finished));
+        assert_eq!(tracker.get_status(b), None);
 
         assert_eq!(tracker.get_status(c), Some(Status::Queued));
         assert_eq!(tracker.get_status(a), Some(Status::Queued));
-
-        let finished = tracker.drain_finished();
-        assert!(finished.contains(&b));
-        assert!(!finished.contains(&a));
-        assert!(!finished.contains(&c));
-        assert_eq!(finished.len(), 1);
+        assert!(tracker.drain_finished().is_empty());
 
         // C finished, so A must also finish
         tracker.finish(c);
@@ -586,4 +748,131 @@ mod tests {
         assert!(finished.contains(&c));
         assert_eq!(finished.len(), 3);
     }
+
+    /// ```text
+    /// B -> A
+    /// ```
+    #[test]
+    fn cancel_chain() {
+        let mut tracker = DependencyTracker::new();
+        let a = tracker.create_id();
+        let b = tracker.create_id();
+        assert!(tracker.add_dependency(b, a).is_ok());
+
+        tracker.cancel(a);
+        assert_eq!(tracker.get_status(a), Some(Status::Cancelled));
+        assert_eq!(tracker.get_status(b), Some(Status::Cancelled));
+
+        // Depending on a cancelled node cancels the dependent.
+        let c = tracker.create_id();
+        assert_eq!(tracker.add_dependency(c, a), Ok(Status::Cancelled));
+        assert_eq!(tracker.drain_cancelled().len(), 3);
+        assert!(tracker.drain_failed().is_empty());
+
+        // Cancelled nodes are removed once drained.
+        let d = tracker.create_id();
+        assert_eq!(tracker.add_dependency(d, a), Err(Error::NotPresent));
+    }
+
+    /// ```text
+    /// B -\
+    ///     -> A
+    /// C -/
+    /// ```
+    #[test]
+    fn prune_failed_merge() {
+        let mut tracker = DependencyTracker::new();
+        let a = tracker.create_id();
+        let b = tracker.create_id();
+        let c = tracker.create_id();
+        assert!(tracker.add_dependency(a, b).is_ok());
+        assert!(tracker.add_dependency(a, c).is_ok());
+
+        tracker.fail(b);
+        assert_eq!(tracker.drain_failed().len(), 2);
+        assert_eq!(tracker.get_status(a), None);
+        assert_eq!(tracker.get_status(b), None);
+
+        // C no longer has A as a dependent.
+        tracker.finish(c);
+        assert_eq!(tracker.drain_finished(), [c]);
+        assert_eq!(
+            tracker.stats(),
+            TrackerStats {
+                capacity: tracker.stats().capacity,
+                ..TrackerStats::default()
+            }
+        );
+    }
+
+    /// ```text
+    /// F -\
+    ///     -> A
+    /// B -/
+    /// ```
+    #[test]
+    fn wait_on_fence() {
+        let mut tracker = DependencyTracker::new();
+        let a = tracker.create_id();
+        let b = tracker.create_id();
+        let fence = tracker.create_fence(Fence::BufferRelease);
+        assert_eq!(tracker.get_fence(fence), Some(Fence::BufferRelease));
+        assert_eq!(tracker.get_fence(a), None);
+        assert!(tracker.add_dependency(a, fence).is_ok());
+        assert!(tracker.add_dependency(a, b).is_ok());
+
+        tracker.finish(b);
+        assert_eq!(tracker.get_status(a), Some(Status::Queued));
+
+        assert_eq!(tracker.signal(fence), Ok(()));
+        assert_eq!(tracker.get_status(a), Some(Status::Finished));
+        assert_eq!(tracker.get_status(fence), Some(Status::Finished));
+    }
+
+    #[test]
+    fn fence_rules() {
+        let mut tracker = DependencyTracker::new();
+        let a = tracker.create_id();
+        let fence = tracker.create_fence(Fence::Presentation);
+
+        assert_eq!(tracker.add_dependency(fence, a), Err(Error::FenceDependency));
+        assert_eq!(tracker.signal(a), Err(Error::NotFence));
+        assert_eq!(tracker.get_status(a), Some(Status::Queued));
+
+        tracker.fail(fence);
+        assert_eq!(tracker.drain_failed(), [fence]);
+        assert_eq!(tracker.signal(fence), Err(Error::NotPresent));
+    }
+
+    #[test]
+    fn reuse_slots() {
+        let mut tracker = DependencyTracker::new();
+        let a = tracker.create_id();
+        tracker.finish(a);
+        assert_eq!(tracker.stats().finished, 1);
+        assert_eq!(tracker.drain_finished(), [a]);
+        let capacity = tracker.stats().capacity;
+
+        // The slot of A is reused, but the id of A does not refer to the new node.
+        let b = tracker.create_id();
+        assert_ne!(a, b);
+        assert_eq!(tracker.get_status(a), None);
+        assert_eq!(tracker.get_status(b), Some(Status::Queued));
+        assert_eq!(tracker.stats().capacity, capacity);
+    }
+
+    #[test]
+    fn reset() {
+        let mut tracker = DependencyTracker::new();
+        let a = tracker.create_id();
+        let b = tracker.create_id();
+        assert!(tracker.add_dependency(a, b).is_ok());
+        tracker.fail(b);
+
+        tracker.reset();
+        assert_eq!(tracker.get_status(a), None);
+        assert!(tracker.drain_failed().is_empty());
+        assert_eq!(tracker.stats().queued, 0);
+        assert!(tracker.stats().capacity >= 2);
+    }
 }
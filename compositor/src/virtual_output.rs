@@ -0,0 +1,313 @@
+//! Virtual outputs
+//!
+//! A virtual output is a headless output created at runtime through the IPC socket or by the wm, for example to
+//! cast a workspace to another device. Clients, the wm and the scene see a virtual output like any other output,
+//! so the wm can place workspaces and views on it. The backend does not scan the output out; instead it renders
+//! the output offscreen whenever a consumer is waiting for a frame. Consumers capture the output using
+//! `wlr-screencopy` or a screen cast.
+
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            gles::{GlesRenderer, GlesTexture},
+            utils::draw_render_elements,
+            AsRenderElements, Bind, Frame, Offscreen, Renderer, Unbind,
+        },
+    },
+    output::{Mode, Output, PhysicalProperties, Scale, Subpixel},
+    utils::{Logical, Physical, Point, Rectangle, Size, Transform},
+};
+use wayland_server::protocol::wl_surface::WlSurface;
+
+use crate::{
+    popup::output_geometry,
+    scene::{Occlusion, Scene, SceneGraphElement},
+    screencast::Screencasts,
+    wayland::wlr::screencopy::{self, ScreencopyState},
+    Aerugo,
+};
+
+/// How many virtual outputs may exist at the same time.
+pub const MAX_VIRTUAL_OUTPUTS: usize = 8;
+
+/// The largest width or height of a virtual output in pixels.
+pub const MAX_VIRTUAL_OUTPUT_SIZE: i32 = 8192;
+
+/// The refresh rate of a virtual output if none is specified, in mHz.
+pub const DEFAULT_REFRESH: i32 = 60_000;
+
+/// Format of the offscreen buffer virtual outputs are rendered into.
+const VIRTUAL_OUTPUT_FORMAT: Fourcc = Fourcc::Abgr8888;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum VirtualOutputError {
+    #[error(
+        "the size of a virtual output must be between 1x1 and {MAX_VIRTUAL_OUTPUT_SIZE}x{MAX_VIRTUAL_OUTPUT_SIZE}"
+    )]
+    InvalidSize,
+
+    #[error("the refresh rate of a virtual output must be positive")]
+    InvalidRefresh,
+
+    #[error("at most {MAX_VIRTUAL_OUTPUTS} virtual outputs may exist")]
+    TooMany,
+
+    #[error("no virtual output is named `{0}`")]
+    NotFound(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+enum RenderError {
+    #[error("the virtual output has no mode")]
+    NoMode,
+
+    #[error("failed to render the virtual output")]
+    Render,
+}
+
+/// The virtual outputs which exist.
+#[derive(Debug, Default)]
+pub struct VirtualOutputs {
+    outputs: Vec<Output>,
+
+    /// The number used for the name of the next virtual output.
+    next_id: u32,
+}
+
+impl VirtualOutputs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a virtual output with the specified mode at a location in the global compositor space.
+    ///
+    /// The output must be added with [`Aerugo::add_output`] before it is used.
+    pub fn create(
+        &mut self,
+        size: Size<i32, Physical>,
+        refresh: Option<i32>,
+        location: Point<i32, Logical>,
+    ) -> Result<Output, VirtualOutputError> {
+        let valid = 1..=MAX_VIRTUAL_OUTPUT_SIZE;
+
+        if !valid.contains(&size.w) || !valid.contains(&size.h) {
+            return Err(VirtualOutputError::InvalidSize);
+        }
+
+        let refresh = refresh.unwrap_or(DEFAULT_REFRESH);
+
+        if refresh <= 0 {
+            return Err(VirtualOutputError::InvalidRefresh);
+        }
+
+        if self.outputs.len() >= MAX_VIRTUAL_OUTPUTS {
+            return Err(VirtualOutputError::TooMany);
+        }
+
+        self.next_id += 1;
+        let output = Output::new(
+            format!("VIRTUAL-{}", self.next_id),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: "Aerugo".into(),
+                model: "Virtual output".into(),
+            },
+        );
+        let mode = Mode { size, refresh };
+        output.change_current_state(
+            Some(mode),
+            Some(Transform::Normal),
+            Some(Scale::Integer(1)),
+            Some(location),
+        );
+        output.set_preferred(mode);
+
+        self.outputs.push(output.clone());
+        Ok(output)
+    }
+
+    /// Forget the virtual output with the specified name.
+    ///
+    /// The output must be removed with [`Aerugo::remove_output`] afterwards.
+    pub fn remove(&mut self, name: &str) -> Option<Output> {
+        let index = self.outputs.iter().position(|output| output.name() == name)?;
+        Some(self.outputs.remove(index))
+    }
+
+    pub fn contains(&self, output: &Output) -> bool {
+        self.outputs.contains(output)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Output> {
+        self.outputs.iter()
+    }
+}
+
+impl Aerugo {
+    /// Create a virtual output and advertise it like a connected output.
+    ///
+    /// The output is placed to the right of the other outputs.
+    pub fn create_virtual_output(
+        &mut self,
+        size: Size<i32, Physical>,
+        refresh: Option<i32>,
+    ) -> Result<Output, VirtualOutputError> {
+        let x = self
+            .outputs
+            .iter()
+            .map(|(output, _)| output_geometry(output))
+            .map(|geometry| geometry.loc.x + geometry.size.w)
+            .max()
+            .unwrap_or(0);
+
+        let output = self.virtual_outputs.create(size, refresh, (x, 0).into())?;
+        self.add_output(output.clone());
+        Ok(output)
+    }
+
+    /// Remove a virtual output as if it was disconnected.
+    pub fn destroy_virtual_output(&mut self, name: &str) -> Result<(), VirtualOutputError> {
+        let output = self
+            .virtual_outputs
+            .remove(name)
+            .ok_or_else(|| VirtualOutputError::NotFound(name.into()))?;
+
+        if let Some(global) = self.remove_output(&output) {
+            self.remove_global_later(global);
+        }
+
+        Ok(())
+    }
+}
+
+/// Render every virtual output with captures waiting or being cast, and fulfill the captures.
+///
+/// Returns the outputs which were rendered and the surfaces which were visible on them. This binds an offscreen
+/// buffer, so the backend must bind its framebuffer again before rendering.
+pub fn render_virtual_outputs(
+    renderer: &mut GlesRenderer,
+    scene: &Scene,
+    screencopy: &mut ScreencopyState,
+    screencasts: &mut Screencasts,
+    outputs: &VirtualOutputs,
+) -> (Vec<Output>, Vec<WlSurface>) {
+    let mut rendered = Vec::new();
+    let mut visible = Vec::new();
+
+    for output in outputs
+        .iter()
+        .filter(|&output| screencopy.has_pending(output) || screencasts.wants_output(output))
+    {
+        let captures = screencopy.take_pending(output);
+
+        match render_output(renderer, scene, output) {
+            Ok((size, surfaces)) => {
+                // The offscreen buffer is still bound.
+                // Virtual outputs are redrawn completely.
+                let damage = [Rectangle::from_loc_and_size((0, 0), size)];
+                screencopy::submit_captures(renderer, captures, &damage);
+                screencasts.submit_output_frame(renderer, output, size);
+                rendered.push(output.clone());
+                visible.extend(surfaces);
+            }
+
+            Err(err) => {
+                tracing::debug!(%err, output = output.name(), "Failed to render virtual output");
+                screencopy::fail_captures(captures);
+            }
+        }
+
+        let _ = renderer.unbind();
+    }
+
+    (rendered, visible)
+}
+
+/// Render the content the wm presents on the output into an offscreen buffer and leave the buffer bound.
+///
+/// Returns the size of the buffer and the surfaces which were visible.
+fn render_output(
+    renderer: &mut GlesRenderer,
+    scene: &Scene,
+    output: &Output,
+) -> Result<(Size<i32, Physical>, Vec<WlSurface>), RenderError> {
+    let size = output.current_mode().ok_or(RenderError::NoMode)?.size;
+    let area = Rectangle::from_loc_and_size((0, 0), size);
+
+    let texture: GlesTexture = renderer
+        .create_buffer(VIRTUAL_OUTPUT_FORMAT, (size.w, size.h).into())
+        .map_err(|_| RenderError::Render)?;
+    renderer.bind(texture).map_err(|_| RenderError::Render)?;
+
+    let graph = scene.get_graph(output);
+    let elements: Vec<SceneGraphElement> = graph
+        .as_ref()
+        .map(|graph| graph.render_elements(renderer, (0, 0).into(), smithay::utils::Scale { x: 1., y: 1. }, 1.0))
+        .unwrap_or_default();
+
+    let mut frame = renderer
+        .render(size, Transform::Normal)
+        .map_err(|_| RenderError::Render)?;
+    frame
+        .clear([0.0, 0.0, 0.0, 1.0], &[area])
+        .map_err(|_| RenderError::Render)?;
+    draw_render_elements::<GlesRenderer, _, _>(&mut frame, 1.0, &elements, &[area]).map_err(|_| RenderError::Render)?;
+    frame.finish().map_err(|_| RenderError::Render)?;
+
+    let visible = graph
+        .map(|graph| graph.visible_surfaces((0, 0).into(), &mut Occlusion::new(area)))
+        .unwrap_or_default();
+
+    Ok((size, visible))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VirtualOutputError, VirtualOutputs, MAX_VIRTUAL_OUTPUTS, MAX_VIRTUAL_OUTPUT_SIZE};
+
+    #[test]
+    fn create_and_remove() {
+        let mut outputs = VirtualOutputs::new();
+        let output = outputs.create((1920, 1080).into(), None, (0, 0).into()).unwrap();
+
+        assert_eq!(output.name(), "VIRTUAL-1");
+        assert_eq!(output.current_mode().unwrap().size, (1920, 1080).into());
+        assert!(outputs.contains(&output));
+
+        assert_eq!(outputs.remove("VIRTUAL-1"), Some(output.clone()));
+        assert!(!outputs.contains(&output));
+
+        // Names are not reused.
+        let output = outputs.create((1920, 1080).into(), None, (0, 0).into()).unwrap();
+        assert_eq!(output.name(), "VIRTUAL-2");
+    }
+
+    #[test]
+    fn limits() {
+        let mut outputs = VirtualOutputs::new();
+
+        assert_eq!(
+            outputs.create((0, 1080).into(), None, (0, 0).into()),
+            Err(VirtualOutputError::InvalidSize)
+        );
+        assert_eq!(
+            outputs.create((MAX_VIRTUAL_OUTPUT_SIZE + 1, 1080).into(), None, (0, 0).into()),
+            Err(VirtualOutputError::InvalidSize)
+        );
+        assert_eq!(
+            outputs.create((100, 100).into(), Some(0), (0, 0).into()),
+            Err(VirtualOutputError::InvalidRefresh)
+        );
+
+        for _ in 0..MAX_VIRTUAL_OUTPUTS {
+            outputs.create((100, 100).into(), None, (0, 0).into()).unwrap();
+        }
+
+        assert_eq!(
+            outputs.create((100, 100).into(), None, (0, 0).into()),
+            Err(VirtualOutputError::TooMany)
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Implementation of the `aerugo-debug-v1` protocol.
+//!
+//! The protocol lets a privileged client learn about the display server it is connected to, similar to what
+//! `wayland-info` reports about the globals of a display server. When the global is bound, the version of the
+//! display server, the backend and renderer in use, the running wm and the cargo features the display server was
+//! built with are sent, followed by a `done` event.
+
+use wayland_server::{Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New};
+
+use crate::{Aerugo, ClientData, PrivilegedGlobals};
+
+use self::generated::aerugo_debug_v1;
+pub use self::generated::aerugo_debug_v1::AerugoDebugV1;
+
+#[allow(non_upper_case_globals, non_camel_case_types)]
+mod generated {
+    use smithay::reexports::wayland_server;
+    use smithay::reexports::wayland_server::protocol::*;
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_server::backend as wayland_backend;
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("../protocols/aerugo-debug-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("../protocols/aerugo-debug-v1.xml");
+}
+
+/// The optional cargo features the display server was built with.
+pub fn features() -> Vec<&'static str> {
+    [
+        ("strict", cfg!(feature = "strict")),
+        ("screencast", cfg!(feature = "screencast")),
+        ("logind", cfg!(feature = "logind")),
+        ("profiling", cfg!(feature = "profiling")),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect()
+}
+
+impl GlobalDispatch<AerugoDebugV1, ()> for Aerugo {
+    fn bind(
+        state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<AerugoDebugV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        let debug = init.init(resource, ());
+
+        debug.compositor("aerugo".into(), env!("CARGO_PKG_VERSION").into());
+        debug.backend(state.backend.name().into());
+        debug.renderer(state.backend.renderer_kind().to_string());
+
+        if let Some(info) = state.wm.info() {
+            debug.wm(info.name.clone(), info.version.clone(), info.abi.major, info.abi.minor);
+        }
+
+        for feature in features() {
+            debug.feature(feature.into());
+        }
+
+        debug.done();
+    }
+
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        ClientData::get_data(&client)
+            .map(|data| data.is_visible(PrivilegedGlobals::DEBUG))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<AerugoDebugV1, ()> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &AerugoDebugV1,
+        request: aerugo_debug_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            aerugo_debug_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::features;
+
+    #[test]
+    fn strict_feature() {
+        assert_eq!(features().contains(&"strict"), cfg!(feature = "strict"));
+    }
+}
@@ -0,0 +1,4 @@
+//! Aerugo specific protocol implementations
+
+pub mod debug;
+pub mod shell;
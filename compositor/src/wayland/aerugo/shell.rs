@@ -0,0 +1,202 @@
+//! Implementation of the `aerugo-shell-v1` protocol.
+//!
+//! The protocol lets a privileged client place its own surfaces in the scene, for example window decorations,
+//! bars and overlays drawn by a shell running out of process. Each surface node is announced to the wm, which
+//! presents the surface tree of the node by building a view of the node and placing the view in a transaction.
+//! The wm decides where the surface is placed and how the surface is stacked relative to other views.
+
+use rustc_hash::FxHashMap;
+use smithay::wayland::compositor;
+use wayland_server::{
+    backend::{ClientId, ObjectId},
+    protocol::wl_surface::WlSurface,
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+};
+use wm_runtime::WmEvent;
+
+use crate::{
+    scene::{NodeIndex, SurfaceTreeIndex},
+    shell::ToplevelId,
+    wm::wm_surface_node_id,
+    Aerugo, ClientData, PrivilegedGlobals,
+};
+
+pub use self::generated::aerugo_shell_v1::AerugoShellV1;
+use self::generated::{
+    aerugo_shell_v1,
+    aerugo_surface_node_v1::{self, AerugoSurfaceNodeV1},
+};
+
+#[allow(non_upper_case_globals, non_camel_case_types)]
+mod generated {
+    use smithay::reexports::wayland_server;
+    use smithay::reexports::wayland_server::protocol::*;
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_server::backend as wayland_backend;
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("../protocols/aerugo-shell-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("../protocols/aerugo-shell-v1.xml");
+}
+
+/// The role given to surfaces with a surface node.
+const SURFACE_NODE_ROLE: &str = "aerugo_surface_node_v1";
+
+#[derive(Debug, Default)]
+pub struct AerugoShellState {
+    /// Surface nodes, keyed by the surface of the node.
+    nodes: FxHashMap<ObjectId, SurfaceNode>,
+}
+
+#[derive(Debug)]
+struct SurfaceNode {
+    node: AerugoSurfaceNodeV1,
+    tree: SurfaceTreeIndex,
+
+    /// The id of the node in the id space of the wm.
+    id: ToplevelId,
+
+    /// The purpose of the surface, as described by the client.
+    name: String,
+}
+
+impl AerugoShellState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The surface tree of the surface node with the specified id.
+    pub fn surface_tree(&self, id: ToplevelId) -> Option<SurfaceTreeIndex> {
+        self.nodes.values().find(|node| node.id == id).map(|node| node.tree)
+    }
+
+    /// The id and name of every surface node.
+    pub fn nodes(&self) -> impl Iterator<Item = (ToplevelId, &str)> {
+        self.nodes.values().map(|node| (node.id, node.name.as_str()))
+    }
+}
+
+impl Aerugo {
+    /// Remove the surface node of a surface from the scene.
+    pub fn remove_surface_node(&mut self, surface: &ObjectId) {
+        let Some(node) = self.aerugo_shell.nodes.remove(surface) else {
+            return;
+        };
+
+        self.wm.forget_node(NodeIndex::SurfaceTree(node.tree));
+        self.scene.destroy_surface_tree(node.tree);
+
+        if let Some(id) = wm_surface_node_id(node.id) {
+            self.wm.send(WmEvent::ClosedSurfaceNode(id));
+        }
+    }
+
+    fn remove_surface_node_resource(&mut self, resource: &AerugoSurfaceNodeV1) {
+        let surface = self
+            .aerugo_shell
+            .nodes
+            .iter()
+            .find(|(_, surface_node)| surface_node.node == *resource)
+            .map(|(surface, _)| surface.clone());
+
+        if let Some(surface) = surface {
+            self.remove_surface_node(&surface);
+        }
+    }
+}
+
+impl GlobalDispatch<AerugoShellV1, ()> for Aerugo {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<AerugoShellV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        init.init(resource, ());
+    }
+
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        ClientData::get_data(&client)
+            .map(|data| data.is_visible(PrivilegedGlobals::AERUGO_SHELL))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<AerugoShellV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &AerugoShellV1,
+        request: aerugo_shell_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            aerugo_shell_v1::Request::GetSurfaceNode { id, surface, name } => {
+                if compositor::give_role(&surface, SURFACE_NODE_ROLE).is_err() {
+                    resource.post_error(aerugo_shell_v1::Error::Role, "surface already has a role");
+                    return;
+                }
+
+                let node = init.init(id, surface.clone());
+
+                // The surface tree is not presented until the wm places a view of the node.
+                let tree = state.scene.create_surface_tree(surface.clone());
+                let node_id = state.shell.allocate_id();
+
+                state.aerugo_shell.nodes.insert(
+                    surface.id(),
+                    SurfaceNode {
+                        node,
+                        tree,
+                        id: node_id,
+                        name: name.clone(),
+                    },
+                );
+
+                if let Some(node) = wm_surface_node_id(node_id) {
+                    state.wm.send(WmEvent::NewSurfaceNode { node, name });
+                }
+            }
+
+            aerugo_shell_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<AerugoSurfaceNodeV1, WlSurface> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &AerugoSurfaceNodeV1,
+        request: aerugo_surface_node_v1::Request,
+        _surface: &WlSurface,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            aerugo_surface_node_v1::Request::Destroy => {
+                // Dispatch::destroyed handles cleanup
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &AerugoSurfaceNodeV1, _surface: &WlSurface) {
+        // The surface may already be destroyed, so look up the node by the resource.
+        state.remove_surface_node_resource(resource);
+    }
+}
@@ -10,8 +10,15 @@ use wayland_server::protocol::wl_buffer;
 
 use crate::Aerugo;
 
+// Buffers attached to surfaces are held by the renderer state of the surface until a commit replaces or detaches
+// the buffer, or the surface is destroyed, at which point Smithay sends wl_buffer.release. With explicit
+// synchronization the release point is signaled instead, see `drm_syncobj`. A client may destroy an attached
+// buffer at any time, the textures imported from the buffer stay valid until the buffer is replaced.
 impl BufferHandler for Aerugo {
-    fn buffer_destroyed(&mut self, _buffer: &wl_buffer::WlBuffer) {}
+    fn buffer_destroyed(&mut self, buffer: &wl_buffer::WlBuffer) {
+        // Captures can no longer be copied into a destroyed buffer.
+        self.screencopy.buffer_destroyed(buffer);
+    }
 }
 
 impl ShmHandler for Aerugo {
@@ -1,10 +1,12 @@
 use std::borrow::Cow;
 
+use rustc_hash::FxHashMap;
 use smithay::{
     backend::renderer::utils::on_commit_buffer_handler,
+    utils::Transform,
     wayland::compositor::{self, CompositorClientState, CompositorHandler, CompositorState},
 };
-use wayland_server::{protocol::wl_surface::WlSurface, Client};
+use wayland_server::{backend::ObjectId, protocol::wl_surface::WlSurface, Client, Resource};
 
 use crate::{shell::Shell, state::ClientData, Aerugo};
 
@@ -14,10 +16,13 @@ impl CompositorHandler for Aerugo {
     }
 
     fn commit(&mut self, surface: &WlSurface) {
+        let _span = tracing::trace_span!("commit", surface = %surface.id()).entered();
+
         // Let Smithay perform buffer management for us.
         //
         // on_commit_buffer_handler will manage the buffer, damage and opaque regions.
         on_commit_buffer_handler::<Self>(surface);
+        self.syncobj_commit(surface);
 
         // If the surface is sync the parent needs to be committed to apply the pending state.
         //
@@ -26,6 +31,9 @@ impl CompositorHandler for Aerugo {
             return;
         }
 
+        let scale = self.output.current_scale().fractional_scale();
+        self.popups.commit(&mut self.scene, scale, surface);
+
         // Select the root surface if a desync subsurface was committed.
         let mut surface = Cow::Borrowed(surface);
 
@@ -38,13 +46,103 @@ impl CompositorHandler for Aerugo {
         Shell::commit(self, &surface);
     }
 
+    fn new_surface(&mut self, surface: &WlSurface) {
+        self.limit_surface(surface);
+    }
+
     fn client_compositor_state<'a>(&self, client: &'a Client) -> &'a CompositorClientState {
         ClientData::get_data(client).unwrap().client_compositor_state()
     }
 
     fn destroyed(&mut self, surface: &WlSurface) {
+        self.release_surface(surface);
+
+        if self.drag_icon.is_some() && self.scene.get_surface_tree_index(surface.clone()) == self.drag_icon {
+            self.destroy_drag_icon();
+        }
+
+        self.remove_surface_node(&surface.id());
         Shell::remove_toplevel(self, surface)
     }
 }
 
 smithay::delegate_compositor!(Aerugo);
+
+impl Aerugo {
+    /// Send the preferred buffer scale and transform to every surface in the scene.
+    ///
+    /// The preference is derived from the outputs each surface is presented on, so clients render at the right
+    /// density as the wm moves surfaces between outputs. Smithay only sends the events when the preference
+    /// changed.
+    pub fn send_preferred_buffer_state(&self) {
+        let mut surfaces = FxHashMap::<ObjectId, (WlSurface, Vec<(f64, Transform)>)>::default();
+
+        for output in self.scene.outputs() {
+            let Some(hierarchy) = self.scene.get_graph(output) else {
+                continue;
+            };
+
+            let state = (output.current_scale().fractional_scale(), output.current_transform());
+
+            for surface in hierarchy.surfaces() {
+                surfaces
+                    .entry(surface.id())
+                    .or_insert_with(|| (surface.clone(), Vec::new()))
+                    .1
+                    .push(state);
+            }
+        }
+
+        for (surface, outputs) in surfaces.into_values() {
+            // TODO: Account for the transform of the node once nodes can be transformed.
+            if let Some((scale, transform)) = preferred_buffer_state(&outputs) {
+                compositor::with_states(&surface, |states| {
+                    compositor::send_surface_state(&surface, states, scale, transform);
+                });
+            }
+        }
+    }
+}
+
+/// The preferred buffer scale and transform of a surface presented on outputs with the specified scale and
+/// transform.
+///
+/// The surface should render for the output with the highest scale, so the surface is never upscaled. Buffer
+/// scales are integers, so fractional scales are rounded up.
+fn preferred_buffer_state(outputs: &[(f64, Transform)]) -> Option<(i32, Transform)> {
+    outputs
+        .iter()
+        .copied()
+        .reduce(|highest, output| if output.0 > highest.0 { output } else { highest })
+        .map(|(scale, transform)| (scale.ceil().max(1.0) as i32, transform))
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::utils::Transform;
+
+    use super::preferred_buffer_state;
+
+    #[test]
+    fn no_outputs() {
+        assert_eq!(preferred_buffer_state(&[]), None);
+    }
+
+    #[test]
+    fn highest_scale() {
+        let outputs = [
+            (1.0, Transform::Normal),
+            (2.0, Transform::_90),
+            (1.5, Transform::Flipped),
+        ];
+        assert_eq!(preferred_buffer_state(&outputs), Some((2, Transform::_90)));
+    }
+
+    #[test]
+    fn fractional_rounds_up() {
+        assert_eq!(
+            preferred_buffer_state(&[(1.25, Transform::Normal)]),
+            Some((2, Transform::Normal))
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use std::os::fd::OwnedFd;
+
+use smithay::{
+    input::Seat,
+    utils::Point,
+    wayland::data_device::{
+        with_source_metadata, ClientDndGrabHandler, DataDeviceHandler, DataDeviceState, ServerDndGrabHandler,
+    },
+};
+use wayland_server::protocol::{wl_data_source::WlDataSource, wl_surface::WlSurface};
+
+use crate::{scene::NodeIndex, wayland::wlr::data_control::Target, Aerugo};
+
+impl DataDeviceHandler for Aerugo {
+    fn data_device_state(&self) -> &DataDeviceState {
+        &self.data_device
+    }
+
+    fn new_selection(&mut self, source: Option<WlDataSource>, _seat: Seat<Self>) {
+        let mime_types = source.and_then(|source| with_source_metadata(&source, |data| data.mime_types.clone()).ok());
+        self.data_control
+            .new_selection(&self.display, Target::Clipboard, mime_types);
+    }
+
+    fn send_selection(&mut self, mime_type: String, fd: OwnedFd, _seat: Seat<Self>) {
+        // Server side selections are only set through data control.
+        self.data_control.send_selection(Target::Clipboard, mime_type, fd);
+    }
+}
+
+impl ClientDndGrabHandler for Aerugo {
+    fn started(&mut self, _source: Option<WlDataSource>, icon: Option<WlSurface>, _seat: Seat<Self>) {
+        self.destroy_drag_icon();
+
+        if let Some(icon) = icon {
+            let tree = self.scene.create_surface_tree(icon);
+            self.scene
+                .branch_add_child(self.scene.drag_icons(), NodeIndex::SurfaceTree(tree))
+                .expect("surface tree was just created");
+            self.drag_icon = Some(tree);
+            self.update_drag_icon();
+        }
+    }
+
+    fn dropped(&mut self, _seat: Seat<Self>) {
+        self.destroy_drag_icon();
+    }
+}
+
+impl ServerDndGrabHandler for Aerugo {}
+
+smithay::delegate_data_device!(Aerugo);
+
+impl Aerugo {
+    /// Move the drag icon to the pointer.
+    pub fn update_drag_icon(&mut self) {
+        let Some(tree) = self.drag_icon else {
+            return;
+        };
+
+        let scale = self.output.current_scale().fractional_scale();
+        let location: Point<i32, _> = self.pointer_location.to_physical(scale).to_i32_round();
+        self.scene.set_node_offset(NodeIndex::SurfaceTree(tree), location);
+    }
+
+    /// Remove the drag icon from the scene once the drag ended or the icon surface was destroyed.
+    pub fn destroy_drag_icon(&mut self) {
+        if let Some(tree) = self.drag_icon.take() {
+            self.scene.destroy_surface_tree(tree);
+        }
+    }
+}
@@ -2,5 +2,6 @@
 
 mod buffer;
 mod compositor;
+mod data_device;
 mod output;
 mod seat;
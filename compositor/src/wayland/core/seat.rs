@@ -1,5 +1,8 @@
-use smithay::input::{pointer::CursorImageStatus, Seat, SeatHandler, SeatState};
-use wayland_server::protocol::wl_surface;
+use smithay::{
+    input::{pointer::CursorImageStatus, Seat, SeatHandler, SeatState},
+    wayland::{data_device::set_data_device_focus, primary_selection::set_primary_focus},
+};
+use wayland_server::{protocol::wl_surface, Resource};
 
 use crate::Aerugo;
 
@@ -11,7 +14,17 @@ impl SeatHandler for Aerugo {
         &mut self.seat_state
     }
 
-    fn focus_changed(&mut self, _seat: &Seat<Self>, _focused: Option<&Self::KeyboardFocus>) {}
+    fn focus_changed(&mut self, seat: &Seat<Self>, focused: Option<&Self::KeyboardFocus>) {
+        // Popups are dismissed when keyboard focus leaves the popup grab.
+        self.popups.focus_changed(focused);
 
-    fn cursor_image(&mut self, _seat: &Seat<Self>, _image: CursorImageStatus) {}
+        // Only the client with keyboard focus may read the selections.
+        let client = focused.and_then(|surface| self.display.get_client(surface.id()).ok());
+        set_data_device_focus(&self.display, seat, client.clone());
+        set_primary_focus(&self.display, seat, client);
+    }
+
+    fn cursor_image(&mut self, _seat: &Seat<Self>, image: CursorImageStatus) {
+        self.cursor.set_image(image);
+    }
 }
@@ -42,30 +42,28 @@ impl GlobalDispatch<ExtForeignToplevelListV1, ()> for Aerugo {
         init: &mut DataInit<'_, Self>,
     ) {
         let instance = init.init(resource, ());
-        let instance = state
-            .shell
-            .foreign_toplevel_instances
-            .entry(instance.id())
-            .or_insert(ForeignToplevelInstance {
-                instance,
-                stopped: false,
-            });
+        let instance = ForeignToplevelInstance {
+            instance,
+            stopped: false,
+        };
 
         let mut new_handles = Vec::with_capacity(state.shell.toplevels.len());
 
         // Create all toplevel handle instances to ensure that extension protocols do not refer to handles
         // that were not yet created.
         for toplevel in state.shell.toplevels.values_mut() {
-            new_handles.push((
-                toplevel.create_handle(state.generation, &instance.instance, display, client),
-                toplevel,
-            ));
+            new_handles.push((toplevel.create_handle(&instance.instance, display, client), toplevel));
         }
 
         // Now describe the toplevels.
         for (handle, toplevel) in new_handles {
-            toplevel.initialize_handle(&handle);
+            toplevel.initialize_handle(display, &handle);
         }
+
+        state
+            .shell
+            .foreign_toplevel_instances
+            .insert(instance.instance.id(), instance);
     }
 
     fn can_view(client: Client, _global_data: &()) -> bool {
@@ -93,7 +91,10 @@ impl Dispatch<ExtForeignToplevelListV1, ()> for Aerugo {
                     return;
                 };
 
-                instance.stopped = true;
+                // No more toplevel events are sent after finished, so finished is only sent once.
+                if !std::mem::replace(&mut instance.stopped, true) {
+                    resource.finished();
+                }
             }
             ext_foreign_toplevel_list_v1::Request::Destroy => {
                 // Dispatch::destroyed handles cleanup
@@ -104,18 +105,17 @@ impl Dispatch<ExtForeignToplevelListV1, ()> for Aerugo {
     }
 
     fn destroyed(state: &mut Self, _client: ClientId, resource: &ExtForeignToplevelListV1, _data: &()) {
-        // TODO
-        //let _ = state.shell.foreign_toplevel_instances.remove(&resource);
+        let _ = state.shell.foreign_toplevel_instances.remove(&resource.id());
     }
 }
 
 impl Dispatch<ExtForeignToplevelHandleV1, ToplevelId> for Aerugo {
     fn request(
-        state: &mut Self,
+        _state: &mut Self,
         _client: &Client,
-        resource: &ExtForeignToplevelHandleV1,
+        _resource: &ExtForeignToplevelHandleV1,
         request: ext_foreign_toplevel_handle_v1::Request,
-        id: &ToplevelId,
+        _id: &ToplevelId,
         _display: &DisplayHandle,
         _init: &mut DataInit<'_, Self>,
     ) {
@@ -123,12 +123,9 @@ impl Dispatch<ExtForeignToplevelHandleV1, ToplevelId> for Aerugo {
         #[allow(unreachable_patterns)]
         match request {
             ext_foreign_toplevel_handle_v1::Request::Destroy => {
+                // Dispatch::destroyed forgets the handle.
+                //
                 // TODO: Check for invalid destruction order in extension protocols.
-                if let Some(toplevel) = state.shell.toplevels.get_mut(id) {
-                    if let Some(_handles) = toplevel.get_handles(resource.id()) {
-                        // TODO
-                    }
-                }
             }
 
             _ => unreachable!(),
@@ -137,8 +134,7 @@ impl Dispatch<ExtForeignToplevelHandleV1, ToplevelId> for Aerugo {
 
     fn destroyed(state: &mut Self, _client: ClientId, resource: &ExtForeignToplevelHandleV1, data: &ToplevelId) {
         if let Some(toplevel) = state.shell.toplevels.get_mut(data) {
-            // TODO
-            //toplevel.remove_handle(resource);
+            toplevel.remove_handle(resource.id());
         };
     }
 }
@@ -0,0 +1,150 @@
+//! Implementation of the `ext-foreign-toplevel-state-v1` protocol.
+//!
+//! The state of a toplevel is described by extending the handles of `ext-foreign-toplevel-list-v1`. The state
+//! sent to a handle is tracked by the shell, which sends changes to every handle when the toplevels are
+//! refreshed.
+
+use wayland_server::{
+    backend::{ClientId, ObjectId},
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+};
+
+use crate::{shell::ToplevelId, Aerugo, ClientData, PrivilegedGlobals};
+
+use self::generated::ext_foreign_toplevel_state_manager_v1;
+pub use self::generated::{
+    ext_foreign_toplevel_state_manager_v1::ExtForeignToplevelStateManagerV1,
+    ext_foreign_toplevel_state_v1::{self, ExtForeignToplevelStateV1},
+};
+
+// ext-foreign-toplevel-state-v1 is only a draft upstream, so we need to generate it.
+#[allow(non_upper_case_globals, non_camel_case_types)]
+mod generated {
+    use smithay::reexports::wayland_server;
+    use smithay::reexports::wayland_server::protocol::*;
+
+    pub use crate::wayland::ext::foreign_toplevel::ext_foreign_toplevel_handle_v1;
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_server::backend as wayland_backend;
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+
+        pub use crate::wayland::ext::foreign_toplevel::__interfaces::*;
+        wayland_scanner::generate_interfaces!("../protocols/ext-foreign-toplevel-state-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("../protocols/ext-foreign-toplevel-state-v1.xml");
+}
+
+/// User data associated with a `ext_foreign_toplevel_state_v1`.
+#[derive(Debug)]
+pub struct ToplevelStateData {
+    toplevel: ToplevelId,
+    handle: ObjectId,
+}
+
+impl GlobalDispatch<ExtForeignToplevelStateManagerV1, ()> for Aerugo {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtForeignToplevelStateManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        init.init(resource, ());
+    }
+
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        ClientData::get_data(&client)
+            .map(|data| data.is_visible(PrivilegedGlobals::FOREIGN_TOPLEVEL_STATE))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<ExtForeignToplevelStateManagerV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ExtForeignToplevelStateManagerV1,
+        request: ext_foreign_toplevel_state_manager_v1::Request,
+        _data: &(),
+        display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            ext_foreign_toplevel_state_manager_v1::Request::GetToplevelState { id, handle } => {
+                let toplevel = *handle.data::<ToplevelId>().unwrap();
+                let data = ToplevelStateData {
+                    toplevel,
+                    handle: handle.id(),
+                };
+                let toplevel_state = init.init(id, data);
+
+                // The handle of a closed toplevel is no longer known, so the state object is inert.
+                let Some(handles) = state
+                    .shell
+                    .toplevels
+                    .get_mut(&toplevel)
+                    .and_then(|toplevel| toplevel.get_handles(handle.id()))
+                else {
+                    return;
+                };
+
+                if handles.state.is_some() {
+                    resource.post_error(
+                        ext_foreign_toplevel_state_manager_v1::Error::AlreadyConstructed,
+                        "a state object was already created for the toplevel handle",
+                    );
+                    return;
+                }
+
+                handles.init_state(display, toplevel_state);
+            }
+
+            ext_foreign_toplevel_state_manager_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<ExtForeignToplevelStateV1, ToplevelStateData> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ExtForeignToplevelStateV1,
+        request: ext_foreign_toplevel_state_v1::Request,
+        _data: &ToplevelStateData,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            ext_foreign_toplevel_state_v1::Request::Destroy => {
+                // Dispatch::destroyed handles cleanup
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &ExtForeignToplevelStateV1, data: &ToplevelStateData) {
+        let Some(handles) = state
+            .shell
+            .toplevels
+            .get_mut(&data.toplevel)
+            .and_then(|toplevel| toplevel.get_handles(data.handle.clone()))
+        else {
+            return;
+        };
+
+        if handles.state.as_ref().map(Resource::id) == Some(resource.id()) {
+            handles.state = None;
+        }
+    }
+}
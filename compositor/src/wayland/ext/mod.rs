@@ -1,3 +1,6 @@
 //! `ext` vendored wayland protocol implementations
 
 pub mod foreign_toplevel;
+pub mod foreign_toplevel_state;
+pub mod session_lock;
+pub mod workspace;
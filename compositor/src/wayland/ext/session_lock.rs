@@ -0,0 +1,252 @@
+//! Implementation of the `ext-session-lock-v1` protocol.
+//!
+//! While the session is locked, outputs only present the lock surface created for that output. If no lock
+//! surface has been created for an output, the output is blanked.
+//!
+//! Input only reaches lock surfaces while the session is locked. Keyboard focus given to other surfaces while
+//! locked, such as by the wm, is remembered and restored once the session is unlocked.
+//!
+//! The display server may also lock the session itself, for example when logind asks the session be locked (see
+//! [`session`](crate::session)). The outputs are blanked immediately and the lock screen is started, which then
+//! takes over the lock using the protocol.
+
+use rustc_hash::FxHashMap;
+use smithay::{
+    output::Output,
+    utils::{Serial, SERIAL_COUNTER},
+    wayland::session_lock::{LockSurface, SessionLockHandler, SessionLockManagerState, SessionLocker},
+};
+use wayland_server::{
+    protocol::{wl_output::WlOutput, wl_surface::WlSurface},
+    Resource,
+};
+use wm_runtime::WmEvent;
+
+use crate::{launcher::Origin, Aerugo};
+
+/// The lock state of the session.
+#[derive(Debug, Default)]
+pub enum SessionLock {
+    #[default]
+    Unlocked,
+
+    /// The session is locked.
+    Locked {
+        /// Confirmation of the lock.
+        ///
+        /// The lock is confirmed once a frame has been presented without any client content. Until the lock
+        /// is confirmed this is [`Some`].
+        locker: Option<SessionLocker>,
+
+        /// The lock surface of each output.
+        surfaces: FxHashMap<Output, LockSurface>,
+
+        /// Whether a frame has been presented since the session was locked.
+        presented: bool,
+
+        /// The surface which receives keyboard focus once the session is unlocked.
+        unlock_focus: Option<WlSurface>,
+    },
+}
+
+impl SessionLock {
+    pub fn is_locked(&self) -> bool {
+        matches!(self, SessionLock::Locked { .. })
+    }
+
+    /// Whether the session is locked and a frame without client content has been presented.
+    pub fn is_presented(&self) -> bool {
+        matches!(self, SessionLock::Locked { presented: true, .. })
+    }
+
+    /// The lock surface to present on the output.
+    ///
+    /// If the session is locked and no surface is returned, the output must be blanked.
+    pub fn surface(&self, output: &Output) -> Option<&WlSurface> {
+        match self {
+            SessionLock::Locked { surfaces, .. } => surfaces.get(output).map(LockSurface::wl_surface),
+            SessionLock::Unlocked => None,
+        }
+    }
+
+    /// Whether a surface may receive input.
+    ///
+    /// While the session is locked, only lock surfaces receive input.
+    pub fn allows_input(&self, surface: &WlSurface) -> bool {
+        match self {
+            SessionLock::Locked { surfaces, .. } => surfaces.values().any(|lock| lock.wl_surface() == surface),
+            SessionLock::Unlocked => true,
+        }
+    }
+
+    /// Notify that a frame has been presented while locked.
+    ///
+    /// This will confirm a pending lock.
+    pub fn frame_presented(&mut self) {
+        if let SessionLock::Locked { locker, presented, .. } = self {
+            *presented = true;
+
+            if let Some(locker) = locker.take() {
+                locker.lock();
+            }
+        }
+    }
+}
+
+impl SessionLockHandler for Aerugo {
+    fn lock_state(&mut self) -> &mut SessionLockManagerState {
+        &mut self.session_lock_state
+    }
+
+    fn lock(&mut self, confirmation: SessionLocker) {
+        tracing::info!("Locking session");
+        let locked = self.session_lock.is_locked();
+        self.enter_lock(Some(confirmation));
+
+        // The session may already be locked by the display server, which started the lock screen.
+        if !locked {
+            self.wm.send(WmEvent::SessionLocked);
+        }
+    }
+
+    fn unlock(&mut self) {
+        // The session may have been unlocked by the display server before the lock screen unlocked.
+        if self.session_lock.is_locked() {
+            tracing::info!("Unlocking session");
+            self.leave_lock();
+        }
+    }
+
+    fn new_surface(&mut self, surface: LockSurface, output: WlOutput) {
+        let Some(output) = Output::from_resource(&output) else {
+            return;
+        };
+
+        // TODO: Outputs without a mode cannot be configured, the lock surface will be configured once the
+        // output has a mode.
+        if let Some(mode) = output.current_mode() {
+            let size = output.current_transform().transform_size(mode.size);
+            let scale = output.current_scale().fractional_scale();
+            let size = size.to_f64().to_logical(scale).to_i32_round();
+
+            surface.with_pending_state(|state| {
+                state.size = Some((size.w as u32, size.h as u32).into());
+            });
+            surface.send_configure();
+        }
+
+        let SessionLock::Locked { surfaces, .. } = &mut self.session_lock else {
+            return;
+        };
+
+        let focus = surface.wl_surface().clone();
+        surfaces.insert(output, surface);
+
+        // The lock surface receives keyboard input unless another lock surface already does.
+        let focused = self.seat.get_keyboard().and_then(|keyboard| keyboard.current_focus());
+
+        if focused.map_or(true, |focused| !self.session_lock.allows_input(&focused)) {
+            self.set_keyboard_focus(Some(focus), SERIAL_COUNTER.next_serial());
+        }
+
+        self.refresh_pointer_focus();
+    }
+}
+
+impl Aerugo {
+    /// Lock the session on behalf of the display server, returning whether the session is locked.
+    ///
+    /// The session is only locked if the lock screen could be started, since nothing could unlock the session
+    /// otherwise.
+    pub fn lock_session(&mut self, lock_command: &[String]) -> bool {
+        if self.session_lock.is_locked() {
+            return true;
+        }
+
+        if lock_command.is_empty() {
+            tracing::warn!("Not locking the session, no lock command is configured");
+            return false;
+        }
+
+        if let Err(err) = self.spawn(Origin::Server, lock_command, false) {
+            tracing::error!(%err, "Failed to start the lock screen");
+            return false;
+        }
+
+        tracing::info!("Locking session");
+        self.enter_lock(None);
+        self.wm.send(WmEvent::SessionLocked);
+        true
+    }
+
+    /// Unlock the session on behalf of the display server, even if the lock screen did not unlock the session.
+    pub fn unlock_session(&mut self) {
+        if self.session_lock.is_locked() {
+            tracing::info!("Unlocking session on behalf of the display server");
+            self.leave_lock();
+        }
+    }
+
+    /// Give keyboard focus to a surface.
+    ///
+    /// While the session is locked, focus given to a surface which is not a lock surface is applied once the
+    /// session is unlocked.
+    pub fn set_keyboard_focus(&mut self, surface: Option<WlSurface>, serial: Serial) {
+        let allowed = surface
+            .as_ref()
+            .map_or(true, |surface| self.session_lock.allows_input(surface));
+
+        if let SessionLock::Locked { unlock_focus, .. } = &mut self.session_lock {
+            if !allowed || surface.is_none() {
+                *unlock_focus = surface;
+                return;
+            }
+        }
+
+        if let Some(keyboard) = self.seat.get_keyboard() {
+            keyboard.set_focus(self, surface, serial);
+        }
+    }
+
+    /// Lock the session and take input away from clients until the lock surfaces are created.
+    fn enter_lock(&mut self, locker: Option<SessionLocker>) {
+        let serial = SERIAL_COUNTER.next_serial();
+        let keyboard = self.seat.get_keyboard();
+
+        // The display server may have locked the session before the lock screen took over the lock.
+        let unlock_focus = match &mut self.session_lock {
+            SessionLock::Locked { unlock_focus, .. } => unlock_focus.take(),
+            SessionLock::Unlocked => keyboard.as_ref().and_then(|keyboard| keyboard.current_focus()),
+        };
+
+        self.session_lock = SessionLock::Locked {
+            locker,
+            surfaces: FxHashMap::default(),
+            presented: false,
+            unlock_focus,
+        };
+
+        if let Some(keyboard) = keyboard {
+            keyboard.set_focus(self, None, serial);
+        }
+
+        // The wm receives no input while locked, so a keybinding or move started before the lock ends now.
+        self.input.key_repeat().stop();
+        self.fallback_wm.cancel_grab();
+        self.refresh_pointer_focus();
+    }
+
+    /// Unlock the session and return input to the surfaces focused before the session was locked.
+    fn leave_lock(&mut self) {
+        let unlock_focus = match std::mem::take(&mut self.session_lock) {
+            SessionLock::Locked { unlock_focus, .. } => unlock_focus.filter(|surface| surface.is_alive()),
+            SessionLock::Unlocked => None,
+        };
+
+        self.set_keyboard_focus(unlock_focus, SERIAL_COUNTER.next_serial());
+        self.refresh_pointer_focus();
+        self.wm.send(WmEvent::SessionUnlocked);
+    }
+}
+
+smithay::delegate_session_lock!(Aerugo);
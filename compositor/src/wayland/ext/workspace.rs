@@ -0,0 +1,338 @@
+//! Implementation of the `ext-workspace-v1` protocol.
+//!
+//! The workspaces of the wm are reflected to clients such as pagers and bars. Each output with workspaces is
+//! advertised as a workspace group. A client may request a workspace be activated, which is forwarded to the wm
+//! when the client commits. The wm decides whether the workspace is activated.
+//!
+//! Instances are updated by comparing the workspaces of the wm against what was already sent to each instance,
+//! so any number of changes to the workspaces is sent to clients as a single batch.
+
+use std::sync::Mutex;
+
+use smithay::output::Output;
+use wayland_server::{
+    backend::{ClientId, ObjectId},
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+};
+use wm_runtime::{Id, WmEvent};
+
+use crate::{workspace::Workspaces, Aerugo};
+
+pub use self::generated::ext_workspace_manager_v1::ExtWorkspaceManagerV1;
+use self::generated::{
+    ext_workspace_group_handle_v1::{self, ExtWorkspaceGroupHandleV1, GroupCapabilities},
+    ext_workspace_handle_v1::{self, ExtWorkspaceHandleV1, WorkspaceCapabilities},
+    ext_workspace_manager_v1,
+};
+
+// ext-workspace-v1 is not part of the wayland-protocols release we use, so we need to generate it.
+#[allow(non_upper_case_globals, non_camel_case_types)]
+mod generated {
+    use smithay::reexports::wayland_server;
+    use smithay::reexports::wayland_server::protocol::*;
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_server::backend as wayland_backend;
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("../protocols/ext-workspace-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("../protocols/ext-workspace-v1.xml");
+}
+
+#[derive(Debug, Default)]
+pub struct ExtWorkspaceState {
+    instances: Vec<Instance>,
+}
+
+/// A bound `ext_workspace_manager_v1` and the objects sent to it.
+#[derive(Debug)]
+struct Instance {
+    manager: ExtWorkspaceManagerV1,
+    groups: Vec<(Output, ExtWorkspaceGroupHandleV1)>,
+    workspaces: Vec<WorkspaceHandle>,
+}
+
+#[derive(Debug)]
+struct WorkspaceHandle {
+    id: Id,
+    handle: ExtWorkspaceHandleV1,
+
+    /// The last state sent to the client.
+    output: Output,
+    name: String,
+    active: bool,
+}
+
+/// User data associated with a `ext_workspace_manager_v1`.
+#[derive(Debug, Default)]
+pub struct WorkspaceManagerData {
+    /// Workspaces the client requested be activated since the last commit.
+    pending: Mutex<Vec<Id>>,
+}
+
+/// User data associated with a `ext_workspace_handle_v1`.
+#[derive(Debug)]
+pub struct WorkspaceHandleData {
+    workspace: Id,
+    manager: ObjectId,
+}
+
+impl ExtWorkspaceState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send the changes to the workspaces of the wm to every instance.
+    pub fn refresh(&mut self, display: &DisplayHandle, workspaces: &Workspaces) {
+        for instance in &mut self.instances {
+            instance.refresh(display, workspaces);
+        }
+    }
+}
+
+impl Instance {
+    fn refresh(&mut self, display: &DisplayHandle, workspaces: &Workspaces) {
+        let Ok(client) = display.get_client(self.manager.id()) else {
+            return;
+        };
+
+        let mut changed = false;
+
+        // Workspaces leave their group before the group may be removed.
+        let groups = &self.groups;
+        self.workspaces.retain(|sent| {
+            let current = workspaces.get(sent.id);
+
+            if current.map_or(false, |workspace| workspace.output == sent.output) {
+                return true;
+            }
+
+            if let Some((_, group)) = groups.iter().find(|(output, _)| *output == sent.output) {
+                group.workspace_leave(&sent.handle);
+            }
+
+            sent.handle.removed();
+            changed = true;
+            false
+        });
+
+        self.groups.retain(|(output, group)| {
+            if workspaces.iter().any(|(_, workspace)| workspace.output == *output) {
+                return true;
+            }
+
+            group.removed();
+            changed = true;
+            false
+        });
+
+        for (id, workspace) in workspaces.iter() {
+            if !self.groups.iter().any(|(output, _)| *output == workspace.output) {
+                let Ok(group) =
+                    client.create_resource::<ExtWorkspaceGroupHandleV1, _, Aerugo>(display, self.manager.version(), ())
+                else {
+                    continue;
+                };
+
+                self.manager.workspace_group(&group);
+                group.capabilities(GroupCapabilities::empty());
+
+                // TODO: Send output_enter for wl_output objects the client binds after the group was created.
+                for wl_output in workspace.output.client_outputs(&client) {
+                    group.output_enter(&wl_output);
+                }
+
+                self.groups.push((workspace.output.clone(), group));
+                changed = true;
+            }
+
+            if let Some(sent) = self.workspaces.iter_mut().find(|sent| sent.id == id) {
+                if sent.name != workspace.name {
+                    sent.handle.name(workspace.name.clone());
+                    sent.name = workspace.name.clone();
+                    changed = true;
+                }
+
+                if sent.active != workspace.active {
+                    sent.handle.state(state(workspace.active));
+                    sent.active = workspace.active;
+                    changed = true;
+                }
+
+                continue;
+            }
+
+            let data = WorkspaceHandleData {
+                workspace: id,
+                manager: self.manager.id(),
+            };
+            let Ok(handle) =
+                client.create_resource::<ExtWorkspaceHandleV1, _, Aerugo>(display, self.manager.version(), data)
+            else {
+                continue;
+            };
+
+            self.manager.workspace(&handle);
+            handle.name(workspace.name.clone());
+            handle.state(state(workspace.active));
+            handle.capabilities(WorkspaceCapabilities::Activate);
+
+            if let Some((_, group)) = self.groups.iter().find(|(output, _)| *output == workspace.output) {
+                group.workspace_enter(&handle);
+            }
+
+            self.workspaces.push(WorkspaceHandle {
+                id,
+                handle,
+                output: workspace.output.clone(),
+                name: workspace.name.clone(),
+                active: workspace.active,
+            });
+            changed = true;
+        }
+
+        if changed {
+            self.manager.done();
+        }
+    }
+}
+
+fn state(active: bool) -> ext_workspace_handle_v1::State {
+    if active {
+        ext_workspace_handle_v1::State::Active
+    } else {
+        ext_workspace_handle_v1::State::empty()
+    }
+}
+
+impl GlobalDispatch<ExtWorkspaceManagerV1, ()> for Aerugo {
+    fn bind(
+        state: &mut Self,
+        display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtWorkspaceManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        let manager = init.init(resource, WorkspaceManagerData::default());
+        let mut instance = Instance {
+            manager,
+            groups: Vec::new(),
+            workspaces: Vec::new(),
+        };
+
+        // The initial state is always followed by done, which refresh only sends if there are workspaces.
+        instance.refresh(display, state.wm.workspaces());
+        if instance.workspaces.is_empty() {
+            instance.manager.done();
+        }
+
+        state.ext_workspace.instances.push(instance);
+    }
+}
+
+impl Dispatch<ExtWorkspaceManagerV1, WorkspaceManagerData> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ExtWorkspaceManagerV1,
+        request: ext_workspace_manager_v1::Request,
+        data: &WorkspaceManagerData,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            ext_workspace_manager_v1::Request::Commit => {
+                let pending = std::mem::take(&mut *data.pending.lock().unwrap());
+
+                for workspace in pending {
+                    state.wm.send(WmEvent::WorkspaceActivationRequested(workspace));
+                }
+            }
+
+            ext_workspace_manager_v1::Request::Stop => {
+                state
+                    .ext_workspace
+                    .instances
+                    .retain(|instance| instance.manager.id() != resource.id());
+                resource.finished();
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &ExtWorkspaceManagerV1, _data: &WorkspaceManagerData) {
+        state
+            .ext_workspace
+            .instances
+            .retain(|instance| instance.manager.id() != resource.id());
+    }
+}
+
+impl Dispatch<ExtWorkspaceGroupHandleV1, ()> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ExtWorkspaceGroupHandleV1,
+        request: ext_workspace_group_handle_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            // Workspaces are only created by the wm, so the capability is not advertised.
+            ext_workspace_group_handle_v1::Request::CreateWorkspace { .. } => {}
+            ext_workspace_group_handle_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<ExtWorkspaceHandleV1, WorkspaceHandleData> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &ExtWorkspaceHandleV1,
+        request: ext_workspace_handle_v1::Request,
+        data: &WorkspaceHandleData,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            ext_workspace_handle_v1::Request::Activate => {
+                let Some(instance) = state
+                    .ext_workspace
+                    .instances
+                    .iter()
+                    .find(|instance| instance.manager.id() == data.manager)
+                else {
+                    return;
+                };
+
+                // Requests for workspaces which were removed are ignored.
+                if instance.workspaces.iter().any(|sent| sent.id == data.workspace) {
+                    let manager_data = instance.manager.data::<WorkspaceManagerData>().unwrap();
+                    manager_data.pending.lock().unwrap().push(data.workspace);
+                }
+            }
+
+            // Only activation is advertised.
+            ext_workspace_handle_v1::Request::Deactivate
+            | ext_workspace_handle_v1::Request::Assign { .. }
+            | ext_workspace_handle_v1::Request::Remove => {}
+
+            ext_workspace_handle_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+}
@@ -5,11 +5,27 @@
 //! Some protocols are not included in this module. Notably `wl_shm` and `zwp_linux_dmabuf_v1` since these two
 //! protocols require deeper integration with the backend.
 
+pub mod aerugo;
 pub mod core;
 pub mod ext;
+pub mod wlr;
+pub mod wp;
 
+pub mod xdg_activation;
+pub mod xdg_decoration;
 pub mod xdg_shell;
 
 pub mod versions {
+    pub const AERUGO_DEBUG_V1: u32 = 1;
+    pub const AERUGO_SHELL_V1: u32 = 1;
     pub const EXT_FOREIGN_TOPLEVEL_LIST_V1: u32 = 1;
+    pub const EXT_FOREIGN_TOPLEVEL_STATE_MANAGER_V1: u32 = 1;
+    pub const EXT_WORKSPACE_MANAGER_V1: u32 = 1;
+    pub const WP_LINUX_DRM_SYNCOBJ_MANAGER_V1: u32 = 1;
+    pub const ZWP_IDLE_INHIBIT_MANAGER_V1: u32 = 1;
+    pub const ZWLR_DATA_CONTROL_MANAGER_V1: u32 = 2;
+    pub const ZWLR_GAMMA_CONTROL_MANAGER_V1: u32 = 1;
+    pub const ZWLR_OUTPUT_MANAGER_V1: u32 = 4;
+    pub const ZWLR_OUTPUT_POWER_MANAGER_V1: u32 = 1;
+    pub const ZWLR_SCREENCOPY_MANAGER_V1: u32 = 3;
 }
@@ -0,0 +1,423 @@
+//! Implementation of the `wlr-data-control-unstable-v1` protocol.
+//!
+//! Data control lets privileged clients such as clipboard managers observe and set the clipboard and primary
+//! selections without having keyboard focus.
+//!
+//! Selections set through data control are server side selections of the seat. When a client requests the
+//! contents of such a selection, the request is forwarded to the data control source which set the selection.
+//!
+//! Every selection has a generation which advances whenever the selection changes. Offers remember the generation
+//! they were created for, and receive requests on offers of a replaced selection are ignored, since the data
+//! would come from the new selection instead.
+
+use std::{os::fd::AsFd, sync::Mutex};
+
+use smithay::{
+    input::Seat,
+    reexports::wayland_protocols_wlr::data_control::v1::server::{
+        zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+        zwlr_data_control_manager_v1::{self, ZwlrDataControlManagerV1},
+        zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+        zwlr_data_control_source_v1::{self, ZwlrDataControlSourceV1},
+    },
+    wayland::{data_device, primary_selection},
+};
+use wayland_server::{backend::ClientId, Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource};
+
+use crate::{Aerugo, ClientData, PrivilegedGlobals};
+
+/// The version of `zwlr_data_control_device_v1` which added the primary selection.
+const PRIMARY_SELECTION_SINCE: u32 = 2;
+
+/// State of the data control protocol.
+#[derive(Debug, Default)]
+pub struct DataControlState {
+    devices: Vec<ZwlrDataControlDeviceV1>,
+
+    /// The mime types of the clipboard selection, if any.
+    clipboard: Option<Vec<String>>,
+
+    /// The mime types of the primary selection, if any.
+    primary: Option<Vec<String>>,
+
+    /// The data control source which set the clipboard selection.
+    clipboard_source: Option<ZwlrDataControlSourceV1>,
+
+    /// The data control source which set the primary selection.
+    primary_source: Option<ZwlrDataControlSourceV1>,
+
+    /// The generation of the clipboard selection.
+    clipboard_generation: u64,
+
+    /// The generation of the primary selection.
+    primary_generation: u64,
+}
+
+/// The selection a data control offer or source is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Clipboard,
+    Primary,
+}
+
+/// User data associated with a `zwlr_data_control_offer_v1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OfferData {
+    target: Target,
+
+    /// The generation of the selection the offer was created for.
+    generation: u64,
+}
+
+impl DataControlState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A client set a new selection of the seat.
+    ///
+    /// Any data control source which previously set the selection is cancelled.
+    pub fn new_selection(&mut self, dh: &DisplayHandle, target: Target, mime_types: Option<Vec<String>>) {
+        if let Some(source) = self.source_mut(target).take() {
+            source.cancelled();
+        }
+
+        self.set_mime_types(dh, target, mime_types);
+    }
+
+    /// Forward a request for the contents of a server side selection to the data control source which set it.
+    pub fn send_selection(&self, target: Target, mime_type: String, fd: impl AsFd) {
+        let source = match target {
+            Target::Clipboard => &self.clipboard_source,
+            Target::Primary => &self.primary_source,
+        };
+
+        if let Some(source) = source {
+            source.send(mime_type, fd.as_fd());
+        }
+    }
+
+    fn source_mut(&mut self, target: Target) -> &mut Option<ZwlrDataControlSourceV1> {
+        match target {
+            Target::Clipboard => &mut self.clipboard_source,
+            Target::Primary => &mut self.primary_source,
+        }
+    }
+
+    /// Start a new generation of the selection of the target, making offers of the previous selection stale.
+    fn advance_generation(&mut self, target: Target) {
+        let generation = match target {
+            Target::Clipboard => &mut self.clipboard_generation,
+            Target::Primary => &mut self.primary_generation,
+        };
+
+        *generation = generation.wrapping_add(1);
+    }
+
+    /// Create the data of an offer for the current selection of the target.
+    fn offer_data(&self, target: Target) -> OfferData {
+        let generation = match target {
+            Target::Clipboard => self.clipboard_generation,
+            Target::Primary => self.primary_generation,
+        };
+
+        OfferData { target, generation }
+    }
+
+    /// Whether the offer was created for the current selection of the target.
+    fn is_current(&self, offer: &OfferData) -> bool {
+        self.offer_data(offer.target) == *offer
+    }
+
+    fn set_mime_types(&mut self, dh: &DisplayHandle, target: Target, mime_types: Option<Vec<String>>) {
+        match target {
+            Target::Clipboard => self.clipboard = mime_types,
+            Target::Primary => self.primary = mime_types,
+        }
+
+        self.advance_generation(target);
+
+        for device in &self.devices {
+            self.send_offer(dh, device, target);
+        }
+    }
+
+    /// Send the current selection of the target to a device.
+    fn send_offer(&self, dh: &DisplayHandle, device: &ZwlrDataControlDeviceV1, target: Target) {
+        let mime_types = match target {
+            Target::Clipboard => &self.clipboard,
+            Target::Primary if device.version() >= PRIMARY_SELECTION_SINCE => &self.primary,
+            Target::Primary => return,
+        };
+
+        let offer = mime_types.as_ref().and_then(|mime_types| {
+            let client = dh.get_client(device.id()).ok()?;
+            let offer = client
+                .create_resource::<ZwlrDataControlOfferV1, _, Aerugo>(dh, device.version(), self.offer_data(target))
+                .ok()?;
+
+            device.data_offer(&offer);
+
+            for mime_type in mime_types {
+                offer.offer(mime_type.clone());
+            }
+
+            Some(offer)
+        });
+
+        match target {
+            Target::Clipboard => device.selection(offer.as_ref()),
+            Target::Primary => device.primary_selection(offer.as_ref()),
+        }
+    }
+}
+
+/// User data associated with a `zwlr_data_control_source_v1`.
+#[derive(Debug, Default)]
+pub struct DataControlSourceData {
+    inner: Mutex<SourceInner>,
+}
+
+#[derive(Debug, Default)]
+struct SourceInner {
+    mime_types: Vec<String>,
+
+    /// Whether the source was used to set a selection.
+    ///
+    /// A source may only be used once and is immutable afterwards.
+    used: bool,
+}
+
+impl GlobalDispatch<ZwlrDataControlManagerV1, ()> for Aerugo {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrDataControlManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        init.init(resource, ());
+    }
+
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        ClientData::get_data(&client)
+            .map(|data| data.is_visible(PrivilegedGlobals::DATA_CONTROL))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<ZwlrDataControlManagerV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrDataControlManagerV1,
+        request: zwlr_data_control_manager_v1::Request,
+        _data: &(),
+        display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_data_control_manager_v1::Request::CreateDataSource { id } => {
+                init.init(id, DataControlSourceData::default());
+            }
+
+            zwlr_data_control_manager_v1::Request::GetDataDevice { id, seat: _ } => {
+                // There is only a single seat.
+                let device = init.init(id, ());
+                state.data_control.send_offer(display, &device, Target::Clipboard);
+                state.data_control.send_offer(display, &device, Target::Primary);
+                state.data_control.devices.push(device);
+            }
+
+            zwlr_data_control_manager_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrDataControlDeviceV1,
+        request: zwlr_data_control_device_v1::Request,
+        _data: &(),
+        display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        let (source, target) = match request {
+            zwlr_data_control_device_v1::Request::SetSelection { source } => (source, Target::Clipboard),
+            zwlr_data_control_device_v1::Request::SetPrimarySelection { source } => (source, Target::Primary),
+            zwlr_data_control_device_v1::Request::Destroy => return,
+            _ => unreachable!(),
+        };
+
+        let mime_types = match &source {
+            Some(source) => {
+                let data = source.data::<DataControlSourceData>().unwrap();
+                let mut inner = data.inner.lock().unwrap();
+
+                if inner.used {
+                    resource.post_error(
+                        zwlr_data_control_device_v1::Error::UsedSource,
+                        "source was already used to set a selection",
+                    );
+                    return;
+                }
+
+                inner.used = true;
+                Some(inner.mime_types.clone())
+            }
+
+            None => None,
+        };
+
+        let seat = state.seat.clone();
+        set_seat_selection(display, &seat, target, mime_types.clone());
+
+        let data_control = &mut state.data_control;
+        if let Some(previous) = data_control.source_mut(target).take() {
+            previous.cancelled();
+        }
+
+        *data_control.source_mut(target) = source;
+        data_control.set_mime_types(display, target, mime_types);
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &ZwlrDataControlDeviceV1, _data: &()) {
+        state.data_control.devices.retain(|device| device.id() != resource.id());
+    }
+}
+
+impl Dispatch<ZwlrDataControlSourceV1, DataControlSourceData> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrDataControlSourceV1,
+        request: zwlr_data_control_source_v1::Request,
+        data: &DataControlSourceData,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_data_control_source_v1::Request::Offer { mime_type } => {
+                let mut inner = data.inner.lock().unwrap();
+
+                if inner.used {
+                    resource.post_error(
+                        zwlr_data_control_source_v1::Error::InvalidOffer,
+                        "offer sent after the source was used to set a selection",
+                    );
+                    return;
+                }
+
+                inner.mime_types.push(mime_type);
+            }
+
+            zwlr_data_control_source_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ClientId,
+        resource: &ZwlrDataControlSourceV1,
+        _data: &DataControlSourceData,
+    ) {
+        let display = state.display.clone();
+        let seat = state.seat.clone();
+
+        // Destroying the source which set a selection clears the selection.
+        for target in [Target::Clipboard, Target::Primary] {
+            let source = state.data_control.source_mut(target);
+
+            if source.as_ref().map(Resource::id) == Some(resource.id()) {
+                *source = None;
+                set_seat_selection(&display, &seat, target, None);
+                state.data_control.set_mime_types(&display, target, None);
+            }
+        }
+    }
+}
+
+impl Dispatch<ZwlrDataControlOfferV1, OfferData> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrDataControlOfferV1,
+        request: zwlr_data_control_offer_v1::Request,
+        offer: &OfferData,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        let (mime_type, fd) = match request {
+            zwlr_data_control_offer_v1::Request::Receive { mime_type, fd } => (mime_type, fd),
+            zwlr_data_control_offer_v1::Request::Destroy => return,
+            _ => unreachable!(),
+        };
+
+        let target = &offer.target;
+
+        // The selection was replaced after the offer was created. Closing the fd tells the client there is no data.
+        if !state.data_control.is_current(offer) {
+            tracing::debug!(?target, "Ignoring receive on a stale data control offer");
+            return;
+        }
+
+        // Selections set through data control are answered by the data control source directly.
+        let has_source = match target {
+            Target::Clipboard => state.data_control.clipboard_source.is_some(),
+            Target::Primary => state.data_control.primary_source.is_some(),
+        };
+
+        if has_source {
+            state.data_control.send_selection(*target, mime_type, fd);
+            return;
+        }
+
+        let result = match target {
+            Target::Clipboard => data_device::request_data_device_client_selection(&state.seat, mime_type, fd)
+                .map_err(|err| err.to_string()),
+            Target::Primary => primary_selection::request_primary_client_selection(&state.seat, mime_type, fd)
+                .map_err(|err| err.to_string()),
+        };
+
+        if let Err(err) = result {
+            tracing::debug!(%err, ?target, "Failed to request selection for data control offer");
+        }
+    }
+}
+
+/// Replace the selection of the seat with a server side selection, or clear the selection.
+fn set_seat_selection(dh: &DisplayHandle, seat: &Seat<Aerugo>, target: Target, mime_types: Option<Vec<String>>) {
+    match (target, mime_types) {
+        (Target::Clipboard, Some(mime_types)) => data_device::set_data_device_selection(dh, seat, mime_types),
+        (Target::Clipboard, None) => data_device::clear_data_device_selection(dh, seat),
+        (Target::Primary, Some(mime_types)) => primary_selection::set_primary_selection(dh, seat, mime_types),
+        (Target::Primary, None) => primary_selection::clear_primary_selection(dh, seat),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DataControlState, Target};
+
+    #[test]
+    fn stale_offers() {
+        let mut state = DataControlState::new();
+        let clipboard = state.offer_data(Target::Clipboard);
+        let primary = state.offer_data(Target::Primary);
+        assert!(state.is_current(&clipboard));
+        assert!(state.is_current(&primary));
+
+        // Replacing the clipboard selection only invalidates clipboard offers.
+        state.advance_generation(Target::Clipboard);
+        assert!(!state.is_current(&clipboard));
+        assert!(state.is_current(&primary));
+        assert!(state.is_current(&state.offer_data(Target::Clipboard)));
+    }
+}
@@ -0,0 +1,202 @@
+//! Implementation of the `wlr-gamma-control-unstable-v1` protocol.
+//!
+//! A client may control the gamma ramp of an output, for example to implement a blue light filter. Only one client
+//! may control an output at a time, and the ramp of the output is restored once the control is destroyed. While a
+//! client controls the gamma of an output, the built-in night light (see [`gamma`](crate::gamma)) has no effect on
+//! the output.
+//!
+//! The gamma of an output can only be controlled if the backend can set the gamma of the output. Controls of other
+//! outputs fail immediately.
+
+use std::{fs::File, os::unix::fs::FileExt};
+
+use smithay::{
+    output::Output,
+    reexports::wayland_protocols_wlr::gamma_control::v1::server::{
+        zwlr_gamma_control_manager_v1::{self, ZwlrGammaControlManagerV1},
+        zwlr_gamma_control_v1::{self, ZwlrGammaControlV1},
+    },
+};
+use wayland_server::{backend::ClientId, Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource};
+
+use crate::{gamma::GammaRamp, Aerugo, ClientData, PrivilegedGlobals};
+
+/// State of the gamma control protocol.
+#[derive(Debug, Default)]
+pub struct GammaControlState {
+    controls: Vec<GammaControl>,
+}
+
+#[derive(Debug)]
+struct GammaControl {
+    control: ZwlrGammaControlV1,
+    output: Output,
+
+    /// The number of entries of each channel of the gamma ramp.
+    size: usize,
+
+    /// The ramp set by the client, if any.
+    ramp: Option<GammaRamp>,
+}
+
+impl GammaControlState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The ramp a client set on the output.
+    pub fn ramp(&self, output: &Output) -> Option<&GammaRamp> {
+        self.controls
+            .iter()
+            .find(|control| control.output == *output)
+            .and_then(|control| control.ramp.as_ref())
+    }
+
+    /// Fail the control of an output which was removed.
+    pub fn remove_output(&mut self, output: &Output) {
+        self.controls.retain(|control| {
+            if control.output != *output {
+                return true;
+            }
+
+            control.control.failed();
+            false
+        });
+    }
+
+    /// Stop tracking a control, returning the output the control controlled.
+    fn remove(&mut self, control: &ZwlrGammaControlV1) -> Option<Output> {
+        let index = self.controls.iter().position(|tracked| tracked.control == *control)?;
+        Some(self.controls.remove(index).output)
+    }
+}
+
+impl Aerugo {
+    /// Stop tracking a gamma control and restore the gamma of the output.
+    fn remove_gamma_control(&mut self, control: &ZwlrGammaControlV1) {
+        let Some(output) = self.gamma_control.remove(control) else {
+            return;
+        };
+
+        if let Err(err) = self.apply_gamma(&output) {
+            tracing::warn!(output = output.name(), %err, "Failed to restore gamma");
+        }
+    }
+}
+
+impl GlobalDispatch<ZwlrGammaControlManagerV1, ()> for Aerugo {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrGammaControlManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        init.init(resource, ());
+    }
+
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        ClientData::get_data(&client)
+            .map(|data| data.is_visible(PrivilegedGlobals::GAMMA_CONTROL))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<ZwlrGammaControlManagerV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrGammaControlManagerV1,
+        request: zwlr_gamma_control_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_gamma_control_manager_v1::Request::GetGammaControl { id, output } => {
+                let control = init.init(id, ());
+
+                // Only one client may control the gamma of an output.
+                let output = Output::from_resource(&output)
+                    .filter(|output| state.outputs.iter().any(|(connected, _)| connected == output))
+                    .filter(|output| !state.gamma_control.controls.iter().any(|c| c.output == *output));
+                let Some((output, size)) =
+                    output.and_then(|output| Some((output.clone(), state.backend.gamma_size(&output)?)))
+                else {
+                    control.failed();
+                    return;
+                };
+
+                control.gamma_size(size);
+                state.gamma_control.controls.push(GammaControl {
+                    control,
+                    output,
+                    size: size as usize,
+                    ramp: None,
+                });
+            }
+
+            zwlr_gamma_control_manager_v1::Request::Destroy => (),
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<ZwlrGammaControlV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrGammaControlV1,
+        request: zwlr_gamma_control_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_gamma_control_v1::Request::SetGamma { fd } => {
+                // Controls which failed are no longer tracked.
+                let Some(control) = state
+                    .gamma_control
+                    .controls
+                    .iter_mut()
+                    .find(|control| control.control == *resource)
+                else {
+                    return;
+                };
+
+                let mut bytes = vec![0; control.size * 3 * 2];
+                let ramp = File::from(fd)
+                    .read_exact_at(&mut bytes, 0)
+                    .ok()
+                    .and_then(|()| GammaRamp::from_bytes(control.size, &bytes));
+
+                let Some(ramp) = ramp else {
+                    resource.post_error(
+                        zwlr_gamma_control_v1::Error::InvalidGamma,
+                        "gamma table does not match the gamma size",
+                    );
+                    return;
+                };
+
+                control.ramp = Some(ramp);
+                let output = control.output.clone();
+
+                if let Err(err) = state.apply_gamma(&output) {
+                    tracing::debug!(output = output.name(), %err, "Failed to set gamma of client");
+                    resource.failed();
+                    state.remove_gamma_control(resource);
+                }
+            }
+
+            zwlr_gamma_control_v1::Request::Destroy => (),
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &ZwlrGammaControlV1, _data: &()) {
+        state.remove_gamma_control(resource);
+    }
+}
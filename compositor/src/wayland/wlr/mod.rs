@@ -0,0 +1,7 @@
+//! `wlr` protocol implementations
+
+pub mod data_control;
+pub mod gamma_control;
+pub mod output_management;
+pub mod output_power;
+pub mod screencopy;
@@ -0,0 +1,667 @@
+//! Implementation of the `wlr-output-management-unstable-v1` protocol.
+//!
+//! Every output known to the compositor is advertised as a head, including disabled outputs. Clients apply
+//! configurations atomically: either every head in the configuration is applied or none are.
+//!
+//! A configuration is first validated against the protocol rules and then tested by the backend using
+//! [`Backend::test_output_configuration`](crate::backend::Backend::test_output_configuration). When a
+//! configuration is applied, enabled outputs are added to the scene graph and disabled outputs are removed.
+//!
+//! Each change to the output configuration increments the serial of the configuration. Configurations created
+//! against an older serial are cancelled.
+
+use std::sync::Mutex;
+
+use smithay::{
+    output::{Mode, Output, Scale},
+    reexports::wayland_protocols_wlr::output_management::v1::server::{
+        zwlr_output_configuration_head_v1::{self, ZwlrOutputConfigurationHeadV1},
+        zwlr_output_configuration_v1::{self, ZwlrOutputConfigurationV1},
+        zwlr_output_head_v1::{self, ZwlrOutputHeadV1},
+        zwlr_output_manager_v1::{self, ZwlrOutputManagerV1},
+        zwlr_output_mode_v1::{self, ZwlrOutputModeV1},
+    },
+    utils::{Logical, Point, Transform},
+};
+use wayland_server::{
+    backend::{ClientId, ObjectId},
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+};
+
+use crate::{frame_scheduler::frame_scheduler, Aerugo, ClientData, PrivilegedGlobals};
+
+/// Refresh rate used for custom modes where the client did not specify a refresh rate, in mHz.
+const DEFAULT_REFRESH: i32 = 60_000;
+
+/// The requested state of an output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputConfiguration {
+    pub enabled: bool,
+    pub mode: Option<Mode>,
+    pub position: Point<i32, Logical>,
+    pub transform: Transform,
+    pub scale: f64,
+    pub adaptive_sync: bool,
+}
+
+impl OutputConfiguration {
+    /// The current configuration of the output.
+    fn current(output: &Output, enabled: bool) -> Self {
+        Self {
+            enabled,
+            mode: output.current_mode(),
+            position: output.current_location(),
+            transform: output.current_transform(),
+            scale: output.current_scale().fractional_scale(),
+            adaptive_sync: frame_scheduler(output).adaptive_sync_allowed(),
+        }
+    }
+}
+
+/// State of the output management protocol.
+#[derive(Debug, Default)]
+pub struct OutputManagementState {
+    /// Outputs advertised as heads.
+    outputs: Vec<Output>,
+
+    managers: Vec<ZwlrOutputManagerV1>,
+    heads: Vec<HeadInstance>,
+
+    /// Serial of the current output configuration.
+    serial: u32,
+}
+
+#[derive(Debug)]
+struct HeadInstance {
+    manager: ObjectId,
+    head: ZwlrOutputHeadV1,
+    output: Output,
+    modes: Vec<(ZwlrOutputModeV1, Mode)>,
+}
+
+impl OutputManagementState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advertise an output to clients.
+    pub fn add_output(&mut self, display: &DisplayHandle, output: &Output, enabled: bool) {
+        if self.outputs.contains(output) {
+            return;
+        }
+
+        self.outputs.push(output.clone());
+        self.serial = self.serial.wrapping_add(1);
+
+        for manager in &self.managers {
+            if let Ok(client) = display.get_client(manager.id()) {
+                let head = send_head(display, &client, manager, output, enabled);
+                self.heads.push(head);
+            }
+
+            manager.done(self.serial);
+        }
+    }
+
+    /// Remove an output which no longer exists.
+    pub fn remove_output(&mut self, output: &Output) {
+        self.outputs.retain(|o| o != output);
+        self.serial = self.serial.wrapping_add(1);
+
+        self.heads.retain(|instance| {
+            if instance.output != *output {
+                return true;
+            }
+
+            for (mode, _) in &instance.modes {
+                mode.finished();
+            }
+            instance.head.finished();
+            false
+        });
+
+        for manager in &self.managers {
+            manager.done(self.serial);
+        }
+    }
+
+    /// Notify clients that the configuration of the outputs changed.
+    pub fn configuration_changed(&mut self, display: &DisplayHandle, enabled: impl Fn(&Output) -> bool) {
+        self.serial = self.serial.wrapping_add(1);
+
+        for instance in &mut self.heads {
+            let Ok(client) = display.get_client(instance.head.id()) else {
+                continue;
+            };
+
+            send_head_state(display, &client, instance, enabled(&instance.output));
+        }
+
+        for manager in &self.managers {
+            manager.done(self.serial);
+        }
+    }
+}
+
+/// User data of a `zwlr_output_configuration_v1`.
+#[derive(Debug)]
+pub struct ConfigurationData {
+    serial: u32,
+    state: Mutex<ConfigurationState>,
+}
+
+#[derive(Debug, Default)]
+struct ConfigurationState {
+    used: bool,
+
+    /// Heads in the configuration. A head with no configuration head is disabled.
+    heads: Vec<(Output, Option<ZwlrOutputConfigurationHeadV1>)>,
+}
+
+/// User data of a `zwlr_output_configuration_head_v1`.
+#[derive(Debug)]
+pub struct ConfigurationHeadData {
+    output: Output,
+    changes: Mutex<HeadChanges>,
+}
+
+/// Changes requested on a configuration head.
+#[derive(Debug, Default)]
+struct HeadChanges {
+    mode: Option<Mode>,
+    position: Option<Point<i32, Logical>>,
+    transform: Option<Transform>,
+    scale: Option<f64>,
+    adaptive_sync: Option<bool>,
+}
+
+/// User data of a `zwlr_output_mode_v1`.
+#[derive(Debug)]
+pub struct ModeData {
+    output: Output,
+    mode: Mode,
+}
+
+fn send_head(
+    display: &DisplayHandle,
+    client: &Client,
+    manager: &ZwlrOutputManagerV1,
+    output: &Output,
+    enabled: bool,
+) -> HeadInstance {
+    let head = client
+        .create_resource::<ZwlrOutputHeadV1, _, Aerugo>(display, manager.version(), output.clone())
+        .unwrap();
+    manager.head(&head);
+
+    let properties = output.physical_properties();
+    head.name(output.name());
+    head.description(output.description());
+
+    if properties.size.w > 0 && properties.size.h > 0 {
+        head.physical_size(properties.size.w, properties.size.h);
+    }
+
+    if head.version() >= zwlr_output_head_v1::EVT_MAKE_SINCE {
+        head.make(properties.make);
+        head.model(properties.model);
+    }
+
+    let mut instance = HeadInstance {
+        manager: manager.id(),
+        head,
+        output: output.clone(),
+        modes: Vec::new(),
+    };
+
+    send_head_state(display, client, &mut instance, enabled);
+    instance
+}
+
+/// Send the current state of the output on the head.
+fn send_head_state(display: &DisplayHandle, client: &Client, instance: &mut HeadInstance, enabled: bool) {
+    let output = &instance.output;
+    let preferred = output.preferred_mode();
+    let modes = output.modes();
+
+    // Retire modes the output no longer has.
+    instance.modes.retain(|(resource, mode)| {
+        let known = modes.contains(mode);
+
+        if !known {
+            resource.finished();
+        }
+
+        known
+    });
+
+    // Advertise modes which the head does not know about yet.
+    for mode in modes {
+        if instance.modes.iter().any(|(_, known)| *known == mode) {
+            continue;
+        }
+
+        let resource = client
+            .create_resource::<ZwlrOutputModeV1, _, Aerugo>(
+                display,
+                instance.head.version(),
+                ModeData {
+                    output: output.clone(),
+                    mode,
+                },
+            )
+            .unwrap();
+        instance.head.mode(&resource);
+        resource.size(mode.size.w, mode.size.h);
+
+        if mode.refresh > 0 {
+            resource.refresh(mode.refresh);
+        }
+
+        if preferred == Some(mode) {
+            resource.preferred();
+        }
+
+        instance.modes.push((resource, mode));
+    }
+
+    instance.head.enabled(enabled as i32);
+
+    if enabled {
+        let current = output.current_mode();
+
+        if let Some((mode, _)) = instance.modes.iter().find(|(_, mode)| Some(*mode) == current) {
+            instance.head.current_mode(mode);
+        }
+
+        let position = output.current_location();
+        instance.head.position(position.x, position.y);
+        instance.head.transform(output.current_transform().into());
+        instance.head.scale(output.current_scale().fractional_scale());
+
+        // The head advertises whether adaptive sync is allowed, adaptive sync is only enabled while a fullscreen
+        // toplevel is visible.
+        if instance.head.version() >= zwlr_output_head_v1::EVT_ADAPTIVE_SYNC_SINCE {
+            instance
+                .head
+                .adaptive_sync(if frame_scheduler(output).adaptive_sync_allowed() {
+                    zwlr_output_head_v1::AdaptiveSyncState::Enabled
+                } else {
+                    zwlr_output_head_v1::AdaptiveSyncState::Disabled
+                });
+        }
+    }
+}
+
+impl Aerugo {
+    /// Test or apply an output configuration.
+    fn output_configuration(&mut self, configuration: &ZwlrOutputConfigurationV1, apply: bool) {
+        let data = configuration.data::<ConfigurationData>().unwrap();
+        let mut state = data.state.lock().unwrap();
+
+        if state.used {
+            configuration.post_error(
+                zwlr_output_configuration_v1::Error::AlreadyUsed,
+                "configuration was already applied or tested",
+            );
+            return;
+        }
+
+        state.used = true;
+
+        if data.serial != self.output_management.serial {
+            configuration.cancelled();
+            return;
+        }
+
+        // Every head must be either enabled or disabled in the configuration.
+        if self
+            .output_management
+            .outputs
+            .iter()
+            .any(|output| !state.heads.iter().any(|(head, _)| head == output))
+        {
+            configuration.post_error(
+                zwlr_output_configuration_v1::Error::UnconfiguredHead,
+                "not all heads were configured",
+            );
+            return;
+        }
+
+        let requested = state
+            .heads
+            .iter()
+            .map(|(output, head)| {
+                let enabled = self.scene.get_output_index(output).is_some();
+                let mut requested = OutputConfiguration::current(output, enabled);
+                requested.enabled = head.is_some();
+
+                if let Some(head) = head {
+                    let changes = head.data::<ConfigurationHeadData>().unwrap().changes.lock().unwrap();
+                    requested.mode = changes.mode.or(requested.mode);
+                    requested.position = changes.position.unwrap_or(requested.position);
+                    requested.transform = changes.transform.unwrap_or(requested.transform);
+                    requested.scale = changes.scale.unwrap_or(requested.scale);
+                    requested.adaptive_sync = changes.adaptive_sync.unwrap_or(requested.adaptive_sync);
+                }
+
+                (output.clone(), requested)
+            })
+            .collect::<Vec<_>>();
+
+        drop(state);
+
+        if !requested.iter().any(|(_, requested)| requested.enabled) {
+            tracing::debug!("Rejected output configuration: every output would be disabled");
+            configuration.failed();
+            return;
+        }
+
+        for (output, requested) in &requested {
+            if let Err(err) = self.backend.test_output_configuration(output, requested) {
+                tracing::debug!(output = output.name(), %err, "Rejected output configuration");
+                configuration.failed();
+                return;
+            }
+        }
+
+        if apply {
+            for (output, requested) in requested {
+                self.apply_output_configuration(&output, &requested);
+            }
+
+            let scene = &self.scene;
+            self.output_management
+                .configuration_changed(&self.display, |output| scene.get_output_index(output).is_some());
+        }
+
+        configuration.succeeded();
+    }
+
+    fn apply_output_configuration(&mut self, output: &Output, requested: &OutputConfiguration) {
+        let enabled = self.scene.get_output_index(output).is_some();
+
+        if !requested.enabled {
+            if enabled {
+                tracing::info!(output = output.name(), "Disabling output");
+                self.scene.destroy_output(output);
+            }
+
+            return;
+        }
+
+        if !enabled {
+            tracing::info!(output = output.name(), "Enabling output");
+            self.scene.create_output(output.clone());
+        }
+
+        output.change_current_state(
+            requested.mode,
+            Some(requested.transform),
+            Some(Scale::Fractional(requested.scale)),
+            Some(requested.position),
+        );
+        self.set_adaptive_sync_allowed(output, requested.adaptive_sync);
+    }
+}
+
+impl GlobalDispatch<ZwlrOutputManagerV1, ()> for Aerugo {
+    fn bind(
+        state: &mut Self,
+        display: &DisplayHandle,
+        client: &Client,
+        resource: New<ZwlrOutputManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        let manager = init.init(resource, ());
+
+        let heads = state
+            .output_management
+            .outputs
+            .iter()
+            .map(|output| {
+                let enabled = state.scene.get_output_index(output).is_some();
+                send_head(display, client, &manager, output, enabled)
+            })
+            .collect::<Vec<_>>();
+        state.output_management.heads.extend(heads);
+
+        manager.done(state.output_management.serial);
+        state.output_management.managers.push(manager);
+    }
+
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        ClientData::get_data(&client)
+            .map(|data| data.is_visible(PrivilegedGlobals::OUTPUT_MANAGEMENT))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<ZwlrOutputManagerV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrOutputManagerV1,
+        request: zwlr_output_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_output_manager_v1::Request::CreateConfiguration { id, serial } => {
+                init.init(
+                    id,
+                    ConfigurationData {
+                        serial,
+                        state: Mutex::new(ConfigurationState::default()),
+                    },
+                );
+            }
+
+            zwlr_output_manager_v1::Request::Stop => {
+                state.output_management.managers.retain(|manager| manager != resource);
+                resource.finished();
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &ZwlrOutputManagerV1, _data: &()) {
+        let id = resource.id();
+        state.output_management.managers.retain(|manager| manager.id() != id);
+        state.output_management.heads.retain(|head| head.manager != id);
+    }
+}
+
+impl Dispatch<ZwlrOutputHeadV1, Output> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrOutputHeadV1,
+        request: zwlr_output_head_v1::Request,
+        _data: &Output,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_output_head_v1::Request::Release => {
+                state.output_management.heads.retain(|head| head.head != *resource);
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &ZwlrOutputHeadV1, _data: &Output) {
+        let id = resource.id();
+        state.output_management.heads.retain(|head| head.head.id() != id);
+    }
+}
+
+impl Dispatch<ZwlrOutputModeV1, ModeData> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrOutputModeV1,
+        request: zwlr_output_mode_v1::Request,
+        _data: &ModeData,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_output_mode_v1::Request::Release => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationV1, ConfigurationData> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrOutputConfigurationV1,
+        request: zwlr_output_configuration_v1::Request,
+        data: &ConfigurationData,
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_output_configuration_v1::Request::EnableHead { id, head } => {
+                let output = head.data::<Output>().unwrap().clone();
+                let mut configuration = data.state.lock().unwrap();
+
+                if configuration.heads.iter().any(|(configured, _)| *configured == output) {
+                    resource.post_error(
+                        zwlr_output_configuration_v1::Error::AlreadyConfiguredHead,
+                        "head was already configured",
+                    );
+                    return;
+                }
+
+                let head = init.init(
+                    id,
+                    ConfigurationHeadData {
+                        output: output.clone(),
+                        changes: Mutex::new(HeadChanges::default()),
+                    },
+                );
+                configuration.heads.push((output, Some(head)));
+            }
+
+            zwlr_output_configuration_v1::Request::DisableHead { head } => {
+                let output = head.data::<Output>().unwrap().clone();
+                let mut configuration = data.state.lock().unwrap();
+
+                if configuration.heads.iter().any(|(configured, _)| *configured == output) {
+                    resource.post_error(
+                        zwlr_output_configuration_v1::Error::AlreadyConfiguredHead,
+                        "head was already configured",
+                    );
+                    return;
+                }
+
+                configuration.heads.push((output, None));
+            }
+
+            zwlr_output_configuration_v1::Request::Apply => state.output_configuration(resource, true),
+            zwlr_output_configuration_v1::Request::Test => state.output_configuration(resource, false),
+            zwlr_output_configuration_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationHeadV1, ConfigurationHeadData> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrOutputConfigurationHeadV1,
+        request: zwlr_output_configuration_head_v1::Request,
+        data: &ConfigurationHeadData,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        use zwlr_output_configuration_head_v1::Error;
+
+        let mut changes = data.changes.lock().unwrap();
+
+        macro_rules! set_once {
+            ($field:expr, $value:expr) => {
+                if $field.is_some() {
+                    resource.post_error(Error::AlreadySet, "property was already set");
+                    return;
+                }
+
+                $field = Some($value);
+            };
+        }
+
+        match request {
+            zwlr_output_configuration_head_v1::Request::SetMode { mode } => {
+                let mode_data = mode.data::<ModeData>().unwrap();
+
+                if mode_data.output != data.output {
+                    resource.post_error(Error::InvalidMode, "mode belongs to another head");
+                    return;
+                }
+
+                set_once!(changes.mode, mode_data.mode);
+            }
+
+            zwlr_output_configuration_head_v1::Request::SetCustomMode { width, height, refresh } => {
+                if width <= 0 || height <= 0 || refresh < 0 {
+                    resource.post_error(Error::InvalidCustomMode, "invalid custom mode");
+                    return;
+                }
+
+                let refresh = if refresh == 0 { DEFAULT_REFRESH } else { refresh };
+                set_once!(
+                    changes.mode,
+                    Mode {
+                        size: (width, height).into(),
+                        refresh,
+                    }
+                );
+            }
+
+            zwlr_output_configuration_head_v1::Request::SetPosition { x, y } => {
+                set_once!(changes.position, (x, y).into());
+            }
+
+            zwlr_output_configuration_head_v1::Request::SetTransform { transform } => {
+                let WEnum::Value(transform) = transform else {
+                    resource.post_error(Error::InvalidTransform, "invalid transform");
+                    return;
+                };
+
+                set_once!(changes.transform, transform.into());
+            }
+
+            zwlr_output_configuration_head_v1::Request::SetScale { scale } => {
+                if !scale.is_finite() || scale <= 0.0 {
+                    resource.post_error(Error::InvalidScale, "scale must be positive");
+                    return;
+                }
+
+                set_once!(changes.scale, scale);
+            }
+
+            zwlr_output_configuration_head_v1::Request::SetAdaptiveSync { state } => {
+                let enabled = match state {
+                    WEnum::Value(zwlr_output_head_v1::AdaptiveSyncState::Enabled) => true,
+                    WEnum::Value(zwlr_output_head_v1::AdaptiveSyncState::Disabled) => false,
+                    _ => {
+                        resource.post_error(Error::InvalidAdaptiveSyncState, "invalid adaptive sync state");
+                        return;
+                    }
+                };
+
+                set_once!(changes.adaptive_sync, enabled);
+            }
+
+            _ => unreachable!(),
+        }
+    }
+}
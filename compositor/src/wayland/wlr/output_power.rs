@@ -0,0 +1,214 @@
+//! Output power management and the `wlr-output-power-management-unstable-v1` protocol.
+//!
+//! An output is on unless it was turned off explicitly, by a client of this protocol, the wm or IPC, or every
+//! output is blanked because the user is idle (see [`idle`](crate::idle)). Turning an output off asks the backend
+//! to power down the display using [`Backend::set_output_power`](crate::backend::Backend::set_output_power).
+//! Nothing is presented to an output which is off and surfaces only visible on the output are throttled.
+//!
+//! The protocol is visible to clients which may manage outputs.
+
+use smithay::{
+    output::Output,
+    reexports::wayland_protocols_wlr::output_power_management::v1::server::{
+        zwlr_output_power_manager_v1::{self, ZwlrOutputPowerManagerV1},
+        zwlr_output_power_v1::{self, ZwlrOutputPowerV1},
+    },
+};
+use wayland_server::{
+    backend::ClientId, Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+};
+
+use crate::{Aerugo, ClientData, PrivilegedGlobals};
+
+/// The power state of the outputs.
+#[derive(Debug, Default)]
+pub struct OutputPowerState {
+    /// Outputs which were turned off explicitly.
+    off: Vec<Output>,
+
+    /// Whether every output is blanked because the user is idle.
+    blanked: bool,
+
+    /// Power objects of clients and the outputs they control.
+    instances: Vec<(ZwlrOutputPowerV1, Output)>,
+}
+
+impl OutputPowerState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the output is on.
+    pub fn is_on(&self, output: &Output) -> bool {
+        !self.blanked && !self.off.contains(output)
+    }
+
+    /// Forget an output which was removed, telling clients controlling the output the output is gone.
+    pub fn remove_output(&mut self, output: &Output) {
+        self.off.retain(|off| off != output);
+        self.instances.retain(|(power, power_output)| {
+            if power_output != output {
+                return true;
+            }
+
+            power.failed();
+            false
+        });
+    }
+}
+
+impl Aerugo {
+    /// Turn an output on or off.
+    pub fn set_output_power(&mut self, output: &Output, on: bool) {
+        self.update_output_power(|state| {
+            state.off.retain(|off| off != output);
+
+            if !on {
+                state.off.push(output.clone());
+            }
+        });
+    }
+
+    /// Blank or unblank every output because the user became idle or active.
+    ///
+    /// Unblanking does not turn on outputs which were turned off explicitly.
+    pub fn set_outputs_blanked(&mut self, blanked: bool) {
+        self.update_output_power(|state| state.blanked = blanked);
+    }
+
+    /// Change the power state and apply the outputs whose power state changed.
+    fn update_output_power(&mut self, f: impl FnOnce(&mut OutputPowerState)) {
+        let previous = self
+            .outputs
+            .iter()
+            .map(|(output, _)| self.output_power.is_on(output))
+            .collect::<Vec<_>>();
+
+        f(&mut self.output_power);
+
+        for ((output, _), was_on) in self.outputs.iter().zip(previous) {
+            let on = self.output_power.is_on(output);
+
+            if on == was_on {
+                continue;
+            }
+
+            tracing::info!(output = output.name(), on, "Output power changed");
+            self.backend.set_output_power(output, on);
+
+            for (power, _) in self
+                .output_power
+                .instances
+                .iter()
+                .filter(|(_, power_output)| power_output == output)
+            {
+                power.mode(power_mode(on));
+            }
+        }
+    }
+}
+
+fn power_mode(on: bool) -> zwlr_output_power_v1::Mode {
+    if on {
+        zwlr_output_power_v1::Mode::On
+    } else {
+        zwlr_output_power_v1::Mode::Off
+    }
+}
+
+impl GlobalDispatch<ZwlrOutputPowerManagerV1, ()> for Aerugo {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrOutputPowerManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        init.init(resource, ());
+    }
+
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        ClientData::get_data(&client)
+            .map(|data| data.is_visible(PrivilegedGlobals::OUTPUT_MANAGEMENT))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<ZwlrOutputPowerManagerV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrOutputPowerManagerV1,
+        request: zwlr_output_power_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_output_power_manager_v1::Request::GetOutputPower { id, output } => {
+                let output = Output::from_resource(&output)
+                    .filter(|output| state.outputs.iter().any(|(connected, _)| connected == output));
+                let power = init.init(id, ());
+
+                let Some(output) = output else {
+                    power.failed();
+                    return;
+                };
+
+                power.mode(power_mode(state.output_power.is_on(&output)));
+                state.output_power.instances.push((power, output));
+            }
+
+            zwlr_output_power_manager_v1::Request::Destroy => (),
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputPowerV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrOutputPowerV1,
+        request: zwlr_output_power_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_output_power_v1::Request::SetMode { mode } => {
+                let on = match mode {
+                    WEnum::Value(zwlr_output_power_v1::Mode::On) => true,
+                    WEnum::Value(zwlr_output_power_v1::Mode::Off) => false,
+                    _ => {
+                        resource.post_error(zwlr_output_power_v1::Error::InvalidMode, "unknown power mode");
+                        return;
+                    }
+                };
+
+                // Power objects of removed outputs already failed.
+                let Some(output) = state
+                    .output_power
+                    .instances
+                    .iter()
+                    .find(|(power, _)| power == resource)
+                    .map(|(_, output)| output.clone())
+                else {
+                    return;
+                };
+
+                state.set_output_power(&output, on);
+            }
+
+            zwlr_output_power_v1::Request::Destroy => (),
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &ZwlrOutputPowerV1, _data: &()) {
+        state.output_power.instances.retain(|(power, _)| power != resource);
+    }
+}
@@ -0,0 +1,395 @@
+//! Implementation of the `wlr-screencopy-unstable-v1` protocol.
+//!
+//! Captures are requested by clients against an output. A capture is not fulfilled immediately, instead the
+//! capture is queued until the backend renders the next frame for the output. After the frame has been rendered,
+//! the backend will copy the framebuffer into the client's buffer using [`submit_captures`].
+//!
+//! With `copy_with_damage` the damage reported is the damage of the captured frame relative to the frame
+//! rendered before, which is exact for clients copying every frame.
+//!
+//! Only `wl_shm` destination buffers are supported, since the backends can only read the framebuffer back into
+//! memory. No dmabuf destinations are advertised, and clients which attempt to copy into a dmabuf anyway will
+//! receive a `failed` event.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use smithay::{
+    backend::renderer::{ExportMem, TextureMapping},
+    output::Output,
+    reexports::wayland_protocols_wlr::screencopy::v1::server::{
+        zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
+        zwlr_screencopy_manager_v1::{self, ZwlrScreencopyManagerV1},
+    },
+    utils::{Logical, Physical, Rectangle},
+    wayland::shm,
+};
+use wayland_server::{
+    backend::ClientId,
+    protocol::{wl_buffer::WlBuffer, wl_shm},
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+};
+
+use crate::{Aerugo, ClientData, PrivilegedGlobals};
+
+/// Bytes per pixel of the buffers captures are written into.
+///
+/// Only 32-bit formats are advertised to clients.
+const BYTES_PER_PIXEL: i32 = 4;
+
+/// State of the screencopy protocol.
+#[derive(Debug, Default)]
+pub struct ScreencopyState {
+    /// Captures waiting for the next frame of an output to be rendered.
+    pending: Vec<PendingCapture>,
+}
+
+impl ScreencopyState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take all the captures which are waiting for a frame to be rendered on the specified output.
+    pub fn take_pending(&mut self, output: &Output) -> Vec<PendingCapture> {
+        let (captures, pending) = self.pending.drain(..).partition(|capture| capture.output == *output);
+        self.pending = pending;
+        captures
+    }
+
+    /// Forget captures whose frame was destroyed, for example because the client disconnected.
+    pub fn remove_dead_captures(&mut self) {
+        self.pending.retain(|capture| capture.frame.is_alive());
+    }
+
+    /// Fail every capture which would be copied into a buffer the client destroyed.
+    pub fn buffer_destroyed(&mut self, buffer: &WlBuffer) {
+        let (failed, pending) = self
+            .pending
+            .drain(..)
+            .partition::<Vec<_>, _>(|capture| capture.buffer == *buffer);
+        self.pending = pending;
+
+        for capture in failed {
+            capture.frame.failed();
+        }
+    }
+
+    /// Fail every capture waiting on an output which was removed.
+    pub fn remove_output(&mut self, output: &Output) {
+        for capture in self.take_pending(output) {
+            capture.frame.failed();
+        }
+    }
+
+    /// Whether any captures are waiting on the specified output.
+    ///
+    /// Backends may use this to schedule a frame for an output which would otherwise be idle.
+    pub fn has_pending(&self, output: &Output) -> bool {
+        self.pending.iter().any(|capture| capture.output == *output)
+    }
+}
+
+/// A capture which is waiting for the next frame of an output.
+#[derive(Debug)]
+pub struct PendingCapture {
+    frame: ZwlrScreencopyFrameV1,
+    output: Output,
+    buffer: WlBuffer,
+    region: Rectangle<i32, Physical>,
+    with_damage: bool,
+}
+
+/// User data associated with a `zwlr_screencopy_frame_v1`.
+#[derive(Debug)]
+pub struct ScreencopyFrameData {
+    /// The output being captured.
+    ///
+    /// If this is [`None`] the frame has already failed.
+    output: Option<Output>,
+
+    /// The region of the output being captured.
+    region: Rectangle<i32, Physical>,
+
+    /// Whether the frame has been used for a copy.
+    ///
+    /// A frame may only be used for a single copy.
+    used: AtomicBool,
+}
+
+/// Fail captures which could not be fulfilled, for example because the output could not be rendered.
+pub fn fail_captures(captures: Vec<PendingCapture>) {
+    for capture in captures {
+        capture.frame.failed();
+    }
+}
+
+/// Copy the contents of the currently bound framebuffer into each capture's buffer.
+///
+/// `damage` is the damage of the rendered frame relative to the previous frame of the output.
+///
+/// This must be called after the frame has finished rendering but before the renderer is unbound from the
+/// framebuffer of the output.
+pub fn submit_captures<R: ExportMem>(
+    renderer: &mut R,
+    captures: Vec<PendingCapture>,
+    damage: &[Rectangle<i32, Physical>],
+) {
+    if captures.is_empty() {
+        return;
+    }
+
+    let time = rustix::time::clock_gettime(rustix::time::ClockId::Monotonic);
+
+    for capture in captures {
+        if !capture.frame.is_alive() {
+            continue;
+        }
+
+        match copy_to_shm(renderer, &capture) {
+            Ok(()) => {
+                capture.frame.flags(zwlr_screencopy_frame_v1::Flags::empty());
+
+                if capture.with_damage {
+                    // Damage is relative to the captured region.
+                    for damage in damage.iter().filter_map(|damage| damage.intersection(capture.region)) {
+                        let loc = damage.loc - capture.region.loc;
+                        capture
+                            .frame
+                            .damage(loc.x as u32, loc.y as u32, damage.size.w as u32, damage.size.h as u32);
+                    }
+                }
+
+                let tv_sec = time.tv_sec as u64;
+                capture
+                    .frame
+                    .ready((tv_sec >> 32) as u32, tv_sec as u32, time.tv_nsec as u32);
+            }
+
+            Err(err) => {
+                tracing::debug!(%err, "Failed to copy output contents for screencopy");
+                capture.frame.failed();
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum CopyError {
+    #[error("failed to read the framebuffer")]
+    Export,
+
+    #[error("buffer is not a wl_shm buffer")]
+    NotShm,
+}
+
+fn copy_to_shm<R: ExportMem>(renderer: &mut R, capture: &PendingCapture) -> Result<(), CopyError> {
+    let region = Rectangle::from_loc_and_size(
+        (capture.region.loc.x, capture.region.loc.y),
+        (capture.region.size.w, capture.region.size.h),
+    );
+    let mapping = renderer.copy_framebuffer(region).map_err(|_| CopyError::Export)?;
+    let flipped = mapping.flipped();
+    let pixels = renderer.map_texture(&mapping).map_err(|_| CopyError::Export)?;
+
+    let src_stride = (capture.region.size.w * BYTES_PER_PIXEL) as usize;
+    let height = capture.region.size.h as usize;
+
+    shm::with_buffer_contents_mut(&capture.buffer, |ptr, len, data| {
+        let dst_stride = data.stride as usize;
+        let dst = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
+        let dst = &mut dst[data.offset as usize..];
+
+        for (row, dst_row) in dst.chunks_mut(dst_stride).take(height).enumerate() {
+            // A flipped mapping holds the rows bottom to top.
+            let row = if flipped { height - 1 - row } else { row };
+            let Some(src_row) = pixels.get(row * src_stride..(row + 1) * src_stride) else {
+                continue;
+            };
+
+            // The renderer exports pixels as RGBA bytes, while wl_shm ARGB8888 is BGRA in memory.
+            for (src, dst) in src_row.chunks_exact(4).zip(dst_row.chunks_exact_mut(4)) {
+                dst.copy_from_slice(&[src[2], src[1], src[0], src[3]]);
+            }
+        }
+    })
+    .map_err(|_| CopyError::NotShm)
+}
+
+impl GlobalDispatch<ZwlrScreencopyManagerV1, ()> for Aerugo {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrScreencopyManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        init.init(resource, ());
+    }
+
+    fn can_view(client: Client, _global_data: &()) -> bool {
+        ClientData::get_data(&client)
+            .map(|data| data.is_visible(PrivilegedGlobals::SCREENCOPY))
+            .unwrap_or(false)
+    }
+}
+
+impl Dispatch<ZwlrScreencopyManagerV1, ()> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZwlrScreencopyManagerV1,
+        request: zwlr_screencopy_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        let (frame, output, region) = match request {
+            zwlr_screencopy_manager_v1::Request::CaptureOutput {
+                frame,
+                overlay_cursor: _,
+                output,
+            } => {
+                let output = Output::from_resource(&output);
+                let region = output.as_ref().and_then(output_region);
+                (frame, output, region)
+            }
+
+            zwlr_screencopy_manager_v1::Request::CaptureOutputRegion {
+                frame,
+                overlay_cursor: _,
+                output,
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let output = Output::from_resource(&output);
+                let region = output.as_ref().and_then(|output| {
+                    let scale = output.current_scale().fractional_scale();
+                    let requested = Rectangle::<i32, Logical>::from_loc_and_size((x, y), (width, height))
+                        .to_f64()
+                        .to_physical(scale)
+                        .to_i32_round();
+
+                    output_region(output).and_then(|full| full.intersection(requested))
+                });
+
+                (frame, output, region)
+            }
+
+            zwlr_screencopy_manager_v1::Request::Destroy => return,
+
+            _ => unreachable!(),
+        };
+
+        // The cursor is not yet rendered by any backend, so overlay_cursor is ignored.
+        let (output, region) = match (output, region) {
+            (Some(output), Some(region)) if !region.is_empty() => (Some(output), region),
+            _ => (None, Rectangle::default()),
+        };
+
+        let frame = init.init(
+            frame,
+            ScreencopyFrameData {
+                output: output.clone(),
+                region,
+                used: AtomicBool::new(false),
+            },
+        );
+
+        if output.is_none() {
+            frame.failed();
+            return;
+        }
+
+        frame.buffer(
+            wl_shm::Format::Argb8888,
+            region.size.w as u32,
+            region.size.h as u32,
+            (region.size.w * BYTES_PER_PIXEL) as u32,
+        );
+
+        // Only wl_shm destinations are supported, so no linux_dmabuf event is sent.
+        if frame.version() >= 3 {
+            frame.buffer_done();
+        }
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, ScreencopyFrameData> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &ZwlrScreencopyFrameV1,
+        request: zwlr_screencopy_frame_v1::Request,
+        data: &ScreencopyFrameData,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        let (buffer, with_damage) = match request {
+            zwlr_screencopy_frame_v1::Request::Copy { buffer } => (buffer, false),
+            zwlr_screencopy_frame_v1::Request::CopyWithDamage { buffer } => (buffer, true),
+            zwlr_screencopy_frame_v1::Request::Destroy => return,
+            _ => unreachable!(),
+        };
+
+        if data.used.swap(true, Ordering::SeqCst) {
+            resource.post_error(
+                zwlr_screencopy_frame_v1::Error::AlreadyUsed,
+                "frame was already used for a copy",
+            );
+            return;
+        }
+
+        let Some(output) = data.output.clone() else {
+            // The frame has already failed.
+            return;
+        };
+
+        let valid = shm::with_buffer_contents(&buffer, |_, _, buffer| {
+            buffer.format == wl_shm::Format::Argb8888
+                && buffer.width == data.region.size.w
+                && buffer.height == data.region.size.h
+                && buffer.stride == data.region.size.w * BYTES_PER_PIXEL
+        });
+
+        match valid {
+            Ok(true) => {}
+            Ok(false) => {
+                resource.post_error(
+                    zwlr_screencopy_frame_v1::Error::InvalidBuffer,
+                    "buffer does not match the advertised buffer parameters",
+                );
+                return;
+            }
+
+            // Not a shm buffer.
+            Err(_) => {
+                resource.failed();
+                return;
+            }
+        }
+
+        state.screencopy.pending.push(PendingCapture {
+            frame: resource.clone(),
+            output,
+            buffer,
+            region: data.region,
+            with_damage,
+        });
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &ZwlrScreencopyFrameV1, _data: &ScreencopyFrameData) {
+        state
+            .screencopy
+            .pending
+            .retain(|capture| capture.frame.id() != resource.id());
+    }
+}
+
+/// The region of the output in physical coordinates.
+fn output_region(output: &Output) -> Option<Rectangle<i32, Physical>> {
+    let mode = output.current_mode()?;
+    let size = output.current_transform().transform_size(mode.size);
+    Some(Rectangle::from_loc_and_size((0, 0), size))
+}
@@ -0,0 +1,679 @@
+//! Implementation of the `linux-drm-syncobj-v1` protocol.
+//!
+//! With explicit synchronization a client attaches a buffer together with two points on DRM syncobj timelines.
+//! The acquire point is signaled by the client once rendering to the buffer finished, and the release point is
+//! signaled by the compositor once the compositor no longer reads from the buffer.
+//!
+//! A commit with an acquire point which is not signaled yet is blocked until the point is signaled. The drm
+//! crate cannot ask the kernel to notify us when a point is signaled, so blocked acquire points are polled,
+//! starting at [`ACQUIRE_POLL_INTERVAL`] and backing off up to [`ACQUIRE_POLL_MAX_INTERVAL`].
+//!
+//! A buffer is no longer used once it is replaced or detached, or when the surface is destroyed. A frame which
+//! samples the buffer may still be rendering at that point, so the release point waits on a
+//! [`Fence::Presentation`] of the next frame and is signaled once the backend reports that the frame was
+//! presented, see [`DrmSyncobjState::frame_submitted`] and [`DrmSyncobjState::frame_presented`].
+//!
+//! The global is only advertised if the backend provides a DRM device which supports timeline syncobjs. Clients
+//! fall back to implicit synchronization otherwise.
+
+use std::{
+    io, mem,
+    os::fd::{AsFd, BorrowedFd, OwnedFd},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use calloop::timer::{TimeoutAction, Timer};
+use drm::{control::syncobj, DriverCapability};
+use slotmap::SecondaryMap;
+use smithay::{
+    backend::renderer::utils::RendererSurfaceStateUserData,
+    wayland::{
+        compositor::{
+            self, Blocker, BlockerState, BufferAssignment, Cacheable, CompositorHandler, SurfaceAttributes,
+            TraversalAction,
+        },
+        dmabuf,
+    },
+};
+use wayland_server::{
+    backend::ClientId, protocol::wl_surface::WlSurface, Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New,
+    Resource,
+};
+
+use crate::{
+    transaction::{DependencyTracker, Fence, Id},
+    Aerugo,
+};
+
+pub use self::generated::wp_linux_drm_syncobj_manager_v1::WpLinuxDrmSyncobjManagerV1;
+use self::generated::{
+    wp_linux_drm_syncobj_manager_v1,
+    wp_linux_drm_syncobj_surface_v1::{self, WpLinuxDrmSyncobjSurfaceV1},
+    wp_linux_drm_syncobj_timeline_v1::{self, WpLinuxDrmSyncobjTimelineV1},
+};
+
+// linux-drm-syncobj-v1 is not available in the version of wayland-protocols we use, so we need to generate it.
+#[allow(non_upper_case_globals, non_camel_case_types)]
+mod generated {
+    use smithay::reexports::wayland_server;
+    use smithay::reexports::wayland_server::protocol::*;
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_server::backend as wayland_backend;
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("../protocols/linux-drm-syncobj-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("../protocols/linux-drm-syncobj-v1.xml");
+}
+
+/// How soon an acquire point which blocks a commit is checked first.
+pub const ACQUIRE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The longest time between two checks of an acquire point which blocks a commit.
+pub const ACQUIRE_POLL_MAX_INTERVAL: Duration = Duration::from_millis(8);
+
+/// The interval until the next check of an acquire point which is still not signaled.
+fn next_poll_interval(interval: Duration) -> Duration {
+    (interval * 2).min(ACQUIRE_POLL_MAX_INTERVAL)
+}
+
+/// State of the linux-drm-syncobj protocol.
+#[derive(Debug)]
+pub struct DrmSyncobjState {
+    device: Arc<SyncobjDevice>,
+
+    /// Release points of buffers which are no longer used, in the order the buffers stopped being used.
+    released: Arc<Mutex<Vec<SyncPoint>>>,
+
+    /// Release points waiting on the presentation of a frame.
+    tracker: DependencyTracker,
+
+    /// The fence of the last submitted frame, if release points wait on the frame.
+    presentation: Option<Id>,
+
+    /// The release point of each node in the tracker which is not a fence.
+    in_flight: SecondaryMap<Id, SyncPoint>,
+}
+
+impl DrmSyncobjState {
+    /// Create the state if the DRM device supports timeline syncobjs.
+    pub fn new(device: OwnedFd) -> Option<Self> {
+        let device = SyncobjDevice(device);
+
+        match drm::Device::get_driver_capability(&device, DriverCapability::TimelineSyncObj) {
+            Ok(supported) if supported != 0 => Some(Self {
+                device: Arc::new(device),
+                released: Arc::default(),
+                tracker: DependencyTracker::new(),
+                presentation: None,
+                in_flight: SecondaryMap::new(),
+            }),
+            Ok(_) => {
+                tracing::info!("DRM device does not support timeline syncobjs, explicit sync is not available");
+                None
+            }
+            Err(err) => {
+                tracing::warn!(%err, "Failed to query timeline syncobj support");
+                None
+            }
+        }
+    }
+
+    /// The backend submitted a frame.
+    ///
+    /// Buffers which stopped being used until now are not sampled by any later frame, so their release points
+    /// are signaled once this frame was presented.
+    pub fn frame_submitted(&mut self) {
+        let released = mem::take(&mut *self.released.lock().unwrap());
+
+        if released.is_empty() {
+            return;
+        }
+
+        let tracker = &mut self.tracker;
+        let fence = *self
+            .presentation
+            .get_or_insert_with(|| tracker.create_fence(Fence::Presentation));
+
+        for point in released {
+            let id = self.tracker.create_id();
+            self.tracker.add_dependency(id, fence).unwrap();
+            self.in_flight.insert(id, point);
+        }
+    }
+
+    /// The backend presented the last submitted frame, so the renderer finished reading from the buffers.
+    pub fn frame_presented(&mut self) {
+        let Some(fence) = self.presentation.take() else {
+            return;
+        };
+
+        self.tracker.signal(fence).unwrap();
+
+        for id in self.tracker.drain_finished() {
+            if let Some(point) = self.in_flight.remove(id) {
+                point.signal();
+            }
+        }
+    }
+}
+
+/// The DRM device timelines are imported into.
+#[derive(Debug)]
+struct SyncobjDevice(OwnedFd);
+
+impl AsFd for SyncobjDevice {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl drm::Device for SyncobjDevice {}
+impl drm::control::Device for SyncobjDevice {}
+
+/// A timeline imported by a client.
+#[derive(Debug)]
+pub struct DrmTimeline {
+    device: Arc<SyncobjDevice>,
+    handle: syncobj::Handle,
+}
+
+impl DrmTimeline {
+    fn import(device: Arc<SyncobjDevice>, fd: OwnedFd) -> io::Result<Self> {
+        let handle = drm::control::Device::fd_to_syncobj(&*device, fd.as_fd(), false)?;
+        Ok(Self { device, handle })
+    }
+}
+
+impl Drop for DrmTimeline {
+    fn drop(&mut self) {
+        if let Err(err) = drm::control::Device::destroy_syncobj(&*self.device, self.handle) {
+            tracing::warn!(%err, "Failed to destroy syncobj");
+        }
+    }
+}
+
+/// A point on a timeline.
+#[derive(Debug, Clone)]
+struct SyncPoint {
+    timeline: Arc<DrmTimeline>,
+    point: u64,
+}
+
+impl SyncPoint {
+    fn new(timeline: &WpLinuxDrmSyncobjTimelineV1, point_hi: u32, point_lo: u32) -> Self {
+        Self {
+            timeline: timeline.data::<Arc<DrmTimeline>>().unwrap().clone(),
+            point: (u64::from(point_hi) << 32) | u64::from(point_lo),
+        }
+    }
+
+    fn is_signaled(&self) -> bool {
+        let device = &*self.timeline.device;
+        // A timeout of zero checks the point without waiting, failing with ETIME if the point is not signaled.
+        drm::control::Device::syncobj_timeline_wait(
+            device,
+            &[self.timeline.handle],
+            &[self.point],
+            0,
+            true,
+            false,
+            false,
+        )
+        .is_ok()
+    }
+
+    fn signal(&self) {
+        let device = &*self.timeline.device;
+
+        if let Err(err) = drm::control::Device::syncobj_timeline_signal(device, &[self.timeline.handle], &[self.point])
+        {
+            tracing::warn!(%err, "Failed to signal release point");
+        }
+    }
+
+    fn key(&self) -> (*const DrmTimeline, u64) {
+        (Arc::as_ptr(&self.timeline), self.point)
+    }
+}
+
+/// A release point, which is queued to be signaled when dropped.
+#[derive(Debug)]
+struct ReleasePoint {
+    point: SyncPoint,
+    released: Arc<Mutex<Vec<SyncPoint>>>,
+}
+
+impl Drop for ReleasePoint {
+    fn drop(&mut self) {
+        self.released.lock().unwrap().push(self.point.clone());
+    }
+}
+
+/// The double buffered timeline points of a surface.
+#[derive(Debug, Default)]
+struct SyncobjCachedState {
+    acquire: Option<SyncPoint>,
+    release: Option<ReleasePoint>,
+}
+
+impl Cacheable for SyncobjCachedState {
+    fn commit(&mut self, _dh: &DisplayHandle) -> Self {
+        Self {
+            acquire: self.acquire.take(),
+            release: self.release.take(),
+        }
+    }
+
+    fn merge_into(self, into: &mut Self, _dh: &DisplayHandle) {
+        // Points are only set together with a new buffer, so the previous buffer is no longer used and the
+        // replaced release point is queued as it is dropped.
+        if self.release.is_some() {
+            *into = self;
+        }
+    }
+}
+
+/// The syncobj surface of a surface, if one exists.
+#[derive(Debug, Default)]
+struct SyncobjSurfaceData(Mutex<Option<WpLinuxDrmSyncobjSurfaceV1>>);
+
+/// Blocks a commit until the acquire point is signaled.
+struct AcquireBlocker(SyncPoint);
+
+impl Blocker for AcquireBlocker {
+    fn state(&self) -> BlockerState {
+        if self.0.is_signaled() {
+            BlockerState::Released
+        } else {
+            BlockerState::Pending
+        }
+    }
+}
+
+/// What buffer a commit attaches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PendingBuffer {
+    /// The buffer is unchanged.
+    None,
+    Removed,
+    Dmabuf,
+    /// A buffer which does not support explicit synchronization, such as a shm buffer.
+    Unsupported,
+}
+
+/// Check the timeline points of a commit are valid.
+///
+/// Both points must be set if and only if a buffer is attached. If both points are on the same timeline the
+/// acquire point must come before the release point.
+fn check_points<T: PartialEq>(
+    buffer: PendingBuffer,
+    acquire: Option<(T, u64)>,
+    release: Option<(T, u64)>,
+) -> Result<(), (wp_linux_drm_syncobj_surface_v1::Error, &'static str)> {
+    use wp_linux_drm_syncobj_surface_v1::Error;
+
+    match buffer {
+        PendingBuffer::None | PendingBuffer::Removed => {
+            if acquire.is_some() || release.is_some() {
+                return Err((Error::NoBuffer, "timeline points were set without attaching a buffer"));
+            }
+
+            Ok(())
+        }
+
+        PendingBuffer::Unsupported => Err((
+            Error::UnsupportedBuffer,
+            "the buffer does not support explicit synchronization",
+        )),
+
+        PendingBuffer::Dmabuf => match (acquire, release) {
+            (None, _) => Err((Error::NoAcquirePoint, "no acquire point was set")),
+            (_, None) => Err((Error::NoReleasePoint, "no release point was set")),
+            (Some((acquire_timeline, acquire)), Some((release_timeline, release)))
+                if acquire_timeline == release_timeline && acquire >= release =>
+            {
+                Err((
+                    Error::ConflictingPoints,
+                    "the acquire point must come before the release point on the same timeline",
+                ))
+            }
+            _ => Ok(()),
+        },
+    }
+}
+
+impl Aerugo {
+    /// Validate the timeline points of a commit and block the commit until the acquire point is signaled.
+    fn syncobj_pre_commit(&mut self, surface: &WlSurface) {
+        let acquire = compositor::with_states(surface, |states| {
+            let syncobj_surface = states
+                .data_map
+                .get::<SyncobjSurfaceData>()
+                .and_then(|data| data.0.lock().unwrap().clone())?;
+
+            let buffer = match &states.cached_state.pending::<SurfaceAttributes>().buffer {
+                None => PendingBuffer::None,
+                Some(BufferAssignment::Removed) => PendingBuffer::Removed,
+                Some(BufferAssignment::NewBuffer(buffer)) if dmabuf::get_dmabuf(buffer).is_ok() => {
+                    PendingBuffer::Dmabuf
+                }
+                Some(BufferAssignment::NewBuffer(_)) => PendingBuffer::Unsupported,
+            };
+
+            let points = states.cached_state.pending::<SyncobjCachedState>();
+            let acquire = points.acquire.as_ref().map(SyncPoint::key);
+            let release = points.release.as_ref().map(|release| release.point.key());
+
+            if let Err((error, message)) = check_points(buffer, acquire, release) {
+                syncobj_surface.post_error(error, message);
+                return None;
+            }
+
+            points.acquire.clone()
+        });
+
+        let Some(acquire) = acquire.filter(|acquire| !acquire.is_signaled()) else {
+            return;
+        };
+
+        let Some(client) = surface.client() else {
+            return;
+        };
+
+        compositor::add_blocker(surface, AcquireBlocker(acquire.clone()));
+
+        // Most points are signaled soon after the commit, so check often first and back off for slow clients.
+        let mut interval = ACQUIRE_POLL_INTERVAL;
+        let timer = Timer::from_duration(interval);
+        let result = self.r#loop.insert_source(timer, move |_, _, aerugo| {
+            if !acquire.is_signaled() {
+                interval = next_poll_interval(interval);
+                return TimeoutAction::ToDuration(interval);
+            }
+
+            let display = aerugo.comp.display.clone();
+            aerugo
+                .comp
+                .client_compositor_state(&client)
+                .blocker_cleared(&mut aerugo.comp, &display);
+            TimeoutAction::Drop
+        });
+
+        if let Err(err) = result {
+            tracing::error!(%err, "Failed to poll acquire point");
+        }
+    }
+
+    /// Queue the release points of buffers which were detached by a commit.
+    pub fn syncobj_commit(&mut self, surface: &WlSurface) {
+        compositor::with_surface_tree_downward(
+            surface,
+            (),
+            |_, _, &()| TraversalAction::DoChildren(()),
+            |_, states, &()| {
+                let detached = states
+                    .data_map
+                    .get::<RendererSurfaceStateUserData>()
+                    .map_or(true, |state| state.borrow().buffer().is_none());
+
+                if detached {
+                    states.cached_state.current::<SyncobjCachedState>().release = None;
+                }
+            },
+            |_, _, &()| true,
+        );
+    }
+}
+
+impl GlobalDispatch<WpLinuxDrmSyncobjManagerV1, ()> for Aerugo {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<WpLinuxDrmSyncobjManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        init.init(resource, ());
+    }
+}
+
+impl Dispatch<WpLinuxDrmSyncobjManagerV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &WpLinuxDrmSyncobjManagerV1,
+        request: wp_linux_drm_syncobj_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            wp_linux_drm_syncobj_manager_v1::Request::GetSurface { id, surface } => {
+                let (first, exists) = compositor::with_states(&surface, |states| {
+                    let first = states
+                        .data_map
+                        .insert_if_missing_threadsafe(SyncobjSurfaceData::default);
+                    let data = states.data_map.get::<SyncobjSurfaceData>().unwrap();
+                    (first, data.0.lock().unwrap().is_some())
+                });
+
+                if exists {
+                    resource.post_error(
+                        wp_linux_drm_syncobj_manager_v1::Error::SurfaceExists,
+                        "the surface already has a syncobj surface",
+                    );
+                    return;
+                }
+
+                let syncobj_surface = init.init(id, surface.clone());
+                compositor::with_states(&surface, |states| {
+                    *states.data_map.get::<SyncobjSurfaceData>().unwrap().0.lock().unwrap() = Some(syncobj_surface);
+                });
+
+                // The hook stays installed if the syncobj surface is destroyed, so only install it once.
+                if first {
+                    compositor::add_pre_commit_hook::<Self, _>(&surface, |state, _display, surface| {
+                        state.syncobj_pre_commit(surface);
+                    });
+                }
+            }
+
+            wp_linux_drm_syncobj_manager_v1::Request::ImportTimeline { id, fd } => {
+                // The global only exists if the state exists.
+                let device = state.drm_syncobj.as_ref().unwrap().device.clone();
+
+                match DrmTimeline::import(device, fd) {
+                    Ok(timeline) => {
+                        init.init(id, Arc::new(timeline));
+                    }
+
+                    Err(err) => {
+                        resource.post_error(
+                            wp_linux_drm_syncobj_manager_v1::Error::InvalidTimeline,
+                            format!("failed to import timeline: {err}"),
+                        );
+                    }
+                }
+            }
+
+            wp_linux_drm_syncobj_manager_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpLinuxDrmSyncobjTimelineV1, Arc<DrmTimeline>> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WpLinuxDrmSyncobjTimelineV1,
+        request: wp_linux_drm_syncobj_timeline_v1::Request,
+        _data: &Arc<DrmTimeline>,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        // in tree generated protocol
+        #[allow(unreachable_patterns)]
+        match request {
+            // Points which were set keep the timeline alive.
+            wp_linux_drm_syncobj_timeline_v1::Request::Destroy => {}
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<WpLinuxDrmSyncobjSurfaceV1, WlSurface> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &WpLinuxDrmSyncobjSurfaceV1,
+        request: wp_linux_drm_syncobj_surface_v1::Request,
+        surface: &WlSurface,
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        let point = match &request {
+            wp_linux_drm_syncobj_surface_v1::Request::SetAcquirePoint {
+                timeline,
+                point_hi,
+                point_lo,
+            }
+            | wp_linux_drm_syncobj_surface_v1::Request::SetReleasePoint {
+                timeline,
+                point_hi,
+                point_lo,
+            } => SyncPoint::new(timeline, *point_hi, *point_lo),
+
+            wp_linux_drm_syncobj_surface_v1::Request::Destroy => {
+                // Dispatch::destroyed handles cleanup
+                return;
+            }
+
+            _ => unreachable!(),
+        };
+
+        if !surface.is_alive() {
+            resource.post_error(
+                wp_linux_drm_syncobj_surface_v1::Error::NoSurface,
+                "the surface was destroyed",
+            );
+            return;
+        }
+
+        compositor::with_states(surface, |states| {
+            let mut points = states.cached_state.pending::<SyncobjCachedState>();
+
+            match request {
+                wp_linux_drm_syncobj_surface_v1::Request::SetAcquirePoint { .. } => points.acquire = Some(point),
+                _ => {
+                    points.release = Some(ReleasePoint {
+                        point,
+                        // The global only exists if the state exists.
+                        released: state.drm_syncobj.as_ref().unwrap().released.clone(),
+                    })
+                }
+            }
+        });
+    }
+
+    fn destroyed(_state: &mut Self, _client: ClientId, resource: &WpLinuxDrmSyncobjSurfaceV1, surface: &WlSurface) {
+        if !surface.is_alive() {
+            return;
+        }
+
+        compositor::with_states(surface, |states| {
+            if let Some(data) = states.data_map.get::<SyncobjSurfaceData>() {
+                let mut data = data.0.lock().unwrap();
+
+                if data.as_ref() == Some(resource) {
+                    *data = None;
+                }
+            }
+
+            // Points set since the last commit are discarded.
+            *states.cached_state.pending::<SyncobjCachedState>() = SyncobjCachedState::default();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        check_points, next_poll_interval, wp_linux_drm_syncobj_surface_v1::Error, PendingBuffer, ACQUIRE_POLL_INTERVAL,
+        ACQUIRE_POLL_MAX_INTERVAL,
+    };
+
+    fn error(result: Result<(), (Error, &'static str)>) -> Option<Error> {
+        result.err().map(|(error, _)| error)
+    }
+
+    #[test]
+    fn points_require_buffer() {
+        assert_eq!(error(check_points::<u32>(PendingBuffer::None, None, None)), None);
+        assert_eq!(
+            error(check_points(PendingBuffer::None, Some((0, 1)), None)),
+            Some(Error::NoBuffer)
+        );
+        assert_eq!(
+            error(check_points(PendingBuffer::Removed, None, Some((0, 1)))),
+            Some(Error::NoBuffer)
+        );
+    }
+
+    #[test]
+    fn buffer_requires_points() {
+        assert_eq!(
+            error(check_points(PendingBuffer::Dmabuf, None, Some((0, 1)))),
+            Some(Error::NoAcquirePoint)
+        );
+        assert_eq!(
+            error(check_points(PendingBuffer::Dmabuf, Some((0, 1)), None)),
+            Some(Error::NoReleasePoint)
+        );
+        assert_eq!(
+            error(check_points(PendingBuffer::Unsupported, Some((0, 1)), Some((0, 2)))),
+            Some(Error::UnsupportedBuffer)
+        );
+    }
+
+    #[test]
+    fn conflicting_points() {
+        assert_eq!(
+            error(check_points(PendingBuffer::Dmabuf, Some((0, 1)), Some((0, 2)))),
+            None
+        );
+        assert_eq!(
+            error(check_points(PendingBuffer::Dmabuf, Some((0, 2)), Some((0, 2)))),
+            Some(Error::ConflictingPoints)
+        );
+        // Points on different timelines do not conflict.
+        assert_eq!(
+            error(check_points(PendingBuffer::Dmabuf, Some((0, 2)), Some((1, 1)))),
+            None
+        );
+    }
+
+    #[test]
+    fn poll_backoff() {
+        let mut interval = ACQUIRE_POLL_INTERVAL;
+        interval = next_poll_interval(interval);
+        assert_eq!(interval, Duration::from_millis(2));
+
+        for _ in 0..10 {
+            interval = next_poll_interval(interval);
+        }
+
+        assert_eq!(interval, ACQUIRE_POLL_MAX_INTERVAL);
+    }
+}
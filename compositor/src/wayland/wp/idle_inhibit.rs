@@ -0,0 +1,91 @@
+//! Implementation of the `idle-inhibit-unstable-v1` protocol.
+//!
+//! An inhibitor keeps the outputs from being blanked while the surface of the inhibitor is visible. Visibility is
+//! checked every time a frame is presented, see [`idle`](crate::idle).
+
+use smithay::reexports::wayland_protocols::wp::idle_inhibit::zv1::server::{
+    zwp_idle_inhibit_manager_v1::{self, ZwpIdleInhibitManagerV1},
+    zwp_idle_inhibitor_v1::{self, ZwpIdleInhibitorV1},
+};
+use wayland_server::{
+    backend::ClientId, protocol::wl_surface::WlSurface, Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New,
+};
+
+use crate::Aerugo;
+
+/// State of the idle inhibit protocol.
+#[derive(Debug, Default)]
+pub struct IdleInhibitState {
+    inhibitors: Vec<(ZwpIdleInhibitorV1, WlSurface)>,
+}
+
+impl IdleInhibitState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the surface of any inhibitor is one of the visible surfaces.
+    pub fn is_inhibited(&self, visible: &[WlSurface]) -> bool {
+        self.inhibitors.iter().any(|(_, surface)| visible.contains(surface))
+    }
+}
+
+impl GlobalDispatch<ZwpIdleInhibitManagerV1, ()> for Aerugo {
+    fn bind(
+        _state: &mut Self,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpIdleInhibitManagerV1>,
+        _global_data: &(),
+        init: &mut DataInit<'_, Self>,
+    ) {
+        init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZwpIdleInhibitManagerV1, ()> for Aerugo {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &ZwpIdleInhibitManagerV1,
+        request: zwp_idle_inhibit_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwp_idle_inhibit_manager_v1::Request::CreateInhibitor { id, surface } => {
+                let inhibitor = init.init(id, ());
+                state.idle_inhibit.inhibitors.push((inhibitor, surface));
+            }
+
+            zwp_idle_inhibit_manager_v1::Request::Destroy => (),
+
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Dispatch<ZwpIdleInhibitorV1, ()> for Aerugo {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &ZwpIdleInhibitorV1,
+        request: zwp_idle_inhibitor_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwp_idle_inhibitor_v1::Request::Destroy => (),
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut Self, _client: ClientId, resource: &ZwpIdleInhibitorV1, _data: &()) {
+        state
+            .idle_inhibit
+            .inhibitors
+            .retain(|(inhibitor, _)| inhibitor != resource);
+    }
+}
@@ -0,0 +1,7 @@
+//! Implementations of protocols in the `wp` namespace
+
+pub mod drm_syncobj;
+pub mod idle_inhibit;
+mod primary_selection;
+mod single_pixel_buffer;
+mod viewporter;
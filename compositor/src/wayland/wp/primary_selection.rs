@@ -0,0 +1,28 @@
+use std::os::fd::OwnedFd;
+
+use smithay::{
+    input::Seat,
+    reexports::wayland_protocols::wp::primary_selection::zv1::server::zwp_primary_selection_source_v1::ZwpPrimarySelectionSourceV1,
+    wayland::primary_selection::{with_source_metadata, PrimarySelectionHandler, PrimarySelectionState},
+};
+
+use crate::{wayland::wlr::data_control::Target, Aerugo};
+
+impl PrimarySelectionHandler for Aerugo {
+    fn primary_selection_state(&self) -> &PrimarySelectionState {
+        &self.primary_selection
+    }
+
+    fn new_selection(&mut self, source: Option<ZwpPrimarySelectionSourceV1>, _seat: Seat<Self>) {
+        let mime_types = source.and_then(|source| with_source_metadata(&source, |data| data.mime_types.clone()).ok());
+        self.data_control
+            .new_selection(&self.display, Target::Primary, mime_types);
+    }
+
+    fn send_selection(&mut self, mime_type: String, fd: OwnedFd, _seat: Seat<Self>) {
+        // Server side selections are only set through data control.
+        self.data_control.send_selection(Target::Primary, mime_type, fd);
+    }
+}
+
+smithay::delegate_primary_selection!(Aerugo);
@@ -0,0 +1,10 @@
+//! The single pixel buffer protocol.
+//!
+//! Smithay creates the buffers and the renderer imports them as a texture of a single pixel, so a client can
+//! present a solid color, scaled to any size using a viewport, without allocating a real buffer.
+
+use smithay::delegate_single_pixel_buffer;
+
+use crate::Aerugo;
+
+delegate_single_pixel_buffer!(Aerugo);
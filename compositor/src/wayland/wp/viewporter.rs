@@ -0,0 +1,10 @@
+//! The viewporter protocol.
+//!
+//! Smithay applies the viewport of a surface to the surface view of the renderer surface state, which the scene
+//! uses to compute the source and destination of each surface.
+
+use smithay::delegate_viewporter;
+
+use crate::Aerugo;
+
+delegate_viewporter!(Aerugo);
@@ -0,0 +1,150 @@
+//! The xdg-activation protocol.
+//!
+//! A client may ask for a toplevel to be activated using a token, for example a launcher passing a token to the
+//! application it starts. To prevent focus stealing, a token is only valid if it was created using the serial
+//! of an input event the seat sent since the keyboard focus last changed, and is used within
+//! [`TOKEN_TIMEOUT`]. The wm is told whether the token was valid and decides whether to activate the toplevel.
+//!
+//! Tokens given to programs started by the display server are always valid, see [`launcher`](crate::launcher).
+
+use std::time::{Duration, Instant};
+
+use rustc_hash::FxHashMap;
+use smithay::{
+    delegate_xdg_activation,
+    input::Seat,
+    wayland::xdg_activation::{XdgActivationHandler, XdgActivationState, XdgActivationToken, XdgActivationTokenData},
+};
+use wayland_server::protocol::wl_surface::WlSurface;
+use wm_runtime::WmEvent;
+
+use crate::{launcher::Origin, shell::Shell, wm, Aerugo};
+
+/// How long a token stays valid after it was created.
+pub const TOKEN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tokens which passed validation when created.
+#[derive(Debug, Default)]
+pub struct ActivationTokens {
+    valid: FxHashMap<XdgActivationToken, Instant>,
+}
+
+impl ActivationTokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert(&mut self, token: XdgActivationToken, now: Instant) {
+        // Tokens which were never used are forgotten once expired.
+        self.valid
+            .retain(|_, created| now.duration_since(*created) < TOKEN_TIMEOUT);
+        self.valid.insert(token, now);
+    }
+
+    /// Use a token, returning whether the token is valid.
+    ///
+    /// A token may only be used once.
+    fn take(&mut self, token: &XdgActivationToken, now: Instant) -> bool {
+        self.valid
+            .remove(token)
+            .map_or(false, |created| now.duration_since(created) < TOKEN_TIMEOUT)
+    }
+}
+
+impl XdgActivationHandler for Aerugo {
+    fn activation_state(&mut self) -> &mut XdgActivationState {
+        &mut self.xdg_activation
+    }
+
+    fn token_created(&mut self, token: XdgActivationToken, data: XdgActivationTokenData) -> bool {
+        if self.validate_activation_token(&data) {
+            self.activation_tokens.insert(token, Instant::now());
+        }
+
+        // Invalid tokens are still handed out, the wm is told the token is invalid when it is used.
+        true
+    }
+
+    fn request_activation(
+        &mut self,
+        token: XdgActivationToken,
+        _token_data: XdgActivationTokenData,
+        surface: WlSurface,
+    ) {
+        let now = Instant::now();
+        let token_valid = self.activation_tokens.take(&token, now);
+        let launch = self.launcher.take(&token, now);
+
+        let Some(toplevel) = Shell::get_toplevel_id(&surface).and_then(wm::wm_toplevel_id) else {
+            tracing::debug!("Activation requested for a surface which is not a toplevel");
+            return;
+        };
+
+        if let Some(Origin::Wm(launch)) = launch {
+            self.wm.send(WmEvent::Launched { launch, toplevel });
+        }
+
+        self.wm.send(WmEvent::ActivationRequested { toplevel, token_valid });
+    }
+}
+
+impl Aerugo {
+    /// Create a token for a program started by the display server.
+    pub fn create_launch_token(&mut self) -> XdgActivationToken {
+        let (token, _) = self.xdg_activation.create_external_token(None);
+        let token = token.clone();
+        self.activation_tokens.insert(token.clone(), Instant::now());
+        token
+    }
+
+    /// Whether the token was created in response to recent user input.
+    fn validate_activation_token(&self, data: &XdgActivationTokenData) -> bool {
+        let Some((serial, seat)) = &data.serial else {
+            return false;
+        };
+
+        if Seat::<Self>::from_resource(seat).as_ref() != Some(&self.seat) {
+            return false;
+        }
+
+        // Input from before the keyboard focus last changed cannot be used to steal focus.
+        self.seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.last_enter())
+            .map_or(false, |last_enter| serial.is_no_older_than(&last_enter))
+    }
+}
+
+delegate_xdg_activation!(Aerugo);
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use smithay::wayland::xdg_activation::XdgActivationToken;
+
+    use super::{ActivationTokens, TOKEN_TIMEOUT};
+
+    #[test]
+    fn tokens_are_used_once() {
+        let mut tokens = ActivationTokens::new();
+        let token = XdgActivationToken::from(String::from("token"));
+        let now = Instant::now();
+
+        tokens.insert(token.clone(), now);
+        assert!(tokens.take(&token, now));
+        assert!(!tokens.take(&token, now));
+    }
+
+    #[test]
+    fn tokens_expire() {
+        let mut tokens = ActivationTokens::new();
+        let [expired, fresh] = ["expired", "fresh"].map(|token| XdgActivationToken::from(String::from(token)));
+        let now = Instant::now();
+
+        tokens.insert(expired.clone(), now);
+        tokens.insert(fresh.clone(), now + TOKEN_TIMEOUT);
+        assert!(!tokens.take(&expired, now + TOKEN_TIMEOUT));
+        assert!(tokens.take(&fresh, now + TOKEN_TIMEOUT));
+    }
+}
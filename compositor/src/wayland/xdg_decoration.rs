@@ -0,0 +1,84 @@
+//! The xdg-decoration protocol.
+//!
+//! The decoration mode a client prefers is forwarded to the wm, which decides whether the toplevel is decorated
+//! by the client or the server. The mode the wm chose is sent with the next configure of the toplevel.
+//!
+//! If the user forced a decoration mode with a window rule, the wm cannot change the mode, so the client is
+//! configured with the forced mode right away.
+
+use smithay::{
+    delegate_xdg_decoration,
+    reexports::wayland_protocols::xdg::decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode,
+    wayland::{
+        compositor,
+        shell::xdg::{decoration::XdgDecorationHandler, ToplevelSurface, XdgToplevelSurfaceData},
+    },
+};
+use wm_runtime::{ConfigureUpdate, DecorationMode, ToplevelUpdate, WmEvent};
+
+use crate::{rules, shell::Shell, wm, Aerugo};
+
+impl XdgDecorationHandler for Aerugo {
+    fn new_decoration(&mut self, toplevel: ToplevelSurface) {
+        // The client has no preference until a mode is requested.
+        self.decoration_preference(&toplevel, None);
+    }
+
+    fn request_mode(&mut self, toplevel: ToplevelSurface, mode: Mode) {
+        let mode = match mode {
+            Mode::ServerSide => DecorationMode::ServerSide,
+            _ => DecorationMode::ClientSide,
+        };
+
+        self.decoration_preference(&toplevel, Some(mode));
+    }
+
+    fn unset_mode(&mut self, toplevel: ToplevelSurface) {
+        self.decoration_preference(&toplevel, None);
+    }
+}
+
+impl Aerugo {
+    fn decoration_preference(&mut self, surface: &ToplevelSurface, preferred: Option<DecorationMode>) {
+        let (app_id, title, initial_configure_sent) = compositor::with_states(surface.wl_surface(), |states| {
+            let data = states.data_map.get::<XdgToplevelSurfaceData>().unwrap().lock().unwrap();
+            (data.app_id.clone(), data.title.clone(), data.initial_configure_sent)
+        });
+
+        let forced = rules::resolve(&self.window_rules, app_id.as_deref(), title.as_deref()).forced_mode();
+
+        if let Some(mode) = forced {
+            surface.with_pending_state(|state| {
+                state.decoration_mode = Some(match mode {
+                    DecorationMode::ServerSide => Mode::ServerSide,
+                    DecorationMode::ClientSide => Mode::ClientSide,
+                });
+            });
+
+            // Otherwise the mode is sent with the initial configure.
+            if initial_configure_sent {
+                surface.send_configure();
+            }
+
+            return;
+        }
+
+        // The wm does not know about the toplevel before the initial commit, so the preference is included when
+        // the toplevel is announced.
+        Shell::with_toplevel_requests(surface.wl_surface(), |requests| requests.decorations = Some(preferred));
+
+        let Some(toplevel) = Shell::get_toplevel_id(surface.wl_surface()).and_then(wm::wm_toplevel_id) else {
+            return;
+        };
+
+        self.wm.send(WmEvent::UpdateToplevel {
+            toplevel,
+            update: ToplevelUpdate {
+                preferred_decorations: ConfigureUpdate::Update(preferred),
+                ..Default::default()
+            },
+        });
+    }
+}
+
+delegate_xdg_decoration!(Aerugo);
@@ -1,13 +1,25 @@
 use smithay::{
-    reexports::wayland_protocols::xdg::shell::server::xdg_toplevel,
-    utils::{Logical, Point, Serial},
+    output::Output,
+    reexports::wayland_protocols::xdg::shell::server::{
+        xdg_popup, xdg_positioner, xdg_surface, xdg_toplevel, xdg_wm_base,
+    },
+    utils::{Logical, Point, Serial, SERIAL_COUNTER},
     wayland::shell::xdg::{
-        Configure, PopupSurface, PositionerState, ShellClient, ToplevelSurface, XdgShellHandler, XdgShellState,
+        Configure, PopupSurface, PositionerState, ShellClient, ToplevelSurface, XdgPositionerUserData, XdgShellHandler,
+        XdgShellState, XdgShellSurfaceUserData, XdgSurfaceUserData, XdgWmBaseUserData,
     },
 };
-use wayland_server::protocol::{wl_output, wl_seat, wl_surface};
+use wayland_server::{
+    backend::ClientId,
+    protocol::{wl_output, wl_seat, wl_surface},
+    Client, DataInit, Dispatch, DisplayHandle,
+};
+use wm_runtime::{StateRequest, ToplevelUpdate, WmEvent};
 
-use crate::{shell::Shell, Aerugo};
+use crate::{
+    shell::{Ack, Shell},
+    wm, Aerugo,
+};
 
 impl XdgShellHandler for Aerugo {
     fn xdg_shell_state(&mut self) -> &mut XdgShellState {
@@ -16,14 +28,23 @@ impl XdgShellHandler for Aerugo {
 
     fn new_client(&mut self, _client: ShellClient) {}
 
-    fn client_pong(&mut self, _client: ShellClient) {}
+    fn client_pong(&mut self, client: ShellClient) {
+        for toplevel in self.shell.toplevels.values_mut() {
+            toplevel.pong(&client);
+        }
+    }
 
     fn new_toplevel(&mut self, surface: ToplevelSurface) {
+        if let Some(xdg_surface) = self.shell.creating_toplevel.take() {
+            Shell::set_xdg_surface(surface.wl_surface(), xdg_surface);
+        }
+
         self.shell.pending_toplevels.push(surface);
     }
 
-    fn new_popup(&mut self, _surface: PopupSurface, _positioner: PositionerState) {
-        // TODO: track popups
+    fn new_popup(&mut self, surface: PopupSurface, positioner: PositionerState) {
+        self.popups
+            .new_popup(&mut self.scene, &self.output, surface, positioner);
     }
 
     fn move_request(&mut self, _surface: ToplevelSurface, _seat: wl_seat::WlSeat, _serial: Serial) {
@@ -40,28 +61,39 @@ impl XdgShellHandler for Aerugo {
         // TODO: forward to wm
     }
 
-    fn grab(&mut self, _surface: PopupSurface, _seat: wl_seat::WlSeat, _serial: Serial) {
-        // TODO
+    fn grab(&mut self, surface: PopupSurface, _seat: wl_seat::WlSeat, serial: Serial) {
+        if !self.popups.grab(&surface) {
+            return;
+        }
+
+        // The topmost grabbing popup receives keyboard input.
+        self.set_keyboard_focus(Some(surface.wl_surface().clone()), serial);
     }
 
-    fn maximize_request(&mut self, _surface: ToplevelSurface) {
-        // TODO: forward to wm
+    fn maximize_request(&mut self, surface: ToplevelSurface) {
+        self.request_state(&surface, StateRequest::SetMaximized);
     }
 
-    fn unmaximize_request(&mut self, _surface: ToplevelSurface) {
-        // TODO: forward to wm
+    fn unmaximize_request(&mut self, surface: ToplevelSurface) {
+        self.request_state(&surface, StateRequest::UnsetMaximized);
     }
 
-    fn fullscreen_request(&mut self, _surface: ToplevelSurface, _output: Option<wl_output::WlOutput>) {
-        // TODO: forward to wm
+    fn fullscreen_request(&mut self, surface: ToplevelSurface, output: Option<wl_output::WlOutput>) {
+        // An output which is not known to the wm is treated as no preference.
+        let output = output
+            .as_ref()
+            .and_then(Output::from_resource)
+            .and_then(|output| self.wm.output_id(&output));
+
+        self.request_state(&surface, StateRequest::SetFullscreen { output });
     }
 
-    fn unfullscreen_request(&mut self, _surface: ToplevelSurface) {
-        // TODO: forward to wm
+    fn unfullscreen_request(&mut self, surface: ToplevelSurface) {
+        self.request_state(&surface, StateRequest::UnsetFullscreen);
     }
 
-    fn minimize_request(&mut self, _surface: ToplevelSurface) {
-        // TODO: forward to wm
+    fn minimize_request(&mut self, surface: ToplevelSurface) {
+        self.request_state(&surface, StateRequest::SetMinimized);
     }
 
     fn show_window_menu(
@@ -74,21 +106,107 @@ impl XdgShellHandler for Aerugo {
         // TODO: Forward to wm
     }
 
-    fn ack_configure(&mut self, _surface: wl_surface::WlSurface, _configure: Configure) {
-        // TODO: Notify wm about current window state
+    fn ack_configure(&mut self, surface: wl_surface::WlSurface, configure: Configure) {
+        let Configure::Toplevel(configure) = configure else {
+            return;
+        };
+
+        let Some(id) = Shell::get_toplevel_id(&surface) else {
+            return;
+        };
+
+        let Some(toplevel) = self.shell.get_state_mut(id) else {
+            return;
+        };
+
+        match toplevel.acked(configure.serial, configure.state.size) {
+            Ack::Wm(configure) => {
+                if let Some(toplevel) = wm::wm_toplevel_id(id) {
+                    self.wm.send(WmEvent::AckToplevel { toplevel, configure });
+                }
+            }
+
+            // The wm was already told the configure was cancelled or acked.
+            Ack::Stale => {
+                tracing::debug!(toplevel = id, serial = ?configure.serial, "Toplevel acked a stale configure");
+            }
+
+            Ack::Untracked => {}
+        }
     }
 
-    fn reposition_request(&mut self, _surface: PopupSurface, _positioner: PositionerState, _token: u32) {
-        // TODO: forward to wm
+    fn reposition_request(&mut self, surface: PopupSurface, positioner: PositionerState, token: u32) {
+        self.popups
+            .reposition(&self.scene, &self.output, &surface, positioner, token);
     }
 
     fn toplevel_destroyed(&mut self, surface: ToplevelSurface) {
         Shell::remove_toplevel(self, surface.wl_surface());
     }
 
-    fn popup_destroyed(&mut self, _surface: PopupSurface) {
-        // TODO: Handle popup death
+    fn popup_destroyed(&mut self, surface: PopupSurface) {
+        let focus = self.popups.remove(&mut self.scene, surface.wl_surface());
+
+        // Return keyboard focus to the parent of a destroyed grabbing popup.
+        if let Some(focus) = focus {
+            self.set_keyboard_focus(Some(focus), SERIAL_COUNTER.next_serial());
+        }
+    }
+}
+
+impl Aerugo {
+    /// Forward a state change requested by a toplevel to the wm.
+    ///
+    /// The wm answers the request by configuring the toplevel. A request made before the initial commit is sent
+    /// when the toplevel is announced.
+    fn request_state(&mut self, surface: &ToplevelSurface, request: StateRequest) {
+        let Some(toplevel) = Shell::get_toplevel_id(surface.wl_surface()).and_then(wm::wm_toplevel_id) else {
+            Shell::with_toplevel_requests(surface.wl_surface(), |requests| requests.state = Some(request));
+            return;
+        };
+
+        self.wm.send(WmEvent::UpdateToplevel {
+            toplevel,
+            update: ToplevelUpdate {
+                request: Some(request),
+                ..Default::default()
+            },
+        });
     }
 }
 
-smithay::delegate_xdg_shell!(Aerugo);
+// Requests of xdg_surface are intercepted to record the xdg_surface of toplevels, see `Shell::xdg_surface`. The
+// other interfaces are delegated like `delegate_xdg_shell` does.
+wayland_server::delegate_global_dispatch!(Aerugo: [xdg_wm_base::XdgWmBase: ()] => XdgShellState);
+wayland_server::delegate_dispatch!(Aerugo: [xdg_wm_base::XdgWmBase: XdgWmBaseUserData] => XdgShellState);
+wayland_server::delegate_dispatch!(Aerugo: [xdg_positioner::XdgPositioner: XdgPositionerUserData] => XdgShellState);
+wayland_server::delegate_dispatch!(Aerugo: [xdg_popup::XdgPopup: XdgShellSurfaceUserData] => XdgShellState);
+wayland_server::delegate_dispatch!(Aerugo: [xdg_toplevel::XdgToplevel: XdgShellSurfaceUserData] => XdgShellState);
+
+impl Dispatch<xdg_surface::XdgSurface, XdgSurfaceUserData> for Aerugo {
+    fn request(
+        state: &mut Self,
+        client: &Client,
+        resource: &xdg_surface::XdgSurface,
+        request: xdg_surface::Request,
+        data: &XdgSurfaceUserData,
+        display: &DisplayHandle,
+        init: &mut DataInit<'_, Self>,
+    ) {
+        // Smithay creates the toplevel while handling the request, see `new_toplevel`.
+        if matches!(request, xdg_surface::Request::GetToplevel { .. }) {
+            state.shell.creating_toplevel = Some(resource.clone());
+        }
+
+        <XdgShellState as Dispatch<xdg_surface::XdgSurface, XdgSurfaceUserData, Self>>::request(
+            state, client, resource, request, data, display, init,
+        );
+        state.shell.creating_toplevel = None;
+    }
+
+    fn destroyed(state: &mut Self, client: ClientId, resource: &xdg_surface::XdgSurface, data: &XdgSurfaceUserData) {
+        <XdgShellState as Dispatch<xdg_surface::XdgSurface, XdgSurfaceUserData, Self>>::destroyed(
+            state, client, resource, data,
+        );
+    }
+}
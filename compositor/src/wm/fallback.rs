@@ -0,0 +1,354 @@
+//! Fallback window manager
+//!
+//! While no wm is running, a minimal floating wm built into the compositor manages toplevels so the session stays
+//! usable. This is the case before a wm module is configured, while a failed wm waits for the backoff to expire and
+//! after the wm was quarantined.
+//!
+//! Toplevels are placed in a cascade from the top left corner of the output at the size the client chooses.
+//! Clicking a toplevel raises and focuses it, and dragging with the left button while holding alt moves it.
+//!
+//! Once a wm runs, the fallback hands over and the toplevels are announced to the wm. The toplevels stay where the
+//! fallback placed them until the wm presents something else on the output.
+
+use smithay::{
+    backend::input::ButtonState,
+    utils::{Logical, Point, Rectangle, Size, SERIAL_COUNTER},
+};
+
+use crate::{
+    input::buttons::BTN_LEFT,
+    popup::output_geometry,
+    scene::{BranchIndex, NodeIndex, Transaction},
+    shell::{self, ToplevelId},
+    Aerugo,
+};
+
+/// Distance between toplevels in the cascade.
+const CASCADE_STEP: i32 = 32;
+
+/// How many toplevels are placed in a cascade before starting over from the corner of the output.
+const CASCADE_LENGTH: u32 = 10;
+
+#[derive(Debug, Default)]
+pub struct FallbackWm {
+    active: bool,
+
+    /// The branch presenting the toplevels.
+    ///
+    /// The branch is kept after handing over to a wm and used again when the fallback is activated again.
+    branch: Option<BranchIndex>,
+
+    /// Toplevels managed by the fallback, from bottom to top.
+    stack: Vec<ToplevelId>,
+
+    /// How many toplevels were placed, used to choose the next position in the cascade.
+    placed: u32,
+
+    /// The toplevel being moved.
+    grab: Option<MoveGrab>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MoveGrab {
+    toplevel: ToplevelId,
+
+    /// The pointer location relative to the toplevel when the grab started.
+    offset: Point<f64, Logical>,
+}
+
+impl FallbackWm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the fallback manages toplevels.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Forget a toplevel which was unmapped or closed.
+    pub fn remove(&mut self, id: ToplevelId) {
+        self.stack.retain(|&toplevel| toplevel != id);
+
+        if self.grab.map_or(false, |grab| grab.toplevel == id) {
+            self.grab = None;
+        }
+    }
+
+    /// Stop moving a toplevel with the pointer.
+    pub fn cancel_grab(&mut self) {
+        self.grab = None;
+    }
+}
+
+impl Aerugo {
+    /// Activate the fallback wm if no wm is running, or hand over to the wm once one runs.
+    pub fn refresh_fallback_wm(&mut self) {
+        match (self.wm.is_running(), self.fallback_wm.active) {
+            (false, false) => self.activate_fallback_wm(),
+            (true, true) => self.hand_over_to_wm(),
+            _ => (),
+        }
+    }
+
+    /// Place a toplevel which was mapped while the fallback is active.
+    pub fn fallback_map(&mut self, id: ToplevelId) {
+        let Some(branch) = self.fallback_wm.branch.filter(|_| self.fallback_wm.active) else {
+            return;
+        };
+
+        let Some(node) = self.toplevel_node(id) else {
+            return;
+        };
+
+        let area = output_geometry(&self.output);
+        let scale = self.output.current_scale().fractional_scale();
+        let location = cascade_position(self.fallback_wm.placed, area.size);
+        self.fallback_wm.placed = self.fallback_wm.placed.wrapping_add(1);
+
+        let mut transaction = Transaction::new();
+        transaction
+            .reparent(node, branch)
+            .set_offset(node, location.to_physical_precise_round(scale));
+
+        if let Err(err) = self.scene.apply_transaction(transaction) {
+            tracing::warn!(%err, id, "Fallback wm failed to place toplevel");
+            return;
+        }
+
+        self.fallback_wm.remove(id);
+        self.fallback_wm.stack.push(id);
+        self.fallback_raise(id);
+    }
+
+    /// Handle a pointer button while the fallback is active.
+    ///
+    /// Pressing a button raises and focuses the toplevel under the pointer. Pressing the left button while alt is
+    /// held also starts moving the toplevel.
+    pub fn fallback_button(&mut self, button: u32, state: ButtonState, location: Point<f64, Logical>) {
+        if !self.fallback_wm.active {
+            return;
+        }
+
+        if state == ButtonState::Released {
+            if button == BTN_LEFT {
+                self.fallback_wm.grab = None;
+            }
+
+            return;
+        }
+
+        // The toplevels are hidden behind the lock screen.
+        if self.session_lock.is_locked() {
+            return;
+        }
+
+        let windows = self.fallback_windows();
+        let Some((id, geometry)) = hit_test(&windows, location) else {
+            return;
+        };
+
+        self.fallback_raise(id);
+
+        let alt = self
+            .seat
+            .get_keyboard()
+            .map_or(false, |keyboard| keyboard.modifier_state().alt);
+
+        if button == BTN_LEFT && alt {
+            self.fallback_wm.grab = Some(MoveGrab {
+                toplevel: id,
+                offset: location - geometry.loc.to_f64(),
+            });
+        }
+    }
+
+    /// Move the grabbed toplevel with the pointer.
+    pub fn fallback_motion(&mut self, location: Point<f64, Logical>) {
+        let Some(grab) = self.fallback_wm.grab else {
+            return;
+        };
+
+        let Some(node) = self.toplevel_node(grab.toplevel) else {
+            self.fallback_wm.grab = None;
+            return;
+        };
+
+        let scale = self.output.current_scale().fractional_scale();
+        self.scene
+            .set_node_offset(node, (location - grab.offset).to_physical_precise_round(scale));
+    }
+
+    fn activate_fallback_wm(&mut self) {
+        tracing::info!("No wm is running, managing toplevels with the fallback wm");
+
+        let branch = *self
+            .fallback_wm
+            .branch
+            .get_or_insert_with(|| self.scene.create_branch());
+        self.scene.set_output_node(&self.output, NodeIndex::Branch(branch));
+        self.fallback_wm.active = true;
+        self.fallback_wm.placed = 0;
+
+        // Toplevels still waiting for the wm to send the initial configure are configured by the fallback.
+        for toplevel in self.shell.toplevels.values() {
+            if !toplevel.is_mapped() {
+                toplevel.send_initial_configure();
+            }
+        }
+
+        // Place the oldest toplevels first.
+        let mut ids = self
+            .shell
+            .mapped_toplevels()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+
+        for id in ids {
+            self.fallback_map(id);
+        }
+    }
+
+    /// Stop managing toplevels.
+    ///
+    /// The toplevels are announced to the wm by [`Aerugo::refresh_wm_handover`].
+    fn hand_over_to_wm(&mut self) {
+        tracing::info!("Wm is running, handing toplevels over from the fallback wm");
+
+        self.fallback_wm.active = false;
+        self.fallback_wm.stack.clear();
+        self.fallback_wm.grab = None;
+    }
+
+    /// Raise a toplevel above the other toplevels and give the toplevel keyboard focus.
+    fn fallback_raise(&mut self, id: ToplevelId) {
+        let Some(node) = self.toplevel_node(id) else {
+            return;
+        };
+
+        self.scene.raise_node_to_top(node);
+
+        if let Some(index) = self.fallback_wm.stack.iter().position(|&toplevel| toplevel == id) {
+            let id = self.fallback_wm.stack.remove(index);
+            self.fallback_wm.stack.push(id);
+        }
+
+        let surface = self.shell.get_state(id).and_then(|toplevel| toplevel.wl_surface());
+
+        if let Some(surface) = surface {
+            self.set_keyboard_focus(Some(surface), SERIAL_COUNTER.next_serial());
+        }
+    }
+
+    /// The geometry of the toplevels managed by the fallback, from bottom to top.
+    fn fallback_windows(&self) -> Vec<(ToplevelId, Rectangle<i32, Logical>)> {
+        let scale = self.output.current_scale().fractional_scale();
+
+        self.fallback_wm
+            .stack
+            .iter()
+            .filter_map(|&id| {
+                let surface = self.shell.get_state(id)?.wl_surface()?;
+                let tree = self.scene.get_surface_tree_index(surface.clone())?;
+                let location = self
+                    .scene
+                    .node_location(NodeIndex::SurfaceTree(tree))
+                    .to_f64()
+                    .to_logical(scale)
+                    .to_i32_round();
+                let bounds = shell::surface_tree_bounds(&surface);
+
+                Some((id, Rectangle::from_loc_and_size(location + bounds.loc, bounds.size)))
+            })
+            .collect()
+    }
+
+    fn toplevel_node(&self, id: ToplevelId) -> Option<NodeIndex> {
+        let surface = self.shell.get_state(id)?.wl_surface()?;
+        let tree = self.scene.get_surface_tree_index(surface)?;
+        Some(NodeIndex::SurfaceTree(tree))
+    }
+}
+
+/// The location of the toplevel placed after `placed` other toplevels.
+///
+/// Each toplevel is placed diagonally below the previous toplevel. After [`CASCADE_LENGTH`] toplevels the cascade
+/// starts over from the top of the output, shifted to the right so toplevels do not cover each other exactly.
+fn cascade_position(placed: u32, size: Size<i32, Logical>) -> Point<i32, Logical> {
+    let step = (placed % CASCADE_LENGTH) as i32 * CASCADE_STEP;
+    let cascade = (placed / CASCADE_LENGTH) as i32 * CASCADE_STEP * 2;
+
+    // Start over from the left edge once the cascades would leave the output.
+    let x = (CASCADE_STEP + cascade + step) % (size.w / 2).max(CASCADE_STEP + 1);
+    let y = CASCADE_STEP + step;
+
+    (x, y).into()
+}
+
+/// Find the topmost window containing the point.
+///
+/// The windows are ordered from bottom to top.
+fn hit_test(
+    windows: &[(ToplevelId, Rectangle<i32, Logical>)],
+    point: Point<f64, Logical>,
+) -> Option<(ToplevelId, Rectangle<i32, Logical>)> {
+    windows
+        .iter()
+        .rev()
+        .find(|(_, geometry)| geometry.to_f64().contains(point))
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU64;
+
+    use smithay::utils::{Point, Rectangle, Size};
+
+    use super::{cascade_position, hit_test, CASCADE_LENGTH, CASCADE_STEP};
+
+    #[test]
+    fn cascade_steps_diagonally() {
+        let size = Size::from((1920, 1080));
+        let first = cascade_position(0, size);
+        let second = cascade_position(1, size);
+
+        assert_eq!(first, Point::from((CASCADE_STEP, CASCADE_STEP)));
+        assert_eq!(second - first, Point::from((CASCADE_STEP, CASCADE_STEP)));
+    }
+
+    #[test]
+    fn cascade_starts_over() {
+        let size = Size::from((1920, 1080));
+        let first = cascade_position(0, size);
+        let next = cascade_position(CASCADE_LENGTH, size);
+
+        assert_eq!(next.y, first.y);
+        assert!(next.x > first.x);
+    }
+
+    #[test]
+    fn cascade_stays_on_small_output() {
+        let size = Size::from((200, 200));
+
+        for placed in 0..100 {
+            assert!(cascade_position(placed, size).x < size.w);
+        }
+    }
+
+    #[test]
+    fn hit_test_finds_topmost() {
+        let bottom = NonZeroU64::new(1).unwrap();
+        let top = NonZeroU64::new(2).unwrap();
+        let windows = [
+            (bottom, Rectangle::from_loc_and_size((0, 0), (100, 100))),
+            (top, Rectangle::from_loc_and_size((50, 50), (100, 100))),
+        ];
+
+        assert_eq!(hit_test(&windows, (75.0, 75.0).into()).map(|(id, _)| id), Some(top));
+        assert_eq!(hit_test(&windows, (10.0, 10.0).into()).map(|(id, _)| id), Some(bottom));
+        assert_eq!(hit_test(&windows, (200.0, 200.0).into()), None);
+    }
+}
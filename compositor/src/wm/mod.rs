@@ -0,0 +1,1247 @@
+//! Window manager supervision
+//!
+//! The wm runs inside the [`WmRuntime`] on a separate thread. If the wm fails while handling an event, the
+//! runtime thread stops and the supervisor is responsible for starting a new runtime.
+//!
+//! The runtime first tries to recover a failed wm itself by instantiating the wm again, as allowed by the
+//! [`RetryPolicy`] from the `[wm_recovery]` section of the configuration. A wm which takes longer than the
+//! [`ExecutionLimits`] allow to handle an event is treated as failed. Once the runtime gives up, a wm which
+//! fails repeatedly is restarted with an exponential backoff. If the wm keeps failing on the same event, the wm
+//! is considered to be in a crash loop: the offending event is quarantined (written to disk so the failure can be
+//! replayed) and the wm is not restarted again until [`WmSupervisor::reload`] is called.
+//!
+//! Whenever the wm fails, the compositor keeps running in a fallback mode where every toplevel floats at the size
+//! the client chooses, until a wm configures the toplevel again. While no wm is running at all, toplevels are
+//! managed by the [fallback wm](fallback).
+//!
+//! Recent traffic between the compositor and the wm is recorded in a [`WmTrace`] which can be dumped on demand
+//! using [`WmSupervisor::dump_trace`].
+//!
+//! The runtime periodically reports statistics of the wm, which are available using [`WmSupervisor::stats`]. A
+//! warning is logged when the wm takes more than half of the callback time limit to handle an event.
+
+pub mod fallback;
+pub mod trace;
+
+use std::{
+    fs, io,
+    num::{NonZeroU32, NonZeroU64},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use calloop::{
+    timer::{TimeoutAction, Timer},
+    LoopHandle, RegistrationToken,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+use smithay::{
+    output::Output,
+    utils::{Logical, Physical, Point, Rectangle, Size, Transform, SERIAL_COUNTER},
+};
+use wm_runtime::{
+    geometry::{
+        euclid::{point2, size2},
+        LogicalRect, LogicalSize, PhysicalSize,
+    },
+    CrashReport, Easing, EventSender, ExecutionLimits, Id, KeyModifiers, ModuleInfo, OutputInfo, RetryPolicy,
+    RuntimeMessage, RuntimeOptions, SceneOperation, ViewSource, ViewTransform, WasiConfig, WmEvent, WmRequest,
+    WmRuntime, WmStats,
+};
+
+use crate::{
+    animation::{self, Animation},
+    config::TimeoutPolicy,
+    crash,
+    input::keybindings::{Keybinding, KeybindingManager},
+    launcher::Origin,
+    popup::output_geometry,
+    scene::{NodeIndex, Transaction},
+    screenshot::PendingScreenshot,
+    shell::ToplevelId,
+    workspace::{Workspace, Workspaces},
+    Aerugo, Loop,
+};
+
+use self::trace::{Direction, WmTrace};
+
+/// Delay before the first restart of a crashed wm.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
+/// The maximum delay between restarts of a crashed wm.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How long a wm must run without failing for the crash history to be forgotten.
+const HEALTHY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many times the wm may fail on the same event before the event is quarantined.
+const CRASH_LOOP_THRESHOLD: usize = 3;
+
+/// The state of the supervised wm.
+#[derive(Debug)]
+pub enum WmStatus {
+    /// No wm module was configured.
+    None,
+
+    /// The wm is running.
+    Running,
+
+    /// The wm failed and will be restarted once the backoff expires.
+    Backoff { restart_at: Instant },
+
+    /// The wm is in a crash loop and will not be restarted until a reload.
+    Quarantined { report: CrashReport },
+
+    /// The display server is shutting down and the wm was told to finalize.
+    ShuttingDown,
+}
+
+#[derive(Debug)]
+pub struct WmSupervisor {
+    r#loop: LoopHandle<'static, Loop>,
+
+    /// Path to the wm module.
+    module: Option<PathBuf>,
+
+    status: WmStatus,
+
+    /// Sender used to send events to the running wm.
+    sender: Option<EventSender>,
+
+    /// Registration of the running wm runtime in the event loop.
+    runtime: Option<RegistrationToken>,
+
+    /// Recent failures of the wm, oldest first.
+    crashes: Vec<Crash>,
+
+    /// Recent traffic between the compositor and the wm.
+    trace: WmTrace,
+
+    /// The statistics last reported by the running wm.
+    stats: Option<WmStats>,
+
+    /// The name, version and ABI reported by the running wm.
+    info: Option<ModuleInfo>,
+
+    /// Scene nodes of the views the wm created.
+    views: FxHashMap<Id, NodeIndex>,
+
+    /// Scene nodes of views which were forgotten since the last refresh.
+    ///
+    /// These nodes are owned by the wm and still need to be removed from the scene.
+    removed_views: Vec<NodeIndex>,
+
+    /// Keybindings registered by the wm, with the id the wm registered each binding with.
+    bindings: KeybindingManager<u32>,
+
+    /// Outputs announced to the wm.
+    ///
+    /// Output ids are allocated by the display server, so the outputs are announced again when a new wm starts.
+    outputs: FxHashMap<Id, Output>,
+
+    /// Workspaces created by the wm.
+    workspaces: Workspaces,
+
+    /// Pending timers set by the wm, with the id the wm set each timer with.
+    timers: FxHashMap<u32, RegistrationToken>,
+
+    /// Outputs the wm requested a frame on.
+    frame_requests: FxHashSet<Id>,
+
+    /// Whether the wm asked the display server to shut down.
+    shutdown_requested: bool,
+
+    /// How often the runtime instantiates a failed wm again.
+    retry_policy: RetryPolicy,
+
+    /// How long the wm may take to handle an event.
+    limits: ExecutionLimits,
+
+    /// WASI features available to the wm, if the wm may use WASI.
+    wasi: Option<WasiConfig>,
+
+    /// Whether a newly started wm still has to be told about the existing toplevels.
+    handover: bool,
+}
+
+#[derive(Debug)]
+struct Crash {
+    time: Instant,
+    report: CrashReport,
+}
+
+impl WmSupervisor {
+    pub fn new(r#loop: LoopHandle<'static, Loop>, module: Option<PathBuf>) -> Self {
+        Self {
+            r#loop,
+            module,
+            status: WmStatus::None,
+            sender: None,
+            runtime: None,
+            crashes: Vec::new(),
+            trace: WmTrace::default(),
+            stats: None,
+            info: None,
+            views: FxHashMap::default(),
+            removed_views: Vec::new(),
+            bindings: KeybindingManager::new(),
+            outputs: FxHashMap::default(),
+            workspaces: Workspaces::new(),
+            timers: FxHashMap::default(),
+            frame_requests: FxHashSet::default(),
+            shutdown_requested: false,
+            retry_policy: RetryPolicy::default(),
+            limits: ExecutionLimits::default(),
+            wasi: None,
+            handover: false,
+        }
+    }
+
+    pub fn status(&self) -> &WmStatus {
+        &self.status
+    }
+
+    /// Whether a wm is running, including a wm which is finalizing.
+    pub fn is_running(&self) -> bool {
+        matches!(self.status, WmStatus::Running | WmStatus::ShuttingDown)
+    }
+
+    /// The message which should be shown to the user in an error overlay.
+    ///
+    /// This is [`Some`] if the wm is quarantined.
+    pub fn error_message(&self) -> Option<String> {
+        match &self.status {
+            WmStatus::Quarantined { report } => Some(format!("Window manager crashed repeatedly: {}", report.error)),
+            _ => None,
+        }
+    }
+
+    /// The statistics last reported by the running wm.
+    pub fn stats(&self) -> Option<&WmStats> {
+        self.stats.as_ref()
+    }
+
+    /// The name, version and ABI reported by the running wm.
+    pub fn info(&self) -> Option<&ModuleInfo> {
+        self.info.as_ref()
+    }
+
+    /// Send an event to the running wm.
+    ///
+    /// If the wm is not running, the event is dropped. An event is also dropped if the wm does not keep up with
+    /// events, in which case the runtime reports the wm as failed.
+    pub fn send(&mut self, event: WmEvent) {
+        if let Some(sender) = self.sender.as_ref() {
+            self.trace.record(Direction::Event, &event);
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Tell the wm a frame of the output was presented, if the wm requested a frame on the output.
+    pub fn frame_presented(&mut self, output: &Output, time: u32) {
+        let ids = self
+            .outputs
+            .iter()
+            .filter(|&(id, presented)| presented == output && self.frame_requests.contains(id))
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+
+        for id in ids {
+            self.frame_requests.remove(&id);
+            self.send(WmEvent::Frame { output: id, time });
+        }
+    }
+
+    /// Write the wm traffic from the last `duration` to a file.
+    ///
+    /// If `path` is [`None`], the trace is written to the `traces` directory in the state directory. If
+    /// `duration` is [`None`], all recorded traffic is written.
+    pub fn dump_trace(&self, duration: Option<Duration>, path: Option<PathBuf>) -> io::Result<PathBuf> {
+        let path = path.unwrap_or_else(|| state_dir().join("traces").join(format!("{}.trace", timestamp())));
+        self.trace.dump(duration, &path)?;
+        Ok(path)
+    }
+
+    /// Set the path to the wm module.
+    ///
+    /// The new module is used the next time the wm is started.
+    pub fn set_module(&mut self, module: Option<PathBuf>) {
+        self.module = module;
+    }
+
+    /// Set how often the runtime instantiates a failed wm again.
+    ///
+    /// The policy is used the next time the wm is started.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Set how long the wm may take to handle an event.
+    ///
+    /// The limits are used the next time the wm is started.
+    pub fn set_execution_limits(&mut self, limits: ExecutionLimits) {
+        self.limits = limits;
+    }
+
+    /// Set the WASI features available to the wm.
+    ///
+    /// The features are used the next time the wm is started.
+    pub fn set_wasi(&mut self, wasi: Option<WasiConfig>) {
+        self.wasi = wasi;
+    }
+
+    /// Start the wm.
+    ///
+    /// This does nothing if the wm is already running.
+    pub fn start(&mut self) {
+        if self.runtime.is_some() {
+            return;
+        }
+
+        let Some(module) = self.module.clone() else {
+            self.status = WmStatus::None;
+            return;
+        };
+
+        let options = RuntimeOptions {
+            retry_policy: self.retry_policy,
+            limits: self.limits,
+            wasi: self.wasi.clone(),
+        };
+        let runtime = fs::read(&module)
+            .map_err(|err| format!("{err}"))
+            .and_then(|bytes| WmRuntime::with_options(&bytes, options).map_err(|err| format!("{err}")));
+
+        let runtime = match runtime {
+            Ok(runtime) => runtime,
+            Err(error) => {
+                tracing::error!(%error, module = %module.display(), "Failed to start wm");
+                self.crashed(CrashReport {
+                    event: "instantiate".into(),
+                    error,
+                });
+                return;
+            }
+        };
+
+        self.sender = Some(runtime.sender());
+        self.info = Some(runtime.info().clone());
+
+        let token = self
+            .r#loop
+            .insert_source(runtime, |message, _, state| match message {
+                RuntimeMessage::Request(request) => {
+                    state.comp.wm.trace.record(Direction::Request, &request);
+                    state.comp.handle_wm_request(request);
+                }
+
+                RuntimeMessage::Restarted(report) => {
+                    tracing::warn!(event = %report.event, error = %report.error, "Wm crashed and was restarted");
+                    state.comp.wm.forget_wm_state();
+                    state.comp.float_toplevels();
+                }
+
+                RuntimeMessage::Stats(stats) => state.comp.wm.update_stats(stats),
+
+                RuntimeMessage::Closed => {
+                    // The wm finished handling the remaining events after being told to finalize.
+                    if matches!(state.comp.wm.status, WmStatus::ShuttingDown) {
+                        tracing::debug!("Wm finalized");
+                        state.comp.wm.stop();
+                        return;
+                    }
+
+                    // If the runtime closed without a crash being reported then the wm thread died.
+                    if state.comp.wm.runtime.is_some() {
+                        state.comp.wm.stop();
+                        state.comp.wm.crashed(CrashReport {
+                            event: "unknown".into(),
+                            error: "wm runtime thread stopped".into(),
+                        });
+                        state.comp.float_toplevels();
+                    }
+                }
+            })
+            .expect("Failed to insert wm runtime");
+
+        self.runtime = Some(token);
+        self.status = WmStatus::Running;
+        crash::set_wm(Some(&module));
+        self.handover = true;
+
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|(&output, state)| WmEvent::NewOutput {
+                output,
+                info: output_info(state),
+            })
+            .collect::<Vec<_>>();
+
+        for event in outputs {
+            self.send(event);
+        }
+    }
+
+    /// Announce a new output to the wm.
+    pub fn output_connected(&mut self, id: Id, output: Output) {
+        let info = output_info(&output);
+        self.outputs.insert(id, output);
+        self.send(WmEvent::NewOutput { output: id, info });
+    }
+
+    /// Notify the wm that the state of an output changed.
+    pub fn output_changed(&mut self, output: &Output) {
+        if let Some(id) = self.output_id(output) {
+            self.send(WmEvent::UpdateOutput {
+                output: id,
+                info: output_info(output),
+            });
+        }
+    }
+
+    /// Notify the wm that an output was disconnected, with the toplevels which were presented on the output.
+    pub fn output_disconnected(&mut self, output: &Output, toplevels: Vec<Id>) {
+        if let Some(id) = self.output_id(output) {
+            self.outputs.remove(&id);
+            self.workspaces.remove_output(output);
+            self.send(WmEvent::DisconnectOutput { output: id, toplevels });
+        }
+    }
+
+    pub fn workspaces(&self) -> &Workspaces {
+        &self.workspaces
+    }
+
+    pub fn workspaces_mut(&mut self) -> &mut Workspaces {
+        &mut self.workspaces
+    }
+
+    /// The id of the wm keybinding matching a key press.
+    pub fn binding(&self, modifiers: KeyModifiers, keysym: u32) -> Option<u32> {
+        self.bindings.get(modifiers, keysym).copied()
+    }
+
+    /// The id the wm knows the output by.
+    pub fn output_id(&self, output: &Output) -> Option<Id> {
+        self.outputs
+            .iter()
+            .find_map(|(&id, state)| (state == output).then_some(id))
+    }
+
+    /// Reload the wm.
+    ///
+    /// This will restart the wm, even if the wm was quarantined. The crash history is cleared.
+    pub fn reload(&mut self) {
+        tracing::info!("Reloading wm");
+        self.stop();
+        self.crashes.clear();
+        self.start();
+    }
+
+    /// Whether the existing toplevels are handed over to a newly started wm by the next refresh.
+    ///
+    /// Toplevels created in the meantime are handed over as well, so they are not announced separately.
+    pub fn handover_pending(&self) -> bool {
+        self.handover
+    }
+
+    /// Whether the wm asked the display server to shut down.
+    pub fn shutdown_requested(&self) -> bool {
+        self.shutdown_requested
+    }
+
+    /// Tell the wm the display server is shutting down.
+    ///
+    /// No events are sent to the wm afterwards. The runtime keeps handling requests until the wm finished
+    /// handling the remaining events or the wm is stopped.
+    pub fn finalize(&mut self) {
+        let running = self.runtime.is_some();
+        // This also keeps a wm waiting for the backoff to expire from being restarted.
+        self.status = WmStatus::ShuttingDown;
+
+        if running {
+            self.send(WmEvent::Shutdown);
+            self.sender.take();
+        }
+    }
+
+    /// Forget views of a scene node which was destroyed.
+    pub fn forget_node(&mut self, node: NodeIndex) {
+        self.views.retain(|_, view| *view != node);
+    }
+
+    /// Stop the wm.
+    pub fn stop(&mut self) {
+        if let Some(token) = self.runtime.take() {
+            self.r#loop.remove(token);
+        }
+
+        self.sender.take();
+        self.stats.take();
+        self.info.take();
+        self.forget_wm_state();
+        crash::set_wm(None);
+    }
+
+    fn update_stats(&mut self, stats: WmStats) {
+        if let Some((event, max)) = stats.slowest_callback() {
+            if max > self.limits.max_callback_time / 2 {
+                tracing::warn!(
+                    event,
+                    ?max,
+                    limit = ?self.limits.max_callback_time,
+                    "Wm is close to the callback time limit"
+                );
+            }
+        }
+
+        self.stats = Some(stats);
+    }
+
+    /// Forget state the display server keeps on behalf of the wm.
+    ///
+    /// Ids are only meaningful to the wm instance which allocated them.
+    fn forget_wm_state(&mut self) {
+        self.removed_views.extend(self.views.drain().map(|(_, node)| node));
+        self.bindings = KeybindingManager::new();
+        self.workspaces.clear();
+        self.frame_requests.clear();
+
+        for (_, token) in self.timers.drain() {
+            self.r#loop.remove(token);
+        }
+    }
+
+    fn crashed(&mut self, report: CrashReport) {
+        let now = Instant::now();
+
+        // A wm which ran for a while without failing is considered healthy again.
+        if self
+            .crashes
+            .last()
+            .map_or(false, |crash| now.duration_since(crash.time) > HEALTHY_INTERVAL)
+        {
+            self.crashes.clear();
+        }
+
+        self.crashes.push(Crash {
+            time: now,
+            report: report.clone(),
+        });
+
+        let repeated = self
+            .crashes
+            .iter()
+            .filter(|crash| crash.report.event == report.event)
+            .count();
+
+        if repeated >= CRASH_LOOP_THRESHOLD {
+            tracing::error!(event = %report.event, "Wm is in a crash loop, quarantining event");
+
+            match quarantine(&report) {
+                Ok(path) => tracing::info!(path = %path.display(), "Wrote quarantined event"),
+                Err(err) => tracing::warn!(%err, "Failed to write quarantined event"),
+            }
+
+            self.status = WmStatus::Quarantined { report };
+            return;
+        }
+
+        let backoff = backoff(self.crashes.len());
+        tracing::info!(?backoff, "Restarting wm after backoff");
+
+        self.status = WmStatus::Backoff {
+            restart_at: now + backoff,
+        };
+
+        self.r#loop
+            .insert_source(Timer::from_duration(backoff), |_, _, state| {
+                if matches!(state.comp.wm.status, WmStatus::Backoff { .. }) {
+                    state.comp.wm.start();
+                }
+
+                TimeoutAction::Drop
+            })
+            .expect("Failed to insert wm restart timer");
+    }
+}
+
+impl Aerugo {
+    fn handle_wm_request(&mut self, request: WmRequest) {
+        match request {
+            WmRequest::Crashed(report) => {
+                tracing::error!(event = %report.event, error = %report.error, "Wm crashed");
+                self.wm.stop();
+                self.wm.crashed(report);
+                self.float_toplevels();
+            }
+
+            WmRequest::ToplevelConfigure {
+                toplevel,
+                serial,
+                configure,
+            } => match self.shell.get_state_mut(toplevel_id(toplevel)) {
+                Some(state) => {
+                    let mut configure = configure;
+
+                    // A decoration mode forced by the user takes precedence over the mode the wm chose.
+                    if let Some(mode) = state.window_rules(&self.window_rules).forced_mode() {
+                        configure.decorations = Some(mode);
+                    }
+
+                    state.configure(&configure, serial);
+
+                    // Check whether the client acked the configure once the timeout passed.
+                    let timer = Timer::from_duration(self.transactions.configure_timeout());
+                    let _ = self.wm.r#loop.insert_source(timer, |_, _, state| {
+                        state.comp.expire_wm_configures();
+                        TimeoutAction::Drop
+                    });
+                }
+                None => tracing::debug!(?toplevel, "Wm configured unknown toplevel"),
+            },
+
+            WmRequest::Transaction(operations) => {
+                let Some(transaction) = self.wm_transaction(operations) else {
+                    tracing::warn!("Wm committed a transaction referencing unknown objects");
+                    return;
+                };
+
+                if let Err(err) = self.scene.apply_transaction(transaction) {
+                    tracing::warn!(%err, "Wm committed an invalid transaction");
+                }
+            }
+
+            WmRequest::RegisterBinding { id, modifiers, keysym } => {
+                if let Err(err) = self.wm.bindings.register(Keybinding { modifiers, keysym }, id) {
+                    tracing::warn!(%err, "Wm registered an invalid keybinding");
+                }
+            }
+
+            WmRequest::UnregisterBinding(id) => self.wm.bindings.retain(|&binding| binding != id),
+
+            WmRequest::CreateWorkspace {
+                workspace,
+                output,
+                name,
+            } => {
+                let Some(output) = self.wm.outputs.get(&output).cloned() else {
+                    tracing::debug!(?workspace, "Wm created a workspace on a disconnected output");
+                    return;
+                };
+
+                let branch = self.scene.create_branch();
+                self.wm.workspaces.insert(
+                    workspace,
+                    Workspace {
+                        name,
+                        output,
+                        branch,
+                        active: false,
+                    },
+                );
+            }
+
+            WmRequest::DestroyWorkspace(workspace) => self.wm.workspaces.remove(workspace),
+
+            WmRequest::SetKeyboardFocus(toplevel) => {
+                // The toplevel may have been destroyed while the request was in flight, which clears the focus.
+                let surface = toplevel.and_then(|toplevel| self.shell.get_state(toplevel_id(toplevel))?.wl_surface());
+                self.set_keyboard_focus(surface, SERIAL_COUNTER.next_serial());
+            }
+
+            WmRequest::ShowOverview => self.show_overview(),
+            WmRequest::HideOverview => self.hide_overview(),
+
+            // The shutdown is started the next time the loop checks for shutdown conditions.
+            WmRequest::Shutdown => {
+                tracing::info!("Wm requested shutdown");
+                self.wm.shutdown_requested = true;
+            }
+
+            WmRequest::ToplevelScreenshot {
+                toplevel,
+                serial,
+                max_size,
+            } => {
+                self.screenshots.request(PendingScreenshot {
+                    toplevel: toplevel_id(toplevel),
+                    wm_id: toplevel,
+                    serial,
+                    max_size: to_physical_size(max_size),
+                });
+            }
+
+            WmRequest::RequestFrame(output) => {
+                if self.wm.outputs.contains_key(&output) {
+                    self.wm.frame_requests.insert(output);
+                }
+            }
+
+            WmRequest::SetTimer { id, duration } => {
+                let timer = Timer::from_duration(duration);
+                let token = self.wm.r#loop.insert_source(timer, move |_, _, state| {
+                    state.comp.wm.timers.remove(&id);
+                    state.comp.wm.send(WmEvent::Timer(id));
+                    TimeoutAction::Drop
+                });
+
+                match token {
+                    Ok(token) => {
+                        // A timer with the same id replaces the pending timer.
+                        if let Some(pending) = self.wm.timers.insert(id, token) {
+                            self.wm.r#loop.remove(pending);
+                        }
+                    }
+
+                    Err(err) => tracing::warn!(%err, id, "Failed to insert wm timer"),
+                }
+            }
+
+            WmRequest::CancelTimer(id) => {
+                if let Some(token) = self.wm.timers.remove(&id) {
+                    self.wm.r#loop.remove(token);
+                }
+            }
+
+            WmRequest::CreateVirtualOutput(size) => {
+                if let Err(err) = self.create_virtual_output(to_physical_size(size), None) {
+                    tracing::warn!(%err, "Failed to create virtual output for the wm");
+                }
+            }
+
+            WmRequest::DestroyVirtualOutput(output) => {
+                let Some(name) = self
+                    .wm
+                    .outputs
+                    .get(&output)
+                    .filter(|&output| self.virtual_outputs.contains(output))
+                    .map(Output::name)
+                else {
+                    return;
+                };
+
+                if let Err(err) = self.destroy_virtual_output(&name) {
+                    tracing::warn!(%err, "Failed to destroy virtual output for the wm");
+                }
+            }
+
+            WmRequest::SetOutputPower { output, on } => {
+                if let Some(output) = self.wm.outputs.get(&output).cloned() {
+                    self.set_output_power(&output, on);
+                }
+            }
+
+            WmRequest::CreateView { view, source } => {
+                let node = match source {
+                    ViewSource::SolidColor { color, size } => {
+                        let size = self.wm_size(size);
+                        NodeIndex::SolidColor(self.scene.create_solid_color(size, to_color(color)))
+                    }
+
+                    ViewSource::Toplevel { toplevel } => {
+                        let Some(surface) = self
+                            .shell
+                            .get_state(toplevel_id(toplevel))
+                            .and_then(|toplevel| toplevel.wl_surface())
+                        else {
+                            tracing::debug!(?toplevel, "Wm created a view of an unknown toplevel");
+                            return;
+                        };
+
+                        // The surface tree is created when the toplevel is mapped, but the wm may place the
+                        // toplevel before the first buffer is committed.
+                        let tree = match self.scene.get_surface_tree_index(surface.clone()) {
+                            Some(tree) => tree,
+                            None => self.scene.create_surface_tree(surface),
+                        };
+
+                        // A toplevel is presented by the newest view of the toplevel.
+                        let node = NodeIndex::SurfaceTree(tree);
+                        self.wm.forget_node(node);
+                        node
+                    }
+
+                    ViewSource::SurfaceNode { node } => {
+                        let Some(tree) = self.aerugo_shell.surface_tree(toplevel_id(node)) else {
+                            tracing::debug!(?node, "Wm created a view of an unknown surface node");
+                            return;
+                        };
+
+                        // Like toplevels, a surface node is presented by the newest view of the node.
+                        let node = NodeIndex::SurfaceTree(tree);
+                        self.wm.forget_node(node);
+                        node
+                    }
+
+                    ViewSource::Branch => NodeIndex::Branch(self.scene.create_branch()),
+                };
+
+                if let Some(previous) = self.wm.views.insert(view, node) {
+                    self.destroy_wm_view(previous);
+                }
+            }
+
+            WmRequest::DestroyView(view) => {
+                if let Some(node) = self.wm.views.remove(&view) {
+                    self.destroy_wm_view(node);
+                }
+            }
+
+            WmRequest::Spawn {
+                launch,
+                command,
+                systemd_scope,
+            } => {
+                if let Err(err) = self.spawn(Origin::Wm(launch), &command, systemd_scope) {
+                    tracing::warn!(%err, ?command, "Failed to start program for the wm");
+                }
+            }
+
+            _request => {
+                // TODO: Handle wm requests
+            }
+        }
+    }
+}
+
+impl Aerugo {
+    /// Hand the existing toplevels over to a newly started wm.
+    ///
+    /// A wm started for the first time, restarted after failing or replaced by a reload does not know about the
+    /// toplevels which were created before it started.
+    pub fn refresh_wm_handover(&mut self) {
+        if !std::mem::take(&mut self.wm.handover) || !self.wm.is_running() {
+            return;
+        }
+
+        let mut ids = self.shell.toplevels.keys().copied().collect::<Vec<_>>();
+
+        if !ids.is_empty() {
+            tracing::info!(toplevels = ids.len(), "Handing toplevels over to the wm");
+        }
+
+        // Announce the oldest toplevels first.
+        ids.sort_unstable();
+
+        for id in ids {
+            self.announce_toplevel(id);
+        }
+
+        let mut nodes = self
+            .aerugo_shell
+            .nodes()
+            .filter_map(|(id, name)| Some((wm_surface_node_id(id)?, name.to_owned())))
+            .collect::<Vec<_>>();
+        nodes.sort_unstable_by_key(|(id, _)| id.rep());
+
+        for (node, name) in nodes {
+            self.wm.send(WmEvent::NewSurfaceNode { node, name });
+        }
+    }
+
+    /// Tell the wm about a toplevel.
+    ///
+    /// The wm sends the initial configure of the toplevel.
+    pub fn announce_toplevel(&mut self, id: ToplevelId) {
+        let (Some(wm_id), Some(toplevel)) = (wm_toplevel_id(id), self.shell.get_state(id)) else {
+            return;
+        };
+
+        let update = toplevel.initial_update(&self.window_rules);
+
+        self.wm.send(WmEvent::NewToplevel {
+            toplevel: wm_id,
+            features: toplevel.features(),
+        });
+        self.wm.send(WmEvent::UpdateToplevel {
+            toplevel: wm_id,
+            update,
+        });
+    }
+
+    /// Float every toplevel while the wm is unavailable.
+    fn float_toplevels(&mut self) {
+        if self.shell.toplevels.is_empty() {
+            return;
+        }
+
+        tracing::info!("Floating toplevels until the wm configures them again");
+
+        for toplevel in self.shell.toplevels.values_mut() {
+            toplevel.float();
+        }
+    }
+
+    /// Tell the wm an output is being disconnected.
+    ///
+    /// This must be called while the output is still in the scene. Configures of the toplevels on the output which
+    /// were not acked yet are cancelled, since the wm sized them for the output. The wm is then told which
+    /// toplevels were on the output, so the wm can configure the toplevels again for another output.
+    pub fn wm_output_disconnected(&mut self, output: &Output) {
+        let roots = self
+            .scene
+            .output_node(output)
+            .into_iter()
+            .chain(
+                self.wm
+                    .workspaces
+                    .iter()
+                    .filter(|(_, workspace)| workspace.output == *output)
+                    .map(|(_, workspace)| NodeIndex::Branch(workspace.branch)),
+            )
+            .collect::<Vec<_>>();
+
+        let mut toplevels = Vec::new();
+        let mut events = Vec::new();
+
+        for (&id, toplevel) in &mut self.shell.toplevels {
+            let Some(tree) = toplevel
+                .wl_surface()
+                .and_then(|surface| self.scene.get_surface_tree_index(surface))
+            else {
+                continue;
+            };
+
+            let node = NodeIndex::SurfaceTree(tree);
+
+            if !roots.iter().any(|&root| self.scene.is_within(node, root)) {
+                continue;
+            }
+
+            let Some(wm_id) = wm_toplevel_id(id) else {
+                continue;
+            };
+
+            events.extend(
+                toplevel
+                    .cancel_configures()
+                    .into_iter()
+                    .map(|serial| WmEvent::ConfigureCancelled {
+                        toplevel: wm_id,
+                        serial,
+                    }),
+            );
+            toplevels.push(wm_id);
+        }
+
+        for event in events {
+            self.wm.send(event);
+        }
+
+        self.wm.output_disconnected(output, toplevels);
+    }
+
+    /// Handle configures sent on behalf of the wm which the client did not ack in time.
+    fn expire_wm_configures(&mut self) {
+        let now = Instant::now();
+        let timeout = self.transactions.configure_timeout();
+        let policy = self.transactions.timeout_policy;
+        let mut events = Vec::new();
+
+        for (&id, toplevel) in &mut self.shell.toplevels {
+            let expired = toplevel.expire_configures(now, timeout);
+
+            if expired.is_empty() {
+                continue;
+            }
+
+            // The client may be stuck, ping the client to find out.
+            if !toplevel.ping() {
+                tracing::warn!(toplevel = id, "Client is not responding");
+            }
+
+            let Some(toplevel) = wm_toplevel_id(id) else {
+                continue;
+            };
+
+            events.extend(expired.into_iter().map(|configure| match policy {
+                TimeoutPolicy::Cancel => WmEvent::ConfigureCancelled {
+                    toplevel,
+                    serial: configure.serial,
+                },
+                // The wm continues as if the client acked the configure. The toplevel catches up when the
+                // client eventually commits.
+                TimeoutPolicy::Apply => WmEvent::ConfigureTimedOut { toplevel, configure },
+            }));
+        }
+
+        for event in events {
+            self.wm.send(event);
+        }
+    }
+
+    /// Remove the views of wms which stopped from the scene.
+    pub fn refresh_wm_views(&mut self) {
+        for node in std::mem::take(&mut self.wm.removed_views) {
+            self.destroy_wm_view(node);
+        }
+    }
+
+    /// Remove the scene node of a view the wm dropped.
+    ///
+    /// Only the nodes the wm owns are removed, views of toplevels present the surfaces of the toplevels and are
+    /// only taken out of the scene.
+    fn destroy_wm_view(&mut self, node: NodeIndex) {
+        match node {
+            NodeIndex::SolidColor(index) => self.scene.destroy_solid_color(index),
+            NodeIndex::Branch(index) => self.scene.destroy_branch(index),
+            NodeIndex::SurfaceTree(_) => self.scene.detach_node(node),
+        }
+    }
+
+    /// Convert a size from the wm to the size in the scene.
+    fn wm_size(&self, size: LogicalSize) -> Size<i32, Physical> {
+        let scale = self.output.current_scale().fractional_scale();
+        Size::<i32, Logical>::from((
+            i32::try_from(size.width).unwrap_or(i32::MAX),
+            i32::try_from(size.height).unwrap_or(i32::MAX),
+        ))
+        .to_physical_precise_round(scale)
+    }
+
+    /// Resolve the objects referenced by a transaction from the wm.
+    ///
+    /// Returns [`None`] if the transaction references a view, output or workspace which does not exist.
+    fn wm_transaction(&self, operations: Vec<SceneOperation>) -> Option<Transaction> {
+        let scale = self.output.current_scale().fractional_scale();
+        let view = |id: Id| self.wm.views.get(&id).copied();
+        let mut transaction = Transaction::new();
+
+        for operation in operations {
+            match operation {
+                SceneOperation::Animate { duration, easing } => {
+                    transaction.animate(Animation {
+                        duration,
+                        easing: to_easing(easing),
+                    });
+                }
+
+                SceneOperation::SetPosition { view: id, x, y } => {
+                    let offset = Point::<i32, Logical>::from((x, y)).to_physical_precise_round(scale);
+                    transaction.set_offset(view(id)?, offset);
+                }
+
+                SceneOperation::PlaceAbove { view: id, sibling } => {
+                    transaction.place_above(view(id)?, view(sibling)?);
+                }
+
+                SceneOperation::PlaceBelow { view: id, sibling } => {
+                    transaction.place_below(view(id)?, view(sibling)?);
+                }
+
+                SceneOperation::Reparent { view: id, parent } => {
+                    // Only branches may have children.
+                    let NodeIndex::Branch(parent) = view(parent)? else {
+                        return None;
+                    };
+
+                    transaction.reparent(view(id)?, parent);
+                }
+
+                SceneOperation::SetOutput { output, view: id } => {
+                    let output = self.wm.outputs.get(&output)?.clone();
+                    transaction.set_output(output, view(id)?);
+                }
+
+                SceneOperation::SetScale { view: id, scale } => {
+                    transaction.set_scale(view(id)?, scale.into());
+                }
+
+                SceneOperation::SetTransform { view: id, transform } => {
+                    transaction.set_transform(view(id)?, to_transform(transform));
+                }
+
+                SceneOperation::SetOpacity { view: id, opacity } => {
+                    transaction.set_opacity(view(id)?, opacity);
+                }
+
+                SceneOperation::SetClip { view: id, clip } => {
+                    let clip = clip.map(|clip| from_wm_rect(clip).to_physical_precise_round(scale));
+                    transaction.set_clip(view(id)?, clip);
+                }
+
+                SceneOperation::SetSolidColor { view: id, color, size } => {
+                    // Only solid color views have a color.
+                    let NodeIndex::SolidColor(node) = view(id)? else {
+                        return None;
+                    };
+
+                    transaction.set_solid_color(node, self.wm_size(size), to_color(color));
+                }
+
+                SceneOperation::SetWorkspace { view: id, workspace } => {
+                    let workspace = self.wm.workspaces.get(workspace)?;
+                    transaction.reparent(view(id)?, workspace.branch);
+                }
+
+                // The previously active workspace of the output is no longer presented once the workspace is
+                // presented in its place.
+                SceneOperation::ActivateWorkspace { workspace } => {
+                    let workspace = self.wm.workspaces.get(workspace)?;
+                    transaction.set_output(workspace.output.clone(), NodeIndex::Branch(workspace.branch));
+                }
+            }
+        }
+
+        Some(transaction)
+    }
+}
+
+/// The id the wm uses for an output.
+///
+/// Returns [`None`] if the id does not fit in the id space of the wm.
+pub(crate) fn wm_output_id(output: ToplevelId) -> Option<Id> {
+    u32::try_from(output.get())
+        .ok()
+        .and_then(NonZeroU32::new)
+        .map(Id::output)
+}
+
+/// The state of an output visible to the wm.
+fn output_info(output: &Output) -> OutputInfo {
+    let geometry = output_geometry(output);
+    let refresh_rate = output
+        .current_mode()
+        .map_or(0, |mode| u32::try_from(mode.refresh).unwrap_or_default());
+
+    OutputInfo {
+        name: Some(output.name()),
+        geometry: to_wm_rect(geometry),
+        refresh_rate,
+    }
+}
+
+/// Convert a rectangle in the global compositor space to the geometry used by the wm runtime.
+fn to_wm_rect(rect: Rectangle<i32, Logical>) -> LogicalRect {
+    LogicalRect::new(
+        point2(rect.loc.x, rect.loc.y),
+        size2(rect.size.w.max(0), rect.size.h.max(0)),
+    )
+}
+
+/// Convert geometry from the wm runtime to a rectangle in the global compositor space.
+fn from_wm_rect(rect: LogicalRect) -> Rectangle<i32, Logical> {
+    Rectangle::from_loc_and_size(
+        (rect.origin.x, rect.origin.y),
+        (rect.size.width.max(0), rect.size.height.max(0)),
+    )
+}
+
+fn to_easing(easing: Easing) -> animation::Easing {
+    match easing {
+        Easing::Linear => animation::Easing::Linear,
+        Easing::EaseIn => animation::Easing::EaseIn,
+        Easing::EaseOut => animation::Easing::EaseOut,
+        Easing::EaseInOut => animation::Easing::EaseInOut,
+    }
+}
+
+fn to_transform(transform: ViewTransform) -> Transform {
+    match transform {
+        ViewTransform::Normal => Transform::Normal,
+        ViewTransform::Rotate90 => Transform::_90,
+        ViewTransform::Rotate180 => Transform::_180,
+        ViewTransform::Rotate270 => Transform::_270,
+        ViewTransform::Flipped => Transform::Flipped,
+        ViewTransform::Flipped90 => Transform::Flipped90,
+        ViewTransform::Flipped180 => Transform::Flipped180,
+        ViewTransform::Flipped270 => Transform::Flipped270,
+    }
+}
+
+/// Convert a color from the wm, as 0xRRGGBBAA, to the color used by the renderer.
+fn to_color(color: u32) -> [f32; 4] {
+    color.to_be_bytes().map(|channel| f32::from(channel) / 255.0)
+}
+
+/// Convert a size in physical pixels from the wm runtime.
+fn to_physical_size(size: PhysicalSize) -> Size<i32, Physical> {
+    (
+        i32::try_from(size.width).unwrap_or(i32::MAX),
+        i32::try_from(size.height).unwrap_or(i32::MAX),
+    )
+        .into()
+}
+
+/// The id the wm uses for a toplevel.
+///
+/// Returns [`None`] if the id does not fit in the id space of the wm.
+pub(crate) fn wm_toplevel_id(toplevel: ToplevelId) -> Option<Id> {
+    u32::try_from(toplevel.get())
+        .ok()
+        .and_then(NonZeroU32::new)
+        .map(Id::toplevel)
+}
+
+/// The id the wm uses for a surface node.
+///
+/// Returns [`None`] if the id does not fit in the id space of the wm.
+pub(crate) fn wm_surface_node_id(node: ToplevelId) -> Option<Id> {
+    u32::try_from(node.get())
+        .ok()
+        .and_then(NonZeroU32::new)
+        .map(Id::surface_node)
+}
+
+/// The toplevel referenced by an id from the wm.
+///
+/// The wm refers to a toplevel using the same id as the compositor.
+fn toplevel_id(id: Id) -> ToplevelId {
+    NonZeroU64::from(id.rep())
+}
+
+/// The backoff before the wm is restarted after failing the specified number of times.
+fn backoff(crashes: usize) -> Duration {
+    let exponent = crashes.saturating_sub(1).min(16) as u32;
+    INITIAL_BACKOFF.saturating_mul(2u32.pow(exponent)).min(MAX_BACKOFF)
+}
+
+/// Write the quarantined event to the state directory.
+fn quarantine(report: &CrashReport) -> io::Result<PathBuf> {
+    let dir = state_dir().join("quarantine");
+    fs::create_dir_all(&dir)?;
+
+    let path = dir.join(format!("{}.log", timestamp()));
+
+    fs::write(&path, format!("event: {}\nerror: {}\n", report.event, report.error))?;
+    Ok(path)
+}
+
+/// Seconds since the unix epoch, used to name files in the state directory.
+pub(crate) fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+/// `$XDG_STATE_HOME/aerugo`, falling back to `~/.local/state/aerugo`.
+pub(crate) fn state_dir() -> PathBuf {
+    std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("aerugo")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{backoff, INITIAL_BACKOFF, MAX_BACKOFF};
+
+    #[test]
+    fn backoff_doubles() {
+        assert_eq!(backoff(1), INITIAL_BACKOFF);
+        assert_eq!(backoff(2), INITIAL_BACKOFF * 2);
+        assert_eq!(backoff(3), INITIAL_BACKOFF * 4);
+    }
+
+    #[test]
+    fn backoff_capped() {
+        assert_eq!(backoff(100), MAX_BACKOFF);
+        assert!(backoff(8) <= Duration::from_secs(30));
+    }
+}
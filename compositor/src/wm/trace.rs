@@ -0,0 +1,193 @@
+//! Recording of recent wm traffic.
+//!
+//! The supervisor records every event sent to the wm and every request received from the wm into a bounded
+//! ring. The ring can be written to disk on demand so a failure reported by a user can be turned into a
+//! reproducible test.
+//!
+//! # Format
+//!
+//! A trace is a text file. The first line is a header (`# aerugo wm trace v1`). Every following line is an
+//! entry made of the milliseconds since the first entry, the direction of the message and the message itself:
+//!
+//! ```text
+//! # aerugo wm trace v1
+//! 0 event NewToplevel { toplevel: Id(1, Toplevel), features: Features(SERVER_SIDE_DECORATIONS) }
+//! 16 request ToplevelRequestClose(Id(1, Toplevel))
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    fs,
+    io::{self, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// Header of a trace file.
+pub const HEADER: &str = "# aerugo wm trace v1";
+
+/// How long entries are kept by default.
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(5 * 60);
+
+/// The maximum number of entries kept, regardless of the retention.
+const MAX_ENTRIES: usize = 16384;
+
+/// The direction of a recorded message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// An event sent to the wm.
+    Event,
+
+    /// A request received from the wm.
+    Request,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Event => "event",
+            Direction::Request => "request",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    time: Instant,
+    direction: Direction,
+    message: String,
+}
+
+/// A ring of recent wm traffic.
+#[derive(Debug)]
+pub struct WmTrace {
+    entries: VecDeque<Entry>,
+    retention: Duration,
+}
+
+impl WmTrace {
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            retention,
+        }
+    }
+
+    /// Record a message.
+    pub fn record(&mut self, direction: Direction, message: &impl Debug) {
+        self.record_at(Instant::now(), direction, format!("{message:?}"));
+    }
+
+    fn record_at(&mut self, time: Instant, direction: Direction, message: String) {
+        self.entries.push_back(Entry {
+            time,
+            direction,
+            message,
+        });
+        self.expire(time);
+    }
+
+    /// Remove entries older than the retention.
+    fn expire(&mut self, now: Instant) {
+        while let Some(entry) = self.entries.front() {
+            if self.entries.len() <= MAX_ENTRIES && now.duration_since(entry.time) <= self.retention {
+                break;
+            }
+
+            self.entries.pop_front();
+        }
+    }
+
+    /// Write the entries from the last `duration` into the writer.
+    ///
+    /// If `duration` is [`None`], every entry in the ring is written.
+    pub fn write(&self, duration: Option<Duration>, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "{HEADER}")?;
+
+        let now = Instant::now();
+        let mut entries = self
+            .entries
+            .iter()
+            .filter(|entry| duration.map_or(true, |duration| now.duration_since(entry.time) <= duration))
+            .peekable();
+
+        let Some(start) = entries.peek().map(|entry| entry.time) else {
+            return Ok(());
+        };
+
+        for entry in entries {
+            // Messages are written one per line.
+            let message = entry.message.replace('\n', " ");
+            writeln!(
+                writer,
+                "{} {} {}",
+                entry.time.duration_since(start).as_millis(),
+                entry.direction.as_str(),
+                message
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the entries from the last `duration` to a file.
+    pub fn dump(&self, duration: Option<Duration>, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = io::BufWriter::new(fs::File::create(path)?);
+        self.write(duration, file)
+    }
+}
+
+impl Default for WmTrace {
+    fn default() -> Self {
+        Self::new(DEFAULT_RETENTION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Direction, WmTrace, HEADER};
+
+    #[test]
+    fn expire_old_entries() {
+        let mut trace = WmTrace::new(Duration::from_secs(10));
+        let start = Instant::now();
+
+        trace.record_at(start, Direction::Event, "a".into());
+        trace.record_at(start + Duration::from_secs(5), Direction::Request, "b".into());
+        assert_eq!(trace.entries.len(), 2);
+
+        // The first entry is now older than the retention.
+        trace.record_at(start + Duration::from_secs(12), Direction::Event, "c".into());
+        assert_eq!(trace.entries.len(), 2);
+    }
+
+    #[test]
+    fn write_format() {
+        let mut trace = WmTrace::default();
+        let start = Instant::now();
+
+        trace.record_at(start, Direction::Event, "NewToplevel".into());
+        trace.record_at(
+            start + Duration::from_millis(16),
+            Direction::Request,
+            "Close\n(1)".into(),
+        );
+
+        let mut out = Vec::new();
+        trace.write(None, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let mut lines = out.lines();
+
+        assert_eq!(lines.next(), Some(HEADER));
+        assert_eq!(lines.next(), Some("0 event NewToplevel"));
+        assert_eq!(lines.next(), Some("16 request Close (1)"));
+        assert_eq!(lines.next(), None);
+    }
+}
@@ -0,0 +1,208 @@
+//! Workspaces created by the wm
+//!
+//! A workspace is a branch in the scene which the wm assigns views to. Activating a workspace presents the
+//! branch of the workspace on the output of the workspace, so only the views of the active workspace of each
+//! output are presented. Since activation is a scene operation, the wm can switch workspaces atomically with
+//! the rest of a layout change.
+//!
+//! The workspaces are reflected to clients such as pagers and bars using the `ext-workspace-v1` protocol.
+
+use smithay::output::Output;
+use wm_runtime::Id;
+
+use crate::{
+    scene::{BranchIndex, NodeIndex, Scene},
+    Aerugo,
+};
+
+#[derive(Debug)]
+pub struct Workspace {
+    /// Human readable name chosen by the wm.
+    pub name: String,
+
+    pub output: Output,
+
+    /// The branch the views of the workspace are children of.
+    pub branch: BranchIndex,
+
+    /// Whether the branch of the workspace is presented on the output.
+    pub active: bool,
+}
+
+/// The workspaces of the wm, in the order the wm created them.
+#[derive(Debug, Default)]
+pub struct Workspaces {
+    workspaces: Vec<(Id, Workspace)>,
+
+    /// Workspaces removed since the last refresh.
+    ///
+    /// The branches of these workspaces still need to be removed from the scene.
+    removed: Vec<Workspace>,
+}
+
+impl Workspaces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, id: Id, workspace: Workspace) {
+        self.remove(id);
+        self.workspaces.push((id, workspace));
+    }
+
+    pub fn remove(&mut self, id: Id) {
+        self.retain(|&workspace, _| workspace != id);
+    }
+
+    /// Remove every workspace, such as when the wm which created the workspaces stopped.
+    pub fn clear(&mut self) {
+        self.retain(|_, _| false);
+    }
+
+    /// Remove the workspaces of a disconnected output.
+    pub fn remove_output(&mut self, output: &Output) {
+        self.retain(|_, workspace| workspace.output != *output);
+    }
+
+    pub fn get(&self, id: Id) -> Option<&Workspace> {
+        self.iter()
+            .find_map(|(workspace, state)| (workspace == id).then_some(state))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Id, &Workspace)> {
+        self.workspaces.iter().map(|(id, workspace)| (*id, workspace))
+    }
+
+    /// Take the workspaces removed since the last call.
+    pub fn take_removed(&mut self) -> Vec<Workspace> {
+        std::mem::take(&mut self.removed)
+    }
+
+    /// Update which workspaces are active from the nodes presented on each output.
+    ///
+    /// The wm may present a node other than a workspace on an output, in which case no workspace of the output
+    /// is active.
+    pub fn update_active(&mut self, scene: &Scene) {
+        for (_, workspace) in &mut self.workspaces {
+            workspace.active = scene.output_node(&workspace.output) == Some(NodeIndex::Branch(workspace.branch));
+        }
+    }
+
+    fn retain(&mut self, mut f: impl FnMut(&Id, &Workspace) -> bool) {
+        let (kept, removed) = self
+            .workspaces
+            .drain(..)
+            .partition::<Vec<_>, _>(|(id, workspace)| f(id, workspace));
+
+        self.workspaces = kept;
+        self.removed.extend(removed.into_iter().map(|(_, workspace)| workspace));
+    }
+}
+
+impl Aerugo {
+    /// Apply the workspace changes made by the wm and reflect the workspaces to clients.
+    pub fn refresh_workspaces(&mut self) {
+        for workspace in self.wm.workspaces_mut().take_removed() {
+            self.scene.destroy_branch(workspace.branch);
+        }
+
+        self.wm.workspaces_mut().update_active(&self.scene);
+        self.ext_workspace.refresh(&self.display, self.wm.workspaces());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use smithay::output::{Output, PhysicalProperties, Subpixel};
+    use wm_runtime::Id;
+
+    use crate::scene::{NodeIndex, Scene};
+
+    use super::{Workspace, Workspaces};
+
+    fn output(name: &str) -> Output {
+        Output::new(
+            name.into(),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: String::new(),
+                model: String::new(),
+            },
+        )
+    }
+
+    fn id(rep: u32) -> Id {
+        Id::workspace(NonZeroU32::new(rep).unwrap())
+    }
+
+    #[test]
+    fn active_follows_output_node() {
+        let mut scene = Scene::new();
+        let [first, second] = [output("first"), output("second")];
+        scene.create_output(first.clone());
+        scene.create_output(second.clone());
+
+        let mut workspaces = Workspaces::new();
+        let branches = [(); 3].map(|_| scene.create_branch());
+
+        for (rep, (output, branch)) in [&first, &first, &second].into_iter().zip(branches).enumerate() {
+            workspaces.insert(
+                id(rep as u32 + 1),
+                Workspace {
+                    name: rep.to_string(),
+                    output: output.clone(),
+                    branch,
+                    active: false,
+                },
+            );
+        }
+
+        scene.set_output_node(&first, NodeIndex::Branch(branches[1]));
+        scene.set_output_node(&second, NodeIndex::Branch(branches[2]));
+        workspaces.update_active(&scene);
+
+        let active = workspaces
+            .iter()
+            .map(|(_, workspace)| workspace.active)
+            .collect::<Vec<_>>();
+        assert_eq!(active, [false, true, true]);
+
+        // Presenting another node deactivates the workspaces of the output.
+        let other = scene.create_branch();
+        scene.set_output_node(&second, NodeIndex::Branch(other));
+        workspaces.update_active(&scene);
+        assert!(!workspaces.get(id(3)).unwrap().active);
+        assert!(workspaces.get(id(2)).unwrap().active);
+    }
+
+    #[test]
+    fn removed_workspaces_are_kept_until_taken() {
+        let mut scene = Scene::new();
+        let [first, second] = [output("first"), output("second")];
+        let mut workspaces = Workspaces::new();
+
+        for (rep, output) in [(1, &first), (2, &second), (3, &first)] {
+            workspaces.insert(
+                id(rep),
+                Workspace {
+                    name: rep.to_string(),
+                    output: output.clone(),
+                    branch: scene.create_branch(),
+                    active: false,
+                },
+            );
+        }
+
+        workspaces.remove_output(&first);
+        assert_eq!(workspaces.iter().map(|(id, _)| id).collect::<Vec<_>>(), [id(2)]);
+        assert_eq!(workspaces.take_removed().len(), 2);
+        assert!(workspaces.take_removed().is_empty());
+
+        workspaces.clear();
+        assert_eq!(workspaces.iter().count(), 0);
+        assert_eq!(workspaces.take_removed().len(), 1);
+    }
+}
@@ -0,0 +1,432 @@
+//! Bounded channel carrying events to the wm thread.
+//!
+//! A wm which is slow to handle events must not make events pile up without bound. The channel holds at most
+//! [`EVENT_CHANNEL_CAPACITY`] events, and events which supersede a queued event are coalesced before they count
+//! against the capacity:
+//!
+//! - An [`WmEvent::UpdateToplevel`] is merged into a queued update of the same toplevel, as long as only updates
+//!   of toplevels were queued after it and the updates do not both carry a state request.
+//! - A [`WmEvent::UpdateOutput`] replaces queued updates of the same output, since only the latest state of the
+//!   output is kept.
+//!
+//! An event sent to a full channel is dropped. Since the wm missed an event, the state of the wm can no longer be
+//! trusted, so the wm thread treats an overflow like a failure of the wm once it receives the next event.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+};
+
+use crate::{ConfigureUpdate, ToplevelUpdate, WmEvent};
+
+/// The most events which may wait to be handled by the wm.
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Create a channel holding at most `capacity` events.
+pub(crate) fn channel(capacity: usize) -> (EventSender, EventReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            events: VecDeque::new(),
+            capacity,
+            senders: 1,
+            receiver: true,
+            dropped: 0,
+        }),
+        ready: Condvar::new(),
+    });
+
+    (EventSender { shared: shared.clone() }, EventReceiver { shared })
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+
+    /// Notified when an event was queued or the last sender was dropped.
+    ready: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    events: VecDeque<WmEvent>,
+    capacity: usize,
+
+    /// The number of live senders.
+    senders: usize,
+
+    /// Whether the receiver is alive.
+    receiver: bool,
+
+    /// The number of events dropped since the receiver was last told about an overflow.
+    dropped: u64,
+}
+
+/// Sends events to the wm.
+///
+/// The sender may be cloned and used from any thread.
+#[derive(Debug)]
+pub struct EventSender {
+    shared: Arc<Shared>,
+}
+
+/// An error returned when an event could not be sent.
+///
+/// The event which could not be sent is returned.
+#[derive(Debug)]
+pub enum SendError {
+    /// The wm did not keep up with events. The wm thread reports the overflow.
+    Full(WmEvent),
+
+    /// The wm thread stopped.
+    Disconnected(WmEvent),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Full(_) => write!(f, "wm event channel is full"),
+            SendError::Disconnected(_) => write!(f, "wm thread stopped"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+impl EventSender {
+    /// Send an event to the wm, coalescing the event with queued events if possible.
+    pub fn send(&self, event: WmEvent) -> Result<(), SendError> {
+        let mut state = self.shared.lock();
+
+        if !state.receiver {
+            return Err(SendError::Disconnected(event));
+        }
+
+        let Some(event) = coalesce(&mut state.events, event) else {
+            return Ok(());
+        };
+
+        if state.events.len() >= state.capacity {
+            state.dropped += 1;
+            return Err(SendError::Full(event));
+        }
+
+        state.events.push_back(event);
+        self.shared.ready.notify_one();
+        Ok(())
+    }
+}
+
+impl Clone for EventSender {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+
+        if state.senders == 0 {
+            self.shared.ready.notify_one();
+        }
+    }
+}
+
+/// What the wm thread received.
+#[derive(Debug)]
+pub(crate) enum Received {
+    Event(WmEvent),
+
+    /// Events were dropped because the channel was full.
+    Overflow {
+        dropped: u64,
+    },
+}
+
+/// Receives events on the wm thread.
+#[derive(Debug)]
+pub(crate) struct EventReceiver {
+    shared: Arc<Shared>,
+}
+
+impl EventReceiver {
+    /// Wait for the next event.
+    ///
+    /// An overflow is reported before the events queued after it. Returns [`None`] once every sender was dropped
+    /// and no events are left.
+    pub fn recv(&self) -> Option<Received> {
+        let mut state = self.shared.lock();
+
+        loop {
+            if state.dropped > 0 {
+                return Some(Received::Overflow {
+                    dropped: std::mem::take(&mut state.dropped),
+                });
+            }
+
+            if let Some(event) = state.events.pop_front() {
+                return Some(Received::Event(event));
+            }
+
+            if state.senders == 0 {
+                return None;
+            }
+
+            state = self
+                .shared
+                .ready
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// The number of queued events.
+    pub fn len(&self) -> usize {
+        self.shared.lock().events.len()
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver = false;
+        state.events.clear();
+    }
+}
+
+/// Coalesce an event with the queued events, returning the event if it still has to be queued.
+fn coalesce(events: &mut VecDeque<WmEvent>, event: WmEvent) -> Option<WmEvent> {
+    match event {
+        WmEvent::UpdateToplevel { toplevel, update } => {
+            // Only look past updates, so the update is not moved before other events of the toplevel.
+            let queued = events
+                .iter_mut()
+                .rev()
+                .map_while(|queued| match queued {
+                    WmEvent::UpdateToplevel { toplevel: id, update } => Some((*id, update)),
+                    _ => None,
+                })
+                .find(|(id, _)| *id == toplevel)
+                .map(|(_, update)| update);
+
+            match queued {
+                Some(queued) if !(queued.request.is_some() && update.request.is_some()) => {
+                    merge_update(queued, update);
+                    None
+                }
+                _ => Some(WmEvent::UpdateToplevel { toplevel, update }),
+            }
+        }
+
+        WmEvent::UpdateOutput { output, info } => {
+            events.retain(|queued| !matches!(queued, WmEvent::UpdateOutput { output: id, .. } if *id == output));
+            Some(WmEvent::UpdateOutput { output, info })
+        }
+
+        event => Some(event),
+    }
+}
+
+/// Apply a newer update on top of a queued update.
+fn merge_update(queued: &mut ToplevelUpdate, update: ToplevelUpdate) {
+    fn merge<T>(queued: &mut ConfigureUpdate<T>, update: ConfigureUpdate<T>) {
+        if update.is_update() {
+            *queued = update;
+        }
+    }
+
+    let ToplevelUpdate {
+        app_id,
+        title,
+        min_size,
+        max_size,
+        geometry,
+        parent,
+        state,
+        decorations,
+        preferred_decorations,
+        decoration_style,
+        resize_edge,
+        request,
+    } = update;
+
+    queued.app_id = app_id.or(queued.app_id.take());
+    queued.title = title.or(queued.title.take());
+    merge(&mut queued.min_size, min_size);
+    merge(&mut queued.max_size, max_size);
+    merge(&mut queued.geometry, geometry);
+    merge(&mut queued.parent, parent);
+    queued.state = state.or(queued.state.take());
+    queued.decorations = decorations.or(queued.decorations.take());
+    merge(&mut queued.preferred_decorations, preferred_decorations);
+    queued.decoration_style = decoration_style.or(queued.decoration_style.take());
+    merge(&mut queued.resize_edge, resize_edge);
+    queued.request = request.or(queued.request.take());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use crate::{
+        geometry::LogicalRect, AckedConfigure, ConfigureRequest, ConfigureUpdate, Id, OutputInfo, StateRequest,
+        ToplevelUpdate, WmEvent,
+    };
+
+    use super::{channel, Received};
+
+    fn toplevel(rep: u32) -> Id {
+        Id::toplevel(NonZeroU32::new(rep).unwrap())
+    }
+
+    fn output(rep: u32) -> Id {
+        Id::output(NonZeroU32::new(rep).unwrap())
+    }
+
+    fn update(toplevel: Id, update: ToplevelUpdate) -> WmEvent {
+        WmEvent::UpdateToplevel { toplevel, update }
+    }
+
+    fn output_update(output: Id, refresh_rate: u32) -> WmEvent {
+        WmEvent::UpdateOutput {
+            output,
+            info: OutputInfo {
+                name: None,
+                geometry: LogicalRect::zero(),
+                refresh_rate,
+            },
+        }
+    }
+
+    fn drain(receiver: &super::EventReceiver) -> Vec<WmEvent> {
+        let mut events = Vec::new();
+
+        while receiver.len() > 0 {
+            match receiver.recv() {
+                Some(Received::Event(event)) => events.push(event),
+                other => panic!("unexpected {other:?}"),
+            }
+        }
+
+        events
+    }
+
+    #[test]
+    fn merge_toplevel_updates() {
+        let (sender, receiver) = channel(8);
+
+        let title = ToplevelUpdate {
+            title: Some("first".into()),
+            ..Default::default()
+        };
+        let app_id = ToplevelUpdate {
+            app_id: Some("app".into()),
+            geometry: ConfigureUpdate::Update(None),
+            ..Default::default()
+        };
+        let retitle = ToplevelUpdate {
+            title: Some("second".into()),
+            ..Default::default()
+        };
+
+        sender.send(update(toplevel(1), title)).unwrap();
+        sender.send(update(toplevel(2), ToplevelUpdate::default())).unwrap();
+        sender.send(update(toplevel(1), app_id)).unwrap();
+        sender.send(update(toplevel(1), retitle)).unwrap();
+
+        let events = drain(&receiver);
+        assert_eq!(events.len(), 2);
+
+        let WmEvent::UpdateToplevel { toplevel: id, update } = &events[0] else {
+            panic!("expected an update");
+        };
+        assert_eq!(*id, toplevel(1));
+        assert_eq!(update.title.as_deref(), Some("second"));
+        assert_eq!(update.app_id.as_deref(), Some("app"));
+        assert!(update.geometry.is_update());
+    }
+
+    #[test]
+    fn keep_order_with_other_events() {
+        let (sender, receiver) = channel(8);
+
+        sender.send(update(toplevel(1), ToplevelUpdate::default())).unwrap();
+        sender
+            .send(WmEvent::AckToplevel {
+                toplevel: toplevel(1),
+                configure: AckedConfigure {
+                    serial: 1,
+                    request: ConfigureRequest::default(),
+                },
+            })
+            .unwrap();
+        sender.send(update(toplevel(1), ToplevelUpdate::default())).unwrap();
+
+        // Two state requests cannot be merged either.
+        let maximize = ToplevelUpdate {
+            request: Some(StateRequest::SetMaximized),
+            ..Default::default()
+        };
+        sender.send(update(toplevel(1), maximize.clone())).unwrap();
+        sender.send(update(toplevel(1), maximize)).unwrap();
+
+        assert_eq!(drain(&receiver).len(), 4);
+    }
+
+    #[test]
+    fn drop_stale_output_updates() {
+        let (sender, receiver) = channel(8);
+
+        sender.send(output_update(output(1), 60_000)).unwrap();
+        sender.send(output_update(output(2), 60_000)).unwrap();
+        sender.send(output_update(output(1), 144_000)).unwrap();
+
+        let events = drain(&receiver);
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            &events[1],
+            WmEvent::UpdateOutput { output: id, info } if *id == output(1) && info.refresh_rate == 144_000
+        ));
+    }
+
+    #[test]
+    fn report_overflow() {
+        let (sender, receiver) = channel(2);
+
+        sender.send(WmEvent::Timer(1)).unwrap();
+        sender.send(WmEvent::Timer(2)).unwrap();
+        assert!(sender.send(WmEvent::Timer(3)).is_err());
+        assert!(sender.send(WmEvent::Timer(4)).is_err());
+
+        assert!(matches!(receiver.recv(), Some(Received::Overflow { dropped: 2 })));
+        assert!(matches!(receiver.recv(), Some(Received::Event(WmEvent::Timer(1)))));
+
+        drop(receiver);
+        assert!(sender.send(WmEvent::Timer(5)).is_err());
+    }
+
+    #[test]
+    fn close_when_senders_dropped() {
+        let (sender, receiver) = channel(2);
+        let clone = sender.clone();
+
+        sender.send(WmEvent::Shutdown).unwrap();
+        drop(sender);
+        drop(clone);
+
+        assert!(matches!(receiver.recv(), Some(Received::Event(WmEvent::Shutdown))));
+        assert!(receiver.recv().is_none());
+    }
+}
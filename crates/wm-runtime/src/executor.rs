@@ -0,0 +1,136 @@
+//! Execution of guest calls.
+//!
+//! Calls into the wm are futures which yield back to the wm thread every time the epoch of the engine advances.
+//! The wm thread drives the futures using [`block_on_timeout`], which gives up on a call that takes too long.
+
+use std::{
+    future::Future,
+    io,
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use wasmtime::Engine;
+
+/// How often the epoch of the engine advances.
+///
+/// The wm yields back to the wm thread at every epoch, so this is the resolution of the callback time limit.
+pub const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Drive a future to completion on the current thread.
+///
+/// Returns [`None`] if the future did not complete within the time limit. The future is dropped in that case,
+/// which unwinds the guest.
+pub fn block_on_timeout<F: Future>(future: F, limit: Duration) -> Option<F::Output> {
+    let deadline = Instant::now() + limit;
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return Some(output);
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return None;
+        }
+
+        thread::park_timeout(deadline - now);
+    }
+}
+
+/// Wakes the thread driving a future.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Advances the epoch of an engine every [`EPOCH_TICK`] until dropped.
+#[derive(Debug)]
+pub struct EpochTicker {
+    stop: Arc<AtomicBool>,
+}
+
+impl EpochTicker {
+    pub fn start(engine: Engine) -> io::Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        thread::Builder::new().name("aerugo wm epoch".into()).spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                thread::sleep(EPOCH_TICK);
+                engine.increment_epoch();
+            }
+        })?;
+
+        Ok(Self { stop })
+    }
+}
+
+impl Drop for EpochTicker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::{self, Future},
+        pin::Pin,
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use super::block_on_timeout;
+
+    /// A future which yields the specified number of times before completing.
+    struct Yield(u32);
+
+    impl Future for Yield {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 == 0 {
+                return Poll::Ready(());
+            }
+
+            self.0 -= 1;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn ready_future_completes() {
+        assert_eq!(block_on_timeout(future::ready(1), Duration::ZERO), Some(1));
+    }
+
+    #[test]
+    fn yielding_future_completes() {
+        assert_eq!(block_on_timeout(Yield(10), Duration::from_secs(10)), Some(()));
+    }
+
+    #[test]
+    fn pending_future_times_out() {
+        assert_eq!(
+            block_on_timeout(future::pending::<()>(), Duration::from_millis(10)),
+            None
+        );
+    }
+}
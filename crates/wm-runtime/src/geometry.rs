@@ -0,0 +1,129 @@
+//! Typed geometry.
+//!
+//! Geometry exchanged between the display server and the runtime uses [`euclid`] types tagged with the coordinate
+//! space, so a size in physical pixels cannot be passed where a logical size is expected. The wit records
+//! describing geometry carry no coordinate space and are converted when crossing into the wm.
+
+pub use euclid;
+
+use euclid::{point2, size2, Rect, Size2D};
+
+use crate::{Geometry, Size};
+
+/// The global compositor space.
+///
+/// Logical coordinates are scaled by the scale of an output to get the physical pixels of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Logical {}
+
+/// Pixels of an output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Physical {}
+
+/// Pixels of a buffer attached by a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Buffer {}
+
+pub type LogicalSize = Size2D<u32, Logical>;
+pub type LogicalRect = Rect<i32, Logical>;
+pub type PhysicalSize = Size2D<u32, Physical>;
+pub type BufferSize = Size2D<u32, Buffer>;
+
+/// Scale a logical size to the physical pixels of an output with the specified scale.
+///
+/// Each dimension is rounded to the nearest pixel.
+pub fn to_physical(size: LogicalSize, scale: f64) -> PhysicalSize {
+    size2(scale_dimension(size.width, scale), scale_dimension(size.height, scale))
+}
+
+/// Scale physical pixels of an output with the specified scale to a logical size.
+///
+/// Each dimension is rounded to the nearest pixel.
+pub fn to_logical(size: PhysicalSize, scale: f64) -> LogicalSize {
+    size2(
+        scale_dimension(size.width, scale.recip()),
+        scale_dimension(size.height, scale.recip()),
+    )
+}
+
+fn scale_dimension(value: u32, scale: f64) -> u32 {
+    // Float to integer casts saturate, so a size never wraps around.
+    (f64::from(value) * scale).round() as u32
+}
+
+impl From<Size> for LogicalSize {
+    fn from(size: Size) -> Self {
+        size2(size.width, size.height)
+    }
+}
+
+impl From<LogicalSize> for Size {
+    fn from(size: LogicalSize) -> Self {
+        Size {
+            width: size.width,
+            height: size.height,
+        }
+    }
+}
+
+impl From<Geometry> for LogicalRect {
+    fn from(geometry: Geometry) -> Self {
+        Rect::new(
+            point2(geometry.x, geometry.y),
+            size2(
+                i32::try_from(geometry.width).unwrap_or(i32::MAX),
+                i32::try_from(geometry.height).unwrap_or(i32::MAX),
+            ),
+        )
+    }
+}
+
+impl From<LogicalRect> for Geometry {
+    fn from(rect: LogicalRect) -> Self {
+        Geometry {
+            x: rect.origin.x,
+            y: rect.origin.y,
+            width: rect.size.width.max(0) as u32,
+            height: rect.size.height.max(0) as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::{point2, size2, Rect};
+
+    use super::{to_logical, to_physical, LogicalRect, LogicalSize, PhysicalSize};
+    use crate::Geometry;
+
+    #[test]
+    fn scale_sizes() {
+        let logical: LogicalSize = size2(100, 51);
+        let physical: PhysicalSize = size2(150, 77);
+
+        assert_eq!(to_physical(logical, 1.5), physical);
+        assert_eq!(to_logical(physical, 1.5), logical);
+        assert_eq!(to_physical(logical, 1.0).to_untyped(), logical.to_untyped());
+    }
+
+    #[test]
+    fn scale_saturates() {
+        assert_eq!(to_physical(size2(u32::MAX, 1), 2.0), size2(u32::MAX, 2));
+    }
+
+    #[test]
+    fn geometry_round_trip() {
+        let rect: LogicalRect = Rect::new(point2(-10, 20), size2(30, 40));
+        let geometry = Geometry::from(rect);
+
+        assert_eq!(
+            (geometry.x, geometry.y, geometry.width, geometry.height),
+            (-10, 20, 30, 40)
+        );
+        assert_eq!(LogicalRect::from(geometry), rect);
+
+        // Negative sizes are not representable by the wm.
+        let negative: LogicalRect = Rect::new(point2(0, 0), size2(-1, 5));
+        assert_eq!(Geometry::from(negative).width, 0);
+    }
+}
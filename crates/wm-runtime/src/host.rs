@@ -2,26 +2,58 @@
 //!
 //! This crate implements the wm runtime used by Aerugo.
 
-use std::num::NonZeroU32;
+use std::{
+    mem,
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
 
 use wasmtime::component::Resource;
 
-use crate::{ConfigureUpdate, Id, IdError, IdType, WmRequest, WmState, WmToplevelConfigure};
+use crate::{
+    geometry::PhysicalSize, ConfigureRequest, ConfigureUpdate, Id, IdError, IdType, SceneOperation, ViewSource,
+    WmEvent, WmRequest, WmState, WmToplevelConfigure, WmTransaction, WmWorkspace, MAX_ANIMATION_DURATION,
+    MAX_SCREENSHOT_SIZE, MAX_VIEW_SCALE, MIN_VIEW_SCALE,
+};
 
 use self::aerugo::wm::types::{
-    DecorationMode, Features, Focus, Geometry, Host, HostOutput, HostServer, HostSnapshot, HostToplevel,
-    HostToplevelConfigure, HostView, HostViewBuilder, Output, OutputId, ResizeEdge, Server, Size, Snapshot, Toplevel,
-    ToplevelConfigure, ToplevelId, ToplevelState, View, ViewBuilder,
+    BindingError, ConfigureError, DecorationMode, DecorationStyle, Easing, Features, Focus, Geometry, Host, HostOutput,
+    HostServer, HostSnapshot, HostToplevel, HostToplevelConfigure, HostTransaction, HostView, HostViewBuilder,
+    HostWorkspace, KeyModifiers, Output, OutputId, ResizeEdge, Server, Size, Snapshot, Toplevel, ToplevelConfigure,
+    ToplevelId, ToplevelState, Transaction, View, ViewBuilder, ViewTransform, Workspace, WorkspaceId,
 };
 
-wasmtime::component::bindgen!(in "../../wm.wit");
+// Only calls into the wm are async, functions provided by the host are synchronous.
+wasmtime::component::bindgen!({
+    path: "../../wm.wit",
+    async: {
+        only_imports: [],
+    },
+});
 
 impl Host for WmState {}
 
 impl HostServer for WmState {
-    fn set_keyboard_focus(&mut self, server: Resource<Server>, _focus: Focus) -> wasmtime::Result<()> {
+    fn set_keyboard_focus(&mut self, server: Resource<Server>, focus: Focus) -> wasmtime::Result<()> {
         self.validate_id_server(&server)?;
-        todo!()
+
+        let toplevel = match focus {
+            Focus::None => None,
+            Focus::Toplevel(toplevel) => {
+                let Some(toplevel) = NonZeroU32::new(toplevel).filter(|rep| self.toplevels.contains_key(rep)) else {
+                    return Err(IdError::InvalidId {
+                        rep: toplevel,
+                        ty: IdType::Toplevel,
+                    }
+                    .into());
+                };
+
+                Some(Id::toplevel(toplevel))
+            }
+        };
+
+        let _ = self.sender.send(WmRequest::SetKeyboardFocus(toplevel));
+        Ok(())
     }
 
     fn set_pointer_focus(&mut self, server: Resource<Server>, _focus: Focus) -> wasmtime::Result<()> {
@@ -29,6 +61,155 @@ impl HostServer for WmState {
         todo!()
     }
 
+    fn show_overview(&mut self, server: Resource<Server>) -> wasmtime::Result<()> {
+        self.validate_id_server(&server)?;
+        let _ = self.sender.send(WmRequest::ShowOverview);
+        Ok(())
+    }
+
+    fn hide_overview(&mut self, server: Resource<Server>) -> wasmtime::Result<()> {
+        self.validate_id_server(&server)?;
+        let _ = self.sender.send(WmRequest::HideOverview);
+        Ok(())
+    }
+
+    fn shutdown(&mut self, server: Resource<Server>) -> wasmtime::Result<()> {
+        self.validate_id_server(&server)?;
+        let _ = self.sender.send(WmRequest::Shutdown);
+        Ok(())
+    }
+
+    fn register_binding(
+        &mut self,
+        server: Resource<Server>,
+        id: u32,
+        modifiers: KeyModifiers,
+        sym: u32,
+    ) -> wasmtime::Result<Result<(), BindingError>> {
+        self.validate_id_server(&server)?;
+
+        if self.bindings.contains_key(&id) {
+            return Ok(Err(BindingError::IdInUse));
+        }
+
+        // Lock modifiers are ignored when matching, so bindings differing only in lock modifiers conflict.
+        let modifiers = modifiers & !(KeyModifiers::CAPS_LOCK | KeyModifiers::NUM_LOCK);
+
+        if self.bindings.values().any(|&binding| binding == (modifiers, sym)) {
+            return Ok(Err(BindingError::AlreadyRegistered));
+        }
+
+        self.bindings.insert(id, (modifiers, sym));
+        let _ = self.sender.send(WmRequest::RegisterBinding {
+            id,
+            modifiers,
+            keysym: sym,
+        });
+        Ok(Ok(()))
+    }
+
+    fn unregister_binding(&mut self, server: Resource<Server>, id: u32) -> wasmtime::Result<()> {
+        self.validate_id_server(&server)?;
+
+        if self.bindings.remove(&id).is_some() {
+            let _ = self.sender.send(WmRequest::UnregisterBinding(id));
+        }
+
+        Ok(())
+    }
+
+    fn request_frame(&mut self, server: Resource<Server>, output: Resource<Output>) -> wasmtime::Result<()> {
+        self.validate_id_server(&server)?;
+        self.get_output_res(&output)?;
+        let id = self.get_id(&output, IdType::Output)?;
+        let _ = self.sender.send(WmRequest::RequestFrame(id));
+        Ok(())
+    }
+
+    fn set_timer(&mut self, server: Resource<Server>, id: u32, duration_ms: u32) -> wasmtime::Result<()> {
+        self.validate_id_server(&server)?;
+
+        // A timer without a duration has expired already. The wm is still handling a callback, so the timer is
+        // dispatched once the callback returns.
+        if duration_ms == 0 {
+            if !self.timers.insert(id) {
+                let _ = self.sender.send(WmRequest::CancelTimer(id));
+            }
+
+            self.events.defer(WmEvent::Timer(id));
+            return Ok(());
+        }
+
+        self.timers.insert(id);
+        let _ = self.sender.send(WmRequest::SetTimer {
+            id,
+            duration: Duration::from_millis(duration_ms.into()),
+        });
+        Ok(())
+    }
+
+    fn cancel_timer(&mut self, server: Resource<Server>, id: u32) -> wasmtime::Result<()> {
+        self.validate_id_server(&server)?;
+
+        if self.timers.remove(&id) {
+            let _ = self.sender.send(WmRequest::CancelTimer(id));
+        }
+
+        Ok(())
+    }
+
+    fn create_virtual_output(&mut self, server: Resource<Server>, width: u32, height: u32) -> wasmtime::Result<()> {
+        self.validate_id_server(&server)?;
+        let _ = self
+            .sender
+            .send(WmRequest::CreateVirtualOutput(PhysicalSize::new(width, height)));
+        Ok(())
+    }
+
+    fn destroy_virtual_output(&mut self, server: Resource<Server>, output: Resource<Output>) -> wasmtime::Result<()> {
+        self.validate_id_server(&server)?;
+        self.get_output_res(&output)?;
+        let id = self.get_id(&output, IdType::Output)?;
+        let _ = self.sender.send(WmRequest::DestroyVirtualOutput(id));
+        Ok(())
+    }
+
+    fn set_output_power(
+        &mut self,
+        server: Resource<Server>,
+        output: Resource<Output>,
+        on: bool,
+    ) -> wasmtime::Result<()> {
+        self.validate_id_server(&server)?;
+        self.get_output_res(&output)?;
+        let id = self.get_id(&output, IdType::Output)?;
+        let _ = self.sender.send(WmRequest::SetOutputPower { output: id, on });
+        Ok(())
+    }
+
+    fn spawn(
+        &mut self,
+        server: Resource<Server>,
+        command: Vec<String>,
+        systemd_scope: bool,
+    ) -> wasmtime::Result<Option<u32>> {
+        self.validate_id_server(&server)?;
+
+        if command.is_empty() {
+            return Ok(None);
+        }
+
+        let launch = self.next_launch;
+        self.next_launch = self.next_launch.wrapping_add(1);
+        let _ = self.sender.send(WmRequest::Spawn {
+            launch,
+            command,
+            systemd_scope,
+        });
+
+        Ok(Some(launch))
+    }
+
     fn drop(&mut self, server: Resource<Server>) -> wasmtime::Result<()> {
         // TODO: What should happen if the server is dropped?
         self.validate_id_server(&server)?;
@@ -40,45 +221,369 @@ impl HostViewBuilder for WmState {
     fn with_toplevel(
         &mut self,
         toplevel: Resource<Toplevel>,
-        image: Resource<Snapshot>,
+        snapshot: Resource<Snapshot>,
     ) -> wasmtime::Result<Resource<ViewBuilder>> {
-        todo!()
+        let toplevel = self.get_id(&toplevel, IdType::Toplevel)?;
+        self.get_toplevel(toplevel)?;
+
+        // The view presents the surfaces of the toplevel as they are committed, the snapshot only has to be
+        // valid.
+        self.snapshots.get(snapshot.rep(), IdType::Snapshot)?;
+
+        let rep = self.view_builders.insert(ViewSource::Toplevel { toplevel })?;
+        Ok(Resource::new_own(rep))
+    }
+
+    fn with_surface_node(&mut self, node: u32) -> wasmtime::Result<Resource<ViewBuilder>> {
+        let Some(node) = NonZeroU32::new(node).filter(|node| self.surface_nodes.contains(node)) else {
+            return Err(IdError::InvalidId {
+                rep: node,
+                ty: IdType::SurfaceNode,
+            }
+            .into());
+        };
+
+        let rep = self.view_builders.insert(ViewSource::SurfaceNode {
+            node: Id::surface_node(node),
+        })?;
+        Ok(Resource::new_own(rep))
+    }
+
+    fn branch(&mut self) -> wasmtime::Result<Resource<ViewBuilder>> {
+        let rep = self.view_builders.insert(ViewSource::Branch)?;
+        Ok(Resource::new_own(rep))
+    }
+
+    fn solid_color(&mut self, color: u32, size: Size) -> wasmtime::Result<Resource<ViewBuilder>> {
+        let rep = self.view_builders.insert(ViewSource::SolidColor {
+            color,
+            size: size.into(),
+        })?;
+        Ok(Resource::new_own(rep))
     }
 
     fn build(&mut self, builder: Resource<ViewBuilder>) -> wasmtime::Result<Resource<View>> {
-        todo!()
+        let source = *self.view_builders.get(builder.rep(), IdType::ViewBuilder)?;
+        let view = self.insert_view(source)?;
+
+        let _ = self.sender.send(WmRequest::CreateView { view, source });
+        Ok(Resource::new_own(view.rep().get()))
     }
 
     fn drop(&mut self, builder: Resource<ViewBuilder>) -> wasmtime::Result<()> {
-        todo!()
+        self.view_builders.remove(builder.rep());
+        Ok(())
     }
 }
 
 impl HostView for WmState {
-    fn drop(&mut self, node: Resource<View>) -> wasmtime::Result<()> {
-        todo!()
+    fn drop(&mut self, view: Resource<View>) -> wasmtime::Result<()> {
+        if self.views.remove(view.rep()).is_some() {
+            // A live handle is never zero.
+            let rep = NonZeroU32::new(view.rep()).unwrap();
+            let _ = self.sender.send(WmRequest::DestroyView(Id::view(rep)));
+        }
+
+        Ok(())
+    }
+}
+
+impl HostTransaction for WmState {
+    fn new(&mut self) -> wasmtime::Result<Resource<Transaction>> {
+        let rep = self.insert_transaction()?;
+        Ok(Resource::new_own(rep))
+    }
+
+    fn commit(&mut self, transaction: Resource<Transaction>) -> wasmtime::Result<()> {
+        let WmTransaction { configures, operations } = mem::take(self.get_transaction(&transaction)?);
+
+        for configure in configures {
+            let _ = self.sender.send(configure.into());
+        }
+
+        if !operations.is_empty() {
+            let _ = self.sender.send(WmRequest::Transaction(operations));
+        }
+
+        Ok(())
+    }
+
+    fn configure(
+        &mut self,
+        transaction: Resource<Transaction>,
+        configure: Resource<ToplevelConfigure>,
+    ) -> wasmtime::Result<Result<u32, ConfigureError>> {
+        // Check the transaction first, so no serial is used up for an invalid transaction.
+        self.get_transaction(&transaction)?;
+
+        let configure = match self.submit_configure(&configure)? {
+            Ok(configure) => configure,
+            Err(err) => return Ok(Err(err)),
+        };
+
+        let serial = configure.serial;
+        self.get_transaction(&transaction)?.configures.push(configure);
+        Ok(Ok(serial))
+    }
+
+    fn animate(
+        &mut self,
+        transaction: Resource<Transaction>,
+        duration_ms: u32,
+        easing: Easing,
+    ) -> wasmtime::Result<()> {
+        let duration = Duration::from_millis(duration_ms.into()).min(MAX_ANIMATION_DURATION);
+        self.get_transaction(&transaction)?
+            .operations
+            .push(SceneOperation::Animate { duration, easing });
+        Ok(())
+    }
+
+    fn set_position(
+        &mut self,
+        transaction: Resource<Transaction>,
+        view: Resource<View>,
+        x: i32,
+        y: i32,
+    ) -> wasmtime::Result<()> {
+        let view = self.get_view(&view)?;
+        self.get_transaction(&transaction)?
+            .operations
+            .push(SceneOperation::SetPosition { view, x, y });
+        Ok(())
+    }
+
+    fn place_above(
+        &mut self,
+        transaction: Resource<Transaction>,
+        view: Resource<View>,
+        sibling: Resource<View>,
+    ) -> wasmtime::Result<()> {
+        let view = self.get_view(&view)?;
+        let sibling = self.get_view(&sibling)?;
+        self.get_transaction(&transaction)?
+            .operations
+            .push(SceneOperation::PlaceAbove { view, sibling });
+        Ok(())
+    }
+
+    fn place_below(
+        &mut self,
+        transaction: Resource<Transaction>,
+        view: Resource<View>,
+        sibling: Resource<View>,
+    ) -> wasmtime::Result<()> {
+        let view = self.get_view(&view)?;
+        let sibling = self.get_view(&sibling)?;
+        self.get_transaction(&transaction)?
+            .operations
+            .push(SceneOperation::PlaceBelow { view, sibling });
+        Ok(())
+    }
+
+    fn reparent(
+        &mut self,
+        transaction: Resource<Transaction>,
+        view: Resource<View>,
+        parent: Resource<View>,
+    ) -> wasmtime::Result<()> {
+        let view = self.get_view(&view)?;
+        let parent = self.get_view(&parent)?;
+        self.get_transaction(&transaction)?
+            .operations
+            .push(SceneOperation::Reparent { view, parent });
+        Ok(())
+    }
+
+    fn set_output(
+        &mut self,
+        transaction: Resource<Transaction>,
+        output: Resource<Output>,
+        view: Resource<View>,
+    ) -> wasmtime::Result<()> {
+        let output = self.get_id(&output, IdType::Output)?;
+        let view = self.get_view(&view)?;
+        self.get_transaction(&transaction)?
+            .operations
+            .push(SceneOperation::SetOutput { output, view });
+        Ok(())
+    }
+
+    fn set_scale(
+        &mut self,
+        transaction: Resource<Transaction>,
+        view: Resource<View>,
+        scale: f32,
+    ) -> wasmtime::Result<()> {
+        let view = self.get_view(&view)?;
+        // A scale which is not a number leaves the view unscaled.
+        let scale = if scale.is_nan() {
+            1.0
+        } else {
+            scale.clamp(MIN_VIEW_SCALE, MAX_VIEW_SCALE)
+        };
+        self.get_transaction(&transaction)?
+            .operations
+            .push(SceneOperation::SetScale { view, scale });
+        Ok(())
+    }
+
+    fn set_transform(
+        &mut self,
+        transaction: Resource<Transaction>,
+        view: Resource<View>,
+        transform: ViewTransform,
+    ) -> wasmtime::Result<()> {
+        let view = self.get_view(&view)?;
+        self.get_transaction(&transaction)?
+            .operations
+            .push(SceneOperation::SetTransform { view, transform });
+        Ok(())
+    }
+
+    fn set_opacity(
+        &mut self,
+        transaction: Resource<Transaction>,
+        view: Resource<View>,
+        opacity: f32,
+    ) -> wasmtime::Result<()> {
+        let view = self.get_view(&view)?;
+        let opacity = if opacity.is_nan() { 1.0 } else { opacity.clamp(0.0, 1.0) };
+        self.get_transaction(&transaction)?
+            .operations
+            .push(SceneOperation::SetOpacity { view, opacity });
+        Ok(())
+    }
+
+    fn set_clip(
+        &mut self,
+        transaction: Resource<Transaction>,
+        view: Resource<View>,
+        clip: Option<Geometry>,
+    ) -> wasmtime::Result<()> {
+        let view = self.get_view(&view)?;
+        let clip = clip.map(Into::into);
+        self.get_transaction(&transaction)?
+            .operations
+            .push(SceneOperation::SetClip { view, clip });
+        Ok(())
+    }
+
+    fn set_solid_color(
+        &mut self,
+        transaction: Resource<Transaction>,
+        view: Resource<View>,
+        color: u32,
+        size: Size,
+    ) -> wasmtime::Result<()> {
+        let view = self.get_view(&view)?;
+        self.get_transaction(&transaction)?
+            .operations
+            .push(SceneOperation::SetSolidColor {
+                view,
+                color,
+                size: size.into(),
+            });
+        Ok(())
+    }
+
+    fn set_workspace(
+        &mut self,
+        transaction: Resource<Transaction>,
+        view: Resource<View>,
+        workspace: Resource<Workspace>,
+    ) -> wasmtime::Result<()> {
+        let view = self.get_view(&view)?;
+        let (workspace, _) = self.get_workspace(&workspace)?;
+        self.get_transaction(&transaction)?
+            .operations
+            .push(SceneOperation::SetWorkspace { view, workspace });
+        Ok(())
+    }
+
+    fn activate_workspace(
+        &mut self,
+        transaction: Resource<Transaction>,
+        workspace: Resource<Workspace>,
+    ) -> wasmtime::Result<()> {
+        let (workspace, _) = self.get_workspace(&workspace)?;
+        self.get_transaction(&transaction)?
+            .operations
+            .push(SceneOperation::ActivateWorkspace { workspace });
+        Ok(())
+    }
+
+    fn drop(&mut self, transaction: Resource<Transaction>) -> wasmtime::Result<()> {
+        // Operations which were not committed are discarded.
+        self.transactions.remove(transaction.rep());
+
+        Ok(())
+    }
+}
+
+impl HostWorkspace for WmState {
+    fn new(&mut self, output: Resource<Output>, name: String) -> wasmtime::Result<Resource<Workspace>> {
+        self.get_output_res(&output)?;
+        let output = self.get_id(&output, IdType::Output)?;
+        let workspace = self.insert_workspace(WmWorkspace {
+            output,
+            name: name.clone(),
+        })?;
+
+        let _ = self.sender.send(WmRequest::CreateWorkspace {
+            workspace,
+            output,
+            name,
+        });
+        Ok(Resource::new_own(workspace.rep().get()))
+    }
+
+    fn id(&mut self, workspace: Resource<Workspace>) -> wasmtime::Result<WorkspaceId> {
+        let (id, _) = self.get_workspace(&workspace)?;
+        Ok(id.rep().get())
+    }
+
+    fn name(&mut self, workspace: Resource<Workspace>) -> wasmtime::Result<String> {
+        Ok(self.get_workspace(&workspace)?.1.name.clone())
+    }
+
+    fn output(&mut self, workspace: Resource<Workspace>) -> wasmtime::Result<OutputId> {
+        Ok(self.get_workspace(&workspace)?.1.output.rep().get())
+    }
+
+    fn drop(&mut self, workspace: Resource<Workspace>) -> wasmtime::Result<()> {
+        if self.workspaces.remove(workspace.rep()).is_some() {
+            // A live handle is never zero.
+            let rep = NonZeroU32::new(workspace.rep()).unwrap();
+            let _ = self.sender.send(WmRequest::DestroyWorkspace(Id::workspace(rep)));
+        }
+
+        Ok(())
     }
 }
 
 impl HostOutput for WmState {
     fn id(&mut self, output: Resource<Output>) -> wasmtime::Result<OutputId> {
-        todo!()
+        let id = self.get_id(&output, IdType::Output)?;
+        Ok(id.rep().get())
     }
 
     fn name(&mut self, output: Resource<Output>) -> wasmtime::Result<Option<String>> {
-        todo!()
+        Ok(self.get_output_res(&output)?.name.clone())
     }
 
     fn geometry(&mut self, output: Resource<Output>) -> wasmtime::Result<Geometry> {
-        todo!()
+        Ok(self.get_output_res(&output)?.geometry.into())
     }
 
     fn refresh_rate(&mut self, output: Resource<Output>) -> wasmtime::Result<u32> {
-        todo!()
+        Ok(self.get_output_res(&output)?.refresh_rate)
     }
 
     fn drop(&mut self, output: Resource<Output>) -> wasmtime::Result<()> {
-        todo!()
+        // The output stays known to the wm until it is disconnected, so the wm may drop the handle at any time.
+        self.handles.remove(output.rep());
+        Ok(())
     }
 }
 
@@ -133,11 +638,26 @@ impl HostToplevel for WmState {
         Ok(toplevel.decorations)
     }
 
+    fn preferred_decorations(&mut self, toplevel: Resource<Toplevel>) -> wasmtime::Result<Option<DecorationMode>> {
+        let toplevel = self.get_toplevel_res(&toplevel)?;
+        Ok(toplevel.preferred_decorations)
+    }
+
+    fn decoration_style(&mut self, toplevel: Resource<Toplevel>) -> wasmtime::Result<DecorationStyle> {
+        let toplevel = self.get_toplevel_res(&toplevel)?;
+        Ok(toplevel.decoration_style.clone())
+    }
+
     fn resize_edge(&mut self, toplevel: Resource<Toplevel>) -> wasmtime::Result<Option<ResizeEdge>> {
         let toplevel = self.get_toplevel_res(&toplevel)?;
         Ok(toplevel.resize_edge)
     }
 
+    fn fullscreen_output(&mut self, toplevel: Resource<Toplevel>) -> wasmtime::Result<Option<OutputId>> {
+        let toplevel = self.get_toplevel_res(&toplevel)?;
+        Ok(toplevel.fullscreen_output.map(|output| output.rep().get()))
+    }
+
     fn request_close(&mut self, toplevel: Resource<Toplevel>) -> wasmtime::Result<()> {
         let toplevel = self.get_toplevel_res(&toplevel)?;
         let id = toplevel.id;
@@ -146,12 +666,32 @@ impl HostToplevel for WmState {
         Ok(())
     }
 
+    fn screenshot(&mut self, toplevel: Resource<Toplevel>, max_size: Size) -> wasmtime::Result<Option<u32>> {
+        let id = self.get_toplevel_res(&toplevel)?.id;
+
+        let Some(serial) = self.screenshot_serial(id, Instant::now()) else {
+            return Ok(None);
+        };
+
+        let max_size = PhysicalSize::new(
+            max_size.width.min(MAX_SCREENSHOT_SIZE),
+            max_size.height.min(MAX_SCREENSHOT_SIZE),
+        );
+
+        let _ = self.sender.send(WmRequest::ToplevelScreenshot {
+            toplevel: id,
+            serial,
+            max_size,
+        });
+        Ok(Some(serial))
+    }
+
     fn drop(&mut self, toplevel: Resource<Toplevel>) -> wasmtime::Result<()> {
-        let toplevel = self.get_toplevel_res(&toplevel)?;
-        let id = toplevel.id;
-        // TODO: Remove id from this side.
+        // The handle is already stale if the toplevel was closed, which is not an error.
+        if let Some(id) = self.handles.remove(toplevel.rep()) {
+            let _ = self.sender.send(WmRequest::ToplevelDrop(id));
+        }
 
-        let _ = self.sender.send(WmRequest::ToplevelDrop(id));
         Ok(())
     }
 }
@@ -161,19 +701,22 @@ impl HostToplevelConfigure for WmState {
         let toplevel = self.get_toplevel_res(&toplevel)?;
         let configure = WmToplevelConfigure {
             toplevel_id: toplevel.id,
-            decorations: Default::default(),
-            parent: Default::default(),
-            state: Default::default(),
-            size: Default::default(),
-            bounds: Default::default(),
+            request: ConfigureRequest::default(),
         };
 
-        Ok(Resource::new_own(todo!("Allocate owned id for toplevel configure")))
+        let rep = self.insert_toplevel_configure(configure)?;
+        Ok(Resource::new_own(rep))
     }
 
-    fn submit(&mut self, configure: Resource<ToplevelConfigure>) -> wasmtime::Result<u32> {
-        let _configure = self.get_toplevel_configure(&configure)?;
-        todo!()
+    fn submit(&mut self, configure: Resource<ToplevelConfigure>) -> wasmtime::Result<Result<u32, ConfigureError>> {
+        let configure = match self.submit_configure(&configure)? {
+            Ok(configure) => configure,
+            Err(err) => return Ok(Err(err)),
+        };
+
+        let serial = configure.serial;
+        let _ = self.sender.send(configure.into());
+        Ok(Ok(serial))
     }
 
     fn decorations(
@@ -182,7 +725,7 @@ impl HostToplevelConfigure for WmState {
         decorations: DecorationMode,
     ) -> wasmtime::Result<()> {
         let configure = self.get_toplevel_configure(&configure)?;
-        configure.decorations = Some(decorations);
+        configure.request.decorations = Some(decorations);
         Ok(())
     }
 
@@ -191,59 +734,60 @@ impl HostToplevelConfigure for WmState {
         configure: Resource<ToplevelConfigure>,
         parent: Option<Resource<Toplevel>>,
     ) -> wasmtime::Result<()> {
-        let configure = self.get_toplevel_configure(&configure)?;
-
-        match parent {
-            Some(parent) => {
-                if parent.owned() {
-                    todo!("propagate error")
-                }
-
-                let parent_id = NonZeroU32::new(parent.rep()).ok_or(IdError::ZeroId)?;
-                configure.parent = ConfigureUpdate::Update(Some(Id(parent_id, IdType::Toplevel)));
-                Ok(())
-            }
+        let parent = match parent {
+            Some(parent) => Some(self.get_id(&parent, IdType::Toplevel)?),
+            None => None,
+        };
 
-            None => {
-                configure.parent = ConfigureUpdate::Update(None);
-                Ok(())
-            }
-        }
+        let configure = self.get_toplevel_configure(&configure)?;
+        configure.request.parent = ConfigureUpdate::Update(parent);
+        Ok(())
     }
 
     fn state(&mut self, configure: Resource<ToplevelConfigure>, states: ToplevelState) -> wasmtime::Result<()> {
         let configure = self.get_toplevel_configure(&configure)?;
-        configure.state = Some(states);
+        configure.request.state = Some(states);
+        Ok(())
+    }
+
+    fn minimized(&mut self, configure: Resource<ToplevelConfigure>, minimized: bool) -> wasmtime::Result<()> {
+        let configure = self.get_toplevel_configure(&configure)?;
+        configure.request.minimized = Some(minimized);
         Ok(())
     }
 
     fn size(&mut self, configure: Resource<ToplevelConfigure>, size: Option<Size>) -> wasmtime::Result<()> {
         let configure = self.get_toplevel_configure(&configure)?;
-        configure.size = ConfigureUpdate::Update(size);
+        configure.request.size = ConfigureUpdate::Update(size.map(Into::into));
         Ok(())
     }
 
     fn bounds(&mut self, configure: Resource<ToplevelConfigure>, bounds: Option<Size>) -> wasmtime::Result<()> {
         let configure = self.get_toplevel_configure(&configure)?;
-        configure.bounds = ConfigureUpdate::Update(bounds);
+        configure.request.bounds = ConfigureUpdate::Update(bounds.map(Into::into));
         Ok(())
     }
 
     fn drop(&mut self, configure: Resource<ToplevelConfigure>) -> wasmtime::Result<()> {
-        todo!()
+        self.configures.remove(configure.rep());
+
+        Ok(())
     }
 }
 
 impl HostSnapshot for WmState {
     fn size(&mut self, snapshot: Resource<Snapshot>) -> wasmtime::Result<Size> {
-        todo!()
+        let snapshot = self.snapshots.get(snapshot.rep(), IdType::Snapshot)?;
+        Ok(snapshot.size.into())
     }
 
     fn scale(&mut self, snapshot: Resource<Snapshot>) -> wasmtime::Result<f32> {
-        todo!()
+        let snapshot = self.snapshots.get(snapshot.rep(), IdType::Snapshot)?;
+        Ok(snapshot.scale)
     }
 
     fn drop(&mut self, snapshot: Resource<Snapshot>) -> wasmtime::Result<()> {
-        todo!()
+        self.snapshots.remove(snapshot.rep());
+        Ok(())
     }
 }
@@ -1,13 +1,193 @@
-//! Id allocator
+//! Id allocator and tables of the resources held by the wm
+//!
+//! Every resource the host gives to the wm is an entry in a [`ResourceTable`], and the rep of the resource is a
+//! handle consisting of the index of a slot in the table and the generation of the slot. The entry is removed when
+//! the wm drops the resource, which releases the host state of the resource right away.
+//!
+//! Toplevels and outputs are destroyed by the display server, while the wm may still hold resources of them, so
+//! their entries are removed by the runtime as well. Removing an entry advances the generation of the slot. A
+//! stale resource therefore no longer matches the slot, even after the slot or the id of the object was reused,
+//! and is rejected with [`IdError::Stale`] instead of aliasing the new object.
+//!
+//! The rep of a resource is never visible to the wm, so the generation does not change the ABI.
 
 use std::{
     cell::RefCell,
+    fmt,
     num::NonZeroU32,
     rc::{Rc, Weak},
 };
 
 use slotmap::SlotMap;
 
+use crate::{Id, IdError, IdType};
+
+/// The number of bits of a handle used for the index of the slot.
+const INDEX_BITS: u32 = 20;
+
+/// The most slots of a resource table.
+///
+/// Handle 0 is the server, so index 0 is never used.
+const MAX_INDEX: u32 = (1 << INDEX_BITS) - 1;
+
+/// The generation of a slot wraps around at this value.
+const GENERATION_MASK: u32 = u32::MAX >> INDEX_BITS;
+
+/// Host state of the resources of one type held by the wm, keyed by generation-tagged handles.
+pub struct ResourceTable<T> {
+    /// The slot with index `i` is at `i - 1`.
+    slots: Vec<Slot<T>>,
+
+    /// Indices of the slots which are not in use.
+    free: Vec<u32>,
+}
+
+/// Handles to toplevels and outputs, which are owned by the display server.
+pub type HandleTable = ResourceTable<Id>;
+
+#[derive(Debug)]
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+impl<T> ResourceTable<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Store the state of a new resource and return the handle of the resource.
+    pub fn insert(&mut self, value: T) -> Result<u32, IdError> {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                let index = u32::try_from(self.slots.len() + 1).unwrap_or(u32::MAX);
+
+                if index > MAX_INDEX {
+                    return Err(IdError::Exhausted);
+                }
+
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: None,
+                });
+                index
+            }
+        };
+
+        let slot = &mut self.slots[index as usize - 1];
+        slot.value = Some(value);
+        Ok((slot.generation << INDEX_BITS) | index)
+    }
+
+    /// The state of a resource.
+    ///
+    /// The type is the type of the resource the handle was taken from, which is used in the error.
+    pub fn get(&self, handle: u32, ty: IdType) -> Result<&T, IdError> {
+        let index = self.index(handle, ty)?;
+        Ok(self.slots[index].value.as_ref().unwrap())
+    }
+
+    pub fn get_mut(&mut self, handle: u32, ty: IdType) -> Result<&mut T, IdError> {
+        let index = self.index(handle, ty)?;
+        Ok(self.slots[index].value.as_mut().unwrap())
+    }
+
+    /// Whether the handle refers to a live resource.
+    pub fn contains(&self, handle: u32) -> bool {
+        // The type only matters for the error.
+        self.index(handle, IdType::Server).is_ok()
+    }
+
+    /// Remove a resource, returning the state of the resource.
+    ///
+    /// Stale handles are ignored.
+    pub fn remove(&mut self, handle: u32) -> Option<T> {
+        let index = self.index(handle, IdType::Server).ok()?;
+        let value = self.slots[index].value.take();
+        self.free_slot(index);
+        value
+    }
+
+    /// Remove every resource for which `f` returns false.
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        for index in 0..self.slots.len() {
+            if self.slots[index].value.as_ref().map_or(false, |value| !f(value)) {
+                self.slots[index].value = None;
+                self.free_slot(index);
+            }
+        }
+    }
+
+    /// Iterate over the handles and states of the live resources.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let value = slot.value.as_ref()?;
+            Some(((slot.generation << INDEX_BITS) | (index as u32 + 1), value))
+        })
+    }
+
+    /// The position of the slot of a live handle in `slots`.
+    fn index(&self, handle: u32, ty: IdType) -> Result<usize, IdError> {
+        let (index, generation) = split(handle);
+
+        if index == 0 {
+            return Err(IdError::ZeroId);
+        }
+
+        let index = index as usize - 1;
+        let slot = self.slots.get(index).ok_or(IdError::InvalidId { rep: handle, ty })?;
+
+        // A removed slot always has a newer generation than the handles which referred to it.
+        if slot.generation != generation || slot.value.is_none() {
+            return Err(IdError::Stale { rep: handle, ty });
+        }
+
+        Ok(index)
+    }
+
+    fn free_slot(&mut self, index: usize) {
+        let slot = &mut self.slots[index];
+        slot.generation = (slot.generation + 1) & GENERATION_MASK;
+        self.free.push(index as u32 + 1);
+    }
+}
+
+impl ResourceTable<Id> {
+    /// The object a handle refers to, if the object has the expected type.
+    pub fn id(&self, handle: u32, ty: IdType) -> Result<Id, IdError> {
+        self.get(handle, ty).copied().and_then(|id| match id.ty() == ty {
+            true => Ok(id),
+            false => Err(IdError::InvalidId { rep: handle, ty }),
+        })
+    }
+
+    /// Make every handle to an object stale, for example because the object was destroyed.
+    pub fn remove_id(&mut self, id: Id) {
+        self.retain(|&handle| handle != id);
+    }
+}
+
+impl<T> Default for ResourceTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ResourceTable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Split a handle into the index and generation.
+fn split(handle: u32) -> (u32, u32) {
+    (handle & MAX_INDEX, handle >> INDEX_BITS)
+}
+
 #[derive(Debug)]
 pub enum AllocError {
     /// All ids the allocator can use have been exhausted.
@@ -149,7 +329,8 @@ struct Range {
 mod tests {
     use std::num::NonZeroU32;
 
-    use super::IdAllocator;
+    use super::{HandleTable, IdAllocator, ResourceTable};
+    use crate::{Id, IdError, IdType};
 
     #[test]
     fn alloc_contig() {
@@ -191,4 +372,56 @@ mod tests {
         let id2 = alloc.alloc().unwrap();
         assert_eq!(id2.get(), 2);
     }
+
+    #[test]
+    fn stale_handles_are_rejected() {
+        let mut table = HandleTable::new();
+        let first = Id::toplevel(NonZeroU32::new(7).unwrap());
+        let second = Id::toplevel(NonZeroU32::new(8).unwrap());
+
+        let handle = table.insert(first).unwrap();
+        assert_eq!(table.id(handle, IdType::Toplevel).unwrap(), first);
+        assert!(matches!(
+            table.id(handle, IdType::Output),
+            Err(IdError::InvalidId { ty: IdType::Output, .. })
+        ));
+
+        // The slot is reused for the second toplevel, but the old handle must not alias it.
+        table.remove_id(first);
+        let reused = table.insert(second).unwrap();
+        assert_ne!(reused, handle);
+        assert!(matches!(table.id(handle, IdType::Toplevel), Err(IdError::Stale { .. })));
+        assert_eq!(table.id(reused, IdType::Toplevel).unwrap(), second);
+
+        // Dropping the stale handle leaves the new object alone.
+        assert_eq!(table.remove(handle), None);
+        assert_eq!(table.remove(reused), Some(second));
+        assert!(matches!(table.id(0, IdType::Toplevel), Err(IdError::ZeroId)));
+    }
+
+    #[test]
+    fn dropped_resources_release_state() {
+        let mut table = ResourceTable::new();
+        let first = table.insert("first").unwrap();
+        let second = table.insert("second").unwrap();
+
+        assert_eq!(table.remove(first), Some("first"));
+        assert_eq!(table.remove(first), None);
+        assert!(!table.contains(first));
+        assert!(matches!(
+            table.get(first, IdType::Transaction),
+            Err(IdError::Stale {
+                ty: IdType::Transaction,
+                ..
+            })
+        ));
+
+        let third = table.insert("third").unwrap();
+        *table.get_mut(second, IdType::Transaction).unwrap() = "changed";
+        // The third resource reuses the slot of the first.
+        assert_eq!(
+            table.iter().collect::<Vec<_>>(),
+            [(third, &"third"), (second, &"changed")]
+        );
+    }
 }
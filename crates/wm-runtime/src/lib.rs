@@ -1,29 +1,102 @@
 //! Wasm WM runtime for the Aerugo.
-
+//!
+//! # Event loop integration
+//!
+//! The wm runs on a separate thread. The display server receives requests from the wm by registering the
+//! [`WmRuntime`] in a [`calloop`] event loop, since [`WmRuntime`] is an [`EventSource`]. Events are sent to the
+//! wm using the [`EventSender`] returned by [`WmRuntime::sender`], which may be used from any thread.
+//!
+//! The event channel is bounded and coalesces events which supersede queued events, so a stalled wm does not make
+//! events pile up. If the channel overflows anyways, the wm is treated as failed. See [`EVENT_CHANNEL_CAPACITY`].
+//!
+//! There is no out of process wm client, so there is no socket or file descriptor to poll. If the display server
+//! ever uses an event loop other than calloop, the channels should be replaced with a channel that exposes a
+//! pollable file descriptor.
+//!
+//! # Recovery
+//!
+//! If the wm traps or the host panics while dispatching an event, the runtime instantiates the wm again from the
+//! already compiled component and reports [`RuntimeMessage::Restarted`]. The new instance is told about the
+//! known outputs, and the toplevels are carried over. Once the wm failed more often than the [`RetryPolicy`]
+//! allows, the runtime reports [`WmRequest::Crashed`] and the runtime thread stops.
+//!
+//! # Execution
+//!
+//! Calls into the wm are async and use epoch interruption: the epoch of the engine advances periodically and the
+//! wm yields back to the wm thread at every epoch. A wm which takes longer than
+//! [`ExecutionLimits::max_callback_time`] to handle an event is stopped and treated as if the wm trapped, so a wm
+//! stuck in a loop is recovered instead of blocking the wm thread forever.
+//!
+//! # Logging
+//!
+//! Messages the wm logs are emitted as [`tracing`] events in a `wm` span naming the wm. The number of messages is
+//! rate limited, so a wm cannot flood the log of the display server.
+//!
+//! # Statistics
+//!
+//! The runtime measures the callbacks into the wm, the fuel the wm consumes, the number of events waiting to be
+//! dispatched and the size of the memory of the wm. The statistics are reported periodically as
+//! [`RuntimeMessage::Stats`], see [`WmStats`].
+//!
+//! # WASI
+//!
+//! A wm may be given access to a sandboxed subset of WASI, see [`WasiConfig`].
+//!
+//! # Testing
+//!
+//! The [`testing`] module runs a wm without a display server and compares the requests of the wm against a golden
+//! trace.
+
+mod channel;
+mod executor;
+pub mod geometry;
 mod host;
 mod id;
+mod log;
+mod queue;
 mod runner;
+mod stats;
+pub mod testing;
+mod wasi;
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::{self, Display},
+    io,
     num::NonZeroU32,
+    time::{Duration, Instant},
 };
 
 use calloop::{
     channel::{Channel, Sender},
     EventSource, Poll, PostAction, TokenFactory,
 };
+use executor::EpochTicker;
+use geometry::{LogicalRect, LogicalSize, PhysicalSize};
 use host::{
-    aerugo::wm::types::{DecorationMode, Features, Geometry, ResizeEdge, Server, Size, ToplevelState},
+    aerugo::wm::types::{ConfigureError, Server},
     exports::aerugo::wm::wm_types::WmTypes,
 };
+use id::{HandleTable, ResourceTable};
+use log::LogLimiter;
+use queue::EventQueue;
 use runner::WmRunner;
+use stats::MemoryTracker;
+use wasi::WasiState;
 use wasmtime::{
-    component::{Linker, Resource},
+    component::{Component, Linker, Resource, ResourceAny},
     Config, Engine, Store,
 };
 
+pub use channel::{EventSender, SendError, EVENT_CHANNEL_CAPACITY};
+pub use host::aerugo::wm::types::{
+    DecorationMode, DecorationStyle, Easing, Features, Geometry, GestureBegin, GestureEvent, GestureKind,
+    GestureUpdate, KeyModifiers, PointerAxis, PointerButton, PointerEvent, PointerLocation, ResizeEdge, Size,
+    ToplevelState, TouchEvent, TouchPoint, ViewTransform,
+};
+pub use stats::{CallbackStats, WmStats, STATS_INTERVAL};
+pub use wasi::{WasiConfig, CONFIG_DIR};
+
 /// An ID which references an object allocated in the WM.
 ///
 /// ID 0 is always reserved by the WM's server object.
@@ -31,6 +104,42 @@ use wasmtime::{
 pub struct Id(NonZeroU32, IdType);
 
 impl Id {
+    /// Create the id of a toplevel.
+    ///
+    /// The rep is the id the display server uses for the toplevel.
+    pub fn toplevel(rep: NonZeroU32) -> Self {
+        Self(rep, IdType::Toplevel)
+    }
+
+    /// Create the id of an output.
+    ///
+    /// Outputs and toplevels share the id space of the wm, so the display server must not use the rep of a
+    /// toplevel for an output.
+    pub fn output(rep: NonZeroU32) -> Self {
+        Self(rep, IdType::Output)
+    }
+
+    /// Create the id of a workspace.
+    ///
+    /// The rep is allocated by the runtime when the wm creates the workspace.
+    pub fn workspace(rep: NonZeroU32) -> Self {
+        Self(rep, IdType::Workspace)
+    }
+
+    /// Create the id of a surface node.
+    ///
+    /// Surface nodes share the id space of the wm with toplevels and outputs.
+    pub fn surface_node(rep: NonZeroU32) -> Self {
+        Self(rep, IdType::SurfaceNode)
+    }
+
+    /// Create the id of a view.
+    ///
+    /// The rep is allocated by the runtime when the wm builds the view.
+    pub fn view(rep: NonZeroU32) -> Self {
+        Self(rep, IdType::View)
+    }
+
     pub fn rep(self) -> NonZeroU32 {
         self.0
     }
@@ -52,6 +161,9 @@ pub enum IdType {
     /// An output.
     Output,
 
+    /// A surface placed in the scene by a shell client.
+    SurfaceNode,
+
     /// A snapshot.
     ///
     /// A snapshot is an object which references the contents of a surface for a given size and scale.
@@ -59,6 +171,37 @@ pub enum IdType {
 
     /// A view is a combination of a surface and a snapshot which can be presented.
     View,
+
+    /// A view being built by the wm.
+    ViewBuilder,
+
+    /// A transaction being built by the wm.
+    Transaction,
+
+    /// A workspace created by the wm.
+    Workspace,
+
+    /// A configure being built by the wm.
+    Configure,
+}
+
+impl Display for IdType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            IdType::Server => "server",
+            IdType::Toplevel => "toplevel",
+            IdType::Output => "output",
+            IdType::SurfaceNode => "surface node",
+            IdType::Snapshot => "snapshot",
+            IdType::View => "view",
+            IdType::ViewBuilder => "view builder",
+            IdType::Transaction => "transaction",
+            IdType::Workspace => "workspace",
+            IdType::Configure => "configure",
+        };
+
+        f.write_str(name)
+    }
 }
 
 /// An event sent to the wm runtime.
@@ -67,38 +210,147 @@ pub enum WmEvent {
     /// Notify the runtime that a new toplevel was created.
     ///
     /// This does not actually tell the wm a new toplevel was created until an initial state is sent.
-    NewToplevel {
-        toplevel: Id,
-        features: Features,
-    },
+    NewToplevel { toplevel: Id, features: Features },
 
     /// Notify the runtime that a toplevel was closed.
     ClosedToplevel(Id),
 
     /// Notify the runtime that a toplevel's state has changed.
-    UpdateToplevel {
+    UpdateToplevel { toplevel: Id, update: ToplevelUpdate },
+
+    /// Notify the runtime that a configure has been acked.
+    ///
+    /// The states and decoration mode of the acked configure become the current state of the toplevel.
+    AckToplevel { toplevel: Id, configure: AckedConfigure },
+
+    /// Notify the runtime that a configure was not acked in time and will not be waited on.
+    ConfigureCancelled { toplevel: Id, serial: u32 },
+
+    /// Notify the runtime that a configure was not acked in time and is applied anyway.
+    ///
+    /// Like [`WmEvent::AckToplevel`], the states and decoration mode of the configure become the current state
+    /// of the toplevel.
+    ConfigureTimedOut { toplevel: Id, configure: AckedConfigure },
+
+    /// Notify the runtime that a toplevel was committed.
+    ///
+    /// `snapshot` is set if the size or scale of the toplevel changed since the last snapshot.
+    CommittedToplevel {
         toplevel: Id,
-        update: ToplevelUpdate,
+        snapshot: Option<SnapshotInfo>,
     },
 
-    /// Notify the runtime that a configure has been acked.
-    AckToplevel {
+    /// Notify the runtime that a client requested a toplevel be activated.
+    ActivationRequested { toplevel: Id, token_valid: bool },
+
+    /// Notify the runtime that a client requested a workspace be activated.
+    WorkspaceActivationRequested(Id),
+
+    /// Notify the runtime that a toplevel requested activation using the token of a launch requested with
+    /// [`WmRequest::Spawn`].
+    ///
+    /// This must be sent before the [`WmEvent::ActivationRequested`] of the same request.
+    Launched { launch: u32, toplevel: Id },
+
+    /// Notify the runtime that an output was connected.
+    NewOutput { output: Id, info: OutputInfo },
+
+    /// Notify the runtime that the state of an output changed.
+    ///
+    /// TODO: Add to wit file. The new state is only visible to the wm when it queries the output.
+    UpdateOutput { output: Id, info: OutputInfo },
+
+    /// Notify the runtime that an output was disconnected.
+    ///
+    /// `toplevels` are the toplevels which were presented on the output, including toplevels on inactive
+    /// workspaces of the output.
+    DisconnectOutput { output: Id, toplevels: Vec<Id> },
+
+    /// Notify the runtime that a shell client placed a surface in the scene.
+    ///
+    /// `name` describes the purpose of the surface, as chosen by the client.
+    NewSurfaceNode { node: Id, name: String },
+
+    /// Notify the runtime that a surface node was destroyed.
+    ClosedSurfaceNode(Id),
+
+    /// Notify the runtime that a keybinding registered by the wm was pressed.
+    ///
+    /// `repeat` is set if the binding is repeating because the key is held.
+    Binding { id: u32, time: u32, repeat: bool },
+
+    /// Notify the runtime that a touch point changed.
+    Touch { time: u32, event: TouchEvent },
+
+    /// Notify the runtime that the pointer moved, a button changed or the pointer scrolled.
+    Pointer { time: u32, event: PointerEvent },
+
+    /// Notify the runtime that a touch gesture changed.
+    Gesture { time: u32, event: GestureEvent },
+
+    /// Notify the runtime that the session has been locked.
+    ///
+    /// While the session is locked, nothing presented by the wm is visible.
+    SessionLocked,
+
+    /// Notify the runtime that the session has been unlocked.
+    SessionUnlocked,
+
+    /// A screenshot of a toplevel requested by the wm has completed.
+    ///
+    /// The image is [`None`] if the toplevel could not be captured.
+    ToplevelScreenshot {
         toplevel: Id,
         serial: u32,
+        image: Option<ScreenshotImage>,
     },
 
-    NewOutput {
-        output: Id,
-        // TODO: Info
-    },
+    /// A frame requested by the wm was presented on an output.
+    ///
+    /// `time` is the presentation time in milliseconds, the same as the time of frame callbacks.
+    Frame { output: Id, time: u32 },
 
-    /// TODO: Add to wit file
-    UpdateOutput {
-        output: Id,
-        // TODO: Info
-    },
+    /// A timer set by the wm has expired.
+    Timer(u32),
 
-    DisconnectOutput(Id),
+    /// Notify the runtime that the display server is shutting down.
+    ///
+    /// This is the last event sent to the runtime. The runtime thread exits once the display server closes the
+    /// channel.
+    Shutdown,
+}
+
+impl WmEvent {
+    /// The name of the event, used to group the statistics of the callbacks handling the event.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::NewToplevel { .. } => "NewToplevel",
+            Self::ClosedToplevel(_) => "ClosedToplevel",
+            Self::UpdateToplevel { .. } => "UpdateToplevel",
+            Self::AckToplevel { .. } => "AckToplevel",
+            Self::ConfigureCancelled { .. } => "ConfigureCancelled",
+            Self::ConfigureTimedOut { .. } => "ConfigureTimedOut",
+            Self::CommittedToplevel { .. } => "CommittedToplevel",
+            Self::ActivationRequested { .. } => "ActivationRequested",
+            Self::WorkspaceActivationRequested(_) => "WorkspaceActivationRequested",
+            Self::Launched { .. } => "Launched",
+            Self::NewOutput { .. } => "NewOutput",
+            Self::UpdateOutput { .. } => "UpdateOutput",
+            Self::DisconnectOutput { .. } => "DisconnectOutput",
+            Self::NewSurfaceNode { .. } => "NewSurfaceNode",
+            Self::ClosedSurfaceNode(_) => "ClosedSurfaceNode",
+            Self::Binding { .. } => "Binding",
+            Self::Touch { .. } => "Touch",
+            Self::Pointer { .. } => "Pointer",
+            Self::Gesture { .. } => "Gesture",
+            Self::SessionLocked => "SessionLocked",
+            Self::SessionUnlocked => "SessionUnlocked",
+            Self::ToplevelScreenshot { .. } => "ToplevelScreenshot",
+            Self::Frame { .. } => "Frame",
+            Self::Timer(_) => "Timer",
+            Self::Shutdown => "Shutdown",
+        }
+    }
 }
 
 /// A request from the wm runtime.
@@ -114,6 +366,304 @@ pub enum WmRequest {
 
     /// The wm runtime requested the toplevel with the specified id be closed.
     ToplevelRequestClose(Id),
+
+    /// The wm submitted a configure for a toplevel.
+    ///
+    /// When the toplevel acks the configure, the display server must send [`WmEvent::AckToplevel`] with the same
+    /// serial and the configure.
+    ToplevelConfigure {
+        toplevel: Id,
+        serial: u32,
+        configure: ConfigureRequest,
+    },
+
+    /// The wm committed a transaction.
+    ///
+    /// The operations must be applied atomically in order. If any operation is invalid, none of the operations
+    /// are applied.
+    Transaction(Vec<SceneOperation>),
+
+    /// The wm created a workspace on an output.
+    ///
+    /// The workspace is inactive until it is activated by a transaction.
+    CreateWorkspace { workspace: Id, output: Id, name: String },
+
+    /// The wm dropped a workspace.
+    DestroyWorkspace(Id),
+
+    /// The wm set the toplevel which receives keyboard input, or cleared the keyboard focus.
+    SetKeyboardFocus(Option<Id>),
+
+    /// The wm requested the overview be shown.
+    ShowOverview,
+
+    /// The wm requested the overview be hidden.
+    HideOverview,
+
+    /// The wm requested the display server shut down.
+    Shutdown,
+
+    /// The wm registered a keybinding.
+    ///
+    /// When the binding is pressed, the display server must send [`WmEvent::Binding`] with the id instead of
+    /// delivering the key to clients. The runtime ensures ids and bindings are unique.
+    RegisterBinding {
+        id: u32,
+        modifiers: KeyModifiers,
+        keysym: u32,
+    },
+
+    /// The wm unregistered the keybinding with the specified id.
+    UnregisterBinding(u32),
+
+    /// The wm requested a screenshot of a toplevel.
+    ///
+    /// The screenshot must fit within `max_size`. The result is sent back with
+    /// [`WmEvent::ToplevelScreenshot`] using the same serial.
+    ToplevelScreenshot {
+        toplevel: Id,
+        serial: u32,
+        max_size: PhysicalSize,
+    },
+
+    /// The wm requested a frame on an output.
+    ///
+    /// Once the next frame of the output was presented, the display server must send [`WmEvent::Frame`].
+    RequestFrame(Id),
+
+    /// The wm set a timer.
+    ///
+    /// Once the duration has passed, the display server must send [`WmEvent::Timer`] with the id. A timer with
+    /// the id of a pending timer replaces the pending timer. Timers without a duration are dispatched by the
+    /// runtime and never requested.
+    SetTimer { id: u32, duration: Duration },
+
+    /// The wm cancelled the pending timer with the specified id.
+    CancelTimer(u32),
+
+    /// The wm requested a virtual output with the specified size be created.
+    CreateVirtualOutput(PhysicalSize),
+
+    /// The wm requested the output be removed, if the output is a virtual output.
+    DestroyVirtualOutput(Id),
+
+    /// The wm turned an output on or off.
+    SetOutputPower { output: Id, on: bool },
+
+    /// The wm built a view.
+    ///
+    /// The view is not presented until a transaction places the view in the scene.
+    CreateView { view: Id, source: ViewSource },
+
+    /// The wm dropped a view.
+    DestroyView(Id),
+
+    /// The wm requested a program be started.
+    ///
+    /// The command is never empty. When a toplevel of the program requests activation using the token of the
+    /// launch, the display server must send [`WmEvent::Launched`] with the id of the launch.
+    Spawn {
+        launch: u32,
+        command: Vec<String>,
+        systemd_scope: bool,
+    },
+
+    /// The wm failed while handling an event.
+    ///
+    /// The wm runtime thread stops after sending this request and the runtime must be created again to continue
+    /// window management.
+    Crashed(CrashReport),
+
+    /// The wm failed while handling an event and the runtime instantiated the wm again.
+    ///
+    /// This is reported as [`RuntimeMessage::Restarted`].
+    Restarted(CrashReport),
+
+    /// Statistics of the wm since the previous report.
+    ///
+    /// This is reported as [`RuntimeMessage::Stats`].
+    Stats(WmStats),
+}
+
+/// What a view built by the wm presents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewSource {
+    /// A rectangle filled with a single color, as 0xRRGGBBAA.
+    SolidColor { color: u32, size: LogicalSize },
+
+    /// The surfaces of a toplevel.
+    Toplevel { toplevel: Id },
+
+    /// The surface of a surface node.
+    SurfaceNode { node: Id },
+
+    /// A view without content which other views are placed in.
+    Branch,
+}
+
+/// A change to the scene in a transaction committed by the wm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SceneOperation {
+    /// Animate the changes to the position, scale and opacity of views made by the transaction.
+    ///
+    /// The duration is at most [`MAX_ANIMATION_DURATION`].
+    Animate { duration: Duration, easing: Easing },
+
+    /// Set the position of the view relative to the parent of the view.
+    SetPosition { view: Id, x: i32, y: i32 },
+
+    /// Place the view directly above a sibling.
+    PlaceAbove { view: Id, sibling: Id },
+
+    /// Place the view directly below a sibling.
+    PlaceBelow { view: Id, sibling: Id },
+
+    /// Make the view a child of another view.
+    Reparent { view: Id, parent: Id },
+
+    /// Present the view on an output.
+    SetOutput { output: Id, view: Id },
+
+    /// Set the scale of the view and the children of the view.
+    ///
+    /// The scale is between [`MIN_VIEW_SCALE`] and [`MAX_VIEW_SCALE`].
+    SetScale { view: Id, scale: f32 },
+
+    /// Rotate and flip the view and the children of the view.
+    SetTransform { view: Id, transform: ViewTransform },
+
+    /// Set the opacity of the view and the children of the view, from 0.0 to 1.0.
+    SetOpacity { view: Id, opacity: f32 },
+
+    /// Only present the part of the view inside of the clip.
+    SetClip { view: Id, clip: Option<LogicalRect> },
+
+    /// Change the color, as 0xRRGGBBAA, and the size of a solid color view.
+    SetSolidColor { view: Id, color: u32, size: LogicalSize },
+
+    /// Assign the view to a workspace.
+    SetWorkspace { view: Id, workspace: Id },
+
+    /// Activate a workspace on the output of the workspace.
+    ActivateWorkspace { workspace: Id },
+}
+
+/// The longest animation of a transaction.
+pub const MAX_ANIMATION_DURATION: Duration = Duration::from_secs(10);
+
+/// The smallest scale of a view.
+pub const MIN_VIEW_SCALE: f32 = 0.01;
+
+/// The largest scale of a view.
+pub const MAX_VIEW_SCALE: f32 = 100.0;
+
+/// The largest size or bounds the wm may configure a toplevel with, in each dimension.
+///
+/// This is the texture size limit of most GPUs. A larger toplevel could not be presented anyways.
+pub const MAX_CONFIGURE_SIZE: u32 = 16384;
+
+/// The largest screenshot of a toplevel the wm may request, in each dimension.
+pub const MAX_SCREENSHOT_SIZE: u32 = 1024;
+
+/// The minimum time between two screenshots of the same toplevel.
+pub const SCREENSHOT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The contents of a toplevel at the size and scale of a commit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnapshotInfo {
+    pub size: LogicalSize,
+    pub scale: f32,
+}
+
+/// Pixels read back from a toplevel.
+///
+/// The pixels are stored row by row without padding in RGBA 8888 with premultiplied alpha.
+#[derive(Clone)]
+pub struct ScreenshotImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl fmt::Debug for ScreenshotImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The pixel data is large and not useful in logs or traces.
+        f.debug_struct("ScreenshotImage")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("len", &self.data.len())
+            .finish()
+    }
+}
+
+/// The state of an output visible to the wm.
+#[derive(Debug, Clone)]
+pub struct OutputInfo {
+    pub name: Option<String>,
+
+    /// The location and size of the output in the global compositor space.
+    pub geometry: LogicalRect,
+
+    /// Refresh rate in millihertz.
+    pub refresh_rate: u32,
+}
+
+/// Description of a failure in the wm.
+#[derive(Debug, Clone)]
+pub struct CrashReport {
+    /// Description of the event which was being dispatched when the wm failed.
+    pub event: String,
+
+    /// Description of the error.
+    ///
+    /// This may be a trap inside the wm or a panic in the host.
+    pub error: String,
+}
+
+/// How often the runtime instantiates a failed wm again before giving up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times the wm may be restarted within the window.
+    ///
+    /// Zero disables restarting the wm inside the runtime.
+    pub max_restarts: u32,
+
+    /// Restarts older than the window are forgotten.
+    pub window: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: 3,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Limits on the execution of the wm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionLimits {
+    /// The longest the wm may take to handle a single event, or to start.
+    pub max_callback_time: Duration,
+}
+
+impl Default for ExecutionLimits {
+    fn default() -> Self {
+        Self {
+            max_callback_time: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Options for a wm runtime.
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeOptions {
+    pub retry_policy: RetryPolicy,
+    pub limits: ExecutionLimits,
+
+    /// WASI features available to the wm, or [`None`] if the wm may not use WASI.
+    pub wasi: Option<WasiConfig>,
 }
 
 /// A message from the wm runtime.
@@ -121,6 +671,15 @@ pub enum WmRequest {
 pub enum RuntimeMessage {
     Request(WmRequest),
 
+    /// The wm failed and was instantiated again.
+    ///
+    /// State the display server keeps on behalf of the wm, such as views, keybindings and workspaces, belongs to
+    /// the failed instance and must be forgotten.
+    Restarted(CrashReport),
+
+    /// Statistics of the wm, reported every [`STATS_INTERVAL`] while the wm handles events.
+    Stats(WmStats),
+
     Closed,
 }
 
@@ -128,13 +687,77 @@ pub enum RuntimeMessage {
 pub struct ToplevelUpdate {
     pub app_id: Option<String>,
     pub title: Option<String>,
-    pub min_size: ConfigureUpdate<Size>,
-    pub max_size: ConfigureUpdate<Size>,
-    pub geometry: ConfigureUpdate<Geometry>,
+    pub min_size: ConfigureUpdate<LogicalSize>,
+    pub max_size: ConfigureUpdate<LogicalSize>,
+    pub geometry: ConfigureUpdate<LogicalRect>,
     pub parent: ConfigureUpdate<Id>,
     pub state: Option<ToplevelState>,
     pub decorations: Option<DecorationMode>,
+    pub preferred_decorations: ConfigureUpdate<DecorationMode>,
+    pub decoration_style: Option<DecorationStyle>,
     pub resize_edge: ConfigureUpdate<ResizeEdge>,
+    pub request: Option<StateRequest>,
+}
+
+/// A change of state requested by a toplevel.
+///
+/// The wm satisfies or denies the request by configuring the toplevel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateRequest {
+    SetMaximized,
+    UnsetMaximized,
+
+    /// The toplevel requested to be made fullscreen, on the output if specified.
+    SetFullscreen {
+        output: Option<Id>,
+    },
+    UnsetFullscreen,
+    SetMinimized,
+}
+
+/// The state a wm configured for a toplevel.
+///
+/// Properties which were not set keep their previous value.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigureRequest {
+    pub decorations: Option<DecorationMode>,
+    pub parent: ConfigureUpdate<Id>,
+    pub state: Option<ToplevelState>,
+    pub minimized: Option<bool>,
+    pub size: ConfigureUpdate<LogicalSize>,
+    pub bounds: ConfigureUpdate<LogicalSize>,
+}
+
+impl ConfigureRequest {
+    /// Combine the configure with a newer configure, which takes precedence for properties set by both.
+    pub fn merge(self, newer: Self) -> Self {
+        fn update<T>(older: ConfigureUpdate<T>, newer: ConfigureUpdate<T>) -> ConfigureUpdate<T> {
+            if newer.is_update() {
+                newer
+            } else {
+                older
+            }
+        }
+
+        Self {
+            decorations: newer.decorations.or(self.decorations),
+            parent: update(self.parent, newer.parent),
+            state: newer.state.or(self.state),
+            minimized: newer.minimized.or(self.minimized),
+            size: update(self.size, newer.size),
+            bounds: update(self.bounds, newer.bounds),
+        }
+    }
+}
+
+/// A configure acked by a toplevel.
+#[derive(Debug, Clone)]
+pub struct AckedConfigure {
+    /// The serial the wm was given for the configure.
+    pub serial: u32,
+
+    /// The state the toplevel acked, including the state of older configures which were acked implicitly.
+    pub request: ConfigureRequest,
 }
 
 /// The WM runtime.
@@ -145,7 +768,8 @@ pub struct ToplevelUpdate {
 #[must_use]
 pub struct WmRuntime {
     channel: Channel<WmRequest>,
-    sender: Sender<WmEvent>,
+    sender: EventSender,
+    info: ModuleInfo,
 }
 
 impl EventSource for WmRuntime {
@@ -168,6 +792,14 @@ impl EventSource for WmRuntime {
         let mut closed = false;
 
         self.channel.process_events(readiness, token, |event, _| match event {
+            channel::Event::Msg(WmRequest::Restarted(report)) => {
+                callback(RuntimeMessage::Restarted(report), &mut ());
+            }
+
+            channel::Event::Msg(WmRequest::Stats(stats)) => {
+                callback(RuntimeMessage::Stats(stats), &mut ());
+            }
+
             channel::Event::Msg(request) => {
                 callback(RuntimeMessage::Request(request), &mut ());
             }
@@ -201,40 +833,180 @@ impl EventSource for WmRuntime {
 }
 
 impl WmRuntime {
-    pub fn new(bytes: &[u8]) -> wasmtime::Result<WmRuntime> {
-        let (event_sender, event_channel) = calloop::channel::channel();
+    /// Returns a sender which may be used to send events to the wm.
+    pub fn sender(&self) -> EventSender {
+        self.sender.clone()
+    }
+
+    /// What the wm module reported about itself.
+    pub fn info(&self) -> &ModuleInfo {
+        &self.info
+    }
+
+    pub fn new(bytes: &[u8]) -> Result<WmRuntime, WmRuntimeError> {
+        Self::with_policy(bytes, RetryPolicy::default())
+    }
+
+    /// Create a runtime which restarts a failed wm according to the retry policy.
+    pub fn with_policy(bytes: &[u8], policy: RetryPolicy) -> Result<WmRuntime, WmRuntimeError> {
+        Self::with_options(
+            bytes,
+            RuntimeOptions {
+                retry_policy: policy,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Create a runtime with the specified options.
+    pub fn with_options(bytes: &[u8], options: RuntimeOptions) -> Result<WmRuntime, WmRuntimeError> {
+        let (event_sender, event_channel) = channel::channel(EVENT_CHANNEL_CAPACITY);
         let (req_sender, req_channel) = calloop::channel::channel();
+        let module = WmModule::compile(bytes, &options)?;
+        let (store, wm, funcs) = module.instantiate(req_sender)?;
+
+        let runtime = WmRuntime {
+            channel: req_channel,
+            sender: event_sender,
+            info: store
+                .data()
+                .info
+                .clone()
+                .expect("the info of the wm is known once the wm was instantiated"),
+        };
+
+        // Start the wm thread.
+        WmRunner::new(event_channel, module, options.retry_policy, store, wm, funcs)
+            .run()
+            .map_err(WmRuntimeError::Spawn)?;
+
+        Ok(runtime)
+    }
+}
+
+/// A compiled wm component.
+///
+/// The component is kept after the wm was instantiated so a failed wm can be instantiated again without compiling
+/// the component again.
+struct WmModule {
+    engine: Engine,
+    component: Component,
+    limits: ExecutionLimits,
+    wasi: Option<WasiConfig>,
+
+    /// Advances the epoch of the engine while the module is alive.
+    _ticker: EpochTicker,
+}
 
+impl WmModule {
+    /// Compile the wm component with the options of the runtime.
+    fn compile(bytes: &[u8], options: &RuntimeOptions) -> Result<Self, WmRuntimeError> {
         let mut config = Config::new();
         config
+            .async_support(true)
             .consume_fuel(true)
+            .epoch_interruption(true)
             .wasm_backtrace(true)
             .wasm_component_model(true);
 
-        let engine = Engine::new(&config)?;
+        let engine = Engine::new(&config).map_err(WmRuntimeError::InstantiationFailed)?;
+        let component = Component::new(&engine, bytes).map_err(WmRuntimeError::InstantiationFailed)?;
+        let ticker = EpochTicker::start(engine.clone()).map_err(WmRuntimeError::Spawn)?;
+
+        Ok(WmModule {
+            engine,
+            component,
+            limits: options.limits,
+            wasi: options.wasi.clone(),
+            _ticker: ticker,
+        })
+    }
+
+    /// Instantiate the wm and create the wm resource.
+    ///
+    /// The wm must start within the callback time limit.
+    fn instantiate(&self, sender: Sender<WmRequest>) -> Result<(Store<WmState>, ResourceAny, WmTypes), WmRuntimeError> {
+        let limit = self.limits.max_callback_time;
+
+        executor::block_on_timeout(self.instantiate_async(sender), limit).unwrap_or_else(|| {
+            Err(WmRuntimeError::InstantiationFailed(wasmtime::Error::msg(format!(
+                "wm did not start within {limit:?}"
+            ))))
+        })
+    }
 
+    async fn instantiate_async(
+        &self,
+        sender: Sender<WmRequest>,
+    ) -> Result<(Store<WmState>, ResourceAny, WmTypes), WmRuntimeError> {
         let mut store = Store::new(
-            &engine,
+            &self.engine,
             WmState {
-                sender: req_sender,
-                ids: Vec::new(),
+                sender,
+                handles: HandleTable::new(),
                 toplevels: HashMap::new(),
+                outputs: HashMap::new(),
+                bindings: HashMap::new(),
+                timers: HashSet::new(),
+                surface_nodes: HashSet::new(),
+                next_launch: 0,
+                events: EventQueue::new(),
+                screenshots: HashMap::new(),
+                next_screenshot_serial: 0,
+                configures: ResourceTable::new(),
+                next_configure_serial: 0,
+                transactions: ResourceTable::new(),
+                workspaces: ResourceTable::new(),
+                snapshots: ResourceTable::new(),
+                view_builders: ResourceTable::new(),
+                views: ResourceTable::new(),
+                info: None,
+                span: tracing::Span::none(),
+                log_limiter: LogLimiter::new(),
+                memory: MemoryTracker::default(),
+                wasi: WasiState::new(self.wasi.as_ref()).map_err(WmRuntimeError::InstantiationFailed)?,
             },
         );
 
-        let component = wasmtime::component::Component::new(&engine, bytes)?;
-        let linker = Linker::new(&engine);
+        let mut linker = Linker::new(&self.engine);
+        host::AerugoWm::add_to_linker(&mut linker, |state: &mut WmState| state)
+            .map_err(WmRuntimeError::InstantiationFailed)?;
+
+        // Without WASI, a wm importing WASI interfaces fails to instantiate.
+        if self.wasi.is_some() {
+            wasmtime_wasi::preview2::command::add_to_linker(&mut linker)
+                .map_err(WmRuntimeError::InstantiationFailed)?;
+        }
 
-        // TODO: Tune the fuel amount
-        store.add_fuel(10000).unwrap();
+        // Yield back to the wm thread at every epoch, so the wm thread can stop a wm which takes too long.
+        store.epoch_deadline_async_yield_and_update(1);
 
-        let (aerugo_wm, instance) = host::AerugoWm::instantiate(&mut store, &component, &linker)?;
+        // Fuel is only metered for the statistics, so the wm never runs out.
+        store.add_fuel(u64::MAX).map_err(WmRuntimeError::InstantiationFailed)?;
+        store.limiter(|state| &mut state.memory);
+
+        let (aerugo_wm, instance) = host::AerugoWm::instantiate_async(&mut store, &self.component, &linker)
+            .await
+            .map_err(WmRuntimeError::InstantiationFailed)?;
         let info = aerugo_wm
             .aerugo_wm_wm_types()
-            .call_get_info(&mut store)?
-            .expect("Handle string error");
+            .call_get_info(&mut store)
+            .await
+            .map_err(WmRuntimeError::InstantiationFailed)?
+            .map_err(WmRuntimeError::GuestError)?;
+
+        let abi = AbiVersion {
+            major: info.abi_major,
+            minor: info.abi_minor,
+        };
+        check_abi(abi)?;
 
-        // TODO: Validate info
+        store.data_mut().span = tracing::info_span!("wm", name = %info.name, version = %info.version);
+        store.data_mut().info = Some(ModuleInfo {
+            name: info.name,
+            version: info.version,
+            abi,
+        });
 
         // Allocate the server (id 0).
         let server = Resource::new_own(0);
@@ -242,25 +1014,107 @@ impl WmRuntime {
         // Initialize the wm on this thread.
         let wm = aerugo_wm
             .aerugo_wm_wm_types()
-            .call_create_wm(&mut store, server)?
-            .expect("Handle string error");
+            .call_create_wm(&mut store, server)
+            .await
+            .map_err(WmRuntimeError::InstantiationFailed)?
+            .map_err(WmRuntimeError::GuestError)?;
 
         let mut exports = instance.exports(&mut store);
-        let mut export_wm = exports.instance("wm").expect("Handle missing wm export");
-        let funcs = WmTypes::new(&mut export_wm)?;
+        let mut export_wm = exports
+            .instance("wm")
+            .ok_or_else(|| WmRuntimeError::MissingExport { name: "wm".into() })?;
+        let funcs = WmTypes::new(&mut export_wm).map_err(WmRuntimeError::InstantiationFailed)?;
 
         // Rust wants us to explicitly drop exports for some reason...
         drop(exports);
 
-        let runtime = WmRuntime {
-            channel: req_channel,
-            sender: event_sender,
-        };
+        Ok((store, wm, funcs))
+    }
+}
 
-        // Start the wm thread.
-        WmRunner::new(event_channel, store, wm, funcs).run()?;
+/// The newest version of the wm ABI supported by the runtime.
+///
+/// A wm linked to an older minor version of the same major version is supported as well.
+pub const ABI_VERSION: AbiVersion = AbiVersion { major: 0, minor: 1 };
+
+/// Version of the wm ABI a wm module was linked to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbiVersion {
+    pub major: u32,
+    pub minor: u32,
+}
 
-        Ok(runtime)
+impl Display for AbiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// What a wm module reported about itself when instantiated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleInfo {
+    pub name: String,
+    pub version: String,
+
+    /// The version of the wm ABI the module was linked to.
+    pub abi: AbiVersion,
+}
+
+/// Check whether the runtime supports the ABI version a wm module was linked to.
+fn check_abi(version: AbiVersion) -> Result<(), WmRuntimeError> {
+    if version.major != ABI_VERSION.major || version.minor > ABI_VERSION.minor {
+        return Err(WmRuntimeError::AbiMismatch {
+            expected: ABI_VERSION,
+            got: version,
+        });
+    }
+
+    Ok(())
+}
+
+/// An error which prevented the wm from starting.
+#[derive(Debug)]
+pub enum WmRuntimeError {
+    /// The wm module could not be compiled or instantiated, or the wm trapped while being created.
+    InstantiationFailed(wasmtime::Error),
+
+    /// The wm module does not export a required interface.
+    MissingExport { name: String },
+
+    /// The wm module was linked to a version of the ABI the runtime does not support.
+    AbiMismatch { expected: AbiVersion, got: AbiVersion },
+
+    /// The wm returned an error from `get-info` or `create-wm`.
+    GuestError(String),
+
+    /// The wm thread could not be spawned.
+    Spawn(io::Error),
+}
+
+impl Display for WmRuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // The alternate format includes the causes of the error.
+            WmRuntimeError::InstantiationFailed(err) => write!(f, "failed to instantiate wm: {err:#}"),
+            WmRuntimeError::MissingExport { name } => write!(f, "wm does not export {name:?}"),
+            WmRuntimeError::AbiMismatch { expected, got } => write!(
+                f,
+                "wm was linked to abi {got}, but the runtime supports abi {}.0 to {expected}",
+                expected.major
+            ),
+            WmRuntimeError::GuestError(err) => write!(f, "wm failed to start: {err}"),
+            WmRuntimeError::Spawn(err) => write!(f, "failed to spawn wm thread: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for WmRuntimeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WmRuntimeError::InstantiationFailed(err) => Some(err.as_ref()),
+            WmRuntimeError::Spawn(err) => Some(err),
+            _ => None,
+        }
     }
 }
 
@@ -289,7 +1143,19 @@ impl From<IdError> for Error {
 pub enum IdError {
     ZeroId,
 
-    InvalidId { rep: u32, ty: IdType },
+    InvalidId {
+        rep: u32,
+        ty: IdType,
+    },
+
+    /// The resource refers to an object which was destroyed, the slot of the handle may have been reused since.
+    Stale {
+        rep: u32,
+        ty: IdType,
+    },
+
+    /// Too many handles are alive.
+    Exhausted,
 }
 
 impl Display for IdError {
@@ -297,6 +1163,11 @@ impl Display for IdError {
         match self {
             IdError::ZeroId => write!(f, "zero id"),
             IdError::InvalidId { rep, ty } => write!(f, "invalid id: Id {{ rep: {rep}, ty: {ty:?} }}"),
+            IdError::Stale { rep, ty } => write!(
+                f,
+                "stale {ty} handle {rep:#x}: the {ty} was destroyed and the handle must no longer be used"
+            ),
+            IdError::Exhausted => write!(f, "too many handles"),
         }
     }
 }
@@ -306,25 +1177,98 @@ impl std::error::Error for IdError {}
 #[derive(Debug)]
 struct WmState {
     sender: Sender<WmRequest>,
-    ids: Vec<Option<IdType>>,
+
+    /// Handles of the toplevel and output resources held by the wm.
+    handles: HandleTable,
+
+    /// Toplevels known to the wm, keyed by the rep of their id.
+    ///
+    /// Events name toplevels by id, so toplevels are not stored in a resource table. Toplevel resources refer to
+    /// the toplevel through [`WmState::handles`].
     toplevels: HashMap<NonZeroU32, WmToplevel>,
+    outputs: HashMap<NonZeroU32, OutputInfo>,
+
+    /// Keybindings registered by the wm, keyed by the id of the binding.
+    bindings: HashMap<u32, (KeyModifiers, u32)>,
+
+    /// Surface nodes known to the wm, keyed by the rep of their id.
+    surface_nodes: HashSet<NonZeroU32>,
+
+    /// Ids of the pending timers set by the wm.
+    ///
+    /// A timer may expire while the wm cancels it, so expired timers are only dispatched if still pending.
+    timers: HashSet<u32>,
+
+    next_launch: u32,
+
+    /// Events waiting to be dispatched to the guest.
+    ///
+    /// Host functions must never call into the guest. Events generated by host functions are deferred through
+    /// this queue instead.
+    events: EventQueue<WmEvent>,
+
+    /// When a screenshot of each toplevel was last requested.
+    screenshots: HashMap<NonZeroU32, Instant>,
+
+    next_screenshot_serial: u32,
+
+    /// Configures being built by the wm.
+    configures: ResourceTable<WmToplevelConfigure>,
+
+    next_configure_serial: u32,
+
+    /// Transactions being built by the wm.
+    transactions: ResourceTable<WmTransaction>,
+
+    /// Workspaces created by the wm.
+    ///
+    /// The handle of a workspace is the rep of the id of the workspace.
+    workspaces: ResourceTable<WmWorkspace>,
+
+    /// Snapshots of toplevels given to the wm.
+    snapshots: ResourceTable<SnapshotInfo>,
+
+    /// View builders which were not built yet.
+    view_builders: ResourceTable<ViewSource>,
+
+    /// Views built by the wm.
+    ///
+    /// The handle of a view is the rep of the id of the view.
+    views: ResourceTable<ViewSource>,
+
+    /// What the wm reported about itself, once instantiated.
+    info: Option<ModuleInfo>,
+
+    /// The span messages logged by the wm are emitted in.
+    span: tracing::Span,
+
+    log_limiter: LogLimiter,
+
+    /// The size of the memories of the wm.
+    memory: MemoryTracker,
+
+    wasi: WasiState,
 }
 
 impl WmState {
-    fn get_id<T: 'static>(&self, resource: &Resource<T>, ty: IdType) -> Result<Id, Error> {
-        let rep = NonZeroU32::new(resource.rep()).ok_or(IdError::ZeroId)?;
-
-        if self
-            .ids
-            .get(rep.get() as usize)
-            .ok_or(IdError::InvalidId { rep: rep.get(), ty })?
-            .filter(|&info| info == ty)
-            .is_none()
-        {
-            return Err(Error::Id(IdError::InvalidId { rep: rep.get(), ty }));
+    /// Rate limit screenshots of a toplevel.
+    ///
+    /// Returns the serial of the screenshot if a screenshot may be taken now.
+    fn screenshot_serial(&mut self, id: Id, now: Instant) -> Option<u32> {
+        if let Some(last) = self.screenshots.get(&id.rep()) {
+            if now.duration_since(*last) < SCREENSHOT_INTERVAL {
+                return None;
+            }
         }
 
-        Ok(Id(rep, IdType::Toplevel))
+        self.screenshots.insert(id.rep(), now);
+        let serial = self.next_screenshot_serial;
+        self.next_screenshot_serial = self.next_screenshot_serial.wrapping_add(1);
+        Some(serial)
+    }
+
+    fn get_id<T: 'static>(&self, resource: &Resource<T>, ty: IdType) -> Result<Id, Error> {
+        Ok(self.handles.id(resource.rep(), ty)?)
     }
 
     fn validate_id_server(&self, resource: &Resource<Server>) -> Result<(), Error> {
@@ -351,11 +1295,109 @@ impl WmState {
         }))
     }
 
-    fn get_toplevel_configure<T: 'static>(&self, _resource: &Resource<T>) -> Result<&mut WmToplevelConfigure, Error> {
-        todo!()
+    fn get_output_res<T: 'static>(&self, resource: &Resource<T>) -> Result<&OutputInfo, Error> {
+        let id = self.get_id(resource, IdType::Output)?;
+        self.outputs.get(&id.rep()).ok_or(Error::Id(IdError::InvalidId {
+            rep: id.rep().get(),
+            ty: IdType::Output,
+        }))
+    }
+
+    fn get_toplevel_configure<T: 'static>(
+        &mut self,
+        resource: &Resource<T>,
+    ) -> Result<&mut WmToplevelConfigure, Error> {
+        Ok(self.configures.get_mut(resource.rep(), IdType::Configure)?)
+    }
+
+    /// Store a new configure and return the rep of the configure resource.
+    fn insert_toplevel_configure(&mut self, configure: WmToplevelConfigure) -> Result<u32, Error> {
+        Ok(self.configures.insert(configure)?)
+    }
+
+    /// Check a configure submitted by the wm and assign the serial of the configure.
+    ///
+    /// No serial is used up if the configure is rejected.
+    fn submit_configure<T: 'static>(
+        &mut self,
+        resource: &Resource<T>,
+    ) -> Result<Result<SubmittedConfigure, ConfigureError>, Error> {
+        let configure = self.get_toplevel_configure(resource)?;
+        let toplevel = configure.toplevel_id;
+        let request = configure.request.clone();
+
+        // The toplevel may have been closed since the configure was created.
+        if self.get_toplevel(toplevel).is_err() {
+            return Ok(Err(ConfigureError::Closed));
+        }
+
+        if let Err(err) = validate_configure(&request) {
+            tracing::warn!(?toplevel, ?request, "Wm submitted an invalid configure");
+            return Ok(Err(err));
+        }
+
+        let serial = self.next_configure_serial;
+        self.next_configure_serial = self.next_configure_serial.wrapping_add(1);
+
+        Ok(Ok(SubmittedConfigure {
+            toplevel,
+            serial,
+            request,
+        }))
+    }
+
+    fn get_transaction<T: 'static>(&mut self, resource: &Resource<T>) -> Result<&mut WmTransaction, Error> {
+        Ok(self.transactions.get_mut(resource.rep(), IdType::Transaction)?)
+    }
+
+    /// Create an empty transaction and return the rep of the transaction resource.
+    fn insert_transaction(&mut self) -> Result<u32, Error> {
+        Ok(self.transactions.insert(WmTransaction::default())?)
+    }
+
+    fn get_workspace<T: 'static>(&self, resource: &Resource<T>) -> Result<(Id, &WmWorkspace), Error> {
+        let workspace = self.workspaces.get(resource.rep(), IdType::Workspace)?;
+        // A live handle is never zero.
+        let rep = NonZeroU32::new(resource.rep()).unwrap();
+        Ok((Id::workspace(rep), workspace))
+    }
+
+    /// Store a new workspace and return the id of the workspace.
+    fn insert_workspace(&mut self, workspace: WmWorkspace) -> Result<Id, Error> {
+        let rep = self.workspaces.insert(workspace)?;
+        Ok(Id::workspace(NonZeroU32::new(rep).unwrap()))
+    }
+
+    fn get_view<T: 'static>(&self, resource: &Resource<T>) -> Result<Id, Error> {
+        self.views.get(resource.rep(), IdType::View)?;
+        // A live handle is never zero.
+        Ok(Id::view(NonZeroU32::new(resource.rep()).unwrap()))
+    }
+
+    /// Store a new view and return the id of the view.
+    fn insert_view(&mut self, source: ViewSource) -> Result<Id, Error> {
+        let rep = self.views.insert(source)?;
+        Ok(Id::view(NonZeroU32::new(rep).unwrap()))
     }
 }
 
+/// Check whether a configure from the wm may be sent to a toplevel.
+///
+/// A buggy wm must not cause a protocol error to be posted to a client, so a configure is rejected before it
+/// is sent.
+fn validate_configure(request: &ConfigureRequest) -> Result<(), ConfigureError> {
+    let too_large = |size: &ConfigureUpdate<LogicalSize>| match size {
+        ConfigureUpdate::Update(Some(size)) => size.width > MAX_CONFIGURE_SIZE || size.height > MAX_CONFIGURE_SIZE,
+        _ => false,
+    };
+
+    if too_large(&request.size) || too_large(&request.bounds) {
+        return Err(ConfigureError::TooLarge);
+    }
+
+    Ok(())
+}
+
 /// Toplevel wm runtime state.
 #[derive(Debug)]
 struct WmToplevel {
@@ -370,7 +1412,40 @@ struct WmToplevel {
     parent: Option<Id>,
     state: ToplevelState,
     decorations: DecorationMode,
+    preferred_decorations: Option<DecorationMode>,
+    decoration_style: DecorationStyle,
     resize_edge: Option<ResizeEdge>,
+
+    /// The output the toplevel last requested to be made fullscreen on.
+    fullscreen_output: Option<Id>,
+}
+
+impl WmToplevel {
+    /// A toplevel the wm is told about once the first update arrives.
+    fn new(id: Id, features: Features) -> Self {
+        Self {
+            id,
+            initial_commit: true,
+            features,
+            app_id: Default::default(),
+            title: Default::default(),
+            min_size: Default::default(),
+            max_size: Default::default(),
+            geometry: Default::default(),
+            parent: Default::default(),
+            state: Default::default(),
+            decorations: DecorationMode::ClientSide,
+            preferred_decorations: None,
+            decoration_style: DecorationStyle {
+                border_width: None,
+                border_color: None,
+                focused_border_color: None,
+                title_bar: None,
+            },
+            resize_edge: Default::default(),
+            fullscreen_output: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -386,19 +1461,73 @@ impl<T> ConfigureUpdate<T> {
     }
 }
 
+/// A workspace created by the wm.
+#[derive(Debug)]
+struct WmWorkspace {
+    output: Id,
+    name: String,
+}
+
 #[derive(Debug)]
 struct WmToplevelConfigure {
     toplevel_id: Id,
-    decorations: Option<DecorationMode>,
-    parent: ConfigureUpdate<Id>,
-    state: Option<ToplevelState>,
-    size: ConfigureUpdate<Size>,
-    bounds: ConfigureUpdate<Size>,
+    request: ConfigureRequest,
+}
+
+/// A configure which was assigned a serial and may be sent to the toplevel.
+#[derive(Debug)]
+struct SubmittedConfigure {
+    toplevel: Id,
+    serial: u32,
+    request: ConfigureRequest,
+}
+
+impl From<SubmittedConfigure> for WmRequest {
+    fn from(configure: SubmittedConfigure) -> Self {
+        WmRequest::ToplevelConfigure {
+            toplevel: configure.toplevel,
+            serial: configure.serial,
+            configure: configure.request,
+        }
+    }
+}
+
+/// A transaction being built by the wm.
+#[derive(Debug, Default)]
+struct WmTransaction {
+    /// Configures sent when the transaction is committed.
+    configures: Vec<SubmittedConfigure>,
+
+    operations: Vec<SceneOperation>,
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Id, WmEvent, WmRequest};
+    use std::{
+        collections::{HashMap, HashSet},
+        num::NonZeroU32,
+        time::Instant,
+    };
+
+    use wasmtime::component::Resource;
+
+    use crate::{
+        check_abi,
+        geometry::LogicalSize,
+        host::aerugo::wm::types::{
+            Features, HostServer, HostSnapshot, HostToplevelConfigure, HostTransaction, HostView, HostViewBuilder,
+            Size, Snapshot, Toplevel, ToplevelConfigure, ToplevelState, Transaction, View, ViewBuilder,
+        },
+        id::{HandleTable, ResourceTable},
+        log::LogLimiter,
+        queue::EventQueue,
+        stats::MemoryTracker,
+        validate_configure,
+        wasi::WasiState,
+        AbiVersion, ConfigureError, ConfigureRequest, ConfigureUpdate, Error, Id, IdError, IdType, SceneOperation,
+        SnapshotInfo, ViewSource, WmEvent, WmRequest, WmRuntimeError, WmState, WmToplevel, WmToplevelConfigure,
+        WmWorkspace, ABI_VERSION, MAX_CONFIGURE_SIZE, SCREENSHOT_INTERVAL,
+    };
 
     fn assert_send<T: Send>() {}
 
@@ -416,4 +1545,444 @@ mod tests {
     fn is_request_send() {
         assert_send::<WmRequest>();
     }
+
+    #[test]
+    fn abi_older_minor_supported() {
+        assert!(check_abi(ABI_VERSION).is_ok());
+        assert!(check_abi(AbiVersion {
+            major: ABI_VERSION.major,
+            minor: 0
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn abi_mismatch() {
+        let newer_minor = AbiVersion {
+            major: ABI_VERSION.major,
+            minor: ABI_VERSION.minor + 1,
+        };
+        let other_major = AbiVersion {
+            major: ABI_VERSION.major + 1,
+            minor: 0,
+        };
+
+        for version in [newer_minor, other_major] {
+            assert!(matches!(
+                check_abi(version),
+                Err(WmRuntimeError::AbiMismatch { got, .. }) if got == version
+            ));
+        }
+    }
+
+    fn state() -> WmState {
+        let (sender, _channel) = calloop::channel::channel();
+        WmState {
+            sender,
+            handles: HandleTable::new(),
+            toplevels: HashMap::new(),
+            outputs: HashMap::new(),
+            bindings: HashMap::new(),
+            timers: HashSet::new(),
+            surface_nodes: HashSet::new(),
+            next_launch: 0,
+            events: EventQueue::new(),
+            screenshots: HashMap::new(),
+            next_screenshot_serial: 0,
+            configures: ResourceTable::new(),
+            next_configure_serial: 0,
+            transactions: ResourceTable::new(),
+            workspaces: ResourceTable::new(),
+            snapshots: ResourceTable::new(),
+            view_builders: ResourceTable::new(),
+            views: ResourceTable::new(),
+            info: None,
+            span: tracing::Span::none(),
+            log_limiter: LogLimiter::new(),
+            memory: MemoryTracker::default(),
+            wasi: WasiState::new(None).unwrap(),
+        }
+    }
+
+    #[test]
+    fn screenshot_rate_limit() {
+        let mut state = state();
+
+        let first = Id::toplevel(NonZeroU32::new(1).unwrap());
+        let second = Id::toplevel(NonZeroU32::new(2).unwrap());
+        let now = Instant::now();
+
+        assert_eq!(state.screenshot_serial(first, now), Some(0));
+        assert_eq!(state.screenshot_serial(first, now), None);
+        // The limit applies per toplevel.
+        assert_eq!(state.screenshot_serial(second, now), Some(1));
+        assert_eq!(state.screenshot_serial(first, now + SCREENSHOT_INTERVAL), Some(2));
+    }
+
+    #[test]
+    fn dropped_configure_is_released() {
+        let mut state = state();
+        let toplevel_id = Id::toplevel(NonZeroU32::new(1).unwrap());
+        let configure = || WmToplevelConfigure {
+            toplevel_id,
+            request: ConfigureRequest::default(),
+        };
+
+        let first = state.insert_toplevel_configure(configure()).unwrap();
+        let resource = Resource::<ToplevelConfigure>::new_own(first);
+        assert!(state.get_toplevel_configure(&resource).is_ok());
+
+        // The guest dropped the configure, the slot is reused but the old resource stays invalid.
+        HostToplevelConfigure::drop(&mut state, Resource::new_own(first)).unwrap();
+        let second = state.insert_toplevel_configure(configure()).unwrap();
+        assert_ne!(first, second);
+        assert!(matches!(
+            state.get_toplevel_configure(&resource),
+            Err(Error::Id(IdError::Stale {
+                ty: IdType::Configure,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn configure_in_transaction() {
+        let mut state = state();
+        let id = Id::toplevel(NonZeroU32::new(1).unwrap());
+        state.toplevels.insert(id.rep(), WmToplevel::new(id, Features::empty()));
+        let toplevel = state.handles.insert(id).unwrap();
+        let size = |width| Some(Size { width, height: 600 });
+
+        let configure = HostToplevelConfigure::new(&mut state, Resource::<Toplevel>::new_borrow(toplevel))
+            .unwrap()
+            .rep();
+        let borrow = Resource::<ToplevelConfigure>::new_borrow;
+        HostToplevelConfigure::size(&mut state, borrow(configure), size(800)).unwrap();
+
+        let transaction = HostTransaction::new(&mut state).unwrap().rep();
+        let serial = HostTransaction::configure(&mut state, Resource::new_borrow(transaction), borrow(configure))
+            .unwrap()
+            .unwrap();
+        assert_eq!(serial, 0);
+
+        // The configure was copied into the transaction, later changes are submitted separately.
+        HostToplevelConfigure::size(&mut state, borrow(configure), size(MAX_CONFIGURE_SIZE + 1)).unwrap();
+        assert!(matches!(
+            HostToplevelConfigure::submit(&mut state, borrow(configure)).unwrap(),
+            Err(ConfigureError::TooLarge)
+        ));
+        HostToplevelConfigure::size(&mut state, borrow(configure), size(1024)).unwrap();
+        assert_eq!(
+            HostToplevelConfigure::submit(&mut state, borrow(configure))
+                .unwrap()
+                .unwrap(),
+            1
+        );
+
+        let pending = &state
+            .transactions
+            .get(transaction, IdType::Transaction)
+            .unwrap()
+            .configures;
+        assert_eq!(pending.len(), 1);
+        assert!(matches!(
+            pending[0].request.size,
+            ConfigureUpdate::Update(Some(size)) if size.width == 800
+        ));
+
+        HostTransaction::commit(&mut state, Resource::<Transaction>::new_borrow(transaction)).unwrap();
+        let committed = state.transactions.get(transaction, IdType::Transaction).unwrap();
+        assert!(committed.configures.is_empty());
+
+        // A closed toplevel cannot be configured, and no serial is used up.
+        state.toplevels.remove(&id.rep());
+        assert!(matches!(
+            HostTransaction::configure(&mut state, Resource::new_borrow(transaction), borrow(configure)).unwrap(),
+            Err(ConfigureError::Closed)
+        ));
+        assert_eq!(state.next_configure_serial, 2);
+    }
+
+    #[test]
+    fn merge_configures() {
+        let older = ConfigureRequest {
+            state: Some(ToplevelState::MAXIMIZED),
+            size: ConfigureUpdate::Update(Some(LogicalSize::new(800, 600))),
+            minimized: Some(true),
+            ..ConfigureRequest::default()
+        };
+        let newer = ConfigureRequest {
+            size: ConfigureUpdate::Update(None),
+            minimized: Some(false),
+            ..ConfigureRequest::default()
+        };
+
+        // Properties the newer configure did not set are kept.
+        let merged = older.merge(newer);
+        assert_eq!(merged.state, Some(ToplevelState::MAXIMIZED));
+        assert!(matches!(merged.size, ConfigureUpdate::Update(None)));
+        assert_eq!(merged.minimized, Some(false));
+        assert!(!merged.bounds.is_update());
+    }
+
+    #[test]
+    fn configure_size_limit() {
+        let size = |width, height| ConfigureUpdate::Update(Some(LogicalSize::new(width, height)));
+
+        // A zero size lets the toplevel pick the size.
+        let zero = ConfigureRequest {
+            size: size(0, 0),
+            ..Default::default()
+        };
+        assert!(validate_configure(&zero).is_ok());
+
+        let largest = ConfigureRequest {
+            size: size(MAX_CONFIGURE_SIZE, MAX_CONFIGURE_SIZE),
+            bounds: size(MAX_CONFIGURE_SIZE, MAX_CONFIGURE_SIZE),
+            ..Default::default()
+        };
+        assert!(validate_configure(&largest).is_ok());
+
+        let size_too_large = ConfigureRequest {
+            size: size(MAX_CONFIGURE_SIZE + 1, 1),
+            ..Default::default()
+        };
+        assert_eq!(validate_configure(&size_too_large), Err(ConfigureError::TooLarge));
+
+        let bounds_too_large = ConfigureRequest {
+            bounds: size(1, u32::MAX),
+            ..Default::default()
+        };
+        assert_eq!(validate_configure(&bounds_too_large), Err(ConfigureError::TooLarge));
+    }
+
+    #[test]
+    fn workspace_reps_are_unique() {
+        let mut state = state();
+        let output = Id::output(NonZeroU32::new(1).unwrap());
+        let workspace = |name: &str| WmWorkspace {
+            output,
+            name: name.into(),
+        };
+
+        let first = state.insert_workspace(workspace("1")).unwrap();
+        let second = state.insert_workspace(workspace("2")).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(
+            state.workspaces.get(first.rep().get(), IdType::Workspace).unwrap().name,
+            "1"
+        );
+
+        // Reps are allocated per resource type, so the first transaction may use the same rep.
+        assert_eq!(first.rep().get(), state.insert_transaction().unwrap());
+    }
+
+    #[test]
+    fn toplevel_and_branch_views() {
+        let mut state = state();
+        let id = Id::toplevel(NonZeroU32::new(1).unwrap());
+        state.toplevels.insert(id.rep(), WmToplevel::new(id, Features::empty()));
+        let toplevel = state.handles.insert(id).unwrap();
+        let snapshot = state
+            .snapshots
+            .insert(SnapshotInfo {
+                size: LogicalSize::new(800, 600),
+                scale: 1.0,
+            })
+            .unwrap();
+
+        let builder = HostViewBuilder::with_toplevel(
+            &mut state,
+            Resource::<Toplevel>::new_borrow(toplevel),
+            Resource::<Snapshot>::new_borrow(snapshot),
+        )
+        .unwrap()
+        .rep();
+        let view = HostViewBuilder::build(&mut state, Resource::new_borrow(builder))
+            .unwrap()
+            .rep();
+        assert_eq!(
+            state.views.get(view, IdType::View).unwrap(),
+            &ViewSource::Toplevel { toplevel: id }
+        );
+
+        let branch = HostViewBuilder::branch(&mut state).unwrap().rep();
+        let branch = HostViewBuilder::build(&mut state, Resource::new_borrow(branch))
+            .unwrap()
+            .rep();
+        assert_eq!(state.views.get(branch, IdType::View).unwrap(), &ViewSource::Branch);
+
+        // The toplevel view can be placed in the branch.
+        let transaction = HostTransaction::new(&mut state).unwrap().rep();
+        HostTransaction::reparent(
+            &mut state,
+            Resource::new_borrow(transaction),
+            Resource::<View>::new_borrow(view),
+            Resource::<View>::new_borrow(branch),
+        )
+        .unwrap();
+
+        // A closed toplevel cannot be presented.
+        state.toplevels.remove(&id.rep());
+        assert!(HostViewBuilder::with_toplevel(
+            &mut state,
+            Resource::<Toplevel>::new_borrow(toplevel),
+            Resource::<Snapshot>::new_borrow(snapshot),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn surface_node_view() {
+        let mut state = state();
+        let node = NonZeroU32::new(3).unwrap();
+
+        // Only announced surface nodes can be presented.
+        assert!(HostViewBuilder::with_surface_node(&mut state, node.get()).is_err());
+        assert!(HostViewBuilder::with_surface_node(&mut state, 0).is_err());
+
+        state.surface_nodes.insert(node);
+        let builder = HostViewBuilder::with_surface_node(&mut state, node.get())
+            .unwrap()
+            .rep();
+        let view = HostViewBuilder::build(&mut state, Resource::new_borrow(builder))
+            .unwrap()
+            .rep();
+        assert_eq!(
+            state.views.get(view, IdType::View).unwrap(),
+            &ViewSource::SurfaceNode {
+                node: Id::surface_node(node)
+            }
+        );
+    }
+
+    #[test]
+    fn solid_color_view() {
+        let mut state = state();
+        let size = Size {
+            width: 1920,
+            height: 1080,
+        };
+
+        let builder = HostViewBuilder::solid_color(&mut state, 0x000000aa, size)
+            .unwrap()
+            .rep();
+        let view = HostViewBuilder::build(&mut state, Resource::new_borrow(builder))
+            .unwrap()
+            .rep();
+        HostViewBuilder::drop(&mut state, Resource::<ViewBuilder>::new_own(builder)).unwrap();
+        assert_eq!(
+            state.views.get(view, IdType::View).unwrap(),
+            &ViewSource::SolidColor {
+                color: 0x000000aa,
+                size: LogicalSize::new(1920, 1080),
+            }
+        );
+
+        let transaction = HostTransaction::new(&mut state).unwrap().rep();
+        HostTransaction::set_solid_color(
+            &mut state,
+            Resource::new_borrow(transaction),
+            Resource::<View>::new_borrow(view),
+            0xff0000ff,
+            size,
+        )
+        .unwrap();
+
+        // Once the view is dropped, transactions may no longer reference the view.
+        HostView::drop(&mut state, Resource::<View>::new_own(view)).unwrap();
+        assert!(HostTransaction::set_opacity(
+            &mut state,
+            Resource::new_borrow(transaction),
+            Resource::<View>::new_borrow(view),
+            0.5,
+        )
+        .is_err());
+
+        let operations = &state
+            .transactions
+            .get(transaction, IdType::Transaction)
+            .unwrap()
+            .operations;
+        assert!(matches!(
+            operations[..],
+            [SceneOperation::SetSolidColor { color: 0xff0000ff, .. }]
+        ));
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut state = state();
+        let snapshot = state
+            .snapshots
+            .insert(SnapshotInfo {
+                size: LogicalSize::new(800, 600),
+                scale: 1.5,
+            })
+            .unwrap();
+
+        let size = HostSnapshot::size(&mut state, Resource::<Snapshot>::new_borrow(snapshot)).unwrap();
+        assert_eq!((size.width, size.height), (800, 600));
+        assert_eq!(
+            HostSnapshot::scale(&mut state, Resource::<Snapshot>::new_borrow(snapshot)).unwrap(),
+            1.5
+        );
+
+        // A dropped snapshot can no longer be inspected.
+        HostSnapshot::drop(&mut state, Resource::<Snapshot>::new_own(snapshot)).unwrap();
+        assert!(HostSnapshot::size(&mut state, Resource::<Snapshot>::new_borrow(snapshot)).is_err());
+    }
+
+    #[test]
+    fn spawn_assigns_launch_ids() {
+        let mut state = state();
+        let command = || vec!["foot".to_string()];
+
+        assert_eq!(
+            HostServer::spawn(&mut state, Resource::new_borrow(0), Vec::new(), false).unwrap(),
+            None
+        );
+        assert_eq!(
+            HostServer::spawn(&mut state, Resource::new_borrow(0), command(), false).unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            HostServer::spawn(&mut state, Resource::new_borrow(0), command(), true).unwrap(),
+            Some(1)
+        );
+    }
+
+    /// Dispatch the pending events like the runner, calling `callback` in place of the guest.
+    fn dispatch(state: &mut WmState, mut callback: impl FnMut(&mut WmState, &WmEvent)) -> Vec<&'static str> {
+        let mut dispatched = Vec::new();
+
+        while let Some(event) = state.events.begin() {
+            callback(state, &event);
+            dispatched.push(event.name());
+            state.events.end();
+        }
+
+        dispatched
+    }
+
+    #[test]
+    fn expired_timer_is_dispatched_after_host_call() {
+        let mut state = state();
+        state.events.defer(WmEvent::Binding {
+            id: 1,
+            time: 0,
+            repeat: false,
+        });
+
+        let dispatched = dispatch(&mut state, |state, event| {
+            if let WmEvent::Binding { .. } = event {
+                HostServer::set_timer(state, Resource::new_borrow(0), 7, 0).unwrap();
+
+                // The guest is still handling the binding, so the timer waits in the queue.
+                assert_eq!(state.events.len(), 1);
+            }
+        });
+
+        assert_eq!(dispatched, ["Binding", "Timer"]);
+    }
 }
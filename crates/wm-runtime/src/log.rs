@@ -0,0 +1,157 @@
+//! Logging for wm guests.
+//!
+//! Messages logged by the wm are emitted as [`tracing`] events inside the span of the wm, so the messages end up
+//! wherever the display server writes its own log. A wm which logs in a loop must not be able to flood the log or
+//! slow down the wm thread, so at most [`MAX_MESSAGES`] messages are logged every [`LOG_WINDOW`]. Messages past
+//! the limit are dropped and the number of dropped messages is logged once the window is over.
+
+use std::{
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    host::aerugo::wm::log::{Field, Host, Level},
+    WmState,
+};
+
+/// How many messages the wm may log per [`LOG_WINDOW`].
+pub const MAX_MESSAGES: u32 = 100;
+
+/// The window the number of messages is limited over.
+pub const LOG_WINDOW: Duration = Duration::from_secs(1);
+
+/// The maximum length of a message and the fields of the message in bytes.
+///
+/// Longer messages are truncated.
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+/// Limits how many messages the wm may log.
+#[derive(Debug)]
+pub(crate) struct LogLimiter {
+    window_start: Option<Instant>,
+    messages: u32,
+    dropped: u32,
+}
+
+impl LogLimiter {
+    pub fn new() -> Self {
+        Self {
+            window_start: None,
+            messages: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Whether a message may be logged now.
+    ///
+    /// If a new window started, the number of messages dropped in the previous window is returned as well.
+    pub fn check(&mut self, now: Instant) -> (bool, u32) {
+        let mut dropped = 0;
+
+        if self
+            .window_start
+            .map_or(true, |start| now.duration_since(start) >= LOG_WINDOW)
+        {
+            self.window_start = Some(now);
+            self.messages = 0;
+            dropped = std::mem::take(&mut self.dropped);
+        }
+
+        if self.messages >= MAX_MESSAGES {
+            self.dropped += 1;
+            return (false, dropped);
+        }
+
+        self.messages += 1;
+        (true, dropped)
+    }
+}
+
+impl Host for WmState {
+    fn log(&mut self, level: Level, mut message: String, fields: Vec<Field>) -> wasmtime::Result<()> {
+        let (allowed, dropped) = self.log_limiter.check(Instant::now());
+        let _span = self.span.enter();
+
+        if dropped > 0 {
+            tracing::warn!(dropped, "Wm logged too many messages, some messages were dropped");
+        }
+
+        if !allowed {
+            return Ok(());
+        }
+
+        let mut formatted = String::new();
+
+        for field in fields {
+            if !formatted.is_empty() {
+                formatted.push(' ');
+            }
+
+            let _ = write!(formatted, "{}={:?}", field.key, field.value);
+        }
+
+        truncate(&mut message, MAX_MESSAGE_LEN);
+        truncate(&mut formatted, MAX_MESSAGE_LEN.saturating_sub(message.len()));
+
+        match level {
+            Level::Trace => tracing::trace!(fields = %formatted, "{message}"),
+            Level::Debug => tracing::debug!(fields = %formatted, "{message}"),
+            Level::Info => tracing::info!(fields = %formatted, "{message}"),
+            Level::Warn => tracing::warn!(fields = %formatted, "{message}"),
+            Level::Error => tracing::error!(fields = %formatted, "{message}"),
+        }
+
+        Ok(())
+    }
+}
+
+/// Truncate a string to at most `len` bytes without splitting a character.
+fn truncate(string: &mut String, len: usize) {
+    if string.len() <= len {
+        return;
+    }
+
+    let mut end = len;
+
+    while !string.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    string.truncate(end);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{truncate, LogLimiter, LOG_WINDOW, MAX_MESSAGES};
+
+    #[test]
+    fn limiter_drops_excess_messages() {
+        let mut limiter = LogLimiter::new();
+        let now = Instant::now();
+
+        for _ in 0..MAX_MESSAGES {
+            assert_eq!(limiter.check(now), (true, 0));
+        }
+
+        assert_eq!(limiter.check(now), (false, 0));
+        assert_eq!(limiter.check(now + Duration::from_millis(10)), (false, 0));
+
+        // The dropped messages are reported once the next window starts.
+        assert_eq!(limiter.check(now + LOG_WINDOW), (true, 2));
+        assert_eq!(limiter.check(now + LOG_WINDOW), (true, 0));
+    }
+
+    #[test]
+    fn truncate_respects_char_boundaries() {
+        let mut string = String::from("aé");
+        truncate(&mut string, 2);
+        assert_eq!(string, "a");
+
+        let mut string = String::from("short");
+        truncate(&mut string, 10);
+        assert_eq!(string, "short");
+    }
+}
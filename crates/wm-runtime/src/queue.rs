@@ -0,0 +1,132 @@
+//! Queue of events waiting to be dispatched to the guest.
+//!
+//! The guest is single threaded and not reentrant: a call into the guest must return before the next call into
+//! the guest is made. A host function called by the guest may need to notify the guest of something, such as a
+//! timer set without a duration expiring. Calling into the guest from a host function would nest guest calls,
+//! so instead the event is deferred and dispatched after the current guest call returns.
+//!
+//! The [`EventQueue`] enforces this. Events are taken from the queue with [`EventQueue::begin`], which marks
+//! a guest call as in progress until [`EventQueue::end`] is called. Beginning a guest call while another is
+//! in progress is a bug in the runtime and panics.
+
+use std::collections::VecDeque;
+
+#[derive(Debug)]
+pub struct EventQueue<E> {
+    pending: VecDeque<E>,
+
+    /// Whether a guest call is in progress.
+    dispatching: bool,
+}
+
+impl<E> EventQueue<E> {
+    pub fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            dispatching: false,
+        }
+    }
+
+    /// Queue an event to be dispatched once no guest call is in progress.
+    pub fn defer(&mut self, event: E) {
+        self.pending.push_back(event);
+    }
+
+    /// The number of events waiting to be dispatched.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Take the next event to dispatch and mark a guest call as in progress.
+    ///
+    /// Returns [`None`] if no events are pending.
+    ///
+    /// # Panics
+    ///
+    /// If a guest call is already in progress.
+    #[must_use]
+    pub fn begin(&mut self) -> Option<E> {
+        assert!(!self.dispatching, "reentrant call into the wm guest");
+
+        let event = self.pending.pop_front()?;
+        self.dispatching = true;
+        Some(event)
+    }
+
+    /// Mark the guest call as finished.
+    pub fn end(&mut self) {
+        debug_assert!(self.dispatching, "no guest call in progress");
+        self.dispatching = false;
+    }
+
+    /// Discard all pending events.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.dispatching = false;
+    }
+}
+
+impl<E> Default for EventQueue<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EventQueue;
+
+    /// Simulate a guest which generates an event from within a callback.
+    #[test]
+    fn event_generated_in_callback_is_deferred() {
+        let mut queue = EventQueue::new();
+        let mut dispatched = Vec::new();
+
+        queue.defer(1);
+
+        while let Some(event) = queue.begin() {
+            // The guest calls a host function which generates an event.
+            if event == 1 {
+                assert!(queue.dispatching);
+                queue.defer(2);
+            }
+
+            dispatched.push(event);
+            queue.end();
+        }
+
+        assert_eq!(dispatched, [1, 2]);
+        assert!(!queue.dispatching);
+    }
+
+    #[test]
+    fn deferred_events_keep_order() {
+        let mut queue = EventQueue::new();
+        let mut dispatched = Vec::new();
+
+        queue.defer(1);
+        queue.defer(2);
+
+        while let Some(event) = queue.begin() {
+            if event == 1 {
+                queue.defer(3);
+            }
+
+            dispatched.push(event);
+            queue.end();
+        }
+
+        assert_eq!(dispatched, [1, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "reentrant call into the wm guest")]
+    fn nested_call_panics() {
+        let mut queue = EventQueue::new();
+        queue.defer(1);
+        queue.defer(2);
+
+        let _first = queue.begin();
+        let _nested = queue.begin();
+    }
+}
@@ -1,21 +1,32 @@
-use std::{fmt, io, thread};
+use std::{
+    fmt, io,
+    panic::{self, AssertUnwindSafe},
+    thread,
+    time::Instant,
+};
 
-use calloop::channel::Channel;
 use wasmtime::{
     component::{Resource, ResourceAny},
     Store,
 };
 
 use crate::{
+    channel::{EventReceiver, Received},
+    executor,
     host::{
-        aerugo::wm::types::{DecorationMode, Features, ToplevelUpdates},
+        aerugo::wm::types::{Features, Image, ToplevelUpdates},
         exports::aerugo::wm::wm_types::WmTypes,
     },
-    ConfigureUpdate, Id, ToplevelUpdate, WmEvent, WmState, WmToplevel,
+    stats::StatsRecorder,
+    AckedConfigure, ConfigureUpdate, CrashReport, Id, OutputInfo, RetryPolicy, ScreenshotImage, SnapshotInfo,
+    StateRequest, ToplevelUpdate, WmEvent, WmModule, WmRequest, WmState, WmToplevel,
 };
 
 pub struct WmRunner {
-    channel: Channel<WmEvent>,
+    channel: EventReceiver,
+    module: WmModule,
+    restarts: RestartBudget,
+    stats: StatsRecorder,
     store: Store<WmState>,
     wm: ResourceAny,
     funcs: WmTypes,
@@ -32,9 +43,19 @@ impl fmt::Debug for WmRunner {
 }
 
 impl WmRunner {
-    pub(super) fn new(channel: Channel<WmEvent>, store: Store<WmState>, wm: ResourceAny, funcs: WmTypes) -> Self {
+    pub(super) fn new(
+        channel: EventReceiver,
+        module: WmModule,
+        policy: RetryPolicy,
+        store: Store<WmState>,
+        wm: ResourceAny,
+        funcs: WmTypes,
+    ) -> Self {
         Self {
             channel,
+            module,
+            restarts: RestartBudget::new(policy),
+            stats: StatsRecorder::new(Instant::now()),
             store,
             wm,
             funcs,
@@ -46,62 +67,394 @@ impl WmRunner {
             loop {
                 // Since this is run on a separate thread, we want to manually poll and suspend the thread if no
                 // wm events are pending.
-                match self.channel.recv() {
-                    Ok(event) => {
-                        // Dispatch the event on the runtime.
-                        // Add some fuel for while dispatching.
-                        let result = match event {
-                            WmEvent::NewToplevel { toplevel, features } => self.new_toplevel(toplevel, features),
-                            WmEvent::ClosedToplevel(id) => self.closed_toplevel(id),
-                            WmEvent::UpdateToplevel { toplevel, update } => self.update_toplevel(toplevel, update),
-                            WmEvent::AckToplevel { toplevel, serial } => todo!(),
-                            WmEvent::NewOutput { output } => todo!(),
-                            WmEvent::UpdateOutput { output } => todo!(),
-                            WmEvent::DisconnectOutput(_) => todo!(),
-                        };
-
-                        result.expect("handle error");
-                    }
+                let running = match self.channel.recv() {
+                    Some(Received::Event(event)) => self.step(event),
+
+                    // The wm missed events, so the state of the wm no longer matches the display server.
+                    Some(Received::Overflow { dropped }) => self.recover(CrashReport {
+                        event: "overflow".into(),
+                        error: format!("wm did not keep up with events, {dropped} events were dropped"),
+                    }),
 
                     // The other end was closed.
-                    Err(_) => return,
+                    None => return,
+                };
+
+                if !running {
+                    return;
                 }
+
+                self.report_stats();
             }
         })?;
 
         Ok(())
     }
 
+    /// Dispatch an event and the events deferred while dispatching, recovering the wm if the wm fails.
+    ///
+    /// Returns false if the wm could not be recovered and the runner must stop.
+    pub(super) fn step(&mut self, event: WmEvent) -> bool {
+        self.store.data_mut().events.defer(event);
+
+        match self.dispatch_pending() {
+            Ok(()) => true,
+            Err(report) => self.recover(report),
+        }
+    }
+
+    /// Instantiate the wm again after the wm failed, if the retry policy allows another restart.
+    ///
+    /// Returns false if the wm could not be recovered. The crash was reported and the runner must stop.
+    fn recover(&mut self, report: CrashReport) -> bool {
+        let sender = self.store.data().sender.clone();
+
+        if !self.restarts.try_restart(Instant::now()) {
+            let _ = sender.send(WmRequest::Crashed(report));
+            return false;
+        }
+
+        let (store, wm, funcs) = match self.module.instantiate(sender.clone()) {
+            Ok(instance) => instance,
+            Err(err) => {
+                let _ = sender.send(WmRequest::Crashed(CrashReport {
+                    event: report.event,
+                    error: format!("{}, instantiating the wm again failed: {err}", report.error),
+                }));
+                return false;
+            }
+        };
+
+        let old = std::mem::replace(&mut self.store, store).into_data();
+        self.wm = wm;
+        self.funcs = funcs;
+        let _ = sender.send(WmRequest::Restarted(report));
+
+        // The toplevels are carried over, but the new instance must be told about the toplevels and outputs.
+        let state = self.store.data_mut();
+
+        for (rep, mut toplevel) in old.toplevels {
+            // An empty update announces the toplevel with the state that was carried over.
+            toplevel.initial_commit = true;
+            state.events.defer(WmEvent::UpdateToplevel {
+                toplevel: toplevel.id,
+                update: ToplevelUpdate::default(),
+            });
+            state.toplevels.insert(rep, toplevel);
+        }
+
+        for (rep, info) in old.outputs {
+            state.events.defer(WmEvent::NewOutput {
+                output: Id::output(rep),
+                info,
+            });
+        }
+
+        // The new instance may fail as well, which uses up another restart.
+        match self.dispatch_pending() {
+            Ok(()) => true,
+            Err(report) => self.recover(report),
+        }
+    }
+
+    /// Send the statistics of the wm to the display server if the interval passed.
+    fn report_stats(&mut self) {
+        let state = self.store.data();
+
+        if let Some(stats) = self.stats.take(Instant::now(), state.memory.size()) {
+            let _ = state.sender.send(WmRequest::Stats(stats));
+        }
+    }
+
+    /// Dispatch pending events to the guest, including events deferred while dispatching.
+    ///
+    /// Each event is dispatched in a separate guest call, so guest calls never nest.
+    fn dispatch_pending(&mut self) -> Result<(), CrashReport> {
+        loop {
+            let events = &mut self.store.data_mut().events;
+            self.stats.queue_depth(events.len() + self.channel.len());
+
+            let Some(event) = events.begin() else {
+                break;
+            };
+
+            // A panic while dispatching is treated the same as the wm trapping, since the wm state
+            // can no longer be trusted. The same goes for a wm which takes too long to handle the event.
+            let limit = self.module.limits.max_callback_time;
+            let start = Instant::now();
+            let fuel = self.store.fuel_consumed().unwrap_or_default();
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                executor::block_on_timeout(self.dispatch(&event), limit)
+            }));
+            self.store.data_mut().events.end();
+
+            let fuel = self.store.fuel_consumed().unwrap_or_default().saturating_sub(fuel);
+            self.stats.callback(event.name(), start.elapsed(), fuel);
+
+            let error = match result {
+                Ok(Some(Ok(()))) => continue,
+                Ok(Some(Err(err))) => format!("{err:?}"),
+                Ok(None) => format!("wm did not handle the event within {limit:?}"),
+                Err(panic) => panic
+                    .downcast_ref::<&str>()
+                    .map(ToString::to_string)
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".into()),
+            };
+
+            self.store.data_mut().events.clear();
+            return Err(CrashReport {
+                event: format!("{event:?}"),
+                error,
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn dispatch(&mut self, event: &WmEvent) -> wasmtime::Result<()> {
+        // Dispatch the event on the runtime.
+        match event {
+            WmEvent::NewToplevel { toplevel, features } => self.new_toplevel(*toplevel, *features),
+            WmEvent::ClosedToplevel(id) => self.closed_toplevel(*id).await,
+            WmEvent::UpdateToplevel { toplevel, update } => self.update_toplevel(*toplevel, update).await,
+            WmEvent::AckToplevel { toplevel, configure } => self.ack_toplevel(*toplevel, configure, false).await,
+            WmEvent::ConfigureTimedOut { toplevel, configure } => self.ack_toplevel(*toplevel, configure, true).await,
+            WmEvent::ConfigureCancelled { toplevel, serial } => {
+                self.funcs
+                    .wm()
+                    .call_configure_cancelled(&mut self.store, self.wm, toplevel.rep().get(), *serial)
+                    .await
+            }
+            WmEvent::CommittedToplevel { toplevel, snapshot } => self.committed_toplevel(*toplevel, *snapshot).await,
+            WmEvent::ActivationRequested { toplevel, token_valid } => {
+                // The toplevel may have been closed while the request was in flight.
+                if !self.store.data().toplevels.contains_key(&toplevel.rep()) {
+                    return Ok(());
+                }
+
+                self.funcs
+                    .wm()
+                    .call_activation_requested(&mut self.store, self.wm, toplevel.rep().get(), *token_valid)
+                    .await
+            }
+            WmEvent::Launched { launch, toplevel } => {
+                // The toplevel may have been closed while the request was in flight.
+                if !self.store.data().toplevels.contains_key(&toplevel.rep()) {
+                    return Ok(());
+                }
+
+                self.funcs
+                    .wm()
+                    .call_launched(&mut self.store, self.wm, *launch, toplevel.rep().get())
+                    .await
+            }
+            WmEvent::WorkspaceActivationRequested(workspace) => {
+                // The workspace may have been dropped by the wm while the request was in flight.
+                if !self.store.data().workspaces.contains(workspace.rep().get()) {
+                    return Ok(());
+                }
+
+                self.funcs
+                    .wm()
+                    .call_workspace_activation_requested(&mut self.store, self.wm, workspace.rep().get())
+                    .await
+            }
+            WmEvent::NewOutput { output, info } => self.new_output(*output, info).await,
+            WmEvent::UpdateOutput { output, info } => {
+                self.store.data_mut().outputs.insert(output.rep(), info.clone());
+                Ok(())
+            }
+            WmEvent::DisconnectOutput { output, toplevels } => self.disconnect_output(*output, toplevels).await,
+            WmEvent::NewSurfaceNode { node, name } => {
+                self.store.data_mut().surface_nodes.insert(node.rep());
+                self.funcs
+                    .wm()
+                    .call_new_surface_node(&mut self.store, self.wm, node.rep().get(), name)
+                    .await
+            }
+            WmEvent::ClosedSurfaceNode(node) => {
+                if !self.store.data().surface_nodes.contains(&node.rep()) {
+                    return Ok(());
+                }
+
+                self.funcs
+                    .wm()
+                    .call_closed_surface_node(&mut self.store, self.wm, node.rep().get())
+                    .await?;
+
+                self.store.data_mut().surface_nodes.remove(&node.rep());
+                Ok(())
+            }
+            WmEvent::Binding { id, time, repeat } => {
+                self.funcs
+                    .wm()
+                    .call_binding(&mut self.store, self.wm, *id, *time, *repeat)
+                    .await
+            }
+            WmEvent::Touch { time, event } => self.funcs.wm().call_touch(&mut self.store, self.wm, *time, event).await,
+            WmEvent::Pointer { time, event } => {
+                self.funcs
+                    .wm()
+                    .call_pointer(&mut self.store, self.wm, *time, event)
+                    .await
+            }
+            WmEvent::Gesture { time, event } => {
+                self.funcs
+                    .wm()
+                    .call_gesture(&mut self.store, self.wm, *time, event)
+                    .await
+            }
+            WmEvent::SessionLocked => self.funcs.wm().call_session_locked(&mut self.store, self.wm).await,
+            WmEvent::SessionUnlocked => self.funcs.wm().call_session_unlocked(&mut self.store, self.wm).await,
+            WmEvent::ToplevelScreenshot {
+                toplevel,
+                serial,
+                image,
+            } => self.toplevel_screenshot(*toplevel, *serial, image.as_ref()).await,
+            WmEvent::Frame { output, time } => {
+                // The output may have been disconnected since the frame was requested.
+                if !self.store.data().outputs.contains_key(&output.rep()) {
+                    return Ok(());
+                }
+
+                self.funcs
+                    .wm()
+                    .call_frame(&mut self.store, self.wm, output.rep().get(), *time)
+                    .await
+            }
+            WmEvent::Timer(id) => {
+                // The wm may have cancelled the timer while the timer expired.
+                if !self.store.data_mut().timers.remove(id) {
+                    return Ok(());
+                }
+
+                self.funcs.wm().call_timer(&mut self.store, self.wm, *id).await
+            }
+            WmEvent::Shutdown => self.funcs.wm().call_shutdown(&mut self.store, self.wm).await,
+        }
+    }
+
     // TODO: Somehow communicate all the initial state
     fn new_toplevel(&mut self, id: Id, features: Features) -> wasmtime::Result<()> {
-        self.store.data_mut().toplevels.insert(
-            id.rep(),
-            WmToplevel {
-                id,
-                initial_commit: false,
-                features,
-                app_id: Default::default(),
-                title: Default::default(),
-                min_size: Default::default(),
-                max_size: Default::default(),
-                geometry: Default::default(),
-                parent: Default::default(),
-                state: Default::default(),
-                decorations: DecorationMode::ClientSide,
-                resize_edge: Default::default(),
-            },
-        );
-
+        let wm = self.store.data_mut();
+        wm.toplevels.insert(id.rep(), WmToplevel::new(id, features));
         Ok(())
     }
 
-    fn closed_toplevel(&mut self, id: Id) -> wasmtime::Result<()> {
+    async fn new_output(&mut self, id: Id, info: &OutputInfo) -> wasmtime::Result<()> {
+        let wm = self.store.data_mut();
+
+        wm.outputs.insert(id.rep(), info.clone());
+
+        let output = Resource::new_own(wm.handles.insert(id)?);
+        self.funcs.wm().call_new_output(&mut self.store, self.wm, output).await
+    }
+
+    async fn disconnect_output(&mut self, id: Id, toplevels: &[Id]) -> wasmtime::Result<()> {
+        let wm = self.store.data_mut();
+
+        if wm.outputs.remove(&id.rep()).is_none() {
+            return Ok(());
+        }
+
+        wm.handles.remove_id(id);
+
+        // Toplevels the wm was not told about yet are announced to the wm later on.
+        let toplevels = toplevels
+            .iter()
+            .filter(|toplevel| wm.toplevels.contains_key(&toplevel.rep()))
+            .map(|toplevel| toplevel.rep().get())
+            .collect::<Vec<_>>();
+
+        self.funcs
+            .wm()
+            .call_disconnect_output(&mut self.store, self.wm, id.rep().get(), &toplevels)
+            .await
+    }
+
+    async fn toplevel_screenshot(
+        &mut self,
+        id: Id,
+        serial: u32,
+        image: Option<&ScreenshotImage>,
+    ) -> wasmtime::Result<()> {
+        let image = image.map(|image| Image {
+            width: image.width,
+            height: image.height,
+            data: image.data.clone(),
+        });
+
+        self.funcs
+            .wm()
+            .call_toplevel_screenshot(&mut self.store, self.wm, id.rep().get(), serial, image.as_ref())
+            .await
+    }
+
+    async fn ack_toplevel(&mut self, id: Id, configure: &AckedConfigure, timed_out: bool) -> wasmtime::Result<()> {
+        let Some(toplevel) = self.store.data_mut().toplevels.get_mut(&id.rep()) else {
+            return Ok(());
+        };
+
+        // The wm queries the acked state as the current state of the toplevel.
+        if let Some(state) = configure.request.state {
+            toplevel.state = state;
+        }
+
+        if let Some(decorations) = configure.request.decorations {
+            toplevel.decorations = decorations;
+        }
+
+        if timed_out {
+            return self
+                .funcs
+                .wm()
+                .call_configure_timed_out(&mut self.store, self.wm, id.rep().get(), configure.serial)
+                .await;
+        }
+
+        self.funcs
+            .wm()
+            .call_ack_toplevel(&mut self.store, self.wm, id.rep().get(), configure.serial)
+            .await
+    }
+
+    async fn committed_toplevel(&mut self, id: Id, snapshot: Option<SnapshotInfo>) -> wasmtime::Result<()> {
+        if !self.store.data().toplevels.contains_key(&id.rep()) {
+            return Ok(());
+        }
+
+        // The wm owns the snapshot and drops it once it is no longer presented.
+        let snapshot = match snapshot {
+            Some(snapshot) => Some(Resource::new_own(self.store.data_mut().snapshots.insert(snapshot)?)),
+            None => None,
+        };
+
+        self.funcs
+            .wm()
+            .call_committed_toplevel(&mut self.store, self.wm, id.rep().get(), snapshot)
+            .await
+    }
+
+    async fn closed_toplevel(&mut self, id: Id) -> wasmtime::Result<()> {
+        if !self.store.data().toplevels.contains_key(&id.rep()) {
+            return Ok(());
+        }
+
         self.funcs
             .wm()
             .call_closed_toplevel(&mut self.store, self.wm, id.rep().get())
+            .await?;
+
+        // The toplevel is forgotten after the wm was told so the wm may still inspect the toplevel while closing it.
+        let wm = self.store.data_mut();
+        wm.toplevels.remove(&id.rep());
+        wm.handles.remove_id(id);
+
+        Ok(())
     }
 
-    fn update_toplevel(&mut self, id: Id, update: ToplevelUpdate) -> wasmtime::Result<()> {
+    async fn update_toplevel(&mut self, id: Id, update: &ToplevelUpdate) -> wasmtime::Result<()> {
         let mut updates = ToplevelUpdates::default();
         let wm = self.store.data_mut();
 
@@ -117,22 +470,22 @@ impl WmRunner {
             updates |= ToplevelUpdates::TITLE;
         }
 
-        if let ConfigureUpdate::Update(min_size) = update.min_size {
+        if let ConfigureUpdate::Update(min_size) = update.min_size.clone() {
             updates |= ToplevelUpdates::MIN_SIZE;
-            toplevel.min_size = min_size;
+            toplevel.min_size = min_size.map(Into::into);
         }
 
-        if let ConfigureUpdate::Update(max_size) = update.max_size {
+        if let ConfigureUpdate::Update(max_size) = update.max_size.clone() {
             updates |= ToplevelUpdates::MAX_SIZE;
-            toplevel.max_size = max_size;
+            toplevel.max_size = max_size.map(Into::into);
         }
 
-        if let ConfigureUpdate::Update(geometry) = update.geometry {
+        if let ConfigureUpdate::Update(geometry) = update.geometry.clone() {
             updates |= ToplevelUpdates::GEOMETRY;
-            toplevel.geometry = geometry;
+            toplevel.geometry = geometry.map(Into::into);
         }
 
-        if let ConfigureUpdate::Update(parent) = update.parent {
+        if let ConfigureUpdate::Update(parent) = &update.parent {
             todo!()
         }
 
@@ -140,21 +493,114 @@ impl WmRunner {
             // TODO
         }
 
-        if let Some(decorations) = update.decorations {}
+        if let ConfigureUpdate::Update(preferred) = update.preferred_decorations {
+            updates |= ToplevelUpdates::PREFERRED_DECORATIONS;
+            toplevel.preferred_decorations = preferred;
+
+            // A toplevel which negotiates decorations can be decorated by the server.
+            toplevel.features |= Features::SERVER_SIDE_DECORATIONS;
+        }
 
-        if let ConfigureUpdate::Update(edge) = update.resize_edge {
+        if let Some(style) = update.decoration_style.clone() {
+            updates |= ToplevelUpdates::DECORATION_STYLE;
+            toplevel.decoration_style = style;
+        }
+
+        if let ConfigureUpdate::Update(edge) = &update.resize_edge {
             updates |= ToplevelUpdates::REQUEST_RESIZE;
         }
 
+        if let Some(request) = update.request {
+            updates |= match request {
+                StateRequest::SetMaximized => ToplevelUpdates::REQUEST_SET_MAXIMIZED,
+                StateRequest::UnsetMaximized => ToplevelUpdates::REQUEST_UNSET_MAXIMIZED,
+                StateRequest::SetFullscreen { output } => {
+                    toplevel.fullscreen_output = output;
+                    ToplevelUpdates::REQUEST_SET_FULLSCREEN
+                }
+                StateRequest::UnsetFullscreen => ToplevelUpdates::REQUEST_UNSET_FULLSCREEN,
+                StateRequest::SetMinimized => ToplevelUpdates::REQUEST_SET_MINIMIZED,
+            };
+        }
+
         if toplevel.initial_commit {
             toplevel.initial_commit = false;
-            let toplevel = Resource::new_own(toplevel.id.rep().get());
+            let toplevel = Resource::new_own(wm.handles.insert(id)?);
 
-            self.funcs.wm().call_new_toplevel(&mut self.store, self.wm, toplevel)
+            self.funcs
+                .wm()
+                .call_new_toplevel(&mut self.store, self.wm, toplevel)
+                .await
         } else {
             self.funcs
                 .wm()
                 .call_update_toplevel(&mut self.store, self.wm, id.rep().get(), updates)
+                .await
         }
     }
 }
+
+/// Restarts of the wm within the window of the retry policy.
+#[derive(Debug)]
+struct RestartBudget {
+    policy: RetryPolicy,
+
+    /// When the wm was restarted, oldest first.
+    restarts: Vec<Instant>,
+}
+
+impl RestartBudget {
+    fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            restarts: Vec::new(),
+        }
+    }
+
+    /// Record a restart, returning false if the retry policy does not allow another restart.
+    fn try_restart(&mut self, now: Instant) -> bool {
+        let window = self.policy.window;
+        self.restarts.retain(|&time| now.duration_since(time) < window);
+
+        if self.restarts.len() >= self.policy.max_restarts as usize {
+            return false;
+        }
+
+        self.restarts.push(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::RestartBudget;
+    use crate::RetryPolicy;
+
+    #[test]
+    fn restarts_are_limited_within_window() {
+        let mut budget = RestartBudget::new(RetryPolicy {
+            max_restarts: 2,
+            window: Duration::from_secs(10),
+        });
+        let now = Instant::now();
+
+        assert!(budget.try_restart(now));
+        assert!(budget.try_restart(now + Duration::from_secs(1)));
+        assert!(!budget.try_restart(now + Duration::from_secs(2)));
+
+        // The first restart left the window.
+        assert!(budget.try_restart(now + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn zero_restarts_disables_recovery() {
+        let mut budget = RestartBudget::new(RetryPolicy {
+            max_restarts: 0,
+            window: Duration::from_secs(10),
+        });
+
+        assert!(!budget.try_restart(Instant::now()));
+    }
+}
@@ -0,0 +1,189 @@
+//! Runtime statistics of the wm.
+//!
+//! The runner measures how long every callback into the wm takes, how much fuel the wm consumes, how many events
+//! are waiting to be dispatched and how large the memory of the wm is. The statistics are reported to the display
+//! server as [`RuntimeMessage::Stats`](crate::RuntimeMessage::Stats) at most every [`STATS_INTERVAL`] while the wm
+//! handles events, so the display server can tell when a wm misbehaves, for example when the wm is close to the
+//! callback time limit or leaks memory.
+//!
+//! Fuel is only metered. The wm never runs out of fuel, the execution time of the wm is limited using epoch
+//! interruption instead.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use wasmtime::ResourceLimiter;
+
+/// How often statistics are reported.
+pub const STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Statistics of the callbacks handling one kind of event.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallbackStats {
+    /// The number of callbacks.
+    pub count: u32,
+
+    /// The time spent in the callbacks.
+    pub total: Duration,
+
+    /// The slowest callback.
+    pub max: Duration,
+}
+
+impl CallbackStats {
+    pub fn average(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => self.total / count,
+        }
+    }
+}
+
+/// Statistics of the wm since the previous report.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WmStats {
+    /// The time since the previous report.
+    pub interval: Duration,
+
+    /// The callbacks into the wm, by the name of the event the wm handled.
+    pub callbacks: BTreeMap<&'static str, CallbackStats>,
+
+    /// The fuel the wm consumed.
+    pub fuel_consumed: u64,
+
+    /// The most events which waited to be dispatched at once.
+    pub max_queue_depth: usize,
+
+    /// The size of the memories of the wm in bytes.
+    pub memory_size: usize,
+}
+
+impl WmStats {
+    /// The event the wm took the longest to handle, with how long the callback took.
+    pub fn slowest_callback(&self) -> Option<(&'static str, Duration)> {
+        self.callbacks
+            .iter()
+            .map(|(&event, stats)| (event, stats.max))
+            .max_by_key(|&(_, max)| max)
+    }
+}
+
+/// Collects statistics until the next report.
+#[derive(Debug)]
+pub(crate) struct StatsRecorder {
+    start: Instant,
+    stats: WmStats,
+}
+
+impl StatsRecorder {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            start: now,
+            stats: WmStats::default(),
+        }
+    }
+
+    /// Record a callback into the wm.
+    pub fn callback(&mut self, event: &'static str, duration: Duration, fuel: u64) {
+        let callback = self.stats.callbacks.entry(event).or_default();
+        callback.count += 1;
+        callback.total += duration;
+        callback.max = callback.max.max(duration);
+        self.stats.fuel_consumed += fuel;
+    }
+
+    /// Record how many events are waiting to be dispatched.
+    pub fn queue_depth(&mut self, depth: usize) {
+        self.stats.max_queue_depth = self.stats.max_queue_depth.max(depth);
+    }
+
+    /// Take the statistics once [`STATS_INTERVAL`] passed since the previous report.
+    pub fn take(&mut self, now: Instant, memory_size: usize) -> Option<WmStats> {
+        let interval = now.duration_since(self.start);
+
+        if interval < STATS_INTERVAL {
+            return None;
+        }
+
+        self.start = now;
+        let stats = std::mem::take(&mut self.stats);
+
+        Some(WmStats {
+            interval,
+            memory_size,
+            ..stats
+        })
+    }
+}
+
+/// Tracks the size of the memories of the wm.
+///
+/// Growing memory is never denied, the wm is limited by the default limits of the engine.
+#[derive(Debug, Default)]
+pub(crate) struct MemoryTracker {
+    size: usize,
+}
+
+impl MemoryTracker {
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl ResourceLimiter for MemoryTracker {
+    fn memory_growing(&mut self, current: usize, desired: usize, _maximum: Option<usize>) -> wasmtime::Result<bool> {
+        self.size += desired.saturating_sub(current);
+        Ok(true)
+    }
+
+    fn table_growing(&mut self, _current: u32, _desired: u32, _maximum: Option<u32>) -> wasmtime::Result<bool> {
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use wasmtime::ResourceLimiter;
+
+    use super::{MemoryTracker, StatsRecorder, STATS_INTERVAL};
+
+    #[test]
+    fn report_after_interval() {
+        let start = Instant::now();
+        let mut recorder = StatsRecorder::new(start);
+
+        recorder.callback("Binding", Duration::from_millis(2), 100);
+        recorder.callback("Binding", Duration::from_millis(4), 50);
+        recorder.callback("Timer", Duration::from_millis(1), 10);
+        recorder.queue_depth(3);
+        recorder.queue_depth(1);
+
+        assert!(recorder.take(start + Duration::from_secs(1), 0).is_none());
+
+        let stats = recorder.take(start + STATS_INTERVAL, 65536).unwrap();
+        assert_eq!(stats.interval, STATS_INTERVAL);
+        assert_eq!(stats.fuel_consumed, 160);
+        assert_eq!(stats.max_queue_depth, 3);
+        assert_eq!(stats.memory_size, 65536);
+        assert_eq!(stats.callbacks["Binding"].count, 2);
+        assert_eq!(stats.callbacks["Binding"].average(), Duration::from_millis(3));
+        assert_eq!(stats.slowest_callback(), Some(("Binding", Duration::from_millis(4))));
+
+        // The next report starts over.
+        let stats = recorder.take(start + STATS_INTERVAL * 2, 65536).unwrap();
+        assert!(stats.callbacks.is_empty());
+        assert_eq!(stats.max_queue_depth, 0);
+    }
+
+    #[test]
+    fn track_memory_growth() {
+        let mut tracker = MemoryTracker::default();
+        assert!(tracker.memory_growing(0, 65536, None).unwrap());
+        assert!(tracker.memory_growing(65536, 131072, None).unwrap());
+        assert_eq!(tracker.size(), 131072);
+    }
+}
@@ -0,0 +1,281 @@
+//! Golden trace tests of wms
+//!
+//! A [`Harness`] runs a wm on the calling thread and dispatches each event synchronously, so the requests a wm
+//! makes in response to a scripted sequence of events are deterministic. The harness records the events and the
+//! requests as a [`Trace`], which is compared against a golden trace stored next to the test. This tests the host
+//! glue and the wm without a display server.
+//!
+//! If the `AERUGO_BLESS` environment variable is set, [`Trace::assert_golden`] writes the trace to the golden file
+//! instead of comparing it. Review the changes to golden files like any other change.
+//!
+//! This module is not part of the stable API of the crate.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use calloop::{
+    channel::{self, Event},
+    EventLoop,
+};
+
+use crate::{runner::WmRunner, RuntimeOptions, WmEvent, WmModule, WmRequest, WmRuntimeError};
+
+/// Environment variable which makes [`Trace::assert_golden`] write the golden files.
+pub const BLESS_ENV: &str = "AERUGO_BLESS";
+
+/// Runs a wm on the calling thread and records a [`Trace`].
+pub struct Harness {
+    runner: WmRunner,
+    event_loop: EventLoop<'static, Vec<WmRequest>>,
+    requests: Vec<WmRequest>,
+    trace: Trace,
+    running: bool,
+}
+
+impl fmt::Debug for Harness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Harness")
+            .field("runner", &self.runner)
+            .field("trace", &self.trace)
+            .field("running", &self.running)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Harness {
+    pub fn new(bytes: &[u8]) -> Result<Self, WmRuntimeError> {
+        Self::with_options(bytes, RuntimeOptions::default())
+    }
+
+    /// Create a harness with the specified options.
+    ///
+    /// The requests the wm makes while starting are the first requests of the trace.
+    pub fn with_options(bytes: &[u8], options: RuntimeOptions) -> Result<Self, WmRuntimeError> {
+        // The runner only reads from the event channel on the wm thread, which the harness does not start.
+        let (_, event_channel) = crate::channel::channel(crate::EVENT_CHANNEL_CAPACITY);
+        let (sender, channel) = channel::channel();
+        let module = WmModule::compile(bytes, &options)?;
+        let (store, wm, funcs) = module.instantiate(sender)?;
+
+        let event_loop = EventLoop::try_new().map_err(|err| WmRuntimeError::Spawn(err.into()))?;
+        event_loop
+            .handle()
+            .insert_source(channel, |event, _, requests: &mut Vec<WmRequest>| {
+                if let Event::Msg(request) = event {
+                    requests.push(request);
+                }
+            })
+            .map_err(|err| WmRuntimeError::Spawn(err.error.into()))?;
+
+        let mut harness = Self {
+            runner: WmRunner::new(event_channel, module, options.retry_policy, store, wm, funcs),
+            event_loop,
+            requests: Vec::new(),
+            trace: Trace::default(),
+            running: true,
+        };
+        harness.collect();
+
+        Ok(harness)
+    }
+
+    /// Dispatch an event to the wm, returning the requests the wm made while handling the event.
+    ///
+    /// If the wm fails, the wm is restarted as the runtime would. Once the wm cannot be restarted, events are
+    /// recorded but no longer dispatched.
+    pub fn send(&mut self, event: WmEvent) -> Vec<WmRequest> {
+        self.trace.lines.push(Line::Event(format!("{event:?}")));
+
+        if self.running {
+            self.running = self.runner.step(event);
+        }
+
+        self.collect()
+    }
+
+    /// Dispatch each event of a script in order.
+    pub fn run(&mut self, script: impl IntoIterator<Item = WmEvent>) {
+        for event in script {
+            self.send(event);
+        }
+    }
+
+    /// Returns whether the wm is still running.
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    /// Returns the trace recorded so far.
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    /// Record the requests sent by the wm.
+    fn collect(&mut self) -> Vec<WmRequest> {
+        // The requests were sent before the event loop is dispatched, so the channel is ready immediately.
+        self.event_loop
+            .dispatch(Duration::ZERO, &mut self.requests)
+            .expect("Failed to receive the requests of the wm");
+
+        let requests = std::mem::take(&mut self.requests);
+        self.trace.lines.extend(
+            requests
+                .iter()
+                // The statistics depend on timing, which would make the trace differ between runs.
+                .filter(|request| !matches!(request, WmRequest::Stats(_)))
+                .map(|request| Line::Request(format!("{request:?}"))),
+        );
+
+        requests
+    }
+}
+
+/// The events dispatched to a wm and the requests the wm made in response, in order.
+///
+/// A trace is displayed with one line per event or request. Lines of events start with `>` and lines of requests
+/// start with `<`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    Event(String),
+    Request(String),
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Line::Event(event) => write!(f, "> {event}"),
+            Line::Request(request) => write!(f, "< {request}"),
+        }
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{line}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Trace {
+    /// Compare the trace with a golden trace, returning a description of the first difference.
+    pub fn compare(&self, golden: &str) -> Result<(), String> {
+        let actual = self.to_string();
+        let mut expected_lines = golden.lines();
+        let mut actual_lines = actual.lines();
+        let mut number = 1;
+
+        loop {
+            match (expected_lines.next(), actual_lines.next()) {
+                (None, None) => return Ok(()),
+                (Some(expected), Some(actual)) if expected == actual => (),
+                (expected, actual) => {
+                    return Err(format!(
+                        "trace differs from the golden trace at line {number}\n  expected: {}\n    actual: {}",
+                        expected.unwrap_or("<end of trace>"),
+                        actual.unwrap_or("<end of trace>"),
+                    ))
+                }
+            }
+
+            number += 1;
+        }
+    }
+
+    /// Assert the trace matches the golden trace in the specified file.
+    ///
+    /// If [`BLESS_ENV`] is set, the trace is written to the file instead.
+    ///
+    /// # Panics
+    ///
+    /// If the trace does not match the golden trace or the golden trace could not be read.
+    pub fn assert_golden(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+
+        if std::env::var_os(BLESS_ENV).is_some() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).expect("Failed to create the directory of the golden trace");
+            }
+
+            fs::write(path, self.to_string()).expect("Failed to write the golden trace");
+            return;
+        }
+
+        let golden = fs::read_to_string(path).unwrap_or_else(|err| {
+            panic!(
+                "Failed to read the golden trace {}: {err}, set {BLESS_ENV} to record the trace",
+                path.display()
+            )
+        });
+
+        if let Err(difference) = self.compare(&golden) {
+            panic!(
+                "{difference}\nthe golden trace is {}, set {BLESS_ENV} to update the trace",
+                path.display()
+            );
+        }
+    }
+}
+
+/// The path of a golden trace in the `tests/golden` directory of the crate being tested.
+pub fn golden_path(manifest_dir: &str, name: &str) -> PathBuf {
+    Path::new(manifest_dir)
+        .join("tests/golden")
+        .join(format!("{name}.trace"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace() -> Trace {
+        Trace {
+            lines: vec![
+                Line::Event("Binding { id: 1, time: 0, repeat: false }".into()),
+                Line::Request("ShowOverview".into()),
+            ],
+        }
+    }
+
+    #[test]
+    fn display_marks_direction() {
+        assert_eq!(
+            trace().to_string(),
+            "> Binding { id: 1, time: 0, repeat: false }\n< ShowOverview\n"
+        );
+    }
+
+    #[test]
+    fn compare_matching_trace() {
+        let trace = trace();
+        assert_eq!(trace.compare(&trace.to_string()), Ok(()));
+    }
+
+    #[test]
+    fn compare_reports_first_difference() {
+        let golden = "> Binding { id: 1, time: 0, repeat: false }\n< HideOverview\n";
+        let difference = trace().compare(golden).unwrap_err();
+
+        assert!(difference.contains("line 2"));
+        assert!(difference.contains("expected: < HideOverview"));
+        assert!(difference.contains("actual: < ShowOverview"));
+    }
+
+    #[test]
+    fn compare_reports_missing_lines() {
+        let golden = "> Binding { id: 1, time: 0, repeat: false }\n";
+        let difference = trace().compare(golden).unwrap_err();
+
+        assert!(difference.contains("expected: <end of trace>"));
+    }
+}
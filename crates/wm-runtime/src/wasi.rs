@@ -0,0 +1,78 @@
+//! WASI support for wm guests.
+//!
+//! WASI is opt-in. If enabled, the wm may read files in its configuration directory, which is preopened read only
+//! at [`CONFIG_DIR`], and may use the clocks and random number generator of the host to drive animations. Nothing
+//! else of the host is visible to the wm: there are no environment variables or arguments, and the standard
+//! streams are empty.
+
+use std::{fmt, path::PathBuf};
+
+use wasmtime_wasi::preview2::{DirPerms, FilePerms, Table, WasiCtx, WasiCtxBuilder, WasiView};
+
+use crate::WmState;
+
+/// Path of the configuration directory inside the guest.
+pub const CONFIG_DIR: &str = "/config";
+
+/// WASI features available to the wm.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WasiConfig {
+    /// Directory the wm may read its configuration from.
+    pub config_dir: Option<PathBuf>,
+}
+
+/// The WASI state of a wm instance.
+pub(crate) struct WasiState {
+    table: Table,
+    ctx: WasiCtx,
+}
+
+impl fmt::Debug for WasiState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WasiState").finish_non_exhaustive()
+    }
+}
+
+impl WasiState {
+    /// Create the WASI state of a wm instance.
+    ///
+    /// If WASI is not enabled the state is empty, since the WASI interfaces are not linked.
+    pub fn new(config: Option<&WasiConfig>) -> wasmtime::Result<Self> {
+        let mut table = Table::new();
+        let mut builder = WasiCtxBuilder::new();
+
+        if let Some(path) = config.and_then(|config| config.config_dir.as_ref()) {
+            // A missing configuration directory is not fatal, the wm should use its defaults.
+            match cap_std::fs::Dir::open_ambient_dir(path, cap_std::ambient_authority()) {
+                Ok(dir) => {
+                    builder.preopened_dir(dir, DirPerms::READ, FilePerms::READ, CONFIG_DIR);
+                }
+
+                Err(err) => {
+                    tracing::warn!(%err, path = %path.display(), "Failed to open wm configuration directory");
+                }
+            }
+        }
+
+        let ctx = builder.build(&mut table)?;
+        Ok(Self { table, ctx })
+    }
+}
+
+impl WasiView for WmState {
+    fn table(&self) -> &Table {
+        &self.wasi.table
+    }
+
+    fn table_mut(&mut self) -> &mut Table {
+        &mut self.wasi.table
+    }
+
+    fn ctx(&self) -> &WasiCtx {
+        &self.wasi.ctx
+    }
+
+    fn ctx_mut(&mut self) -> &mut WasiCtx {
+        &mut self.wasi.ctx
+    }
+}
@@ -0,0 +1,328 @@
+//! Drive the tiling wm example through the runtime with synthetic events.
+//!
+//! The tests build the example for `wasm32-unknown-unknown` and encode the module as a component, so the target
+//! must be installed:
+//!
+//! ```sh
+//! rustup target add wasm32-unknown-unknown
+//! ```
+//!
+//! A prebuilt component is used instead if `AERUGO_TILING_WM` is set to the path of the component.
+//!
+//! The golden trace is recorded by running the tests with `AERUGO_BLESS=1` set.
+
+use std::{num::NonZeroU32, path::Path, process::Command, sync::OnceLock};
+
+use aerugo_wm_runtime::{
+    geometry::{LogicalRect, LogicalSize},
+    testing::{golden_path, Harness},
+    ConfigureUpdate, Features, Id, OutputInfo, SceneOperation, SnapshotInfo, ToplevelState, ToplevelUpdate, ViewSource,
+    WmEvent, WmRequest,
+};
+
+/// Bindings registered by the tiling wm.
+const FOCUS_NEXT: u32 = 1;
+const ZOOM: u32 = 3;
+const SWITCH_WORKSPACE: u32 = 10;
+
+/// The tiling wm component, built once for all tests.
+fn component() -> &'static [u8] {
+    static COMPONENT: OnceLock<Vec<u8>> = OnceLock::new();
+
+    COMPONENT.get_or_init(|| {
+        if let Some(path) = std::env::var_os("AERUGO_TILING_WM") {
+            return std::fs::read(path).expect("Failed to read the tiling wm");
+        }
+
+        // The build running the tests holds the lock of the target directory, so the wm is built in another one.
+        let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("tiling_wm");
+        let status = Command::new(env!("CARGO"))
+            .args([
+                "build",
+                "-p",
+                "tiling_wm",
+                "--release",
+                "--target",
+                "wasm32-unknown-unknown",
+            ])
+            .arg("--target-dir")
+            .arg(&target_dir)
+            .status()
+            .expect("Failed to run cargo");
+        assert!(
+            status.success(),
+            "Failed to build the tiling wm, is the wasm32-unknown-unknown target installed?"
+        );
+
+        let module = std::fs::read(target_dir.join("wasm32-unknown-unknown/release/tiling_wm.wasm"))
+            .expect("Failed to read the tiling wm module");
+        wit_component::ComponentEncoder::default()
+            .module(&module)
+            .and_then(|encoder| encoder.validate(true).encode())
+            .expect("Failed to encode the tiling wm as a component")
+    })
+}
+
+fn harness() -> Harness {
+    Harness::new(component()).expect("Failed to start the tiling wm")
+}
+
+fn binding(id: u32) -> WmEvent {
+    WmEvent::Binding {
+        id,
+        time: 0,
+        repeat: false,
+    }
+}
+
+fn new_output() -> WmEvent {
+    WmEvent::NewOutput {
+        output: Id::output(NonZeroU32::new(1).unwrap()),
+        info: OutputInfo {
+            name: Some("TEST-1".into()),
+            geometry: LogicalRect::new(euclid::point2(0, 0), LogicalSize::new(1000, 800)),
+            refresh_rate: 60_000,
+        },
+    }
+}
+
+/// Create a toplevel and announce the toplevel to the wm, returning the requests made in response.
+fn new_toplevel(harness: &mut Harness, toplevel: Id) -> Vec<WmRequest> {
+    harness.send(WmEvent::NewToplevel {
+        toplevel,
+        features: Features::TILED_STATES,
+    });
+    harness.send(WmEvent::UpdateToplevel {
+        toplevel,
+        update: ToplevelUpdate::default(),
+    })
+}
+
+/// Commit the first snapshot of a toplevel, returning the view the wm built for the toplevel and the operations
+/// of the transaction placing the view.
+fn commit(harness: &mut Harness, toplevel: Id) -> (Id, Vec<SceneOperation>) {
+    let requests = harness.send(WmEvent::CommittedToplevel {
+        toplevel,
+        snapshot: Some(SnapshotInfo {
+            size: LogicalSize::new(500, 800),
+            scale: 1.0,
+        }),
+    });
+
+    let view = requests
+        .iter()
+        .find_map(|request| match request {
+            WmRequest::CreateView {
+                view,
+                source: ViewSource::Toplevel { toplevel: source },
+            } if *source == toplevel => Some(*view),
+            _ => None,
+        })
+        .expect("The wm did not build a view of the toplevel");
+
+    (view, operations(&requests))
+}
+
+fn operations(requests: &[WmRequest]) -> Vec<SceneOperation> {
+    requests
+        .iter()
+        .filter_map(|request| match request {
+            WmRequest::Transaction(operations) => Some(operations.clone()),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// The toplevel the wm focused last while handling an event.
+fn keyboard_focus(requests: &[WmRequest]) -> Option<Option<Id>> {
+    requests.iter().rev().find_map(|request| match request {
+        WmRequest::SetKeyboardFocus(toplevel) => Some(*toplevel),
+        _ => None,
+    })
+}
+
+fn configures(requests: &[WmRequest]) -> Vec<(Id, LogicalSize, ToplevelState)> {
+    requests.iter().filter_map(configure).collect()
+}
+
+fn configure(request: &WmRequest) -> Option<(Id, LogicalSize, ToplevelState)> {
+    let WmRequest::ToplevelConfigure {
+        toplevel, configure, ..
+    } = request
+    else {
+        return None;
+    };
+
+    match (&configure.size, configure.state) {
+        (ConfigureUpdate::Update(Some(size)), Some(state)) => Some((*toplevel, *size, state)),
+        _ => None,
+    }
+}
+
+fn activated(configures: &[(Id, LogicalSize, ToplevelState)]) -> Vec<Id> {
+    configures
+        .iter()
+        .filter(|(_, _, state)| state.contains(ToplevelState::ACTIVATED))
+        .map(|&(id, _, _)| id)
+        .collect()
+}
+
+fn toplevel(rep: u32) -> Id {
+    Id::toplevel(NonZeroU32::new(rep).unwrap())
+}
+
+#[test]
+fn tiling_wm() {
+    let mut harness = harness();
+    let half = LogicalSize::new(500, 800);
+    let full = LogicalSize::new(1000, 800);
+
+    let requests = harness.trace().to_string();
+    assert_eq!(requests.matches("RegisterBinding").count(), 7);
+    assert!(requests.contains(&format!("RegisterBinding {{ id: {ZOOM},")));
+
+    // Every output gets four workspaces and the first is activated.
+    let requests = harness.send(new_output());
+    let workspaces = requests
+        .iter()
+        .filter_map(|request| match request {
+            WmRequest::CreateWorkspace { workspace, .. } => Some(*workspace),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(workspaces.len(), 4);
+    assert!(requests.iter().any(|request| matches!(
+        request,
+        WmRequest::Transaction(operations)
+            if operations == &[SceneOperation::ActivateWorkspace { workspace: workspaces[0] }]
+    )));
+
+    // A single toplevel fills the output and is focused.
+    let requests = new_toplevel(&mut harness, toplevel(2));
+    assert_eq!(keyboard_focus(&requests), Some(Some(toplevel(2))));
+    let configures = self::configures(&requests);
+    assert_eq!(configures.len(), 1);
+    assert_eq!(configures[0].0, toplevel(2));
+    assert_eq!(configures[0].1, full);
+    assert!(configures[0]
+        .2
+        .contains(ToplevelState::ACTIVATED | ToplevelState::TILED_LEFT | ToplevelState::TILED_RIGHT));
+
+    // The view of the toplevel is placed on the active workspace once the toplevel committed.
+    let (master, operations) = commit(&mut harness, toplevel(2));
+    assert!(operations.contains(&SceneOperation::SetWorkspace {
+        view: master,
+        workspace: workspaces[0],
+    }));
+    assert!(operations.contains(&SceneOperation::SetPosition {
+        view: master,
+        x: 0,
+        y: 0
+    }));
+
+    // The new toplevel is stacked next to the master and focused.
+    let requests = new_toplevel(&mut harness, toplevel(3));
+    assert_eq!(keyboard_focus(&requests), Some(Some(toplevel(3))));
+    let configures = self::configures(&requests);
+    assert_eq!(configures.len(), 2);
+    assert!(configures.iter().all(|&(_, size, _)| size == half));
+    assert_eq!(configures[0].0, toplevel(2));
+    assert_eq!(activated(&configures), [toplevel(3)]);
+
+    let (stacked, operations) = commit(&mut harness, toplevel(3));
+    assert!(operations.contains(&SceneOperation::SetPosition {
+        view: stacked,
+        x: 500,
+        y: 0
+    }));
+
+    // Zooming makes the focused toplevel the master.
+    let requests = harness.send(binding(ZOOM));
+    let configures = self::configures(&requests);
+    assert_eq!(configures[0].0, toplevel(3));
+    assert!(!configures[0].2.contains(ToplevelState::TILED_RIGHT));
+    let operations = self::operations(&requests);
+    assert!(operations.contains(&SceneOperation::SetPosition {
+        view: stacked,
+        x: 0,
+        y: 0
+    }));
+    assert!(operations.contains(&SceneOperation::SetPosition {
+        view: master,
+        x: 500,
+        y: 0
+    }));
+
+    let requests = harness.send(binding(FOCUS_NEXT));
+    assert_eq!(keyboard_focus(&requests), Some(Some(toplevel(2))));
+    assert_eq!(activated(&self::configures(&requests)), [toplevel(2)]);
+
+    // Switching the workspace suspends the toplevels of the previous workspace.
+    let requests = harness.send(binding(SWITCH_WORKSPACE + 1));
+    let configures = self::configures(&requests);
+    assert_eq!(configures.len(), 2);
+    assert!(configures
+        .iter()
+        .all(|(_, _, state)| state.contains(ToplevelState::SUSPENDED)));
+    assert!(activated(&configures).is_empty());
+    assert_eq!(keyboard_focus(&requests), Some(None));
+
+    // The views stay on the previous workspace, which is no longer presented.
+    let operations = self::operations(&requests);
+    assert_eq!(
+        operations.first(),
+        Some(&SceneOperation::ActivateWorkspace {
+            workspace: workspaces[1]
+        })
+    );
+    assert!(operations.contains(&SceneOperation::SetWorkspace {
+        view: master,
+        workspace: workspaces[0],
+    }));
+
+    let configures = self::configures(&new_toplevel(&mut harness, toplevel(4)));
+    assert_eq!((configures[0].0, configures[0].1), (toplevel(4), full));
+
+    // Switching back focuses the master of the workspace.
+    let configures = self::configures(&harness.send(binding(SWITCH_WORKSPACE)));
+    assert_eq!(configures.len(), 3);
+    assert_eq!(activated(&configures), [toplevel(3)]);
+    assert!(configures
+        .iter()
+        .any(|&(id, _, state)| id == toplevel(4) && state.contains(ToplevelState::SUSPENDED)));
+
+    // The remaining toplevel fills the output and takes the focus of the closed toplevel.
+    let requests = harness.send(WmEvent::ClosedToplevel(toplevel(3)));
+    assert!(requests
+        .iter()
+        .any(|request| matches!(request, WmRequest::DestroyView(view) if *view == stacked)));
+    assert_eq!(keyboard_focus(&requests), Some(Some(toplevel(2))));
+    let configures = self::configures(&requests);
+    assert_eq!((configures[0].0, configures[0].1), (toplevel(2), full));
+    assert_eq!(activated(&configures), [toplevel(2)]);
+}
+
+/// Compare the requests of the tiling wm against the golden trace in `tests/golden/tiling_wm.trace`.
+#[test]
+fn tiling_wm_trace() {
+    let mut harness = harness();
+    harness.send(new_output());
+
+    for rep in 2..5 {
+        new_toplevel(&mut harness, toplevel(rep));
+    }
+
+    harness.run([
+        binding(ZOOM),
+        binding(FOCUS_NEXT),
+        binding(SWITCH_WORKSPACE + 1),
+        binding(SWITCH_WORKSPACE),
+        WmEvent::ClosedToplevel(toplevel(3)),
+    ]);
+
+    assert!(harness.running());
+    harness
+        .trace()
+        .assert_golden(golden_path(env!("CARGO_MANIFEST_DIR"), "tiling_wm"));
+}
@@ -1,8 +1,8 @@
 use std::collections::HashMap;
 
 use aerugo::wm::types::{
-    KeyFilter, KeyModifiers, KeyStatus, Output, OutputId, Server, Snapshot, Toplevel, ToplevelConfigure, ToplevelId,
-    ToplevelUpdates,
+    GestureEvent, Image, KeyFilter, KeyModifiers, KeyStatus, Output, OutputId, PointerEvent, Server, Snapshot,
+    SurfaceNodeId, Toplevel, ToplevelConfigure, ToplevelId, ToplevelUpdates, TouchEvent, Transaction, WorkspaceId,
 };
 use exports::aerugo::wm::wm_types::{Guest, GuestWm, WmInfo};
 use wit_bindgen::{rt::string::String, Resource};
@@ -11,37 +11,63 @@ use xkeysym::KeyCode;
 pub struct Wm {
     /// All known toplevels.
     toplevels: HashMap<ToplevelId, Toplevel>,
+
+    /// The serial of the configure each toplevel has not acked yet.
+    pending: HashMap<ToplevelId, u32>,
 }
 
 impl Wm {
     fn new(_server: Server) -> Self {
-        todo!()
+        Self {
+            toplevels: HashMap::new(),
+            pending: HashMap::new(),
+        }
     }
 
     fn new_toplevel(&mut self, toplevel: Toplevel) {
         let id = toplevel.id();
         let toplevel = self.toplevels.entry(id).or_insert(toplevel);
 
-        let _configure = ToplevelConfigure::new(toplevel);
+        // Let the toplevel pick its own size. The configure is sent when the transaction is committed.
+        let configure = ToplevelConfigure::new(toplevel);
+        configure.size(None);
+
+        let transaction = Transaction::new();
+        if let Ok(serial) = transaction.configure(&configure) {
+            self.pending.insert(id, serial);
+        }
+        transaction.commit();
     }
 
     fn closed_toplevel(&mut self, toplevel: ToplevelId) {
         // The wm may keep the toplevel around for animations. For the example drop the toplevel handle.
         self.toplevels.remove(&toplevel);
+        self.pending.remove(&toplevel);
     }
 
     fn update_toplevel(&mut self, _toplevel: ToplevelId, _updates: ToplevelUpdates) {
         todo!()
     }
 
-    fn ack_toplevel(&mut self, _toplevel: ToplevelId, _serial: u32) {
-        todo!()
+    fn ack_toplevel(&mut self, toplevel: ToplevelId, serial: u32) {
+        // An older configure may be acked after a newer one was sent.
+        if self.pending.get(&toplevel) == Some(&serial) {
+            self.pending.remove(&toplevel);
+        }
     }
 
-    fn committed_toplevel(&mut self, _toplevel: ToplevelId, _snapshot: Option<Snapshot>) {
-        todo!()
+    fn configure_cancelled(&mut self, toplevel: ToplevelId, serial: u32) {
+        self.ack_toplevel(toplevel, serial);
+    }
+
+    fn configure_timed_out(&mut self, toplevel: ToplevelId, serial: u32) {
+        self.ack_toplevel(toplevel, serial);
     }
 
+    fn committed_toplevel(&mut self, _toplevel: ToplevelId, _snapshot: Option<Snapshot>) {}
+
+    fn binding(&mut self, _id: u32, _time: u32, _repeat: bool) {}
+
     fn key(&mut self, _time: u32, _key_code: KeyCode, _compose: Option<String>, _status: KeyStatus) -> KeyFilter {
         todo!()
     }
@@ -50,13 +76,41 @@ impl Wm {
         todo!()
     }
 
+    fn touch(&mut self, _time: u32, _event: TouchEvent) {}
+
+    fn pointer(&mut self, _time: u32, _event: PointerEvent) {}
+
+    fn gesture(&mut self, _time: u32, _event: GestureEvent) {}
+
+    fn activation_requested(&mut self, _toplevel: ToplevelId, _token_valid: bool) {}
+
+    fn launched(&mut self, _launch: u32, _toplevel: ToplevelId) {}
+
+    fn workspace_activation_requested(&mut self, _workspace: WorkspaceId) {}
+
     fn new_output(&mut self, __output: Output) {
         todo!()
     }
 
-    fn disconnect_output(&mut self, __output: OutputId) {
+    fn disconnect_output(&mut self, __output: OutputId, __toplevels: Vec<ToplevelId>) {
         todo!()
     }
+
+    fn new_surface_node(&mut self, _node: SurfaceNodeId, _name: String) {}
+
+    fn closed_surface_node(&mut self, _node: SurfaceNodeId) {}
+
+    fn session_locked(&mut self) {}
+
+    fn session_unlocked(&mut self) {}
+
+    fn toplevel_screenshot(&mut self, _toplevel: ToplevelId, _serial: u32, _image: Option<Image>) {}
+
+    fn frame(&mut self, _output: OutputId, _time: u32) {}
+
+    fn timer(&mut self, _id: u32) {}
+
+    fn shutdown(&mut self) {}
 }
 
 wit_bindgen::generate!({
@@ -105,10 +159,22 @@ impl GuestWm for WmImpl {
         self.0.borrow_mut().ack_toplevel(toplevel, serial);
     }
 
+    fn configure_cancelled(&self, toplevel: ToplevelId, serial: u32) {
+        self.0.borrow_mut().configure_cancelled(toplevel, serial);
+    }
+
+    fn configure_timed_out(&self, toplevel: ToplevelId, serial: u32) {
+        self.0.borrow_mut().configure_timed_out(toplevel, serial);
+    }
+
     fn committed_toplevel(&self, toplevel: ToplevelId, snapshot: Option<Snapshot>) {
         self.0.borrow_mut().committed_toplevel(toplevel, snapshot)
     }
 
+    fn binding(&self, id: u32, time: u32, repeat: bool) {
+        self.0.borrow_mut().binding(id, time, repeat)
+    }
+
     fn key(&self, time: u32, sym: u32, compose: Option<String>, status: KeyStatus) -> KeyFilter {
         self.0.borrow_mut().key(time, KeyCode::from(sym), compose, status)
     }
@@ -117,11 +183,67 @@ impl GuestWm for WmImpl {
         self.0.borrow_mut().key_modifiers(modifiers)
     }
 
+    fn touch(&self, time: u32, event: TouchEvent) {
+        self.0.borrow_mut().touch(time, event);
+    }
+
+    fn pointer(&self, time: u32, event: PointerEvent) {
+        self.0.borrow_mut().pointer(time, event);
+    }
+
+    fn gesture(&self, time: u32, event: GestureEvent) {
+        self.0.borrow_mut().gesture(time, event);
+    }
+
+    fn activation_requested(&self, toplevel: ToplevelId, token_valid: bool) {
+        self.0.borrow_mut().activation_requested(toplevel, token_valid);
+    }
+
+    fn launched(&self, launch: u32, toplevel: ToplevelId) {
+        self.0.borrow_mut().launched(launch, toplevel);
+    }
+
+    fn workspace_activation_requested(&self, workspace: WorkspaceId) {
+        self.0.borrow_mut().workspace_activation_requested(workspace);
+    }
+
     fn new_output(&self, output: Output) {
         self.0.borrow_mut().new_output(output);
     }
 
-    fn disconnect_output(&self, output: OutputId) {
-        self.0.borrow_mut().disconnect_output(output);
+    fn disconnect_output(&self, output: OutputId, toplevels: Vec<ToplevelId>) {
+        self.0.borrow_mut().disconnect_output(output, toplevels);
+    }
+
+    fn new_surface_node(&self, node: SurfaceNodeId, name: String) {
+        self.0.borrow_mut().new_surface_node(node, name);
+    }
+
+    fn closed_surface_node(&self, node: SurfaceNodeId) {
+        self.0.borrow_mut().closed_surface_node(node);
+    }
+
+    fn session_locked(&self) {
+        self.0.borrow_mut().session_locked();
+    }
+
+    fn session_unlocked(&self) {
+        self.0.borrow_mut().session_unlocked();
+    }
+
+    fn toplevel_screenshot(&self, toplevel: ToplevelId, serial: u32, image: Option<Image>) {
+        self.0.borrow_mut().toplevel_screenshot(toplevel, serial, image);
+    }
+
+    fn frame(&self, output: OutputId, time: u32) {
+        self.0.borrow_mut().frame(output, time);
+    }
+
+    fn timer(&self, id: u32) {
+        self.0.borrow_mut().timer(id);
+    }
+
+    fn shutdown(&self) {
+        self.0.borrow_mut().shutdown();
     }
 }
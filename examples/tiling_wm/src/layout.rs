@@ -0,0 +1,125 @@
+//! Master-stack layout
+
+use crate::aerugo::wm::types::{Geometry, ToplevelState};
+
+/// The share of the width of an output given to the master, in percent.
+pub const MASTER_RATIO: u32 = 50;
+
+/// The place of a toplevel in the layout.
+#[derive(Debug, Clone, Copy)]
+pub struct Tile {
+    pub geometry: Geometry,
+
+    /// The tiled states of the toplevel, for the edges of the toplevel which touch the edges of the area.
+    pub state: ToplevelState,
+}
+
+/// Tile `count` toplevels in an area.
+///
+/// The first toplevel is the master and takes the left part of the area. The other toplevels are stacked on the
+/// right with equal heights. A single toplevel takes the whole area.
+pub fn master_stack(area: Geometry, count: usize) -> Vec<Tile> {
+    let all_edges =
+        ToplevelState::TILED_LEFT | ToplevelState::TILED_RIGHT | ToplevelState::TILED_TOP | ToplevelState::TILED_BOTTOM;
+
+    match count {
+        0 => Vec::new(),
+        1 => vec![Tile {
+            geometry: area,
+            state: all_edges,
+        }],
+        _ => {
+            let master_width = area.width * MASTER_RATIO / 100;
+            let stack = count as u32 - 1;
+            let mut tiles = Vec::with_capacity(count);
+
+            tiles.push(Tile {
+                geometry: Geometry {
+                    width: master_width,
+                    ..area
+                },
+                state: all_edges - ToplevelState::TILED_RIGHT,
+            });
+
+            let mut y = area.y;
+
+            for index in 0..stack {
+                let last = index + 1 == stack;
+                // The last toplevel takes the remainder, so the stack fills the area.
+                let height = match last {
+                    true => (area.y + area.height as i32 - y) as u32,
+                    false => area.height / stack,
+                };
+
+                let mut state = ToplevelState::TILED_RIGHT;
+
+                if index == 0 {
+                    state |= ToplevelState::TILED_TOP;
+                }
+
+                if last {
+                    state |= ToplevelState::TILED_BOTTOM;
+                }
+
+                tiles.push(Tile {
+                    geometry: Geometry {
+                        x: area.x + master_width as i32,
+                        y,
+                        width: area.width - master_width,
+                        height,
+                    },
+                    state,
+                });
+                y += height as i32;
+            }
+
+            tiles
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aerugo::wm::types::{Geometry, ToplevelState};
+
+    use super::master_stack;
+
+    const AREA: Geometry = Geometry {
+        x: 100,
+        y: 0,
+        width: 1000,
+        height: 800,
+    };
+
+    #[test]
+    fn single_toplevel_fills_area() {
+        let tiles = master_stack(AREA, 1);
+        assert_eq!(tiles.len(), 1);
+        assert_eq!((tiles[0].geometry.x, tiles[0].geometry.width), (100, 1000));
+        assert!(tiles[0]
+            .state
+            .contains(ToplevelState::TILED_LEFT | ToplevelState::TILED_RIGHT));
+    }
+
+    #[test]
+    fn stack_fills_area() {
+        let tiles = master_stack(AREA, 4);
+        let geometry = tiles.iter().map(|tile| tile.geometry).collect::<Vec<_>>();
+
+        assert_eq!((geometry[0].width, geometry[0].height), (500, 800));
+        assert!(!tiles[0].state.contains(ToplevelState::TILED_RIGHT));
+
+        // 800 does not divide into three, the last toplevel takes the remainder.
+        assert!(geometry[1..]
+            .iter()
+            .all(|geometry| geometry.x == 600 && geometry.width == 500));
+        assert_eq!(
+            geometry[1..].iter().map(|geometry| geometry.height).collect::<Vec<_>>(),
+            [266, 266, 268]
+        );
+        assert_eq!(geometry[3].y + geometry[3].height as i32, 800);
+        assert!(tiles[1].state.contains(ToplevelState::TILED_TOP));
+        assert!(tiles[3].state.contains(ToplevelState::TILED_BOTTOM));
+        assert!(master_stack(AREA, 0).is_empty());
+    }
+}
@@ -0,0 +1,475 @@
+//! An example tiling wm
+//!
+//! Toplevels are tiled in a master-stack layout on the active workspace of each output. Every output has
+//! [`WORKSPACES`] workspaces. New toplevels are placed on the active workspace of the focused output and focused.
+//!
+//! The wm registers these bindings:
+//!
+//! - Logo+J and Logo+K focus the next and previous toplevel of the workspace.
+//! - Logo+Return makes the focused toplevel the master.
+//! - Logo+1 to Logo+4 switch the workspace of the focused output.
+//!
+//! A toplevel is presented by a view on the workspace of the toplevel once the toplevel committed a snapshot. The
+//! layout decides the size, the states and the position of the view of each toplevel. The focused toplevel
+//! receives keyboard input.
+
+mod layout;
+
+use std::collections::HashMap;
+
+use aerugo::wm::{
+    log::{log, Level},
+    types::{
+        Focus, GestureEvent, Image, KeyFilter, KeyModifiers, KeyStatus, Output, OutputId, PointerEvent, Server, Size,
+        Snapshot, SurfaceNodeId, Toplevel, ToplevelConfigure, ToplevelId, ToplevelState, ToplevelUpdates, TouchEvent,
+        Transaction, View, ViewBuilder, Workspace, WorkspaceId,
+    },
+};
+use exports::aerugo::wm::wm_types::{Guest, GuestWm, WmInfo};
+use wit_bindgen::{rt::string::String, Resource};
+
+/// The number of workspaces of each output.
+pub const WORKSPACES: usize = 4;
+
+/// Binding ids.
+pub const FOCUS_NEXT: u32 = 1;
+pub const FOCUS_PREVIOUS: u32 = 2;
+pub const ZOOM: u32 = 3;
+
+/// The binding switching to the first workspace, the following workspaces use the following ids.
+pub const SWITCH_WORKSPACE: u32 = 10;
+
+/// X11 keysyms of the bound keys.
+const KEY_J: u32 = 0x006a;
+const KEY_K: u32 = 0x006b;
+const KEY_RETURN: u32 = 0xff0d;
+const KEY_1: u32 = 0x0031;
+
+struct OutputState {
+    id: OutputId,
+    output: Output,
+    workspaces: Vec<WorkspaceState>,
+
+    /// The index of the active workspace.
+    active: usize,
+}
+
+struct WorkspaceState {
+    workspace: Workspace,
+
+    /// The toplevels on the workspace, the master first.
+    toplevels: Vec<ToplevelId>,
+}
+
+pub struct Wm {
+    /// Dropping the server is not supported, so the server lives as long as the wm.
+    server: Server,
+
+    /// Outputs in the order they were connected.
+    outputs: Vec<OutputState>,
+
+    toplevels: HashMap<ToplevelId, Toplevel>,
+
+    /// The views presenting toplevels which committed a snapshot.
+    views: HashMap<ToplevelId, View>,
+
+    /// Toplevels created while no output was connected.
+    unassigned: Vec<ToplevelId>,
+
+    focused: Option<ToplevelId>,
+
+    /// The index of the output new toplevels are placed on.
+    focused_output: usize,
+}
+
+impl Wm {
+    fn new(server: Server) -> Self {
+        let bindings = [(FOCUS_NEXT, KEY_J), (FOCUS_PREVIOUS, KEY_K), (ZOOM, KEY_RETURN)]
+            .into_iter()
+            .chain((0..WORKSPACES as u32).map(|index| (SWITCH_WORKSPACE + index, KEY_1 + index)));
+
+        for (id, sym) in bindings {
+            if let Err(err) = server.register_binding(id, KeyModifiers::LOGO, sym) {
+                log(Level::Warn, &format!("Failed to register binding {id}: {err:?}"), &[]);
+            }
+        }
+
+        Self {
+            server,
+            outputs: Vec::new(),
+            toplevels: HashMap::new(),
+            views: HashMap::new(),
+            unassigned: Vec::new(),
+            focused: None,
+            focused_output: 0,
+        }
+    }
+
+    fn new_toplevel(&mut self, toplevel: Toplevel) {
+        let id = toplevel.id();
+        self.toplevels.insert(id, toplevel);
+
+        let Some(output) = self.outputs.get_mut(self.focused_output) else {
+            self.unassigned.push(id);
+            return;
+        };
+
+        output.workspaces[output.active].toplevels.push(id);
+        self.focused = Some(id);
+
+        let transaction = Transaction::new();
+        self.arrange(self.focused_output, &transaction);
+        transaction.commit();
+    }
+
+    fn closed_toplevel(&mut self, toplevel: ToplevelId) {
+        self.toplevels.remove(&toplevel);
+        self.views.remove(&toplevel);
+        self.unassigned.retain(|&id| id != toplevel);
+
+        let Some((output, workspace, position)) = self.locate(toplevel) else {
+            return;
+        };
+
+        let toplevels = &mut self.outputs[output].workspaces[workspace].toplevels;
+        toplevels.remove(position);
+
+        // Focus the toplevel which took the place of the closed toplevel.
+        if self.focused == Some(toplevel) {
+            self.focused = toplevels.get(position.min(toplevels.len().saturating_sub(1))).copied();
+        }
+
+        let transaction = Transaction::new();
+        self.arrange(output, &transaction);
+        transaction.commit();
+    }
+
+    fn update_toplevel(&mut self, toplevel: ToplevelId, updates: ToplevelUpdates) {
+        // The toplevel waits on a configure after these requests. The requests are denied by configuring the
+        // layout again.
+        let requests = ToplevelUpdates::REQUEST_SET_MAXIMIZED
+            | ToplevelUpdates::REQUEST_UNSET_MAXIMIZED
+            | ToplevelUpdates::REQUEST_SET_FULLSCREEN
+            | ToplevelUpdates::REQUEST_UNSET_FULLSCREEN;
+
+        if !updates.intersects(requests) {
+            return;
+        }
+
+        if let Some((output, _, _)) = self.locate(toplevel) {
+            let transaction = Transaction::new();
+            self.arrange(output, &transaction);
+            transaction.commit();
+        }
+    }
+
+    fn committed_toplevel(&mut self, toplevel: ToplevelId, snapshot: Option<Snapshot>) {
+        // Later snapshots only tell about a new size, which the view presents already.
+        if self.views.contains_key(&toplevel) {
+            return;
+        }
+
+        let (Some(snapshot), Some(handle)) = (snapshot, self.toplevels.get(&toplevel)) else {
+            return;
+        };
+
+        let view = ViewBuilder::with_toplevel(handle, &snapshot).build();
+        self.views.insert(toplevel, view);
+
+        // Toplevels waiting for an output are placed once an output is connected.
+        if let Some((output, workspace, _)) = self.locate(toplevel) {
+            let transaction = Transaction::new();
+            self.arrange_workspace(output, workspace, &transaction);
+            transaction.commit();
+        }
+    }
+
+    fn binding(&mut self, id: u32) {
+        match id {
+            FOCUS_NEXT => self.cycle_focus(1),
+            FOCUS_PREVIOUS => self.cycle_focus(-1),
+            ZOOM => self.zoom(),
+            id if (SWITCH_WORKSPACE..SWITCH_WORKSPACE + WORKSPACES as u32).contains(&id) => {
+                self.switch_workspace((id - SWITCH_WORKSPACE) as usize);
+            }
+            _ => (),
+        }
+    }
+
+    fn new_output(&mut self, output: Output) {
+        let workspaces = (1..=WORKSPACES)
+            .map(|index| WorkspaceState {
+                workspace: Workspace::new(&output, &index.to_string()),
+                toplevels: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut state = OutputState {
+            id: output.id(),
+            output,
+            workspaces,
+            active: 0,
+        };
+
+        // Toplevels waiting for an output are placed on the first output.
+        state.workspaces[0].toplevels.append(&mut self.unassigned);
+
+        if self.focused.is_none() {
+            self.focused = state.workspaces[0].toplevels.first().copied();
+        }
+
+        let transaction = Transaction::new();
+        transaction.activate_workspace(&state.workspaces[0].workspace);
+        self.outputs.push(state);
+        self.arrange(self.outputs.len() - 1, &transaction);
+        transaction.commit();
+    }
+
+    fn disconnect_output(&mut self, output: OutputId) {
+        let Some(index) = self.outputs.iter().position(|state| state.id == output) else {
+            return;
+        };
+
+        // The workspaces of the output are destroyed once dropped.
+        let state = self.outputs.remove(index);
+        let orphans = state
+            .workspaces
+            .into_iter()
+            .flat_map(|workspace| workspace.toplevels)
+            .collect::<Vec<_>>();
+
+        if self.focused_output >= index {
+            self.focused_output = self.focused_output.saturating_sub(1);
+        }
+
+        let Some(target) = self.outputs.get_mut(self.focused_output) else {
+            self.unassigned.extend(orphans);
+            return;
+        };
+
+        target.workspaces[target.active].toplevels.extend(orphans);
+
+        let transaction = Transaction::new();
+        self.arrange(self.focused_output, &transaction);
+        transaction.commit();
+    }
+
+    /// Move the focus within the active workspace of the focused output.
+    fn cycle_focus(&mut self, offset: isize) {
+        let Some(output) = self.outputs.get(self.focused_output) else {
+            return;
+        };
+
+        let toplevels = &output.workspaces[output.active].toplevels;
+
+        if toplevels.is_empty() {
+            return;
+        }
+
+        let current = self
+            .focused
+            .and_then(|focused| toplevels.iter().position(|&id| id == focused))
+            .unwrap_or(0) as isize;
+        let next = (current + offset).rem_euclid(toplevels.len() as isize) as usize;
+        self.focused = Some(toplevels[next]);
+
+        let transaction = Transaction::new();
+        self.arrange(self.focused_output, &transaction);
+        transaction.commit();
+    }
+
+    /// Swap the focused toplevel with the master.
+    fn zoom(&mut self) {
+        let Some((output, workspace, position)) = self.focused.and_then(|focused| self.locate(focused)) else {
+            return;
+        };
+
+        self.outputs[output].workspaces[workspace].toplevels.swap(0, position);
+
+        let transaction = Transaction::new();
+        self.arrange(output, &transaction);
+        transaction.commit();
+    }
+
+    fn switch_workspace(&mut self, index: usize) {
+        let Some(output) = self.outputs.get_mut(self.focused_output) else {
+            return;
+        };
+
+        if output.active == index {
+            return;
+        }
+
+        let previous = std::mem::replace(&mut output.active, index);
+        let workspace = &output.workspaces[index];
+        self.focused = workspace.toplevels.first().copied();
+
+        let transaction = Transaction::new();
+        transaction.activate_workspace(&workspace.workspace);
+        // The toplevels of the previous workspace are suspended and lose focus.
+        self.arrange_workspace(self.focused_output, previous, &transaction);
+        self.arrange(self.focused_output, &transaction);
+        transaction.commit();
+    }
+
+    /// Find the output, workspace and position in the workspace of a toplevel.
+    fn locate(&self, toplevel: ToplevelId) -> Option<(usize, usize, usize)> {
+        self.outputs.iter().enumerate().find_map(|(output, state)| {
+            state.workspaces.iter().enumerate().find_map(|(workspace, state)| {
+                let position = state.toplevels.iter().position(|&id| id == toplevel)?;
+                Some((output, workspace, position))
+            })
+        })
+    }
+
+    /// Configure the toplevels on the active workspace of an output and focus the focused toplevel.
+    fn arrange(&self, output: usize, transaction: &Transaction) {
+        self.arrange_workspace(output, self.outputs[output].active, transaction);
+
+        let focus = match self.focused {
+            Some(toplevel) => Focus::Toplevel(toplevel),
+            None => Focus::None,
+        };
+        self.server.set_keyboard_focus(focus);
+    }
+
+    fn arrange_workspace(&self, output: usize, workspace: usize, transaction: &Transaction) {
+        let state = &self.outputs[output];
+        let toplevels = &state.workspaces[workspace].toplevels;
+        let area = state.output.geometry();
+        let tiles = layout::master_stack(area, toplevels.len());
+
+        for (id, tile) in toplevels.iter().zip(tiles) {
+            let Some(toplevel) = self.toplevels.get(id) else {
+                continue;
+            };
+
+            let mut toplevel_state = tile.state;
+
+            if workspace != state.active {
+                toplevel_state |= ToplevelState::SUSPENDED;
+            } else if self.focused == Some(*id) {
+                toplevel_state |= ToplevelState::ACTIVATED;
+            }
+
+            let configure = ToplevelConfigure::new(toplevel);
+            configure.size(Some(Size {
+                width: tile.geometry.width,
+                height: tile.geometry.height,
+            }));
+            configure.state(toplevel_state);
+
+            // The toplevel may have been closed already, the wm is told shortly.
+            let _ = transaction.configure(&configure);
+
+            // Views are positioned relative to the output of the workspace.
+            if let Some(view) = self.views.get(id) {
+                transaction.set_workspace(view, &state.workspaces[workspace].workspace);
+                transaction.set_position(view, tile.geometry.x - area.x, tile.geometry.y - area.y);
+            }
+        }
+    }
+}
+
+wit_bindgen::generate!({
+    path: "../../wm.wit",
+
+    world: "aerugo-wm",
+
+    exports: {
+        "aerugo:wm/wm-types": WmImpl,
+        "aerugo:wm/wm-types/wm": WmImpl,
+    },
+});
+
+pub struct WmImpl(std::cell::RefCell<Wm>);
+
+impl Guest for WmImpl {
+    fn get_info() -> Result<WmInfo, String> {
+        Ok(WmInfo {
+            abi_major: 0,
+            abi_minor: 1,
+            name: "tiling wm".into(),
+            version: env!("CARGO_PKG_VERSION").into(),
+        })
+    }
+
+    fn create_wm(server: Server) -> Result<Resource<WmImpl>, String> {
+        let wm = Wm::new(server);
+        Ok(Resource::new(Self(std::cell::RefCell::new(wm))))
+    }
+}
+
+impl GuestWm for WmImpl {
+    fn new_toplevel(&self, toplevel: Toplevel) {
+        self.0.borrow_mut().new_toplevel(toplevel);
+    }
+
+    fn closed_toplevel(&self, toplevel: ToplevelId) {
+        self.0.borrow_mut().closed_toplevel(toplevel);
+    }
+
+    fn update_toplevel(&self, toplevel: ToplevelId, updates: ToplevelUpdates) {
+        self.0.borrow_mut().update_toplevel(toplevel, updates);
+    }
+
+    fn ack_toplevel(&self, _toplevel: ToplevelId, _serial: u32) {}
+
+    fn configure_cancelled(&self, _toplevel: ToplevelId, _serial: u32) {}
+
+    fn configure_timed_out(&self, _toplevel: ToplevelId, _serial: u32) {}
+
+    fn committed_toplevel(&self, toplevel: ToplevelId, snapshot: Option<Snapshot>) {
+        self.0.borrow_mut().committed_toplevel(toplevel, snapshot);
+    }
+
+    fn binding(&self, id: u32, _time: u32, repeat: bool) {
+        // Holding a binding does not cycle through every toplevel.
+        if !repeat {
+            self.0.borrow_mut().binding(id);
+        }
+    }
+
+    fn key(&self, _time: u32, _sym: u32, _compose: Option<String>, _status: KeyStatus) -> KeyFilter {
+        KeyFilter::Forward
+    }
+
+    fn key_modifiers(&self, _modifiers: KeyModifiers) {}
+
+    fn touch(&self, _time: u32, _event: TouchEvent) {}
+
+    fn pointer(&self, _time: u32, _event: PointerEvent) {}
+
+    fn gesture(&self, _time: u32, _event: GestureEvent) {}
+
+    fn activation_requested(&self, _toplevel: ToplevelId, _token_valid: bool) {}
+
+    fn launched(&self, _launch: u32, _toplevel: ToplevelId) {}
+
+    fn workspace_activation_requested(&self, _workspace: WorkspaceId) {}
+
+    fn new_output(&self, output: Output) {
+        self.0.borrow_mut().new_output(output);
+    }
+
+    // The toplevels of the output are known from the workspaces of the output.
+    fn disconnect_output(&self, output: OutputId, _toplevels: Vec<ToplevelId>) {
+        self.0.borrow_mut().disconnect_output(output);
+    }
+
+    fn new_surface_node(&self, _node: SurfaceNodeId, _name: String) {}
+
+    fn closed_surface_node(&self, _node: SurfaceNodeId) {}
+
+    fn session_locked(&self) {}
+
+    fn session_unlocked(&self) {}
+
+    fn toplevel_screenshot(&self, _toplevel: ToplevelId, _serial: u32, _image: Option<Image>) {}
+
+    fn frame(&self, _output: OutputId, _time: u32) {}
+
+    fn timer(&self, _id: u32) {}
+
+    fn shutdown(&self) {}
+}